@@ -0,0 +1,69 @@
+/*
+ * C interface of the minimal toy-rpc client, see the `ffi` module of toy-rpc-core
+ *
+ * The arguments and the bodies are serialized with `postcard`, and the server must
+ * serve the connection with the `PostcardCodec` of toy-rpc.
+ */
+
+#ifndef TOY_RPC_H
+#define TOY_RPC_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define TOY_RPC_MAX_PENDING 64
+#define TOY_RPC_BUF_LEN (64 * 1024)
+
+/* status codes */
+#define TOY_RPC_OK 0
+#define TOY_RPC_ERR_ARGUMENT -1
+#define TOY_RPC_ERR_TRANSPORT -2
+#define TOY_RPC_ERR_TOO_MANY_CALLS -3
+#define TOY_RPC_ERR_BUFFER_FULL -4
+#define TOY_RPC_ERR_PROTOCOL -5
+
+/* kinds of messages */
+#define TOY_RPC_NONE 0
+#define TOY_RPC_REPLY_OK 1
+#define TOY_RPC_REPLY_ERR 2
+#define TOY_RPC_PUBLICATION 3
+
+typedef struct ToyRpcClient ToyRpcClient;
+
+/* the topic and the body are valid until the next toy_rpc_poll or toy_rpc_close */
+typedef struct ToyRpcMessage {
+    int kind;
+    uint16_t id;
+    const uint8_t *topic;
+    size_t topic_len;
+    const uint8_t *body;
+    size_t body_len;
+} ToyRpcMessage;
+
+ToyRpcClient *toy_rpc_connect(const char *addr);
+
+int toy_rpc_call(ToyRpcClient *client, const char *service_method, const uint8_t *args,
+                 size_t args_len, uint64_t timeout_ms, uint16_t *id);
+
+int toy_rpc_cancel(ToyRpcClient *client, uint16_t id);
+
+int toy_rpc_publish(ToyRpcClient *client, const char *topic, const uint8_t *item,
+                    size_t item_len);
+
+int toy_rpc_subscribe(ToyRpcClient *client, const char *topic);
+
+int toy_rpc_unsubscribe(ToyRpcClient *client, const char *topic);
+
+int toy_rpc_poll(ToyRpcClient *client, ToyRpcMessage *message);
+
+void toy_rpc_close(ToyRpcClient *client);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* TOY_RPC_H */
@@ -0,0 +1,659 @@
+//! Minimal client for embedded devices
+//!
+//! `Client` calls the methods of a toy-rpc server over any `Transport` that moves
+//! bytes, eg. a UART or the TCP socket of a network co-processor. The headers and
+//! the bodies are serialized with `postcard`, so the server must serve the connection
+//! with the `PostcardCodec` of toy-rpc (feature `postcard_codec`).
+//!
+//! The client does not need an async runtime or a timer. It is driven by calling
+//! `Client::poll` from the main loop or from an RTIC/Embassy task whenever the
+//! transport may have received bytes, and each call of `poll` returns at most one
+//! `Reply`. The memory of the client is fixed:
+//!
+//! - at most `N` calls can be pending at a time
+//! - a whole reply, ie. its header and body frames, must fit in the receive buffer
+//!   of `BUF` bytes, and the header or the argument of a request must fit in the
+//!   transmit buffer of `BUF` bytes
+//!
+//! A call is pending until its reply is polled or until it is canceled with
+//! `Client::cancel`, eg. when the device decides that it has timed out. The replies
+//! of the calls that are not pending are dropped.
+//!
+//! The client can also publish to topics and subscribe to them with
+//! `Client::subscribe`. The
+//! publications are only returned by `Client::poll_incoming`, which returns both the
+//! replies and the publications, and `Client::poll` drops them. A publication that
+//! the server delivers at least once is acknowledged as soon as it is returned.
+//!
+//! `Client::call_bytes` sends an argument that is already serialized, and the body
+//! of a `Reply` or a `Publication` can be read as bytes, so that the client can
+//! carry payloads that are serialized by another program, eg. over FFI.
+//!
+//! # Example
+//!
+//! ```no_run
+//! # #[cfg(feature = "client")]
+//! # {
+//! # use core::time::Duration;
+//! # use toy_rpc_core::client::{CallError, Client, ClientError, Transport};
+//! # #[derive(Debug)]
+//! # enum Error<E> {
+//! #     Client(ClientError<E>),
+//! #     Call(CallError),
+//! # }
+//! # impl<E> From<ClientError<E>> for Error<E> {
+//! #     fn from(err: ClientError<E>) -> Self {
+//! #         Self::Client(err)
+//! #     }
+//! # }
+//! # impl<E> From<CallError> for Error<E> {
+//! #     fn from(err: CallError) -> Self {
+//! #         Self::Call(err)
+//! #     }
+//! # }
+//! # fn run<T: Transport>(uart: T) -> Result<(), Error<T::Error>> {
+//! let mut client: Client<_, 4, 256> = Client::new(uart);
+//! let id = client.call("Arith.add", &(1i32, 6i32), Duration::from_secs(1))?;
+//! loop {
+//!     if let Some(reply) = client.poll()? {
+//!         if reply.id == id {
+//!             let sum: i32 = reply.result()?;
+//!             break;
+//!         }
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! # }
+//! ```
+
+use alloc::format;
+use alloc::string::String;
+use core::fmt;
+use core::time::Duration;
+use serde::{Deserialize, Serialize};
+
+use crate::frame::{self, FrameError, FrameHeader, PayloadLen, PayloadType, MAGIC};
+use crate::message::{ErrorMessage, MessageId, CANCELLATION_TOKEN, CANCELLATION_TOKEN_DELIM};
+use crate::protocol::Header;
+
+/// Transport of the bytes of a `Client`
+pub trait Transport {
+    /// Error of the transport
+    type Error;
+
+    /// Writes all the bytes of `buf`
+    fn write_all(&mut self, buf: &[u8]) -> Result<(), Self::Error>;
+
+    /// Reads the bytes that are available into `buf` and returns the number of bytes
+    /// read. This should not block and return `0` if no byte is available.
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error>;
+}
+
+/// Error of a `Client`
+#[derive(Debug)]
+pub enum ClientError<E> {
+    /// Error of the transport
+    Transport(E),
+    /// `N` calls are already pending
+    TooManyCalls,
+    /// A reply does not fit in the receive buffer. The connection cannot be used
+    /// anymore.
+    BufferFull,
+    /// The bytes received are not a valid frame
+    Frame(FrameError),
+    /// A message cannot be serialized or deserialized
+    Codec(postcard::Error),
+}
+
+impl<E: fmt::Display> fmt::Display for ClientError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Transport(err) => write!(f, "Transport error: {}", err),
+            Self::TooManyCalls => write!(f, "Too many pending calls"),
+            Self::BufferFull => write!(f, "Reply does not fit in the receive buffer"),
+            Self::Frame(err) => write!(f, "{}", err),
+            Self::Codec(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<E: fmt::Debug + fmt::Display> std::error::Error for ClientError<E> {}
+
+impl<E> From<FrameError> for ClientError<E> {
+    fn from(err: FrameError) -> Self {
+        Self::Frame(err)
+    }
+}
+
+impl<E> From<postcard::Error> for ClientError<E> {
+    fn from(err: postcard::Error) -> Self {
+        Self::Codec(err)
+    }
+}
+
+/// Error of a call returned by `Reply::result`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CallError {
+    /// The server returned an error
+    Remote(ErrorMessage),
+    /// The body of the reply cannot be deserialized
+    Codec(postcard::Error),
+}
+
+impl fmt::Display for CallError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Remote(msg) => write!(f, "Server returned an error: {:?}", msg),
+            Self::Codec(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for CallError {}
+
+/// Reply to a call, which borrows the receive buffer of the `Client`
+#[derive(Debug)]
+pub struct Reply<'a> {
+    /// Id returned by `Client::call`
+    pub id: MessageId,
+    /// Whether the method returned `Ok`
+    pub is_ok: bool,
+    /// Body of the reply
+    pub body: &'a [u8],
+}
+
+impl<'a> Reply<'a> {
+    /// Deserializes the result of the call
+    pub fn result<R: Deserialize<'a>>(&self) -> Result<R, CallError> {
+        if self.is_ok {
+            postcard::from_bytes(self.body).map_err(CallError::Codec)
+        } else {
+            match postcard::from_bytes(self.body) {
+                Ok(msg) => Err(CallError::Remote(msg)),
+                Err(err) => Err(CallError::Codec(err)),
+            }
+        }
+    }
+}
+
+/// Publication to a topic that the client subscribed to, which borrows the receive
+/// buffer of the `Client`
+#[derive(Debug)]
+pub struct Publication<'a> {
+    /// Id of the message
+    pub id: MessageId,
+    /// Topic of the publication
+    pub topic: String,
+    /// Type tag of the item if the publisher sent one
+    pub tag: Option<u64>,
+    /// Body of the publication
+    pub body: &'a [u8],
+}
+
+impl<'a> Publication<'a> {
+    /// Deserializes the item
+    pub fn item<I: Deserialize<'a>>(&self) -> Result<I, postcard::Error> {
+        postcard::from_bytes(self.body)
+    }
+}
+
+/// Message returned by `Client::poll_incoming`
+#[derive(Debug)]
+pub enum Incoming<'a> {
+    /// Reply to a pending call
+    Reply(Reply<'a>),
+    /// Publication to a topic that the client subscribed to
+    Publication(Publication<'a>),
+}
+
+/// Client with at most `N` pending calls and buffers of `BUF` bytes
+pub struct Client<T, const N: usize, const BUF: usize> {
+    transport: T,
+    next_id: MessageId,
+    pending: [Option<MessageId>; N],
+    rx: [u8; BUF],
+    rx_len: usize,
+    /// Length of the reply returned by the last `poll`, which is dropped by the next
+    /// `poll`
+    consumed: usize,
+    tx: [u8; BUF],
+}
+
+impl<T: Transport, const N: usize, const BUF: usize> Client<T, N, BUF> {
+    /// Creates a client over `transport`
+    pub fn new(transport: T) -> Self {
+        Self {
+            transport,
+            next_id: 0,
+            pending: [None; N],
+            rx: [0; BUF],
+            rx_len: 0,
+            consumed: 0,
+            tx: [0; BUF],
+        }
+    }
+
+    /// Returns the transport
+    pub fn transport_mut(&mut self) -> &mut T {
+        &mut self.transport
+    }
+
+    /// Returns the transport and drops the client
+    pub fn into_transport(self) -> T {
+        self.transport
+    }
+
+    /// Returns the number of pending calls
+    pub fn pending(&self) -> usize {
+        self.pending.iter().filter(|id| id.is_some()).count()
+    }
+
+    /// Sends a request to `service_method`, which is in the format of
+    /// "{Service}.{method}", and returns the id of the call. The reply is returned
+    /// by `poll`. The server cancels the call after `timeout`.
+    pub fn call<A>(
+        &mut self,
+        service_method: &str,
+        args: &A,
+        timeout: Duration,
+    ) -> Result<MessageId, ClientError<T::Error>>
+    where
+        A: Serialize + ?Sized,
+    {
+        let slot = self.free_slot()?;
+        let header = Header::Request {
+            id: self.next_id,
+            service_method: service_method.into(),
+            timeout,
+        };
+        self.send(&header, args)?;
+        Ok(self.pending_call(slot))
+    }
+
+    /// Sends a request like `call` with an argument that is already serialized with
+    /// `postcard`
+    pub fn call_bytes(
+        &mut self,
+        service_method: &str,
+        args: &[u8],
+        timeout: Duration,
+    ) -> Result<MessageId, ClientError<T::Error>> {
+        let slot = self.free_slot()?;
+        let id = self.next_id;
+        let header = Header::Request {
+            id,
+            service_method: service_method.into(),
+            timeout,
+        };
+        self.write_frame(id, 0, PayloadType::Header, &header)?;
+        write_payload(&mut self.transport, id, 1, PayloadType::Data, args)?;
+        Ok(self.pending_call(slot))
+    }
+
+    /// Publishes `item` to `topic`
+    pub fn publish<I>(&mut self, topic: &str, item: &I) -> Result<(), ClientError<T::Error>>
+    where
+        I: Serialize + ?Sized,
+    {
+        let header = Header::Publish {
+            id: self.next_message_id(),
+            topic: topic.into(),
+        };
+        self.send(&header, item)
+    }
+
+    /// Publishes an item that is already serialized with `postcard` to `topic`
+    pub fn publish_bytes(&mut self, topic: &str, item: &[u8]) -> Result<(), ClientError<T::Error>> {
+        let id = self.next_message_id();
+        let header = Header::Publish {
+            id,
+            topic: topic.into(),
+        };
+        self.write_frame(id, 0, PayloadType::Header, &header)?;
+        write_payload(&mut self.transport, id, 1, PayloadType::Data, item)
+    }
+
+    /// Subscribes to `topic`. The publications are returned by `poll_incoming`.
+    pub fn subscribe(&mut self, topic: &str) -> Result<(), ClientError<T::Error>> {
+        let header = Header::Subscribe {
+            id: self.next_message_id(),
+            topic: topic.into(),
+        };
+        self.send(&header, &())
+    }
+
+    /// Unsubscribes from `topic`
+    pub fn unsubscribe(&mut self, topic: &str) -> Result<(), ClientError<T::Error>> {
+        let header = Header::Unsubscribe {
+            id: self.next_message_id(),
+            topic: topic.into(),
+        };
+        self.send(&header, &())
+    }
+
+    /// Cancels the call `id`. The call is not pending anymore even if the
+    /// cancellation cannot be sent.
+    pub fn cancel(&mut self, id: MessageId) -> Result<(), ClientError<T::Error>> {
+        if let Some(slot) = self.slot(id) {
+            self.pending[slot] = None;
+        }
+        let token = format!("{}{}{}", CANCELLATION_TOKEN, CANCELLATION_TOKEN_DELIM, id);
+        self.send(&Header::Cancel(id), &token)
+    }
+
+    /// Reads the bytes available on the transport and returns the reply to a pending
+    /// call if one is received. The publications are dropped.
+    pub fn poll(&mut self) -> Result<Option<Reply<'_>>, ClientError<T::Error>> {
+        match self.next_incoming(false)? {
+            Some(Incoming::Reply(reply)) => Ok(Some(reply)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Reads the bytes available on the transport and returns the reply to a pending
+    /// call or the publication to a topic if one is received
+    pub fn poll_incoming(&mut self) -> Result<Option<Incoming<'_>>, ClientError<T::Error>> {
+        self.next_incoming(true)
+    }
+
+    fn next_incoming(
+        &mut self,
+        publications: bool,
+    ) -> Result<Option<Incoming<'_>>, ClientError<T::Error>> {
+        self.drop_front(self.consumed);
+        self.consumed = 0;
+
+        let read = self
+            .transport
+            .read(&mut self.rx[self.rx_len..])
+            .map_err(ClientError::Transport)?;
+        self.rx_len += read;
+
+        // a message is a header frame followed by a body frame
+        loop {
+            let (header_len, header) = match frame::decode_frame(&self.rx[..self.rx_len])? {
+                Some(frame) => (frame.len, decode_header(&frame)),
+                None => return self.incomplete(),
+            };
+            let body = match frame::decode_frame(&self.rx[header_len..self.rx_len])? {
+                Some(frame) => frame.len,
+                None => return self.incomplete(),
+            };
+            let len = header_len + body;
+            let body = header_len + 1 + frame::FRAME_HEADER_LEN..len;
+
+            let (id, topic, tag) = match header {
+                Ok(Header::Response { id, is_ok }) => {
+                    if let Some(slot) = self.slot(id) {
+                        self.pending[slot] = None;
+                        self.consumed = len;
+                        let body = &self.rx[body];
+                        return Ok(Some(Incoming::Reply(Reply { id, is_ok, body })));
+                    }
+                    self.drop_front(len);
+                    continue;
+                }
+                Ok(Header::Publish { id, topic }) if publications => (id, topic, None),
+                Ok(Header::TaggedPublish { id, topic, tag }) if publications => {
+                    (id, topic, Some(tag))
+                }
+                Ok(Header::AckedPublish { id, topic, tag }) if publications => {
+                    self.send(&Header::Ack(id), &())?;
+                    (id, topic, tag)
+                }
+                Ok(_) => {
+                    self.drop_front(len);
+                    continue;
+                }
+                Err(err) => {
+                    self.drop_front(len);
+                    return Err(err.into());
+                }
+            };
+            self.consumed = len;
+            let body = &self.rx[body];
+            return Ok(Some(Incoming::Publication(Publication {
+                id,
+                topic,
+                tag,
+                body,
+            })));
+        }
+    }
+
+    fn free_slot(&self) -> Result<usize, ClientError<T::Error>> {
+        self.pending
+            .iter()
+            .position(Option::is_none)
+            .ok_or(ClientError::TooManyCalls)
+    }
+
+    /// Marks the call with the next id as pending in `slot` and returns its id
+    fn pending_call(&mut self, slot: usize) -> MessageId {
+        let id = self.next_message_id();
+        self.pending[slot] = Some(id);
+        id
+    }
+
+    fn next_message_id(&mut self) -> MessageId {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        id
+    }
+
+    fn slot(&self, id: MessageId) -> Option<usize> {
+        self.pending.iter().position(|pending| *pending == Some(id))
+    }
+
+    fn incomplete(&self) -> Result<Option<Incoming<'_>>, ClientError<T::Error>> {
+        if self.rx_len == BUF {
+            Err(ClientError::BufferFull)
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn drop_front(&mut self, len: usize) {
+        self.rx.copy_within(len..self.rx_len, 0);
+        self.rx_len -= len;
+    }
+
+    fn send<B>(&mut self, header: &Header, body: &B) -> Result<(), ClientError<T::Error>>
+    where
+        B: Serialize + ?Sized,
+    {
+        let id = header.id();
+        self.write_frame(id, 0, PayloadType::Header, header)?;
+        self.write_frame(id, 1, PayloadType::Data, body)
+    }
+
+    fn write_frame<S>(
+        &mut self,
+        id: MessageId,
+        frame_id: u8,
+        payload_type: PayloadType,
+        val: &S,
+    ) -> Result<(), ClientError<T::Error>>
+    where
+        S: Serialize + ?Sized,
+    {
+        let payload = postcard::to_slice(val, &mut self.tx[..])?;
+        write_payload(&mut self.transport, id, frame_id, payload_type, payload)
+    }
+}
+
+/// Decodes the header of a message, with the fixed encoding if the server sends it
+fn decode_header(frame: &frame::RawFrame<'_>) -> Result<Header, postcard::Error> {
+    match PayloadType::from(frame.header.payload_type) {
+        PayloadType::FixedHeader => {
+            Header::decode_fixed(frame.payload).ok_or(postcard::Error::DeserializeBadEncoding)
+        }
+        _ => postcard::from_bytes(frame.payload),
+    }
+}
+
+fn write_payload<T: Transport>(
+    transport: &mut T,
+    id: MessageId,
+    frame_id: u8,
+    payload_type: PayloadType,
+    payload: &[u8],
+) -> Result<(), ClientError<T::Error>> {
+    let header = FrameHeader::new(id, frame_id, payload_type, payload.len() as PayloadLen);
+    transport
+        .write_all(&[MAGIC])
+        .and_then(|_| transport.write_all(&header.encode()))
+        .and_then(|_| transport.write_all(payload))
+        .map_err(ClientError::Transport)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    /// Transport that records the bytes written and replays `incoming`
+    #[derive(Default)]
+    struct Loopback {
+        written: Vec<u8>,
+        incoming: Vec<u8>,
+    }
+
+    impl Transport for Loopback {
+        type Error = ();
+
+        fn write_all(&mut self, buf: &[u8]) -> Result<(), ()> {
+            self.written.extend_from_slice(buf);
+            Ok(())
+        }
+
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, ()> {
+            let len = buf.len().min(self.incoming.len());
+            buf[..len].copy_from_slice(&self.incoming[..len]);
+            self.incoming.drain(..len);
+            Ok(len)
+        }
+    }
+
+    fn message<B: Serialize>(out: &mut Vec<u8>, header: &Header, body: &B) {
+        let id = header.id();
+        let header = postcard::to_allocvec(header).unwrap();
+        let body = postcard::to_allocvec(body).unwrap();
+        frame::encode_frame(out, id, 0, PayloadType::Header, &header).unwrap();
+        frame::encode_frame(out, id, 1, PayloadType::Data, &body).unwrap();
+    }
+
+    #[test]
+    fn replies_are_matched_to_calls() {
+        let mut client: Client<Loopback, 2, 64> = Client::new(Loopback::default());
+        let add = client
+            .call("Arith.add", &(1i32, 6i32), Duration::from_secs(1))
+            .unwrap();
+        let div = client
+            .call("Arith.div", &(1i32, 0i32), Duration::from_secs(1))
+            .unwrap();
+        assert!(matches!(
+            client.call("Arith.add", &(0i32, 0i32), Duration::from_secs(1)),
+            Err(ClientError::TooManyCalls)
+        ));
+
+        let request = frame::decode_frame(&client.transport_mut().written)
+            .unwrap()
+            .unwrap();
+        let header: Header = postcard::from_bytes(request.payload).unwrap();
+        assert_eq!(header.id(), add);
+
+        // a reply to an unknown call is dropped
+        let mut incoming = Vec::new();
+        message(&mut incoming, &Header::Response { id: 9, is_ok: true }, &0i32);
+        let error = ErrorMessage::ExecutionError("Divide by zero".into());
+        message(&mut incoming, &Header::Response { id: div, is_ok: false }, &error);
+        message(&mut incoming, &Header::Response { id: add, is_ok: true }, &7i32);
+        client.transport_mut().incoming = incoming;
+
+        let reply = client.poll().unwrap().unwrap();
+        assert_eq!(reply.id, div);
+        assert_eq!(reply.result::<i32>(), Err(CallError::Remote(error)));
+        let reply = client.poll().unwrap().unwrap();
+        assert_eq!(reply.id, add);
+        assert_eq!(reply.result::<i32>(), Ok(7));
+        assert!(client.poll().unwrap().is_none());
+        assert_eq!(client.pending(), 0);
+    }
+
+    #[test]
+    fn serialized_arguments_are_sent_as_is() {
+        let mut client: Client<Loopback, 2, 64> = Client::new(Loopback::default());
+        let args = postcard::to_allocvec(&(1i32, 6i32)).unwrap();
+        let id = client
+            .call_bytes("Arith.add", &args, Duration::from_secs(1))
+            .unwrap();
+
+        let written = client.transport_mut().written.clone();
+        let header = frame::decode_frame(&written).unwrap().unwrap();
+        let body = frame::decode_frame(&written[header.len..])
+            .unwrap()
+            .unwrap();
+        assert_eq!(body.payload, &args[..]);
+
+        let mut incoming = Vec::new();
+        message(&mut incoming, &Header::Response { id, is_ok: true }, &7i32);
+        client.transport_mut().incoming = incoming;
+        let reply = client.poll().unwrap().unwrap();
+        assert_eq!(reply.body, &postcard::to_allocvec(&7i32).unwrap()[..]);
+    }
+
+    #[test]
+    fn publications_are_returned_and_acknowledged() {
+        let mut client: Client<Loopback, 2, 64> = Client::new(Loopback::default());
+        client.subscribe("Count").unwrap();
+        let subscribe = frame::decode_frame(&client.transport_mut().written)
+            .unwrap()
+            .unwrap();
+        let header: Header = postcard::from_bytes(subscribe.payload).unwrap();
+        assert!(matches!(header, Header::Subscribe { ref topic, .. } if topic == "Count"));
+        client.transport_mut().written.clear();
+
+        let mut incoming = Vec::new();
+        let publish = Header::Publish {
+            id: 1,
+            topic: "Count".into(),
+        };
+        message(&mut incoming, &publish, &1u32);
+        let acked = Header::AckedPublish {
+            id: 2,
+            topic: "Count".into(),
+            tag: None,
+        };
+        message(&mut incoming, &acked, &2u32);
+        client.transport_mut().incoming = incoming.clone();
+
+        match client.poll_incoming().unwrap() {
+            Some(Incoming::Publication(publication)) => {
+                assert_eq!(publication.topic, "Count");
+                assert_eq!(publication.item::<u32>(), Ok(1));
+            }
+            other => panic!("Expecting a publication, got {:?}", other),
+        }
+        assert!(client.transport_mut().written.is_empty());
+        match client.poll_incoming().unwrap() {
+            Some(Incoming::Publication(publication)) => {
+                assert_eq!(publication.id, 2);
+                assert_eq!(publication.item::<u32>(), Ok(2));
+            }
+            other => panic!("Expecting a publication, got {:?}", other),
+        }
+        let ack = frame::decode_frame(&client.transport_mut().written)
+            .unwrap()
+            .unwrap();
+        let header: Header = postcard::from_bytes(ack.payload).unwrap();
+        assert_eq!(header, Header::Ack(2));
+
+        // `poll` drops the publications
+        client.transport_mut().incoming = incoming;
+        assert!(client.poll().unwrap().is_none());
+    }
+}
@@ -0,0 +1,353 @@
+//! C interface of the minimal client
+//!
+//! The functions of this module let programs in other languages, eg. Python with
+//! `ctypes` or Node with `ffi-napi`, call the methods of a toy-rpc server and
+//! publish or subscribe to its topics with the same implementation of the protocol
+//! as the `client::Client`, instead of reimplementing the headers and the frames.
+//!
+//! A `ToyRpcClient` is connected over TCP and the server must serve the connection
+//! with the `PostcardCodec` of toy-rpc (feature `postcard_codec`). The arguments and
+//! the bodies of the replies and the publications are passed as bytes serialized
+//! with `postcard`, which is left to the bindings. The body of a reply to a call
+//! that failed is a serialized `message::ErrorMessage`.
+//!
+//! Like the `client::Client`, a `ToyRpcClient` never blocks on reads and is driven
+//! by calling `toy_rpc_poll` until it returns a message. All the functions return
+//! `TOY_RPC_OK` or one of the negative `TOY_RPC_ERR_*` codes. The declarations for
+//! C are in `include/toy_rpc.h`.
+//!
+//! The library is built with
+//!
+//! ```sh
+//! cargo rustc -p toy-rpc-core --release --features ffi --crate-type cdylib
+//! ```
+//!
+//! # Example
+//!
+//! ```c
+//! ToyRpcClient *client = toy_rpc_connect("127.0.0.1:23333");
+//! uint16_t id;
+//! toy_rpc_call(client, "Arith.add", args, args_len, 1000, &id);
+//!
+//! ToyRpcMessage message;
+//! do {
+//!     toy_rpc_poll(client, &message);
+//! } while (message.kind != TOY_RPC_REPLY_OK && message.kind != TOY_RPC_REPLY_ERR);
+//! toy_rpc_close(client);
+//! ```
+
+use std::ffi::CStr;
+use std::io::{ErrorKind, Read, Write};
+use std::net::TcpStream;
+use std::os::raw::{c_char, c_int};
+use std::time::Duration;
+
+use crate::client::{Client, ClientError, Incoming, Transport};
+use crate::message::MessageId;
+
+/// Maximum number of pending calls of a `ToyRpcClient`
+pub const TOY_RPC_MAX_PENDING: usize = 64;
+/// Size in bytes of the buffers of a `ToyRpcClient`, which bounds the size of a
+/// message
+pub const TOY_RPC_BUF_LEN: usize = 64 * 1024;
+
+/// The function succeeded
+pub const TOY_RPC_OK: c_int = 0;
+/// A pointer is null or a string is not valid UTF-8
+pub const TOY_RPC_ERR_ARGUMENT: c_int = -1;
+/// The connection failed
+pub const TOY_RPC_ERR_TRANSPORT: c_int = -2;
+/// `TOY_RPC_MAX_PENDING` calls are already pending
+pub const TOY_RPC_ERR_TOO_MANY_CALLS: c_int = -3;
+/// A message does not fit in the buffers. The client cannot be used anymore.
+pub const TOY_RPC_ERR_BUFFER_FULL: c_int = -4;
+/// The bytes received are not valid frames or headers
+pub const TOY_RPC_ERR_PROTOCOL: c_int = -5;
+
+/// No message has been received
+pub const TOY_RPC_NONE: c_int = 0;
+/// Reply to a call that succeeded
+pub const TOY_RPC_REPLY_OK: c_int = 1;
+/// Reply to a call that failed
+pub const TOY_RPC_REPLY_ERR: c_int = 2;
+/// Publication to a topic that the client subscribed to
+pub const TOY_RPC_PUBLICATION: c_int = 3;
+
+/// Client returned by `toy_rpc_connect`
+pub struct ToyRpcClient {
+    client: Client<TcpTransport, TOY_RPC_MAX_PENDING, TOY_RPC_BUF_LEN>,
+    // topic of the last publication, which is borrowed by the last message
+    topic: String,
+}
+
+/// Message returned by `toy_rpc_poll`
+///
+/// The topic and the body are borrowed from the client and are valid until the
+/// next call of `toy_rpc_poll` or `toy_rpc_close`.
+#[repr(C)]
+#[derive(Debug)]
+pub struct ToyRpcMessage {
+    /// One of `TOY_RPC_NONE`, `TOY_RPC_REPLY_OK`, `TOY_RPC_REPLY_ERR` and
+    /// `TOY_RPC_PUBLICATION`
+    pub kind: c_int,
+    /// Id of the call of a reply, or id of a publication
+    pub id: MessageId,
+    /// Topic of a publication, which is not null-terminated
+    pub topic: *const u8,
+    /// Length of the topic
+    pub topic_len: usize,
+    /// Body of the message
+    pub body: *const u8,
+    /// Length of the body
+    pub body_len: usize,
+}
+
+impl ToyRpcMessage {
+    fn none() -> Self {
+        Self {
+            kind: TOY_RPC_NONE,
+            id: 0,
+            topic: std::ptr::null(),
+            topic_len: 0,
+            body: std::ptr::null(),
+            body_len: 0,
+        }
+    }
+}
+
+/// Transport over a non-blocking `TcpStream`
+struct TcpTransport(TcpStream);
+
+impl Transport for TcpTransport {
+    type Error = std::io::Error;
+
+    fn write_all(&mut self, buf: &[u8]) -> Result<(), Self::Error> {
+        let mut written = 0;
+        while written < buf.len() {
+            match self.0.write(&buf[written..]) {
+                Ok(0) => return Err(ErrorKind::WriteZero.into()),
+                Ok(n) => written += n,
+                Err(err) if err.kind() == ErrorKind::WouldBlock => std::thread::yield_now(),
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        match self.0.read(buf) {
+            // the transport is never read into an empty buffer
+            Ok(0) => Err(ErrorKind::UnexpectedEof.into()),
+            Ok(n) => Ok(n),
+            Err(err) if err.kind() == ErrorKind::WouldBlock => Ok(0),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+fn status<T, E>(result: Result<T, ClientError<E>>) -> c_int {
+    match result {
+        Ok(_) => TOY_RPC_OK,
+        Err(ClientError::Transport(_)) => TOY_RPC_ERR_TRANSPORT,
+        Err(ClientError::TooManyCalls) => TOY_RPC_ERR_TOO_MANY_CALLS,
+        Err(ClientError::BufferFull) => TOY_RPC_ERR_BUFFER_FULL,
+        Err(ClientError::Frame(_)) | Err(ClientError::Codec(_)) => TOY_RPC_ERR_PROTOCOL,
+    }
+}
+
+/// Reads a null-terminated UTF-8 string
+unsafe fn str_arg<'a>(ptr: *const c_char) -> Option<&'a str> {
+    if ptr.is_null() {
+        return None;
+    }
+    CStr::from_ptr(ptr).to_str().ok()
+}
+
+/// Connects to the server at `addr`, eg. `"127.0.0.1:23333"`, and returns the
+/// client, or null if the connection failed
+///
+/// # Safety
+///
+/// `addr` must be null or a null-terminated string
+#[no_mangle]
+pub unsafe extern "C" fn toy_rpc_connect(addr: *const c_char) -> *mut ToyRpcClient {
+    let addr = match str_arg(addr) {
+        Some(addr) => addr,
+        None => return std::ptr::null_mut(),
+    };
+    let stream = match TcpStream::connect(addr) {
+        Ok(stream) => stream,
+        Err(_) => return std::ptr::null_mut(),
+    };
+    if stream.set_nonblocking(true).is_err() {
+        return std::ptr::null_mut();
+    }
+    let _ = stream.set_nodelay(true);
+    Box::into_raw(Box::new(ToyRpcClient {
+        client: Client::new(TcpTransport(stream)),
+        topic: String::new(),
+    }))
+}
+
+/// Calls `service_method`, which is in the format of "{Service}.{method}", with the
+/// `args_len` bytes of `args` and writes the id of the call to `id`. The server
+/// cancels the call after `timeout_ms` milliseconds.
+///
+/// # Safety
+///
+/// `client` must be returned by `toy_rpc_connect`, `service_method` must be a
+/// null-terminated string, `args` must point to `args_len` bytes and `id` must be
+/// valid for writes
+#[no_mangle]
+pub unsafe extern "C" fn toy_rpc_call(
+    client: *mut ToyRpcClient,
+    service_method: *const c_char,
+    args: *const u8,
+    args_len: usize,
+    timeout_ms: u64,
+    id: *mut MessageId,
+) -> c_int {
+    let (client, service_method) = match (client.as_mut(), str_arg(service_method)) {
+        (Some(client), Some(service_method)) => (client, service_method),
+        _ => return TOY_RPC_ERR_ARGUMENT,
+    };
+    if args.is_null() || id.is_null() {
+        return TOY_RPC_ERR_ARGUMENT;
+    }
+    let args = std::slice::from_raw_parts(args, args_len);
+    let timeout = Duration::from_millis(timeout_ms);
+    let result = client.client.call_bytes(service_method, args, timeout);
+    if let Ok(call) = result {
+        *id = call;
+    }
+    status(result)
+}
+
+/// Cancels the call `id`
+///
+/// # Safety
+///
+/// `client` must be returned by `toy_rpc_connect`
+#[no_mangle]
+pub unsafe extern "C" fn toy_rpc_cancel(client: *mut ToyRpcClient, id: MessageId) -> c_int {
+    match client.as_mut() {
+        Some(client) => status(client.client.cancel(id)),
+        None => TOY_RPC_ERR_ARGUMENT,
+    }
+}
+
+/// Publishes the `item_len` bytes of `item` to `topic`
+///
+/// # Safety
+///
+/// `client` must be returned by `toy_rpc_connect`, `topic` must be a
+/// null-terminated string and `item` must point to `item_len` bytes
+#[no_mangle]
+pub unsafe extern "C" fn toy_rpc_publish(
+    client: *mut ToyRpcClient,
+    topic: *const c_char,
+    item: *const u8,
+    item_len: usize,
+) -> c_int {
+    let (client, topic) = match (client.as_mut(), str_arg(topic)) {
+        (Some(client), Some(topic)) => (client, topic),
+        _ => return TOY_RPC_ERR_ARGUMENT,
+    };
+    if item.is_null() {
+        return TOY_RPC_ERR_ARGUMENT;
+    }
+    let item = std::slice::from_raw_parts(item, item_len);
+    status(client.client.publish_bytes(topic, item))
+}
+
+/// Subscribes to `topic`. The publications are returned by `toy_rpc_poll`.
+///
+/// # Safety
+///
+/// `client` must be returned by `toy_rpc_connect` and `topic` must be a
+/// null-terminated string
+#[no_mangle]
+pub unsafe extern "C" fn toy_rpc_subscribe(
+    client: *mut ToyRpcClient,
+    topic: *const c_char,
+) -> c_int {
+    match (client.as_mut(), str_arg(topic)) {
+        (Some(client), Some(topic)) => status(client.client.subscribe(topic)),
+        _ => TOY_RPC_ERR_ARGUMENT,
+    }
+}
+
+/// Unsubscribes from `topic`
+///
+/// # Safety
+///
+/// `client` must be returned by `toy_rpc_connect` and `topic` must be a
+/// null-terminated string
+#[no_mangle]
+pub unsafe extern "C" fn toy_rpc_unsubscribe(
+    client: *mut ToyRpcClient,
+    topic: *const c_char,
+) -> c_int {
+    match (client.as_mut(), str_arg(topic)) {
+        (Some(client), Some(topic)) => status(client.client.unsubscribe(topic)),
+        _ => TOY_RPC_ERR_ARGUMENT,
+    }
+}
+
+/// Reads the bytes available on the connection and writes the reply or the
+/// publication received, if any, to `message`. `message.kind` is `TOY_RPC_NONE` if
+/// no message has been received.
+///
+/// # Safety
+///
+/// `client` must be returned by `toy_rpc_connect` and `message` must be valid for
+/// writes
+#[no_mangle]
+pub unsafe extern "C" fn toy_rpc_poll(
+    client: *mut ToyRpcClient,
+    message: *mut ToyRpcMessage,
+) -> c_int {
+    let client = match client.as_mut() {
+        Some(client) if !message.is_null() => client,
+        _ => return TOY_RPC_ERR_ARGUMENT,
+    };
+    message.write(ToyRpcMessage::none());
+    let message = &mut *message;
+    let ToyRpcClient { client, topic } = client;
+    match client.poll_incoming() {
+        Ok(Some(Incoming::Reply(reply))) => {
+            message.kind = match reply.is_ok {
+                true => TOY_RPC_REPLY_OK,
+                false => TOY_RPC_REPLY_ERR,
+            };
+            message.id = reply.id;
+            message.body = reply.body.as_ptr();
+            message.body_len = reply.body.len();
+            TOY_RPC_OK
+        }
+        Ok(Some(Incoming::Publication(publication))) => {
+            *topic = publication.topic;
+            message.kind = TOY_RPC_PUBLICATION;
+            message.id = publication.id;
+            message.topic = topic.as_ptr();
+            message.topic_len = topic.len();
+            message.body = publication.body.as_ptr();
+            message.body_len = publication.body.len();
+            TOY_RPC_OK
+        }
+        result => status(result),
+    }
+}
+
+/// Closes the connection and frees the client
+///
+/// # Safety
+///
+/// `client` must be null or returned by `toy_rpc_connect`, and must not be used
+/// afterwards
+#[no_mangle]
+pub unsafe extern "C" fn toy_rpc_close(client: *mut ToyRpcClient) {
+    if !client.is_null() {
+        drop(Box::from_raw(client));
+    }
+}
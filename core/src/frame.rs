@@ -0,0 +1,315 @@
+//! Frames of the custom binary transport
+//!
+//! A frame is the `MAGIC` byte, followed by a `FrameHeader` of `FRAME_HEADER_LEN`
+//! bytes and by the payload of `payload_len` bytes. The frame header is encoded in
+//! the same way as `bincode` with fixed-size integers in little endian:
+//!
+//! | field          | size |
+//! |----------------|------|
+//! | `message_id`   | 2    |
+//! | `frame_id`     | 1    |
+//! | `payload_type` | 1    |
+//! | `payload_len`  | 4    |
+//!
+//! The header of a message is sent in a frame with `frame_id` 0 and
+//! `PayloadType::Header`, and its body in a frame with `frame_id` 1 and
+//! `PayloadType::Data`. A connection is closed gracefully with the frame returned by
+//! `FrameHeader::end`.
+//!
+//! The low 4 bits of `payload_type` hold the `PayloadType`. The high 4 bits hold the
+//! id of the algorithm the payload is compressed with, or 0 if the payload is not
+//! compressed, see `FrameHeader::compression`.
+
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+use core::fmt;
+use serde::{Deserialize, Serialize};
+
+use crate::message::MessageId;
+
+/// Id of a frame
+pub type FrameId = u8;
+/// Length of the payload of a frame
+pub type PayloadLen = u32;
+
+/// Byte at the beginning of every frame
+pub const MAGIC: u8 = 13;
+/// Length in bytes of an encoded `FrameHeader`
+pub const FRAME_HEADER_LEN: usize = 8;
+/// Frame id of the frame that closes a connection
+pub const END_FRAME_ID: FrameId = 131;
+/// Bits of `payload_type` that hold the id of the compression of the payload
+pub const COMPRESSION_MASK: u8 = 0xF0;
+
+/// Error of the encoding or decoding of a frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameError {
+    /// The frame does not start with `MAGIC`. The peer is likely using a different
+    /// protocol or version.
+    InvalidMagic(u8),
+    /// The buffer is shorter than a frame header
+    Truncated(usize),
+    /// The payload is longer than `PayloadLen::MAX`
+    PayloadTooLong(usize),
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidMagic(byte) => write!(
+                f,
+                "Magic byte mismatch, found {}. Client may be using a different protocol or version",
+                byte
+            ),
+            Self::Truncated(len) => write!(
+                f,
+                "Frame header is {} bytes, found {} bytes",
+                FRAME_HEADER_LEN, len
+            ),
+            Self::PayloadTooLong(len) => write!(
+                f,
+                "Payload length exceeded maximum. Max is {}, found {}",
+                PayloadLen::MAX,
+                len
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for FrameError {}
+
+/// Header of a frame
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub struct FrameHeader {
+    /// Id of the message the frame belongs to
+    pub message_id: MessageId,
+    /// Id of the frame in the message
+    pub frame_id: FrameId,
+    /// Type of the payload, see `PayloadType`
+    pub payload_type: u8,
+    /// Length of the payload in bytes
+    pub payload_len: PayloadLen,
+}
+
+impl FrameHeader {
+    /// Constructs a new frame header
+    pub fn new(
+        message_id: MessageId,
+        frame_id: FrameId,
+        payload_type: PayloadType,
+        payload_len: PayloadLen,
+    ) -> Self {
+        Self {
+            message_id,
+            frame_id,
+            payload_type: payload_type.into(),
+            payload_len,
+        }
+    }
+
+    /// Constructs the header of the frame that closes a connection
+    pub fn end() -> Self {
+        Self::new(0, END_FRAME_ID, PayloadType::Trailer, 0)
+    }
+
+    /// Returns whether this is the header of the frame that closes a connection
+    pub fn is_end(&self) -> bool {
+        matches!(PayloadType::from(self.payload_type), PayloadType::Trailer)
+            && self.frame_id == END_FRAME_ID
+            && self.message_id == 0
+            && self.payload_len == 0
+    }
+
+    /// Returns the id of the algorithm the payload is compressed with, or 0 if the
+    /// payload is not compressed
+    pub fn compression(&self) -> u8 {
+        (self.payload_type & COMPRESSION_MASK) >> 4
+    }
+
+    /// Sets the id of the algorithm the payload is compressed with, 0 if the payload
+    /// is not compressed. Only the low 4 bits of `id` are kept.
+    pub fn with_compression(self, id: u8) -> Self {
+        let mut header = self;
+        header.payload_type = (header.payload_type & !COMPRESSION_MASK) | (id << 4);
+        header
+    }
+
+    /// Encodes the frame header, without the magic byte
+    pub fn encode(&self) -> [u8; FRAME_HEADER_LEN] {
+        let mut buf = [0; FRAME_HEADER_LEN];
+        buf[0..2].copy_from_slice(&self.message_id.to_le_bytes());
+        buf[2] = self.frame_id;
+        buf[3] = self.payload_type;
+        buf[4..8].copy_from_slice(&self.payload_len.to_le_bytes());
+        buf
+    }
+
+    /// Decodes a frame header from the first `FRAME_HEADER_LEN` bytes of `buf`,
+    /// which does not include the magic byte
+    pub fn decode(buf: &[u8]) -> Result<Self, FrameError> {
+        if buf.len() < FRAME_HEADER_LEN {
+            return Err(FrameError::Truncated(buf.len()));
+        }
+        let mut message_id = [0; 2];
+        message_id.copy_from_slice(&buf[0..2]);
+        let mut payload_len = [0; 4];
+        payload_len.copy_from_slice(&buf[4..8]);
+        Ok(Self {
+            message_id: MessageId::from_le_bytes(message_id),
+            frame_id: buf[2],
+            payload_type: buf[3],
+            payload_len: PayloadLen::from_le_bytes(payload_len),
+        })
+    }
+
+    /// Constructs a new frame header from bytes
+    pub fn from_slice(buf: &[u8]) -> Result<Self, FrameError> {
+        Self::decode(buf)
+    }
+
+    /// Convert a frame header to bytes
+    pub fn to_vec(&self) -> Result<Vec<u8>, FrameError> {
+        Ok(self.encode().to_vec())
+    }
+}
+
+/// Type of payload carried by a frame
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub enum PayloadType {
+    /// Message header
+    #[default]
+    Header,
+    /// Message body
+    Data,
+    /// Message trailer
+    Trailer,
+    /// Message header with the fixed encoding, see `Header::encode_fixed`
+    FixedHeader,
+}
+
+impl From<u8> for PayloadType {
+    fn from(t: u8) -> Self {
+        match t & !COMPRESSION_MASK {
+            0 => Self::Header,
+            1 => Self::Data,
+            2 => Self::Trailer,
+            3 => Self::FixedHeader,
+            _ => Self::Trailer,
+        }
+    }
+}
+
+impl From<PayloadType> for u8 {
+    fn from(t: PayloadType) -> Self {
+        match t {
+            PayloadType::Header => 0,
+            PayloadType::Data => 1,
+            PayloadType::Trailer => 2,
+            PayloadType::FixedHeader => 3,
+        }
+    }
+}
+
+/// Appends a whole frame, ie. the magic byte, the frame header and the payload, to
+/// `out`
+pub fn encode_frame(
+    out: &mut Vec<u8>,
+    message_id: MessageId,
+    frame_id: FrameId,
+    payload_type: PayloadType,
+    payload: &[u8],
+) -> Result<(), FrameError> {
+    let payload_len = PayloadLen::try_from(payload.len())
+        .map_err(|_| FrameError::PayloadTooLong(payload.len()))?;
+    let header = FrameHeader::new(message_id, frame_id, payload_type, payload_len);
+    out.reserve(1 + FRAME_HEADER_LEN + payload.len());
+    out.push(MAGIC);
+    out.extend_from_slice(&header.encode());
+    out.extend_from_slice(payload);
+    Ok(())
+}
+
+/// A frame decoded from a buffer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawFrame<'a> {
+    /// Header of the frame
+    pub header: FrameHeader,
+    /// Payload of the frame
+    pub payload: &'a [u8],
+    /// Length of the whole frame in the buffer
+    pub len: usize,
+}
+
+/// Decodes the frame at the beginning of `buf`. Returns `None` if `buf` does not
+/// hold a whole frame yet.
+pub fn decode_frame(buf: &[u8]) -> Result<Option<RawFrame<'_>>, FrameError> {
+    let magic = match buf.first() {
+        Some(magic) => *magic,
+        None => return Ok(None),
+    };
+    if magic != MAGIC {
+        return Err(FrameError::InvalidMagic(magic));
+    }
+    if buf.len() < 1 + FRAME_HEADER_LEN {
+        return Ok(None);
+    }
+    let header = FrameHeader::decode(&buf[1..])?;
+    let len = 1 + FRAME_HEADER_LEN + header.payload_len as usize;
+    if buf.len() < len {
+        return Ok(None);
+    }
+    let payload = &buf[1 + FRAME_HEADER_LEN..len];
+    Ok(Some(RawFrame {
+        header,
+        payload,
+        len,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bincode::Options;
+
+    #[test]
+    fn encoding_matches_bincode() {
+        let header = FrameHeader::new(0x1234, 1, PayloadType::Data, 0x0102_0304);
+        let bytes = bincode::DefaultOptions::new()
+            .with_fixint_encoding()
+            .serialize(&header)
+            .unwrap();
+        assert_eq!(&header.encode()[..], &bytes[..]);
+        assert_eq!(FrameHeader::decode(&bytes).unwrap(), header);
+    }
+
+    #[test]
+    fn frames_are_split() {
+        let mut buf = Vec::new();
+        encode_frame(&mut buf, 7, 0, PayloadType::Header, b"head").unwrap();
+        encode_frame(&mut buf, 7, 1, PayloadType::Data, b"body").unwrap();
+
+        let first = decode_frame(&buf).unwrap().unwrap();
+        assert_eq!(first.header.message_id, 7);
+        assert_eq!(first.payload, b"head");
+        let second = decode_frame(&buf[first.len..]).unwrap().unwrap();
+        assert_eq!(second.header.frame_id, 1);
+        assert_eq!(second.payload, b"body");
+
+        assert_eq!(decode_frame(&buf[..first.len - 1]).unwrap(), None);
+        assert_eq!(decode_frame(&[0]), Err(FrameError::InvalidMagic(0)));
+    }
+
+    #[test]
+    fn compression_bits() {
+        let header = FrameHeader::new(3, 1, PayloadType::Data, 16).with_compression(2);
+        let decoded = FrameHeader::decode(&header.encode()).unwrap();
+        assert_eq!(decoded.compression(), 2);
+        assert_eq!(PayloadType::from(decoded.payload_type), PayloadType::Data);
+        assert_eq!(decoded.with_compression(0).payload_type, 1);
+        assert_eq!(
+            FrameHeader::new(3, 1, PayloadType::Data, 16).compression(),
+            0
+        );
+    }
+}
@@ -0,0 +1,64 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+#![cfg_attr(not(feature = "ffi"), forbid(unsafe_code))]
+#![cfg_attr(feature = "ffi", deny(unsafe_code))]
+#![warn(missing_docs)]
+
+//! # Core of the toy-rpc protocol
+//!
+//! This crate holds the parts of the toy-rpc wire format that do not depend on an
+//! async runtime or on the standard library: the `Header` of the messages and the
+//! frames of the custom binary transport. It only needs `alloc`, so that a minimal
+//! client can be implemented on an embedded device and talk to a full toy-rpc server
+//! over TCP or a serial link.
+//!
+//! A message is a header followed by a body, and each of them is sent in its own
+//! frame. The header and the body are serialized with the serde format of the codec
+//! of the server (eg. `bincode` with varint encoding for the default codec), which is
+//! left to the device.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use toy_rpc_core::{frame::{self, FrameHeader, PayloadType}, protocol::Header};
+//! # use std::io::Write;
+//! # use std::time::Duration;
+//! # fn serialize<T: serde::Serialize>(value: &T) -> Vec<u8> {
+//! #     bincode::serialize(value).unwrap()
+//! # }
+//! # fn main() -> Result<(), frame::FrameError> {
+//! # let mut uart = Vec::new();
+//!
+//! let header = Header::Request {
+//!     id: 1,
+//!     service_method: "Arith.add".into(),
+//!     timeout: Duration::from_secs(10),
+//! };
+//! let mut out = Vec::new();
+//! frame::encode_frame(&mut out, 1, 0, PayloadType::Header, &serialize(&header))?;
+//! frame::encode_frame(&mut out, 1, 1, PayloadType::Data, &serialize(&(1i32, 6i32)))?;
+//! uart.write_all(&out);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The `std` feature implements `std::error::Error` for the errors of the crate.
+//!
+//! The `client` feature enables a minimal `client::Client` with a fixed number of
+//! pending calls, which uses the `postcard` format.
+//!
+//! The `ffi` feature exposes the client over TCP to other languages with a C
+//! interface, see `ffi`. It requires `std` and is the only part of the crate that
+//! uses `unsafe`.
+
+extern crate alloc;
+
+#[cfg(feature = "client")]
+pub mod client;
+#[cfg(feature = "ffi")]
+#[allow(unsafe_code)]
+pub mod ffi;
+pub mod frame;
+pub mod message;
+pub mod protocol;
+
+pub use message::MessageId;
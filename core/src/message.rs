@@ -0,0 +1,264 @@
+//! Identification of the messages and errors of the responses
+use alloc::string::{String, ToString};
+use core::fmt;
+use serde::de::{self, Deserializer, EnumAccess, VariantAccess, Visitor};
+use serde::{Deserialize, Serialize, Serializer};
+
+/// Type of message id is u16
+pub type MessageId = u16;
+
+/// Body of a `Header::Cancel` message is this token, followed by
+/// `CANCELLATION_TOKEN_DELIM` and the id of the canceled request
+pub const CANCELLATION_TOKEN: &str = "RPC_TASK_CANCELLATION";
+/// Delimiter between the cancellation token and the id of the canceled request
+pub const CANCELLATION_TOKEN_DELIM: &str = ".";
+
+/// Body of a response whose `is_ok` is `false`
+///
+/// The error kinds are encoded like a derived serde enum, by the index or the name
+/// of the variant depending on the format. New kinds are only ever added as
+/// variants carrying a `String`, so that a peer of an older version, which does not
+/// know the variant, decodes it as a `Remote` error instead of failing to parse the
+/// response.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ErrorMessage {
+    /// The argument of the request cannot be deserialized
+    InvalidArgument,
+    /// The service is not found on the server
+    ServiceNotFound,
+    /// The method is not found on the service
+    MethodNotFound,
+    /// The method returned an error
+    ExecutionError(String),
+    /// The server rejected the topic of a publication or a subscription
+    TopicRejected(String),
+    /// The request or the response exceeds a size limit of the server
+    PayloadTooLarge(String),
+    /// The server stopped waiting for the handler of the call, either while the
+    /// call was queued or while it was executing
+    Timeout(String),
+    /// An error kind that is unknown to this version. It is never decoded from a
+    /// known variant, and it is encoded as an `ExecutionError`, which every version
+    /// knows.
+    Remote(RemoteError),
+}
+
+/// Error of a kind added in a later version of the protocol
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteError {
+    /// Name of the variant, or its index for the formats that encode the variants
+    /// by index (eg. `bincode` and `postcard`)
+    pub kind: String,
+    /// Message carried by the variant
+    pub payload: String,
+}
+
+impl fmt::Display for RemoteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.kind, self.payload)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for RemoteError {}
+
+const NAME: &str = "ErrorMessage";
+const VARIANTS: &[&str] = &[
+    "InvalidArgument",
+    "ServiceNotFound",
+    "MethodNotFound",
+    "ExecutionError",
+    "TopicRejected",
+    "PayloadTooLarge",
+    "Timeout",
+];
+
+impl Serialize for ErrorMessage {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Self::InvalidArgument => serializer.serialize_unit_variant(NAME, 0, VARIANTS[0]),
+            Self::ServiceNotFound => serializer.serialize_unit_variant(NAME, 1, VARIANTS[1]),
+            Self::MethodNotFound => serializer.serialize_unit_variant(NAME, 2, VARIANTS[2]),
+            Self::ExecutionError(msg) => {
+                serializer.serialize_newtype_variant(NAME, 3, VARIANTS[3], msg)
+            }
+            Self::TopicRejected(msg) => {
+                serializer.serialize_newtype_variant(NAME, 4, VARIANTS[4], msg)
+            }
+            Self::PayloadTooLarge(msg) => {
+                serializer.serialize_newtype_variant(NAME, 5, VARIANTS[5], msg)
+            }
+            Self::Timeout(msg) => serializer.serialize_newtype_variant(NAME, 6, VARIANTS[6], msg),
+            // the peer may not know the kind either
+            Self::Remote(err) => {
+                serializer.serialize_newtype_variant(NAME, 3, VARIANTS[3], &err.to_string())
+            }
+        }
+    }
+}
+
+/// Variant of an `ErrorMessage`, which is either one of `VARIANTS` or unknown
+enum Kind {
+    Known(usize),
+    Unknown(String),
+}
+
+impl<'de> Deserialize<'de> for Kind {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct KindVisitor;
+
+        impl<'de> Visitor<'de> for KindVisitor {
+            type Value = Kind;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("the index or the name of an error kind")
+            }
+
+            fn visit_u64<E: de::Error>(self, index: u64) -> Result<Kind, E> {
+                Ok(match index {
+                    index if index < VARIANTS.len() as u64 => Kind::Known(index as usize),
+                    index => Kind::Unknown(index.to_string()),
+                })
+            }
+
+            fn visit_str<E: de::Error>(self, name: &str) -> Result<Kind, E> {
+                Ok(match VARIANTS.iter().position(|variant| *variant == name) {
+                    Some(index) => Kind::Known(index),
+                    None => Kind::Unknown(name.into()),
+                })
+            }
+
+            fn visit_bytes<E: de::Error>(self, name: &[u8]) -> Result<Kind, E> {
+                match core::str::from_utf8(name) {
+                    Ok(name) => self.visit_str(name),
+                    Err(_) => Err(E::invalid_value(de::Unexpected::Bytes(name), &self)),
+                }
+            }
+        }
+
+        deserializer.deserialize_identifier(KindVisitor)
+    }
+}
+
+impl<'de> Deserialize<'de> for ErrorMessage {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct MessageVisitor;
+
+        impl<'de> Visitor<'de> for MessageVisitor {
+            type Value = ErrorMessage;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("enum ErrorMessage")
+            }
+
+            fn visit_enum<A: EnumAccess<'de>>(self, data: A) -> Result<ErrorMessage, A::Error> {
+                let (kind, variant) = data.variant::<Kind>()?;
+                let msg = match kind {
+                    Kind::Known(0) => variant
+                        .unit_variant()
+                        .map(|_| ErrorMessage::InvalidArgument)?,
+                    Kind::Known(1) => variant
+                        .unit_variant()
+                        .map(|_| ErrorMessage::ServiceNotFound)?,
+                    Kind::Known(2) => variant
+                        .unit_variant()
+                        .map(|_| ErrorMessage::MethodNotFound)?,
+                    Kind::Known(3) => ErrorMessage::ExecutionError(variant.newtype_variant()?),
+                    Kind::Known(4) => ErrorMessage::TopicRejected(variant.newtype_variant()?),
+                    Kind::Known(5) => ErrorMessage::PayloadTooLarge(variant.newtype_variant()?),
+                    Kind::Known(_) => ErrorMessage::Timeout(variant.newtype_variant()?),
+                    Kind::Unknown(kind) => ErrorMessage::Remote(RemoteError {
+                        kind,
+                        payload: variant.newtype_variant()?,
+                    }),
+                };
+                Ok(msg)
+            }
+        }
+
+        deserializer.deserialize_enum(NAME, VARIANTS, MessageVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    /// `ErrorMessage` as derived before it had its own serde impls
+    #[derive(Debug, Serialize, Deserialize)]
+    enum Derived {
+        InvalidArgument,
+        ServiceNotFound,
+        MethodNotFound,
+        ExecutionError(String),
+        TopicRejected(String),
+        PayloadTooLarge(String),
+        Timeout(String),
+        // a kind added by a later version
+        Overloaded(String),
+    }
+
+    fn pairs() -> Vec<(Derived, ErrorMessage)> {
+        alloc::vec![
+            (Derived::InvalidArgument, ErrorMessage::InvalidArgument),
+            (Derived::ServiceNotFound, ErrorMessage::ServiceNotFound),
+            (Derived::MethodNotFound, ErrorMessage::MethodNotFound),
+            (
+                Derived::ExecutionError("panic".into()),
+                ErrorMessage::ExecutionError("panic".into())
+            ),
+            (
+                Derived::TopicRejected("a.b".into()),
+                ErrorMessage::TopicRejected("a.b".into())
+            ),
+            (
+                Derived::PayloadTooLarge("1 MB".into()),
+                ErrorMessage::PayloadTooLarge("1 MB".into())
+            ),
+            (
+                Derived::Timeout("phase=queue".into()),
+                ErrorMessage::Timeout("phase=queue".into())
+            ),
+        ]
+    }
+
+    #[test]
+    fn known_kinds_match_the_derived_encoding() {
+        for (derived, msg) in pairs() {
+            let bytes = bincode::serialize(&derived).unwrap();
+            assert_eq!(bincode::serialize(&msg).unwrap(), bytes);
+            assert_eq!(bincode::deserialize::<ErrorMessage>(&bytes).unwrap(), msg);
+
+            let bytes = postcard::to_allocvec(&derived).unwrap();
+            assert_eq!(postcard::to_allocvec(&msg).unwrap(), bytes);
+            assert_eq!(postcard::from_bytes::<ErrorMessage>(&bytes).unwrap(), msg);
+        }
+    }
+
+    #[test]
+    fn unknown_kinds_are_remote_errors() {
+        let newer = Derived::Overloaded("queue is full".into());
+        let remote = ErrorMessage::Remote(RemoteError {
+            kind: "7".into(),
+            payload: "queue is full".into(),
+        });
+        let bytes = bincode::serialize(&newer).unwrap();
+        assert_eq!(
+            bincode::deserialize::<ErrorMessage>(&bytes).unwrap(),
+            remote
+        );
+        let bytes = postcard::to_allocvec(&newer).unwrap();
+        assert_eq!(
+            postcard::from_bytes::<ErrorMessage>(&bytes).unwrap(),
+            remote
+        );
+
+        // and they are passed on as execution errors
+        let bytes = bincode::serialize(&remote).unwrap();
+        match bincode::deserialize::<Derived>(&bytes).unwrap() {
+            Derived::ExecutionError(msg) => assert_eq!(msg, "7: queue is full"),
+            other => panic!("Expecting an execution error, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,340 @@
+//! Message protocol between server and client
+//!
+//! The header of a message is serialized with the serde format of the codec. The
+//! `Request` and `Response` headers, which are sent for every call, also have a
+//! fixed encoding that is written and read without serde, see `Header::encode_fixed`.
+//! A header with the fixed encoding is sent in a frame of type
+//! `PayloadType::FixedHeader`.
+use alloc::string::String;
+use core::time::Duration;
+use serde::{Deserialize, Serialize};
+
+use crate::message::MessageId;
+
+/// Length of the fixed encoding of a `Header::Response`
+pub const FIXED_RESPONSE_LEN: usize = 4;
+/// Length of the fixed encoding of a `Header::Request` without its `service_method`
+pub const FIXED_REQUEST_LEN: usize = 15;
+
+const FIXED_REQUEST_TAG: u8 = 0;
+const FIXED_RESPONSE_TAG: u8 = 1;
+
+/// Header of a message
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Header {
+    /// Header of a request
+    ///
+    /// The body contains the content of the request
+    Request {
+        /// Message id
+        id: MessageId,
+        /// RPC service and method in the format of "{Service}.{method}"
+        service_method: String,
+        /// RPC timeout, all requests will have timeouts
+        timeout: Duration,
+    },
+
+    /// Header of a response
+    ///
+    /// The body contains the content of the response/result
+    Response {
+        /// Message id
+        id: MessageId,
+        /// Whether the result is Ok
+        is_ok: bool,
+    },
+
+    /// Header of a cancellation message
+    ///
+    /// TODO: The body should be an unit type ie. `()`
+    Cancel(MessageId),
+
+    /// Header of a publish message
+    ///
+    /// The body contains the publishing content
+    Publish {
+        /// Message id
+        id: MessageId,
+        /// Topic to publish to
+        topic: String,
+    },
+
+    /// Header of a subscribe message
+    /// Message will be pushed to the subscriber
+    ///
+    /// The body should be an unit type ie. `()`
+    Subscribe {
+        /// Message id
+        id: MessageId,
+        /// Topic to subscribe to
+        topic: String,
+    },
+
+    /// Header of a unsubscribe message
+    ///
+    /// The body should be an unit type ie. `()`
+    Unsubscribe {
+        /// Message Id
+        id: MessageId,
+        /// Topic to unsubscribe from
+        topic: String,
+    },
+
+    // /// Header of a subscription item
+    // ///
+    // /// The body contains the content of the subscription item
+    // Subscription {
+    //     /// Message id
+    //     id: MessageId,
+    //     /// Topic of the subscription item
+    //     topic: String,
+    // },
+    /// Acknowledge of an `AckedPublish` message
+    ///
+    /// The server acknowledges that it has accepted a publication from a publisher,
+    /// and a subscriber acknowledges that it has received a publication.
+    ///
+    /// The body should be an unit type `()`
+    Ack(MessageId),
+
+    /// Reserved for a potential message queue like design
+    /// Produce a message to be consumed
+    Produce {
+        /// Message id
+        id: MessageId,
+        /// Topic of the queue
+        topic: String,
+        /// Number of times this message can be consumed
+        tickets: u32,
+    },
+
+    /// Reserved for a potential message queue like design
+    /// Consumes a message by pulling message from broker/server
+    Consume {
+        /// Message id
+        id: MessageId,
+        /// Topic of the queue
+        topic: String,
+    },
+
+    /// Header of a rejection of a subscribe message
+    ///
+    /// The body contains the error
+    Reject {
+        /// Message id of the rejected message
+        id: MessageId,
+        /// Topic of the rejected message
+        topic: String,
+    },
+
+    /// Header of a publish message with the type tag of the item
+    ///
+    /// The body contains the item
+    TaggedPublish {
+        /// Message id
+        id: MessageId,
+        /// Topic of the message
+        topic: String,
+        /// Type tag of the item, see `pubsub::type_tag`
+        tag: u64,
+    },
+
+    /// Reserved for further extension to the message protocol
+    Ext {
+        /// Message id
+        id: MessageId,
+        /// Reserved for content of extension
+        content: String,
+        /// Reserved for some numerical/enum content
+        marker: u32,
+    },
+
+    /// Header of an item of a streaming response
+    ///
+    /// The body contains the item
+    StreamItem {
+        /// Message id of the request
+        id: MessageId,
+        /// Whether the item is Ok
+        is_ok: bool,
+    },
+
+    /// Header of the end of a streaming response
+    ///
+    /// The body should be an unit type ie. `()`
+    StreamEnd(MessageId),
+
+    /// Header of a request whose argument is a stream of items sent by a `ClientSink`
+    ///
+    /// The body should be an unit type ie. `()`. The items of the argument follow
+    /// in `SinkItem` messages until a `SinkEnd` message.
+    SinkRequest {
+        /// Message id
+        id: MessageId,
+        /// RPC service and method in the format of "{Service}.{method}"
+        service_method: String,
+        /// RPC timeout
+        timeout: Duration,
+    },
+
+    /// Header of an item of the argument of a `SinkRequest`
+    ///
+    /// The body contains the item
+    SinkItem(MessageId),
+
+    /// Header of the end of the argument of a `SinkRequest`
+    ///
+    /// The body should be an unit type ie. `()`
+    SinkEnd(MessageId),
+
+    /// Header of a publish message that must be acknowledged with an `Ack` of the
+    /// same id. The server redelivers the message to a subscriber until it is
+    /// acknowledged.
+    ///
+    /// The body contains the publishing content
+    AckedPublish {
+        /// Message id
+        id: MessageId,
+        /// Topic of the message
+        topic: String,
+        /// Type tag of the item, see `pubsub::type_tag`
+        tag: Option<u64>,
+    },
+}
+
+impl Header {
+    /// Returns the id of the message
+    pub fn id(&self) -> MessageId {
+        match self {
+            Self::Request { id, .. } => *id,
+            Self::Response { id, .. } => *id,
+            Self::Cancel(id) => *id,
+            Self::Publish { id, .. } => *id,
+            Self::Subscribe { id, .. } => *id,
+            Self::Unsubscribe { id, .. } => *id,
+            // Self::Subscription { id, .. } => *id,
+            Self::Ack(id) => *id,
+            Self::Produce { id, .. } => *id,
+            Self::Consume { id, .. } => *id,
+            Self::Reject { id, .. } => *id,
+            Self::TaggedPublish { id, .. } => *id,
+            Self::Ext { id, .. } => *id,
+            Self::StreamItem { id, .. } => *id,
+            Self::StreamEnd(id) => *id,
+            Self::SinkRequest { id, .. } => *id,
+            Self::SinkItem(id) => *id,
+            Self::SinkEnd(id) => *id,
+            Self::AckedPublish { id, .. } => *id,
+        }
+    }
+}
+
+impl Header {
+    /// Encodes a `Request` or `Response` header with the fixed encoding into `buf`,
+    /// and returns the number of bytes written. Returns `None` for the other headers
+    /// or if `buf` is too short.
+    ///
+    /// The encoding is a tag byte (0 for a request, 1 for a response) followed by the
+    /// id in little endian. A response then has a byte for `is_ok`. A request then has
+    /// the seconds (`u64`) and nanoseconds (`u32`) of the timeout in little endian,
+    /// and the UTF-8 bytes of `service_method` up to the end of the payload.
+    pub fn encode_fixed(&self, buf: &mut [u8]) -> Option<usize> {
+        match self {
+            Self::Request {
+                id,
+                service_method,
+                timeout,
+            } => {
+                let len = FIXED_REQUEST_LEN + service_method.len();
+                let buf = buf.get_mut(..len)?;
+                buf[0] = FIXED_REQUEST_TAG;
+                buf[1..3].copy_from_slice(&id.to_le_bytes());
+                buf[3..11].copy_from_slice(&timeout.as_secs().to_le_bytes());
+                buf[11..15].copy_from_slice(&timeout.subsec_nanos().to_le_bytes());
+                buf[FIXED_REQUEST_LEN..].copy_from_slice(service_method.as_bytes());
+                Some(len)
+            }
+            Self::Response { id, is_ok } => {
+                let buf = buf.get_mut(..FIXED_RESPONSE_LEN)?;
+                buf[0] = FIXED_RESPONSE_TAG;
+                buf[1..3].copy_from_slice(&id.to_le_bytes());
+                buf[3] = *is_ok as u8;
+                Some(FIXED_RESPONSE_LEN)
+            }
+            _ => None,
+        }
+    }
+
+    /// Decodes a header with the fixed encoding, see `encode_fixed`. Returns `None`
+    /// if `buf` is not a valid fixed encoding.
+    pub fn decode_fixed(buf: &[u8]) -> Option<Self> {
+        let id = MessageId::from_le_bytes([*buf.get(1)?, *buf.get(2)?]);
+        match buf[0] {
+            FIXED_REQUEST_TAG if buf.len() >= FIXED_REQUEST_LEN => {
+                let mut secs = [0; 8];
+                secs.copy_from_slice(&buf[3..11]);
+                let mut nanos = [0; 4];
+                nanos.copy_from_slice(&buf[11..15]);
+                let nanos = u32::from_le_bytes(nanos);
+                if nanos >= 1_000_000_000 {
+                    return None;
+                }
+                let service_method = core::str::from_utf8(&buf[FIXED_REQUEST_LEN..]).ok()?;
+                Some(Self::Request {
+                    id,
+                    service_method: service_method.into(),
+                    timeout: Duration::new(u64::from_le_bytes(secs), nanos),
+                })
+            }
+            FIXED_RESPONSE_TAG if buf.len() == FIXED_RESPONSE_LEN => match buf[3] {
+                0 => Some(Self::Response { id, is_ok: false }),
+                1 => Some(Self::Response { id, is_ok: true }),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fixed_round_trip() {
+        let mut buf = [0; 64];
+        let request = Header::Request {
+            id: 513,
+            service_method: "Arith.add".into(),
+            timeout: Duration::new(10, 5),
+        };
+        let len = request.encode_fixed(&mut buf).unwrap();
+        assert_eq!(len, FIXED_REQUEST_LEN + "Arith.add".len());
+        assert_eq!(Header::decode_fixed(&buf[..len]), Some(request));
+
+        let response = Header::Response { id: 7, is_ok: true };
+        let len = response.encode_fixed(&mut buf).unwrap();
+        assert_eq!(&buf[..len], &[1, 7, 0, 1]);
+        assert_eq!(Header::decode_fixed(&buf[..len]), Some(response));
+    }
+
+    #[test]
+    fn fixed_encoding_limits() {
+        let mut buf = [0; FIXED_REQUEST_LEN];
+        let request = Header::Request {
+            id: 1,
+            service_method: "Arith.add".into(),
+            timeout: Duration::from_secs(1),
+        };
+        assert_eq!(request.encode_fixed(&mut buf), None);
+        assert_eq!(Header::Cancel(1).encode_fixed(&mut buf), None);
+
+        assert_eq!(Header::decode_fixed(&[]), None);
+        assert_eq!(Header::decode_fixed(&[1, 7, 0, 2]), None);
+        assert_eq!(Header::decode_fixed(&[1, 7, 0, 1, 0]), None);
+        assert_eq!(Header::decode_fixed(&[2, 7, 0, 1]), None);
+        let mut invalid_nanos = [0; FIXED_REQUEST_LEN];
+        invalid_nanos[11..15].copy_from_slice(&1_000_000_000u32.to_le_bytes());
+        assert_eq!(Header::decode_fixed(&invalid_nanos), None);
+    }
+}
@@ -0,0 +1,22 @@
+use tokio::net::TcpStream;
+
+use toy_rpc::Client;
+
+use custom_codec::{Postcard, Reading, ADDR};
+
+#[tokio::main]
+async fn main() {
+    env_logger::init();
+
+    let stream = TcpStream::connect(ADDR).await.unwrap();
+    let client = Client::with_custom_codec::<_, Postcard>(stream);
+
+    let reading = Reading {
+        sensor: "kitchen".into(),
+        celsius: 21.5,
+    };
+    let reply: Result<Reading, _> = client.call("Thermometer.to_fahrenheit", reading).await;
+    println!("{:?}", reply);
+
+    client.close().await;
+}
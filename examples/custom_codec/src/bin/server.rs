@@ -0,0 +1,28 @@
+use std::sync::Arc;
+use tokio::net::TcpListener;
+
+use toy_rpc::codec::custom::CustomCodec;
+use toy_rpc::Server;
+
+use custom_codec::{Postcard, Thermometer, ADDR};
+
+#[tokio::main]
+async fn main() {
+    env_logger::init();
+
+    let server = Arc::new(Server::builder().register(Arc::new(Thermometer)).build());
+    let listener = TcpListener::bind(ADDR).await.unwrap();
+    log::info!("Starting server at {}", ADDR);
+
+    loop {
+        let (stream, peer_addr) = listener.accept().await.unwrap();
+        log::info!("Accepting connection from {}", peer_addr);
+        let server = server.clone();
+        tokio::spawn(async move {
+            let codec = CustomCodec::<_, _, Postcard>::new(stream);
+            if let Err(err) = server.serve_codec(codec).await {
+                log::error!("{}", err);
+            }
+        });
+    }
+}
@@ -0,0 +1,98 @@
+//! A wire format with `postcard`, which is not one of the `serde_*` codecs of
+//! `toy-rpc`, plugged in with a `CustomCodec`
+
+use postcard::de_flavors::Flavor;
+use serde::{Deserialize, Serialize};
+
+use toy_rpc::codec::custom::erase_owned;
+use toy_rpc::codec::{EraseDeserializer, Marshal, Unmarshal};
+use toy_rpc::macros::export_impl;
+use toy_rpc::Error;
+
+pub const ADDR: &str = "127.0.0.1:23336";
+
+/// The `postcard` format
+pub struct Postcard;
+
+impl Marshal for Postcard {
+    fn marshal<S: Serialize>(val: &S) -> Result<Vec<u8>, Error> {
+        postcard::to_allocvec(val).map_err(|err| Error::ParseError(Box::new(err)))
+    }
+}
+
+impl Unmarshal for Postcard {
+    fn unmarshal<'de, D: Deserialize<'de>>(buf: &'de [u8]) -> Result<D, Error> {
+        postcard::from_bytes(buf).map_err(|err| Error::ParseError(Box::new(err)))
+    }
+}
+
+impl EraseDeserializer for Postcard {
+    fn from_bytes(buf: Vec<u8>) -> Box<dyn erased_serde::Deserializer<'static> + Send> {
+        erase_owned(postcard::Deserializer::from_flavor(OwnedBytes {
+            buf,
+            pos: 0,
+        }))
+    }
+}
+
+/// Flavor of `postcard` that owns the bytes, so that the deserializer is `'static`
+pub struct OwnedBytes {
+    buf: Vec<u8>,
+    pos: usize,
+}
+
+impl Flavor<'static> for OwnedBytes {
+    type Remainder = Vec<u8>;
+    type Source = Vec<u8>;
+
+    fn pop(&mut self) -> postcard::Result<u8> {
+        let byte = *self
+            .buf
+            .get(self.pos)
+            .ok_or(postcard::Error::DeserializeUnexpectedEnd)?;
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    /// Borrowing from the buffer is not supported
+    fn try_take_n(&mut self, _: usize) -> postcard::Result<&'static [u8]> {
+        Err(postcard::Error::WontImplement)
+    }
+
+    fn try_take_n_temp<'a>(&'a mut self, ct: usize) -> postcard::Result<&'a [u8]>
+    where
+        'static: 'a,
+    {
+        let end = self
+            .pos
+            .checked_add(ct)
+            .filter(|end| *end <= self.buf.len())
+            .ok_or(postcard::Error::DeserializeUnexpectedEnd)?;
+        let bytes = &self.buf[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn finalize(mut self) -> postcard::Result<Vec<u8>> {
+        Ok(self.buf.split_off(self.pos))
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Reading {
+    pub sensor: String,
+    pub celsius: f32,
+}
+
+pub struct Thermometer;
+
+#[export_impl]
+impl Thermometer {
+    #[export_method]
+    pub async fn to_fahrenheit(&self, args: Reading) -> Result<Reading, String> {
+        Ok(Reading {
+            sensor: args.sensor,
+            celsius: args.celsius * 9.0 / 5.0 + 32.0,
+        })
+    }
+}
@@ -30,6 +30,7 @@ async fn main() {
         server.accept(listener).await.unwrap();
     });
 
-    // tokio JoinHandle returns an extra result
+    // tokio JoinHandle returns an extra result,
+    // `toy_rpc::util::spawn_task` can be used to avoid the difference between runtimes
     handle.await.unwrap();
 }
\ No newline at end of file
@@ -1,11 +1,10 @@
 use tokio::net::TcpListener;
-use tokio::task;
-use std::sync::{Arc};
 use async_trait::async_trait;
 
 use toy_rpc::Server;
 use toy_rpc::macros::export_trait_impl;
 use toy_rpc::Error;
+use toy_rpc::util::spawn_task;
 
 use tokio_tcp::rpc::*;
 
@@ -28,21 +27,16 @@ async fn main() {
     env_logger::init();
 
     let addr = "127.0.0.1:23333";
-    let echo_service = Arc::new(
-        Echo { }
-    );
-    let arith = Arc::new(Abacus { });
-
     let server = Server::builder()
-        .register(echo_service)
-        .register(arith)
+        .register(Echo { })
+        .register(Abacus { })
         .build();
 
     let listener = TcpListener::bind(addr).await.unwrap();
 
     log::info!("Starting server at {}", &addr);
 
-    let handle = task::spawn(async move {
+    let handle = spawn_task(async move {
         server.accept(listener).await.unwrap();
     });
     handle.await.expect("Error");
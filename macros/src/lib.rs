@@ -10,10 +10,16 @@ use darling::FromMeta;
 use util::item_impl::*;
 // #[cfg(any(feature = "server", feature = "client"))]
 use util::item_trait::*;
+#[cfg(all(feature = "client", feature = "runtime"))]
+use util::item_mod::*;
 
 // #[cfg(any(feature = "server", feature = "client"))]
 pub(crate) const ATTR_EXPORT_METHOD: &str = "export_method";
 #[cfg(feature = "server")]
+pub(crate) const ATTR_BLOCKING: &str = "blocking";
+#[cfg(any(feature = "server", all(feature = "client", feature = "runtime")))]
+pub(crate) const ATTR_PROST: &str = "prost";
+#[cfg(feature = "server")]
 pub(crate) const HANDLER_SUFFIX: &str = "handler";
 #[cfg(feature = "server")]
 pub(crate) const EXPORTED_TRAIT_SUFFIX: &str = "Handler";
@@ -21,6 +27,12 @@ pub(crate) const EXPORTED_TRAIT_SUFFIX: &str = "Handler";
 pub(crate) const CLIENT_SUFFIX: &str = "Client";
 #[cfg(all(feature = "client", feature = "runtime",))]
 pub(crate) const CLIENT_STUB_SUFFIX: &str = "ClientStub";
+#[cfg(all(feature = "client", feature = "runtime",))]
+pub(crate) const PAGE_STREAM_SUFFIX: &str = "stream";
+#[cfg(all(feature = "client", feature = "runtime",))]
+pub(crate) const METHODS_SUFFIX: &str = "Methods";
+#[cfg(all(feature = "client", feature = "runtime",))]
+pub(crate) const TOPICS_PREFIX: &str = "Topics";
 
 /// A macro that impls serde::Deserializer by simply calling the
 /// corresponding functions of the inner deserializer
@@ -244,16 +256,38 @@ pub fn impl_inner_deserializer(_: proc_macro::TokenStream) -> proc_macro::TokenS
 ///
 /// - The default service name generated will be the same as the name of the struct.
 ///
+/// - A method marked with `#[export_method(blocking)]` is executed on the blocking
+///   thread pool of the runtime. See the `toy_rpc::server::blocking` module.
+///
+/// - A method marked with `#[export_method(prost)]` takes and returns `prost`
+///   messages, which are sent as `toy_rpc::codec::prost::Protobuf`. This requires the
+///   `prost_codec` feature of `toy-rpc`. See the `toy_rpc::codec::prost` module.
+///
+/// - The metadata of the exported methods allows calling them with
+///   `rpc_call!(client, Abacus::add, args)`. See the `toy_rpc::client::typed` module.
+///
+/// - A method taking a `toy_rpc::metadata::Context` before its argument gets the
+///   context of the call, which holds the metadata of the request. See the
+///   `toy_rpc::metadata` module.
+///
 /// ### Example - Export impl block
 ///
-/// ```rust
+/// ```no_run
+/// # use toy_rpc::macros::export_impl;
 /// struct Abacus { }
 ///
 /// #[export_impl] // This will give a default service name of "Abacus"
 /// impl Abacus {
 ///     #[export_method]
-///     async fn subtract(&self, args(i32, i32)) -> Result<i32, String> {
+///     async fn subtract(&self, args: (i32, i32)) -> Result<i32, String> {
 ///         // ...
+/// #         unimplemented!()
+///     }
+///
+///     #[export_method(blocking)]
+///     async fn factorize(&self, n: u64) -> Result<Vec<u64>, String> {
+///         // CPU-heavy work that should not stall the executor
+/// #         unimplemented!()
 ///     }
 /// }
 /// ```
@@ -265,7 +299,7 @@ pub fn export_impl(
     // parse item
     let input = syn::parse_macro_input!(item as syn::ItemImpl);
     #[cfg(feature = "server")]
-    let (handler_impl, names, handler_idents) = transform_impl(input.clone());
+    let (handler_impl, names, handler_idents, blocking) = transform_impl(input.clone());
 
     // extract Self type and use it for construct Ident for handler HashMap
     #[cfg(any(feature = "server", all(feature = "client", feature = "runtime")))]
@@ -277,13 +311,17 @@ pub fn export_impl(
         }
     };
     #[cfg(feature = "server")]
-    let register_service_impl = impl_register_service_for_struct(ident, names, handler_idents);
+    let register_service_impl =
+        impl_register_service_for_struct(ident, names, handler_idents, blocking);
 
     // generate client stub
     #[cfg(all(feature = "client", feature = "runtime"))]
     let (client_ty, client_impl) = generate_service_client_for_struct(&ident, &input);
     #[cfg(all(feature = "client", feature = "runtime"))]
     let (stub_trait, stub_impl) = generate_client_stub_for_struct(&ident);
+    #[cfg(all(feature = "client", feature = "runtime"))]
+    let (methods_ty, methods_impl, service_methods_impl) =
+        generate_method_metadata_for_struct(ident, &input);
 
     let input = remove_export_attr_from_impl(input);
     #[cfg(feature = "server")]
@@ -300,6 +338,9 @@ pub fn export_impl(
         #client_impl
         #stub_trait
         #stub_impl
+        #methods_ty
+        #methods_impl
+        #service_methods_impl
     };
     #[cfg(all(not(feature = "server"), feature = "client", feature = "runtime"))]
     let output = quote::quote! {
@@ -308,6 +349,9 @@ pub fn export_impl(
         #client_impl
         #stub_trait
         #stub_impl
+        #methods_ty
+        #methods_impl
+        #service_methods_impl
     };
     #[cfg(all(
         feature = "server",
@@ -332,6 +376,7 @@ pub fn export_impl(
 // #[export_trait]
 // =============================================================================
 
+#[cfg(all(feature = "client", feature = "runtime"))]
 #[derive(Debug, darling::FromMeta)]
 struct MacroArgs {
     #[darling(default)]
@@ -350,16 +395,27 @@ struct MacroArgs {
 ///
 /// - This macro should be placed on the trait definition.
 ///
+/// - The trait object `dyn Trait + Send + Sync` can also be registered as a service,
+///   so the trait must be object safe.
+///
+/// - A method marked with `#[export_method(prost)]` takes and returns `prost`
+///   messages. See the `toy_rpc::codec::prost` module.
+///
+/// - The metadata of the exported methods allows calling them with
+///   `rpc_call!(client, dyn Arith::add, args)`. See the `toy_rpc::client::typed` module.
+///
 /// ## Example
 ///
-/// ```rust
+/// ```no_run
+/// # use async_trait::async_trait;
+/// # use toy_rpc::macros::{export_trait, export_trait_impl};
 /// #[async_trait]
 /// #[export_trait] // This will give a default service name of "Arith"
 /// pub trait Arith {
 ///     // Mark method(s) to be "exported" with `#[export_method]`
 ///     // in the definition.
 ///     #[export_method]
-///     async fn add(&self, args(i32, i32)) -> Result<i32, String>;
+///     async fn add(&self, args: (i32, i32)) -> Result<i32, String>;
 /// }
 /// ```
 #[proc_macro_attribute]
@@ -380,7 +436,7 @@ pub fn export_trait(
 
     let input = syn::parse_macro_input!(item as syn::ItemTrait);
     #[cfg(feature = "server")]
-    let (transformed_trait, transformed_trait_impl, names, handler_idents) =
+    let (transformed_trait, transformed_trait_impl, names, handler_idents, blocking) =
         transform_trait(input.clone());
     #[cfg(feature = "server")]
     let local_registry = impl_local_registry_for_trait(
@@ -388,12 +444,19 @@ pub fn export_trait(
         &transformed_trait.ident,
         names,
         handler_idents,
+        blocking,
     );
+    #[cfg(feature = "server")]
+    let trait_object_impl =
+        impl_register_service_for_trait_object(&input.ident, &transformed_trait.ident);
 
     #[cfg(all(feature = "client", feature = "runtime"))]
     let (client_ty, client_impl) = generate_service_client_for_trait(&input.ident, &input);
     #[cfg(all(feature = "client", feature = "runtime"))]
     let (stub_trait, stub_impl) = generate_client_stub_for_trait(&input.ident);
+    #[cfg(all(feature = "client", feature = "runtime"))]
+    let (methods_ty, methods_impl, service_methods_impl) =
+        generate_method_metadata_for_trait(&input.ident, &input);
 
     #[cfg(all(feature = "client", feature = "runtime"))]
     let trait_impl = {
@@ -414,10 +477,14 @@ pub fn export_trait(
             #transformed_trait
             #transformed_trait_impl
             #local_registry
+            #trait_object_impl
             #client_ty
             #client_impl
             #stub_trait
             #stub_impl
+            #methods_ty
+            #methods_impl
+            #service_methods_impl
             #trait_impl
         }
     } else {
@@ -426,10 +493,14 @@ pub fn export_trait(
             #transformed_trait
             #transformed_trait_impl
             #local_registry
+            #trait_object_impl
             #client_ty
             #client_impl
             #stub_trait
             #stub_impl
+            #methods_ty
+            #methods_impl
+            #service_methods_impl
         }
     };
     #[cfg(all(not(feature = "server"), feature = "client", feature = "runtime"))]
@@ -440,6 +511,9 @@ pub fn export_trait(
             #client_impl
             #stub_trait
             #stub_impl
+            #methods_ty
+            #methods_impl
+            #service_methods_impl
             #trait_impl
         }
     } else {
@@ -449,6 +523,9 @@ pub fn export_trait(
             #client_impl
             #stub_trait
             #stub_impl
+            #methods_ty
+            #methods_impl
+            #service_methods_impl
         }
     };
     #[cfg(all(
@@ -460,6 +537,7 @@ pub fn export_trait(
         #transformed_trait
         #transformed_trait_impl
         #local_registry
+        #trait_object_impl
     };
     #[cfg(all(
         not(feature = "server"),
@@ -478,11 +556,19 @@ pub fn export_trait(
 /// ## Note
 ///
 /// - This macro should be placed on the impl block of the defined RPC service
-/// trait
+///   trait
 ///
 /// ## Example
 ///
-/// ```rust
+/// ```no_run
+/// # use async_trait::async_trait;
+/// # use toy_rpc::macros::{export_trait, export_trait_impl};
+/// # #[async_trait]
+/// # #[export_trait]
+/// # pub trait Arith {
+/// #     #[export_method]
+/// #     async fn add(&self, args: (i32, i32)) -> Result<i32, String>;
+/// # }
 /// struct Abacus { }
 ///
 /// #[async_trait]
@@ -490,8 +576,9 @@ pub fn export_trait(
 /// impl Arith  for Abacus {
 ///     // Notice that you do NOT mark the method with `#[export_method]`
 ///     // again
-///     async fn add(&self, args(i32, i32)) -> Result<i32, String> {
+///     async fn add(&self, args: (i32, i32)) -> Result<i32, String> {
 ///         // ...
+/// #         unimplemented!()
 ///     }
 /// }
 /// ```
@@ -530,3 +617,79 @@ pub fn export_trait_impl(
     };
     output.into()
 }
+
+// =============================================================================
+// #[export_topics]
+// =============================================================================
+
+/// Generates typed publishers and subscribers on the client for the topics defined
+/// in a module.
+///
+/// The macro is placed on a module with its items inline. For each type in the
+/// module that implements `toy_rpc::pubsub::Topic`, the trait `TopicsClientStub`
+/// generated in the module has a `<topic>_publisher()` and a
+/// `<topic>_subscriber(cap)` method, which are implemented for the client.
+///
+/// ## Note
+///
+/// - The name of the methods is the name of the type in snake case, eg.
+///   `count_publisher()` and `count_subscriber(cap)` for `Count`.
+///
+/// - The types implementing `Topic` must be public, as they appear in the signature
+///   of the generated trait.
+///
+/// ## Example
+///
+/// ```no_run
+/// # #[cfg(all(feature = "client", feature = "runtime"))]
+/// # {
+/// # use toy_rpc::macros::export_topics;
+/// #[export_topics]
+/// pub mod topics {
+/// #     use serde::{Deserialize, Serialize};
+/// #     use toy_rpc::pubsub::Topic;
+///     #[derive(Serialize, Deserialize)]
+///     pub struct Count(pub u32);
+///
+///     impl Topic for Count {
+///         type Item = Count;
+///
+///         fn topic() -> String {
+///             "Count".into()
+///         }
+///     }
+/// }
+///
+/// use topics::TopicsClientStub;
+///
+/// # async fn run(mut client: toy_rpc::Client) -> Result<(), toy_rpc::Error> {
+/// # use futures::SinkExt;
+/// let mut subscriber = client.count_subscriber(10)?;
+/// let mut publisher = client.count_publisher();
+/// publisher.send(topics::Count(1)).await?;
+/// # Ok(())
+/// # }
+/// # }
+/// ```
+#[proc_macro_attribute]
+pub fn export_topics(
+    _attr: proc_macro::TokenStream,
+    item: proc_macro::TokenStream,
+) -> proc_macro::TokenStream {
+    let input = syn::parse_macro_input!(item as syn::ItemMod);
+
+    #[cfg(all(feature = "client", feature = "runtime"))]
+    let input = {
+        let topics = match get_topic_idents(&input) {
+            Ok(topics) => topics,
+            Err(err) => return err.to_compile_error().into(),
+        };
+        let (stub_trait, stub_impl) = generate_topics_client_stub(&input.ident, &topics);
+        append_items(input, vec![stub_trait, syn::Item::Impl(stub_impl)])
+    };
+
+    let output = quote::quote! {
+        #input
+    };
+    output.into()
+}
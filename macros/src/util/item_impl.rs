@@ -4,7 +4,8 @@ use super::*;
 ///
 /// Example
 ///
-/// ```rust
+/// ```no_run
+/// # use toy_rpc::macros::export_impl;
 /// pub struct Foo { }
 ///
 /// #[export_impl]
@@ -18,7 +19,7 @@ use super::*;
 ///
 /// will generate the following impl
 ///
-/// ```rust
+/// ```no_run
 /// pub struct Foo { }
 ///
 /// impl Foo {
@@ -26,6 +27,8 @@ use super::*;
 ///         Ok(arg + 1)
 ///     }
 /// }
+/// # #[cfg(feature = "server")]
+/// # impl Foo {
 /// pub fn increment_handler(
 ///     self: std::sync::Arc<Self>,
 ///     mut deserializer: Box<dyn toy_rpc::erased_serde::Deserializer<'static> + Send>,
@@ -36,21 +39,20 @@ use super::*;
 ///     let res = self
 ///         .increment(req) // executes the RPC method
 ///         .await
-///         .map(|r| {
-///             Box::new(r)
-///                 as Box<dyn toy_rpc::erased_serde::Serialize + Send + Sync + 'static>
-///         })
+///         .map(toy_rpc::service::IntoSuccess::into_success)
 ///         .map_err(|e| toy_rpc::error::Error::ExecutionError(e.to_string()));
 ///     res
 ///     })
 /// }
+/// # }
 /// ```
 #[cfg(feature = "server")]
 pub(crate) fn transform_impl(
     input: syn::ItemImpl,
-) -> (syn::ItemImpl, Vec<String>, Vec<syn::Ident>) {
+) -> (syn::ItemImpl, Vec<String>, Vec<syn::Ident>, Vec<String>) {
     let mut names = Vec::new();
     let mut idents = Vec::new();
+    let mut blocking = Vec::new();
     let mut output = filter_exported_impl_items(input);
 
     output.trait_ = None;
@@ -64,11 +66,14 @@ pub(crate) fn transform_impl(
         })
         .for_each(|f| {
             names.push(f.sig.ident.to_string());
+            if is_blocking(&f.attrs) {
+                blocking.push(f.sig.ident.to_string());
+            }
             transform_impl_item(f);
             idents.push(f.sig.ident.clone());
         });
 
-    (output, names, idents)
+    (output, names, idents, blocking)
 }
 
 /// transform method to meet the signature of service function
@@ -83,20 +88,37 @@ pub(crate) fn transform_impl_item(f: &mut syn::ImplItemMethod) {
     f.sig.asyncness = None;
 
     // transform function request type
+    let context = takes_context(&f.sig);
+    let prost = is_prost(&f.attrs);
     if let syn::FnArg::Typed(pt) = f.sig.inputs.last().unwrap() {
-        let req_ty = &pt.ty;
+        let (req_ty, req, into_success) = handler_conversions(&pt.ty, prost);
 
-        f.block = syn::parse_quote!({
-            Box::pin(
-                async move {
-                    let req: #req_ty = toy_rpc::erased_serde::deserialize(&mut deserializer)
-                        .map_err(|e| toy_rpc::error::Error::ParseError(Box::new(e)))?;
-                    self.#ident(req).await
-                        .map(|r| Box::new(r) as Box<dyn toy_rpc::erased_serde::Serialize + Send + Sync + 'static>)
-                        .map_err(|err| err.into())
-                }
-            )
-        });
+        f.block = match context {
+            // the context is taken when the handler is called, before it is polled
+            true => syn::parse_quote!({
+                let context = toy_rpc::metadata::Context::current();
+                Box::pin(
+                    async move {
+                        let req: #req_ty = toy_rpc::erased_serde::deserialize(&mut deserializer)
+                            .map_err(|e| toy_rpc::error::Error::ParseError(Box::new(e)))?;
+                        self.#ident(context, #req).await
+                            .map(#into_success)
+                            .map_err(|err| err.into())
+                    }
+                )
+            }),
+            false => syn::parse_quote!({
+                Box::pin(
+                    async move {
+                        let req: #req_ty = toy_rpc::erased_serde::deserialize(&mut deserializer)
+                            .map_err(|e| toy_rpc::error::Error::ParseError(Box::new(e)))?;
+                        self.#ident(#req).await
+                            .map(#into_success)
+                            .map_err(|err| err.into())
+                    }
+                )
+            }),
+        };
 
         f.sig.inputs = syn::parse_quote!(
             self: std::sync::Arc<Self>, mut deserializer: Box<dyn toy_rpc::erased_serde::Deserializer<'static> + Send>
@@ -136,6 +158,7 @@ pub(crate) fn impl_register_service_for_struct(
     struct_ident: &syn::Ident,
     names: Vec<String>,
     handler_idents: Vec<syn::Ident>,
+    blocking: Vec<String>,
 ) -> impl quote::ToTokens {
     let service_name = struct_ident.to_string();
     let ret = quote::quote! {
@@ -149,6 +172,10 @@ pub(crate) fn impl_register_service_for_struct(
             fn default_name() -> &'static str {
                 #service_name
             }
+
+            fn blocking_methods() -> Vec<&'static str> {
+                vec![#(#blocking),*]
+            }
         }
     };
 
@@ -198,8 +225,14 @@ fn client_stub_impl_for_struct(
             if let Some(method) = generate_client_stub_for_struct_method(service_ident, f) {
                 generated_items.push(syn::ImplItem::Method(method));
             }
+            if let Some(method) = item_trait::generate_page_stream_stub(service_ident, &f.sig) {
+                generated_items.push(syn::ImplItem::Method(method));
+            }
         }
     });
+    if let Some(method) = generate_capabilities_stub(&generated_items) {
+        generated_items.push(syn::ImplItem::Method(method));
+    }
 
     let mut output: syn::ItemImpl = syn::parse_quote!(
         impl<'c> #client_ident<'c> {
@@ -227,6 +260,7 @@ pub(crate) fn generate_client_stub_for_struct_method(
                 fn_ident,
                 &req_ty,
                 &ok_ty,
+                is_prost(&f.attrs),
             ));
         }
     }
@@ -267,3 +301,17 @@ pub(crate) fn generate_client_stub_for_struct(
 
     (stub_trait, stub_impl)
 }
+
+/// Generate the metadata of the exported methods that is used by `rpc_call!`
+#[cfg(all(feature = "client", feature = "runtime"))]
+pub(crate) fn generate_method_metadata_for_struct(
+    struct_ident: &syn::Ident,
+    input: &syn::ItemImpl,
+) -> (syn::Item, syn::ItemImpl, syn::ItemImpl) {
+    let input = filter_exported_impl_items(input.clone());
+    let sigs = input.items.iter().filter_map(|item| match item {
+        syn::ImplItem::Method(f) => Some(&f.sig),
+        _ => None,
+    });
+    generate_method_metadata(struct_ident, (*input.self_ty).clone(), sigs)
+}
@@ -0,0 +1,98 @@
+use super::*;
+
+/// Returns the types implementing `Topic` in the module, in the order of their impl
+/// blocks
+pub(crate) fn get_topic_idents(input: &syn::ItemMod) -> Result<Vec<syn::Ident>, syn::Error> {
+    let items = match &input.content {
+        Some((_, items)) => items,
+        None => {
+            return Err(syn::Error::new_spanned(
+                &input.ident,
+                "#[export_topics] requires a module with its items inline",
+            ))
+        }
+    };
+    let idents = items
+        .iter()
+        .filter_map(|item| match item {
+            syn::Item::Impl(item_impl) => {
+                let (_, path, _) = item_impl.trait_.as_ref()?;
+                if path.segments.last()?.ident != "Topic" {
+                    return None;
+                }
+                match &*item_impl.self_ty {
+                    syn::Type::Path(tp) => Some(tp.path.segments.last()?.ident.clone()),
+                    _ => None,
+                }
+            }
+            _ => None,
+        })
+        .collect();
+    Ok(idents)
+}
+
+/// Generates the trait with the typed publishers and subscribers of the topics, and
+/// its impl for the client
+pub(crate) fn generate_topics_client_stub(
+    mod_ident: &syn::Ident,
+    topics: &[syn::Ident],
+) -> (syn::Item, syn::ItemImpl) {
+    let concat_name = format!("{}{}", TOPICS_PREFIX, CLIENT_STUB_SUFFIX);
+    let stub_ident = syn::Ident::new(&concat_name, mod_ident.span());
+
+    let publisher_fns: Vec<syn::Ident> = topics
+        .iter()
+        .map(|topic| concat_fn_name(topic, "publisher"))
+        .collect();
+    let subscriber_fns: Vec<syn::Ident> = topics
+        .iter()
+        .map(|topic| concat_fn_name(topic, "subscriber"))
+        .collect();
+
+    let stub_trait = syn::parse_quote!(
+        pub trait #stub_ident {
+            #(
+                fn #publisher_fns(&self) -> toy_rpc::client::pubsub::Publisher<#topics>;
+
+                fn #subscriber_fns(
+                    &mut self,
+                    cap: usize,
+                ) -> Result<toy_rpc::client::pubsub::Subscriber<#topics>, toy_rpc::Error>;
+            )*
+        }
+    );
+
+    let stub_impl = syn::parse_quote!(
+        impl #stub_ident for toy_rpc::client::Client {
+            #(
+                fn #publisher_fns(&self) -> toy_rpc::client::pubsub::Publisher<#topics> {
+                    self.publisher::<#topics>()
+                }
+
+                fn #subscriber_fns(
+                    &mut self,
+                    cap: usize,
+                ) -> Result<toy_rpc::client::pubsub::Subscriber<#topics>, toy_rpc::Error> {
+                    self.subscriber::<#topics>(cap)
+                }
+            )*
+        }
+    );
+
+    (stub_trait, stub_impl)
+}
+
+/// `Count` and `"subscriber"` give `count_subscriber`
+fn concat_fn_name(topic: &syn::Ident, suffix: &str) -> syn::Ident {
+    let name = format!("{}_{}", parse_stub_fn_name(topic), suffix);
+    syn::Ident::new(&name, topic.span())
+}
+
+/// Appends the generated items to the inline items of the module
+pub(crate) fn append_items(input: syn::ItemMod, generated: Vec<syn::Item>) -> syn::ItemMod {
+    let mut output = input;
+    if let Some((_, items)) = &mut output.content {
+        items.extend(generated);
+    }
+    output
+}
@@ -6,9 +6,16 @@ use super::*;
 #[cfg(feature = "server")]
 pub(crate) fn transform_trait(
     input: syn::ItemTrait,
-) -> (syn::ItemTrait, syn::ItemImpl, Vec<String>, Vec<syn::Ident>) {
+) -> (
+    syn::ItemTrait,
+    syn::ItemImpl,
+    Vec<String>,
+    Vec<syn::Ident>,
+    Vec<String>,
+) {
     let mut names: Vec<String> = Vec::new();
     let mut idents: Vec<syn::Ident> = Vec::new();
+    let mut blocking: Vec<String> = Vec::new();
     let mut handler_idents = Vec::new();
     let input = filter_exported_trait_items(input.clone());
     let trait_ident = &input.ident;
@@ -18,6 +25,9 @@ pub(crate) fn transform_trait(
     input.items.iter().for_each(|item| {
         if let syn::TraitItem::Method(f) = item {
             names.push(f.sig.ident.to_string());
+            if is_blocking(&f.attrs) {
+                blocking.push(f.sig.ident.to_string());
+            }
             // transform_trait_item(f);
             idents.push(f.sig.ident.clone());
         }
@@ -37,7 +47,7 @@ pub(crate) fn transform_trait(
     }
 
     let transformed_trait_impl: syn::ItemImpl = syn::parse_quote!(
-        impl<T: #trait_ident + Send + Sync + 'static> #transformed_trait_ident for T {
+        impl<T: ?Sized + #trait_ident + Send + Sync + 'static> #transformed_trait_ident for T {
 
         }
     );
@@ -49,6 +59,7 @@ pub(crate) fn transform_trait(
         transformed_trait_impl,
         names,
         handler_idents,
+        blocking,
     )
 }
 
@@ -88,7 +99,7 @@ fn impl_transformed_trait(
     let items = handler_items.zip(orig_items);
     for (handler_item, orig_item) in items {
         if let syn::FnArg::Typed(pt) = orig_item.sig.inputs.last().unwrap() {
-            let req_ty = &pt.ty;
+            let (req_ty, req, into_success) = handler_conversions(&pt.ty, is_prost(&orig_item.attrs));
             let handler_ident = &handler_item.sig.ident;
             let orig_ident = &orig_item.sig.ident;
 
@@ -102,8 +113,8 @@ fn impl_transformed_trait(
                         async move {
                             let req: #req_ty = toy_rpc::erased_serde::deserialize(&mut deserializer)
                                 .map_err(|e| toy_rpc::error::Error::ParseError(Box::new(e)))?;
-                            self.#orig_ident(req).await
-                                .map(|r| Box::new(r) as Box<dyn toy_rpc::erased_serde::Serialize + Send + Sync + 'static>)
+                            self.#orig_ident(#req).await
+                                .map(#into_success)
                                 .map_err(|err| err.into())
                         }
                     )
@@ -136,6 +147,7 @@ pub(crate) fn impl_local_registry_for_trait(
     transformed_trait_ident: &syn::Ident,
     names: Vec<String>,
     handler_idents: Vec<syn::Ident>,
+    blocking: Vec<String>,
 ) -> impl quote::ToTokens {
     let service_name = orig_trait_ident.to_string();
     let concat_name = format!("{}{}", transformed_trait_ident.to_string(), REGISTRY_SUFFIX);
@@ -144,11 +156,12 @@ pub(crate) fn impl_local_registry_for_trait(
         pub trait #registry_ident {
             fn handlers() -> std::collections::HashMap<&'static str, toy_rpc::service::AsyncHandler<Self>>;
             fn default_name() -> &'static str;
+            fn blocking_methods() -> Vec<&'static str>;
         }
 
         impl<T> #registry_ident for T
         where
-            T: #transformed_trait_ident + Send + Sync + 'static
+            T: ?Sized + #transformed_trait_ident + Send + Sync + 'static
         {
             fn handlers() -> std::collections::HashMap<&'static str, toy_rpc::service::AsyncHandler<Self>> {
                 let mut map = std::collections::HashMap::<&'static str, toy_rpc::service::AsyncHandler<Self>>::new();
@@ -159,6 +172,38 @@ pub(crate) fn impl_local_registry_for_trait(
             fn default_name() -> &'static str {
                 #service_name
             }
+
+            fn blocking_methods() -> Vec<&'static str> {
+                vec![#(#blocking),*]
+            }
+        }
+    };
+    ret
+}
+
+/// Implements `RegisterService` for the trait object `dyn Trait + Send + Sync` so that
+/// an implementation of the trait can be chosen at runtime
+#[cfg(feature = "server")]
+pub(crate) fn impl_register_service_for_trait_object(
+    orig_trait_ident: &syn::Ident,
+    transformed_trait_ident: &syn::Ident,
+) -> impl quote::ToTokens {
+    let registry_name = format!("{}{}", transformed_trait_ident, REGISTRY_SUFFIX);
+    let registry_ident = syn::Ident::new(&registry_name, transformed_trait_ident.span());
+
+    let ret = quote::quote! {
+        impl toy_rpc::util::RegisterService for dyn #orig_trait_ident + Send + Sync {
+            fn handlers() -> std::collections::HashMap<&'static str, toy_rpc::service::AsyncHandler<Self>> {
+                <Self as #registry_ident>::handlers()
+            }
+
+            fn default_name() -> &'static str {
+                <Self as #registry_ident>::default_name()
+            }
+
+            fn blocking_methods() -> Vec<&'static str> {
+                <Self as #registry_ident>::blocking_methods()
+            }
         }
     };
     ret
@@ -184,6 +229,10 @@ pub(crate) fn impl_register_service_for_trait_impl(
             fn default_name() -> &'static str {
                 <Self as #registry_ident>::default_name()
             }
+
+            fn blocking_methods() -> Vec<&'static str> {
+                <Self as #registry_ident>::blocking_methods()
+            }
         }
     };
     ret
@@ -240,8 +289,14 @@ fn client_stub_impl_for_trait(
             if let Some(method) = generate_client_stub_for_trait_method(service_ident, f) {
                 generated_items.push(syn::ImplItem::Method(method))
             }
+            if let Some(method) = generate_page_stream_stub(service_ident, &f.sig) {
+                generated_items.push(syn::ImplItem::Method(method))
+            }
         }
     });
+    if let Some(method) = generate_capabilities_stub(&generated_items) {
+        generated_items.push(syn::ImplItem::Method(method));
+    }
 
     let mut output: syn::ItemImpl = syn::parse_quote!(
         impl<'c> #client_ident<'c> {
@@ -268,6 +323,7 @@ fn generate_client_stub_for_trait_method(
                 fn_ident,
                 &req_ty,
                 &ok_ty,
+                is_prost(&f.attrs),
             ));
         }
     }
@@ -340,10 +396,10 @@ fn generate_trait_method_impl_for_client(
 
     let method_ident = &method.sig.ident;
     let arg = method.sig.inputs.last().unwrap();
-    let arg_ident = match arg {
+    let (arg_ident, arg_ty) = match arg {
         syn::FnArg::Typed(pt) => {
             if let syn::Pat::Ident(pat_id) = pt.pat.deref() {
-                &pat_id.ident
+                (&pat_id.ident, pt.ty.deref())
             } else {
                 panic!("Argument ident not found")
             }
@@ -351,15 +407,78 @@ fn generate_trait_method_impl_for_client(
         _ => panic!("Argument ident not found"),
     };
     let service_method = format!("{}.{}", service_ident, method_ident);
-    let block: syn::Block = syn::parse_quote!(
-        {
-            Box::pin(
-                async move {
-                    self.call(#service_method, #arg_ident).await.into()
-                }
-            )
+    let is_stream = match method.sig.output.clone() {
+        syn::ReturnType::Type(_, ret_ty) => get_ok_ident_from_type(ret_ty)
+            .and_then(|ok_ty| get_stream_item_type(&ok_ty))
+            .is_some(),
+        syn::ReturnType::Default => false,
+    };
+    let block: syn::Block = if is_request_stream(arg_ty) && is_stream {
+        syn::parse_quote!(
+            {
+                Box::pin(
+                    async move {
+                        let (sink, items) = self.call_stream_with_sink(#service_method);
+                        sink.forward(#arg_ident);
+                        Ok(toy_rpc::streaming::RpcStream::new(items))
+                    }
+                )
+            }
+        )
+    } else if is_request_stream(arg_ty) {
+        syn::parse_quote!(
+            {
+                Box::pin(
+                    async move {
+                        let (sink, call) = self.call_with_sink(#service_method);
+                        sink.forward(#arg_ident);
+                        call.await.into()
+                    }
+                )
+            }
+        )
+    } else if is_prost(&method.attrs) {
+        let ok_ty = match method.sig.output.clone() {
+            syn::ReturnType::Type(_, ret_ty) => get_ok_ident_from_type(ret_ty),
+            syn::ReturnType::Default => None,
         }
-    );
+        .expect("Return type of a prost method is not a Result");
+        syn::parse_quote!(
+            {
+                Box::pin(
+                    async move {
+                        self.call::<_, toy_rpc::codec::prost::Protobuf<#ok_ty>>(
+                            #service_method,
+                            toy_rpc::codec::prost::Protobuf(#arg_ident),
+                        )
+                        .await
+                        .map(toy_rpc::codec::prost::Protobuf::into_inner)
+                        .into()
+                    }
+                )
+            }
+        )
+    } else if is_stream {
+        syn::parse_quote!(
+            {
+                Box::pin(
+                    async move {
+                        Ok(toy_rpc::streaming::RpcStream::new(self.call_stream(#service_method, #arg_ident)))
+                    }
+                )
+            }
+        )
+    } else {
+        syn::parse_quote!(
+            {
+                Box::pin(
+                    async move {
+                        self.call(#service_method, #arg_ident).await.into()
+                    }
+                )
+            }
+        )
+    };
 
     syn::ImplItemMethod {
         attrs: method.attrs.clone(),
@@ -369,3 +488,43 @@ fn generate_trait_method_impl_for_client(
         block,
     }
 }
+
+/// Generate a `<method>_stream` stub that returns a stream of all the items if the
+/// method returns a `Page`
+#[cfg(all(feature = "client", feature = "runtime"))]
+pub(crate) fn generate_page_stream_stub(
+    service_ident: &syn::Ident,
+    sig: &syn::Signature,
+) -> Option<syn::ImplItemMethod> {
+    let ret_ty = match sig.output.clone() {
+        syn::ReturnType::Type(_, ret_ty) => ret_ty,
+        syn::ReturnType::Default => return None,
+    };
+    let item_ty = get_page_item_type(&get_ok_ident_from_type(ret_ty)?)?;
+    let fn_ident = &sig.ident;
+    let stream_ident = syn::Ident::new(
+        &format!("{}_{}", fn_ident, PAGE_STREAM_SUFFIX),
+        fn_ident.span(),
+    );
+    let service_method = format!("{}.{}", service_ident, fn_ident);
+    Some(syn::parse_quote!(
+        pub fn #stream_ident(&'c self, limit: usize) -> toy_rpc::pagination::PageStream<'c, #item_ty> {
+            self.client.paginate(#service_method, limit)
+        }
+    ))
+}
+
+/// Generate the metadata of the exported methods that is used by `rpc_call!`. The
+/// metadata is looked up with the trait object `dyn Trait`.
+#[cfg(all(feature = "client", feature = "runtime"))]
+pub(crate) fn generate_method_metadata_for_trait(
+    trait_ident: &syn::Ident,
+    input: &syn::ItemTrait,
+) -> (syn::Item, syn::ItemImpl, syn::ItemImpl) {
+    let input = filter_exported_trait_items(input.clone());
+    let sigs = input.items.iter().filter_map(|item| match item {
+        syn::TraitItem::Method(f) => Some(&f.sig),
+        _ => None,
+    });
+    generate_method_metadata(trait_ident, syn::parse_quote!(dyn #trait_ident), sigs)
+}
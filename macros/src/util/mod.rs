@@ -1,5 +1,11 @@
+#[cfg(feature = "server")]
+use super::ATTR_BLOCKING;
+#[cfg(any(feature = "server", all(feature = "client", feature = "runtime")))]
+use super::ATTR_PROST;
 #[cfg(all(feature = "client", feature = "runtime",))]
-use super::{CLIENT_STUB_SUFFIX, CLIENT_SUFFIX};
+use super::{
+    CLIENT_STUB_SUFFIX, CLIENT_SUFFIX, METHODS_SUFFIX, PAGE_STREAM_SUFFIX, TOPICS_PREFIX,
+};
 #[cfg(feature = "server")]
 use super::{EXPORTED_TRAIT_SUFFIX, HANDLER_SUFFIX};
 // #[cfg(any(feature = "server", feature = "client"))]
@@ -9,6 +15,9 @@ pub mod item_impl;
 
 pub mod item_trait;
 
+#[cfg(all(feature = "client", feature = "runtime"))]
+pub mod item_mod;
+
 #[cfg(all(feature = "client", feature = "runtime"))]
 pub(crate) fn get_ok_ident_from_type(ty: Box<syn::Type>) -> Option<syn::GenericArgument> {
     let ty = Box::leak(ty);
@@ -57,6 +66,44 @@ pub(crate) fn recusively_get_result_from_type(ty: &syn::Type) -> Option<syn::Gen
     }
 }
 
+/// Returns the item type if the type is a `Page`
+#[cfg(all(feature = "client", feature = "runtime"))]
+pub(crate) fn get_page_item_type(ty: &syn::GenericArgument) -> Option<syn::Type> {
+    get_item_type(ty, "Page")
+}
+
+/// Returns the item type if the type is a `RpcStream`
+#[cfg(all(feature = "client", feature = "runtime"))]
+pub(crate) fn get_stream_item_type(ty: &syn::GenericArgument) -> Option<syn::Type> {
+    get_item_type(ty, "RpcStream")
+}
+
+/// Returns whether the argument of a method is a `RequestStream`
+#[cfg(all(feature = "client", feature = "runtime"))]
+pub(crate) fn is_request_stream(ty: &syn::Type) -> bool {
+    get_item_type(&syn::GenericArgument::Type(ty.clone()), "RequestStream").is_some()
+}
+
+/// Returns the type argument if the type is `container<T>`
+#[cfg(all(feature = "client", feature = "runtime"))]
+fn get_item_type(ty: &syn::GenericArgument, container: &str) -> Option<syn::Type> {
+    let path = match ty {
+        syn::GenericArgument::Type(syn::Type::Path(path)) => path,
+        _ => return None,
+    };
+    let segment = path.path.segments.last()?;
+    if segment.ident != container {
+        return None;
+    }
+    match &segment.arguments {
+        syn::PathArguments::AngleBracketed(angle_bracket) => match angle_bracket.args.first()? {
+            syn::GenericArgument::Type(item_ty) => Some(item_ty.clone()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
 #[cfg(any(feature = "server", all(feature = "client", feature = "runtime",)))]
 pub(crate) fn parse_impl_self_ty(self_ty: &syn::Type) -> Result<&syn::Ident, syn::Error> {
     match self_ty {
@@ -95,16 +142,157 @@ fn is_exported(attr: &syn::Attribute) -> bool {
     }
 }
 
+/// Returns true if `flag` is listed in the `#[export_method(..)]` attribute
+#[cfg(any(feature = "server", all(feature = "client", feature = "runtime")))]
+fn has_export_flag(attrs: &[syn::Attribute], flag: &str) -> bool {
+    attrs
+        .iter()
+        .filter(|attr| is_exported(attr))
+        .filter_map(|attr| attr.parse_meta().ok())
+        .any(|meta| match meta {
+            syn::Meta::List(list) => list.nested.iter().any(|nested| match nested {
+                syn::NestedMeta::Meta(syn::Meta::Path(path)) => path.is_ident(flag),
+                _ => false,
+            }),
+            _ => false,
+        })
+}
+
+/// Returns true if the method is exported with `#[export_method(blocking)]`
+#[cfg(feature = "server")]
+fn is_blocking(attrs: &[syn::Attribute]) -> bool {
+    has_export_flag(attrs, ATTR_BLOCKING)
+}
+
+/// Returns true if the method is exported with `#[export_method(prost)]`
+#[cfg(any(feature = "server", all(feature = "client", feature = "runtime")))]
+fn is_prost(attrs: &[syn::Attribute]) -> bool {
+    has_export_flag(attrs, ATTR_PROST)
+}
+
+/// Returns the type that the handler deserializes the argument as, the expression
+/// that takes the argument out of `req`, and the function that converts the reply
+/// into a `Success`. The `prost` messages are wrapped in a `Protobuf`.
+#[cfg(feature = "server")]
+fn handler_conversions(req_ty: &syn::Type, prost: bool) -> (syn::Type, syn::Expr, syn::Expr) {
+    match prost {
+        true => (
+            syn::parse_quote!(toy_rpc::codec::prost::Protobuf<#req_ty>),
+            syn::parse_quote!(req.0),
+            syn::parse_quote!(|res| {
+                toy_rpc::service::IntoSuccess::into_success(toy_rpc::codec::prost::Protobuf(res))
+            }),
+        ),
+        false => (
+            req_ty.clone(),
+            syn::parse_quote!(req),
+            syn::parse_quote!(toy_rpc::service::IntoSuccess::into_success),
+        ),
+    }
+}
+
+/// Returns true if the method takes a `toy_rpc::metadata::Context` before its argument
+///
+/// The type is either named `Context`, as imported from the crate, or spelled out
+/// with a path starting at `toy_rpc`, so that a `Context` of another crate is
+/// deserialized like any other argument.
+#[cfg(feature = "server")]
+fn takes_context(sig: &syn::Signature) -> bool {
+    if sig.inputs.len() != 3 {
+        return false;
+    }
+    let path = match &sig.inputs[1] {
+        syn::FnArg::Typed(pt) => match &*pt.ty {
+            syn::Type::Path(ty) if ty.qself.is_none() => &ty.path,
+            _ => return false,
+        },
+        _ => return false,
+    };
+    let is_context = path
+        .segments
+        .last()
+        .is_some_and(|segment| segment.ident == "Context");
+    let from_crate = match path.segments.len() {
+        1 => path.leading_colon.is_none(),
+        _ => path.segments[0].ident == "toy_rpc",
+    };
+    is_context && from_crate
+}
+
+/// Generate a `capabilities` stub that returns the capabilities of the service
+/// cached by the client, unless an exported method already has that name
+#[cfg(all(feature = "client", feature = "runtime"))]
+pub(crate) fn generate_capabilities_stub(
+    generated_items: &[syn::ImplItem],
+) -> Option<syn::ImplItemMethod> {
+    let taken = generated_items.iter().any(|item| match item {
+        syn::ImplItem::Method(f) => f.sig.ident == "capabilities",
+        _ => false,
+    });
+    if taken {
+        return None;
+    }
+    Some(syn::parse_quote!(
+        pub async fn capabilities(&'c self) -> Result<toy_rpc::capability::Capabilities, toy_rpc::Error> {
+            self.client.capabilities(self.service_name).await
+        }
+    ))
+}
+
 #[cfg(all(feature = "client", feature = "runtime"))]
 pub(crate) fn generate_client_stub_for_struct_method_impl(
     service_ident: &syn::Ident,
     fn_ident: &syn::Ident,
     req_ty: &syn::Type,
     ok_ty: &syn::GenericArgument,
+    prost: bool,
 ) -> syn::ImplItemMethod {
     let service = service_ident.to_string();
     let method = fn_ident.to_string();
     let service_method = format!("{}.{}", service, method);
+    if prost {
+        // the messages are wrapped in a `Protobuf` on the wire
+        return syn::parse_quote!(
+            pub async fn #fn_ident(&'c self, args: #req_ty) -> Result<#ok_ty, toy_rpc::Error> {
+                self.client
+                    .call::<_, toy_rpc::codec::prost::Protobuf<#ok_ty>>(
+                        #service_method,
+                        toy_rpc::codec::prost::Protobuf(args),
+                    )
+                    .await
+                    .map(toy_rpc::codec::prost::Protobuf::into_inner)
+            }
+        );
+    }
+    if is_request_stream(req_ty) {
+        // the items of the argument are sent by a `ClientSink`
+        if let Some(item_ty) = get_stream_item_type(ok_ty) {
+            return syn::parse_quote!(
+                pub fn #fn_ident(&'c self, items: #req_ty) -> toy_rpc::client::CallStream<#item_ty> {
+                    let (sink, call) = self.client.call_stream_with_sink(#service_method);
+                    sink.forward(items);
+                    call
+                }
+            );
+        }
+        return syn::parse_quote!(
+            pub fn #fn_ident(&'c self, items: #req_ty) -> toy_rpc::client::Call<#ok_ty> {
+                let (sink, call) = self.client.call_with_sink(#service_method);
+                sink.forward(items);
+                call
+            }
+        );
+    }
+    if let Some(item_ty) = get_stream_item_type(ok_ty) {
+        return syn::parse_quote!(
+            pub fn #fn_ident<A>(&'c self, args: A) -> toy_rpc::client::CallStream<#item_ty>
+            where
+                A: std::borrow::Borrow<#req_ty> + Send + Sync + toy_rpc::serde::Serialize + 'static,
+            {
+                self.client.call_stream(#service_method, args)
+            }
+        );
+    }
     syn::parse_quote!(
         pub fn #fn_ident<A>(&'c self, args: A) -> toy_rpc::client::Call<#ok_ty>
         where
@@ -114,3 +302,69 @@ pub(crate) fn generate_client_stub_for_struct_method_impl(
         }
     )
 }
+
+/// Generate the metadata of the exported methods of a service, which is looked up by
+/// `rpc_call!` with `<#self_ty as ServiceMethods>::Methods`
+#[cfg(all(feature = "client", feature = "runtime"))]
+pub(crate) fn generate_method_metadata<'a>(
+    service_ident: &syn::Ident,
+    self_ty: syn::Type,
+    sigs: impl Iterator<Item = &'a syn::Signature>,
+) -> (syn::Item, syn::ItemImpl, syn::ItemImpl) {
+    let concat_name = format!("{}{}", service_ident, METHODS_SUFFIX);
+    let methods_ident = syn::Ident::new(&concat_name, service_ident.span());
+
+    let methods_struct = syn::parse_quote!(
+        #[doc(hidden)]
+        pub struct #methods_ident;
+    );
+    let mut methods_impl: syn::ItemImpl = syn::parse_quote!(
+        impl #methods_ident {
+
+        }
+    );
+    methods_impl.items = sigs
+        .filter_map(|sig| generate_method_metadata_fn(service_ident, sig))
+        .map(syn::ImplItem::Method)
+        .collect();
+    let service_impl = syn::parse_quote!(
+        impl toy_rpc::client::typed::ServiceMethods for #self_ty {
+            type Methods = #methods_ident;
+        }
+    );
+    (methods_struct, methods_impl, service_impl)
+}
+
+/// Generate the function returning the metadata of a method. The methods whose
+/// argument is a `RequestStream` have no metadata.
+#[cfg(all(feature = "client", feature = "runtime"))]
+fn generate_method_metadata_fn(
+    service_ident: &syn::Ident,
+    sig: &syn::Signature,
+) -> Option<syn::ImplItemMethod> {
+    let req_ty = match sig.inputs.last()? {
+        syn::FnArg::Typed(pt) => &pt.ty,
+        _ => return None,
+    };
+    if is_request_stream(req_ty) {
+        return None;
+    }
+    let ok_ty = match &sig.output {
+        syn::ReturnType::Type(_, ret_ty) => get_ok_ident_from_type(ret_ty.clone())?,
+        syn::ReturnType::Default => return None,
+    };
+    let fn_ident = &sig.ident;
+    let service_method = format!("{}.{}", service_ident, fn_ident);
+    if let Some(item_ty) = get_stream_item_type(&ok_ty) {
+        return Some(syn::parse_quote!(
+            pub fn #fn_ident() -> toy_rpc::client::typed::StreamMethod<#req_ty, #item_ty> {
+                toy_rpc::client::typed::StreamMethod::new(#service_method)
+            }
+        ));
+    }
+    Some(syn::parse_quote!(
+        pub fn #fn_ident() -> toy_rpc::client::typed::Method<#req_ty, #ok_ty> {
+            toy_rpc::client::typed::Method::new(#service_method)
+        }
+    ))
+}
@@ -0,0 +1,180 @@
+//! Optional capabilities of the services
+//!
+//! A service can declare a map of named flags, eg. `"supports_streaming"`, when it is
+//! registered with `ServerBuilder::capabilities`. Every registered service answers
+//! the reserved method `DESCRIBE_METHOD` with its `Capabilities`, which is empty if
+//! none are declared. A client fetches the capabilities of a service with
+//! `Client::capabilities`, or with the `capabilities` method of the generated client
+//! of the service, and caches them for the lifetime of the client. This allows a
+//! client to branch on the optional features of the server instead of probing with
+//! calls that fail.
+//!
+//! A server that predates this module replies to `DESCRIBE_METHOD` with
+//! `Error::MethodNotFound`, which the client takes as no capabilities at all.
+//!
+//! # Example
+//!
+//! ```no_run
+//! # #[cfg(all(feature = "server", feature = "client", feature = "tokio_runtime", not(feature = "async_std_runtime")))]
+//! # {
+//! # use std::sync::Arc;
+//! # use toy_rpc::{Client, Server};
+//! # use toy_rpc::macros::export_impl;
+//! # struct Example;
+//! # #[export_impl]
+//! # impl Example {
+//! #     #[export_method]
+//! #     async fn echo(&self, args: String) -> Result<String, String> {
+//! #         Ok(args)
+//! #     }
+//! # }
+//! # use toy_rpc::capability::Capabilities;
+//! # async fn run(client: Client) -> Result<(), Box<dyn std::error::Error>> {
+//! # let example_service = Arc::new(Example);
+//! let server = Server::builder()
+//!     .register(example_service)
+//!     .capabilities(
+//!         "Example",
+//!         Capabilities::new()
+//!             .enable("supports_streaming")
+//!             .set("supports_compression", false),
+//!     )
+//!     .build();
+//!
+//! let capabilities = client.example().capabilities().await?;
+//! if capabilities.supports("supports_streaming") {
+//!     // use the streaming methods
+//! }
+//! # Ok(())
+//! # }
+//! # }
+//! ```
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Name of the method of every service that returns its capabilities
+pub const DESCRIBE_METHOD: &str = "describe_capabilities";
+
+/// Named flags of the optional features of a service
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Capabilities {
+    flags: BTreeMap<String, bool>,
+}
+
+impl Capabilities {
+    /// Creates an empty set of capabilities
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Turns on the capability `name`
+    pub fn enable(self, name: impl ToString) -> Self {
+        self.set(name, true)
+    }
+
+    /// Sets the capability `name` to `enabled`. Declaring a capability that is
+    /// turned off tells a client that the server knows about it but does not
+    /// support it.
+    pub fn set(self, name: impl ToString, enabled: bool) -> Self {
+        let mut capabilities = self;
+        capabilities.flags.insert(name.to_string(), enabled);
+        capabilities
+    }
+
+    /// Returns whether the capability `name` is declared and turned on
+    pub fn supports(&self, name: &str) -> bool {
+        self.flags.get(name).copied().unwrap_or(false)
+    }
+
+    /// Returns the value of the capability `name`, or `None` if it is not declared
+    pub fn get(&self, name: &str) -> Option<bool> {
+        self.flags.get(name).copied()
+    }
+
+    /// Returns whether no capability is declared
+    pub fn is_empty(&self) -> bool {
+        self.flags.is_empty()
+    }
+
+    /// Iterates over the declared capabilities in the order of their names
+    pub fn iter(&self) -> impl Iterator<Item = (&str, bool)> {
+        self.flags
+            .iter()
+            .map(|(name, enabled)| (name.as_str(), *enabled))
+    }
+}
+
+#[cfg(feature = "server")]
+mod describe {
+    use erased_serde as erased;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use super::{Capabilities, DESCRIBE_METHOD};
+    use crate::service::{ArcAsyncServiceCall, AsyncServiceMap, HandlerResultFut, Success};
+
+    /// Answers `DESCRIBE_METHOD` on every registered service
+    pub(crate) fn apply(
+        services: &mut AsyncServiceMap,
+        capabilities: &HashMap<String, Capabilities>,
+    ) {
+        for name in capabilities.keys() {
+            if !services.contains_key(name.as_str()) {
+                warn!("Capabilities are set on unknown service {}", name);
+            }
+        }
+
+        for (name, call) in services.iter_mut() {
+            let declared = capabilities.get(*name).cloned().unwrap_or_default();
+            let inner = call.clone();
+            *call = with_describe(inner, declared);
+        }
+    }
+
+    fn with_describe(
+        inner: ArcAsyncServiceCall,
+        capabilities: Capabilities,
+    ) -> ArcAsyncServiceCall {
+        let call = move |method_name: String,
+                         deserializer: Box<dyn erased::Deserializer<'static> + Send>|
+              -> HandlerResultFut {
+            if method_name == DESCRIBE_METHOD {
+                let capabilities = capabilities.clone();
+                Box::pin(async move { Ok(Success::Reply(Box::new(capabilities))) })
+            } else {
+                inner(method_name, deserializer)
+            }
+        };
+        Arc::new(call)
+    }
+}
+
+#[cfg(feature = "server")]
+pub(crate) use describe::apply;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn capabilities_flags() {
+        let capabilities = Capabilities::new()
+            .enable("supports_streaming")
+            .set("supports_compression", false);
+
+        assert!(capabilities.supports("supports_streaming"));
+        assert!(!capabilities.supports("supports_compression"));
+        assert!(!capabilities.supports("supports_pubsub"));
+        assert_eq!(capabilities.get("supports_compression"), Some(false));
+        assert_eq!(capabilities.get("supports_pubsub"), None);
+        assert_eq!(
+            capabilities.iter().collect::<Vec<_>>(),
+            vec![
+                ("supports_compression", false),
+                ("supports_streaming", true)
+            ]
+        );
+        assert!(Capabilities::new().is_empty());
+    }
+}
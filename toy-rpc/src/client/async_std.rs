@@ -9,38 +9,49 @@ cfg_if! {
             not(feature = "serde_json"),
             not(feature = "serde_cbor"),
             not(feature = "serde_rmp"),
+            not(feature = "serde_postcard"),
         ),
         all(
             feature = "serde_cbor",
             not(feature = "serde_json"),
             not(feature = "serde_bincode"),
             not(feature = "serde_rmp"),
+            not(feature = "serde_postcard"),
         ),
         all(
             feature = "serde_json",
             not(feature = "serde_bincode"),
             not(feature = "serde_cbor"),
             not(feature = "serde_rmp"),
+            not(feature = "serde_postcard"),
         ),
         all(
             feature = "serde_rmp",
             not(feature = "serde_cbor"),
             not(feature = "serde_json"),
             not(feature = "serde_bincode"),
+            not(feature = "serde_postcard"),
+        ),
+        all(
+            feature = "serde_postcard",
+            not(feature = "serde_cbor"),
+            not(feature = "serde_json"),
+            not(feature = "serde_bincode"),
+            not(feature = "serde_rmp"),
         )
     ))] {
         use futures::{AsyncRead, AsyncWrite};
         use ::async_std::net::{TcpStream, ToSocketAddrs};
-        use async_tungstenite::async_std::connect_async;
+        use async_tungstenite::client_async;
 
         #[cfg(feature = "tls")]
         use rustls::{ClientConfig};
 
         use crate::{Error, codec::DefaultCodec};
-        use crate::transport::ws::WebSocketConn;
+        use crate::transport::ws::{client_request, offered_in, WebSocketConn};
         use crate::DEFAULT_RPC_PATH;
 
-        use super::{Client};
+        use super::{Client, ClientBuilder};
 
         /// The following impl block is controlled by feature flag. It is enabled
         /// if and only if **exactly one** of the the following feature flag is turned on
@@ -48,6 +59,7 @@ cfg_if! {
         /// - `serde_json`
         /// - `serde_cbor`
         /// - `serde_rmp`
+        /// - `serde_postcard`
         impl Client {
             /// Connects the an RPC server over socket at the specified network address
             ///
@@ -57,17 +69,21 @@ cfg_if! {
             /// - `serde_json`
             /// - `serde_cbor`
             /// - `serde_rmp`
+            /// - `serde_postcard`
             ///
             /// # Example
             ///
-            /// ```rust
+            /// ```no_run
+            /// # use toy_rpc::Client;
+            /// # async fn run() -> Result<(), Box<dyn std::error::Error>> {
             /// let addr = "127.0.0.1:8080";
             /// let client = Client::dial(addr).await.unwrap();
+            /// # Ok(())
+            /// # }
             /// ```
             #[cfg_attr(feature = "docs", doc(cfg(feature = "async_std_runtime")))]
             pub async fn dial(addr: impl ToSocketAddrs)-> Result<Client, Error> {
-                let stream = TcpStream::connect(addr).await?;
-                Ok(Self::with_stream(stream))
+                Self::builder().dial(addr).await
             }
 
             /// Connects to an RPC server with TLS enabled
@@ -81,7 +97,7 @@ cfg_if! {
                 domain: &str,
                 config: ClientConfig
             ) -> Result<Client, Error> {
-                super::tcp_client_with_tls_config(addr, domain, config).await
+                Self::builder().dial_with_tls_config(addr, domain, config).await
             }
 
             /// Connects to an HTTP RPC server at the specified network address using WebSocket and the defatul codec.
@@ -102,19 +118,21 @@ cfg_if! {
             /// - `serde_json`
             /// - `serde_cbor`
             /// - `serde_rmp`
+            /// - `serde_postcard`
             ///
             /// # Example
             ///
-            /// ```rust
+            /// ```no_run
+            /// # use toy_rpc::Client;
+            /// # async fn run() -> Result<(), Box<dyn std::error::Error>> {
             /// let addr = "ws://127.0.0.1:8080/rpc/";
             /// let client = Client::dial_http(addr).await.unwrap();
+            /// # Ok(())
+            /// # }
             /// ```
             #[cfg_attr(feature = "docs", doc(cfg(feature = "async_std_runtime")))]
             pub async fn dial_http(addr: &str) -> Result<Client, Error> {
-                let mut url = url::Url::parse(addr)?.join(DEFAULT_RPC_PATH)?;
-                url.set_scheme("ws").expect("Failed to change scheme to ws");
-
-                Self::dial_websocket_url(url).await
+                Self::builder().dial_http(addr).await
             }
 
             /// Connects to an HTTP RPC server with TLS enabled
@@ -128,10 +146,43 @@ cfg_if! {
                 domain: &str,
                 config: ClientConfig,
             ) -> Result<Client, Error> {
-                let mut url = url::Url::parse(addr)?.join(DEFAULT_RPC_PATH)?;
-                url.set_scheme("ws").expect("Failed to change scheme to ws");
+                Self::builder().dial_http_with_tls_config(addr, domain, config).await
+            }
 
-                super::websocket_client_with_tls_config(url, domain, config).await
+            /// Connects to an HTTP RPC server at the specified network address with the
+            /// HTTP POST transport, for networks whose proxies block the WebSocket
+            /// upgrades that `dial_http` relies on.
+            ///
+            /// Like `dial_http`, `DEFAULT_RPC_PATH="_rpc"` is appended to the end of `addr`.
+            /// The payloads are carried by plain HTTP/1.1 POST requests instead of a
+            /// WebSocket connection, and the server holds a request until it has a
+            /// response or a publication to send back, so calls and subscriptions work
+            /// the same, at the cost of a request per round trip. See the
+            /// `transport::post` module for the protocol. The server has to be served
+            /// with `serve_hyper` or the `warp` integration, and the transport does not
+            /// support TLS.
+            ///
+            /// This is enabled
+            /// if and only if **exactly one** of the the following feature flag is turned on
+            /// - `serde_bincode`
+            /// - `serde_json`
+            /// - `serde_cbor`
+            /// - `serde_rmp`
+            /// - `serde_postcard`
+            ///
+            /// # Example
+            ///
+            /// ```no_run
+            /// # use toy_rpc::Client;
+            /// # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+            /// let addr = "http://127.0.0.1:8080/rpc/";
+            /// let client = Client::dial_http_post(addr).await.unwrap();
+            /// # Ok(())
+            /// # }
+            /// ```
+            #[cfg_attr(feature = "docs", doc(cfg(feature = "async_std_runtime")))]
+            pub async fn dial_http_post(addr: &str) -> Result<Client, Error> {
+                Self::builder().dial_http_post(addr).await
             }
 
             /// Similar to `dial`, this connects to an WebSocket RPC server at the specified network address using the defatul codec.
@@ -139,34 +190,54 @@ cfg_if! {
             /// The difference between `dial_websocket` and `dial_http` is that, `dial_websocket` does not
             /// append `DEFAULT_RPC_PATH="_rpc"` to the end of the addr.
             ///
+            /// With the `tls` feature, a "wss://" url is connected with TLS, trusting the Mozilla root
+            /// certificates and using the host of the url as the server name. Use
+            /// `dial_websocket_with_tls_config` to trust another root store, for example a self-signed
+            /// certificate in development.
+            ///
             /// This is enabled
             /// if and only if **exactly one** of the the following feature flag is turned on
             /// - `serde_bincode`
             /// - `serde_json`
             /// - `serde_cbor`
             /// - `serde_rmp`
+            /// - `serde_postcard`
             ///
             /// # Example
             ///
-            /// ```rust
+            /// ```no_run
+            /// # use toy_rpc::Client;
+            /// # async fn run() -> Result<(), Box<dyn std::error::Error>> {
             /// let addr = "ws://127.0.0.1:8080";
             /// let client = Client::dial_websocket(addr).await.unwrap();
+            /// # Ok(())
+            /// # }
             /// ```
             ///
             #[cfg_attr(feature = "docs", doc(cfg(feature = "async_std_runtime")))]
             pub async fn dial_websocket(addr: &str) -> Result<Client, Error> {
-                let url = url::Url::parse(addr)?;
-                Self::dial_websocket_url(url).await
-            }
-
-            async fn dial_websocket_url(url: url::Url) -> Result<Client, Error> {
-                let (ws_stream, _) = connect_async(&url).await?;
-                let ws_stream = WebSocketConn::new(ws_stream);
-                let codec = DefaultCodec::with_websocket(ws_stream);
-                Ok(Self::with_codec(codec))
+                Self::builder().dial_websocket(addr).await
             }
 
             /// Similar to `dial_websocket` but with TLS enabled
+            ///
+            /// The server certificate is verified against the root store of `config` with `domain`
+            /// as the server name (SNI), so that a self-signed certificate can be trusted by adding
+            /// it to the root store.
+            ///
+            /// # Example
+            ///
+            /// ```no_run
+            /// # use toy_rpc::Client;
+            /// # use std::fs::File;
+            /// # use std::io::BufReader;
+            /// # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+            /// let mut config = rustls::ClientConfig::new();
+            /// config.root_store.add_pem_file(&mut BufReader::new(File::open("ca.pem")?)).unwrap();
+            /// let client = Client::dial_websocket_with_tls_config("wss://127.0.0.1:8443", "localhost", config).await?;
+            /// # Ok(())
+            /// # }
+            /// ```
             #[cfg(feature = "tls")]
             #[cfg_attr(feature = "docs",doc(cfg(all(feature ="tls", feature = "async_std_runtime"))))]
             pub async fn dial_websocket_with_tls_config(
@@ -174,8 +245,7 @@ cfg_if! {
                 domain: &str,
                 config: ClientConfig,
             ) -> Result<Client, Error> {
-                let url = url::Url::parse(addr)?;
-                super::websocket_client_with_tls_config(url, domain, config).await
+                Self::builder().dial_websocket_with_tls_config(addr, domain, config).await
             }
 
             /// Creates an RPC `Client` over a stream that implements `futures::io::AsyncRead`
@@ -187,11 +257,17 @@ cfg_if! {
             /// - `serde_json`
             /// - `serde_cbor`
             /// - `serde_rmp`
+            /// - `serde_postcard`
             ///
             /// # Example
-            /// ```
+            /// ```no_run
+            /// # use toy_rpc::Client;
+            /// # use async_std::net::TcpStream;
+            /// # async fn run() -> Result<(), Box<dyn std::error::Error>> {
             /// let stream = TcpStream::connect("127.0.0.1:8080").await.unwrap();
             /// let client = Client::with_stream(stream);
+            /// # Ok(())
+            /// # }
             /// ```
             #[cfg_attr(feature = "docs", doc(cfg(feature = "async_std_runtime")))]
             pub fn with_stream<T>(stream: T) -> Client
@@ -202,5 +278,194 @@ cfg_if! {
                 Self::with_codec(codec)
             }
         }
+
+        /// The following impl block is controlled by feature flag. It is enabled
+        /// if and only if **exactly one** of the the following feature flag is turned on
+        /// - `serde_bincode`
+        /// - `serde_json`
+        /// - `serde_cbor`
+        /// - `serde_rmp`
+        /// - `serde_postcard`
+        impl ClientBuilder {
+            /// Connects to an RPC server over socket like `Client::dial`, within the
+            /// timeouts of the builder, and applies the options to the client
+            ///
+            /// # Example
+            ///
+            /// ```no_run
+            /// # use toy_rpc::Client;
+            /// # use std::time::Duration;
+            /// # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+            /// let client = Client::builder()
+            ///     .connect_timeout(Duration::from_secs(3))
+            ///     .dial("127.0.0.1:8080")
+            ///     .await?;
+            /// # Ok(())
+            /// # }
+            /// ```
+            #[cfg_attr(feature = "docs", doc(cfg(feature = "async_std_runtime")))]
+            pub async fn dial(self, addr: impl ToSocketAddrs) -> Result<Client, Error> {
+                let stream = self.timeouts.connect(TcpStream::connect(addr)).await?;
+                #[cfg(all(feature = "codec_negotiation", not(feature = "serde_json")))]
+                if !self.content_types.is_empty() {
+                    let mut stream = stream;
+                    let negotiation = crate::codec::negotiate::request(&mut stream, &self.content_types);
+                    let content_type = self.timeouts.handshake(negotiation).await??;
+                    let frame = self.frame;
+                    return crate::codec::negotiate::with_format_codec!(content_type, stream, frame, |codec| {
+                        self.build(Client::with_codec(codec))
+                    })
+                }
+                let mut codec = DefaultCodec::new(stream);
+                codec.frame = self.frame;
+                self.build(Client::with_codec(codec))
+            }
+
+            /// Connects to an RPC server with TLS enabled like `Client::dial_with_tls_config`,
+            /// within the timeouts of the builder
+            #[cfg(feature = "tls")]
+            #[cfg_attr(feature = "docs",doc(cfg(all(feature = "tls", feature = "async_std_runtime"))))]
+            pub async fn dial_with_tls_config(
+                self,
+                addr: impl ToSocketAddrs,
+                domain: &str,
+                config: ClientConfig
+            ) -> Result<Client, Error> {
+                let client = super::tcp_client_with_tls_config(addr, domain, config, &self.timeouts, self.frame).await?;
+                self.build(client)
+            }
+
+            /// Connects to an HTTP RPC server like `Client::dial_http`, within the timeouts
+            /// of the builder
+            #[cfg_attr(feature = "docs", doc(cfg(feature = "async_std_runtime")))]
+            pub async fn dial_http(self, addr: &str) -> Result<Client, Error> {
+                let mut url = url::Url::parse(addr)?.join(DEFAULT_RPC_PATH)?;
+                url.set_scheme("ws").expect("Failed to change scheme to ws");
+
+                self.dial_websocket_url(url).await
+            }
+
+            /// Connects to an HTTP RPC server with TLS enabled like
+            /// `Client::dial_http_with_tls_config`, within the timeouts of the builder
+            #[cfg(feature = "tls")]
+            #[cfg_attr(feature = "docs",doc(cfg(all(feature = "tls", feature = "async_std_runtime"))))]
+            pub async fn dial_http_with_tls_config(
+                self,
+                addr: &str,
+                domain: &str,
+                config: ClientConfig,
+            ) -> Result<Client, Error> {
+                let mut url = url::Url::parse(addr)?.join(DEFAULT_RPC_PATH)?;
+                url.set_scheme("ws").expect("Failed to change scheme to ws");
+
+                let client = super::websocket_client_with_tls_config(url, domain, config, &self.timeouts).await?;
+                self.build(client)
+            }
+
+            /// Connects to an HTTP RPC server with the HTTP POST transport like
+            /// `Client::dial_http_post`. The request that opens the session is bounded
+            /// by the `handshake_timeout` of the builder.
+            #[cfg_attr(feature = "docs", doc(cfg(feature = "async_std_runtime")))]
+            pub async fn dial_http_post(self, addr: &str) -> Result<Client, Error> {
+                let url = url::Url::parse(addr)?.join(DEFAULT_RPC_PATH)?;
+                let codec = self.timeouts.handshake(super::post::open(url)).await??;
+                self.build(Client::with_codec(codec))
+            }
+
+            /// Connects to a WebSocket RPC server like `Client::dial_websocket`, within the
+            /// timeouts of the builder
+            #[cfg_attr(feature = "docs", doc(cfg(feature = "async_std_runtime")))]
+            pub async fn dial_websocket(self, addr: &str) -> Result<Client, Error> {
+                let url = url::Url::parse(addr)?;
+                #[cfg(feature = "tls")]
+                if url.scheme() == "wss" {
+                    let client = super::secure_websocket_client(url, &self.timeouts).await?;
+                    return self.build(client);
+                }
+                self.dial_websocket_url(url).await
+            }
+
+            async fn dial_websocket_url(self, url: url::Url) -> Result<Client, Error> {
+                let stream = self.timeouts.connect(TcpStream::connect(super::ws_addr(&url)?)).await?;
+                let (ws_stream, response) = self.timeouts.handshake(client_async(client_request(url)?, stream)).await?
+                    .map_err(Error::from_ws_handshake)?;
+                let ws_stream = WebSocketConn::new(ws_stream).chunked(offered_in(response.headers()));
+                let codec = DefaultCodec::with_websocket(ws_stream);
+                self.build(Client::with_codec(codec))
+            }
+
+            /// Connects to a WebSocket RPC server with TLS enabled like
+            /// `Client::dial_websocket_with_tls_config`, within the timeouts of the builder
+            #[cfg(feature = "tls")]
+            #[cfg_attr(feature = "docs",doc(cfg(all(feature = "tls", feature = "async_std_runtime"))))]
+            pub async fn dial_websocket_with_tls_config(
+                self,
+                addr: &str,
+                domain: &str,
+                config: ClientConfig,
+            ) -> Result<Client, Error> {
+                let url = url::Url::parse(addr)?;
+                let client = super::websocket_client_with_tls_config(url, domain, config, &self.timeouts).await?;
+                self.build(client)
+            }
+        }
+    }
+}
+
+#[cfg(not(feature = "serde_json"))]
+mod custom {
+    use futures::{AsyncRead, AsyncWrite};
+
+    use crate::codec::custom::CustomCodec;
+    use crate::codec::{EraseDeserializer, Marshal, Unmarshal};
+
+    use super::super::Client;
+
+    impl Client {
+        /// Creates an RPC `Client` over a stream with a `CustomCodec` of the format `F`
+        ///
+        /// Unlike `Client::with_stream`, this does not need any of the `serde_*`
+        /// feature flags. See the `codec::custom` module for details.
+        ///
+        /// Example
+        ///
+        /// ```no_run
+        /// # use toy_rpc::Client;
+        /// # use async_std::net::TcpStream;
+        /// # use toy_rpc::Error;
+        /// # use toy_rpc::codec::{EraseDeserializer, Marshal, Unmarshal};
+        /// # struct Postcard;
+        /// # impl Marshal for Postcard {
+        /// #     fn marshal<S: serde::Serialize>(val: &S) -> Result<Vec<u8>, Error> {
+        /// #         unimplemented!()
+        /// #     }
+        /// # }
+        /// # impl Unmarshal for Postcard {
+        /// #     fn unmarshal<'de, D: serde::Deserialize<'de>>(buf: &'de [u8]) -> Result<D, Error> {
+        /// #         unimplemented!()
+        /// #     }
+        /// # }
+        /// # impl EraseDeserializer for Postcard {
+        /// #     fn from_bytes(buf: Vec<u8>) -> Box<dyn erased_serde::Deserializer<'static> + Send> {
+        /// #         unimplemented!()
+        /// #     }
+        /// # }
+        /// # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+        /// let stream = TcpStream::connect("127.0.0.1:8080").await.unwrap();
+        /// let client = Client::with_custom_codec::<_, Postcard>(stream);
+        /// # Ok(())
+        /// # }
+        /// ```
+        #[cfg_attr(
+            feature = "docs",
+            doc(cfg(all(feature = "async_std_runtime", not(feature = "serde_json"))))
+        )]
+        pub fn with_custom_codec<T, F>(stream: T) -> Client
+        where
+            T: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+            F: Marshal + Unmarshal + EraseDeserializer + Send + Sync + 'static,
+        {
+            Self::with_codec(CustomCodec::<_, _, F>::new(stream))
+        }
     }
 }
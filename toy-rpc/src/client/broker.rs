@@ -1,30 +1,74 @@
 use cfg_if::cfg_if;
-use flume::Sender;
 use futures::channel::oneshot;
-use std::time::Duration;
+use std::{sync::Arc, time::Duration};
 
 cfg_if! {
     if #[cfg(any(
         all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
         all(feature = "async_std_runtime", not(feature = "tokio_runtime"))
     ))] {
-        use std::{sync::{Arc, atomic::Ordering}, collections::HashMap};
+        use std::{sync::atomic::Ordering, collections::HashMap, time::Instant};
         use brw::{Context, Running};
         use futures::{Sink, SinkExt};
 
-        use crate::message::AtomicMessageId;
+        use crate::extension::HEARTBEAT_MARKER;
+        use crate::message::{AtomicMessageId, ErrorMessage};
+        use crate::metadata::{self, METADATA_MARKER};
+        use crate::payload::{PayloadCounters, SizeLimits};
+        use crate::pubsub;
 
-        use super::{writer::ClientWriterItem};
+        use futures::future::{AbortHandle, Abortable};
+
+        use super::{
+            disconnect::{DisconnectReason, Lifecycle},
+            layer::{Request, Response, ResponseAction},
+            low_power::{self, Heartbeat},
+            metrics::{CallOutcome, CallTimer, PendingRequest},
+            pubsub::deliver_all,
+            resilience::{self, Resilience, Revalidation},
+            storm::StormAction,
+            unexpected::ResponseTracker,
+            writer::ClientWriterItem,
+        };
+
+        #[cfg(feature = "server")]
+        use crate::service::Success;
+        #[cfg(feature = "server")]
+        use super::duplex::Serving;
     }
 }
 
+#[cfg(feature = "server")]
+use crate::service::{AsyncServiceMap, HandlerResult};
+#[cfg(feature = "signing")]
+use crate::signing::{ResponseVerifier, Signed};
+
 use crate::{
+    extension::ExtensionHandler,
     message::MessageId,
+    metadata::Metadata,
+    payload::SizeLimit,
     protocol::{InboundBody, OutboundBody},
+    pubsub::PublishReport,
     Error,
 };
 
-use super::ResponseResult;
+use super::{
+    call_stream::StreamEvent, disconnect::DisconnectHandler, layer::Layer, low_power::LowPower,
+    metrics::MetricsSink, mirror::Mirror, pubsub::LocalSubscriber, resilience::ClockJumpHandler,
+    storm::TimeoutStorm, unexpected::UnexpectedResponseHandler, ResponseResult,
+};
+
+/// Signature of a response and the bytes of the body that it covers
+#[cfg(feature = "signing")]
+pub(crate) struct Signature {
+    pub content: String,
+    pub body: Vec<u8>,
+}
+
+/// Notified once the server has accepted a publication, with the report of its
+/// delivery if the server sends one
+pub(crate) type PublishAcked = oneshot::Sender<Result<Option<PublishReport>, Error>>;
 
 #[cfg_attr(
     all(not(feature = "tokio_runtime"), not(feature = "async_std_runtime")),
@@ -35,30 +79,81 @@ pub(crate) enum ClientBrokerItem {
         id: MessageId,
         service_method: String,
         duration: Duration,
-        body: Box<OutboundBody>,
+        body: Arc<OutboundBody>,
         resp_tx: oneshot::Sender<Result<ResponseResult, Error>>,
+        /// Whether the argument is sent by a `ClientSink` instead of the body
+        sink: bool,
+        /// Metadata of the request
+        metadata: Metadata,
+        /// Receives the metadata of the response if the caller waits for it
+        reply_metadata: Option<oneshot::Sender<Metadata>>,
     },
     Response {
         id: MessageId,
         result: ResponseResult,
+        /// Size of the response body in bytes
+        bytes: usize,
+        /// Signature that preceded the response
+        #[cfg(feature = "signing")]
+        signature: Option<Signature>,
     },
+    /// Request of a streaming call
+    StreamRequest {
+        id: MessageId,
+        service_method: String,
+        duration: Duration,
+        body: Arc<OutboundBody>,
+        items: flume::Sender<StreamEvent>,
+        /// Whether the argument is sent by a `ClientSink` instead of the body
+        sink: bool,
+    },
+    /// Item of the argument of a call, sent by a `ClientSink`
+    SinkItem {
+        id: MessageId,
+        body: Box<OutboundBody>,
+    },
+    /// End of the argument of a call
+    SinkEnd(MessageId),
+    /// Item of a streaming response
+    StreamItem {
+        id: MessageId,
+        result: ResponseResult,
+        /// Size of the item in bytes
+        bytes: usize,
+        /// Signature that preceded the item
+        #[cfg(feature = "signing")]
+        signature: Option<Signature>,
+    },
+    /// End of a streaming response
+    StreamEnd(MessageId),
     Cancel(MessageId),
+    /// The call has timed out
+    Timeout(MessageId),
     /// New publication to the server
     Publish {
         // id: MessageId,
         topic: String,
         body: Box<OutboundBody>,
+        // type tag of the item
+        tag: Option<u64>,
+        // notified once the server has accepted the publication
+        acked: Option<PublishAcked>,
     },
+    /// The server has accepted a publication. The body is the `PublishReport` of its
+    /// delivery, or `()` from a server that does not report the deliveries.
+    Ack(MessageId, Box<InboundBody>),
     Subscribe {
         // id: MessageId,
         topic: String,
+        // number of past messages to replay, if this is the first local subscriber
+        replay: Option<usize>,
 
         // message is deserialized as it is read on the subscriber
-        item_sink: Sender<Box<InboundBody>>,
+        subscriber: LocalSubscriber,
     },
     NewLocalSubscriber {
         topic: String,
-        new_item_sink: Sender<Box<InboundBody>>,
+        subscriber: LocalSubscriber,
     },
     Unsubscribe {
         // id: MessageId,
@@ -68,10 +163,140 @@ pub(crate) enum ClientBrokerItem {
     Subscription {
         id: MessageId,
         topic: String,
-        item: Box<InboundBody>,
+        bytes: Vec<u8>,
+        // every local subscriber gets its own deserializer
+        from_bytes: fn(Vec<u8>) -> Box<InboundBody>,
+        tag: Option<u64>,
+        // whether the server waits for an acknowledgement
+        ack: bool,
+    },
+    /// The server rejected a subscription
+    Rejected {
+        id: MessageId,
+        topic: String,
+        bytes: Vec<u8>,
+        from_bytes: fn(Vec<u8>) -> Box<InboundBody>,
+    },
+    /// Registers a handler of a protocol extension
+    RegisterExtension {
+        marker: u32,
+        handler: ExtensionHandler,
+    },
+    /// New protocol extension message to the server
+    Ext {
+        marker: u32,
+        content: String,
+    },
+    /// Protocol extension message from the server
+    ExtReceived {
+        id: MessageId,
+        marker: u32,
+        content: String,
+    },
+    /// Sets the handler of the responses that do not match a pending call
+    SetUnexpectedResponseHandler {
+        handler: UnexpectedResponseHandler,
+    },
+    /// Sets the handler of a lost connection
+    SetDisconnectHandler {
+        handler: DisconnectHandler,
+    },
+    /// Enables the resilience mode
+    SetClockJumpHandler {
+        threshold: Duration,
+        handler: ClockJumpHandler,
+    },
+    /// The wall clock has advanced further than the monotonic clock
+    ClockJump(Duration),
+    /// Sets the receiver of the call metrics
+    SetMetricsSink {
+        sink: Arc<dyn MetricsSink>,
+    },
+    /// Sets the layers intercepting the calls
+    SetLayers {
+        layers: Vec<Arc<dyn Layer>>,
+    },
+    /// Sets the policy on storms of timeouts
+    SetTimeoutStorm {
+        storm: TimeoutStorm,
+    },
+    /// Sets the shadow client that selected calls are mirrored to
+    SetMirror {
+        mirror: Mirror,
+    },
+    /// Sets the size limit of a service or of a method
+    SetSizeLimit {
+        service_method: String,
+        limit: SizeLimit,
+    },
+    /// Enables the low-power mode
+    SetLowPower {
+        options: LowPower,
+    },
+    /// Sets the keys that the signatures of the responses are verified with
+    #[cfg(feature = "signing")]
+    SetVerifier {
+        verifier: Arc<ResponseVerifier>,
+    },
+    /// The heartbeat timer has ticked
+    Heartbeat,
+    /// Quiesces the traffic of the client
+    Pause,
+    /// Writes the messages queued while paused
+    Resume,
+    /// The connection is closed by the server
+    Closed,
+    /// Gets the calls waiting for their responses
+    PendingRequests {
+        done: oneshot::Sender<Vec<super::metrics::PendingRequest>>,
     },
     /// Stops the broker
     Stop,
+    /// Sets the services that the server calls over the connection
+    #[cfg(feature = "server")]
+    Serve {
+        services: Arc<AsyncServiceMap>,
+    },
+    /// Call from the server to a service of the client
+    #[cfg(feature = "server")]
+    ServeRequest {
+        id: MessageId,
+        service_method: String,
+        duration: Duration,
+        deserializer: Box<InboundBody>,
+    },
+    /// The server has canceled a call to a service of the client
+    #[cfg(feature = "server")]
+    ServeCancel(MessageId),
+    /// Result of a call from the server
+    #[cfg(feature = "server")]
+    ServeResponse {
+        id: MessageId,
+        result: HandlerResult,
+    },
+}
+
+impl ClientBrokerItem {
+    /// Whether the item is written to the server on behalf of the user, which is
+    /// queued while the client is paused
+    #[cfg_attr(
+        all(not(feature = "tokio_runtime"), not(feature = "async_std_runtime")),
+        allow(dead_code)
+    )]
+    fn is_outbound(&self) -> bool {
+        matches!(
+            self,
+            ClientBrokerItem::Request { .. }
+                | ClientBrokerItem::StreamRequest { .. }
+                | ClientBrokerItem::SinkItem { .. }
+                | ClientBrokerItem::SinkEnd(_)
+                | ClientBrokerItem::Cancel(_)
+                | ClientBrokerItem::Publish { .. }
+                | ClientBrokerItem::Subscribe { .. }
+                | ClientBrokerItem::Unsubscribe { .. }
+                | ClientBrokerItem::Ext { .. }
+        )
+    }
 }
 
 #[cfg(all(feature = "async_std_runtime", not(feature = "tokio_runtime")))]
@@ -79,40 +304,425 @@ use ::async_std::task::{self};
 #[cfg(all(feature = "tokio_runtime", not(feature = "async_std_runtime")))]
 use ::tokio::task::{self};
 
+#[cfg(any(
+    all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+    all(feature = "async_std_runtime", not(feature = "tokio_runtime"))
+))]
+/// A call waiting for its response
+pub(crate) struct PendingCall {
+    pub tx: oneshot::Sender<Result<(ResponseResult, usize), Error>>,
+    pub service_method: String,
+    pub started: Instant,
+    // number of times the request was sent
+    pub attempt: u32,
+    // kept to be sent again if a layer retries the call
+    pub request: Option<SentRequest>,
+    // receives the metadata of the response
+    pub metadata: Option<oneshot::Sender<Metadata>>,
+}
+
+#[cfg(any(
+    all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+    all(feature = "async_std_runtime", not(feature = "tokio_runtime"))
+))]
+/// A serialized request with the extension messages attached by the layers
+pub(crate) struct SentRequest {
+    pub duration: Duration,
+    pub buf: Vec<u8>,
+    pub extensions: Vec<(u32, String)>,
+}
+
+#[cfg(any(
+    all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+    all(feature = "async_std_runtime", not(feature = "tokio_runtime"))
+))]
+impl SentRequest {
+    /// The messages that write the request, the extension messages first
+    fn items(&self, id: MessageId, service_method: &str, sink: bool) -> Vec<ClientWriterItem> {
+        let mut items: Vec<ClientWriterItem> = self
+            .extensions
+            .iter()
+            .map(|(marker, content)| ClientWriterItem::Ext(id, *marker, content.clone()))
+            .collect();
+        let (service_method, buf) = (service_method.to_string(), self.buf.clone());
+        items.push(match sink {
+            true => ClientWriterItem::SinkRequest(id, service_method, self.duration, buf),
+            false => ClientWriterItem::Request(id, service_method, self.duration, buf),
+        });
+        items
+    }
+}
+
+#[cfg(any(
+    all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+    all(feature = "async_std_runtime", not(feature = "tokio_runtime"))
+))]
+/// Writes the messages in order
+async fn send_all<W>(writer: &mut W, items: Vec<ClientWriterItem>) -> Result<(), Error>
+where
+    W: Sink<ClientWriterItem, Error = flume::SendError<ClientWriterItem>> + Send + Unpin,
+{
+    for item in items {
+        writer.send(item).await?;
+    }
+    Ok(())
+}
+
+/// Error of a call from the server as it is sent back. The errors that the protocol
+/// cannot carry are sent as an `ExecutionError`.
+#[cfg(all(
+    feature = "server",
+    any(
+        all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+        all(feature = "async_std_runtime", not(feature = "tokio_runtime"))
+    )
+))]
+fn error_message(err: Error) -> ErrorMessage {
+    use std::convert::TryFrom;
+    ErrorMessage::try_from(err).unwrap_or_else(|err| ErrorMessage::ExecutionError(err.to_string()))
+}
+
+#[cfg(any(
+    all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+    all(feature = "async_std_runtime", not(feature = "tokio_runtime"))
+))]
+/// A streaming call waiting for its items
+pub(crate) struct PendingStream {
+    pub items: flume::Sender<StreamEvent>,
+    pub service_method: String,
+}
+
 #[cfg(any(
     all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
     all(feature = "async_std_runtime", not(feature = "tokio_runtime"))
 ))]
 pub(crate) struct ClientBroker {
     pub count: Arc<AtomicMessageId>,
-    pub pending: HashMap<
-        MessageId,
-        oneshot::Sender<Result<ResponseResult, Error>>,
-    >,
+    pub pending: HashMap<MessageId, PendingCall>,
+    // streaming calls by the id of the request
+    pub streams: HashMap<MessageId, PendingStream>,
     pub next_timeout: Option<Duration>,
-    pub subscriptions: HashMap<String, Sender<Box<InboundBody>>>,
+    pub subscriptions: HashMap<String, Vec<LocalSubscriber>>,
+    // publications waiting to be accepted by the server
+    pub publishes: HashMap<MessageId, PublishAcked>,
+    pub extensions: HashMap<u32, ExtensionHandler>,
+    pub metrics: Option<Arc<dyn MetricsSink>>,
+    pub layers: Vec<Arc<dyn Layer>>,
+    pub tracker: ResponseTracker,
+    pub resilience: Option<Resilience>,
+    pub storm: Option<TimeoutStorm>,
+    pub mirror: Option<Mirror>,
+    pub limits: SizeLimits,
+    pub payload: Arc<PayloadCounters>,
+    // serializes the requests with the codec of the connection
+    pub marshal: fn(&OutboundBody) -> Result<Vec<u8>, Error>,
+    // the connection is closed after a storm of timeouts
+    pub disconnected: bool,
+    // notifies the application once the connection is lost
+    pub lifecycle: Lifecycle,
+    // messages written once the client is resumed
+    pub paused: Option<Vec<ClientBrokerItem>>,
+    pub heartbeat: Option<Heartbeat>,
+    // verifies the signatures of the responses
+    #[cfg(feature = "signing")]
+    pub verifier: Option<Arc<ResponseVerifier>>,
+    // services that the server calls over the connection
+    #[cfg(feature = "server")]
+    pub serving: Option<Serving>,
 }
 
 #[cfg(any(
     all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
     all(feature = "async_std_runtime", not(feature = "tokio_runtime"))
 ))]
-#[async_trait::async_trait]
-impl brw::Broker for ClientBroker {
-    type Item = ClientBrokerItem;
-    type WriterItem = ClientWriterItem;
-    type Ok = ();
-    type Error = Error;
+impl ClientBroker {
+    fn untrack(&mut self, id: MessageId) {
+        if let Some(resilience) = &mut self.resilience {
+            resilience.untrack(id);
+        }
+    }
 
-    async fn op<W>(
+    /// Passes a request through the layers. The metadata of the request is attached
+    /// as the last extension message.
+    fn layer_request(
+        &self,
+        id: MessageId,
+        service_method: String,
+        duration: Duration,
+        metadata: Metadata,
+    ) -> Result<Request, Error> {
+        let mut request = Request::new(id, service_method, duration, metadata);
+        for layer in &self.layers {
+            layer.on_request(&mut request)?;
+        }
+        if !request.metadata.is_empty() {
+            let content = metadata::encode(&request.metadata);
+            request.insert_extension(METADATA_MARKER, content);
+        }
+        Ok(request)
+    }
+
+    /// Passes a response through the layers in the reverse order, and returns the
+    /// messages to write if a layer retries the call
+    fn layer_response(
         &mut self,
-        _: &Arc<Context<Self::Item>>,
-        item: Self::Item,
-        mut writer: W,
-    ) -> Running<Result<Self::Ok, Self::Error>>
+        id: MessageId,
+        is_ok: bool,
+        bytes: usize,
+    ) -> Option<Vec<ClientWriterItem>> {
+        let call = self.pending.get_mut(&id)?;
+        let request = call.request.as_ref()?;
+        let response = Response {
+            id,
+            service_method: call.service_method.clone(),
+            is_ok,
+            bytes,
+            latency: call.started.elapsed(),
+            attempt: call.attempt,
+        };
+        let retry = self
+            .layers
+            .iter()
+            .rev()
+            .any(|layer| layer.on_response(&response) == ResponseAction::Retry);
+        if !retry {
+            return None;
+        }
+        debug!("Retrying call {} after {} attempts", id, call.attempt);
+        call.attempt += 1;
+        Some(request.items(id, &call.service_method, false))
+    }
+
+    /// Serializes the body of a request and checks it against the size limit
+    fn marshal_request(&self, service_method: &str, body: &OutboundBody) -> Result<Vec<u8>, Error> {
+        let buf = (self.marshal)(body)?;
+        self.payload.record_request(service_method, buf.len());
+        self.limits.check_request(service_method, buf.len())?;
+        Ok(buf)
+    }
+
+    /// Counts the bytes of a response and checks them against the size limit
+    fn check_response(&self, service_method: &str, bytes: usize) -> Result<(), Error> {
+        self.payload.record_response(service_method, bytes);
+        self.limits.check_response(service_method, bytes)
+    }
+
+    /// Verifies the signature of a response or of an item of a streaming response,
+    /// if the client has a verifier
+    #[cfg(feature = "signing")]
+    fn verify(&self, item: &ClientBrokerItem) -> Result<(), Error> {
+        let verifier = match &self.verifier {
+            Some(verifier) => verifier,
+            None => return Ok(()),
+        };
+        let (kind, id, is_ok, signature) = match item {
+            ClientBrokerItem::Response {
+                id,
+                result,
+                signature,
+                ..
+            } => (Signed::Response, *id, result.is_ok(), signature),
+            ClientBrokerItem::StreamItem {
+                id,
+                result,
+                signature,
+                ..
+            } => (Signed::StreamItem, *id, result.is_ok(), signature),
+            _ => return Ok(()),
+        };
+        match signature {
+            Some(signature) => {
+                let content = Some(signature.content.as_str());
+                verifier.verify(content, kind, id, is_ok, &signature.body)
+            }
+            None => verifier.verify(None, kind, id, is_ok, &[]),
+        }
+    }
+
+    /// Fails the call of a response whose signature is not valid. The rest of a
+    /// streaming response is canceled.
+    #[cfg(feature = "signing")]
+    async fn reject_unverified<W>(
+        &mut self,
+        item: ClientBrokerItem,
+        err: Error,
+        writer: &mut W,
+    ) -> Result<(), Error>
     where
-        W: Sink<Self::WriterItem, Error = flume::SendError<Self::WriterItem>> + Send + Unpin,
+        W: Sink<ClientWriterItem, Error = flume::SendError<ClientWriterItem>> + Send + Unpin,
+    {
+        let (id, stream_item) = match item {
+            ClientBrokerItem::Response { id, .. } => (id, false),
+            ClientBrokerItem::StreamItem { id, .. } => (id, true),
+            _ => return Ok(()),
+        };
+        warn!("Rejecting message {}: {}", id, err);
+        self.untrack(id);
+        if let Some(stream) = self.streams.remove(&id) {
+            let _ = stream.items.send(StreamEvent::Failed(err));
+            if stream_item {
+                self.tracker.expired(id);
+                return writer
+                    .send(ClientWriterItem::Cancel(id))
+                    .await
+                    .map_err(|err| err.into());
+            }
+        } else if let Some(call) = self.pending.remove(&id) {
+            let _ = call.tx.send(Err(err));
+        } else {
+            self.tracker.unexpected(id);
+            return Ok(());
+        }
+        self.tracker.completed(id);
+        Ok(())
+    }
+
+    /// Lets the clock jump handler decide what to do with each call in flight
+    async fn revalidate<W>(&mut self, jump: Duration, writer: &mut W) -> Result<(), Error>
+    where
+        W: Sink<ClientWriterItem, Error = flume::SendError<ClientWriterItem>> + Send + Unpin,
     {
+        let resilience = match &self.resilience {
+            Some(resilience) => resilience,
+            None => return Ok(()),
+        };
+        let mut failed = Vec::new();
+        for (id, revalidation, request) in resilience.revalidate(jump) {
+            debug!("Revalidating call {}: {:?}", id, revalidation);
+            match revalidation {
+                Revalidation::Keep => {}
+                Revalidation::Resend => {
+                    let buf = (self.marshal)(&*request.body)?;
+                    writer
+                        .send(ClientWriterItem::Request(
+                            id,
+                            request.service_method.clone(),
+                            request.duration,
+                            buf,
+                        ))
+                        .await?;
+                }
+                Revalidation::Fail => failed.push(id),
+            }
+        }
+        for id in failed {
+            self.untrack(id);
+            if let Some(call) = self.pending.remove(&id) {
+                self.tracker.expired(id);
+                let _ = call.tx.send(Err(Error::Timeout(Some(id))));
+            }
+        }
+        Ok(())
+    }
+
+    /// Fails all the calls in flight after a storm of timeouts
+    fn fail_pending(&mut self) {
+        warn!(
+            "Timeout storm detected, failing {} calls in flight",
+            self.pending.len()
+        );
+        let pending: Vec<_> = self.pending.drain().collect();
+        for (id, call) in pending {
+            self.untrack(id);
+            self.tracker.expired(id);
+            let _ = call.tx.send(Err(Error::Timeout(Some(id))));
+        }
+    }
+
+    /// Fails a call or a streaming call with `Error::Canceled`
+    fn cancel_locally(&mut self, id: MessageId) -> Result<(), Error> {
+        self.untrack(id);
+        if self.streams.remove(&id).is_some() {
+            self.tracker.expired(id);
+        }
+        if let Some(call) = self.pending.remove(&id) {
+            self.tracker.expired(id);
+            if call.tx.send(Err(Error::Canceled(Some(id)))).is_err() {
+                return Err(Error::Internal(
+                    format!(
+                        "Unable to send Error::Canceled(Some({})) over response channel",
+                        id
+                    )
+                    .into(),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Queues an item while the client is paused. Canceling a queued call removes it
+    /// from the queue instead.
+    fn queue(&mut self, item: ClientBrokerItem) -> Result<(), Error> {
+        let id = match item {
+            ClientBrokerItem::Cancel(id) => id,
+            item => {
+                if let Some(queue) = &mut self.paused {
+                    queue.push(item);
+                }
+                return Ok(());
+            }
+        };
+        let queued = match &mut self.paused {
+            Some(queue) => {
+                let len = queue.len();
+                queue.retain(|item| {
+                    !matches!(item,
+                        ClientBrokerItem::Request { id: queued, .. }
+                        | ClientBrokerItem::StreamRequest { id: queued, .. } if *queued == id
+                    )
+                });
+                queue.len() < len
+            }
+            None => false,
+        };
+        if queued {
+            // the `Call` is canceled by dropping the sender
+            self.tracker.completed(id);
+            return Ok(());
+        }
+        // the server is told once the client is resumed
+        self.cancel_locally(id)?;
+        if let Some(queue) = &mut self.paused {
+            queue.push(ClientBrokerItem::Cancel(id));
+        }
+        Ok(())
+    }
+
+    /// Lists the calls waiting for their responses, the oldest first
+    fn pending_requests(&self) -> Vec<PendingRequest> {
+        let mut requests: Vec<PendingRequest> = self
+            .pending
+            .iter()
+            .map(|(id, call)| PendingRequest {
+                id: *id,
+                service_method: call.service_method.clone(),
+                age: call.started.elapsed(),
+            })
+            .collect();
+        requests.sort_by_key(|request| std::cmp::Reverse(request.age));
+        requests
+    }
+}
+
+#[cfg(any(
+    all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+    all(feature = "async_std_runtime", not(feature = "tokio_runtime"))
+))]
+impl ClientBroker {
+    /// Handles an item that is not queued while the client is paused
+    async fn handle<W>(
+        &mut self,
+        ctx: &Arc<Context<ClientBrokerItem>>,
+        item: ClientBrokerItem,
+        writer: &mut W,
+    ) -> Running<Result<(), Error>>
+    where
+        W: Sink<ClientWriterItem, Error = flume::SendError<ClientWriterItem>> + Send + Unpin,
+    {
+        #[cfg(feature = "signing")]
+        if let Err(err) = self.verify(&item) {
+            return Running::Continue(self.reject_unverified(item, err, writer).await);
+        }
         let res = match item {
             ClientBrokerItem::Request {
                 id,
@@ -120,7 +730,41 @@ impl brw::Broker for ClientBroker {
                 duration,
                 body,
                 resp_tx,
+                sink,
+                metadata,
+                reply_metadata,
             } => {
+                if self.disconnected {
+                    self.tracker.completed(id);
+                    let err = std::io::Error::new(
+                        std::io::ErrorKind::NotConnected,
+                        "Connection is closed after a storm of timeouts",
+                    );
+                    let _ = resp_tx.send(Err(err.into()));
+                    return Running::Continue(Ok(()));
+                }
+                self.lifecycle.call();
+                let Request {
+                    service_method,
+                    timeout: duration,
+                    extensions,
+                    ..
+                } = match self.layer_request(id, service_method, duration, metadata) {
+                    Ok(request) => request,
+                    Err(err) => {
+                        self.tracker.completed(id);
+                        let _ = resp_tx.send(Err(err));
+                        return Running::Continue(Ok(()));
+                    }
+                };
+                let buf = match self.marshal_request(&service_method, &*body) {
+                    Ok(buf) => buf,
+                    Err(err) => {
+                        self.tracker.completed(id);
+                        let _ = resp_tx.send(Err(err));
+                        return Running::Continue(Ok(()));
+                    }
+                };
                 // fetch_add returns the previous value
                 // let id = self.count.fetch_add(1, Ordering::Relaxed);
                 let (tx, rx) = oneshot::channel();
@@ -131,95 +775,337 @@ impl brw::Broker for ClientBroker {
                         Err(_) => Err(Error::Canceled(Some(id))),
                     }
                 };
-                let request_result = writer
-                    .send(ClientWriterItem::Request(
-                        id,
-                        service_method,
-                        duration,
-                        body,
-                    ))
-                    .await;
+                // the items sent by a `ClientSink` can neither be resent nor mirrored
+                if !sink {
+                    if let Some(resilience) = &mut self.resilience {
+                        resilience.track(id, &service_method, duration, body.clone());
+                    }
+                    if let Some(mirror) = &self.mirror {
+                        mirror.forward(&service_method, duration, body.clone());
+                    }
+                }
+                let timer = self
+                    .metrics
+                    .as_ref()
+                    .map(|sink| CallTimer::start(sink, id, &service_method, buf.len()));
+                let request = SentRequest {
+                    duration,
+                    buf,
+                    extensions,
+                };
+                let request_result =
+                    send_all(writer, request.items(id, &service_method, sink)).await;
+                let call = PendingCall {
+                    tx,
+                    service_method,
+                    started: Instant::now(),
+                    attempt: 1,
+                    request: match sink || self.layers.is_empty() {
+                        true => None,
+                        false => Some(request),
+                    },
+                    metadata: reply_metadata,
+                };
 
+                let broker = ctx.broker.clone();
                 task::spawn(async move {
-                    #[cfg(all(feature = "tokio_runtime", not(feature = "async_std_runtime")))]
-                    let timout_result = ::tokio::time::timeout(duration, fut).await;
-                    #[cfg(all(feature = "async_std_runtime", not(feature = "tokio_runtime")))]
-                    let timout_result = ::async_std::future::timeout(duration, fut).await;
+                    let timout_result = crate::clock::timeout(duration, fut).await;
 
                     let cancellation_result = match timout_result {
                         Ok(res) => res,
                         Err(_) => {
+                            if let Some(timer) = timer {
+                                timer.finish(CallOutcome::Timeout, 0);
+                            }
                             if let Err(_) = resp_tx.send(Err(Error::Timeout(Some(id)))) {
-                                log::trace!("InternalError: Unable to send Error::Timeout(Some({})) over response channel, response receiver is dropped", id);
+                                trace!("InternalError: Unable to send Error::Timeout(Some({})) over response channel, response receiver is dropped", id);
+                            }
+                            if broker.send(ClientBrokerItem::Timeout(id)).is_err() {
+                                trace!(
+                                    "InternalError: Unable to send timeout of {} to client broker",
+                                    id
+                                );
                             }
                             return;
                         }
                     };
                     match cancellation_result {
-                        Ok(res) => {
+                        Ok((res, bytes)) => {
+                            if let Some(timer) = timer {
+                                let outcome = match res {
+                                    Ok(_) => CallOutcome::Ok,
+                                    Err(_) => CallOutcome::Error,
+                                };
+                                timer.finish(outcome, bytes);
+                            }
                             let response_result = Ok(res);
                             resp_tx.send(response_result)
-                                .unwrap_or_else(|_| log::trace!("InternalError: Unable to send RPC response over response channel, response receiver is dropped"));
-                        },
-                        Err(_) => {
-                            // RPC request is already canceled, simply return
-                            return
+                                .unwrap_or_else(|_| trace!("InternalError: Unable to send RPC response over response channel, response receiver is dropped"));
+                        }
+                        Err(err) => {
+                            // RPC request is canceled or failed by the broker
+                            if let Some(timer) = timer {
+                                let outcome = match err {
+                                    Error::Timeout(_) => CallOutcome::Timeout,
+                                    Error::PayloadTooLarge(_) => CallOutcome::Error,
+                                    _ => CallOutcome::Canceled,
+                                };
+                                timer.finish(outcome, 0);
+                            }
+                            resp_tx.send(Err(err))
+                                .unwrap_or_else(|_| trace!("InternalError: Unable to send error over response channel, response receiver is dropped"));
                         }
                     };
                 });
 
-                self.pending.insert(id, tx);
-                request_result.map_err(|err| err.into())
+                self.pending.insert(id, call);
+                request_result
+            }
+            ClientBrokerItem::StreamRequest {
+                id,
+                service_method,
+                duration,
+                body,
+                items,
+                sink,
+            } => {
+                if self.disconnected {
+                    self.tracker.completed(id);
+                    let err = std::io::Error::new(
+                        std::io::ErrorKind::NotConnected,
+                        "Connection is closed after a storm of timeouts",
+                    );
+                    let _ = items.send(StreamEvent::Failed(err.into()));
+                    return Running::Continue(Ok(()));
+                }
+                self.lifecycle.call();
+                let Request {
+                    service_method,
+                    timeout: duration,
+                    extensions,
+                    ..
+                } = match self.layer_request(id, service_method, duration, Metadata::new()) {
+                    Ok(request) => request,
+                    Err(err) => {
+                        self.tracker.completed(id);
+                        let _ = items.send(StreamEvent::Failed(err));
+                        return Running::Continue(Ok(()));
+                    }
+                };
+                let buf = match self.marshal_request(&service_method, &*body) {
+                    Ok(buf) => buf,
+                    Err(err) => {
+                        self.tracker.completed(id);
+                        let _ = items.send(StreamEvent::Failed(err));
+                        return Running::Continue(Ok(()));
+                    }
+                };
+                let stream = PendingStream {
+                    items,
+                    service_method: service_method.clone(),
+                };
+                self.streams.insert(id, stream);
+                let request = SentRequest {
+                    duration,
+                    buf,
+                    extensions,
+                };
+                send_all(writer, request.items(id, &service_method, sink)).await
             }
-            ClientBrokerItem::Response { id, result } => {
-                if let Some(tx) = self.pending.remove(&id) {
-                    tx.send(Ok(result)).map_err(|_| {
-                        Error::Internal(
-                            "InternalError: client failed to send response over channel".into(),
-                        )
-                    })
+            ClientBrokerItem::SinkItem { id, body } => {
+                // the items are dropped once the call has finished
+                if self.pending.contains_key(&id) || self.streams.contains_key(&id) {
+                    writer
+                        .send(ClientWriterItem::SinkItem(id, body))
+                        .await
+                        .map_err(|err| err.into())
                 } else {
-                    Err(Error::Internal(
-                        format!("InternalError: Response channel not found for id: {}", id).into()
-                    ))
+                    Ok(())
+                }
+            }
+            ClientBrokerItem::SinkEnd(id) => {
+                // the server may still be waiting for the end of the argument
+                writer
+                    .send(ClientWriterItem::SinkEnd(id))
+                    .await
+                    .map_err(|err| err.into())
+            }
+            ClientBrokerItem::StreamItem {
+                id, result, bytes, ..
+            } => {
+                let checked = match self.streams.get(&id) {
+                    // the call is canceled if the `CallStream` is dropped
+                    Some(stream) => match self.check_response(&stream.service_method, bytes) {
+                        Ok(_) => {
+                            let _ = stream.items.send(StreamEvent::Item(result));
+                            Ok(())
+                        }
+                        Err(err) => Err(err),
+                    },
+                    None => {
+                        self.tracker.unexpected(id);
+                        Ok(())
+                    }
+                };
+                match checked {
+                    Ok(_) => Ok(()),
+                    // the rest of the stream is canceled
+                    Err(err) => {
+                        if let Some(stream) = self.streams.remove(&id) {
+                            let _ = stream.items.send(StreamEvent::Failed(err));
+                        }
+                        self.tracker.expired(id);
+                        writer
+                            .send(ClientWriterItem::Cancel(id))
+                            .await
+                            .map_err(|err| err.into())
+                    }
+                }
+            }
+            ClientBrokerItem::StreamEnd(id) => {
+                match self.streams.remove(&id) {
+                    Some(stream) => {
+                        let _ = stream.items.send(StreamEvent::End);
+                        self.tracker.completed(id);
+                    }
+                    None => {
+                        self.tracker.unexpected(id);
+                    }
+                }
+                Ok(())
+            }
+            // the method has failed or returned a single response instead of a stream
+            ClientBrokerItem::Response {
+                id, result, bytes, ..
+            } if self.streams.contains_key(&id) => {
+                if let Some(stream) = self.streams.remove(&id) {
+                    match self.check_response(&stream.service_method, bytes) {
+                        Ok(_) => {
+                            let _ = stream.items.send(StreamEvent::Item(result));
+                            let _ = stream.items.send(StreamEvent::End);
+                        }
+                        Err(err) => {
+                            let _ = stream.items.send(StreamEvent::Failed(err));
+                        }
+                    }
                 }
+                self.tracker.completed(id);
+                Ok(())
             }
-            ClientBrokerItem::Publish { topic, body } => {
+            ClientBrokerItem::Response {
+                id, result, bytes, ..
+            } => {
+                if let Some(items) = self.layer_response(id, result.is_ok(), bytes) {
+                    return Running::Continue(send_all(writer, items).await);
+                }
+                self.untrack(id);
+                match self.pending.remove(&id) {
+                    Some(call) => {
+                        let checked = self.check_response(&call.service_method, bytes);
+                        match call.tx.send(checked.map(|_| (result, bytes))) {
+                            Ok(_) => self.tracker.completed(id),
+                            Err(_) => {
+                                // the call has timed out but the broker is not notified yet
+                                self.tracker.expired(id);
+                                self.tracker.unexpected(id);
+                            }
+                        }
+                    }
+                    None => {
+                        self.tracker.unexpected(id);
+                    }
+                }
+                Ok(())
+            }
+            ClientBrokerItem::Timeout(id) => {
+                self.untrack(id);
+                if self.pending.remove(&id).is_some() {
+                    self.tracker.expired(id);
+                }
+                let action = match &mut self.storm {
+                    Some(storm) => storm.record(crate::clock::now()),
+                    None => None,
+                };
+                match action {
+                    Some(StormAction::FailPending) => {
+                        self.fail_pending();
+                        Ok(())
+                    }
+                    Some(StormAction::Disconnect) => {
+                        let in_flight = self.pending.len() + self.streams.len();
+                        self.fail_pending();
+                        // the broker keeps running to fail new calls right away
+                        self.disconnected = true;
+                        self.lifecycle
+                            .disconnected(DisconnectReason::TimeoutStorm, in_flight);
+                        writer
+                            .send(ClientWriterItem::Stop)
+                            .await
+                            .map_err(|err| err.into())
+                    }
+                    None => Ok(()),
+                }
+            }
+            ClientBrokerItem::Publish {
+                topic,
+                body,
+                tag,
+                acked,
+            } => {
                 let id = self.count.fetch_add(1, Ordering::Relaxed);
-                // TODO: QoS check? at least once?
+                let ack = acked.is_some();
+                if let Some(acked) = acked {
+                    self.publishes.insert(id, acked);
+                }
                 let res = writer
-                    .send(ClientWriterItem::Publish(id, topic, body))
+                    .send(ClientWriterItem::Publish(id, topic, body, tag, ack))
                     .await
                     .map_err(|err| err.into());
-
-                // TODO: Spawn a timed task to check Ack?
-                // task::spawn(async move {
-
-                // });
+                if res.is_err() {
+                    // the publisher is notified by dropping the sender
+                    self.publishes.remove(&id);
+                }
                 res
             }
-            ClientBrokerItem::Subscribe { topic, item_sink } => {
+            ClientBrokerItem::Ack(id, mut body) => {
+                match self.publishes.remove(&id) {
+                    Some(acked) => {
+                        let report = erased_serde::deserialize::<PublishReport>(&mut body).ok();
+                        let _ = acked.send(Ok(report));
+                    }
+                    None => debug!("Received Ack of an unknown publication {}", id),
+                }
+                Ok(())
+            }
+            ClientBrokerItem::Subscribe {
+                topic,
+                replay,
+                subscriber,
+            } => {
+                // local subscribers on the same topic share the subscription on the
+                // server, and a new local subscriber does not replay anything
+                if let Some(subscribers) = self.subscriptions.get_mut(&topic) {
+                    subscribers.push(subscriber);
+                    return Running::Continue(Ok(()));
+                }
                 let id = self.count.fetch_add(1, Ordering::Relaxed);
-                // NOTE: Only one local subscriber is allowed
-                self.subscriptions.insert(topic.clone(), item_sink);
+                self.subscriptions.insert(topic.clone(), vec![subscriber]);
 
                 let res = writer
-                    .send(ClientWriterItem::Subscribe(id, topic))
+                    .send(ClientWriterItem::Subscribe(id, topic, replay))
                     .await
                     .map_err(|err| err.into());
                 // TODO: Spawn a timed task to check Ack?
                 res
             }
-            ClientBrokerItem::NewLocalSubscriber {
-                topic,
-                new_item_sink,
-            } => {
-                self.subscriptions.insert(topic, new_item_sink);
+            ClientBrokerItem::NewLocalSubscriber { topic, subscriber } => {
+                self.subscriptions.insert(topic, vec![subscriber]);
                 Ok(())
             }
             ClientBrokerItem::Unsubscribe { topic } => {
                 let id = self.count.fetch_add(1, Ordering::Relaxed);
-                // NOTE: the sender should be dropped on the Client side
+                // dropping the senders ends the local subscribers
+                self.subscriptions.remove(&topic);
                 let res = writer
                     .send(ClientWriterItem::Unsubscribe(id, topic))
                     .await
@@ -227,47 +1113,249 @@ impl brw::Broker for ClientBroker {
                 // TODO: Spawn  timed task to check Ack?
                 res
             }
-            ClientBrokerItem::Subscription { id, topic, item } => {
-                log::info!(
+            ClientBrokerItem::Subscription {
+                id,
+                topic,
+                bytes,
+                from_bytes,
+                tag,
+                ack,
+            } => {
+                info!(
                     "Received subscription message {{id: {}, topic: {}}}",
-                    id,
-                    &topic
+                    id, &topic
                 );
-                if let Some(sub) = self.subscriptions.get(&topic) {
-                    match sub.try_send(item) {
-                        Ok(_) => Ok(()),
-                        Err(err) => match err {
-                            flume::TrySendError::Disconnected(_) => {
-                                self.subscriptions.remove(&topic);
-                                Err(Error::Internal(
-                                    "Subscription recver is Disconnected".into(),
-                                ))
-                            }
-                            _ => Ok(()),
-                        },
+                // the item is delivered to the local subscribers on the topic and on
+                // the patterns that match it
+                let mut found = false;
+                let mut dropped = false;
+                for (key, subscribers) in self.subscriptions.iter_mut() {
+                    if key == &topic || (pubsub::is_pattern(key) && pubsub::matches(key, &topic)) {
+                        found = true;
+                        dropped |= deliver_all(subscribers, &topic, &bytes, from_bytes, tag);
                     }
-                } else {
-                    Err(Error::Internal("Topic is not found locally".into()))
+                }
+                match (found, ack && !dropped) {
+                    (false, _) => Err(Error::Internal("Topic is not found locally".into())),
+                    // the server delivers the item again if it is dropped by a local subscriber
+                    (true, true) => writer
+                        .send(ClientWriterItem::Ack(id))
+                        .await
+                        .map_err(|err| err.into()),
+                    (true, false) => Ok(()),
                 }
             }
-            ClientBrokerItem::Cancel(id) => {
-                if let Some(tx) = self.pending.remove(&id) {
-                    if let Err(_) = tx.send(Err(Error::Canceled(Some(id)))) {
-                        return Running::Continue(
-                            Err(Error::Internal(
-                                format!("Unable to send Error::Canceled(Some({})) over response channel", id).into()
-                            ))
-                        )
+            ClientBrokerItem::Rejected {
+                id,
+                topic,
+                bytes,
+                from_bytes,
+            } if self.publishes.contains_key(&id) => {
+                warn!("Publication is rejected {{id: {}, topic: {}}}", id, &topic);
+                let mut body = from_bytes(bytes);
+                let err = match erased_serde::deserialize::<ErrorMessage>(&mut body) {
+                    Ok(msg) => Error::from_err_msg(msg),
+                    Err(err) => err.into(),
+                };
+                if let Some(acked) = self.publishes.remove(&id) {
+                    let _ = acked.send(Err(err));
+                }
+                Ok(())
+            }
+            ClientBrokerItem::Rejected {
+                id,
+                topic,
+                bytes,
+                from_bytes,
+            } => {
+                warn!("Topic is rejected {{id: {}, topic: {}}}", id, &topic);
+                // the local subscribers end after receiving the rejection
+                if let Some(subscribers) = self.subscriptions.remove(&topic) {
+                    for sub in subscribers {
+                        sub.reject(from_bytes(bytes.clone()));
                     }
                 }
+                Ok(())
+            }
+            ClientBrokerItem::RegisterExtension { marker, handler } => {
+                self.extensions.insert(marker, handler);
+                Ok(())
+            }
+            ClientBrokerItem::Ext { marker, content } => {
+                let id = self.count.fetch_add(1, Ordering::Relaxed);
+                writer
+                    .send(ClientWriterItem::Ext(id, marker, content))
+                    .await
+                    .map_err(|err| err.into())
+            }
+            ClientBrokerItem::ExtReceived {
+                id,
+                marker,
+                content,
+            } if marker == METADATA_MARKER => {
+                // the metadata is sent right before the response
+                let reply = self
+                    .pending
+                    .get_mut(&id)
+                    .and_then(|call| call.metadata.take());
+                if let Some(reply) = reply {
+                    let _ = reply.send(metadata::decode(&content));
+                }
+                Ok(())
+            }
+            ClientBrokerItem::ExtReceived {
+                id,
+                marker,
+                content,
+            } => match self.extensions.get(&marker) {
+                Some(handler) => match handler(content) {
+                    Some(content) => writer
+                        .send(ClientWriterItem::Ext(id, marker, content))
+                        .await
+                        .map_err(|err| err.into()),
+                    None => Ok(()),
+                },
+                None => {
+                    warn!("Extension not found for marker: {}", marker);
+                    Ok(())
+                }
+            },
+            ClientBrokerItem::Cancel(id) => {
+                if let Err(err) = self.cancel_locally(id) {
+                    return Running::Continue(Err(err));
+                }
                 writer
                     .send(ClientWriterItem::Cancel(id))
                     .await
                     .map_err(|err| err.into())
             }
-            ClientBrokerItem::Stop => {
+            ClientBrokerItem::SetUnexpectedResponseHandler { handler } => {
+                self.tracker.set_handler(handler);
+                Ok(())
+            }
+            ClientBrokerItem::SetDisconnectHandler { handler } => {
+                self.lifecycle.set_handler(handler);
+                Ok(())
+            }
+            ClientBrokerItem::SetClockJumpHandler { threshold, handler } => {
+                let (watchdog, registration) = AbortHandle::new_pair();
+                let watch = resilience::watch_clock(threshold, ctx.broker.clone());
+                task::spawn(Abortable::new(watch, registration));
+                // replacing the previous handler stops its watchdog
+                self.resilience = Some(Resilience::new(handler, watchdog));
+                Ok(())
+            }
+            ClientBrokerItem::ClockJump(jump) => self.revalidate(jump, writer).await,
+            ClientBrokerItem::SetMetricsSink { sink } => {
+                self.metrics = Some(sink);
+                Ok(())
+            }
+            ClientBrokerItem::SetLayers { layers } => {
+                self.layers = layers;
+                Ok(())
+            }
+            #[cfg(feature = "signing")]
+            ClientBrokerItem::SetVerifier { verifier } => {
+                self.verifier = Some(verifier);
+                Ok(())
+            }
+            ClientBrokerItem::SetTimeoutStorm { storm } => {
+                self.storm = Some(storm);
+                Ok(())
+            }
+            ClientBrokerItem::SetMirror { mirror } => {
+                self.mirror = Some(mirror);
+                Ok(())
+            }
+            ClientBrokerItem::SetSizeLimit {
+                service_method,
+                limit,
+            } => {
+                self.limits.insert(service_method, limit);
+                Ok(())
+            }
+            ClientBrokerItem::PendingRequests { done } => {
+                let _ = done.send(self.pending_requests());
+                Ok(())
+            }
+            #[cfg(feature = "server")]
+            ClientBrokerItem::Serve { services } => {
+                self.serving = Some(Serving::new(services));
+                Ok(())
+            }
+            #[cfg(feature = "server")]
+            ClientBrokerItem::ServeRequest {
+                id,
+                service_method,
+                duration,
+                deserializer,
+            } => {
+                let started = match &mut self.serving {
+                    Some(serving) => serving.start(
+                        id,
+                        service_method,
+                        duration,
+                        deserializer,
+                        ctx.broker.clone(),
+                    ),
+                    None => Err(Error::ServiceNotFound),
+                };
+                match started {
+                    Ok(_) => Ok(()),
+                    Err(err) => {
+                        warn!("{}", &err);
+                        writer
+                            .send(ClientWriterItem::Response(id, Err(error_message(err))))
+                            .await
+                            .map_err(|err| err.into())
+                    }
+                }
+            }
+            #[cfg(feature = "server")]
+            ClientBrokerItem::ServeCancel(id) => {
+                if let Some(serving) = &mut self.serving {
+                    serving.cancel(id);
+                }
+                Ok(())
+            }
+            #[cfg(feature = "server")]
+            ClientBrokerItem::ServeResponse { id, result } => {
+                // the call may have been canceled by the server
+                if !self
+                    .serving
+                    .as_mut()
+                    .is_some_and(|serving| serving.finish(id))
+                {
+                    return Running::Continue(Ok(()));
+                }
+                let result = result
+                    .and_then(Success::into_reply)
+                    .and_then(|body| (self.marshal)(&*body))
+                    .map_err(error_message);
+                writer
+                    .send(ClientWriterItem::Response(id, result))
+                    .await
+                    .map_err(|err| err.into())
+            }
+            // handled before the items are queued
+            ClientBrokerItem::SetLowPower { .. }
+            | ClientBrokerItem::Heartbeat
+            | ClientBrokerItem::Pause
+            | ClientBrokerItem::Resume => Ok(()),
+            // a connection closed on purpose is not a reason to stop
+            ClientBrokerItem::Closed if self.disconnected => Ok(()),
+            ClientBrokerItem::Closed | ClientBrokerItem::Stop => {
+                if let ClientBrokerItem::Closed = item {
+                    let in_flight = self.pending.len() + self.streams.len();
+                    self.lifecycle
+                        .disconnected(DisconnectReason::Closed, in_flight);
+                }
+                #[cfg(feature = "server")]
+                if let Some(serving) = &mut self.serving {
+                    serving.stop();
+                }
                 if let Err(err) = writer.send(ClientWriterItem::Stop).await {
-                    log::error!("{:?}", err);
+                    error!("{:?}", err);
                 }
                 return Running::Stop;
             }
@@ -276,3 +1364,104 @@ impl brw::Broker for ClientBroker {
         Running::Continue(res)
     }
 }
+
+#[cfg(any(
+    all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+    all(feature = "async_std_runtime", not(feature = "tokio_runtime"))
+))]
+#[async_trait::async_trait]
+impl brw::Broker for ClientBroker {
+    type Item = ClientBrokerItem;
+    type WriterItem = ClientWriterItem;
+    type Ok = ();
+    type Error = Error;
+
+    async fn op<W>(
+        &mut self,
+        ctx: &Arc<Context<Self::Item>>,
+        item: Self::Item,
+        mut writer: W,
+    ) -> Running<Result<Self::Ok, Self::Error>>
+    where
+        W: Sink<Self::WriterItem, Error = flume::SendError<Self::WriterItem>> + Send + Unpin,
+    {
+        let res = match item {
+            ClientBrokerItem::SetLowPower { options } => {
+                let (timer, registration) = AbortHandle::new_pair();
+                let heartbeat = low_power::heartbeat(options, ctx.broker.clone());
+                task::spawn(Abortable::new(heartbeat, registration));
+                // replacing the previous options stops its timer
+                self.heartbeat = Some(Heartbeat::new(timer));
+                Ok(())
+            }
+            ClientBrokerItem::Heartbeat => {
+                // any other message counts as a heartbeat
+                let due = self.heartbeat.as_mut().is_some_and(Heartbeat::is_due);
+                match due && self.paused.is_none() {
+                    true => {
+                        let id = self.count.fetch_add(1, Ordering::Relaxed);
+                        writer
+                            .send(ClientWriterItem::Ext(id, HEARTBEAT_MARKER, String::new()))
+                            .await
+                            .map_err(|err| err.into())
+                    }
+                    false => Ok(()),
+                }
+            }
+            ClientBrokerItem::Pause if self.paused.is_none() => {
+                debug!("Pausing the client");
+                self.paused = Some(Vec::new());
+                // the local subscribers are kept
+                let mut res = Ok(());
+                for topic in self.subscriptions.keys() {
+                    let id = self.count.fetch_add(1, Ordering::Relaxed);
+                    res = writer
+                        .send(ClientWriterItem::Unsubscribe(id, topic.clone()))
+                        .await
+                        .map_err(|err| err.into());
+                    if res.is_err() {
+                        break;
+                    }
+                }
+                res
+            }
+            ClientBrokerItem::Resume => {
+                let queue = match self.paused.take() {
+                    Some(queue) => queue,
+                    None => return Running::Continue(Ok(())),
+                };
+                debug!("Resuming the client with {} queued messages", queue.len());
+                for topic in self.subscriptions.keys() {
+                    let id = self.count.fetch_add(1, Ordering::Relaxed);
+                    if let Err(err) = writer
+                        .send(ClientWriterItem::Subscribe(id, topic.clone(), None))
+                        .await
+                    {
+                        return Running::Continue(Err(err.into()));
+                    }
+                }
+                if let Some(heartbeat) = &mut self.heartbeat {
+                    heartbeat.written();
+                }
+                for item in queue {
+                    match self.handle(ctx, item, &mut writer).await {
+                        Running::Continue(Ok(_)) => {}
+                        Running::Continue(Err(err)) => error!("{:?}", err),
+                        Running::Stop => return Running::Stop,
+                    }
+                }
+                Ok(())
+            }
+            ClientBrokerItem::Pause => Ok(()),
+            item if self.paused.is_some() && item.is_outbound() => self.queue(item),
+            item => {
+                if let (true, Some(heartbeat)) = (item.is_outbound(), &mut self.heartbeat) {
+                    heartbeat.written();
+                }
+                return self.handle(ctx, item, &mut writer).await;
+            }
+        };
+
+        Running::Continue(res)
+    }
+}
@@ -0,0 +1,382 @@
+//! Builder of the options of a client that are set before the first call
+
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::error::Error;
+use crate::{codec::FrameOptions, compression::Compression};
+#[cfg(feature = "server")]
+use crate::{
+    service::{build_service, AsyncServiceMap, HandleService, HandlerResultFut},
+    util::{IntoService, RegisterService},
+};
+
+#[cfg(all(
+    feature = "codec_negotiation",
+    not(feature = "serde_json"),
+    any(feature = "tokio_runtime", feature = "async_std_runtime"),
+))]
+use crate::codec::negotiate::ContentType;
+
+#[cfg(feature = "signing")]
+use crate::signing::ResponseVerifier;
+
+use super::{broker::ClientBrokerItem, layer::Layer, low_power::LowPower, Client};
+
+/// Options of a `Client`, applied to the client once it is connected
+///
+/// The client is connected either with the `dial*` methods of the builder, which
+/// also apply the timeouts of the connection attempt, or with `connect` and any
+/// of the `Client::dial*` functions.
+///
+/// # Example
+///
+/// ```no_run
+/// # #[cfg(any(feature = "async_std_runtime", feature = "tokio_runtime"))]
+/// # {
+/// # use toy_rpc::Client;
+/// # use std::time::Duration;
+/// # use toy_rpc::client::layer::{Layer, Request};
+/// # struct Auth(String);
+/// # impl Layer for Auth {
+/// #     fn on_request(&self, request: &mut Request) -> Result<(), toy_rpc::Error> {
+/// #         request.insert_extension(1, self.0.clone());
+/// #         Ok(())
+/// #     }
+/// # }
+/// # async fn run(addr: &str, token: String) -> Result<(), toy_rpc::Error> {
+/// let client = Client::builder()
+///     .layer(Auth(token))
+///     .connect_timeout(Duration::from_secs(3))
+///     .dial(addr)
+///     .await?;
+/// # Ok(())
+/// # }
+/// # }
+/// ```
+#[derive(Default)]
+pub struct ClientBuilder {
+    layers: Vec<Arc<dyn Layer>>,
+    pub(crate) timeouts: DialTimeouts,
+    pub(crate) frame: FrameOptions,
+    low_power: Option<LowPower>,
+    #[cfg(feature = "signing")]
+    verifier: Option<ResponseVerifier>,
+    #[cfg(all(
+        feature = "codec_negotiation",
+        not(feature = "serde_json"),
+        any(feature = "tokio_runtime", feature = "async_std_runtime"),
+    ))]
+    pub(crate) content_types: Vec<ContentType>,
+    #[cfg(feature = "server")]
+    services: AsyncServiceMap,
+}
+
+/// Timeouts of the steps of a connection attempt. There is no timeout by default,
+/// so a step waits as long as the OS lets it.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct DialTimeouts {
+    pub connect: Option<Duration>,
+    pub tls_handshake: Option<Duration>,
+    pub handshake: Option<Duration>,
+}
+
+#[cfg(any(
+    all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+    all(feature = "async_std_runtime", not(feature = "tokio_runtime"))
+))]
+impl DialTimeouts {
+    /// Waits for the TCP connection, fails with `Error::ConnectTimeout`
+    pub async fn connect<F, T>(&self, fut: F) -> Result<T, Error>
+    where
+        F: Future<Output = std::io::Result<T>>,
+    {
+        within(self.connect, fut, Error::ConnectTimeout)
+            .await?
+            .map_err(Error::from_connect)
+    }
+
+    /// Waits for the TLS handshake, fails with `Error::TlsHandshakeTimeout`
+    #[cfg(feature = "tls")]
+    pub async fn tls_handshake<F, T>(&self, fut: F) -> Result<T, Error>
+    where
+        F: Future<Output = std::io::Result<T>>,
+    {
+        within(self.tls_handshake, fut, Error::TlsHandshakeTimeout)
+            .await?
+            .map_err(|err| Error::TlsHandshake(err.to_string()))
+    }
+
+    /// Waits for the WebSocket or QUIC handshake, fails with `Error::HandshakeTimeout`
+    pub async fn handshake<F: Future>(&self, fut: F) -> Result<F::Output, Error> {
+        within(self.handshake, fut, Error::HandshakeTimeout).await
+    }
+}
+
+#[cfg(any(
+    all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+    all(feature = "async_std_runtime", not(feature = "tokio_runtime"))
+))]
+async fn within<F: Future>(
+    duration: Option<Duration>,
+    fut: F,
+    elapsed: Error,
+) -> Result<F::Output, Error> {
+    match duration {
+        Some(duration) => crate::clock::timeout(duration, fut)
+            .await
+            .map_err(|_| elapsed),
+        None => Ok(fut.await),
+    }
+}
+
+impl ClientBuilder {
+    /// Creates a builder without any option
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a layer that intercepts every RPC call of the client. See the `layer`
+    /// module for details.
+    pub fn layer(self, layer: impl Layer) -> Self {
+        let mut builder = self;
+        builder.layers.push(Arc::new(layer));
+        builder
+    }
+
+    /// Sets the timeout of the TCP connection to the server. A connection attempt that
+    /// takes longer fails with `Error::ConnectTimeout`.
+    pub fn connect_timeout(self, duration: Duration) -> Self {
+        let mut builder = self;
+        builder.timeouts.connect = Some(duration);
+        builder
+    }
+
+    /// Sets the timeout of the TLS handshake once the TCP connection is established. A
+    /// handshake that takes longer fails with `Error::TlsHandshakeTimeout`.
+    pub fn tls_handshake_timeout(self, duration: Duration) -> Self {
+        let mut builder = self;
+        builder.timeouts.tls_handshake = Some(duration);
+        builder
+    }
+
+    /// Sets the timeout of the handshake of the transport protocol, ie. the upgrade of
+    /// a WebSocket connection or the handshake of a QUIC connection. A handshake that
+    /// takes longer fails with `Error::HandshakeTimeout`.
+    pub fn handshake_timeout(self, duration: Duration) -> Self {
+        let mut builder = self;
+        builder.timeouts.handshake = Some(duration);
+        builder
+    }
+
+    /// Compresses the outbound payloads of the connection established by the `dial*`
+    /// methods of the builder. The inbound payloads are decompressed whether or not
+    /// this is set.
+    ///
+    /// This has no effect on WebSocket connections or with the `serde_json` codec. See
+    /// the `compression` module for details.
+    pub fn compression(self, compression: Compression) -> Self {
+        let mut builder = self;
+        builder.frame.compression = Some(compression);
+        builder
+    }
+
+    /// Writes the `Request` and `Response` headers with the fixed encoding instead of
+    /// serde on the connection established by the `dial*` methods of the builder. The
+    /// inbound headers are read with either encoding whether or not this is set.
+    ///
+    /// This is disabled by default, since the servers of an older version can only
+    /// read the serde encoding. It has no effect on WebSocket connections or with the
+    /// `serde_json` codec. See `Header::encode_fixed` for the encoding.
+    pub fn fixed_headers(self, enabled: bool) -> Self {
+        let mut builder = self;
+        builder.frame.fixed_headers = enabled;
+        builder
+    }
+
+    /// Sends heartbeats to keep an idle connection alive, batched with the rest of the
+    /// traffic. See the `low_power` module for details.
+    pub fn low_power(self, options: LowPower) -> Self {
+        let mut builder = self;
+        builder.low_power = Some(options);
+        builder
+    }
+
+    /// Verifies the signatures of the responses with the keys of the trusted servers.
+    /// A response whose signature is missing or not valid fails the call with
+    /// `Error::InvalidSignature`. See the `signing` module for details.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use toy_rpc::Client;
+    /// # use toy_rpc::signing::ResponseVerifier;
+    /// # async fn run(addr: &str, public_key: Vec<u8>) -> Result<(), toy_rpc::Error> {
+    /// let client = Client::builder()
+    ///     .verify_responses(ResponseVerifier::new().ed25519("server-1", &public_key))
+    ///     .dial(addr)
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[cfg(feature = "signing")]
+    #[cfg_attr(feature = "docs", doc(cfg(feature = "signing")))]
+    pub fn verify_responses(self, verifier: ResponseVerifier) -> Self {
+        let mut builder = self;
+        builder.verifier = Some(verifier);
+        builder
+    }
+
+    /// Negotiates the content type of the connection established by `dial` with the
+    /// server, among `content_types` in order of preference, instead of using the
+    /// default codec. Dialing fails with `Error::NoCommonContentType` if the server
+    /// supports none of them. The negotiation is part of the handshake, so it is
+    /// bounded by `handshake_timeout`. See the `codec::negotiate` module for details.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use toy_rpc::Client;
+    /// # use toy_rpc::codec::negotiate::ContentType;
+    /// # async fn run(addr: &str) -> Result<(), toy_rpc::Error> {
+    /// let client = Client::builder()
+    ///     .content_types(vec![ContentType::MessagePack, ContentType::Json])
+    ///     .dial(addr)
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[cfg(all(
+        feature = "codec_negotiation",
+        not(feature = "serde_json"),
+        any(feature = "tokio_runtime", feature = "async_std_runtime"),
+    ))]
+    #[cfg_attr(
+        feature = "docs",
+        doc(cfg(all(feature = "codec_negotiation", not(feature = "serde_json"))))
+    )]
+    pub fn content_types(self, content_types: impl IntoIterator<Item = ContentType>) -> Self {
+        let mut builder = self;
+        builder.content_types = content_types.into_iter().collect();
+        builder
+    }
+
+    /// Serves a service with its default name to the server over the connection of
+    /// the client. See the `duplex` module for details.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # #[cfg(any(feature = "async_std_runtime", feature = "tokio_runtime"))]
+    /// # {
+    /// # use toy_rpc::Client;
+    /// # use toy_rpc::macros::export_impl;
+    /// # struct Agent;
+    /// # impl Agent {
+    /// #     fn new() -> Self {
+    /// #         Agent
+    /// #     }
+    /// # }
+    /// # #[export_impl]
+    /// # impl Agent {
+    /// #     #[export_method]
+    /// #     async fn status(&self, args: ()) -> Result<String, String> {
+    /// #         Ok("ok".into())
+    /// #     }
+    /// # }
+    /// # async fn run(addr: &str) -> Result<(), toy_rpc::Error> {
+    /// let client = Client::builder()
+    ///     .serve(Agent::new())
+    ///     .connect(Client::dial(addr))
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// # }
+    /// ```
+    #[cfg(feature = "server")]
+    #[cfg_attr(feature = "docs", doc(cfg(feature = "server")))]
+    pub fn serve<S>(self, service: S) -> Self
+    where
+        S: IntoService,
+    {
+        self.serve_with_name(S::Service::default_name(), service)
+    }
+
+    /// Serves a service with a name to the server over the connection of the client
+    #[cfg(feature = "server")]
+    #[cfg_attr(feature = "docs", doc(cfg(feature = "server")))]
+    pub fn serve_with_name<S>(self, name: &'static str, service: S) -> Self
+    where
+        S: IntoService,
+    {
+        let service = build_service(service.into_service(), S::Service::handlers());
+        let call = move |method_name: String,
+                         deserializer: Box<dyn erased_serde::Deserializer<'static> + Send>|
+              -> HandlerResultFut { service.call(&method_name, deserializer) };
+        let mut builder = self;
+        builder.services.insert(name, Arc::new(call));
+        builder
+    }
+
+    /// Waits for a client to connect with any of the `Client::dial*` functions, and
+    /// applies the options to it
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # #[cfg(any(feature = "async_std_runtime", feature = "tokio_runtime"))]
+    /// # {
+    /// # use toy_rpc::Client;
+    /// # use toy_rpc::client::layer::{Layer, Request};
+    /// # struct Auth(String);
+    /// # impl Layer for Auth {
+    /// #     fn on_request(&self, request: &mut Request) -> Result<(), toy_rpc::Error> {
+    /// #         request.insert_extension(1, self.0.clone());
+    /// #         Ok(())
+    /// #     }
+    /// # }
+    /// # async fn run(token: String) -> Result<(), toy_rpc::Error> {
+    /// let client = Client::builder()
+    ///     .layer(Auth(token))
+    ///     .connect(Client::dial_websocket("ws://127.0.0.1:8080"))
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// # }
+    /// ```
+    pub async fn connect<F>(self, client: F) -> Result<Client, Error>
+    where
+        F: Future<Output = Result<Client, Error>>,
+    {
+        self.build(client.await?)
+    }
+
+    /// Applies the options to a client that is just created, eg. with
+    /// `Client::with_stream`
+    pub fn build(self, client: Client) -> Result<Client, Error> {
+        if !self.layers.is_empty() {
+            client.broker.send(ClientBrokerItem::SetLayers {
+                layers: self.layers,
+            })?;
+        }
+        #[cfg(feature = "signing")]
+        if let Some(verifier) = self.verifier {
+            client.broker.send(ClientBrokerItem::SetVerifier {
+                verifier: Arc::new(verifier),
+            })?;
+        }
+        if let Some(options) = self.low_power {
+            client
+                .broker
+                .send(ClientBrokerItem::SetLowPower { options })?;
+        }
+        #[cfg(feature = "server")]
+        if !self.services.is_empty() {
+            client.broker.send(ClientBrokerItem::Serve {
+                services: Arc::new(self.services),
+            })?;
+        }
+        Ok(client)
+    }
+}
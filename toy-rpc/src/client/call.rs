@@ -10,10 +10,14 @@ use flume::Sender;
 use futures::{channel::oneshot, Future};
 use serde::de::DeserializeOwned;
 
-use crate::{message::MessageId, protocol::InboundBody, Error};
+use crate::{message::MessageId, metadata::Metadata, protocol::InboundBody, Error};
 
 use super::{broker, ResponseResult};
 
+#[cfg_attr(
+    all(not(feature = "tokio_runtime"), not(feature = "async_std_runtime")),
+    allow(dead_code)
+)]
 enum CallStatus {
     Pending,
     Canceled,
@@ -30,16 +34,23 @@ enum CallStatus {
 ///
 /// # Example
 ///
-/// ```rust
+/// ```no_run
+/// # #[cfg(any(feature = "async_std_runtime", feature = "tokio_runtime"))]
+/// # {
+/// # use toy_rpc::Client;
+/// # use toy_rpc::client::Call;
+/// # async fn run(client: Client) {
 /// // `.await` to wait for the response
 /// let call: Call<i32> = client.call("Arith.add", (1i32, 6i32));
 /// let result = call.await;
 ///
 /// // cancel the call regardless of whether the response is received or not
-/// let call: Call<()> = client.call("Arith.infinite_loop", ());
+/// let mut call: Call<()> = client.call("Arith.infinite_loop", ());
 /// call.cancel();
 /// // You can still .await on the canceled `Call` but will get an error
 /// let result = call.await; // Err(Error::Canceled(Some(id)))
+/// # }
+/// # }
 /// ```
 #[pin_project::pin_project(PinnedDrop)]
 pub struct Call<Res: DeserializeOwned> {
@@ -52,17 +63,21 @@ pub struct Call<Res: DeserializeOwned> {
 }
 
 impl<Res: DeserializeOwned> Call<Res> {
+    #[cfg(any(
+        all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+        all(feature = "async_std_runtime", not(feature = "tokio_runtime"))
+    ))]
     pub(crate) fn new(
-        id: MessageId, 
-        cancel: Sender<broker::ClientBrokerItem>, 
+        id: MessageId,
+        cancel: Sender<broker::ClientBrokerItem>,
         done: oneshot::Receiver<Result<ResponseResult, Error>>,
     ) -> Self {
         Self {
-            status: CallStatus::Pending, 
+            status: CallStatus::Pending,
             id,
             cancel,
             done,
-            marker: PhantomData
+            marker: PhantomData,
         }
     }
 }
@@ -73,7 +88,7 @@ impl<Res: DeserializeOwned> PinnedDrop for Call<Res> {
         let this = self.project();
         if let CallStatus::Pending = this.status {
             if let Err(_) = this.cancel.send(broker::ClientBrokerItem::Cancel(*this.id)) {
-                log::error!("Failed to send cancellation message to client broker");
+                error!("Failed to send cancellation message to client broker");
             }
         }
         *this.status = CallStatus::Dropped;
@@ -83,10 +98,19 @@ impl<Res: DeserializeOwned> PinnedDrop for Call<Res> {
 impl<Res: DeserializeOwned> Call<Res> {
     /// Cancel the RPC call
     ///
+    /// A pending call is removed from the client and `Header::Cancel` is sent to the
+    /// server, which aborts the execution. Calling `cancel()` more than once, or after
+    /// the response is received, has no effect.
     pub fn cancel(&mut self) {
-        self.status = CallStatus::Canceled;
-        if let Err(_) = self.cancel.send(broker::ClientBrokerItem::Cancel(self.id)) {
-            log::error!("Failed to send cancellation message to client broker");
+        if let CallStatus::Pending = self.status {
+            self.status = CallStatus::Canceled;
+            if self
+                .cancel
+                .send(broker::ClientBrokerItem::Cancel(self.id))
+                .is_err()
+            {
+                error!("Failed to send cancellation message to client broker");
+            }
         }
     }
 
@@ -111,24 +135,20 @@ where
         > = this.done;
 
         match done.poll(cx) {
-            Poll::Pending => {
-                match this.status {
-                    CallStatus::Canceled | CallStatus::Dropped => {
-                        Poll::Ready(Err(Error::Canceled(Some(*this.id))))
-                    },
-                    _ => {
-                        Poll::Pending
-                    }
+            Poll::Pending => match this.status {
+                CallStatus::Canceled | CallStatus::Dropped => {
+                    Poll::Ready(Err(Error::Canceled(Some(*this.id))))
                 }
+                _ => Poll::Pending,
             },
             Poll::Ready(res) => {
                 match this.status {
                     CallStatus::Canceled | CallStatus::Dropped => {
                         return Poll::Ready(Err(Error::Canceled(Some(*this.id))))
-                    },
-                    _ => { }
+                    }
+                    _ => {}
                 }
-                
+
                 let res = match res {
                     Ok(val) => val,
                     Err(_canceled) => return Poll::Ready(Err(Error::Canceled(Some(*this.id)))),
@@ -137,14 +157,7 @@ where
                     Ok(val) => val,
                     Err(err) => return Poll::Ready(Err(err)),
                 };
-                let res = match res {
-                    Ok(mut resp_body) => erased_serde::deserialize(&mut resp_body)
-                        .map_err(|err| Error::ParseError(Box::new(err))),
-                    Err(mut err_body) => erased_serde::deserialize(&mut err_body).map_or_else(
-                        |err| Err(Error::ParseError(Box::new(err))),
-                        |msg| Err(Error::from_err_msg(msg)),
-                    ),
-                };
+                let res = deserialize_response(res);
 
                 *this.status = CallStatus::Received;
                 Poll::Ready(res)
@@ -152,3 +165,81 @@ where
         }
     }
 }
+
+/// Deserializes the content of a response, or the error if the response is an error
+pub(crate) fn deserialize_response<Res: DeserializeOwned>(
+    res: ResponseResult,
+) -> Result<Res, Error> {
+    match res {
+        Ok(mut resp_body) => erased_serde::deserialize(&mut resp_body)
+            .map_err(|err| Error::ParseError(Box::new(err))),
+        Err(mut err_body) => erased_serde::deserialize(&mut err_body).map_or_else(
+            |err| Err(Error::ParseError(Box::new(err))),
+            |msg| Err(Error::from_err_msg(msg)),
+        ),
+    }
+}
+
+/// Call of a RPC request made with `Client::call_with_metadata`. `.await`ing on the
+/// `CallWithMetadata<Res>` yields a `Result<(Res, Metadata), toy_rpc::Error>` with the
+/// metadata of the response, which is empty if the server did not attach any. Like a
+/// `Call`, the call is canceled if it is dropped before the value is consumed.
+#[pin_project::pin_project]
+pub struct CallWithMetadata<Res: DeserializeOwned> {
+    #[pin]
+    call: Call<Res>,
+    #[pin]
+    metadata: oneshot::Receiver<Metadata>,
+    reply: Option<Res>,
+}
+
+impl<Res: DeserializeOwned> CallWithMetadata<Res> {
+    #[cfg(any(
+        all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+        all(feature = "async_std_runtime", not(feature = "tokio_runtime"))
+    ))]
+    pub(crate) fn new(call: Call<Res>, metadata: oneshot::Receiver<Metadata>) -> Self {
+        Self {
+            call,
+            metadata,
+            reply: None,
+        }
+    }
+
+    /// Cancel the RPC call. See `Call::cancel`.
+    pub fn cancel(&mut self) {
+        self.call.cancel()
+    }
+
+    /// Gets the ID number of the call
+    pub fn get_id(&self) -> MessageId {
+        self.call.get_id()
+    }
+}
+
+impl<Res: DeserializeOwned> Future for CallWithMetadata<Res> {
+    type Output = Result<(Res, Metadata), Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        if this.reply.is_none() {
+            match this.call.poll(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+                Poll::Ready(Ok(reply)) => *this.reply = Some(reply),
+            }
+        }
+        // the metadata is read before the response, otherwise its sender is dropped
+        // along with the call
+        let metadata = match this.metadata.poll(cx) {
+            Poll::Pending => return Poll::Pending,
+            Poll::Ready(metadata) => metadata.unwrap_or_default(),
+        };
+        match this.reply.take() {
+            Some(reply) => Poll::Ready(Ok((reply, metadata))),
+            None => Poll::Ready(Err(Error::Internal(
+                "CallWithMetadata is polled after completion".into(),
+            ))),
+        }
+    }
+}
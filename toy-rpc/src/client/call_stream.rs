@@ -0,0 +1,151 @@
+//! Streaming RPC call
+
+use std::{
+    marker::PhantomData,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+#[cfg(any(
+    all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+    all(feature = "async_std_runtime", not(feature = "tokio_runtime"))
+))]
+use flume::Receiver;
+use flume::{r#async::RecvStream, Sender};
+use futures::{ready, Stream};
+use serde::de::DeserializeOwned;
+
+use crate::{message::MessageId, Error};
+
+use super::{broker, call::deserialize_response, ResponseResult};
+
+/// What the broker delivers to a `CallStream`
+#[cfg_attr(
+    all(not(feature = "tokio_runtime"), not(feature = "async_std_runtime")),
+    allow(dead_code)
+)]
+pub(crate) enum StreamEvent {
+    /// An item of the response
+    Item(ResponseResult),
+    /// The call failed before the server responded
+    Failed(Error),
+    /// The server has sent all the items
+    End,
+}
+
+/// Call of a RPC method that returns a `RpcStream`. The items are obtained by
+/// polling the `CallStream` as a `Stream` of `Result<Res, toy_rpc::Error>`.
+///
+/// If the method fails before returning the stream, the error is the only item.
+/// If the connection is closed before the end of the stream, the last item is
+/// `Err(Error::Canceled(Some(id)))`. Dropping the `CallStream` before the end of
+/// the stream cancels the call.
+///
+/// # Example
+///
+/// ```no_run
+/// # #[cfg(any(feature = "async_std_runtime", feature = "tokio_runtime"))]
+/// # {
+/// # use toy_rpc::Client;
+/// # use futures::StreamExt;
+/// # use toy_rpc::client::CallStream;
+/// # async fn run(client: Client) -> Result<(), Box<dyn std::error::Error>> {
+/// let mut readings: CallStream<f64> = client.call_stream("Sensor.readings", 10usize);
+/// while let Some(reading) = readings.next().await {
+///     println!("{}", reading?);
+/// }
+/// # Ok(())
+/// # }
+/// # }
+/// ```
+#[pin_project::pin_project(PinnedDrop)]
+pub struct CallStream<Res: DeserializeOwned> {
+    id: MessageId,
+    cancel: Sender<broker::ClientBrokerItem>,
+    #[pin]
+    items: RecvStream<'static, StreamEvent>,
+    done: bool,
+    marker: PhantomData<Res>,
+}
+
+impl<Res: DeserializeOwned> CallStream<Res> {
+    #[cfg(any(
+        all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+        all(feature = "async_std_runtime", not(feature = "tokio_runtime"))
+    ))]
+    pub(crate) fn new(
+        id: MessageId,
+        cancel: Sender<broker::ClientBrokerItem>,
+        items: Receiver<StreamEvent>,
+    ) -> Self {
+        Self {
+            id,
+            cancel,
+            items: items.into_stream(),
+            done: false,
+            marker: PhantomData,
+        }
+    }
+
+    /// Cancels the call. No more items are yielded afterwards.
+    pub fn cancel(&mut self) {
+        if !self.done {
+            self.done = true;
+            if self
+                .cancel
+                .send(broker::ClientBrokerItem::Cancel(self.id))
+                .is_err()
+            {
+                error!("Failed to send cancellation message to client broker");
+            }
+        }
+    }
+
+    /// Gets the ID number of the call
+    pub fn get_id(&self) -> MessageId {
+        self.id
+    }
+}
+
+#[pin_project::pinned_drop]
+impl<Res: DeserializeOwned> PinnedDrop for CallStream<Res> {
+    fn drop(self: Pin<&mut Self>) {
+        let this = self.project();
+        if !*this.done
+            && this
+                .cancel
+                .send(broker::ClientBrokerItem::Cancel(*this.id))
+                .is_err()
+        {
+            error!("Failed to send cancellation message to client broker");
+        }
+    }
+}
+
+impl<Res: DeserializeOwned> Stream for CallStream<Res> {
+    type Item = Result<Res, Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.project();
+        if *this.done {
+            return Poll::Ready(None);
+        }
+        let item = match ready!(this.items.poll_next(cx)) {
+            Some(StreamEvent::Item(res)) => Some(deserialize_response(res)),
+            Some(StreamEvent::Failed(err)) => {
+                *this.done = true;
+                Some(Err(err))
+            }
+            Some(StreamEvent::End) => {
+                *this.done = true;
+                None
+            }
+            // the broker is stopped before the end of the stream
+            None => {
+                *this.done = true;
+                Some(Err(Error::Canceled(Some(*this.id))))
+            }
+        };
+        Poll::Ready(item)
+    }
+}
@@ -0,0 +1,133 @@
+//! Notification of a lost connection
+//!
+//! A handler registered with `Client::on_disconnect` is called once when the
+//! connection of the client is lost, either because it is closed by the server or
+//! the transport, or because the client closes it after a storm of timeouts (see the
+//! `storm` module). The handler is not called when the client is closed with
+//! `Client::close` or dropped.
+//!
+//! A client never reconnects by itself. The handler is where the application learns
+//! that it has to dial the server again with a new `Client`, and since it is called
+//! from the task of the client, it should hand the reconnection over to another
+//! task rather than block.
+//!
+//! # Example
+//!
+//! ```no_run
+//! # #[cfg(any(feature = "async_std_runtime", feature = "tokio_runtime"))]
+//! # {
+//! # use toy_rpc::Client;
+//! # use toy_rpc::client::disconnect::DisconnectInfo;
+//! # async fn run(client: Client, addr: &str) -> Result<(), Box<dyn std::error::Error>> {
+//! let (lost_tx, lost_rx) = flume::bounded(1);
+//! client.on_disconnect(move |info: &DisconnectInfo| {
+//!     log::warn!("Connection lost after {:?}: {:?}", info.duration, info.reason);
+//!     let _ = lost_tx.try_send(());
+//! })?;
+//!
+//! lost_rx.recv_async().await?;
+//! let client = Client::dial(addr).await?;
+//! # Ok(())
+//! # }
+//! # }
+//! ```
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::clock;
+
+/// Why the connection of a client is lost
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisconnectReason {
+    /// The connection is closed by the server or the transport
+    Closed,
+    /// The client closed the connection after a storm of timeouts
+    TimeoutStorm,
+}
+
+/// A connection that is lost
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DisconnectInfo {
+    /// Why the connection is lost
+    pub reason: DisconnectReason,
+    /// Time since the client was created
+    pub duration: Duration,
+    /// Number of calls made on the connection
+    pub calls: u64,
+    /// Number of calls that were waiting for their responses
+    pub in_flight: usize,
+}
+
+/// Handler of a lost connection
+pub type DisconnectHandler = Arc<dyn Fn(&DisconnectInfo) + Send + Sync + 'static>;
+
+/// State of the connection kept by the broker
+pub(crate) struct Lifecycle {
+    handler: Option<DisconnectHandler>,
+    connected_at: Instant,
+    calls: u64,
+}
+
+#[cfg_attr(
+    all(not(feature = "tokio_runtime"), not(feature = "async_std_runtime")),
+    allow(dead_code)
+)]
+impl Lifecycle {
+    pub fn new() -> Self {
+        Self {
+            handler: None,
+            connected_at: clock::now(),
+            calls: 0,
+        }
+    }
+
+    pub fn set_handler(&mut self, handler: DisconnectHandler) {
+        self.handler = Some(handler);
+    }
+
+    pub fn call(&mut self) {
+        self.calls += 1;
+    }
+
+    /// Calls the handler, at most once
+    pub fn disconnected(&mut self, reason: DisconnectReason, in_flight: usize) {
+        if let Some(handler) = self.handler.take() {
+            let info = DisconnectInfo {
+                reason,
+                duration: clock::now().saturating_duration_since(self.connected_at),
+                calls: self.calls,
+                in_flight,
+            };
+            handler(&info);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[test]
+    fn handler_is_called_once() {
+        let lost = Arc::new(Mutex::new(Vec::new()));
+        let mut lifecycle = Lifecycle::new();
+        lifecycle.disconnected(DisconnectReason::Closed, 0);
+
+        let handler_lost = lost.clone();
+        lifecycle.set_handler(Arc::new(move |info: &DisconnectInfo| {
+            handler_lost.lock().unwrap().push(*info)
+        }));
+        lifecycle.call();
+        lifecycle.call();
+        lifecycle.disconnected(DisconnectReason::TimeoutStorm, 1);
+        lifecycle.disconnected(DisconnectReason::Closed, 0);
+
+        let lost = lost.lock().unwrap();
+        assert_eq!(lost.len(), 1);
+        assert_eq!(lost[0].reason, DisconnectReason::TimeoutStorm);
+        assert_eq!(lost[0].calls, 2);
+        assert_eq!(lost[0].in_flight, 1);
+    }
+}
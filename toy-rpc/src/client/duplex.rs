@@ -0,0 +1,139 @@
+//! Services served by a client over its own connection
+//!
+//! A client registers services with `ClientBuilder::serve`, which the server then
+//! calls over the connection the client dialed out, so an agent behind a NAT or a
+//! firewall receives calls without accepting any inbound connection. The server
+//! makes the calls with the `Peer` of the connection, see the `server::peer` module.
+//!
+//! Each call from the server runs in its own task with the timeout of the request,
+//! and is aborted if the server cancels it or once the connection is closed. A
+//! method exported with `#[export_method(blocking)]` runs like the other methods,
+//! and the methods returning a stream are not supported.
+//!
+//! # Example
+//!
+//! ```no_run
+//! # use toy_rpc::Client;
+//! # use toy_rpc::macros::export_impl;
+//! # struct Agent;
+//! # impl Agent {
+//! #     fn new() -> Self {
+//! #         Agent
+//! #     }
+//! # }
+//! # #[export_impl]
+//! # impl Agent {
+//! #     #[export_method]
+//! #     async fn status(&self, args: ()) -> Result<String, String> {
+//! #         Ok("ok".into())
+//! #     }
+//! # }
+//! # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+//! let client = Client::builder()
+//!     .serve(Agent::new())
+//!     .connect(Client::dial("hub.example.com:23333"))
+//!     .await?;
+//! // the hub calls `Agent` through the `Peer` from `Context::peer`
+//! client.call::<_, ()>("Hub.register", "agent-1".to_string()).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use flume::Sender;
+use futures::future::{AbortHandle, Abortable};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::error::Error;
+use crate::message::MessageId;
+use crate::metadata::{Context, Metadata};
+use crate::protocol::InboundBody;
+use crate::server::broker::{execute_call, start_call};
+use crate::service::{ArcAsyncServiceCall, AsyncServiceMap};
+
+use super::broker::ClientBrokerItem;
+
+#[cfg(all(feature = "async_std_runtime", not(feature = "tokio_runtime")))]
+use ::async_std::task;
+#[cfg(all(feature = "tokio_runtime", not(feature = "async_std_runtime")))]
+use ::tokio::task;
+
+/// The services of the client and the calls from the server in flight
+pub(crate) struct Serving {
+    services: Arc<AsyncServiceMap>,
+    executions: HashMap<MessageId, AbortHandle>,
+}
+
+impl Serving {
+    pub fn new(services: Arc<AsyncServiceMap>) -> Self {
+        Self {
+            services,
+            executions: HashMap::new(),
+        }
+    }
+
+    /// Starts a call from the server in a new task, which sends the result back to
+    /// the broker as `ClientBrokerItem::ServeResponse`. Returns an error right away
+    /// if the method is not found.
+    pub fn start(
+        &mut self,
+        id: MessageId,
+        service_method: String,
+        duration: Duration,
+        deserializer: Box<InboundBody>,
+        broker: Sender<ClientBrokerItem>,
+    ) -> Result<(), Error> {
+        let (call, method) = get_service(&self.services, &service_method)?;
+        let context = Context::new(id, service_method, Metadata::new());
+        let fut = start_call(&call, method, deserializer, &context);
+        let (handle, registration) = AbortHandle::new_pair();
+        let execution = async move {
+            let result = crate::clock::timeout(duration, execute_call(id, fut))
+                .await
+                .unwrap_or(Err(Error::Timeout(Some(id))));
+            // the connection may have been closed already
+            let _ = broker
+                .send_async(ClientBrokerItem::ServeResponse { id, result })
+                .await;
+        };
+        task::spawn(Abortable::new(execution, registration));
+        self.executions.insert(id, handle);
+        Ok(())
+    }
+
+    /// Removes a call that has finished, returns whether it was not canceled
+    pub fn finish(&mut self, id: MessageId) -> bool {
+        self.executions.remove(&id).is_some()
+    }
+
+    /// Aborts a call canceled by the server
+    pub fn cancel(&mut self, id: MessageId) {
+        if let Some(handle) = self.executions.remove(&id) {
+            handle.abort();
+        }
+    }
+
+    /// Aborts the calls in flight once the connection is closed
+    pub fn stop(&mut self) {
+        for (_, handle) in self.executions.drain() {
+            debug!("Stopping execution as the connection is closed");
+            handle.abort();
+        }
+    }
+}
+
+fn get_service(
+    services: &AsyncServiceMap,
+    service_method: &str,
+) -> Result<(ArcAsyncServiceCall, String), Error> {
+    let args: Vec<&str> = service_method.split('.').collect();
+    let (service, method) = match args[..] {
+        [s, m] => (s, m),
+        _ => return Err(Error::MethodNotFound),
+    };
+    match services.get(service) {
+        Some(call) => Ok((call.clone(), method.to_string())),
+        None => Err(Error::ServiceNotFound),
+    }
+}
@@ -0,0 +1,167 @@
+//! Interceptors of the RPC calls made by a client
+//!
+//! A `Layer` added with `ClientBuilder::layer` sees every RPC call of the client. Its
+//! `on_request` is called before the request is written, and its `on_response` after
+//! the response is read. The layers are called in the order they are added for the
+//! requests and in the reverse order for the responses, like the middlewares of a
+//! server.
+//!
+//! A layer may
+//!
+//! - rename the method or change the timeout of a call,
+//! - fail a call before it is sent by returning an error from `on_request`,
+//! - attach key-value metadata, like the id of a trace, to a call with
+//!   `Request::metadata`. See the `metadata` module,
+//! - attach metadata, like an authentication token, to a call with
+//!   `Request::insert_extension`. The metadata is written as a `Header::Ext` message
+//!   with the id of the call right before the request, and is read on the server
+//!   with a handler registered with `ServerBuilder::register_extension`,
+//! - record the latency and the outcome of a call in `on_response`,
+//! - send a call again by returning `ResponseAction::Retry` from `on_response`. The
+//!   request is written again with the same id and metadata, and the timeout of the
+//!   call is not restarted.
+//!
+//! `on_response` is only called for the responses of the calls made with
+//! `Client::call` and alike. The calls that time out or are canceled, the streaming
+//! calls and the calls whose argument is sent by a `ClientSink` do not get one, and
+//! the latter two cannot be retried.
+//!
+//! # Example
+//!
+//! ```no_run
+//! # #[cfg(any(feature = "async_std_runtime", feature = "tokio_runtime"))]
+//! # {
+//! use toy_rpc::client::layer::{Layer, Request, Response, ResponseAction};
+//! # use toy_rpc::{Client, Error};
+//! # const AUTH_MARKER: u32 = 1;
+//!
+//! struct Auth(String);
+//!
+//! impl Layer for Auth {
+//!     fn on_request(&self, request: &mut Request) -> Result<(), Error> {
+//!         request.insert_extension(AUTH_MARKER, self.0.clone());
+//!         Ok(())
+//!     }
+//! }
+//!
+//! struct RetryOnError;
+//!
+//! impl Layer for RetryOnError {
+//!     fn on_response(&self, response: &Response) -> ResponseAction {
+//!         match response.is_ok || response.attempt >= 3 {
+//!             true => ResponseAction::Return,
+//!             false => ResponseAction::Retry,
+//!         }
+//!     }
+//! }
+//!
+//! # async fn run(addr: &str, token: String) -> Result<(), Error> {
+//! let client = Client::builder()
+//!     .layer(Auth(token))
+//!     .layer(RetryOnError)
+//!     .connect(Client::dial(addr))
+//!     .await?;
+//! # Ok(())
+//! # }
+//! # }
+//! ```
+
+use std::time::Duration;
+
+use crate::{error::Error, message::MessageId, metadata::Metadata};
+
+/// Interceptor of the RPC calls made by a client. See the module documentation.
+///
+/// The methods are called from the task handling the connection, so an
+/// implementation should avoid blocking.
+pub trait Layer: Send + Sync + 'static {
+    /// Called before the request is written. Returning an error fails the call
+    /// without sending it.
+    fn on_request(&self, request: &mut Request) -> Result<(), Error> {
+        let _ = request;
+        Ok(())
+    }
+
+    /// Called after the response is read
+    fn on_response(&self, response: &Response) -> ResponseAction {
+        let _ = response;
+        ResponseAction::Return
+    }
+}
+
+/// A request about to be written
+#[derive(Debug, Clone)]
+pub struct Request {
+    id: MessageId,
+    /// Name of the service and method, eg. `"Arith.add"`
+    pub service_method: String,
+    /// Timeout of the call
+    pub timeout: Duration,
+    /// Metadata of the call, including the metadata passed to
+    /// `Client::call_with_metadata`
+    pub metadata: Metadata,
+    pub(crate) extensions: Vec<(u32, String)>,
+}
+
+impl Request {
+    #[cfg(any(
+        all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+        all(feature = "async_std_runtime", not(feature = "tokio_runtime"))
+    ))]
+    pub(crate) fn new(
+        id: MessageId,
+        service_method: String,
+        timeout: Duration,
+        metadata: Metadata,
+    ) -> Self {
+        Self {
+            id,
+            service_method,
+            timeout,
+            metadata,
+            extensions: Vec::new(),
+        }
+    }
+
+    /// ID of the call
+    pub fn id(&self) -> MessageId {
+        self.id
+    }
+
+    /// Attaches a protocol extension message to the call, which is written before
+    /// the request. See the `extension` module.
+    pub fn insert_extension(&mut self, marker: u32, content: impl ToString) {
+        self.extensions.push((marker, content.to_string()));
+    }
+
+    /// The protocol extension messages attached to the call
+    pub fn extensions(&self) -> &[(u32, String)] {
+        &self.extensions
+    }
+}
+
+/// A response that has just been read
+#[derive(Debug, Clone)]
+pub struct Response {
+    /// ID of the call
+    pub id: MessageId,
+    /// Name of the service and method, eg. `"Arith.add"`
+    pub service_method: String,
+    /// Whether the server executed the call successfully
+    pub is_ok: bool,
+    /// Size of the response body in bytes
+    pub bytes: usize,
+    /// Time from sending the request for the first time until the response was read
+    pub latency: Duration,
+    /// Number of times the request was sent before this response, starting at `1`
+    pub attempt: u32,
+}
+
+/// What the client does with a response
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResponseAction {
+    /// Returns the response to the caller
+    Return,
+    /// Sends the request again. The remaining layers are not called.
+    Retry,
+}
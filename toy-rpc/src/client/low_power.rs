@@ -0,0 +1,210 @@
+//! Low-power mode for clients embedded in mobile and desktop apps
+//!
+//! An app that goes to the background should stop using the network without
+//! closing the connection, and the OS may suspend it for a long time before it
+//! comes back to the foreground.
+//!
+//! `Client::pause` quiesces the traffic of the client. The subscriptions are
+//! canceled on the server but the local subscribers are kept, and the calls,
+//! publications and other messages made while paused are queued instead of sent.
+//! `Client::resume` subscribes to the topics again and sends the queued messages
+//! in order. The timeout of a queued call starts once it is sent, so the calls
+//! made while paused do not time out while the app is in the background.
+//! Canceling a queued call removes it from the queue.
+//!
+//! With `ClientBuilder::low_power`, the client also sends a heartbeat every
+//! `interval`, which is an extension message with the marker
+//! `extension::HEARTBEAT_MARKER` that the server drops, unless something else was
+//! written to the connection since the previous tick. Any other message counts as
+//! a heartbeat, so a busy client sends none. No heartbeat is sent while the client
+//! is paused, and the heartbeats missed during a suspension of the process are not
+//! made up for: a single heartbeat is sent once the process is resumed.
+//!
+//! # Example
+//!
+//! ```no_run
+//! # #[cfg(any(feature = "async_std_runtime", feature = "tokio_runtime"))]
+//! # {
+//! # use toy_rpc::Client;
+//! # use std::time::Duration;
+//! # use toy_rpc::client::low_power::LowPower;
+//! # async fn run(addr: &str) -> Result<(), Box<dyn std::error::Error>> {
+//! let client = Client::builder()
+//!     .low_power(LowPower::new(Duration::from_secs(60)))
+//!     .dial(addr)
+//!     .await?;
+//!
+//! // the app goes to the background
+//! client.pause().await?;
+//! // ... and back to the foreground
+//! client.resume().await?;
+//! # Ok(())
+//! # }
+//! # }
+//! ```
+
+use std::time::Duration;
+
+use futures::future::AbortHandle;
+
+#[cfg(any(
+    all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+    all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+))]
+use flume::Sender;
+
+#[cfg(any(
+    all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+    all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+))]
+use super::broker::ClientBrokerItem;
+#[cfg(any(
+    all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+    all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+))]
+use crate::clock;
+
+/// Options of the low-power mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LowPower {
+    /// Period of the heartbeat timer
+    pub interval: Duration,
+    /// How much longer than `interval` a tick of the heartbeat timer must take to be
+    /// considered a suspension of the process
+    pub suspension: Duration,
+}
+
+impl LowPower {
+    /// Sends a heartbeat every `interval` without traffic. A tick of the heartbeat
+    /// timer that is late by more than `interval` is considered a suspension.
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            suspension: interval,
+        }
+    }
+
+    /// Sets how late a tick of the heartbeat timer must be to be considered a
+    /// suspension of the process
+    pub fn suspension(self, suspension: Duration) -> Self {
+        Self { suspension, ..self }
+    }
+}
+
+/// Returns by how much a tick of the heartbeat timer was late, if that is more than
+/// `threshold`
+#[cfg_attr(
+    all(not(feature = "tokio_runtime"), not(feature = "async_std_runtime")),
+    allow(dead_code)
+)]
+fn detect_suspension(
+    elapsed: Duration,
+    interval: Duration,
+    threshold: Duration,
+) -> Option<Duration> {
+    elapsed
+        .checked_sub(interval)
+        .filter(|late| *late > threshold)
+}
+
+/// Asks the broker for a heartbeat every `interval`. Stops when the broker is stopped.
+#[cfg(any(
+    all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+    all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+))]
+pub(crate) async fn heartbeat(options: LowPower, broker: Sender<ClientBrokerItem>) {
+    let mut last = clock::system_now();
+    loop {
+        clock::sleep(options.interval).await;
+
+        // the monotonic clock may not advance while the process is suspended
+        let now = clock::system_now();
+        let elapsed = now.duration_since(last).unwrap_or_default();
+        last = now;
+
+        if let Some(late) = detect_suspension(elapsed, options.interval, options.suspension) {
+            debug!(
+                "Heartbeat is late by {:?}, the process was likely suspended",
+                late
+            );
+        }
+        if broker.send(ClientBrokerItem::Heartbeat).is_err() {
+            return;
+        }
+    }
+}
+
+/// Heartbeat timer of a client broker
+#[cfg_attr(
+    all(not(feature = "tokio_runtime"), not(feature = "async_std_runtime")),
+    allow(dead_code)
+)]
+pub(crate) struct Heartbeat {
+    timer: AbortHandle,
+    // whether a message was written since the last tick
+    written: bool,
+}
+
+#[cfg_attr(
+    all(not(feature = "tokio_runtime"), not(feature = "async_std_runtime")),
+    allow(dead_code)
+)]
+impl Heartbeat {
+    pub fn new(timer: AbortHandle) -> Self {
+        Self {
+            timer,
+            written: false,
+        }
+    }
+
+    pub fn written(&mut self) {
+        self.written = true;
+    }
+
+    /// Whether a heartbeat is to be sent on this tick, ie. nothing was written since
+    /// the last tick
+    pub fn is_due(&mut self) -> bool {
+        !std::mem::replace(&mut self.written, false)
+    }
+}
+
+impl Drop for Heartbeat {
+    fn drop(&mut self) {
+        self.timer.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn suspensions() {
+        let secs = Duration::from_secs;
+        let options = LowPower::new(secs(30));
+        assert_eq!(options.suspension, secs(30));
+        let detect = |elapsed| detect_suspension(elapsed, options.interval, options.suspension);
+        assert_eq!(detect(secs(30)), None);
+        assert_eq!(detect(secs(59)), None);
+        assert_eq!(detect(secs(3600)), Some(secs(3570)));
+        // wall clock set backwards
+        assert_eq!(detect(secs(0)), None);
+
+        let options = options.suspension(secs(5));
+        assert_eq!(
+            detect_suspension(secs(40), options.interval, options.suspension),
+            Some(secs(10))
+        );
+    }
+
+    #[test]
+    fn heartbeats_are_batched_with_traffic() {
+        let (timer, _) = AbortHandle::new_pair();
+        let mut heartbeat = Heartbeat::new(timer);
+        assert!(heartbeat.is_due());
+        heartbeat.written();
+        heartbeat.written();
+        assert!(!heartbeat.is_due());
+        assert!(heartbeat.is_due());
+    }
+}
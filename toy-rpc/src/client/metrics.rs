@@ -0,0 +1,167 @@
+//! Client side metrics of RPC calls
+//!
+//! A `MetricsSink` set with `Client::set_metrics_sink` is notified when an RPC call
+//! starts and when it finishes, which allows feeding the latency, outcome and
+//! payload sizes of the calls to an existing telemetry system.
+//!
+//! # Example
+//!
+//! ```no_run
+//! # #[cfg(any(feature = "async_std_runtime", feature = "tokio_runtime"))]
+//! # {
+//! use toy_rpc::client::metrics::CallMetrics;
+//! # use toy_rpc::Client;
+//! # async fn run(client: Client) -> Result<(), Box<dyn std::error::Error>> {
+//!
+//! let (tx, rx) = flume::unbounded::<CallMetrics>();
+//! client.set_metrics_sink(tx)?;
+//!
+//! let reply: i32 = client.call("Arith.add", (1i32, 6i32)).await?;
+//! let metrics = rx.recv_async().await?;
+//! println!("{} took {:?}", metrics.service_method, metrics.latency);
+//! # Ok(())
+//! # }
+//! # }
+//! ```
+//!
+//! The calls that are still waiting for their responses can be listed with
+//! `Client::pending_requests`, eg. to log what is stuck when a latency alarm fires.
+//!
+//! ```no_run
+//! # #[cfg(any(feature = "async_std_runtime", feature = "tokio_runtime"))]
+//! # {
+//! # use toy_rpc::Client;
+//! # async fn run(client: Client) -> Result<(), Box<dyn std::error::Error>> {
+//! for request in client.pending_requests().await? {
+//!     log::warn!("{} ({}) pending for {:?}", request.service_method, request.id, request.age);
+//! }
+//! # Ok(())
+//! # }
+//! # }
+//! ```
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::message::MessageId;
+
+/// Outcome of an RPC call
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CallOutcome {
+    /// The server executed the call successfully
+    Ok,
+    /// The server responded with an error
+    Error,
+    /// No response was received before the timeout
+    Timeout,
+    /// The call was canceled, or the connection was closed before a response was received
+    Canceled,
+}
+
+/// Metrics of a single finished RPC call
+#[derive(Debug, Clone)]
+pub struct CallMetrics {
+    /// ID of the call
+    pub id: MessageId,
+    /// Name of the service and method, eg. `"Arith.add"`
+    pub service_method: String,
+    /// Time from sending the request until the call finished
+    pub latency: Duration,
+    /// Outcome of the call
+    pub outcome: CallOutcome,
+    /// Size of the serialized request body in bytes. This is `0` if the request
+    /// was not written before the call finished
+    pub request_bytes: usize,
+    /// Size of the serialized response body in bytes. This is `0` if no response
+    /// was received
+    pub response_bytes: usize,
+}
+
+/// A call that is waiting for its response
+#[derive(Debug, Clone)]
+pub struct PendingRequest {
+    /// ID of the call
+    pub id: MessageId,
+    /// Name of the service and method, eg. `"Arith.add"`
+    pub service_method: String,
+    /// Time since the call was made
+    pub age: Duration,
+}
+
+/// Receiver of the client side metrics
+///
+/// The methods are called from the tasks handling the connection, so an
+/// implementation should avoid blocking. This is implemented for closures taking
+/// a `CallMetrics`, which are only notified of finished calls, and for
+/// `flume::Sender<CallMetrics>`.
+pub trait MetricsSink: Send + Sync + 'static {
+    /// Called when the request of a call is about to be sent
+    fn on_call_start(&self, _id: MessageId, _service_method: &str) {}
+
+    /// Called when a call finishes
+    fn on_call_finish(&self, metrics: CallMetrics);
+}
+
+impl<F> MetricsSink for F
+where
+    F: Fn(CallMetrics) + Send + Sync + 'static,
+{
+    fn on_call_finish(&self, metrics: CallMetrics) {
+        self(metrics)
+    }
+}
+
+impl MetricsSink for flume::Sender<CallMetrics> {
+    fn on_call_finish(&self, metrics: CallMetrics) {
+        self.send(metrics)
+            .unwrap_or_else(|err| error!("Failed to send call metrics: {}", err));
+    }
+}
+
+/// Keeps track of a call until it finishes
+#[cfg_attr(
+    all(not(feature = "tokio_runtime"), not(feature = "async_std_runtime")),
+    allow(dead_code)
+)]
+pub(crate) struct CallTimer {
+    sink: Arc<dyn MetricsSink>,
+    id: MessageId,
+    service_method: String,
+    started: Instant,
+    request_bytes: usize,
+}
+
+#[cfg_attr(
+    all(not(feature = "tokio_runtime"), not(feature = "async_std_runtime")),
+    allow(dead_code)
+)]
+impl CallTimer {
+    /// Notifies the sink that the call has started
+    pub fn start(
+        sink: &Arc<dyn MetricsSink>,
+        id: MessageId,
+        service_method: &str,
+        request_bytes: usize,
+    ) -> Self {
+        sink.on_call_start(id, service_method);
+        Self {
+            sink: sink.clone(),
+            id,
+            service_method: service_method.to_string(),
+            started: Instant::now(),
+            request_bytes,
+        }
+    }
+
+    /// Notifies the sink that the call has finished
+    pub fn finish(self, outcome: CallOutcome, response_bytes: usize) {
+        self.sink.on_call_finish(CallMetrics {
+            id: self.id,
+            service_method: self.service_method,
+            latency: self.started.elapsed(),
+            outcome,
+            request_bytes: self.request_bytes,
+            response_bytes,
+        })
+    }
+}
@@ -0,0 +1,171 @@
+//! Mirroring of calls to a shadow server
+//!
+//! When a shadow client is set with `Client::mirror_to`, every call selected by the
+//! filter is also sent to the server of the shadow client, with the same arguments
+//! and timeout. The caller only ever gets the response of its own server. The
+//! response of the shadow server is discarded, and the outcome is counted in the
+//! `MirrorStats` returned by `Client::mirror_stats`. This allows validating a new
+//! server implementation against production traffic without affecting the callers.
+//!
+//! Streaming calls are not mirrored. A metrics sink set on the shadow client before
+//! passing it to `mirror_to` gets the latency and the outcome of each mirrored call.
+//!
+//! # Example
+//!
+//! ```no_run
+//! # #[cfg(any(feature = "async_std_runtime", feature = "tokio_runtime"))]
+//! # {
+//! # use toy_rpc::Client;
+//! # async fn run(client: Client) -> Result<(), Box<dyn std::error::Error>> {
+//! let shadow = Client::dial("127.0.0.1:23334").await?;
+//! client.mirror_to(shadow, |service_method| service_method.starts_with("Arith."))?;
+//!
+//! let reply: i32 = client.call("Arith.add", (1i32, 6i32)).await?;
+//! println!("{:?}", client.mirror_stats());
+//! # Ok(())
+//! # }
+//! # }
+//! ```
+
+use cfg_if::cfg_if;
+use std::sync::atomic::AtomicU64;
+#[cfg(any(
+    all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+    all(feature = "async_std_runtime", not(feature = "tokio_runtime"))
+))]
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use super::Client;
+
+/// Numbers of calls mirrored to the shadow server
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MirrorStats {
+    /// Number of calls sent to the shadow server
+    pub mirrored: u64,
+    /// Number of mirrored calls that the shadow server executed successfully
+    pub succeeded: u64,
+    /// Number of mirrored calls that failed, timed out or were canceled
+    pub failed: u64,
+}
+
+/// Selects the calls to mirror by their service method
+pub type MirrorFilter = Arc<dyn Fn(&str) -> bool + Send + Sync + 'static>;
+
+/// Counters shared between the client and its broker
+#[derive(Debug, Default)]
+#[cfg_attr(
+    all(not(feature = "tokio_runtime"), not(feature = "async_std_runtime")),
+    allow(dead_code)
+)]
+pub(crate) struct Counters {
+    mirrored: AtomicU64,
+    succeeded: AtomicU64,
+    failed: AtomicU64,
+}
+
+#[cfg(any(
+    all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+    all(feature = "async_std_runtime", not(feature = "tokio_runtime"))
+))]
+impl Counters {
+    pub fn snapshot(&self) -> MirrorStats {
+        MirrorStats {
+            mirrored: self.mirrored.load(Ordering::Relaxed),
+            succeeded: self.succeeded.load(Ordering::Relaxed),
+            failed: self.failed.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// The shadow client of a client broker
+#[cfg_attr(
+    all(not(feature = "tokio_runtime"), not(feature = "async_std_runtime")),
+    allow(dead_code)
+)]
+pub(crate) struct Mirror {
+    shadow: Client,
+    filter: MirrorFilter,
+    counters: Arc<Counters>,
+}
+
+#[cfg_attr(
+    all(not(feature = "tokio_runtime"), not(feature = "async_std_runtime")),
+    allow(dead_code)
+)]
+impl Mirror {
+    pub fn new(shadow: Client, filter: MirrorFilter, counters: Arc<Counters>) -> Self {
+        Self {
+            shadow,
+            filter,
+            counters,
+        }
+    }
+}
+
+cfg_if! {
+    if #[cfg(any(
+        all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+        all(feature = "async_std_runtime", not(feature = "tokio_runtime"))
+    ))] {
+        use futures::channel::oneshot;
+        use std::time::Duration;
+
+        #[cfg(all(feature = "async_std_runtime", not(feature = "tokio_runtime")))]
+        use ::async_std::task;
+        #[cfg(all(feature = "tokio_runtime", not(feature = "async_std_runtime")))]
+        use ::tokio::task;
+
+        use crate::protocol::OutboundBody;
+
+        use super::{broker::ClientBrokerItem, unexpected};
+
+        impl Mirror {
+            /// Sends the call to the shadow server if it is selected by the filter
+            pub fn forward(&self, service_method: &str, duration: Duration, body: Arc<OutboundBody>) {
+                if !(self.filter)(service_method) {
+                    return;
+                }
+                let id = unexpected::reserve_id(&self.shadow.count, &self.shadow.reserved);
+                let (resp_tx, resp_rx) = oneshot::channel();
+                let request = ClientBrokerItem::Request {
+                    id,
+                    service_method: service_method.to_string(),
+                    duration,
+                    body,
+                    resp_tx,
+                    sink: false,
+                    metadata: Default::default(),
+                    reply_metadata: None,
+                };
+                if let Err(err) = self.shadow.broker.send(request) {
+                    error!("Failed to mirror {}: {}", service_method, err);
+                    return;
+                }
+                self.counters.mirrored.fetch_add(1, Ordering::Relaxed);
+
+                let service_method = service_method.to_string();
+                let counters = self.counters.clone();
+                task::spawn(async move {
+                    match resp_rx.await {
+                        Ok(Ok(Ok(_))) => {
+                            counters.succeeded.fetch_add(1, Ordering::Relaxed);
+                        }
+                        Ok(Ok(Err(_))) => {
+                            warn!("Mirrored call {} failed on the shadow server", service_method);
+                            counters.failed.fetch_add(1, Ordering::Relaxed);
+                        }
+                        Ok(Err(err)) => {
+                            warn!("Mirrored call {} failed: {}", service_method, err);
+                            counters.failed.fetch_add(1, Ordering::Relaxed);
+                        }
+                        Err(_) => {
+                            warn!("Mirrored call {} is dropped", service_method);
+                            counters.failed.fetch_add(1, Ordering::Relaxed);
+                        }
+                    }
+                });
+            }
+        }
+    }
+}
@@ -3,16 +3,54 @@
 use cfg_if::cfg_if;
 use crossbeam::atomic::AtomicCell;
 use flume::Sender;
-use std::{any::TypeId, collections::HashMap, sync::Arc, time::Duration};
+use std::{
+    any::TypeId,
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
-use crate::{message::AtomicMessageId, protocol::InboundBody};
+use crate::{
+    capability::Capabilities, message::AtomicMessageId, payload::PayloadCounters,
+    protocol::InboundBody,
+};
 
 pub(crate) mod broker;
+pub mod builder;
+pub mod disconnect;
+#[cfg(all(
+    feature = "server",
+    any(
+        all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+        all(feature = "async_std_runtime", not(feature = "tokio_runtime"))
+    )
+))]
+pub mod duplex;
+pub mod layer;
+pub mod low_power;
+pub mod metrics;
+pub mod mirror;
+#[cfg(any(
+    all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+    all(feature = "async_std_runtime", not(feature = "tokio_runtime"))
+))]
+mod post;
 pub mod pubsub;
+#[cfg(any(
+    feature = "docs",
+    all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+    all(feature = "async_std_runtime", not(feature = "tokio_runtime"))
+))]
 mod reader;
+pub mod resilience;
+pub mod storm;
+pub mod typed;
+pub mod unexpected;
 mod writer;
 
 use broker::ClientBrokerItem;
+pub use builder::ClientBuilder;
+use unexpected::{Counters, ReservedIds};
 
 type ResponseResult = Result<Box<InboundBody>, Box<InboundBody>>;
 
@@ -22,16 +60,35 @@ cfg_if! {
         all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
         all(feature = "async_std_runtime", not(feature = "tokio_runtime"))
     ))] {
-        use futures::channel::oneshot;
-        use crate::{Error, protocol::OutboundBody};
+        use futures::{channel::oneshot, future, stream, StreamExt};
+        use crate::{
+            Error,
+            metadata::Metadata,
+            pagination::{Page, PageRequest, PageStream},
+            payload::{PayloadStats, SizeLimit},
+            protocol::OutboundBody,
+        };
 
         #[cfg(feature = "tls")]
-        use crate::transport::ws::WebSocketConn;
+        use crate::transport::ws::{client_request, offered_in, WebSocketConn};
 
         #[cfg(feature = "tls")]
         use crate::codec::DefaultCodec;
 
         const DEFAULT_TIMEOUT_SECONDS: u64 = 10;
+
+        /// Host and port of the server of a WebSocket or an HTTP url
+        #[cfg(any(
+            all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+            all(feature = "async_std_runtime", not(feature = "tokio_runtime"))
+        ))]
+        fn ws_addr(url: &url::Url) -> Result<(&str, u16), Error> {
+            let host = url.host_str()
+                .ok_or(Error::Internal("Invalid host address".into()))?;
+            let port = url.port_or_known_default()
+                .ok_or(Error::Internal("Invalid port".into()))?;
+            Ok((host, port))
+        }
     }
 }
 
@@ -42,24 +99,35 @@ cfg_if! {
             not(feature = "serde_json"),
             not(feature = "serde_cbor"),
             not(feature = "serde_rmp"),
+            not(feature = "serde_postcard"),
         ),
         all(
             feature = "serde_cbor",
             not(feature = "serde_json"),
             not(feature = "serde_bincode"),
             not(feature = "serde_rmp"),
+            not(feature = "serde_postcard"),
         ),
         all(
             feature = "serde_json",
             not(feature = "serde_bincode"),
             not(feature = "serde_cbor"),
             not(feature = "serde_rmp"),
+            not(feature = "serde_postcard"),
         ),
         all(
             feature = "serde_rmp",
             not(feature = "serde_cbor"),
             not(feature = "serde_json"),
             not(feature = "serde_bincode"),
+            not(feature = "serde_postcard"),
+        ),
+        all(
+            feature = "serde_postcard",
+            not(feature = "serde_cbor"),
+            not(feature = "serde_json"),
+            not(feature = "serde_bincode"),
+            not(feature = "serde_rmp"),
         )
     ))] {
         #[cfg(all(
@@ -93,6 +161,14 @@ cfg_if! {
             all(feature = "async_std_runtime", not(feature = "tokio_runtime"))
         ))]
         use async_tungstenite::client_async;
+        #[cfg(all(
+            feature = "tls",
+            any(
+                all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+                all(feature = "async_std_runtime", not(feature = "tokio_runtime"))
+            )
+        ))]
+        use builder::DialTimeouts;
 
         #[cfg(all(
             feature = "tls",
@@ -104,14 +180,18 @@ cfg_if! {
         async fn tcp_client_with_tls_config(
             addr: impl ToSocketAddrs,
             domain: &str,
-            config: rustls::ClientConfig
+            config: rustls::ClientConfig,
+            timeouts: &DialTimeouts,
+            frame: crate::codec::FrameOptions,
         ) -> Result<Client, Error> {
-            let stream = TcpStream::connect(addr).await?;
+            let stream = timeouts.connect(TcpStream::connect(addr)).await?;
             let connector = TlsConnector::from(std::sync::Arc::new(config));
             let domain = webpki::DNSNameRef::try_from_ascii_str(domain)?;
-            let tls_stream = connector.connect(domain, stream).await?;
+            let tls_stream = timeouts.tls_handshake(connector.connect(domain, stream)).await?;
 
-            Ok(Client::with_stream(tls_stream))
+            let mut codec = DefaultCodec::new(tls_stream);
+            codec.frame = frame;
+            Ok(Client::with_codec(codec))
         }
 
         #[cfg(all(
@@ -125,21 +205,50 @@ cfg_if! {
             url: url::Url,
             domain: &str,
             config: rustls::ClientConfig,
+            timeouts: &DialTimeouts,
         ) -> Result<Client, Error> {
-            let host = url.host_str()
-                .ok_or(Error::Internal("Invalid host address".into()))?;
-            let port = url.port_or_known_default()
-                .ok_or(Error::Internal("Invalid port".into()))?;
-            let addr = (host, port);
-            let stream = TcpStream::connect(addr).await?;
+            let stream = timeouts.connect(TcpStream::connect(ws_addr(&url)?)).await?;
             let connector = TlsConnector::from(std::sync::Arc::new(config));
             let domain = webpki::DNSNameRef::try_from_ascii_str(domain)?;
-            let tls_stream = connector.connect(domain, stream).await?;
-            let (ws_stream, _) = client_async(url, tls_stream).await?;
-            let ws_stream = WebSocketConn::new(ws_stream);
+            let tls_stream = timeouts.tls_handshake(connector.connect(domain, stream)).await?;
+            let (ws_stream, response) = timeouts.handshake(client_async(client_request(url)?, tls_stream)).await?
+                .map_err(Error::from_ws_handshake)?;
+            let ws_stream = WebSocketConn::new(ws_stream).chunked(offered_in(response.headers()));
             let codec = DefaultCodec::with_websocket(ws_stream);
             Ok(Client::with_codec(codec))
         }
+
+        /// TLS configuration that trusts the Mozilla root certificates
+        #[cfg(all(
+            feature = "tls",
+            any(
+                all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+                all(feature = "async_std_runtime", not(feature = "tokio_runtime"))
+            )
+        ))]
+        fn default_tls_config() -> rustls::ClientConfig {
+            let mut config = rustls::ClientConfig::new();
+            config
+                .root_store
+                .add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS);
+            config
+        }
+
+        /// Connects with TLS to the host of a "wss://" url with the default TLS
+        /// configuration
+        #[cfg(all(
+            feature = "tls",
+            any(
+                all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+                all(feature = "async_std_runtime", not(feature = "tokio_runtime"))
+            )
+        ))]
+        async fn secure_websocket_client(url: url::Url, timeouts: &DialTimeouts) -> Result<Client, Error> {
+            let domain = url.host_str()
+                .ok_or(Error::Internal("Invalid host address".into()))?
+                .to_string();
+            websocket_client_with_tls_config(url, &domain, default_tls_config(), timeouts).await
+        }
     }
 }
 
@@ -155,7 +264,118 @@ mod async_std;
 mod tokio;
 
 pub mod call;
-pub use call::Call;
+pub use call::{Call, CallWithMetadata};
+pub mod call_stream;
+pub use call_stream::CallStream;
+pub mod sink;
+pub use sink::ClientSink;
+
+#[cfg(any(
+    feature = "docs",
+    all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+    all(feature = "async_std_runtime", not(feature = "tokio_runtime"))
+))]
+pub mod pool;
+#[cfg(any(
+    feature = "docs",
+    all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+    all(feature = "async_std_runtime", not(feature = "tokio_runtime"))
+))]
+pub mod prepared;
+#[cfg(any(
+    feature = "docs",
+    all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+    all(feature = "async_std_runtime", not(feature = "tokio_runtime"))
+))]
+pub mod transaction;
+#[cfg(all(
+    feature = "wasm",
+    target_arch = "wasm32",
+    any(
+        all(
+            feature = "serde_bincode",
+            not(feature = "serde_json"),
+            not(feature = "serde_cbor"),
+            not(feature = "serde_rmp"),
+            not(feature = "serde_postcard"),
+        ),
+        all(
+            feature = "serde_cbor",
+            not(feature = "serde_json"),
+            not(feature = "serde_bincode"),
+            not(feature = "serde_rmp"),
+            not(feature = "serde_postcard"),
+        ),
+        all(
+            feature = "serde_json",
+            not(feature = "serde_bincode"),
+            not(feature = "serde_cbor"),
+            not(feature = "serde_rmp"),
+            not(feature = "serde_postcard"),
+        ),
+        all(
+            feature = "serde_rmp",
+            not(feature = "serde_cbor"),
+            not(feature = "serde_json"),
+            not(feature = "serde_bincode"),
+            not(feature = "serde_postcard"),
+        ),
+        all(
+            feature = "serde_postcard",
+            not(feature = "serde_cbor"),
+            not(feature = "serde_json"),
+            not(feature = "serde_bincode"),
+            not(feature = "serde_rmp"),
+        )
+    )
+))]
+pub mod wasm;
+
+// the message handling of `WasmClient`, which is also tested natively
+#[cfg(all(
+    any(
+        all(feature = "wasm", target_arch = "wasm32"),
+        all(test, any(feature = "async_std_runtime", feature = "tokio_runtime"))
+    ),
+    any(
+        all(
+            feature = "serde_bincode",
+            not(feature = "serde_json"),
+            not(feature = "serde_cbor"),
+            not(feature = "serde_rmp"),
+            not(feature = "serde_postcard"),
+        ),
+        all(
+            feature = "serde_cbor",
+            not(feature = "serde_json"),
+            not(feature = "serde_bincode"),
+            not(feature = "serde_rmp"),
+            not(feature = "serde_postcard"),
+        ),
+        all(
+            feature = "serde_json",
+            not(feature = "serde_bincode"),
+            not(feature = "serde_cbor"),
+            not(feature = "serde_rmp"),
+            not(feature = "serde_postcard"),
+        ),
+        all(
+            feature = "serde_rmp",
+            not(feature = "serde_cbor"),
+            not(feature = "serde_json"),
+            not(feature = "serde_bincode"),
+            not(feature = "serde_postcard"),
+        ),
+        all(
+            feature = "serde_postcard",
+            not(feature = "serde_cbor"),
+            not(feature = "serde_json"),
+            not(feature = "serde_bincode"),
+            not(feature = "serde_rmp"),
+        )
+    )
+))]
+mod wasm_shared;
 
 /// RPC client
 ///
@@ -169,6 +389,11 @@ pub struct Client {
     next_timeout: AtomicCell<Option<Duration>>,
     broker: Sender<ClientBrokerItem>,
     subscriptions: HashMap<String, TypeId>,
+    reserved: ReservedIds,
+    unexpected: Arc<Counters>,
+    mirrored: Arc<mirror::Counters>,
+    payload: Arc<PayloadCounters>,
+    capabilities: Mutex<HashMap<String, Capabilities>>,
 }
 
 // seems like it still works even without this impl
@@ -177,16 +402,22 @@ impl Drop for Client {
         for (topic, _) in self.subscriptions.drain() {
             self.broker
                 .try_send(broker::ClientBrokerItem::Unsubscribe { topic })
-                .unwrap_or_else(|err| log::error!("{}", err));
+                .unwrap_or_else(|err| error!("{}", err));
         }
 
         if let Err(err) = self.broker.try_send(broker::ClientBrokerItem::Stop) {
-            log::error!("Failed to send stop signal to writer loop: {}", err);
+            error!("Failed to send stop signal to writer loop: {}", err);
         }
     }
 }
 
 impl Client {
+    /// Creates a builder of the options that are set before the first call, eg. the
+    /// layers intercepting the calls. See `ClientBuilder`.
+    pub fn builder() -> ClientBuilder {
+        ClientBuilder::new()
+    }
+
     /// Closes connection with the server
     ///
     /// Dropping the client will close the connection as well
@@ -196,13 +427,13 @@ impl Client {
             self.broker
                 .send_async(broker::ClientBrokerItem::Unsubscribe { topic })
                 .await
-                .unwrap_or_else(|err| log::error!("{}", err));
+                .unwrap_or_else(|err| error!("{}", err));
         }
 
         self.broker
             .send_async(broker::ClientBrokerItem::Stop)
             .await
-            .unwrap_or_else(|err| log::error!("{}", err));
+            .unwrap_or_else(|err| error!("{}", err));
     }
 }
 
@@ -216,10 +447,10 @@ cfg_if! {
         all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
         all(feature = "tokio_runtime", not(feature = "async_std_runtime"))
     ))] {
-        use std::sync::atomic::Ordering;
+        use crate::message::MessageId;
 
         use crate::{
-            codec::split::SplittableCodec,
+            codec::{split::SplittableCodec, Marshal},
             // message::{ClientRequestBody, RequestHeader},
         };
         use reader::*;
@@ -230,11 +461,19 @@ cfg_if! {
             ///
             /// Example
             ///
-            /// ```rust
+            /// ```no_run
+            /// # #[cfg(feature = "async_std_runtime")]
+            /// # use async_std::net::TcpStream;
+            /// # #[cfg(feature = "tokio_runtime")]
+            /// # use tokio::net::TcpStream;
+            /// # use toy_rpc::Client;
+            /// # use toy_rpc::codec::Codec;
+            /// # async fn run() {
             /// let addr = "127.0.0.1:8080";
             /// let stream = TcpStream::connect(addr).await.unwrap();
             /// let codec = Codec::new(stream);
             /// let client = Client::with_codec(codec);
+            /// # }
             /// ```
             // #[cfg(any(
             //     all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
@@ -247,15 +486,38 @@ cfg_if! {
                 C: SplittableCodec + Send + 'static,
             {
                 let (writer, reader) = codec.split();
-                let reader = ClientReader { reader };
+                let reader = ClientReader::new(reader);
                 let writer = ClientWriter { writer };
                 let count = Arc::new(AtomicMessageId::new(0));
+                let reserved = ReservedIds::default();
+                let unexpected = Arc::new(Counters::default());
+                let payload = Arc::new(PayloadCounters::default());
 
                 let broker = broker::ClientBroker {
                     count: count.clone(),
                     pending: HashMap::new(),
+                    streams: HashMap::new(),
                     next_timeout: None,
                     subscriptions: HashMap::new(),
+                    publishes: HashMap::new(),
+                    extensions: HashMap::new(),
+                    metrics: None,
+                    layers: Vec::new(),
+                    tracker: unexpected::ResponseTracker::new(reserved.clone(), unexpected.clone()),
+                    resilience: None,
+                    storm: None,
+                    mirror: None,
+                    limits: Default::default(),
+                    payload: payload.clone(),
+                    marshal: |body| <C::Writer as Marshal>::marshal(&body),
+                    disconnected: false,
+                    lifecycle: disconnect::Lifecycle::new(),
+                    paused: None,
+                    heartbeat: None,
+                    #[cfg(feature = "signing")]
+                    verifier: None,
+                    #[cfg(feature = "server")]
+                    serving: None,
                 };
                 let (_, broker) = brw::spawn(broker, reader, writer);
 
@@ -265,6 +527,11 @@ cfg_if! {
                     next_timeout: AtomicCell::new(None),
                     broker,
                     subscriptions: HashMap::new(),
+                    reserved,
+                    unexpected,
+                    mirrored: Arc::new(mirror::Counters::default()),
+                    payload,
+                    capabilities: Mutex::new(HashMap::new()),
                 }
             }
         }
@@ -274,12 +541,16 @@ cfg_if! {
             ///
             /// Example
             ///
-            /// ```rust
+            /// ```no_run
+            /// # use toy_rpc::Client;
+            /// # use toy_rpc::client::Call;
+            /// # async fn run(mut client: Client) {
             /// let call: Call<()> = client
             ///     .set_default_timeout(std::time::Duration::from_secs(2)) // the RPC Call will timeout after 2 seconds
             ///     .call("Service.wait_for_10secs", ()); // request a RPC call that waits for 10 seconds
             /// let result = call.await;
             /// println!("{:?}", result); // Err(Error::Timeout(Some(call_id)))
+            /// # }
             /// ```
             #[cfg_attr(feature = "docs", doc(cfg(all(feature = "async_std_runtime", not(feature = "tokio_runtime")))))]
             #[cfg_attr(feature = "docs", doc(cfg(all(feature = "tokio_runtime", not(feature = "async_std_runtime")))))]
@@ -292,12 +563,16 @@ cfg_if! {
             ///
             /// Example
             ///
-            /// ```rust
+            /// ```no_run
+            /// # use toy_rpc::Client;
+            /// # use toy_rpc::client::Call;
+            /// # async fn run(client: Client) {
             /// let call: Call<()> = client
             ///     .set_next_timeout(std::time::Duration::from_secs(2)) // the RPC Call will timeout after 2 seconds
             ///     .call("Service.wait_for_10secs", ()); // request a RPC call that waits for 10 seconds
             /// let result = call.await;
             /// println!("{:?}", result); // Err(Error::Timeout(Some(call_id)))
+            /// # }
             /// ```
             #[cfg_attr(feature = "docs", doc(cfg(all(feature = "async_std_runtime", not(feature = "tokio_runtime")))))]
             #[cfg_attr(feature = "docs", doc(cfg(all(feature = "tokio_runtime", not(feature = "async_std_runtime")))))]
@@ -306,6 +581,419 @@ cfg_if! {
                 self
             }
 
+            /// Registers a handler of the protocol extension identified by `marker`.
+            /// See the `extension` module for details.
+            ///
+            /// Example
+            ///
+            /// ```no_run
+            /// # use toy_rpc::{Client, Error};
+            /// # async fn run(client: Client) -> Result<(), Error> {
+            /// client.register_extension(1, |content| {
+            ///     println!("{}", content);
+            ///     None
+            /// })?;
+            /// # Ok(())
+            /// # }
+            /// ```
+            pub fn register_extension<F>(&self, marker: u32, handler: F) -> Result<(), Error>
+            where
+                F: Fn(String) -> Option<String> + Send + Sync + 'static,
+            {
+                let handler = Arc::new(handler);
+                self.broker
+                    .send(ClientBrokerItem::RegisterExtension { marker, handler })
+                    .map_err(|err| err.into())
+            }
+
+            /// Sends a protocol extension message to the server. The reply from the server,
+            /// if any, is passed to the handler registered with the same `marker`.
+            ///
+            /// Example
+            ///
+            /// ```no_run
+            /// # use toy_rpc::{Client, Error};
+            /// # async fn run(client: Client) -> Result<(), Error> {
+            /// client.send_extension(1, "ping")?;
+            /// # Ok(())
+            /// # }
+            /// ```
+            pub fn send_extension(&self, marker: u32, content: impl ToString) -> Result<(), Error> {
+                let content = content.to_string();
+                self.broker
+                    .send(ClientBrokerItem::Ext { marker, content })
+                    .map_err(|err| err.into())
+            }
+
+            /// Sets the receiver of the metrics of the RPC calls made by this client.
+            /// See the `metrics` module for details.
+            ///
+            /// Example
+            ///
+            /// ```no_run
+            /// # use toy_rpc::client::metrics::CallMetrics;
+            /// # use toy_rpc::{Client, Error};
+            /// # async fn run(client: Client) -> Result<(), Error> {
+            /// client.set_metrics_sink(|metrics: CallMetrics| {
+            ///     println!("{} {:?} {:?}", metrics.service_method, metrics.outcome, metrics.latency);
+            /// })?;
+            /// # Ok(())
+            /// # }
+            /// ```
+            pub fn set_metrics_sink(&self, sink: impl metrics::MetricsSink) -> Result<(), Error> {
+                let sink = Arc::new(sink);
+                self.broker
+                    .send(ClientBrokerItem::SetMetricsSink { sink })
+                    .map_err(|err| err.into())
+            }
+
+            /// Opens a transaction. See the `transaction` module for details.
+            ///
+            /// Example
+            ///
+            /// ```no_run
+            /// # use toy_rpc::{Client, Error};
+            /// # async fn run(client: Client) -> Result<(), Error> {
+            /// let transaction = client.transaction();
+            /// transaction.call::<_, ()>("Bank.withdraw", 10u32).await?;
+            /// transaction.call::<_, ()>("Bank.deposit", 10u32).await?;
+            /// transaction.commit().await?;
+            /// # Ok(())
+            /// # }
+            /// ```
+            pub fn transaction(&self) -> transaction::Transaction<'_> {
+                transaction::Transaction::new(self)
+            }
+
+            /// Prepares calls to the named RPC function that share the metadata and the
+            /// timeout bound to the returned `PreparedCall`. See the `prepared` module
+            /// for details.
+            ///
+            /// Example
+            ///
+            /// ```no_run
+            /// # use std::time::Duration;
+            /// # use toy_rpc::metadata::Metadata;
+            /// # use toy_rpc::{Client, Error};
+            /// # async fn run(client: Client, auth: Metadata, timeout: Duration) -> Result<(), Error> {
+            /// let echo = client.prepare("Echo.echo_i32").bind_meta(auth).timeout(timeout);
+            /// let reply: i32 = echo.call(7i32).await?;
+            /// # Ok(())
+            /// # }
+            /// ```
+            pub fn prepare(&self, service_method: impl ToString) -> prepared::PreparedCall<'_> {
+                prepared::PreparedCall::new(self, service_method.to_string())
+            }
+
+            /// Returns the capabilities of `service`, which are fetched from the server with
+            /// the first call and cached for the lifetime of the client. A server that does
+            /// not describe its services returns no capabilities. See the `capability`
+            /// module for details.
+            ///
+            /// Example
+            ///
+            /// ```no_run
+            /// # use toy_rpc::{Client, Error};
+            /// # async fn run(client: Client) -> Result<(), Error> {
+            /// if client.capabilities("Example").await?.supports("supports_streaming") {
+            ///     // use the streaming methods
+            /// }
+            /// # Ok(())
+            /// # }
+            /// ```
+            pub async fn capabilities(&self, service: &str) -> Result<Capabilities, Error> {
+                if let Some(capabilities) = self.cached_capabilities(service) {
+                    return Ok(capabilities);
+                }
+
+                let service_method = format!("{}.{}", service, crate::capability::DESCRIBE_METHOD);
+                let capabilities = match self.call(service_method, ()).await {
+                    Ok(capabilities) => capabilities,
+                    Err(Error::MethodNotFound) => Capabilities::new(),
+                    Err(err) => return Err(err),
+                };
+                self.capabilities
+                    .lock()
+                    .map_err(|_| Error::Internal("Capabilities cache is poisoned".into()))?
+                    .insert(service.to_string(), capabilities.clone());
+                Ok(capabilities)
+            }
+
+            fn cached_capabilities(&self, service: &str) -> Option<Capabilities> {
+                self.capabilities.lock().ok()?.get(service).cloned()
+            }
+
+            /// Returns the calls that are waiting for their responses, the oldest first.
+            /// See the `metrics` module for details.
+            pub async fn pending_requests(&self) -> Result<Vec<metrics::PendingRequest>, Error> {
+                let (done, rx) = oneshot::channel();
+                self.broker
+                    .send_async(ClientBrokerItem::PendingRequests { done })
+                    .await?;
+                rx.await
+                    .map_err(|_| Error::Internal("Client broker is stopped".into()))
+            }
+
+            /// Returns a stream of all the items of a paginated method, which requests the
+            /// following pages of `limit` items as it is consumed. The stream ends after
+            /// the first error. See the `pagination` module for details.
+            ///
+            /// Example
+            ///
+            /// ```no_run
+            /// # use futures::StreamExt;
+            /// # #[derive(Debug, serde::Deserialize)]
+            /// # struct Product {
+            /// #     name: String,
+            /// # }
+            /// # use toy_rpc::{Client, Error};
+            /// # async fn run(client: Client) -> Result<(), Error> {
+            /// let mut products = client.paginate::<Product>("Catalog.products", 100);
+            /// while let Some(product) = products.next().await {
+            ///     println!("{:?}", product?);
+            /// }
+            /// # Ok(())
+            /// # }
+            /// ```
+            pub fn paginate<'c, T>(&'c self, service_method: impl ToString, limit: usize) -> PageStream<'c, T>
+            where
+                T: serde::de::DeserializeOwned + Send + 'static,
+            {
+                let service_method = service_method.to_string();
+                let pages = stream::unfold(Some(PageRequest::first(limit)), move |request| {
+                    let service_method = service_method.clone();
+                    async move {
+                        let request = request?;
+                        let limit = request.limit;
+                        match self.call::<_, Page<T>>(service_method, request).await {
+                            Ok(page) => {
+                                let next = page.next.map(|cursor| PageRequest {
+                                    cursor: Some(cursor),
+                                    limit,
+                                });
+                                Some((Ok(page.items), next))
+                            }
+                            Err(err) => Some((Err(err), None)),
+                        }
+                    }
+                });
+                Box::pin(pages.flat_map(|page| match page {
+                    Ok(items) => stream::iter(items.into_iter().map(Ok)).left_stream(),
+                    Err(err) => stream::once(future::ready(Err(err))).right_stream(),
+                }))
+            }
+
+            /// Returns the numbers of responses received so far that did not match a pending
+            /// call. See the `unexpected` module for details.
+            pub fn unexpected_responses(&self) -> unexpected::UnexpectedResponseStats {
+                self.unexpected.snapshot()
+            }
+
+            /// Registers a handler that is called with the id and the kind of every response
+            /// that does not match a pending call. Such responses are dropped regardless of
+            /// the handler.
+            ///
+            /// Example
+            ///
+            /// ```no_run
+            /// # use toy_rpc::{Client, Error};
+            /// # async fn run(client: Client) -> Result<(), Error> {
+            /// client.on_unexpected_response(|id, kind| {
+            ///     log::warn!("Dropped {:?} response with id {}", kind, id);
+            /// })?;
+            /// # Ok(())
+            /// # }
+            /// ```
+            pub fn on_unexpected_response<F>(&self, handler: F) -> Result<(), Error>
+            where
+                F: Fn(MessageId, unexpected::UnexpectedResponse) + Send + Sync + 'static,
+            {
+                let handler = Arc::new(handler);
+                self.broker
+                    .send(ClientBrokerItem::SetUnexpectedResponseHandler { handler })
+                    .map_err(|err| err.into())
+            }
+
+            /// Registers a handler that is called once when the connection is lost, so that
+            /// the application can dial the server again. It is not called when the client
+            /// is closed or dropped. Registering another handler replaces the previous one.
+            /// See the `disconnect` module for details.
+            ///
+            /// Example
+            ///
+            /// ```no_run
+            /// # use toy_rpc::client::disconnect::{DisconnectInfo, DisconnectReason};
+            /// # use toy_rpc::{Client, Error};
+            /// # async fn run(client: Client, reconnect_tx: flume::Sender<DisconnectReason>) -> Result<(), Error> {
+            /// client.on_disconnect(move |info: &DisconnectInfo| {
+            ///     let _ = reconnect_tx.try_send(info.reason);
+            /// })?;
+            /// # Ok(())
+            /// # }
+            /// ```
+            pub fn on_disconnect<F>(&self, handler: F) -> Result<(), Error>
+            where
+                F: Fn(&disconnect::DisconnectInfo) + Send + Sync + 'static,
+            {
+                let handler = Arc::new(handler);
+                self.broker
+                    .send(ClientBrokerItem::SetDisconnectHandler { handler })
+                    .map_err(|err| err.into())
+            }
+
+            /// Enables the resilience mode, in which `handler` decides what to do with each
+            /// call in flight when the wall clock advances further than the monotonic clock
+            /// by at least `threshold`, eg. after the machine resumes from suspension.
+            /// Only calls made after enabling are tracked. See the `resilience` module
+            /// for details.
+            ///
+            /// Example
+            ///
+            /// ```no_run
+            /// # use std::time::Duration;
+            /// # use toy_rpc::client::resilience::Revalidation;
+            /// # use toy_rpc::{Client, Error};
+            /// # async fn run(client: Client) -> Result<(), Error> {
+            /// client.on_clock_jump(Duration::from_secs(5), |_call| Revalidation::Resend)?;
+            /// # Ok(())
+            /// # }
+            /// ```
+            pub fn on_clock_jump<F>(&self, threshold: Duration, handler: F) -> Result<(), Error>
+            where
+                F: Fn(&resilience::InFlightCall) -> resilience::Revalidation + Send + Sync + 'static,
+            {
+                let handler = Arc::new(handler);
+                self.broker
+                    .send(ClientBrokerItem::SetClockJumpHandler { threshold, handler })
+                    .map_err(|err| err.into())
+            }
+
+            /// Fails all the calls in flight as soon as more than `threshold` calls time
+            /// out within `window`, and with `StormAction::Disconnect` also closes the
+            /// connection. A disconnected client fails all the calls right away until it
+            /// is dropped. See the `storm` module for details.
+            ///
+            /// Example
+            ///
+            /// ```no_run
+            /// # use std::time::Duration;
+            /// # use toy_rpc::client::storm::StormAction;
+            /// # use toy_rpc::{Client, Error};
+            /// # async fn run(client: Client) -> Result<(), Error> {
+            /// client.on_timeout_storm(10, Duration::from_secs(5), StormAction::FailPending)?;
+            /// # Ok(())
+            /// # }
+            /// ```
+            pub fn on_timeout_storm(
+                &self,
+                threshold: usize,
+                window: Duration,
+                action: storm::StormAction,
+            ) -> Result<(), Error> {
+                let storm = storm::TimeoutStorm::new(threshold, window, action);
+                self.broker
+                    .send(ClientBrokerItem::SetTimeoutStorm { storm })
+                    .map_err(|err| err.into())
+            }
+
+            /// Mirrors the calls selected by `filter` to the server of `shadow`. The responses
+            /// of the shadow server are discarded and their outcomes are counted in
+            /// `mirror_stats`. Setting another shadow client replaces the previous one. See
+            /// the `mirror` module for details.
+            ///
+            /// Example
+            ///
+            /// ```no_run
+            /// # use toy_rpc::{Client, Error};
+            /// # async fn run(client: Client) -> Result<(), Error> {
+            /// let shadow = Client::dial("127.0.0.1:23334").await?;
+            /// client.mirror_to(shadow, |service_method| service_method != "Ledger.deposit")?;
+            /// # Ok(())
+            /// # }
+            /// ```
+            pub fn mirror_to<F>(&self, shadow: Client, filter: F) -> Result<(), Error>
+            where
+                F: Fn(&str) -> bool + Send + Sync + 'static,
+            {
+                let mirror = mirror::Mirror::new(shadow, Arc::new(filter), self.mirrored.clone());
+                self.broker
+                    .send(ClientBrokerItem::SetMirror { mirror })
+                    .map_err(|err| err.into())
+            }
+
+            /// Returns the numbers of calls mirrored to the shadow server so far and of their
+            /// outcomes. See the `mirror` module for details.
+            pub fn mirror_stats(&self) -> mirror::MirrorStats {
+                self.mirrored.snapshot()
+            }
+
+            /// Sets the maximum sizes of the serialized arguments and responses of a
+            /// service (`"Service"`) or of a method (`"Service.method"`). A request that
+            /// is too large is not sent and a response that is too large is discarded,
+            /// and the call fails with `Error::PayloadTooLarge`. See the `payload` module
+            /// for details.
+            ///
+            /// Example
+            ///
+            /// ```no_run
+            /// # use toy_rpc::payload::SizeLimit;
+            /// # use toy_rpc::{Client, Error};
+            /// # async fn run(client: Client) -> Result<(), Error> {
+            /// client.set_size_limit("Arith", SizeLimit::default().max_response(1024))?;
+            /// # Ok(())
+            /// # }
+            /// ```
+            pub fn set_size_limit(
+                &self,
+                service_method: impl ToString,
+                limit: SizeLimit,
+            ) -> Result<(), Error> {
+                self.broker
+                    .send(ClientBrokerItem::SetSizeLimit {
+                        service_method: service_method.to_string(),
+                        limit,
+                    })
+                    .map_err(|err| err.into())
+            }
+
+            /// Quiesces the traffic of the client, eg. when the app goes to the background.
+            /// The calls, publications and subscriptions made while paused are queued
+            /// until `resume` is called. See the `low_power` module for details.
+            ///
+            /// Example
+            ///
+            /// ```no_run
+            /// # use toy_rpc::{Client, Error};
+            /// # async fn run(client: Client) -> Result<(), Error> {
+            /// client.pause().await?;
+            /// let call = client.call::<_, i32>("Arith.add", (1, 2)); // queued
+            /// client.resume().await?;
+            /// assert_eq!(call.await?, 3);
+            /// # Ok(())
+            /// # }
+            /// ```
+            pub async fn pause(&self) -> Result<(), Error> {
+                self.broker
+                    .send_async(ClientBrokerItem::Pause)
+                    .await
+                    .map_err(|err| err.into())
+            }
+
+            /// Subscribes to the topics again and sends the messages queued since
+            /// `pause` was called. See the `low_power` module for details.
+            pub async fn resume(&self) -> Result<(), Error> {
+                self.broker
+                    .send_async(ClientBrokerItem::Resume)
+                    .await
+                    .map_err(|err| err.into())
+            }
+
+            /// Returns the numbers of calls and bytes of the payloads of every method
+            /// called so far. See the `payload` module for details.
+            pub fn payload_stats(&self) -> HashMap<String, PayloadStats> {
+                self.payload.snapshot()
+            }
+
             /// Invokes the named function and wait synchronously in a blocking manner.
             ///
             /// This function internally calls `task::block_on` to wait for the response.
@@ -313,11 +1001,14 @@ cfg_if! {
             ///
             /// Example
             ///
-            /// ```rust
+            /// ```no_run
+            /// # use toy_rpc::{Client, Error};
+            /// # fn run(client: Client) {
             /// let args = "arguments";
             /// let reply: Result<String, Error> = client
-            ///     .call_blocking("EchoService.echo", &args); // This is a blocking call and you dont need to .await
+            ///     .call_blocking("EchoService.echo", args); // This is a blocking call and you dont need to .await
             /// println!("{:?}", reply);
+            /// # }
             /// ```
             #[cfg_attr(feature = "docs", doc(cfg(all(feature = "async_std_runtime", not(feature = "tokio_runtime")))))]
             #[cfg_attr(feature = "docs", doc(cfg(all(feature = "tokio_runtime", not(feature = "async_std_runtime")))))]
@@ -359,35 +1050,89 @@ cfg_if! {
             ///
             /// Example
             ///
-            /// ```rust
+            /// ```no_run
+            /// # use toy_rpc::Client;
+            /// # use toy_rpc::client::Call;
+            /// # async fn run(client: Client) {
             /// // Get the result by `.await`ing on the `Call`
             /// let call: Call<i32> = client.call("SomeService.echo_i32", 7i32);
             /// let reply: Result<i32, toy_rpc::Error> = call.await;
             ///
             /// // Cancel the call
-            /// let call: Call<()> = client.call("SomeService.infinite_loop", ());
-            /// // cancel takes a reference
+            /// let mut call: Call<()> = client.call("SomeService.infinite_loop", ());
+            /// // cancel takes a mutable reference
             /// // .await on a canceled `Call` will return `Err(Error::Canceled(Some(id)))`
             /// call.cancel();
             /// let reply = call.await;
             /// println!("This should be a Err(Error::Canceled) {:?}", reply);
+            /// # }
             /// ```
             #[cfg_attr(feature = "docs", doc(cfg(all(feature = "async_std_runtime", not(feature = "tokio_runtime")))))]
             #[cfg_attr(feature = "docs", doc(cfg(all(feature = "tokio_runtime", not(feature = "async_std_runtime")))))]
             pub fn call<Req, Res>(&self, service_method: impl ToString, args: Req) -> Call<Res>
+            where
+                Req: serde::Serialize + Send + Sync + 'static,
+                Res: serde::de::DeserializeOwned + Send + 'static,
+            {
+                let duration = self.take_timeout();
+                self.call_with_timeout(service_method.to_string(), args, duration, false)
+            }
+
+            /// Invokes the named RPC function with the metadata of the request, eg. an
+            /// authentication token, and returns a `CallWithMetadata`, which yields the
+            /// reply along with the metadata of the response. See the `metadata` module
+            /// for details.
+            ///
+            /// Example
+            ///
+            /// ```no_run
+            /// # use toy_rpc::metadata::Metadata;
+            /// # use toy_rpc::{Client, Error};
+            /// # async fn run(client: Client) -> Result<(), Error> {
+            /// let mut metadata = Metadata::new();
+            /// metadata.insert("trace-id".into(), "4bf92f35".into());
+            /// let (reply, metadata): (i32, Metadata) = client
+            ///     .call_with_metadata("SomeService.echo_i32", 7i32, metadata)
+            ///     .await?;
+            /// # Ok(())
+            /// # }
+            /// ```
+            pub fn call_with_metadata<Req, Res>(&self, service_method: impl ToString, args: Req, metadata: Metadata) -> CallWithMetadata<Res>
+            where
+                Req: serde::Serialize + Send + Sync + 'static,
+                Res: serde::de::DeserializeOwned + Send + 'static,
+            {
+                let duration = self.take_timeout();
+                let (reply_tx, reply_rx) = oneshot::channel();
+                let call = self.call_with_options(service_method.to_string(), args, duration, false, metadata, Some(reply_tx));
+                CallWithMetadata::new(call, reply_rx)
+            }
+
+            fn call_with_timeout<Req, Res>(&self, service_method: String, args: Req, duration: Duration, sink: bool) -> Call<Res>
+            where
+                Req: serde::Serialize + Send + Sync + 'static,
+                Res: serde::de::DeserializeOwned + Send + 'static,
+            {
+                self.call_with_options(service_method, args, duration, sink, Metadata::new(), None)
+            }
+
+            fn call_with_options<Req, Res>(
+                &self,
+                service_method: String,
+                args: Req,
+                duration: Duration,
+                sink: bool,
+                metadata: Metadata,
+                reply_metadata: Option<oneshot::Sender<Metadata>>,
+            ) -> Call<Res>
             where
                 Req: serde::Serialize + Send + Sync + 'static,
                 Res: serde::de::DeserializeOwned + Send + 'static,
             {
                 // Prepare RPC request
                 // let id = self.count.load(Ordering::Relaxed) as MessageId;
-                let id = self.count.fetch_add(1, Ordering::Relaxed);
-                let service_method = service_method.to_string();
-                let duration = match self.next_timeout.swap(None) {
-                    Some(dur) => dur,
-                    None => self.default_timeout.clone()
-                };
-                let body = Box::new(args) as Box<OutboundBody>;
+                let id = unexpected::reserve_id(&self.count, &self.reserved);
+                let body = Arc::new(args) as Arc<OutboundBody>;
                 let (resp_tx, resp_rx) = oneshot::channel();
 
                 if let Err(err) = self.broker.send(
@@ -397,14 +1142,192 @@ cfg_if! {
                         duration,
                         body,
                         resp_tx,
+                        sink,
+                        metadata,
+                        reply_metadata,
                     }
                 ) {
-                    log::error!("{:?}", err);
+                    error!("{:?}", err);
                 }
 
                 // Creates Call
                 Call::<Res>::new(id, self.broker.clone(), resp_rx)
             }
+
+            /// Invokes the named RPC function that returns a `RpcStream` and returns a
+            /// `CallStream` of its items. See the `streaming` module for details.
+            ///
+            /// The timeout of the call applies to the method returning the stream on the
+            /// server, but not to the items of the stream.
+            ///
+            /// Example
+            ///
+            /// ```no_run
+            /// # use futures::StreamExt;
+            /// # use toy_rpc::client::CallStream;
+            /// # use toy_rpc::{Client, Error};
+            /// # async fn run(client: Client) -> Result<(), Error> {
+            /// let mut readings: CallStream<f64> = client.call_stream("Sensor.readings", 10usize);
+            /// while let Some(reading) = readings.next().await {
+            ///     println!("{}", reading?);
+            /// }
+            /// # Ok(())
+            /// # }
+            /// ```
+            pub fn call_stream<Req, Res>(&self, service_method: impl ToString, args: Req) -> CallStream<Res>
+            where
+                Req: serde::Serialize + Send + Sync + 'static,
+                Res: serde::de::DeserializeOwned + Send + 'static,
+            {
+                let duration = self.take_timeout();
+                self.call_stream_with_timeout(service_method.to_string(), args, duration, false)
+            }
+
+            fn call_stream_with_timeout<Req, Res>(&self, service_method: String, args: Req, duration: Duration, sink: bool) -> CallStream<Res>
+            where
+                Req: serde::Serialize + Send + Sync + 'static,
+                Res: serde::de::DeserializeOwned + Send + 'static,
+            {
+                let id = unexpected::reserve_id(&self.count, &self.reserved);
+                let body = Arc::new(args) as Arc<OutboundBody>;
+                let (items, items_rx) = flume::unbounded();
+
+                if let Err(err) = self.broker.send(
+                    ClientBrokerItem::StreamRequest {
+                        id,
+                        service_method,
+                        duration,
+                        body,
+                        items,
+                        sink,
+                    }
+                ) {
+                    error!("{:?}", err);
+                }
+
+                CallStream::<Res>::new(id, self.broker.clone(), items_rx)
+            }
+
+            /// Invokes the named RPC function that takes a `RequestStream`. Returns the
+            /// `ClientSink` to send the items of the argument and the `Call` of the
+            /// response. See the `streaming` module for details.
+            ///
+            /// The timeout of the call applies to the whole call, including the time
+            /// spent sending the items.
+            ///
+            /// Example
+            ///
+            /// ```no_run
+            /// # use toy_rpc::{Client, Error};
+            /// # async fn run(client: Client) -> Result<(), Error> {
+            /// let (numbers, sum) = client.call_with_sink::<i64, i64>("Stats.sum");
+            /// for number in 1..=10 {
+            ///     numbers.send(number)?;
+            /// }
+            /// numbers.finish()?;
+            /// assert_eq!(sum.await?, 55);
+            /// # Ok(())
+            /// # }
+            /// ```
+            pub fn call_with_sink<T, Res>(&self, service_method: impl ToString) -> (ClientSink<T>, Call<Res>)
+            where
+                T: serde::Serialize + Send + Sync + 'static,
+                Res: serde::de::DeserializeOwned + Send + 'static,
+            {
+                let duration = self.take_timeout();
+                let call = self.call_with_timeout(service_method.to_string(), (), duration, true);
+                (ClientSink::new(call.get_id(), self.broker.clone()), call)
+            }
+
+            /// Invokes the named RPC function that takes a `RequestStream` and returns a
+            /// `RpcStream`. Returns the `ClientSink` to send the items of the argument and
+            /// the `CallStream` of the items of the response. See the `streaming` module
+            /// for details.
+            ///
+            /// Example
+            ///
+            /// ```no_run
+            /// # use futures::StreamExt;
+            /// # use toy_rpc::{Client, Error};
+            /// # async fn run(client: Client) -> Result<(), Error> {
+            /// let (numbers, mut sums) = client.call_stream_with_sink::<i64, i64>("Stats.running_sum");
+            /// numbers.send(1)?;
+            /// assert_eq!(sums.next().await.unwrap()?, 1);
+            /// numbers.send(2)?;
+            /// assert_eq!(sums.next().await.unwrap()?, 3);
+            /// # Ok(())
+            /// # }
+            /// ```
+            pub fn call_stream_with_sink<T, Res>(&self, service_method: impl ToString) -> (ClientSink<T>, CallStream<Res>)
+            where
+                T: serde::Serialize + Send + Sync + 'static,
+                Res: serde::de::DeserializeOwned + Send + 'static,
+            {
+                let duration = self.take_timeout();
+                let items = self.call_stream_with_timeout(service_method.to_string(), (), duration, true);
+                (ClientSink::new(items.get_id(), self.broker.clone()), items)
+            }
+
+            /// Returns the timeout of the next call and resets it to the default timeout
+            fn take_timeout(&self) -> Duration {
+                match self.next_timeout.swap(None) {
+                    Some(dur) => dur,
+                    None => self.default_timeout
+                }
+            }
+
+            /// Overrides the timeout of the calls made through the returned `WithTimeout`.
+            /// Unlike `set_next_timeout`, the timeout cannot be taken by a call made
+            /// concurrently from another task.
+            ///
+            /// Example
+            ///
+            /// ```no_run
+            /// # use toy_rpc::{Client, Error};
+            /// # async fn run(client: Client) {
+            /// let reply: Result<(), Error> = client
+            ///     .timeout(std::time::Duration::from_secs(2))
+            ///     .call("Service.wait_for_10secs", ())
+            ///     .await; // Err(Error::Timeout(Some(call_id)))
+            /// # }
+            /// ```
+            pub fn timeout(&self, duration: Duration) -> WithTimeout<'_> {
+                WithTimeout {
+                    client: self,
+                    duration,
+                }
+            }
+        }
+
+        /// Makes calls with a timeout that overrides the default timeout of the client.
+        /// This is created by `Client::timeout`.
+        pub struct WithTimeout<'c> {
+            client: &'c Client,
+            duration: Duration,
+        }
+
+        impl<'c> WithTimeout<'c> {
+            /// Invokes the named RPC function call asynchronously with the timeout. See
+            /// `Client::call` for details.
+            pub fn call<Req, Res>(&self, service_method: impl ToString, args: Req) -> Call<Res>
+            where
+                Req: serde::Serialize + Send + Sync + 'static,
+                Res: serde::de::DeserializeOwned + Send + 'static,
+            {
+                self.client
+                    .call_with_timeout(service_method.to_string(), args, self.duration, false)
+            }
+
+            /// Invokes the named RPC function that returns a `RpcStream` with the timeout.
+            /// See `Client::call_stream` for details.
+            pub fn call_stream<Req, Res>(&self, service_method: impl ToString, args: Req) -> CallStream<Res>
+            where
+                Req: serde::Serialize + Send + Sync + 'static,
+                Res: serde::de::DeserializeOwned + Send + 'static,
+            {
+                self.client
+                    .call_stream_with_timeout(service_method.to_string(), args, self.duration, false)
+            }
         }
     }
 }
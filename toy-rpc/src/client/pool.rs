@@ -0,0 +1,236 @@
+//! Pool of connections to the same server
+//!
+//! A `Client` multiplexes all its calls over a single connection, which becomes a
+//! bottleneck when many tasks make concurrent calls with large payloads, since a
+//! large request or response delays all the others behind it. A `ClientPool` keeps
+//! several connections to the same server and distributes the calls across them
+//! according to its `PoolStrategy`.
+//!
+//! Only the calls made with `ClientPool::call` and `ClientPool::call_stream` are
+//! counted by `PoolStrategy::LeastPending`. A call is counted until it is awaited to
+//! completion or dropped, and a streaming call until its stream is dropped.
+//!
+//! # Example
+//!
+//! ```no_run
+//! # use toy_rpc::Client;
+//! # use toy_rpc::client::pool::{ClientPool, PoolStrategy};
+//! # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+//! let pool = ClientPool::connect(4, || Client::dial("127.0.0.1:23333"))
+//!     .await?
+//!     .strategy(PoolStrategy::LeastPending);
+//!
+//! let reply: i32 = pool.call("Arith.add", (1i32, 6i32)).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use futures::{Future, Stream};
+use serde::de::DeserializeOwned;
+use std::{
+    ops::{Deref, DerefMut},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+};
+
+use super::{Call, CallStream, Client};
+use crate::Error;
+
+/// How a `ClientPool` chooses the connection of a call
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PoolStrategy {
+    /// Uses the connections one after the other
+    #[default]
+    RoundRobin,
+    /// Uses the connection with the fewest calls in flight
+    LeastPending,
+}
+
+/// Connections to the same server that share the calls. See the `pool` module for
+/// details.
+pub struct ClientPool {
+    clients: Vec<Client>,
+    pending: Vec<Arc<AtomicUsize>>,
+    next: AtomicUsize,
+    strategy: PoolStrategy,
+}
+
+impl ClientPool {
+    /// Creates a pool of the connections of `clients` with `PoolStrategy::RoundRobin`.
+    /// Returns an error if `clients` is empty.
+    pub fn new(clients: Vec<Client>) -> Result<Self, Error> {
+        if clients.is_empty() {
+            return Err(Error::Internal(
+                "A ClientPool needs at least one connection".into(),
+            ));
+        }
+        let pending = clients
+            .iter()
+            .map(|_| Arc::new(AtomicUsize::new(0)))
+            .collect();
+        Ok(Self {
+            clients,
+            pending,
+            next: AtomicUsize::new(0),
+            strategy: PoolStrategy::default(),
+        })
+    }
+
+    /// Opens `size` connections with `connect`, which is called once per connection
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use toy_rpc::Client;
+    /// # use toy_rpc::client::pool::ClientPool;
+    /// # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+    /// let pool = ClientPool::connect(4, || Client::dial_websocket("ws://127.0.0.1:23333")).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn connect<F, Fut>(size: usize, connect: F) -> Result<Self, Error>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<Client, Error>>,
+    {
+        let mut clients = Vec::with_capacity(size);
+        for _ in 0..size {
+            clients.push(connect().await?);
+        }
+        Self::new(clients)
+    }
+
+    /// Sets how the connection of each call is chosen
+    pub fn strategy(self, strategy: PoolStrategy) -> Self {
+        let mut pool = self;
+        pool.strategy = strategy;
+        pool
+    }
+
+    /// Returns the number of connections
+    pub fn size(&self) -> usize {
+        self.clients.len()
+    }
+
+    /// Returns the number of calls in flight on each connection
+    pub fn pending(&self) -> Vec<usize> {
+        self.pending
+            .iter()
+            .map(|count| count.load(Ordering::Relaxed))
+            .collect()
+    }
+
+    /// Returns the client of the connection chosen by the strategy. The calls made
+    /// directly on the client are not counted by `PoolStrategy::LeastPending`.
+    pub fn get(&self) -> &Client {
+        &self.clients[self.choose()]
+    }
+
+    fn choose(&self) -> usize {
+        let size = self.clients.len();
+        let start = self.next.fetch_add(1, Ordering::Relaxed) % size;
+        match self.strategy {
+            PoolStrategy::RoundRobin => start,
+            // starting from the next connection spreads the calls over the
+            // connections with the same number of calls in flight
+            PoolStrategy::LeastPending => (0..size)
+                .map(|offset| (start + offset) % size)
+                .min_by_key(|&index| self.pending[index].load(Ordering::Relaxed))
+                .unwrap_or(start),
+        }
+    }
+
+    fn track<T>(&self, index: usize, inner: T) -> Pooled<T> {
+        let pending = self.pending[index].clone();
+        pending.fetch_add(1, Ordering::Relaxed);
+        Pooled {
+            inner,
+            _slot: Slot(pending),
+        }
+    }
+
+    /// Invokes the named RPC function on one of the connections. See `Client::call`
+    /// for details.
+    pub fn call<Req, Res>(&self, service_method: impl ToString, args: Req) -> Pooled<Call<Res>>
+    where
+        Req: serde::Serialize + Send + Sync + 'static,
+        Res: DeserializeOwned + Send + 'static,
+    {
+        let index = self.choose();
+        self.track(index, self.clients[index].call(service_method, args))
+    }
+
+    /// Invokes the named RPC function that returns a `RpcStream` on one of the
+    /// connections. See `Client::call_stream` for details.
+    pub fn call_stream<Req, Res>(
+        &self,
+        service_method: impl ToString,
+        args: Req,
+    ) -> Pooled<CallStream<Res>>
+    where
+        Req: serde::Serialize + Send + Sync + 'static,
+        Res: DeserializeOwned + Send + 'static,
+    {
+        let index = self.choose();
+        self.track(index, self.clients[index].call_stream(service_method, args))
+    }
+
+    /// Closes all the connections
+    pub async fn close(self) {
+        for client in self.clients {
+            client.close().await;
+        }
+    }
+}
+
+/// Counts a call in flight until it is dropped
+struct Slot(Arc<AtomicUsize>);
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// A `Call` or a `CallStream` made through a `ClientPool`. It derefs to the call, so
+/// that it can be canceled in the same way.
+#[pin_project::pin_project]
+pub struct Pooled<T> {
+    #[pin]
+    inner: T,
+    _slot: Slot,
+}
+
+impl<T> Deref for Pooled<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+impl<T> DerefMut for Pooled<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.inner
+    }
+}
+
+impl<T: Future> Future for Pooled<T> {
+    type Output = T::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.project().inner.poll(cx)
+    }
+}
+
+impl<T: Stream> Stream for Pooled<T> {
+    type Item = T::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.project().inner.poll_next(cx)
+    }
+}
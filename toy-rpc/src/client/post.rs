@@ -0,0 +1,358 @@
+//! Client side of the HTTP POST transport
+//!
+//! The requests are plain HTTP/1.1 requests, each one on its own TCP connection, so
+//! that they go through the proxies that block the WebSocket upgrades. See the
+//! `transport::post` module for the protocol.
+
+use flume::{Receiver, Sender};
+use futures::future::{select, Either};
+use futures::stream::FuturesUnordered;
+use futures::{AsyncReadExt, AsyncWriteExt, StreamExt};
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Duration;
+
+use crate::clock;
+use crate::codec::{Codec, ConnTypePayload};
+use crate::error::Error;
+use crate::transport::post::{
+    self, ChannelReader, ChannelWriter, CLOSE_HEADER, INPUT_HEADER, MAX_BODY_LEN,
+    MAX_PENDING_INPUTS, POLL_TIMEOUT, SEQ_HEADER, SESSION_HEADER,
+};
+
+/// Time that a request may take on top of the time the server may hold it
+const REQUEST_MARGIN: Duration = Duration::from_secs(10);
+
+/// Codec of a connection over the HTTP POST transport
+pub(crate) type PostCodec = Codec<ChannelReader, ChannelWriter, ConnTypePayload>;
+
+/// Where the requests of a session are sent
+#[derive(Debug, Clone)]
+struct Endpoint {
+    host: String,
+    port: u16,
+    path: String,
+}
+
+/// Response to a request of a session
+struct Reply {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Reply {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn seq(&self) -> Result<u64, Error> {
+        self.header(SEQ_HEADER)
+            .and_then(|seq| seq.parse().ok())
+            .ok_or_else(|| Error::Internal("Missing sequence number in HTTP POST response".into()))
+    }
+}
+
+fn invalid_response() -> Error {
+    Error::Internal("Invalid HTTP POST response".into())
+}
+
+impl Endpoint {
+    fn new(url: &url::Url) -> Result<Self, Error> {
+        let (host, port) = super::ws_addr(url)?;
+        let mut path = url.path().to_string();
+        if let Some(query) = url.query() {
+            path.push('?');
+            path.push_str(query);
+        }
+        Ok(Self {
+            host: host.to_string(),
+            port,
+            path,
+        })
+    }
+
+    /// Sends a request with the headers of the protocol in `headers` and waits for
+    /// its response
+    async fn send(
+        self,
+        headers: Vec<(&'static str, String)>,
+        body: Vec<u8>,
+    ) -> Result<Reply, Error> {
+        let exchange = async {
+            let mut stream = connect(&self.host, self.port).await?;
+            let mut request = format!(
+                "POST {} HTTP/1.1\r\nHost: {}:{}\r\nConnection: close\r\n\
+                Content-Type: application/octet-stream\r\nContent-Length: {}\r\n",
+                self.path,
+                self.host,
+                self.port,
+                body.len()
+            );
+            for (name, value) in &headers {
+                request.push_str(&format!("{}: {}\r\n", name, value));
+            }
+            request.push_str("\r\n");
+            let mut bytes = request.into_bytes();
+            bytes.extend_from_slice(&body);
+            stream.write_all(&bytes).await?;
+            stream.flush().await?;
+
+            // every request has its own connection, which the server closes after
+            // the response
+            let mut response = Vec::new();
+            stream.read_to_end(&mut response).await?;
+            parse_response(&response)
+        };
+        clock::timeout(POLL_TIMEOUT + REQUEST_MARGIN, exchange)
+            .await
+            .map_err(|_| Error::Timeout(None))?
+    }
+}
+
+#[cfg(all(feature = "tokio_runtime", not(feature = "async_std_runtime")))]
+async fn connect(
+    host: &str,
+    port: u16,
+) -> Result<impl futures::AsyncRead + futures::AsyncWrite + Unpin, Error> {
+    let stream = ::tokio::net::TcpStream::connect((host, port))
+        .await
+        .map_err(Error::from_connect)?;
+    Ok(async_tungstenite::tokio::TokioAdapter::new(stream))
+}
+
+#[cfg(all(feature = "async_std_runtime", not(feature = "tokio_runtime")))]
+async fn connect(
+    host: &str,
+    port: u16,
+) -> Result<impl futures::AsyncRead + futures::AsyncWrite + Unpin, Error> {
+    ::async_std::net::TcpStream::connect((host, port))
+        .await
+        .map_err(Error::from_connect)
+}
+
+fn parse_response(response: &[u8]) -> Result<Reply, Error> {
+    let end = response
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .ok_or_else(invalid_response)?;
+    let head = std::str::from_utf8(&response[..end]).map_err(|_| invalid_response())?;
+    let mut lines = head.split("\r\n");
+    let status = lines
+        .next()
+        .and_then(|line| line.split(' ').nth(1))
+        .and_then(|status| status.parse().ok())
+        .ok_or_else(invalid_response)?;
+    let headers = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+        .collect();
+    let mut reply = Reply {
+        status,
+        headers,
+        body: Vec::new(),
+    };
+
+    let body = &response[end + 4..];
+    reply.body = if reply
+        .header("transfer-encoding")
+        .is_some_and(|encoding| encoding.eq_ignore_ascii_case("chunked"))
+    {
+        decode_chunked(body)?
+    } else {
+        match reply.header("content-length").map(str::parse::<usize>) {
+            Some(Ok(len)) => body.get(..len).ok_or_else(invalid_response)?.to_vec(),
+            Some(Err(_)) => return Err(invalid_response()),
+            None => body.to_vec(),
+        }
+    };
+    Ok(reply)
+}
+
+/// Decodes a body with the chunked transfer encoding, which a proxy may use
+fn decode_chunked(mut body: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut decoded = Vec::new();
+    loop {
+        let line_end = body
+            .windows(2)
+            .position(|window| window == b"\r\n")
+            .ok_or_else(invalid_response)?;
+        let size = std::str::from_utf8(&body[..line_end])
+            .ok()
+            .and_then(|line| line.split(';').next())
+            .and_then(|size| usize::from_str_radix(size.trim(), 16).ok())
+            .ok_or_else(invalid_response)?;
+        body = &body[line_end + 2..];
+        if size == 0 {
+            return Ok(decoded);
+        }
+        decoded.extend_from_slice(body.get(..size).ok_or_else(invalid_response)?);
+        body = body.get(size + 2..).ok_or_else(invalid_response)?;
+    }
+}
+
+/// Opens a session at `url` and returns the codec of its connection. The requests
+/// of the session are sent by a task that runs until the session is closed.
+pub(crate) async fn open(url: url::Url) -> Result<PostCodec, Error> {
+    if matches!(url.scheme(), "https" | "wss") {
+        return Err(Error::Internal(
+            "TLS is not supported by the HTTP POST transport".into(),
+        ));
+    }
+    let endpoint = Endpoint::new(&url)?;
+    let reply = endpoint.clone().send(Vec::new(), Vec::new()).await?;
+    if reply.status != 200 {
+        return Err(Error::Internal(
+            format!("HTTP POST session refused with status {}", reply.status).into(),
+        ));
+    }
+    let session = reply
+        .header(SESSION_HEADER)
+        .ok_or_else(|| Error::Internal("Missing session in HTTP POST response".into()))?
+        .to_string();
+
+    let (reader, writer, inbound, outbound) = post::channel();
+    crate::util::spawn_task(drive(endpoint, session, inbound, outbound));
+    Ok(PostCodec::with_post_session(reader, writer))
+}
+
+type PendingReply = Pin<Box<dyn Future<Output = Result<Reply, Error>> + Send>>;
+
+enum Event {
+    Outgoing(Option<Vec<u8>>),
+    Reply(Result<Reply, Error>),
+}
+
+/// Sends the payloads written by the client and passes the payloads of the
+/// responses to the client in order, keeping a request pending at all times
+async fn drive(
+    endpoint: Endpoint,
+    session: String,
+    inbound: Sender<Vec<u8>>,
+    outbound: Receiver<Vec<u8>>,
+) {
+    let request = |input: Option<u64>, close: bool, body: Vec<u8>| -> PendingReply {
+        let mut headers = vec![(SESSION_HEADER, session.clone())];
+        if let Some(input) = input {
+            headers.push((INPUT_HEADER, input.to_string()));
+        }
+        if close {
+            headers.push((CLOSE_HEADER, String::from("1")));
+        }
+        Box::pin(endpoint.clone().send(headers, body))
+    };
+
+    let mut requests = FuturesUnordered::new();
+    let mut input = 0;
+    let mut next_seq = 1;
+    let mut replies = BTreeMap::new();
+    let mut closing = false;
+    // a payload left out of the last request, which would have made its body too long
+    let mut held = None;
+    loop {
+        let event = if closing {
+            // the requests sent before the one that closes the session still have
+            // to reach the server
+            match requests.next().await {
+                Some(reply) => Event::Reply(reply),
+                None => return,
+            }
+        } else if requests.len() >= MAX_PENDING_INPUTS as usize {
+            // the server refuses the requests too far ahead of those it has read
+            match requests.next().await {
+                Some(reply) => Event::Reply(reply),
+                None => continue,
+            }
+        } else if held.is_some() {
+            Event::Outgoing(held.take())
+        } else {
+            if requests.is_empty() {
+                // an empty request that the server holds until it has something to write
+                requests.push(request(None, false, Vec::new()));
+            }
+            match select(outbound.recv_async(), requests.next()).await {
+                Either::Left((payload, _)) => Event::Outgoing(payload.ok()),
+                Either::Right((Some(reply), _)) => Event::Reply(reply),
+                Either::Right((None, _)) => continue,
+            }
+        };
+
+        match event {
+            Event::Outgoing(Some(payload)) => {
+                let mut len = 4 + payload.len();
+                let mut payloads = vec![payload];
+                for payload in outbound.try_iter() {
+                    len += 4 + payload.len();
+                    if len > MAX_BODY_LEN {
+                        held = Some(payload);
+                        break;
+                    }
+                    payloads.push(payload);
+                }
+                input += 1;
+                requests.push(request(Some(input), false, post::encode_batch(&payloads)));
+            }
+            Event::Outgoing(None) => {
+                // the client is closed, and the session is closed once the server has
+                // read the payloads sent before
+                input += 1;
+                requests.push(request(Some(input), true, Vec::new()));
+                closing = true;
+            }
+            Event::Reply(Ok(reply)) if reply.status == 200 => {
+                if reply.header(CLOSE_HEADER).is_some() {
+                    return;
+                }
+                let seq = match reply.seq() {
+                    Ok(seq) => seq,
+                    Err(err) => return error!("{}", err),
+                };
+                match post::decode_batch(&reply.body) {
+                    Ok(payloads) => replies.insert(seq, payloads),
+                    Err(err) => return error!("{}", err),
+                };
+                while let Some(payloads) = replies.remove(&next_seq) {
+                    for payload in payloads {
+                        if inbound.send(payload).is_err() {
+                            return;
+                        }
+                    }
+                    next_seq += 1;
+                }
+            }
+            Event::Reply(Ok(reply)) => {
+                return error!("HTTP POST session ended with status {}", reply.status)
+            }
+            Event::Reply(Err(err)) => return error!("{}", err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn responses_are_parsed() {
+        let reply =
+            parse_response(b"HTTP/1.1 200 OK\r\nx-toy-rpc-seq: 3\r\ncontent-length: 4\r\n\r\nbody")
+                .unwrap();
+        assert_eq!(reply.status, 200);
+        assert_eq!(reply.seq().unwrap(), 3);
+        assert_eq!(reply.body, b"body");
+
+        let reply = parse_response(
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n2\r\nbo\r\n2;ext\r\ndy\r\n0\r\n\r\n",
+        )
+        .unwrap();
+        assert!(reply.seq().is_err());
+        assert_eq!(reply.body, b"body");
+
+        assert!(parse_response(b"HTTP/1.1 200 OK\r\ncontent-length: 5\r\n\r\nbody").is_err());
+        assert!(parse_response(b"HTTP/1.1 200 OK\r\n").is_err());
+    }
+}
@@ -0,0 +1,143 @@
+//! Calls prepared once and made many times
+//!
+//! A `PreparedCall` binds the name of the RPC function together with the
+//! metadata and the timeout of the request so that they are not rebuilt at every
+//! call site of a frequently called method.
+//!
+//! Example
+//!
+//! ```no_run
+//! # use toy_rpc::Client;
+//! # use toy_rpc::metadata::Metadata;
+//! # async fn run(client: Client) -> Result<(), Box<dyn std::error::Error>> {
+//! let mut auth = Metadata::new();
+//! auth.insert("authorization".into(), "Bearer 8a1c".into());
+//! let echo = client
+//!     .prepare("Echo.echo_i32")
+//!     .bind_meta(auth)
+//!     .timeout(std::time::Duration::from_secs(2));
+//! for i in 0..10i32 {
+//!     let reply: i32 = echo.call(i).await?;
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::time::Duration;
+
+use futures::channel::oneshot;
+
+#[cfg(all(feature = "codec_negotiation", not(feature = "serde_json")))]
+use crate::codec::negotiate::{ContentType, ACCEPT};
+use crate::metadata::Metadata;
+
+use super::{Call, CallWithMetadata, Client};
+
+/// The name, metadata and timeout of a request that can be invoked many times
+/// with different arguments
+///
+/// Created with `Client::prepare`.
+pub struct PreparedCall<'c> {
+    client: &'c Client,
+    service_method: String,
+    metadata: Metadata,
+    timeout: Option<Duration>,
+}
+
+impl<'c> PreparedCall<'c> {
+    pub(crate) fn new(client: &'c Client, service_method: String) -> Self {
+        Self {
+            client,
+            service_method,
+            metadata: Metadata::new(),
+            timeout: None,
+        }
+    }
+
+    /// Adds `metadata` to the metadata sent with every call. An entry replaces
+    /// a previously bound entry with the same key.
+    pub fn bind_meta(self, metadata: Metadata) -> Self {
+        let mut builder = self;
+        builder.metadata.extend(metadata);
+        builder
+    }
+
+    /// Adds a single entry to the metadata sent with every call
+    pub fn meta(self, key: impl ToString, value: impl ToString) -> Self {
+        let mut builder = self;
+        builder.metadata.insert(key.to_string(), value.to_string());
+        builder
+    }
+
+    /// Asks the server to encode the responses with `content_type` instead of the
+    /// content type of the connection. See the `codec::negotiate` module.
+    #[cfg(all(feature = "codec_negotiation", not(feature = "serde_json")))]
+    #[cfg_attr(
+        feature = "docs",
+        doc(cfg(all(feature = "codec_negotiation", not(feature = "serde_json"))))
+    )]
+    pub fn accept(self, content_type: ContentType) -> Self {
+        self.meta(ACCEPT, content_type.as_str())
+    }
+
+    /// Sets the timeout of every call. The timeout of the client is used if
+    /// none is set.
+    pub fn timeout(self, duration: Duration) -> Self {
+        let mut builder = self;
+        builder.timeout = Some(duration);
+        builder
+    }
+
+    /// Returns the name of the prepared RPC function
+    pub fn service_method(&self) -> &str {
+        &self.service_method
+    }
+
+    /// Returns the metadata sent with every call
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    fn duration(&self) -> Duration {
+        match self.timeout {
+            Some(duration) => duration,
+            None => self.client.take_timeout(),
+        }
+    }
+
+    /// Invokes the prepared RPC function with `args`. See `Client::call` for details.
+    pub fn call<Req, Res>(&self, args: Req) -> Call<Res>
+    where
+        Req: serde::Serialize + Send + Sync + 'static,
+        Res: serde::de::DeserializeOwned + Send + 'static,
+    {
+        self.client.call_with_options(
+            self.service_method.clone(),
+            args,
+            self.duration(),
+            false,
+            self.metadata.clone(),
+            None,
+        )
+    }
+
+    /// Invokes the prepared RPC function with `args` and yields the reply along
+    /// with the metadata of the response. See `Client::call_with_metadata` for
+    /// details.
+    pub fn call_with_metadata<Req, Res>(&self, args: Req) -> CallWithMetadata<Res>
+    where
+        Req: serde::Serialize + Send + Sync + 'static,
+        Res: serde::de::DeserializeOwned + Send + 'static,
+    {
+        let (reply_tx, reply_rx) = oneshot::channel();
+        let call = self.client.call_with_options(
+            self.service_method.clone(),
+            args,
+            self.duration(),
+            false,
+            self.metadata.clone(),
+            Some(reply_tx),
+        );
+        CallWithMetadata::new(call, reply_rx)
+    }
+}
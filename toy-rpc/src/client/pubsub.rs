@@ -1,26 +1,164 @@
 //! PubSub impl on the client side
+//!
+//! Multiple local subscribers on the same topic share a single subscription on the
+//! server. Each of them has its own buffer and `DropPolicy`, so a slow subscriber
+//! does not hold back the others.
+//!
+//! Errors are delivered on the subscriber stream as `Err` items. When the server
+//! rejects the subscription, every local subscriber on the topic receives
+//! `Error::TopicRejected` and then ends. What happens after an item fails to
+//! deserialize, or has a type tag that does not match the item type of the
+//! subscriber, is controlled by the `ErrorPolicy` of the subscriber.
+//!
+//! A `PatternSubscriber` receives the items of every topic that matches a topic
+//! pattern (see the `pubsub` module for the syntax). The topics may have different
+//! item types, so it is not tied to a `Topic` and yields the name of the topic along
+//! with the item deserialized into any type, such as `serde_json::Value`. Type tags
+//! are not checked by a pattern subscriber.
+//!
+//! A `NamedPublisher` and a `NamedSubscriber` work like a `Publisher` and a
+//! `Subscriber` on a topic whose name is only known at runtime, such as a topic
+//! per tenant. They are created with `Client::publisher_str` and
+//! `Client::subscriber_str` from the name of the topic and its item type. Their type
+//! tags are the same as the ones of the topics with the same item type.
+//!
+//! A publisher created `with_stats` records the outcome of every publication that
+//! is published with `publish_with_ack` in its `PublisherStats`: the time until the
+//! server has accepted it, and the numbers of subscribers that it was delivered to
+//! and that dropped it as reported by the server. A producer can read the stats
+//! from another task to adapt its rate. The publications sent through the `Sink`
+//! are not acknowledged and therefore not recorded.
 
 use flume::r#async::{RecvStream, SendSink};
+#[cfg(any(
+    all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+    all(feature = "async_std_runtime", not(feature = "tokio_runtime"))
+))]
+use flume::TrySendError;
 use flume::{Receiver, Sender};
-use futures::{Sink, Stream};
+use futures::channel::oneshot;
+use futures::{Sink, SinkExt, Stream};
 use pin_project::pin_project;
+use serde::{de::DeserializeOwned, Serialize};
 use std::any::TypeId;
 use std::marker::PhantomData;
 use std::pin::Pin;
+use std::sync::{Arc, Mutex, Weak};
 use std::task::{Context, Poll};
+use std::time::Duration;
 
 use super::{broker::ClientBrokerItem, Client};
 use crate::{
+    clock,
     error::Error,
+    message::ErrorMessage,
     protocol::{InboundBody, OutboundBody},
-    pubsub::Topic,
+    pubsub::{
+        self, check_item_type_tag, check_type_tag, item_type_tag, type_tag, PublishReport, Topic,
+    },
 };
 
+/// Outcomes of the publications of a publisher that are published with
+/// `publish_with_ack`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PublishStats {
+    /// Number of publications accepted by the server
+    pub acked: u64,
+    /// Number of publications rejected by the server or canceled before they were
+    /// accepted
+    pub failed: u64,
+    /// Number of times a publication, or the oldest item of a subscriber, was dropped
+    /// by a full subscriber, as reported by the server
+    pub dropped: u64,
+    /// Number of subscribers that the last accepted publication was delivered to, as
+    /// reported by the server. This is `None` until a server that reports the
+    /// deliveries has accepted a publication.
+    pub subscribers: Option<u32>,
+    /// Time from publishing to the acceptance of the last accepted publication
+    pub last_latency: Option<Duration>,
+    /// Mean time from publishing to the acceptance of the accepted publications
+    pub mean_latency: Option<Duration>,
+    /// Longest time from publishing to the acceptance of a publication
+    pub max_latency: Option<Duration>,
+}
+
+/// Handle to the `PublishStats` of a publisher, which can be cloned and read from
+/// another task
+#[derive(Debug, Clone, Default)]
+pub struct PublisherStats {
+    inner: Arc<Mutex<(PublishStats, Duration)>>,
+}
+
+impl PublisherStats {
+    /// Returns the stats recorded so far
+    pub fn snapshot(&self) -> PublishStats {
+        match self.inner.lock() {
+            Ok(inner) => inner.0,
+            Err(poisoned) => poisoned.into_inner().0,
+        }
+    }
+
+    /// Records the outcome of a publication, which took `latency` to be accepted
+    fn record(&self, result: &Result<Option<PublishReport>, Error>, latency: Duration) {
+        let mut inner = match self.inner.lock() {
+            Ok(inner) => inner,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let (stats, total_latency) = &mut *inner;
+        let report = match result {
+            Ok(report) => report,
+            Err(_) => {
+                stats.failed += 1;
+                return;
+            }
+        };
+        stats.acked += 1;
+        *total_latency += latency;
+        stats.last_latency = Some(latency);
+        let mean = total_latency.as_nanos() / stats.acked as u128;
+        stats.mean_latency = Some(Duration::from_nanos(mean as u64));
+        stats.max_latency = stats.max_latency.max(Some(latency));
+        if let Some(report) = report {
+            stats.dropped += report.dropped as u64;
+            stats.subscribers = Some(report.subscribers);
+        }
+    }
+}
+
+/// Publishes an item that the server acknowledges and records its outcome in `stats`
+async fn publish_acked(
+    inner: &mut SendSink<'static, ClientBrokerItem>,
+    topic: String,
+    body: Box<OutboundBody>,
+    tag: Option<u64>,
+    stats: Option<&PublisherStats>,
+) -> Result<(), Error> {
+    let (acked, rx) = oneshot::channel();
+    let item = ClientBrokerItem::Publish {
+        topic,
+        body,
+        tag,
+        acked: Some(acked),
+    };
+    let start = clock::now();
+    inner.send(item).await?;
+    let result = match rx.await {
+        Ok(result) => result,
+        Err(_) => Err(Error::Canceled(None)),
+    };
+    if let Some(stats) = stats {
+        stats.record(&result, clock::now().saturating_duration_since(start));
+    }
+    result.map(|_| ())
+}
+
 /// Publisher of topic T on the client side
 #[pin_project]
 pub struct Publisher<T: Topic> {
     #[pin]
     inner: SendSink<'static, ClientBrokerItem>,
+    tag: Option<u64>,
+    stats: Option<PublisherStats>,
     marker: PhantomData<T>,
 }
 
@@ -28,11 +166,81 @@ impl<T: Topic> From<Sender<ClientBrokerItem>> for Publisher<T> {
     fn from(inner: Sender<ClientBrokerItem>) -> Self {
         Self {
             inner: inner.into_sink(),
+            tag: None,
+            stats: None,
             marker: PhantomData,
         }
     }
 }
 
+impl<T: Topic> Publisher<T> {
+    /// Includes the type tag of `T::Item` with every published item so that the
+    /// subscribers can verify the item type. See `pubsub::type_tag`.
+    pub fn with_type_tag(mut self) -> Self {
+        self.tag = Some(type_tag::<T>());
+        self
+    }
+
+    /// Records the outcomes of the publications published with `publish_with_ack`
+    /// in a `PublisherStats`, which is returned by `stats`
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use toy_rpc::Client;
+    /// # use toy_rpc::pubsub::Topic;
+    /// # #[derive(serde::Serialize, serde::Deserialize)]
+    /// # struct Count(u32);
+    /// # impl Topic for Count {
+    /// #     type Item = Count;
+    /// #     fn topic() -> String {
+    /// #         "count".into()
+    /// #     }
+    /// # }
+    /// # async fn run(client: Client) -> Result<(), Box<dyn std::error::Error>> {
+    /// let mut publisher = client.publisher::<Count>().with_stats();
+    /// let stats = publisher.stats().unwrap();
+    /// publisher.publish_with_ack(Count(7)).await?;
+    /// if stats.snapshot().dropped > 0 {
+    ///     // slow down
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_stats(mut self) -> Self {
+        self.stats = Some(PublisherStats::default());
+        self
+    }
+
+    /// Returns the handle to the stats of the publisher if it is created `with_stats`
+    pub fn stats(&self) -> Option<PublisherStats> {
+        self.stats.clone()
+    }
+
+    /// Publishes an item with at-least-once delivery and waits until the server
+    /// has accepted it.
+    ///
+    /// The subscribers acknowledge the item, and the server delivers it again to a
+    /// subscriber that has not acknowledged it within the redelivery timeout of the
+    /// server (see `ServerBuilder::redelivery_timeout`). A subscriber may therefore
+    /// receive the item more than once.
+    ///
+    /// Returns the error of the server if the topic is rejected, or
+    /// `Error::Canceled(None)` if the connection is closed before the server has
+    /// accepted the item.
+    pub async fn publish_with_ack(&mut self, item: T::Item) -> Result<(), Error> {
+        let body = Box::new(item);
+        publish_acked(
+            &mut self.inner,
+            T::topic(),
+            body,
+            self.tag,
+            self.stats.as_ref(),
+        )
+        .await
+    }
+}
+
 impl<T: Topic> Sink<T::Item> for Publisher<T> {
     type Error = Error;
 
@@ -45,7 +253,12 @@ impl<T: Topic> Sink<T::Item> for Publisher<T> {
         let this = self.project();
         let topic = T::topic();
         let body = Box::new(item) as Box<OutboundBody>;
-        let item = ClientBrokerItem::Publish { topic, body };
+        let item = ClientBrokerItem::Publish {
+            topic,
+            body,
+            tag: *this.tag,
+            acked: None,
+        };
         this.inner.start_send(item).map_err(|err| err.into())
     }
 
@@ -60,20 +273,266 @@ impl<T: Topic> Sink<T::Item> for Publisher<T> {
     }
 }
 
+/// Publisher of items of type `V` on a topic named at runtime
+#[pin_project]
+pub struct NamedPublisher<V> {
+    #[pin]
+    inner: SendSink<'static, ClientBrokerItem>,
+    topic: String,
+    tag: Option<u64>,
+    stats: Option<PublisherStats>,
+    marker: PhantomData<V>,
+}
+
+impl<V: Serialize + Send + Sync + 'static> NamedPublisher<V> {
+    /// Includes the type tag of `V` with every published item so that the
+    /// subscribers can verify the item type. See `pubsub::item_type_tag`.
+    pub fn with_type_tag(mut self) -> Self {
+        self.tag = Some(item_type_tag::<V>());
+        self
+    }
+
+    /// Records the outcomes of the publications published with `publish_with_ack`
+    /// in a `PublisherStats`. See `Publisher::with_stats`.
+    pub fn with_stats(mut self) -> Self {
+        self.stats = Some(PublisherStats::default());
+        self
+    }
+
+    /// Returns the handle to the stats of the publisher if it is created `with_stats`
+    pub fn stats(&self) -> Option<PublisherStats> {
+        self.stats.clone()
+    }
+
+    /// Returns the name of the topic
+    pub fn topic(&self) -> &str {
+        &self.topic
+    }
+
+    /// Publishes an item with at-least-once delivery and waits until the server
+    /// has accepted it. See `Publisher::publish_with_ack`.
+    pub async fn publish_with_ack(&mut self, item: V) -> Result<(), Error> {
+        let topic = self.topic.clone();
+        let body = Box::new(item);
+        publish_acked(&mut self.inner, topic, body, self.tag, self.stats.as_ref()).await
+    }
+}
+
+impl<V: Serialize + Send + Sync + 'static> Sink<V> for NamedPublisher<V> {
+    type Error = Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.project();
+        this.inner.poll_ready(cx).map_err(|err| err.into())
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: V) -> Result<(), Self::Error> {
+        let this = self.project();
+        let item = ClientBrokerItem::Publish {
+            topic: this.topic.clone(),
+            body: Box::new(item),
+            tag: *this.tag,
+            acked: None,
+        };
+        this.inner.start_send(item).map_err(|err| err.into())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.project();
+        this.inner.poll_flush(cx).map_err(|err| err.into())
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.project();
+        this.inner.poll_close(cx).map_err(|err| err.into())
+    }
+}
+
+/// What a local subscriber does with a new item when its buffer is full
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DropPolicy {
+    /// Drops the new item
+    #[default]
+    DropNewest,
+    /// Drops the oldest item in the buffer to make room for the new item
+    DropOldest,
+}
+
+/// What a local subscriber does after an item fails to deserialize or has a
+/// mismatched type tag
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ErrorPolicy {
+    /// Yields the error and keeps receiving items
+    #[default]
+    Continue,
+    /// Yields the error and ends the subscriber
+    Cancel,
+}
+
+/// An item delivered to a local subscriber
+#[cfg_attr(
+    all(not(feature = "tokio_runtime"), not(feature = "async_std_runtime")),
+    allow(dead_code)
+)]
+pub(crate) enum SubscriptionItem {
+    /// A publication, the topic it is published to and its type tag
+    Item(String, Box<InboundBody>, Option<u64>),
+    /// The server rejected the subscription. The body is an `ErrorMessage`.
+    Rejected(Box<InboundBody>),
+}
+
+/// Outcome of delivering an item to a local subscriber
+#[cfg(any(
+    all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+    all(feature = "async_std_runtime", not(feature = "tokio_runtime"))
+))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Delivery {
+    /// The item is in the buffer of the subscriber
+    Accepted,
+    /// The buffer of the subscriber is full and the item is dropped
+    Dropped,
+    /// The `Subscriber` is dropped
+    Closed,
+}
+
+/// A local subscriber as seen by the client broker
+#[cfg_attr(
+    all(not(feature = "tokio_runtime"), not(feature = "async_std_runtime")),
+    allow(dead_code)
+)]
+pub(crate) struct LocalSubscriber {
+    tx: Sender<SubscriptionItem>,
+    // shared with the `Subscriber` to drop the oldest item, which means the channel
+    // is never disconnected and `alive` is needed to tell if the `Subscriber` is dropped
+    rx: Receiver<SubscriptionItem>,
+    alive: Weak<()>,
+    policy: DropPolicy,
+}
+
+impl LocalSubscriber {
+    /// Creates a local subscriber and the receiving end of its buffer
+    fn new(cap: usize, policy: DropPolicy) -> (Self, Receiver<SubscriptionItem>, Arc<()>) {
+        let (tx, rx) = flume::bounded(cap);
+        let alive = Arc::new(());
+        let local = LocalSubscriber {
+            tx,
+            rx: rx.clone(),
+            alive: Arc::downgrade(&alive),
+            policy,
+        };
+        (local, rx, alive)
+    }
+
+    /// Delivers an item according to the drop policy
+    #[cfg(any(
+        all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+        all(feature = "async_std_runtime", not(feature = "tokio_runtime"))
+    ))]
+    pub fn deliver(&self, topic: &str, item: Box<InboundBody>, tag: Option<u64>) -> Delivery {
+        if self.alive.upgrade().is_none() {
+            return Delivery::Closed;
+        }
+        match self
+            .tx
+            .try_send(SubscriptionItem::Item(topic.to_string(), item, tag))
+        {
+            Ok(_) => Delivery::Accepted,
+            Err(TrySendError::Full(item)) => match self.policy {
+                DropPolicy::DropNewest => {
+                    debug!("Subscriber is full, dropping the newest item");
+                    Delivery::Dropped
+                }
+                DropPolicy::DropOldest => {
+                    debug!("Subscriber is full, dropping the oldest item");
+                    let _ = self.rx.try_recv();
+                    let _ = self.tx.try_send(item);
+                    Delivery::Accepted
+                }
+            },
+            Err(TrySendError::Disconnected(_)) => Delivery::Closed,
+        }
+    }
+
+    /// Delivers the rejection of the subscription, making room for it if the
+    /// buffer is full regardless of the drop policy
+    #[cfg(any(
+        all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+        all(feature = "async_std_runtime", not(feature = "tokio_runtime"))
+    ))]
+    pub fn reject(&self, error: Box<InboundBody>) {
+        let item = SubscriptionItem::Rejected(error);
+        if let Err(TrySendError::Full(item)) = self.tx.try_send(item) {
+            let _ = self.rx.try_recv();
+            let _ = self.tx.try_send(item);
+        }
+    }
+}
+
+/// Delivers an item to the local subscribers of a topic or a pattern, and removes
+/// the subscribers that are dropped. Returns whether any of them dropped the item.
+#[cfg(any(
+    all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+    all(feature = "async_std_runtime", not(feature = "tokio_runtime"))
+))]
+pub(crate) fn deliver_all(
+    subscribers: &mut Vec<LocalSubscriber>,
+    topic: &str,
+    bytes: &[u8],
+    from_bytes: fn(Vec<u8>) -> Box<InboundBody>,
+    tag: Option<u64>,
+) -> bool {
+    let mut dropped = false;
+    subscribers.retain(
+        |sub| match sub.deliver(topic, from_bytes(bytes.to_vec()), tag) {
+            Delivery::Accepted => true,
+            Delivery::Dropped => {
+                dropped = true;
+                true
+            }
+            Delivery::Closed => false,
+        },
+    );
+    dropped
+}
+
+/// Converts the rejection of a subscription into the error of the server
+fn rejection(mut body: Box<InboundBody>) -> Error {
+    match erased_serde::deserialize::<ErrorMessage>(&mut body) {
+        Ok(msg) => Error::from_err_msg(msg),
+        Err(err) => err.into(),
+    }
+}
+
 /// Subscriber of topic T on the client side
 #[pin_project]
 pub struct Subscriber<T: Topic> {
     #[pin]
-    inner: RecvStream<'static, Box<InboundBody>>,
+    inner: RecvStream<'static, SubscriptionItem>,
+    // taken when the subscriber is canceled so that the client broker removes it
+    alive: Option<Arc<()>>,
+    error_policy: ErrorPolicy,
     marker: PhantomData<T>,
 }
 
-impl<T: Topic> From<Receiver<Box<InboundBody>>> for Subscriber<T> {
-    fn from(rx: Receiver<Box<InboundBody>>) -> Self {
-        Self {
+impl<T: Topic> Subscriber<T> {
+    /// Creates a subscriber and its counterpart in the client broker
+    fn new(cap: usize, policy: DropPolicy) -> (Self, LocalSubscriber) {
+        let (local, rx, alive) = LocalSubscriber::new(cap, policy);
+        let sub = Self {
             inner: rx.into_stream(),
+            alive: Some(alive),
+            error_policy: ErrorPolicy::default(),
             marker: PhantomData,
-        }
+        };
+        (sub, local)
+    }
+
+    /// Sets what the subscriber does after an item fails to deserialize or has a
+    /// mismatched type tag
+    pub fn with_error_policy(mut self, policy: ErrorPolicy) -> Self {
+        self.error_policy = policy;
+        self
     }
 }
 
@@ -82,13 +541,149 @@ impl<T: Topic> Stream for Subscriber<T> {
 
     fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
         let this = self.project();
+        if this.alive.is_none() {
+            return Poll::Ready(None);
+        }
         match this.inner.poll_next(cx) {
             Poll::Pending => Poll::Pending,
             Poll::Ready(val) => match val {
-                Some(mut body) => {
-                    let result = erased_serde::deserialize(&mut body).map_err(|err| err.into());
+                Some(SubscriptionItem::Item(_, mut body, tag)) => {
+                    let result: Result<T::Item, Error> = check_type_tag::<T>(tag).and_then(|_| {
+                        erased_serde::deserialize(&mut body).map_err(|err| err.into())
+                    });
+                    if let Err(err) = &result {
+                        warn!("Error deserializing item on {}: {}", T::topic(), err);
+                        if *this.error_policy == ErrorPolicy::Cancel {
+                            this.alive.take();
+                        }
+                    }
                     Poll::Ready(Some(result))
                 }
+                Some(SubscriptionItem::Rejected(body)) => {
+                    this.alive.take();
+                    Poll::Ready(Some(Err(rejection(body))))
+                }
+                None => Poll::Ready(None),
+            },
+        }
+    }
+}
+
+/// Subscriber of items of type `V` on a topic named at runtime
+#[pin_project]
+pub struct NamedSubscriber<V> {
+    #[pin]
+    inner: RecvStream<'static, SubscriptionItem>,
+    // taken when the subscriber is canceled so that the client broker removes it
+    alive: Option<Arc<()>>,
+    topic: String,
+    error_policy: ErrorPolicy,
+    marker: PhantomData<V>,
+}
+
+impl<V> NamedSubscriber<V> {
+    /// Sets what the subscriber does after an item fails to deserialize or has a
+    /// mismatched type tag
+    pub fn with_error_policy(mut self, policy: ErrorPolicy) -> Self {
+        self.error_policy = policy;
+        self
+    }
+
+    /// Returns the name of the topic
+    pub fn topic(&self) -> &str {
+        &self.topic
+    }
+}
+
+impl<V: DeserializeOwned> Stream for NamedSubscriber<V> {
+    type Item = Result<V, Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.project();
+        if this.alive.is_none() {
+            return Poll::Ready(None);
+        }
+        match this.inner.poll_next(cx) {
+            Poll::Pending => Poll::Pending,
+            Poll::Ready(val) => match val {
+                Some(SubscriptionItem::Item(topic, mut body, tag)) => {
+                    let result: Result<V, Error> =
+                        check_item_type_tag::<V>(&topic, tag).and_then(|_| {
+                            erased_serde::deserialize(&mut body).map_err(|err| err.into())
+                        });
+                    if let Err(err) = &result {
+                        warn!("Error deserializing item on {}: {}", topic, err);
+                        if *this.error_policy == ErrorPolicy::Cancel {
+                            this.alive.take();
+                        }
+                    }
+                    Poll::Ready(Some(result))
+                }
+                Some(SubscriptionItem::Rejected(body)) => {
+                    this.alive.take();
+                    Poll::Ready(Some(Err(rejection(body))))
+                }
+                None => Poll::Ready(None),
+            },
+        }
+    }
+}
+
+/// Subscriber of a topic pattern on the client side
+///
+/// Yields the name of the topic and the item for every item published to a topic
+/// that matches the pattern.
+#[pin_project]
+pub struct PatternSubscriber<V> {
+    #[pin]
+    inner: RecvStream<'static, SubscriptionItem>,
+    // taken when the subscriber is canceled so that the client broker removes it
+    alive: Option<Arc<()>>,
+    pattern: String,
+    error_policy: ErrorPolicy,
+    marker: PhantomData<V>,
+}
+
+impl<V> PatternSubscriber<V> {
+    /// Sets what the subscriber does after an item fails to deserialize
+    pub fn with_error_policy(mut self, policy: ErrorPolicy) -> Self {
+        self.error_policy = policy;
+        self
+    }
+
+    /// Returns the pattern of the subscriber
+    pub fn pattern(&self) -> &str {
+        &self.pattern
+    }
+}
+
+impl<V: DeserializeOwned> Stream for PatternSubscriber<V> {
+    type Item = Result<(String, V), Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.project();
+        if this.alive.is_none() {
+            return Poll::Ready(None);
+        }
+        match this.inner.poll_next(cx) {
+            Poll::Pending => Poll::Pending,
+            Poll::Ready(val) => match val {
+                Some(SubscriptionItem::Item(topic, mut body, _)) => {
+                    match erased_serde::deserialize::<V>(&mut body) {
+                        Ok(item) => Poll::Ready(Some(Ok((topic, item)))),
+                        Err(err) => {
+                            warn!("Error deserializing item on {}: {}", topic, err);
+                            if *this.error_policy == ErrorPolicy::Cancel {
+                                this.alive.take();
+                            }
+                            Poll::Ready(Some(Err(err.into())))
+                        }
+                    }
+                }
+                Some(SubscriptionItem::Rejected(body)) => {
+                    this.alive.take();
+                    Poll::Ready(Some(Err(rejection(body))))
+                }
                 None => Poll::Ready(None),
             },
         }
@@ -104,35 +699,219 @@ impl Client {
         Publisher::from(tx)
     }
 
-    /// Creates a new subscriber on a topic
+    /// Creates a new subscriber on a topic that can buffer up to `cap` items and
+    /// drops the newest item when the buffer is full
     ///
+    /// Multiple local subscribers on the same topic are allowed. Only the first
+    /// one subscribes to the topic on the server, and every item received from the
+    /// server is delivered to all of them.
     pub fn subscriber<T: Topic + 'static>(&mut self, cap: usize) -> Result<Subscriber<T>, Error> {
-        let (tx, rx) = flume::bounded(cap);
+        self.subscriber_with_policy(cap, DropPolicy::default())
+    }
+
+    /// Creates a new subscriber on a topic that can buffer up to `cap` items and
+    /// first receives the last `replay` items published to the topic
+    ///
+    /// The server replays the messages it retains for the topic, or its history
+    /// from the `TopicSource` of the topic if more messages are asked than retained.
+    /// A server without a source replays at most the retained messages, and an
+    /// older server delivers all of the retained messages. Only the first local
+    /// subscriber on a topic can replay, as the others share its subscription on
+    /// the server.
+    pub fn subscriber_with_replay<T: Topic + 'static>(
+        &mut self,
+        cap: usize,
+        replay: usize,
+    ) -> Result<Subscriber<T>, Error> {
+        self.subscribe(cap, DropPolicy::default(), Some(replay))
+    }
+
+    /// Creates a new subscriber on a topic that can buffer up to `cap` items and
+    /// handles a full buffer according to `policy`
+    pub fn subscriber_with_policy<T: Topic + 'static>(
+        &mut self,
+        cap: usize,
+        policy: DropPolicy,
+    ) -> Result<Subscriber<T>, Error> {
+        self.subscribe(cap, policy, None)
+    }
+
+    fn subscribe<T: Topic + 'static>(
+        &mut self,
+        cap: usize,
+        policy: DropPolicy,
+        replay: Option<usize>,
+    ) -> Result<Subscriber<T>, Error> {
         let topic = T::topic();
 
-        // Check if there is an existing subscriber
-        if self.subscriptions.contains_key(&topic) {
-            return Err(Error::Internal(
-                "Only one local subscriber per topic is allowed".into(),
-            ));
+        // Local subscribers on the same topic must have the same type
+        match self.subscriptions.get(&topic) {
+            Some(type_id) if type_id != &TypeId::of::<T>() => {
+                return Err(Error::Internal("TypeId mismatch".into()))
+            }
+            Some(_) => {}
+            None => {
+                self.subscriptions.insert(topic.clone(), TypeId::of::<T>());
+            }
         }
-        self.subscriptions.insert(topic.clone(), TypeId::of::<T>());
 
-        // Create new subscription
+        let (sub, subscriber) = Subscriber::new(cap, policy);
         if let Err(err) = self.broker.send(ClientBrokerItem::Subscribe {
             topic,
-            item_sink: tx,
+            replay,
+            subscriber,
         }) {
             return Err(err.into());
         };
+        Ok(sub)
+    }
 
-        let sub = Subscriber::from(rx);
+    /// Creates a new publisher of items of type `V` on the topic named `topic`
+    ///
+    /// The name is checked by the server when the items are published, and a name
+    /// that is a pattern is rejected.
+    pub fn publisher_str<V: Serialize + Send + Sync + 'static>(
+        &self,
+        topic: impl ToString,
+    ) -> NamedPublisher<V> {
+        NamedPublisher {
+            inner: self.broker.clone().into_sink(),
+            topic: topic.to_string(),
+            tag: None,
+            stats: None,
+            marker: PhantomData,
+        }
+    }
+
+    /// Creates a new subscriber of items of type `V` on the topic named `topic`,
+    /// which can buffer up to `cap` items and drops the newest item when the buffer
+    /// is full
+    ///
+    /// Multiple local subscribers on the same topic are allowed as long as they have
+    /// the same item type. A topic cannot have both named subscribers and
+    /// subscribers created with `subscriber`. Use `subscriber_pattern` to subscribe
+    /// to a pattern.
+    pub fn subscriber_str<V: DeserializeOwned + 'static>(
+        &mut self,
+        topic: impl ToString,
+        cap: usize,
+    ) -> Result<NamedSubscriber<V>, Error> {
+        let topic = topic.to_string();
+        if pubsub::is_pattern(&topic) {
+            return Err(Error::TopicRejected(format!(
+                "{} is a topic pattern",
+                topic
+            )));
+        }
+        match self.subscriptions.get(&topic) {
+            Some(type_id) if type_id != &TypeId::of::<V>() => {
+                return Err(Error::Internal("TypeId mismatch".into()))
+            }
+            Some(_) => {}
+            None => {
+                self.subscriptions.insert(topic.clone(), TypeId::of::<V>());
+            }
+        }
+
+        let (subscriber, rx, alive) = LocalSubscriber::new(cap, DropPolicy::default());
+        let sub = NamedSubscriber {
+            inner: rx.into_stream(),
+            alive: Some(alive),
+            topic: topic.clone(),
+            error_policy: ErrorPolicy::default(),
+            marker: PhantomData,
+        };
+        self.broker.send(ClientBrokerItem::Subscribe {
+            topic,
+            replay: None,
+            subscriber,
+        })?;
         Ok(sub)
     }
 
-    /// Replaces the local subscriber without sending any message to the server
+    /// Unsubscribe from a topic named at runtime
     ///
-    /// The previous subscriber will no longer receive any message.
+    /// All the local subscribers on the topic are ended.
+    pub async fn unsubscribe_str(&mut self, topic: &str) -> Result<(), Error> {
+        if !pubsub::is_pattern(topic) && self.subscriptions.remove(topic).is_some() {
+            self.broker
+                .send_async(ClientBrokerItem::Unsubscribe {
+                    topic: topic.to_string(),
+                })
+                .await?;
+            return Ok(());
+        }
+        Err(Error::Internal(
+            format!("Not registered to topic: {}", topic).into(),
+        ))
+    }
+
+    /// Creates a new subscriber on every topic that matches `pattern`, which can
+    /// buffer up to `cap` items and drops the newest item when the buffer is full
+    ///
+    /// The items are deserialized into `V`. Multiple local subscribers on the same
+    /// pattern are allowed as long as they have the same item type. A client that
+    /// subscribes to both a topic and a pattern that matches it receives each item
+    /// from the server once, and delivers it to all the local subscribers.
+    pub fn subscriber_pattern<V: DeserializeOwned + 'static>(
+        &mut self,
+        pattern: impl ToString,
+        cap: usize,
+    ) -> Result<PatternSubscriber<V>, Error> {
+        let pattern = pattern.to_string();
+        if !pubsub::is_pattern(&pattern) {
+            return Err(Error::TopicRejected(format!(
+                "{} is not a topic pattern",
+                pattern
+            )));
+        }
+        match self.subscriptions.get(&pattern) {
+            Some(type_id) if type_id != &TypeId::of::<V>() => {
+                return Err(Error::Internal("TypeId mismatch".into()))
+            }
+            Some(_) => {}
+            None => {
+                self.subscriptions
+                    .insert(pattern.clone(), TypeId::of::<V>());
+            }
+        }
+
+        let (subscriber, rx, alive) = LocalSubscriber::new(cap, DropPolicy::default());
+        let sub = PatternSubscriber {
+            inner: rx.into_stream(),
+            alive: Some(alive),
+            pattern: pattern.clone(),
+            error_policy: ErrorPolicy::default(),
+            marker: PhantomData,
+        };
+        self.broker.send(ClientBrokerItem::Subscribe {
+            topic: pattern,
+            replay: None,
+            subscriber,
+        })?;
+        Ok(sub)
+    }
+
+    /// Unsubscribe from a topic pattern
+    ///
+    /// All the local subscribers on the pattern are ended.
+    pub async fn unsubscribe_pattern(&mut self, pattern: &str) -> Result<(), Error> {
+        if pubsub::is_pattern(pattern) && self.subscriptions.remove(pattern).is_some() {
+            self.broker
+                .send_async(ClientBrokerItem::Unsubscribe {
+                    topic: pattern.to_string(),
+                })
+                .await?;
+            return Ok(());
+        }
+        Err(Error::Internal(
+            format!("Not registered to pattern: {}", pattern).into(),
+        ))
+    }
+
+    /// Replaces the local subscribers without sending any message to the server
+    ///
+    /// The previous subscribers will no longer receive any message.
     pub fn replace_local_subscriber<T: Topic + 'static>(
         &mut self,
         cap: usize,
@@ -141,14 +920,13 @@ impl Client {
         match self.subscriptions.get(&topic) {
             Some(entry) => match &TypeId::of::<T>() == entry {
                 true => {
-                    let (tx, rx) = flume::bounded(cap);
-                    if let Err(err) = self.broker.send(ClientBrokerItem::NewLocalSubscriber {
-                        topic,
-                        new_item_sink: tx,
-                    }) {
+                    let (sub, subscriber) = Subscriber::new(cap, DropPolicy::default());
+                    if let Err(err) = self
+                        .broker
+                        .send(ClientBrokerItem::NewLocalSubscriber { topic, subscriber })
+                    {
                         return Err(err.into());
                     }
-                    let sub = Subscriber::from(rx);
                     Ok(sub)
                 }
                 false => Err(Error::Internal("TypeId mismatch".into())),
@@ -160,6 +938,8 @@ impl Client {
     }
 
     /// Unsubscribe from a topic
+    ///
+    /// All the local subscribers on the topic are ended.
     pub async fn unsubscribe<T: Topic + 'static>(&mut self) -> Result<(), Error> {
         let topic = T::topic();
         if let Some(type_id) = self.subscriptions.get(&topic) {
@@ -4,11 +4,70 @@ use futures::Sink;
 use futures::SinkExt;
 
 use super::broker::ClientBrokerItem;
+#[cfg(feature = "signing")]
+use super::broker::Signature;
+use crate::message::MessageId;
 use crate::protocol::{Header, InboundBody};
+#[cfg(feature = "signing")]
+use crate::signing::SIGNATURE_MARKER;
+#[cfg(all(feature = "codec_negotiation", not(feature = "serde_json")))]
+use crate::{
+    codec::negotiate::{self, ContentType},
+    metadata::{self, METADATA_MARKER},
+};
 use crate::{codec::CodecRead, Error};
 
 pub(crate) struct ClientReader<R> {
     pub reader: R,
+    // signature of the next response
+    #[cfg(feature = "signing")]
+    signature: Option<(MessageId, String)>,
+    // content type of the next response if it is not the codec's
+    #[cfg(all(feature = "codec_negotiation", not(feature = "serde_json")))]
+    content_type: Option<(MessageId, ContentType)>,
+}
+
+impl<R> ClientReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            #[cfg(feature = "signing")]
+            signature: None,
+            #[cfg(all(feature = "codec_negotiation", not(feature = "serde_json")))]
+            content_type: None,
+        }
+    }
+
+    /// The signature that preceded a response and the bytes that it covers
+    #[cfg(feature = "signing")]
+    fn signature(&mut self, id: MessageId, bytes: &[u8]) -> Option<Signature> {
+        match self.signature.take() {
+            Some((signed, content)) if signed == id => Some(Signature {
+                content,
+                body: bytes.to_vec(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl<R: CodecRead> ClientReader<R> {
+    /// The deserializer of the body of a response, in the content type that the
+    /// request asked for if the server encoded it so
+    #[cfg_attr(
+        not(all(feature = "codec_negotiation", not(feature = "serde_json"))),
+        allow(unused_variables)
+    )]
+    fn response_body(&mut self, id: MessageId, bytes: Vec<u8>) -> Box<InboundBody> {
+        #[cfg(all(feature = "codec_negotiation", not(feature = "serde_json")))]
+        if let Some((encoded, content_type)) = self.content_type {
+            if encoded == id {
+                self.content_type = None;
+                return content_type.deserializer(bytes);
+            }
+        }
+        R::from_bytes(bytes)
+    }
 }
 
 #[async_trait]
@@ -21,47 +80,202 @@ impl<R: CodecRead> brw::Reader for ClientReader<R> {
     where
         B: Sink<Self::BrokerItem, Error = flume::SendError<Self::BrokerItem>> + Send + Unpin,
     {
-        if let Some(header) = self.reader.read_header().await {
+        if let Some(header) = self.reader.read_protocol_header().await {
             let header: Header = match header {
                 Ok(header) => header,
                 Err(err) => return Running::Continue(Err(err)),
             };
-            log::debug!("{:?}", &header);
-            let deserializer: Box<InboundBody> = match self.reader.read_body().await {
+            debug!("{:?}", &header);
+            let bytes = match self.reader.read_bytes().await {
                 Some(res) => match res {
-                    Ok(de) => de,
+                    Ok(bytes) => bytes,
                     Err(err) => return Running::Continue(Err(err)),
                 },
-                None => return Running::Stop,
+                None => return stop(&mut broker).await,
             };
+            let size = bytes.len();
 
             match header {
                 Header::Response { id, is_ok } => {
+                    #[cfg(feature = "signing")]
+                    let signature = self.signature(id, &bytes);
+                    let deserializer = self.response_body(id, bytes);
                     let result = match is_ok {
                         true => Ok(deserializer),
                         false => Err(deserializer),
                     };
 
-                    if let Err(err) = broker.send(ClientBrokerItem::Response { id, result }).await {
+                    if let Err(err) = broker
+                        .send(ClientBrokerItem::Response {
+                            id,
+                            result,
+                            bytes: size,
+                            #[cfg(feature = "signing")]
+                            signature,
+                        })
+                        .await
+                    {
                         return Running::Continue(Err(err.into()));
                     }
                     Running::Continue(Ok(()))
                 }
+                Header::StreamItem { id, is_ok } => {
+                    #[cfg(feature = "signing")]
+                    let signature = self.signature(id, &bytes);
+                    let deserializer: Box<InboundBody> = R::from_bytes(bytes);
+                    let result = match is_ok {
+                        true => Ok(deserializer),
+                        false => Err(deserializer),
+                    };
+                    Running::Continue(
+                        broker
+                            .send(ClientBrokerItem::StreamItem {
+                                id,
+                                result,
+                                bytes: size,
+                                #[cfg(feature = "signing")]
+                                signature,
+                            })
+                            .await
+                            .map_err(|err| err.into()),
+                    )
+                }
+                Header::StreamEnd(id) => Running::Continue(
+                    broker
+                        .send(ClientBrokerItem::StreamEnd(id))
+                        .await
+                        .map_err(|err| err.into()),
+                ),
+                #[cfg(feature = "signing")]
+                Header::Ext {
+                    id,
+                    content,
+                    marker: SIGNATURE_MARKER,
+                } => {
+                    self.signature = Some((id, content));
+                    Running::Continue(Ok(()))
+                }
+                Header::Ext {
+                    id,
+                    content,
+                    marker,
+                } => {
+                    #[cfg(all(feature = "codec_negotiation", not(feature = "serde_json")))]
+                    if marker == METADATA_MARKER {
+                        let metadata = metadata::decode(&content);
+                        self.content_type = negotiate::response_content_type(&metadata)
+                            .map(|content_type| (id, content_type));
+                    }
+                    Running::Continue(
+                        broker
+                            .send(ClientBrokerItem::ExtReceived {
+                                id,
+                                marker,
+                                content,
+                            })
+                            .await
+                            .map_err(|err| err.into()),
+                    )
+                }
                 Header::Publish { id, topic } => Running::Continue(
                     broker
                         .send(ClientBrokerItem::Subscription {
                             id,
                             topic,
-                            item: deserializer,
+                            bytes,
+                            from_bytes: R::from_bytes,
+                            tag: None,
+                            ack: false,
+                        })
+                        .await
+                        .map_err(|err| err.into()),
+                ),
+                Header::TaggedPublish { id, topic, tag } => Running::Continue(
+                    broker
+                        .send(ClientBrokerItem::Subscription {
+                            id,
+                            topic,
+                            bytes,
+                            from_bytes: R::from_bytes,
+                            tag: Some(tag),
+                            ack: false,
+                        })
+                        .await
+                        .map_err(|err| err.into()),
+                ),
+                Header::AckedPublish { id, topic, tag } => Running::Continue(
+                    broker
+                        .send(ClientBrokerItem::Subscription {
+                            id,
+                            topic,
+                            bytes,
+                            from_bytes: R::from_bytes,
+                            tag,
+                            ack: true,
+                        })
+                        .await
+                        .map_err(|err| err.into()),
+                ),
+                Header::Ack(id) => Running::Continue(
+                    broker
+                        .send(ClientBrokerItem::Ack(id, R::from_bytes(bytes)))
+                        .await
+                        .map_err(|err| err.into()),
+                ),
+                Header::Reject { id, topic } => Running::Continue(
+                    broker
+                        .send(ClientBrokerItem::Rejected {
+                            id,
+                            topic,
+                            bytes,
+                            from_bytes: R::from_bytes,
                         })
                         .await
                         .map_err(|err| err.into()),
                 ),
+                // a call from the server to a service of the client
+                #[cfg(feature = "server")]
+                Header::Request {
+                    id,
+                    service_method,
+                    timeout,
+                } => Running::Continue(
+                    broker
+                        .send(ClientBrokerItem::ServeRequest {
+                            id,
+                            service_method,
+                            duration: timeout,
+                            deserializer: R::from_bytes(bytes),
+                        })
+                        .await
+                        .map_err(|err| err.into()),
+                ),
+                #[cfg(feature = "server")]
+                Header::Cancel(id) => Running::Continue(
+                    broker
+                        .send(ClientBrokerItem::ServeCancel(id))
+                        .await
+                        .map_err(|err| err.into()),
+                ),
                 _ => Running::Continue(Err(Error::Internal("Unexpected Header type".into()))),
             }
         } else {
-            if broker.send(ClientBrokerItem::Stop).await.is_ok() {}
-            Running::Stop
+            stop(&mut broker).await
         }
     }
 }
+
+/// Asks the broker to stop once the connection is closed
+///
+/// The reader is stopped by the broker after the items that are already sent are
+/// handled. Stopping the reader right away would stop the broker, which could drop
+/// the responses that are still queued.
+async fn stop<B>(broker: &mut B) -> Running<Result<(), Error>>
+where
+    B: Sink<ClientBrokerItem, Error = flume::SendError<ClientBrokerItem>> + Send + Unpin,
+{
+    match broker.send(ClientBrokerItem::Closed).await {
+        Ok(_) => futures::future::pending().await,
+        Err(_) => Running::Stop,
+    }
+}
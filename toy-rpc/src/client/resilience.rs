@@ -0,0 +1,255 @@
+//! Revalidation of in-flight calls after the system clock jumps
+//!
+//! Timeouts are tracked with the monotonic clock. On most platforms the monotonic
+//! clock does not advance while the machine is suspended (eg. laptop sleep or a
+//! paused VM), so after a resume the calls in flight keep waiting for responses
+//! that the server may never send because the connection was dropped in the
+//! meantime. On other platforms all of them time out at once.
+//!
+//! When a handler is registered with `Client::on_clock_jump`, the client compares
+//! the monotonic clock with the wall clock every second. If the wall clock has
+//! advanced more than the monotonic clock by at least the threshold, the handler is
+//! called for every call in flight and decides whether to keep waiting, send the
+//! request again or fail the call.
+//!
+//! # Example
+//!
+//! ```no_run
+//! # #[cfg(any(feature = "async_std_runtime", feature = "tokio_runtime"))]
+//! # {
+//! use toy_rpc::client::resilience::Revalidation;
+//! # use std::time::Duration;
+//! # use toy_rpc::{Client, Error};
+//! # fn run(client: Client) -> Result<(), Error> {
+//!
+//! client.on_clock_jump(Duration::from_secs(5), |call| {
+//!     log::warn!("{} was in flight for {:?} across a clock jump of {:?}", call.service_method, call.elapsed, call.jump);
+//!     Revalidation::Resend
+//! })?;
+//! # Ok(())
+//! # }
+//! # }
+//! ```
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+#[cfg(any(
+    all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+    all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+))]
+use flume::Sender;
+use futures::future::AbortHandle;
+
+use crate::{clock, message::MessageId, protocol::OutboundBody};
+
+#[cfg(any(
+    all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+    all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+))]
+use super::broker::ClientBrokerItem;
+
+/// How often the clocks are compared
+#[cfg(any(
+    all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+    all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+))]
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// What to do with a call that was in flight when the clock jumped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Revalidation {
+    /// Keep waiting for the response
+    Keep,
+    /// Send the request again with the same id. The timeout of the call is not
+    /// restarted.
+    Resend,
+    /// Fail the call with `Error::Timeout`
+    Fail,
+}
+
+/// A call that was in flight when the clock jumped
+#[derive(Debug, Clone)]
+pub struct InFlightCall {
+    /// ID of the call
+    pub id: MessageId,
+    /// Name of the service and method, eg. `"Arith.add"`
+    pub service_method: String,
+    /// Time since the request was sent, measured with the monotonic clock
+    pub elapsed: Duration,
+    /// How much further the wall clock advanced than the monotonic clock
+    pub jump: Duration,
+}
+
+/// Handler deciding what to do with the calls in flight after a clock jump
+pub type ClockJumpHandler = Arc<dyn Fn(&InFlightCall) -> Revalidation + Send + Sync + 'static>;
+
+/// Returns by how much the wall clock advanced further than the monotonic clock, if
+/// that is at least `threshold`
+#[cfg_attr(
+    all(not(feature = "tokio_runtime"), not(feature = "async_std_runtime")),
+    allow(dead_code)
+)]
+fn detect_jump(monotonic: Duration, wall: Duration, threshold: Duration) -> Option<Duration> {
+    wall.checked_sub(monotonic)
+        .filter(|jump| *jump >= threshold)
+}
+
+/// Compares the clocks every `CHECK_INTERVAL` and notifies the broker of jumps. Stops
+/// when the broker is stopped.
+#[cfg(any(
+    all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+    all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+))]
+pub(crate) async fn watch_clock(threshold: Duration, broker: Sender<ClientBrokerItem>) {
+    let mut last = (clock::now(), clock::system_now());
+    loop {
+        clock::sleep(CHECK_INTERVAL).await;
+
+        let now = (clock::now(), clock::system_now());
+        let monotonic = now.0.duration_since(last.0);
+        // the wall clock may also be set backwards, which is not a suspension
+        let wall = now.1.duration_since(last.1).unwrap_or_default();
+        last = now;
+
+        if let Some(jump) = detect_jump(monotonic, wall, threshold) {
+            warn!("Clock jumped by {:?}, revalidating in-flight calls", jump);
+            if broker.send(ClientBrokerItem::ClockJump(jump)).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// A request that may need to be sent again
+#[cfg_attr(
+    all(not(feature = "tokio_runtime"), not(feature = "async_std_runtime")),
+    allow(dead_code)
+)]
+pub(crate) struct InFlightRequest {
+    pub service_method: String,
+    pub duration: Duration,
+    pub body: Arc<OutboundBody>,
+    started: Instant,
+}
+
+/// Resilience mode of a client broker
+#[cfg_attr(
+    all(not(feature = "tokio_runtime"), not(feature = "async_std_runtime")),
+    allow(dead_code)
+)]
+pub(crate) struct Resilience {
+    handler: ClockJumpHandler,
+    watchdog: AbortHandle,
+    in_flight: HashMap<MessageId, InFlightRequest>,
+}
+
+#[cfg_attr(
+    all(not(feature = "tokio_runtime"), not(feature = "async_std_runtime")),
+    allow(dead_code)
+)]
+impl Resilience {
+    pub fn new(handler: ClockJumpHandler, watchdog: AbortHandle) -> Self {
+        Self {
+            handler,
+            watchdog,
+            in_flight: HashMap::new(),
+        }
+    }
+
+    pub fn track(
+        &mut self,
+        id: MessageId,
+        service_method: &str,
+        duration: Duration,
+        body: Arc<OutboundBody>,
+    ) {
+        let request = InFlightRequest {
+            service_method: service_method.to_string(),
+            duration,
+            body,
+            started: clock::now(),
+        };
+        self.in_flight.insert(id, request);
+    }
+
+    pub fn untrack(&mut self, id: MessageId) {
+        self.in_flight.remove(&id);
+    }
+
+    /// Asks the handler what to do with each call in flight
+    pub fn revalidate(&self, jump: Duration) -> Vec<(MessageId, Revalidation, &InFlightRequest)> {
+        self.in_flight
+            .iter()
+            .map(|(id, request)| {
+                let call = InFlightCall {
+                    id: *id,
+                    service_method: request.service_method.clone(),
+                    elapsed: request.started.elapsed(),
+                    jump,
+                };
+                (*id, (self.handler)(&call), request)
+            })
+            .collect()
+    }
+}
+
+impl Drop for Resilience {
+    fn drop(&mut self) {
+        self.watchdog.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clock_jumps() {
+        let threshold = Duration::from_secs(5);
+        let secs = Duration::from_secs;
+        assert_eq!(detect_jump(secs(1), secs(1), threshold), None);
+        assert_eq!(detect_jump(secs(1), secs(4), threshold), None);
+        assert_eq!(
+            detect_jump(secs(1), secs(3600), threshold),
+            Some(secs(3599))
+        );
+        // wall clock set backwards
+        assert_eq!(detect_jump(secs(1), secs(0), threshold), None);
+    }
+
+    #[test]
+    fn revalidate_in_flight_calls() {
+        let handler: ClockJumpHandler = Arc::new(|call: &InFlightCall| {
+            if call.service_method.ends_with("idempotent") {
+                Revalidation::Resend
+            } else {
+                Revalidation::Fail
+            }
+        });
+        let (watchdog, _) = AbortHandle::new_pair();
+        let mut resilience = Resilience::new(handler, watchdog);
+        let body = Arc::new(()) as Arc<OutboundBody>;
+        resilience.track(
+            1,
+            "Service.idempotent",
+            Duration::from_secs(10),
+            body.clone(),
+        );
+        resilience.track(2, "Service.transfer", Duration::from_secs(10), body.clone());
+        resilience.track(3, "Service.transfer", Duration::from_secs(10), body);
+        resilience.untrack(3);
+
+        let mut decisions: Vec<_> = resilience
+            .revalidate(Duration::from_secs(60))
+            .into_iter()
+            .map(|(id, decision, _)| (id, decision))
+            .collect();
+        decisions.sort_by_key(|(id, _)| *id);
+        assert_eq!(
+            decisions,
+            vec![(1, Revalidation::Resend), (2, Revalidation::Fail)]
+        );
+    }
+}
@@ -0,0 +1,174 @@
+//! Streaming argument of an RPC call
+
+use cfg_if::cfg_if;
+use flume::Sender;
+use std::{
+    marker::PhantomData,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use crate::{message::MessageId, protocol::OutboundBody, Error};
+
+use super::broker::ClientBrokerItem;
+
+/// Sends the items of the argument of a call to a method that takes a
+/// `RequestStream`. It is created by `Client::call_with_sink` and
+/// `Client::call_stream_with_sink`. See the `streaming` module for details.
+///
+/// The items are sent with `send`, or through the `Sink` implementation. The
+/// argument ends when `finish` is called, when the `Sink` is closed or when the
+/// `ClientSink` is dropped. The items sent after the call has finished are dropped.
+///
+/// # Example
+///
+/// ```no_run
+/// # #[cfg(any(feature = "async_std_runtime", feature = "tokio_runtime"))]
+/// # {
+/// # use toy_rpc::Client;
+/// # async fn run(client: Client) -> Result<(), Box<dyn std::error::Error>> {
+/// let (numbers, sum) = client.call_with_sink::<i64, i64>("Stats.sum");
+/// for number in 1..=10 {
+///     numbers.send(number)?;
+/// }
+/// numbers.finish()?;
+/// assert_eq!(sum.await?, 55);
+/// # Ok(())
+/// # }
+/// # }
+/// ```
+pub struct ClientSink<T> {
+    id: MessageId,
+    broker: Sender<ClientBrokerItem>,
+    finished: bool,
+    marker: PhantomData<fn(T)>,
+}
+
+impl<T> ClientSink<T> {
+    #[cfg(any(
+        all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+        all(feature = "async_std_runtime", not(feature = "tokio_runtime"))
+    ))]
+    pub(crate) fn new(id: MessageId, broker: Sender<ClientBrokerItem>) -> Self {
+        Self {
+            id,
+            broker,
+            finished: false,
+            marker: PhantomData,
+        }
+    }
+
+    /// Gets the ID number of the call
+    pub fn get_id(&self) -> MessageId {
+        self.id
+    }
+
+    /// Ends the argument
+    pub fn finish(mut self) -> Result<(), Error> {
+        self.end()
+    }
+
+    fn end(&mut self) -> Result<(), Error> {
+        if self.finished {
+            return Ok(());
+        }
+        self.finished = true;
+        self.broker
+            .send(ClientBrokerItem::SinkEnd(self.id))
+            .map_err(|err| err.into())
+    }
+}
+
+impl<T> ClientSink<T>
+where
+    T: serde::Serialize + Send + Sync + 'static,
+{
+    /// Sends an item of the argument
+    pub fn send(&self, item: T) -> Result<(), Error> {
+        if self.finished {
+            return Err(Error::Internal("The ClientSink is finished".into()));
+        }
+        let body = Box::new(item) as Box<OutboundBody>;
+        self.broker
+            .send(ClientBrokerItem::SinkItem { id: self.id, body })
+            .map_err(|err| err.into())
+    }
+}
+
+impl<T> futures::Sink<T> for ClientSink<T>
+where
+    T: serde::Serialize + Send + Sync + 'static,
+{
+    type Error = Error;
+
+    fn poll_ready(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: T) -> Result<(), Self::Error> {
+        self.send(item)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(self.end())
+    }
+}
+
+impl<T> Drop for ClientSink<T> {
+    fn drop(&mut self) {
+        if let Err(err) = self.end() {
+            error!("Failed to end the argument of call {}: {}", self.id, err);
+        }
+    }
+}
+
+cfg_if! {
+    if #[cfg(any(
+        all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+        all(feature = "async_std_runtime", not(feature = "tokio_runtime"))
+    ))] {
+        use futures::{Stream, StreamExt};
+
+        #[cfg(all(feature = "async_std_runtime", not(feature = "tokio_runtime")))]
+        use ::async_std::task;
+        #[cfg(all(feature = "tokio_runtime", not(feature = "async_std_runtime")))]
+        use ::tokio::task;
+
+        impl<T> ClientSink<T>
+        where
+            T: serde::Serialize + Send + Sync + 'static,
+        {
+            /// Sends the items of `items` in a background task and ends the argument
+            /// at the end of the stream. An error in the stream cancels the call.
+            pub fn forward<S>(self, items: S)
+            where
+                S: Stream<Item = Result<T, Error>> + Send + 'static,
+            {
+                task::spawn(async move {
+                    let mut items = Box::pin(items);
+                    while let Some(item) = items.next().await {
+                        let item = match item {
+                            Ok(item) => item,
+                            Err(err) => {
+                                error!("Canceling call {}: {}", self.id, err);
+                                if self.broker.send(ClientBrokerItem::Cancel(self.id)).is_err() {
+                                    error!("Failed to send cancellation message to client broker");
+                                }
+                                return;
+                            }
+                        };
+                        if self.send(item).is_err() {
+                            // the broker is stopped
+                            return;
+                        }
+                    }
+                    // dropping the sink ends the argument
+                });
+            }
+        }
+    }
+}
@@ -0,0 +1,107 @@
+//! Failing fast on a storm of timeouts
+//!
+//! When the server or the socket is wedged, every call in flight eventually times
+//! out, and each caller waits out its own full timeout. When a policy is set with
+//! `Client::on_timeout_storm`, the client counts the calls that time out, and once
+//! more than `threshold` of them time out within `window` it
+//!
+//! - fails all the other calls in flight right away with `Error::Timeout`, and
+//! - with `StormAction::Disconnect`, also closes the connection. Calls made
+//!   afterwards fail right away with an `IoError` of kind `NotConnected`, and the
+//!   application is expected to dial the server again with a new `Client`.
+//!
+//! There is no action reconnecting the client in place. The reader and the writer
+//! of a connection are owned by the task of the client for its whole life, so a
+//! new connection takes a new `Client`. A handler registered with
+//! `Client::on_disconnect` is notified with `DisconnectReason::TimeoutStorm` and is
+//! the place to start dialing again (see the `disconnect` module).
+//!
+//! # Example
+//!
+//! ```no_run
+//! # #[cfg(any(feature = "async_std_runtime", feature = "tokio_runtime"))]
+//! # {
+//! use toy_rpc::client::storm::StormAction;
+//! # use std::time::Duration;
+//! # use toy_rpc::{Client, Error};
+//! # fn run(client: Client) -> Result<(), Error> {
+//!
+//! client.on_timeout_storm(10, Duration::from_secs(5), StormAction::Disconnect)?;
+//! # Ok(())
+//! # }
+//! # }
+//! ```
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// What to do when a storm of timeouts is detected
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StormAction {
+    /// Fail the calls in flight and keep the connection
+    FailPending,
+    /// Fail the calls in flight and close the connection
+    Disconnect,
+}
+
+/// Detects more than `threshold` timeouts within `window`
+#[derive(Debug, Clone)]
+pub(crate) struct TimeoutStorm {
+    threshold: usize,
+    window: Duration,
+    action: StormAction,
+    timeouts: VecDeque<Instant>,
+}
+
+#[cfg_attr(
+    all(not(feature = "tokio_runtime"), not(feature = "async_std_runtime")),
+    allow(dead_code)
+)]
+impl TimeoutStorm {
+    pub fn new(threshold: usize, window: Duration, action: StormAction) -> Self {
+        Self {
+            threshold,
+            window,
+            action,
+            timeouts: VecDeque::new(),
+        }
+    }
+
+    /// Records a timeout at `now` and returns the action to take if this timeout
+    /// starts a storm
+    pub fn record(&mut self, now: Instant) -> Option<StormAction> {
+        while let Some(first) = self.timeouts.front() {
+            if now.saturating_duration_since(*first) <= self.window {
+                break;
+            }
+            self.timeouts.pop_front();
+        }
+        self.timeouts.push_back(now);
+        if self.timeouts.len() > self.threshold {
+            // the calls failed by the storm do not count towards the next one
+            self.timeouts.clear();
+            Some(self.action)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timeout_storms() {
+        let start = Instant::now();
+        let at = |millis| start + Duration::from_millis(millis);
+        let mut storm = TimeoutStorm::new(2, Duration::from_millis(100), StormAction::FailPending);
+        assert_eq!(storm.record(at(0)), None);
+        assert_eq!(storm.record(at(50)), None);
+        // the first timeout has left the window
+        assert_eq!(storm.record(at(120)), None);
+        assert_eq!(storm.record(at(140)), Some(StormAction::FailPending));
+        // counting starts over after a storm
+        assert_eq!(storm.record(at(150)), None);
+    }
+}
@@ -0,0 +1,115 @@
+//! Transactions opened by a client
+//!
+//! See the `toy_rpc::transaction` module for details.
+
+use std::collections::hash_map::RandomState;
+use std::collections::BTreeSet;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use crate::{
+    transaction::{InTransaction, TransactionId, COMMIT_METHOD, ROLLBACK_METHOD},
+    Error,
+};
+
+use super::{Call, Client};
+
+/// Returns an id that is unlikely to be used by another client
+fn new_transaction_id() -> TransactionId {
+    static COUNT: AtomicU64 = AtomicU64::new(0);
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(COUNT.fetch_add(1, Ordering::Relaxed));
+    TransactionId(hasher.finish())
+}
+
+/// A group of calls that are committed or rolled back together
+///
+/// Created with `Client::transaction`.
+pub struct Transaction<'c> {
+    client: &'c Client,
+    id: TransactionId,
+    // names of the services called in the transaction
+    participants: Mutex<BTreeSet<String>>,
+}
+
+impl<'c> Transaction<'c> {
+    pub(crate) fn new(client: &'c Client) -> Self {
+        Self {
+            client,
+            id: new_transaction_id(),
+            participants: Mutex::new(BTreeSet::new()),
+        }
+    }
+
+    /// Returns the id of the transaction
+    pub fn id(&self) -> TransactionId {
+        self.id
+    }
+
+    /// Returns the names of the services called in the transaction so far
+    pub fn participants(&self) -> Vec<String> {
+        self.lock().iter().cloned().collect()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, BTreeSet<String>> {
+        match self.participants.lock() {
+            Ok(participants) => participants,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    /// Invokes the named function in the transaction. The service receives `args`
+    /// wrapped in an `InTransaction` along with the id of the transaction.
+    pub fn call<Req, Res>(&self, service_method: impl ToString, args: Req) -> Call<Res>
+    where
+        Req: serde::Serialize + Send + Sync + 'static,
+        Res: serde::de::DeserializeOwned + Send + 'static,
+    {
+        let service_method = service_method.to_string();
+        let service = match service_method.rfind('.') {
+            Some(pos) => &service_method[..pos],
+            None => &service_method[..],
+        };
+        self.lock().insert(service.to_string());
+        let args = InTransaction {
+            transaction: self.id,
+            args,
+        };
+        self.client.call(service_method, args)
+    }
+
+    /// Commits the transaction on every service called in it
+    ///
+    /// All the services are committed even if some of them fail, and the first
+    /// error is returned.
+    pub async fn commit(self) -> Result<(), Error> {
+        self.finish(COMMIT_METHOD).await
+    }
+
+    /// Rolls back the transaction on every service called in it
+    ///
+    /// All the services are rolled back even if some of them fail, and the first
+    /// error is returned.
+    pub async fn rollback(self) -> Result<(), Error> {
+        self.finish(ROLLBACK_METHOD).await
+    }
+
+    async fn finish(self, method: &str) -> Result<(), Error> {
+        let mut result = Ok(());
+        for service in self.participants() {
+            let service_method = format!("{}.{}", service, method);
+            let reply: Result<(), Error> = self.client.call(&service_method, self.id).await;
+            if let Err(err) = reply {
+                error!(
+                    "Failed to {} transaction {}: {}",
+                    service_method, self.id, err
+                );
+                if result.is_ok() {
+                    result = Err(err);
+                }
+            }
+        }
+        result
+    }
+}
@@ -0,0 +1,180 @@
+//! Calls checked against the signatures of the exported methods
+//!
+//! Along with the client stubs, `#[export_impl]` and `#[export_trait]` generate the
+//! metadata of every exported method, which holds the `"Service.method"` name, the
+//! type of the argument and the type of the reply. The `rpc_call!` macro looks up
+//! the metadata from the path to the method, so a call with an argument or a reply
+//! of the wrong type does not compile, without going through the client stub:
+//!
+//! ```no_run
+//! # #[cfg(any(feature = "async_std_runtime", feature = "tokio_runtime"))]
+//! # {
+//! # use toy_rpc::Client;
+//! # use toy_rpc::rpc_call;
+//! # use toy_rpc::macros::export_trait;
+//! # use toy_rpc::macros::export_impl;
+//! # struct Arith;
+//! # #[export_impl]
+//! # impl Arith {
+//! #     #[export_method]
+//! #     async fn add(&self, args: (i32, i32)) -> Result<i32, String> {
+//! #         Ok(args.0 + args.1)
+//! #     }
+//! # }
+//! # #[async_trait::async_trait]
+//! # #[export_trait]
+//! # pub trait Calculator {
+//! #     #[export_method]
+//! #     async fn add(&self, args: (i32, i32)) -> Result<i32, String>;
+//! # }
+//! # async fn run(client: Client) -> Result<(), Box<dyn std::error::Error>> {
+//! // `Arith` is exported with `#[export_impl]`
+//! let sum: i32 = rpc_call!(client, Arith::add, (1, 2)).await?;
+//!
+//! // `Calculator` is a trait exported with `#[export_trait]`
+//! let sum: i32 = rpc_call!(client, dyn Calculator::add, (1, 2)).await?;
+//! # Ok(())
+//! # }
+//! # }
+//! ```
+//!
+//! A method returning a `RpcStream` is called with `Client::call_stream`, and the
+//! macro returns a `CallStream` of its items. The methods whose argument is a
+//! `RequestStream` have no metadata.
+//!
+//! The name of the service is the name of the type or of the trait, so a service
+//! registered under another name with `ServerBuilder::register_with_name` cannot
+//! be called with `rpc_call!`.
+
+use std::marker::PhantomData;
+
+cfg_if::cfg_if! {
+    if #[cfg(any(
+        all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+        all(feature = "async_std_runtime", not(feature = "tokio_runtime"))
+    ))] {
+        use serde::{de::DeserializeOwned, Serialize};
+
+        use super::{Call, CallStream, Client};
+    }
+}
+
+/// Implemented by the macros for the services whose methods have metadata
+pub trait ServiceMethods {
+    /// Type with an associated function per exported method, which returns the
+    /// metadata of the method
+    type Methods;
+}
+
+/// Metadata of a method returning a single reply
+pub struct Method<Req, Res> {
+    service_method: &'static str,
+    _marker: PhantomData<fn(Req) -> Res>,
+}
+
+impl<Req, Res> Method<Req, Res> {
+    /// Creates the metadata of the method named `service_method`
+    pub fn new(service_method: &'static str) -> Self {
+        Self {
+            service_method,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Name of the service and method, eg. `"Arith.add"`
+    pub fn service_method(&self) -> &'static str {
+        self.service_method
+    }
+}
+
+#[cfg(any(
+    all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+    all(feature = "async_std_runtime", not(feature = "tokio_runtime"))
+))]
+impl<Req, Res> Method<Req, Res>
+where
+    Req: Serialize + Send + Sync + 'static,
+    Res: DeserializeOwned + Send + 'static,
+{
+    /// Calls the method with `Client::call`
+    pub fn call(&self, client: &Client, args: Req) -> Call<Res> {
+        client.call(self.service_method, args)
+    }
+}
+
+/// Metadata of a method returning a `RpcStream`
+pub struct StreamMethod<Req, Item> {
+    service_method: &'static str,
+    _marker: PhantomData<fn(Req) -> Item>,
+}
+
+impl<Req, Item> StreamMethod<Req, Item> {
+    /// Creates the metadata of the method named `service_method`
+    pub fn new(service_method: &'static str) -> Self {
+        Self {
+            service_method,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Name of the service and method, eg. `"Sensor.readings"`
+    pub fn service_method(&self) -> &'static str {
+        self.service_method
+    }
+}
+
+#[cfg(any(
+    all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+    all(feature = "async_std_runtime", not(feature = "tokio_runtime"))
+))]
+impl<Req, Item> StreamMethod<Req, Item>
+where
+    Req: Serialize + Send + Sync + 'static,
+    Item: DeserializeOwned + Send + 'static,
+{
+    /// Calls the method with `Client::call_stream`
+    pub fn call(&self, client: &Client, args: Req) -> CallStream<Item> {
+        client.call_stream(self.service_method, args)
+    }
+}
+
+/// Calls an exported method with an argument and a reply checked at compile time.
+/// See the `client::typed` module for details.
+///
+/// The method of a service exported with `#[export_impl]` is named as
+/// `Service::method`, and the method of a trait exported with `#[export_trait]` as
+/// `dyn Trait::method`.
+///
+/// # Example
+///
+/// ```no_run
+/// # #[cfg(any(feature = "async_std_runtime", feature = "tokio_runtime"))]
+/// # {
+/// # use toy_rpc::Client;
+/// # use toy_rpc::rpc_call;
+/// # use toy_rpc::macros::export_impl;
+/// # struct Arith;
+/// # #[export_impl]
+/// # impl Arith {
+/// #     #[export_method]
+/// #     async fn add(&self, args: (i32, i32)) -> Result<i32, String> {
+/// #         Ok(args.0 + args.1)
+/// #     }
+/// # }
+/// # async fn run(client: Client) -> Result<(), Box<dyn std::error::Error>> {
+/// let sum: i32 = rpc_call!(client, Arith::add, (1, 2)).await?;
+/// # Ok(())
+/// # }
+/// # }
+/// ```
+#[macro_export]
+macro_rules! rpc_call {
+    ($client:expr, dyn $service:ident :: $method:ident, $args:expr) => {
+        <<dyn $service as $crate::client::typed::ServiceMethods>::Methods>::$method()
+            .call(&$client, $args)
+    };
+    ($client:expr, $service:ident :: $method:ident, $args:expr) => {
+        <<$service as $crate::client::typed::ServiceMethods>::Methods>::$method()
+            .call(&$client, $args)
+    };
+}
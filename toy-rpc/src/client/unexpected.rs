@@ -0,0 +1,241 @@
+//! Handling of responses that do not match a pending call
+//!
+//! A response may arrive with an id that does not belong to any pending call, eg.
+//! when the server answers after the call has timed out or been canceled, when the
+//! server sends the same response twice, or when the peer is misbehaving. Such a
+//! response is dropped without affecting other calls and is counted by its kind.
+//! The counters can be read with `Client::unexpected_responses`, and a handler can
+//! be registered with `Client::on_unexpected_response`.
+//!
+//! The id of a call that timed out or was canceled is not reused by new calls for
+//! a while, so a late response can never be mistaken for the response of a new
+//! call that happens to have the recycled id.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::clock;
+use crate::message::{AtomicMessageId, MessageId};
+
+/// How long the id of a call that timed out or was canceled is kept from reuse
+const QUARANTINE: Duration = Duration::from_secs(300);
+/// Number of completed calls remembered to detect duplicate responses
+const RECENT_CAPACITY: usize = 256;
+
+/// Kind of a response that does not match a pending call
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnexpectedResponse {
+    /// Response to a call that timed out or was canceled
+    Late,
+    /// Another response to a call that has already received its response
+    Duplicate,
+    /// Response to a call that was never made by this client
+    Unknown,
+}
+
+/// Numbers of unexpected responses received by a client
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct UnexpectedResponseStats {
+    /// Number of `UnexpectedResponse::Late`
+    pub late: u64,
+    /// Number of `UnexpectedResponse::Duplicate`
+    pub duplicate: u64,
+    /// Number of `UnexpectedResponse::Unknown`
+    pub unknown: u64,
+}
+
+/// Handler of the unexpected responses
+pub type UnexpectedResponseHandler =
+    Arc<dyn Fn(MessageId, UnexpectedResponse) + Send + Sync + 'static>;
+
+/// Counters shared between the client and its broker
+#[derive(Debug, Default)]
+#[cfg_attr(
+    all(not(feature = "tokio_runtime"), not(feature = "async_std_runtime")),
+    allow(dead_code)
+)]
+pub(crate) struct Counters {
+    late: AtomicU64,
+    duplicate: AtomicU64,
+    unknown: AtomicU64,
+}
+
+#[cfg(any(
+    all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+    all(feature = "async_std_runtime", not(feature = "tokio_runtime"))
+))]
+impl Counters {
+    pub fn snapshot(&self) -> UnexpectedResponseStats {
+        UnexpectedResponseStats {
+            late: self.late.load(Ordering::Relaxed),
+            duplicate: self.duplicate.load(Ordering::Relaxed),
+            unknown: self.unknown.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Ids that must not be used by new calls
+pub(crate) type ReservedIds = Arc<Mutex<HashSet<MessageId>>>;
+
+/// Returns the next id that is not reserved and reserves it
+#[cfg_attr(
+    all(not(feature = "tokio_runtime"), not(feature = "async_std_runtime")),
+    allow(dead_code)
+)]
+pub(crate) fn reserve_id(count: &AtomicMessageId, reserved: &ReservedIds) -> MessageId {
+    let mut reserved = match reserved.lock() {
+        Ok(reserved) => reserved,
+        Err(poisoned) => poisoned.into_inner(),
+    };
+    let mut id = count.fetch_add(1, Ordering::Relaxed);
+    for _ in 0..MessageId::MAX {
+        if reserved.insert(id) {
+            return id;
+        }
+        id = count.fetch_add(1, Ordering::Relaxed);
+    }
+    warn!("All message ids are in use, reusing id {}", id);
+    id
+}
+
+/// Keeps track of the ids of the calls made by a client
+#[cfg_attr(
+    all(not(feature = "tokio_runtime"), not(feature = "async_std_runtime")),
+    allow(dead_code)
+)]
+pub(crate) struct ResponseTracker {
+    reserved: ReservedIds,
+    quarantined: HashMap<MessageId, Instant>,
+    recent: VecDeque<MessageId>,
+    counters: Arc<Counters>,
+    handler: Option<UnexpectedResponseHandler>,
+}
+
+#[cfg_attr(
+    all(not(feature = "tokio_runtime"), not(feature = "async_std_runtime")),
+    allow(dead_code)
+)]
+impl ResponseTracker {
+    pub fn new(reserved: ReservedIds, counters: Arc<Counters>) -> Self {
+        Self {
+            reserved,
+            quarantined: HashMap::new(),
+            recent: VecDeque::with_capacity(RECENT_CAPACITY),
+            counters,
+            handler: None,
+        }
+    }
+
+    pub fn set_handler(&mut self, handler: UnexpectedResponseHandler) {
+        self.handler = Some(handler);
+    }
+
+    fn release(&self, ids: impl IntoIterator<Item = MessageId>) {
+        let mut reserved = match self.reserved.lock() {
+            Ok(reserved) => reserved,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        for id in ids {
+            reserved.remove(&id);
+        }
+    }
+
+    /// The call has received its response, so the id can be reused
+    pub fn completed(&mut self, id: MessageId) {
+        self.release(Some(id));
+        if self.recent.len() == RECENT_CAPACITY {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(id);
+    }
+
+    /// The call timed out or was canceled, so the id is kept from reuse until
+    /// the quarantine is over
+    pub fn expired(&mut self, id: MessageId) {
+        self.purge();
+        self.quarantined.insert(id, clock::now());
+    }
+
+    /// Releases the ids whose quarantine is over
+    pub fn purge(&mut self) {
+        let now = clock::now();
+        let released: Vec<MessageId> = self
+            .quarantined
+            .iter()
+            .filter(|(_, since)| now.duration_since(**since) >= QUARANTINE)
+            .map(|(id, _)| *id)
+            .collect();
+        if released.is_empty() {
+            return;
+        }
+        for id in &released {
+            self.quarantined.remove(id);
+        }
+        self.release(released);
+    }
+
+    /// Classifies and counts a response that does not match a pending call
+    pub fn unexpected(&mut self, id: MessageId) -> UnexpectedResponse {
+        let kind = if self.quarantined.remove(&id).is_some() {
+            self.release(Some(id));
+            self.counters.late.fetch_add(1, Ordering::Relaxed);
+            UnexpectedResponse::Late
+        } else if self.recent.contains(&id) {
+            self.counters.duplicate.fetch_add(1, Ordering::Relaxed);
+            UnexpectedResponse::Duplicate
+        } else {
+            self.counters.unknown.fetch_add(1, Ordering::Relaxed);
+            UnexpectedResponse::Unknown
+        };
+        debug!("Dropping {:?} response with id: {}", kind, id);
+        if let Some(handler) = &self.handler {
+            handler(id, kind);
+        }
+        kind
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reserved_ids_are_skipped() {
+        let count = AtomicMessageId::new(0);
+        let reserved: ReservedIds = Arc::new(Mutex::new(HashSet::new()));
+        reserved.lock().unwrap().insert(1);
+        assert_eq!(reserve_id(&count, &reserved), 0);
+        assert_eq!(reserve_id(&count, &reserved), 2);
+
+        let mut tracker = ResponseTracker::new(reserved.clone(), Arc::new(Counters::default()));
+        tracker.completed(0);
+        count.store(0, Ordering::Relaxed);
+        assert_eq!(reserve_id(&count, &reserved), 0);
+    }
+
+    #[test]
+    fn classify_unexpected_responses() {
+        let reserved: ReservedIds = Arc::new(Mutex::new(HashSet::new()));
+        let counters = Arc::new(Counters::default());
+        let mut tracker = ResponseTracker::new(reserved.clone(), counters.clone());
+        reserved.lock().unwrap().extend([1, 2]);
+
+        tracker.completed(1);
+        tracker.expired(2);
+        assert!(reserved.lock().unwrap().contains(&2));
+
+        assert_eq!(tracker.unexpected(1), UnexpectedResponse::Duplicate);
+        assert_eq!(tracker.unexpected(2), UnexpectedResponse::Late);
+        assert_eq!(tracker.unexpected(3), UnexpectedResponse::Unknown);
+        // the quarantine ends with the late response
+        assert!(!reserved.lock().unwrap().contains(&2));
+        assert_eq!(tracker.unexpected(2), UnexpectedResponse::Unknown);
+
+        let stats = counters.snapshot();
+        assert_eq!(stats.late, 1);
+        assert_eq!(stats.duplicate, 1);
+        assert_eq!(stats.unknown, 2);
+    }
+}
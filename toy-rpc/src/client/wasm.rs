@@ -0,0 +1,327 @@
+//! Client for browsers
+//!
+//! `WasmClient` calls the methods of a toy-rpc server from a module compiled for
+//! `wasm32-unknown-unknown`, eg. the frontend of a web application, over the
+//! `WebSocket` of the browser. It does not need `tokio` or `async-std`: its futures
+//! can be spawned with `wasm_bindgen_futures::spawn_local` or awaited in any future
+//! that is handed to JavaScript as a `Promise`.
+//!
+//! The server is an HTTP server with one of the integrations (`http_actix_web`,
+//! `http_warp` or `http_tide`) or a server accepting WebSocket connections, and it
+//! must use the same default codec as the client (feature `serde_bincode`,
+//! `serde_json`, `serde_cbor`, `serde_rmp` or `serde_postcard`).
+//!
+//! Unlike `Client`, which runs a broker on the async runtime, `WasmClient` handles
+//! the messages in the callbacks of the `WebSocket`, and only supports calls. A
+//! call times out on the client like on the server, and it is canceled on the
+//! server when it times out or when its future is dropped.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use toy_rpc::client::wasm::WasmClient;
+//!
+//! wasm_bindgen_futures::spawn_local(async {
+//!     let client = WasmClient::dial_http("http://127.0.0.1:23333/rpc/").await.unwrap();
+//!     let reply: i32 = client.call("Arith.add", (3i32, 4i32)).await.unwrap();
+//!     client.close();
+//! });
+//! ```
+
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::Duration;
+
+use futures::channel::oneshot;
+use futures::future::{self, Either};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{BinaryType, CloseEvent, MessageEvent, WebSocket};
+
+use toy_rpc_core::message::{CANCELLATION_TOKEN, CANCELLATION_TOKEN_DELIM};
+
+use crate::codec::{Marshal, Unmarshal};
+use crate::message::{ErrorMessage, MessageId};
+use crate::protocol::Header;
+use crate::transport::ws::chunk::{self, Reassembly};
+use crate::{Error, DEFAULT_RPC_PATH};
+
+use super::wasm_shared::{Format, Reply, Shared};
+
+const DEFAULT_TIMEOUT_SECONDS: u64 = 10;
+
+#[wasm_bindgen]
+extern "C" {
+    // available in the windows and in the workers
+    #[wasm_bindgen(js_name = setTimeout)]
+    fn set_timeout(handler: &js_sys::Function, timeout: i32) -> JsValue;
+}
+
+async fn sleep(duration: Duration) {
+    let millis = std::cmp::min(duration.as_millis(), i32::MAX as u128) as i32;
+    let promise = js_sys::Promise::new(&mut |resolve, _| {
+        set_timeout(&resolve, millis);
+    });
+    let _ = JsFuture::from(promise).await;
+}
+
+fn js_error(value: JsValue) -> Error {
+    Error::Internal(format!("{:?}", value).into())
+}
+
+/// RPC client for browsers. See the `wasm` module for details.
+pub struct WasmClient {
+    ws: WebSocket,
+    shared: Rc<RefCell<Shared>>,
+    default_timeout: Duration,
+    _on_message: Closure<dyn FnMut(MessageEvent)>,
+    _on_close: Closure<dyn FnMut(CloseEvent)>,
+}
+
+impl WasmClient {
+    /// Connects to an HTTP RPC server at the specified address using WebSocket.
+    /// Like `Client::dial_http`, `DEFAULT_RPC_PATH` is appended to `addr`, which
+    /// must end with a slash "/" if it has a path. The scheme "http" is changed to
+    /// "ws" and "https" to "wss".
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use toy_rpc::client::wasm::WasmClient;
+    /// # async fn run() -> Result<(), toy_rpc::Error> {
+    /// let client = WasmClient::dial_http("https://example.com/rpc/").await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn dial_http(addr: &str) -> Result<Self, Error> {
+        let mut url = url::Url::parse(addr)?.join(DEFAULT_RPC_PATH)?;
+        let scheme = match url.scheme() {
+            "https" | "wss" => "wss",
+            _ => "ws",
+        };
+        url.set_scheme(scheme)
+            .expect("Failed to change scheme to ws");
+
+        Self::dial_websocket(url.as_str()).await
+    }
+
+    /// Connects to a WebSocket RPC server at the specified url
+    pub async fn dial_websocket(addr: &str) -> Result<Self, Error> {
+        // the server accepts the offered subprotocol if it splits the payloads
+        let ws = WebSocket::new_with_str(addr, chunk::PROTOCOL).map_err(js_error)?;
+        ws.set_binary_type(BinaryType::Arraybuffer);
+
+        let shared = Rc::new(RefCell::new(Shared::new()));
+
+        // resolved when the connection is opened or closed, whichever comes first
+        let (opened_tx, opened_rx) = oneshot::channel();
+        let opened_tx = Rc::new(RefCell::new(Some(opened_tx)));
+
+        let on_open = {
+            let opened_tx = opened_tx.clone();
+            Closure::<dyn FnMut(JsValue)>::new(move |_| {
+                if let Some(tx) = opened_tx.borrow_mut().take() {
+                    let _ = tx.send(true);
+                }
+            })
+        };
+        ws.set_onopen(Some(on_open.as_ref().unchecked_ref()));
+        // some implementations do not close the socket after a failed connection
+        let on_error = {
+            let opened_tx = opened_tx.clone();
+            Closure::<dyn FnMut(JsValue)>::new(move |_| {
+                if let Some(tx) = opened_tx.borrow_mut().take() {
+                    let _ = tx.send(false);
+                }
+            })
+        };
+        ws.set_onerror(Some(on_error.as_ref().unchecked_ref()));
+
+        let on_message = {
+            let shared = shared.clone();
+            Closure::<dyn FnMut(MessageEvent)>::new(move |event: MessageEvent| {
+                match event.data().dyn_into::<js_sys::ArrayBuffer>() {
+                    Ok(buf) => shared
+                        .borrow_mut()
+                        .receive(js_sys::Uint8Array::new(&buf).to_vec()),
+                    Err(_) => warn!("Expecting binary WebSocket message"),
+                }
+            })
+        };
+        ws.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+
+        let on_close = {
+            let shared = shared.clone();
+            Closure::<dyn FnMut(CloseEvent)>::new(move |event: CloseEvent| {
+                info!("WebSocket closed with code {}", event.code());
+                if let Some(tx) = opened_tx.borrow_mut().take() {
+                    let _ = tx.send(false);
+                }
+                shared.borrow_mut().close();
+            })
+        };
+        ws.set_onclose(Some(on_close.as_ref().unchecked_ref()));
+
+        let opened = opened_rx.await.unwrap_or(false);
+        ws.set_onopen(None);
+        ws.set_onerror(None);
+        drop(on_open);
+        drop(on_error);
+        shared.borrow_mut().reassembly = Reassembly::new(ws.protocol() == chunk::PROTOCOL);
+        let client = Self {
+            ws,
+            shared,
+            default_timeout: Duration::from_secs(DEFAULT_TIMEOUT_SECONDS),
+            _on_message: on_message,
+            _on_close: on_close,
+        };
+        match opened {
+            true => Ok(client),
+            // the browser does not tell why the connection failed
+            false => Err(Error::ConnectionRefused),
+        }
+    }
+
+    /// Sets the default timeout duration for all calls
+    pub fn set_default_timeout(&mut self, duration: Duration) -> &Self {
+        self.default_timeout = duration;
+        self
+    }
+
+    /// Invokes the named RPC function with the default timeout. See `Client::call`.
+    pub async fn call<Req, Res>(
+        &self,
+        service_method: impl ToString,
+        args: Req,
+    ) -> Result<Res, Error>
+    where
+        Req: serde::Serialize,
+        Res: serde::de::DeserializeOwned,
+    {
+        self.call_with_timeout(service_method, args, self.default_timeout)
+            .await
+    }
+
+    /// Invokes the named RPC function with a timeout that overrides the default
+    /// timeout
+    pub async fn call_with_timeout<Req, Res>(
+        &self,
+        service_method: impl ToString,
+        args: Req,
+        duration: Duration,
+    ) -> Result<Res, Error>
+    where
+        Req: serde::Serialize,
+        Res: serde::de::DeserializeOwned,
+    {
+        let (id, reply) = self.request(service_method.to_string(), &args, duration)?;
+        let mut pending = PendingCall {
+            client: self,
+            id: Some(id),
+        };
+
+        let timeout = Box::pin(sleep(duration));
+        let reply = match future::select(reply, timeout).await {
+            Either::Left((Ok(reply), _)) => reply,
+            Either::Left((Err(_), _)) => Err(Error::Canceled(Some(id))),
+            Either::Right(_) => return Err(Error::Timeout(Some(id))),
+        };
+        pending.id = None;
+
+        let (is_ok, body) = reply?;
+        match is_ok {
+            true => Format::unmarshal(&body),
+            false => {
+                let msg: ErrorMessage = Format::unmarshal(&body)?;
+                Err(Error::from_err_msg(msg))
+            }
+        }
+    }
+
+    /// Closes the connection. The pending calls fail with `Error::ConnectionReset`.
+    pub fn close(&self) {
+        if let Err(err) = self.ws.close() {
+            error!("{:?}", err);
+        }
+        self.shared.borrow_mut().close();
+    }
+
+    fn request<Req: serde::Serialize>(
+        &self,
+        service_method: String,
+        args: &Req,
+        duration: Duration,
+    ) -> Result<(MessageId, oneshot::Receiver<Reply>), Error> {
+        let (id, rx) = {
+            let mut shared = self.shared.borrow_mut();
+            if shared.closed {
+                return Err(Error::ConnectionReset);
+            }
+            let id = shared.next_id;
+            shared.next_id = id.wrapping_add(1);
+            let (tx, rx) = oneshot::channel();
+            shared.pending.insert(id, tx);
+            (id, rx)
+        };
+
+        let header = Header::Request {
+            id,
+            service_method,
+            timeout: duration,
+        };
+        if let Err(err) = self.send(&header, args) {
+            self.shared.borrow_mut().pending.remove(&id);
+            return Err(err);
+        }
+        Ok((id, rx))
+    }
+
+    fn send<B: serde::Serialize>(&self, header: &Header, body: &B) -> Result<(), Error> {
+        let payloads = [Format::marshal(header)?, Format::marshal(body)?];
+        let chunked = self.shared.borrow().reassembly.chunked();
+        for payload in payloads.iter() {
+            for message in chunk::split(payload, chunked) {
+                self.ws.send_with_u8_array(&message).map_err(js_error)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for WasmClient {
+    fn drop(&mut self) {
+        // the callbacks are freed with the client
+        self.ws.set_onmessage(None);
+        self.ws.set_onclose(None);
+        let _ = self.ws.close();
+        self.shared.borrow_mut().close();
+    }
+}
+
+/// Cancels the call on the server if it is dropped before the reply is received
+struct PendingCall<'c> {
+    client: &'c WasmClient,
+    id: Option<MessageId>,
+}
+
+impl<'c> Drop for PendingCall<'c> {
+    fn drop(&mut self) {
+        if let Some(id) = self.id {
+            if self
+                .client
+                .shared
+                .borrow_mut()
+                .pending
+                .remove(&id)
+                .is_none()
+            {
+                return;
+            }
+            let token = format!("{}{}{}", CANCELLATION_TOKEN, CANCELLATION_TOKEN_DELIM, id);
+            if let Err(err) = self.client.send(&Header::Cancel(id), &token) {
+                error!("Failed to cancel call {}: {}", id, err);
+            }
+        }
+    }
+}
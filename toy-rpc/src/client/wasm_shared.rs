@@ -0,0 +1,187 @@
+//! Handling of the messages received by a `WasmClient`
+//!
+//! The state is shared between the client and the callbacks of its `WebSocket`. It
+//! does not depend on the browser, so that it is also tested natively.
+
+use std::collections::HashMap;
+
+use futures::channel::oneshot;
+
+use crate::codec::{ConnTypePayload, DefaultCodec, Unmarshal};
+use crate::message::MessageId;
+use crate::protocol::Header;
+use crate::transport::ws::chunk::Reassembly;
+use crate::Error;
+
+/// Serializes the messages like the default codec of the server
+pub(crate) type Format = DefaultCodec<(), (), ConnTypePayload>;
+
+/// Body of a response and whether the result is Ok
+pub(crate) type Reply = Result<(bool, Vec<u8>), Error>;
+
+/// State of a `WasmClient` that is shared with the callbacks of its `WebSocket`
+// the ids are only assigned by the client
+#[cfg_attr(not(target_arch = "wasm32"), allow(dead_code))]
+pub(crate) struct Shared {
+    pub next_id: MessageId,
+    pub pending: HashMap<MessageId, oneshot::Sender<Reply>>,
+    pub reassembly: Reassembly,
+    // header of the message whose body is the next payload
+    header: Option<Header>,
+    pub closed: bool,
+}
+
+impl Shared {
+    pub fn new() -> Self {
+        Self {
+            next_id: 0,
+            pending: HashMap::new(),
+            reassembly: Reassembly::new(false),
+            header: None,
+            closed: false,
+        }
+    }
+
+    /// Handles a message of the `WebSocket`. The body of a response is sent to its
+    /// pending call, and the other bodies are dropped.
+    pub fn receive(&mut self, message: Vec<u8>) {
+        let payload = match self.reassembly.push(message) {
+            Ok(Some(payload)) => payload,
+            Ok(None) => return,
+            Err(err) => {
+                warn!("{}", err);
+                return;
+            }
+        };
+        match self.header.take() {
+            None => match Format::unmarshal::<Header>(&payload) {
+                Ok(header) => {
+                    debug!("{:?}", &header);
+                    self.header = Some(header);
+                }
+                Err(err) => warn!("Failed to read header: {}", err),
+            },
+            Some(Header::Response { id, is_ok }) => {
+                if let Some(tx) = self.pending.remove(&id) {
+                    let _ = tx.send(Ok((is_ok, payload)));
+                }
+            }
+            // only the responses are handled
+            Some(header) => debug!("Dropping the body of {:?}", header),
+        }
+    }
+
+    /// Fails the pending calls with `Error::ConnectionReset`
+    pub fn close(&mut self) {
+        self.closed = true;
+        for (_, tx) in self.pending.drain() {
+            let _ = tx.send(Err(Error::ConnectionReset));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::Marshal;
+    use crate::transport::ws::chunk;
+
+    fn pending(shared: &mut Shared, id: MessageId) -> oneshot::Receiver<Reply> {
+        let (tx, rx) = oneshot::channel();
+        shared.pending.insert(id, tx);
+        rx
+    }
+
+    fn send<B: serde::Serialize>(shared: &mut Shared, header: &Header, body: &B, chunked: bool) {
+        let payloads = [
+            Format::marshal(header).unwrap(),
+            Format::marshal(body).unwrap(),
+        ];
+        for payload in payloads.iter() {
+            for message in chunk::split(payload, chunked) {
+                shared.receive(message);
+            }
+        }
+    }
+
+    fn reply(rx: &mut oneshot::Receiver<Reply>) -> (bool, i32) {
+        let (is_ok, body) = rx.try_recv().unwrap().expect("No reply").unwrap();
+        (is_ok, Format::unmarshal(&body).unwrap())
+    }
+
+    #[test]
+    fn responses_are_sent_to_their_calls() {
+        let mut shared = Shared::new();
+        let mut first = pending(&mut shared, 0);
+        let mut second = pending(&mut shared, 1);
+
+        send(
+            &mut shared,
+            &Header::Response {
+                id: 1,
+                is_ok: false,
+            },
+            &7i32,
+            false,
+        );
+        assert_eq!(reply(&mut second), (false, 7));
+        assert!(first.try_recv().unwrap().is_none());
+
+        // the body of a message that is not a response is not taken for a header
+        send(&mut shared, &Header::Cancel(0), &"cancel", false);
+        send(
+            &mut shared,
+            &Header::Response { id: 0, is_ok: true },
+            &3i32,
+            false,
+        );
+        assert_eq!(reply(&mut first), (true, 3));
+        assert!(shared.pending.is_empty());
+
+        // the response of a call that is no longer pending is dropped
+        send(
+            &mut shared,
+            &Header::Response { id: 2, is_ok: true },
+            &5i32,
+            false,
+        );
+        let mut third = pending(&mut shared, 3);
+        send(
+            &mut shared,
+            &Header::Response { id: 3, is_ok: true },
+            &6i32,
+            false,
+        );
+        assert_eq!(reply(&mut third), (true, 6));
+    }
+
+    #[test]
+    fn chunked_responses_are_reassembled() {
+        let mut shared = Shared::new();
+        shared.reassembly = Reassembly::new(true);
+        let mut rx = pending(&mut shared, 0);
+
+        let body = vec![7u8; 3 * chunk::MAX_MESSAGE_LEN];
+        send(
+            &mut shared,
+            &Header::Response { id: 0, is_ok: true },
+            &body,
+            true,
+        );
+        let (is_ok, bytes) = rx.try_recv().unwrap().expect("No reply").unwrap();
+        assert!(is_ok);
+        assert_eq!(Format::unmarshal::<Vec<u8>>(&bytes).unwrap(), body);
+    }
+
+    #[test]
+    fn pending_calls_fail_when_closed() {
+        let mut shared = Shared::new();
+        let mut rx = pending(&mut shared, 0);
+
+        shared.close();
+        assert!(shared.closed);
+        assert!(shared.pending.is_empty());
+        let reply = rx.try_recv().unwrap().expect("No reply");
+        assert!(matches!(reply, Err(Error::ConnectionReset)));
+    }
+}
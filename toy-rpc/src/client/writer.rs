@@ -10,6 +10,8 @@ cfg_if! {
         use brw::Running;
 
         use crate::{message::Metadata, util::GracefulShutdown};
+        #[cfg(feature = "server")]
+        use crate::message::ErrorMessage;
 
         use crate::{
             Error, codec::CodecWrite,
@@ -22,11 +24,24 @@ cfg_if! {
         };
 
         pub enum ClientWriterItem {
-            Request(MessageId, String, Duration, Box<OutboundBody>),
-            Publish(MessageId, String, Box<OutboundBody>),
-            Subscribe(MessageId, String),
+            /// Request with its serialized body
+            Request(MessageId, String, Duration, Vec<u8>),
+            /// Request whose argument is sent by a `ClientSink`
+            SinkRequest(MessageId, String, Duration, Vec<u8>),
+            SinkItem(MessageId, Box<OutboundBody>),
+            SinkEnd(MessageId),
+            /// Publication with its type tag and whether the server acknowledges it
+            Publish(MessageId, String, Box<OutboundBody>, Option<u64>, bool),
+            /// Acknowledges a publication from the server
+            Ack(MessageId),
+            /// Subscription with the number of past messages to replay
+            Subscribe(MessageId, String, Option<usize>),
             Unsubscribe(MessageId, String),
+            Ext(MessageId, u32, String),
             Cancel(MessageId),
+            /// Response to a call from the server, with its serialized body
+            #[cfg(feature = "server")]
+            Response(MessageId, Result<Vec<u8>, ErrorMessage>),
             Stop,
         }
 
@@ -44,6 +59,17 @@ cfg_if! {
                 self.writer.write_header(header).await?;
                 self.writer.write_body(id, body).await
             }
+
+            /// Writes a request whose body is already serialized
+            pub async fn write_request_bytes(
+                &mut self,
+                header: Header,
+                buf: &[u8],
+            ) -> Result<(), Error> {
+                let id = header.get_id();
+                self.writer.write_header(header).await?;
+                self.writer.write_body_bytes(id, buf).await
+            }
         }
 
         #[async_trait]
@@ -54,34 +80,77 @@ cfg_if! {
 
             async fn op(&mut self, item: Self::Item) -> Running<Result<Self::Ok, Self::Error>> {
                 let res = match item {
-                    ClientWriterItem::Request(id, service_method, duration, body) => {
+                    ClientWriterItem::Request(id, service_method, duration, buf) => {
                         let header = Header::Request{id, service_method, timeout: duration};
-                        log::debug!("{:?}", &header);
+                        debug!("{:?}", &header);
+                        self.write_request_bytes(header, &buf).await
+                    },
+                    ClientWriterItem::SinkRequest(id, service_method, duration, buf) => {
+                        let header = Header::SinkRequest{id, service_method, timeout: duration};
+                        debug!("{:?}", &header);
+                        self.write_request_bytes(header, &buf).await
+                    },
+                    ClientWriterItem::SinkItem(id, body) => {
+                        let header = Header::SinkItem(id);
+                        trace!("{:?}", &header);
                         self.write_request(header, &body).await
                     },
+                    ClientWriterItem::SinkEnd(id) => {
+                        let header = Header::SinkEnd(id);
+                        debug!("{:?}", &header);
+                        self.write_request(header, &()).await
+                    },
                     ClientWriterItem::Cancel(id) => {
                         let header = Header::Cancel(id);
-                        log::debug!("{:?}", &header);
+                        debug!("{:?}", &header);
                         let body: String =
                             format!("{}{}{}", CANCELLATION_TOKEN, CANCELLATION_TOKEN_DELIM, id);
                         let body = Box::new(body) as Box<OutboundBody>;
                         self.write_request(header, &body).await
                     },
-                    ClientWriterItem::Publish(id, topic, body) => {
-                        let header = Header::Publish{id, topic};
-                        log::debug!("{:?}", &header);
+                    ClientWriterItem::Publish(id, topic, body, tag, ack) => {
+                        let header = match (tag, ack) {
+                            (tag, true) => Header::AckedPublish{id, topic, tag},
+                            (Some(tag), false) => Header::TaggedPublish{id, topic, tag},
+                            (None, false) => Header::Publish{id, topic},
+                        };
+                        debug!("{:?}", &header);
                         self.write_request(header, &body).await
                     },
-                    ClientWriterItem::Subscribe(id, topic) => {
-                        let header = Header::Subscribe{id, topic};
-                        log::debug!("{:?}", &header);
+                    ClientWriterItem::Ack(id) => {
+                        let header = Header::Ack(id);
+                        trace!("{:?}", &header);
                         self.write_request(header, &()).await
                     },
+                    ClientWriterItem::Subscribe(id, topic, replay) => {
+                        let header = Header::Subscribe{id, topic};
+                        debug!("{:?}", &header);
+                        // servers that do not replay read the body of a plain
+                        // subscription as `()`
+                        match replay {
+                            Some(n) => self.write_request(header, &Some(n as u64)).await,
+                            None => self.write_request(header, &()).await,
+                        }
+                    },
                     ClientWriterItem::Unsubscribe(id, topic) => {
                         let header = Header::Unsubscribe{id, topic};
-                        log::debug!("{:?}", &header);
+                        debug!("{:?}", &header);
+                        self.write_request(header, &()).await
+                    }
+                    ClientWriterItem::Ext(id, marker, content) => {
+                        let header = Header::Ext{id, content, marker};
+                        debug!("{:?}", &header);
                         self.write_request(header, &()).await
                     }
+                    #[cfg(feature = "server")]
+                    ClientWriterItem::Response(id, result) => {
+                        let header = Header::Response{id, is_ok: result.is_ok()};
+                        debug!("{:?}", &header);
+                        match result {
+                            Ok(buf) => self.write_request_bytes(header, &buf).await,
+                            Err(msg) => self.write_request(header, &msg).await,
+                        }
+                    },
                     ClientWriterItem::Stop => {
                         self.writer.close().await;
                         return Running::Stop
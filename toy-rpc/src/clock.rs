@@ -0,0 +1,94 @@
+//! Clock of the timers of the crate
+//!
+//! The timeouts of the calls and of their executions, the grace period of the
+//! shutdowns, the timers of the pubsub broker and the clock jump watchdog of the
+//! client all read the time and wait through this module. With the `test-util`
+//! feature the clock can be paused and advanced by hand, see `test_util::clock`.
+
+#![cfg_attr(
+    all(not(feature = "tokio_runtime"), not(feature = "async_std_runtime")),
+    allow(dead_code)
+)]
+
+use std::time::Instant;
+#[cfg(any(feature = "client", feature = "server"))]
+use std::time::SystemTime;
+
+#[cfg(any(
+    all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+    all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+))]
+use std::{future::Future, time::Duration};
+
+/// Error of a `timeout` that elapsed before the future completed
+#[derive(Debug)]
+pub(crate) struct Elapsed;
+
+/// Current time of the monotonic clock
+pub(crate) fn now() -> Instant {
+    #[cfg(feature = "test-util")]
+    {
+        if let Some(now) = crate::test_util::clock::paused_now() {
+            return now;
+        }
+    }
+    Instant::now()
+}
+
+/// Current time of the wall clock, which moves along with `now` while the clock is
+/// paused
+#[cfg(any(feature = "client", feature = "server"))]
+pub(crate) fn system_now() -> SystemTime {
+    #[cfg(feature = "test-util")]
+    {
+        if let Some(now) = crate::test_util::clock::paused_system_now() {
+            return now;
+        }
+    }
+    SystemTime::now()
+}
+
+/// Waits until `duration` has elapsed
+#[cfg(any(
+    all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+    all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+))]
+pub(crate) async fn sleep(duration: Duration) {
+    #[cfg(feature = "test-util")]
+    {
+        if let Some(sleep) = crate::test_util::clock::paused_sleep(duration) {
+            return sleep.await;
+        }
+    }
+
+    #[cfg(all(feature = "tokio_runtime", not(feature = "async_std_runtime")))]
+    ::tokio::time::sleep(duration).await;
+    #[cfg(all(feature = "async_std_runtime", not(feature = "tokio_runtime")))]
+    ::async_std::task::sleep(duration).await;
+}
+
+/// Waits for `fut` to complete, fails with `Elapsed` once `duration` has elapsed
+#[cfg(any(
+    all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+    all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+))]
+pub(crate) async fn timeout<F: Future>(duration: Duration, fut: F) -> Result<F::Output, Elapsed> {
+    #[cfg(feature = "test-util")]
+    {
+        if let Some(sleep) = crate::test_util::clock::paused_sleep(duration) {
+            use futures::future::{select, Either};
+
+            futures::pin_mut!(fut);
+            return match select(fut, sleep).await {
+                Either::Left((output, _)) => Ok(output),
+                Either::Right(_) => Err(Elapsed),
+            };
+        }
+    }
+
+    #[cfg(all(feature = "tokio_runtime", not(feature = "async_std_runtime")))]
+    let result = ::tokio::time::timeout(duration, fut).await;
+    #[cfg(all(feature = "async_std_runtime", not(feature = "tokio_runtime")))]
+    let result = ::async_std::future::timeout(duration, fut).await;
+    result.map_err(|_| Elapsed)
+}
@@ -21,6 +21,7 @@ where
         Self {
             reader,
             writer,
+            frame: FrameOptions::default(),
             conn_type: PhantomData,
         }
     }
@@ -49,12 +50,12 @@ where
     async fn close(&mut self) {
         match self.writer.flush().await {
             Ok(()) => (),
-            Err(e) => log::error!("Error closing connection: {}", e),
+            Err(e) => error!("Error closing connection: {}", e),
         };
 
         match AsyncWriteExt::close(&mut self.writer).await {
             Ok(()) => (),
-            Err(e) => log::error!("Error closing connection: {}", e),
+            Err(e) => error!("Error closing connection: {}", e),
         };
     }
 }
@@ -68,4 +69,8 @@ where
     async fn close(&mut self) {
         self.writer.close().await;
     }
+
+    async fn close_with(&mut self, code: u16, reason: &str) {
+        self.writer.close_with(code, reason).await;
+    }
 }
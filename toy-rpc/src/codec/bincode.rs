@@ -9,6 +9,8 @@ cfg_if! {
 
     } else if #[cfg(feature = "serde_rmp")] {
 
+    } else if #[cfg(feature = "serde_postcard")] {
+
     } else {
         use bincode::{DefaultOptions, Options};
         use erased_serde as erased;
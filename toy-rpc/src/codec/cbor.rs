@@ -9,6 +9,8 @@ cfg_if! {
 
     } else if #[cfg(feature = "serde_rmp")] {
 
+    } else if #[cfg(feature = "serde_postcard")] {
+
     } else {
         use erased_serde as erased;
         use serde::de::Visitor;
@@ -30,7 +32,14 @@ cfg_if! {
 
         impl<R, W, C> Marshal for Codec<R, W, C> {
             fn marshal<S: serde::Serialize>(val: &S) -> Result<Vec<u8>, Error> {
-                serde_cbor::to_vec(val).map_err(|e| e.into())
+                // the entries of a `serde_cbor::Value::Map` are sorted by key
+                #[cfg(feature = "canonical")]
+                let bytes = serde_cbor::value::to_value(val)
+                    .and_then(|value| serde_cbor::to_vec(&value));
+                #[cfg(not(feature = "canonical"))]
+                let bytes = serde_cbor::to_vec(val);
+
+                bytes.map_err(|e| e.into())
             }
         }
 
@@ -0,0 +1,424 @@
+//! Codec with a wire format implemented outside of `toy-rpc`
+//!
+//! The `serde_*` feature flags choose among the formats that come with `toy-rpc`.
+//! Any other format that works with serde can be plugged in by implementing
+//! `Marshal`, `Unmarshal` and `EraseDeserializer` on a type of the application, and
+//! serving the connections with a `CustomCodec` of that type, which writes the
+//! messages over the custom binary transport. The custom codec does not need any of
+//! the `serde_*` feature flags, but it is not available with `serde_json`, whose
+//! codec uses another transport.
+//!
+//! `EraseDeserializer::from_bytes` must return a deserializer that owns the bytes.
+//! With a format whose deserializer reads from an `std::io::Read`, this is the
+//! deserializer of a `Cursor<Vec<u8>>` wrapped with `erase_owned`.
+//!
+//! # Example
+//!
+//! ```no_run
+//! # #[cfg(all(feature = "postcard_codec", feature = "tokio_runtime", not(feature = "async_std_runtime")))]
+//! # {
+//! # use tokio::net::{TcpListener, TcpStream};
+//! # use toy_rpc::{Client, Server};
+//! # use toy_rpc::codec::custom::CustomCodec;
+//! # use toy_rpc::Error;
+//! # use toy_rpc::codec::{EraseDeserializer, Marshal, Unmarshal};
+//! # use toy_rpc::codec::custom::erase_owned;
+//! # use toy_rpc::erased_serde;
+//! # use toy_rpc::serde;
+//! # struct OwnedBytes {
+//! #     buf: Vec<u8>,
+//! #     pos: usize,
+//! # }
+//! # impl OwnedBytes {
+//! #     fn new(buf: Vec<u8>) -> Self {
+//! #         Self { buf, pos: 0 }
+//! #     }
+//! # }
+//! # impl postcard::de_flavors::Flavor<'static> for OwnedBytes {
+//! #     type Remainder = ();
+//! #     type Source = ();
+//! #     fn pop(&mut self) -> postcard::Result<u8> {
+//! #         let byte = *self.buf.get(self.pos).ok_or(postcard::Error::DeserializeUnexpectedEnd)?;
+//! #         self.pos += 1;
+//! #         Ok(byte)
+//! #     }
+//! #     fn try_take_n(&mut self, _: usize) -> postcard::Result<&'static [u8]> {
+//! #         Err(postcard::Error::WontImplement)
+//! #     }
+//! #     fn finalize(self) -> postcard::Result<()> {
+//! #         Ok(())
+//! #     }
+//! # }
+//! pub struct Postcard;
+//!
+//! impl Marshal for Postcard {
+//!     fn marshal<S: serde::Serialize>(val: &S) -> Result<Vec<u8>, Error> {
+//!         postcard::to_allocvec(val).map_err(|err| Error::ParseError(Box::new(err)))
+//!     }
+//! }
+//!
+//! impl Unmarshal for Postcard {
+//!     fn unmarshal<'de, D: serde::Deserialize<'de>>(buf: &'de [u8]) -> Result<D, Error> {
+//!         postcard::from_bytes(buf).map_err(|err| Error::ParseError(Box::new(err)))
+//!     }
+//! }
+//!
+//! impl EraseDeserializer for Postcard {
+//!     fn from_bytes(buf: Vec<u8>) -> Box<dyn erased_serde::Deserializer<'static> + Send> {
+//!         // a `postcard::de_flavors::Flavor` that owns the bytes
+//!         erase_owned(postcard::Deserializer::from_flavor(OwnedBytes::new(buf)))
+//!     }
+//! }
+//!
+//! # async fn run(server: Server, listener: TcpListener, addr: &str) -> Result<(), Box<dyn std::error::Error>> {
+//! // server
+//! let (stream, _) = listener.accept().await?;
+//! server.serve_codec(CustomCodec::<_, _, Postcard>::new(stream)).await?;
+//!
+//! // client
+//! let stream = TcpStream::connect(addr).await?;
+//! let client = Client::with_custom_codec::<_, Postcard>(stream);
+//! # Ok(())
+//! # }
+//! # }
+//! ```
+//!
+//! See `examples/custom_codec` for the complete example.
+
+use cfg_if::cfg_if;
+use erased_serde as erased;
+use serde::de::Visitor;
+use std::marker::PhantomData;
+
+use super::split::{CodecReadHalf, CodecWriteHalf, SplittableCodec};
+use super::{ConnTypeReadWrite, EraseDeserializer, FrameOptions, Marshal, Unmarshal};
+use crate::compression::Compression;
+use crate::error::Error;
+use crate::transport::frame::{FrameRead, FrameWrite};
+use crate::util::GracefulShutdown;
+
+cfg_if! {
+    if #[cfg(any(
+        feature = "async_std_runtime",
+        feature = "http_tide"
+    ))] {
+        use futures::io::{AsyncRead, AsyncReadExt, AsyncWrite, BufReader, BufWriter, ReadHalf, WriteHalf};
+    } else {
+        use ::tokio::io::{split, AsyncRead, AsyncWrite, BufReader, BufWriter, ReadHalf, WriteHalf};
+    }
+}
+
+/// Codec that serializes the messages with the format `F` over the custom binary
+/// transport
+pub struct CustomCodec<R, W, F> {
+    reader: R,
+    writer: W,
+    pub(crate) frame: FrameOptions,
+    format: PhantomData<F>,
+}
+
+impl<R, W, F> CustomCodec<R, W, F>
+where
+    R: FrameRead + Send + Unpin,
+    W: FrameWrite + Send + Unpin,
+{
+    /// Creates a `CustomCodec` with a reader and a writer
+    pub fn with_reader_writer(reader: R, writer: W) -> Self {
+        Self {
+            reader,
+            writer,
+            frame: FrameOptions::default(),
+            format: PhantomData,
+        }
+    }
+}
+
+impl<T, F> CustomCodec<BufReader<ReadHalf<T>>, BufWriter<WriteHalf<T>>, F>
+where
+    T: AsyncRead + AsyncWrite + Send + Unpin,
+{
+    /// Creates a `CustomCodec` with a stream that implements both `AsyncRead` and
+    /// `AsyncWrite`
+    pub fn new(stream: T) -> Self {
+        cfg_if! {
+            if #[cfg(any(
+                feature = "async_std_runtime",
+                feature = "http_tide"
+            ))] {
+                let (reader, writer) = stream.split();
+            } else {
+                let (reader, writer) = split(stream);
+            }
+        }
+        Self::with_reader_writer(BufReader::new(reader), BufWriter::new(writer))
+    }
+}
+
+impl<R, W, F> CustomCodec<R, W, F> {
+    /// Compresses the outbound payloads of the codec with `compression`. The inbound
+    /// payloads are decompressed whether or not this is set.
+    pub fn with_compression(self, compression: Compression) -> Self {
+        let mut codec = self;
+        codec.frame.compression = Some(compression);
+        codec
+    }
+
+    /// Writes the `Request` and `Response` headers with the fixed encoding instead of
+    /// the format `F`. See `Codec::with_fixed_headers`.
+    pub fn with_fixed_headers(self) -> Self {
+        let mut codec = self;
+        codec.frame.fixed_headers = true;
+        codec
+    }
+}
+
+impl<R, W, F> SplittableCodec for CustomCodec<R, W, F>
+where
+    R: FrameRead + Send + Unpin,
+    W: FrameWrite + GracefulShutdown + Send + Unpin,
+    F: Marshal + Unmarshal + EraseDeserializer + Send + Sync + 'static,
+{
+    type Writer = CodecWriteHalf<W, Self, ConnTypeReadWrite>;
+    type Reader = CodecReadHalf<R, Self, ConnTypeReadWrite>;
+
+    fn split(self) -> (Self::Writer, Self::Reader) {
+        (
+            CodecWriteHalf {
+                writer: self.writer,
+                frame: self.frame,
+                marker: PhantomData,
+                conn_type: PhantomData,
+                meter: None,
+            },
+            CodecReadHalf {
+                reader: self.reader,
+                marker: PhantomData,
+                conn_type: PhantomData,
+                meter: None,
+            },
+        )
+    }
+}
+
+impl<R, W, F: Marshal> Marshal for CustomCodec<R, W, F> {
+    fn marshal<S: serde::Serialize>(val: &S) -> Result<Vec<u8>, Error> {
+        F::marshal(val)
+    }
+}
+
+impl<R, W, F: Unmarshal> Unmarshal for CustomCodec<R, W, F> {
+    fn unmarshal<'de, D: serde::Deserialize<'de>>(buf: &'de [u8]) -> Result<D, Error> {
+        F::unmarshal(buf)
+    }
+}
+
+impl<R, W, F: EraseDeserializer> EraseDeserializer for CustomCodec<R, W, F> {
+    fn from_bytes(buf: Vec<u8>) -> Box<dyn erased::Deserializer<'static> + Send> {
+        F::from_bytes(buf)
+    }
+}
+
+/// Erases a deserializer that owns its input, for `EraseDeserializer::from_bytes`
+///
+/// Like the deserializers of most formats, `&mut D` has to implement
+/// `serde::Deserializer`.
+pub fn erase_owned<D, E>(de: D) -> Box<dyn erased::Deserializer<'static> + Send>
+where
+    D: Send + 'static,
+    for<'a> &'a mut D: serde::Deserializer<'static, Error = E>,
+    E: serde::de::Error,
+{
+    let mut inner = de;
+    let human_readable = serde::Deserializer::is_human_readable(&&mut inner);
+    Box::new(<dyn erased::Deserializer>::erase(Owned {
+        inner,
+        human_readable,
+    }))
+}
+
+/// A deserializer that owns its input, so that it can be erased as `'static`
+struct Owned<D> {
+    inner: D,
+    human_readable: bool,
+}
+
+impl<'de, D, E> serde::Deserializer<'de> for Owned<D>
+where
+    for<'a> &'a mut D: serde::Deserializer<'de, Error = E>,
+    E: serde::de::Error,
+{
+    type Error = E;
+
+    fn deserialize_any<V: Visitor<'de>>(mut self, visitor: V) -> Result<V::Value, E> {
+        serde::Deserializer::deserialize_any(&mut self.inner, visitor)
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(mut self, visitor: V) -> Result<V::Value, E> {
+        serde::Deserializer::deserialize_bool(&mut self.inner, visitor)
+    }
+
+    fn deserialize_i8<V: Visitor<'de>>(mut self, visitor: V) -> Result<V::Value, E> {
+        serde::Deserializer::deserialize_i8(&mut self.inner, visitor)
+    }
+
+    fn deserialize_i16<V: Visitor<'de>>(mut self, visitor: V) -> Result<V::Value, E> {
+        serde::Deserializer::deserialize_i16(&mut self.inner, visitor)
+    }
+
+    fn deserialize_i32<V: Visitor<'de>>(mut self, visitor: V) -> Result<V::Value, E> {
+        serde::Deserializer::deserialize_i32(&mut self.inner, visitor)
+    }
+
+    fn deserialize_i64<V: Visitor<'de>>(mut self, visitor: V) -> Result<V::Value, E> {
+        serde::Deserializer::deserialize_i64(&mut self.inner, visitor)
+    }
+
+    fn deserialize_i128<V: Visitor<'de>>(mut self, visitor: V) -> Result<V::Value, E> {
+        serde::Deserializer::deserialize_i128(&mut self.inner, visitor)
+    }
+
+    fn deserialize_u8<V: Visitor<'de>>(mut self, visitor: V) -> Result<V::Value, E> {
+        serde::Deserializer::deserialize_u8(&mut self.inner, visitor)
+    }
+
+    fn deserialize_u16<V: Visitor<'de>>(mut self, visitor: V) -> Result<V::Value, E> {
+        serde::Deserializer::deserialize_u16(&mut self.inner, visitor)
+    }
+
+    fn deserialize_u32<V: Visitor<'de>>(mut self, visitor: V) -> Result<V::Value, E> {
+        serde::Deserializer::deserialize_u32(&mut self.inner, visitor)
+    }
+
+    fn deserialize_u64<V: Visitor<'de>>(mut self, visitor: V) -> Result<V::Value, E> {
+        serde::Deserializer::deserialize_u64(&mut self.inner, visitor)
+    }
+
+    fn deserialize_u128<V: Visitor<'de>>(mut self, visitor: V) -> Result<V::Value, E> {
+        serde::Deserializer::deserialize_u128(&mut self.inner, visitor)
+    }
+
+    fn deserialize_f32<V: Visitor<'de>>(mut self, visitor: V) -> Result<V::Value, E> {
+        serde::Deserializer::deserialize_f32(&mut self.inner, visitor)
+    }
+
+    fn deserialize_f64<V: Visitor<'de>>(mut self, visitor: V) -> Result<V::Value, E> {
+        serde::Deserializer::deserialize_f64(&mut self.inner, visitor)
+    }
+
+    fn deserialize_char<V: Visitor<'de>>(mut self, visitor: V) -> Result<V::Value, E> {
+        serde::Deserializer::deserialize_char(&mut self.inner, visitor)
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(mut self, visitor: V) -> Result<V::Value, E> {
+        serde::Deserializer::deserialize_str(&mut self.inner, visitor)
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(mut self, visitor: V) -> Result<V::Value, E> {
+        serde::Deserializer::deserialize_string(&mut self.inner, visitor)
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(mut self, visitor: V) -> Result<V::Value, E> {
+        serde::Deserializer::deserialize_bytes(&mut self.inner, visitor)
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(mut self, visitor: V) -> Result<V::Value, E> {
+        serde::Deserializer::deserialize_byte_buf(&mut self.inner, visitor)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(mut self, visitor: V) -> Result<V::Value, E> {
+        serde::Deserializer::deserialize_option(&mut self.inner, visitor)
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(mut self, visitor: V) -> Result<V::Value, E> {
+        serde::Deserializer::deserialize_unit(&mut self.inner, visitor)
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        mut self,
+        name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, E> {
+        serde::Deserializer::deserialize_unit_struct(&mut self.inner, name, visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        mut self,
+        name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, E> {
+        serde::Deserializer::deserialize_newtype_struct(&mut self.inner, name, visitor)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(mut self, visitor: V) -> Result<V::Value, E> {
+        serde::Deserializer::deserialize_seq(&mut self.inner, visitor)
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(mut self, len: usize, visitor: V) -> Result<V::Value, E> {
+        serde::Deserializer::deserialize_tuple(&mut self.inner, len, visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        mut self,
+        name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, E> {
+        serde::Deserializer::deserialize_tuple_struct(&mut self.inner, name, len, visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(mut self, visitor: V) -> Result<V::Value, E> {
+        serde::Deserializer::deserialize_map(&mut self.inner, visitor)
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        mut self,
+        name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, E> {
+        serde::Deserializer::deserialize_struct(&mut self.inner, name, fields, visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        mut self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, E> {
+        serde::Deserializer::deserialize_enum(&mut self.inner, name, variants, visitor)
+    }
+
+    fn deserialize_identifier<V: Visitor<'de>>(mut self, visitor: V) -> Result<V::Value, E> {
+        serde::Deserializer::deserialize_identifier(&mut self.inner, visitor)
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(mut self, visitor: V) -> Result<V::Value, E> {
+        serde::Deserializer::deserialize_ignored_any(&mut self.inner, visitor)
+    }
+
+    fn is_human_readable(&self) -> bool {
+        self.human_readable
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bincode::Options;
+    use std::io::Cursor;
+    use std::net::{IpAddr, Ipv6Addr};
+
+    #[test]
+    fn erased_owned_deserializer() {
+        // `IpAddr` is serialized as a string or as bytes depending on
+        // `is_human_readable`, which has to be forwarded
+        let addr = IpAddr::V6(Ipv6Addr::LOCALHOST);
+        let buf = bincode::serialize(&(addr, "owned".to_string())).unwrap();
+        let mut de = erase_owned(bincode::Deserializer::with_reader(
+            Cursor::new(buf),
+            bincode::options().with_fixint_encoding(),
+        ));
+        let value: (IpAddr, String) = erased::deserialize(&mut de).unwrap();
+        assert_eq!(value, (addr, "owned".to_string()));
+    }
+}
@@ -9,15 +9,19 @@ cfg_if! {
 
     } else if #[cfg(feature = "serde_rmp")] {
 
+    } else if #[cfg(feature = "serde_postcard")] {
+
     } else {
         use futures::io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt};
         use async_trait::async_trait;
         use erased_serde as erased;
         use std::marker::PhantomData;
+        use std::sync::Arc;
 
         use super::*;
         use crate::codec::split::{SplittableCodec};
         use crate::codec::split::{CodecReadHalf, CodecWriteHalf};
+        use crate::transport::bandwidth::TrafficMeter;
         use crate::util::GracefulShutdown;
 
         #[async_trait]
@@ -72,11 +76,33 @@ cfg_if! {
                             // EOF
                             return None
                         }
+                        if let Some(meter) = &self.meter {
+                            meter.read(n).await;
+                        }
                         Some(Ok(buf.into_bytes()))
                     },
                     Err(err) => Some(Err(err.into()))
                 }
             }
+
+            fn set_meter(&mut self, meter: Arc<TrafficMeter>) {
+                self.meter = Some(meter);
+            }
+        }
+
+        impl<W, C> CodecWriteHalf<W, C, ConnTypeReadWrite>
+        where
+            W: AsyncWrite + Send + Unpin,
+        {
+            /// Writes a line once the bandwidth limit of the meter allows it
+            async fn write_metered(&mut self, buf: &[u8]) -> Result<(), Error> {
+                if let Some(meter) = &self.meter {
+                    meter.write(buf.len()).await;
+                }
+                let _ = self.writer.write(buf).await?;
+                self.writer.flush().await?;
+                Ok(())
+            }
         }
 
         #[async_trait]
@@ -92,10 +118,7 @@ cfg_if! {
                 let _ = header.get_id();
                 let buf = Self::marshal(&header)?;
 
-                let _ = self.writer.write(&buf).await?;
-                self.writer.flush().await?;
-
-                Ok(())
+                self.write_metered(&buf).await
             }
 
             async fn write_body(
@@ -105,18 +128,16 @@ cfg_if! {
             ) -> Result<(), Error> {
                 let buf = Self::marshal(&body)?;
 
-                let _ = self.writer.write(&buf).await?;
-                self.writer.flush().await?;
-
-                Ok(())
+                self.write_metered(&buf).await
             }
 
             async fn write_body_bytes(&mut self, _: MessageId, bytes: &[u8]) -> Result<(), Error> {
-                let _ = self.writer.write(bytes).await?;
-                self.writer.flush().await?;
-                Ok(())
+                self.write_metered(bytes).await
             }
 
+            fn set_meter(&mut self, meter: Arc<TrafficMeter>) {
+                self.meter = Some(meter);
+            }
         }
 
         impl<R, W> SplittableCodec for Codec<R, W, ConnTypeReadWrite>
@@ -131,13 +152,16 @@ cfg_if! {
                 (
                     CodecWriteHalf::<W, Self, ConnTypeReadWrite> {
                         writer: self.writer,
+                        frame: crate::codec::FrameOptions::default(),
                         marker: PhantomData,
                         conn_type: PhantomData,
+                        meter: None,
                     },
                     CodecReadHalf::<R, Self, ConnTypeReadWrite> {
                         reader: self.reader,
                         marker: PhantomData,
-                        conn_type: PhantomData
+                        conn_type: PhantomData,
+                        meter: None,
                     }
                 )
             }
@@ -151,11 +175,11 @@ cfg_if! {
             async fn close(&mut self) {
                 match self.flush().await {
                     Ok(()) => (),
-                    Err(e) => log::error!("Error closing connection: {}", e),
+                    Err(e) => error!("Error closing connection: {}", e),
                 };
                 match AsyncWriteExt::close(self).await {
                     Ok(()) => (),
-                    Err(e) => log::error!("Error closing connection: {}", e),
+                    Err(e) => error!("Error closing connection: {}", e),
                 };
             }
         }
@@ -24,19 +24,22 @@ cfg_if! {
 
     } else if #[cfg(feature = "serde_rmp")] {
 
+    } else if #[cfg(feature = "serde_postcard")] {
+
     } else {
         use erased_serde as erased;
         use serde::de::Visitor;
         use std::io::Cursor; // serde doesn't support AsyncRead
 
-        use super::{
-            Codec, CodecRead, CodecWrite, DeserializerOwned, EraseDeserializer, Marshal, Unmarshal,
-        };
+        use super::{Codec, DeserializerOwned, EraseDeserializer, Marshal, Unmarshal};
         use crate::error::Error;
         use crate::macros::impl_inner_deserializer;
-        use crate::message::{MessageId, Metadata};
 
-        use super::ConnTypeReadWrite;
+        // used by the implementations of `CodecRead` and `CodecWrite`
+        #[cfg(any(feature = "async_std_runtime", feature = "tokio_runtime"))]
+        use super::{CodecRead, CodecWrite, ConnTypeReadWrite};
+        #[cfg(any(feature = "async_std_runtime", feature = "tokio_runtime"))]
+        use crate::message::{MessageId, Metadata};
 
         impl<'de, R> serde::Deserializer<'de> for DeserializerOwned<serde_json::Deserializer<R>>
         where
@@ -51,7 +54,13 @@ cfg_if! {
 
         impl<R, W, C> Marshal for Codec<R, W, C> {
             fn marshal<S: serde::Serialize>(val: &S) -> Result<Vec<u8>, Error> {
-                serde_json::to_vec(val)
+                #[cfg(feature = "canonical")]
+                let bytes = serde_json::to_value(val)
+                    .and_then(|value| serde_json::to_vec(&canonical(value)));
+                #[cfg(not(feature = "canonical"))]
+                let bytes = serde_json::to_vec(val);
+
+                bytes
                     .map(|mut v| {
                         v.push(b'\n');
                         v
@@ -60,6 +69,28 @@ cfg_if! {
             }
         }
 
+        /// Sorts the entries of every object by key. This does not rely on `serde_json::Map`
+        /// being sorted, which is not the case with the `preserve_order` feature of `serde_json`.
+        #[cfg(feature = "canonical")]
+        fn canonical(value: serde_json::Value) -> serde_json::Value {
+            use serde_json::Value;
+
+            match value {
+                Value::Array(items) => Value::Array(items.into_iter().map(canonical).collect()),
+                Value::Object(map) => {
+                    let mut entries: Vec<_> = map.into_iter().collect();
+                    entries.sort_by(|a, b| a.0.cmp(&b.0));
+                    Value::Object(
+                        entries
+                            .into_iter()
+                            .map(|(key, value)| (key, canonical(value)))
+                            .collect(),
+                    )
+                }
+                value => value,
+            }
+        }
+
         impl<R, W, C> Unmarshal for Codec<R, W, C> {
             fn unmarshal<'de, D: serde::Deserialize<'de>>(buf: &'de [u8]) -> Result<D, Error> {
                 serde_json::from_slice(buf).map_err(|e| e.into())
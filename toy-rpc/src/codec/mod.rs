@@ -1,7 +1,7 @@
 //! `SplittibleCodec` is defined in this module, and they are implemented
 //! for the `DefaultCodec`
 //! Default codec implementations are feature gated behind the following features
-//! `serde_bincode`, `serde_json`, `serde_cbor`, `serde_rmp`.
+//! `serde_bincode`, `serde_json`, `serde_cbor`, `serde_rmp`, `serde_postcard`.
 
 use async_trait::async_trait;
 use cfg_if::cfg_if;
@@ -9,12 +9,16 @@ use erased_serde as erased;
 use futures::stream::{SplitSink, SplitStream};
 use futures::{Sink, Stream};
 use std::marker::PhantomData;
+use std::sync::Arc;
 use tungstenite::Message as WsMessage;
 
+use crate::compression::Compression;
 use crate::error::Error;
 use crate::message::{MessageId, Metadata};
+use crate::protocol::Header;
 
 use crate::protocol::InboundBody;
+use crate::transport::bandwidth::TrafficMeter;
 use crate::transport::ws::{CanSink, SinkHalf, StreamHalf, WebSocketConn};
 
 pub mod split;
@@ -66,49 +70,64 @@ cfg_if! {
             not(feature = "serde_json"),
             not(feature = "serde_cbor"),
             not(feature = "serde_rmp"),
+            not(feature = "serde_postcard"),
         ),
         all(
             feature = "serde_cbor",
             not(feature = "serde_json"),
             not(feature = "serde_bincode"),
             not(feature = "serde_rmp"),
+            not(feature = "serde_postcard"),
         ),
         all(
             feature = "serde_json",
             not(feature = "serde_bincode"),
             not(feature = "serde_cbor"),
             not(feature = "serde_rmp"),
+            not(feature = "serde_postcard"),
         ),
         all(
             feature = "serde_rmp",
             not(feature = "serde_cbor"),
             not(feature = "serde_json"),
             not(feature = "serde_bincode"),
+            not(feature = "serde_postcard"),
+        ),
+        all(
+            feature = "serde_postcard",
+            not(feature = "serde_cbor"),
+            not(feature = "serde_json"),
+            not(feature = "serde_bincode"),
+            not(feature = "serde_rmp"),
         )
     ))] {
         pub use Codec as DefaultCodec;
     }
 }
 
+// the formats are also used by the client for browsers, which has no runtime
 cfg_if! {
     if #[cfg(any(
         feature = "async_std_runtime",
         feature = "tokio_runtime",
         feature = "docs",
+        feature = "wasm",
     ))] {
         #[cfg(all(
             feature = "serde_bincode",
             not(feature = "serde_json"),
             not(feature = "serde_cbor"),
             not(feature = "serde_rmp"),
+            not(feature = "serde_postcard"),
         ))]
         #[cfg_attr(
-            doc,
+            feature = "docs",
             doc(cfg(all(
                 feature = "serde_bincode",
                 not(feature = "serde_json"),
                 not(feature = "serde_cbor"),
                 not(feature = "serde_rmp"),
+                not(feature = "serde_postcard"),
             )))
         )]
         pub mod bincode;
@@ -118,14 +137,16 @@ cfg_if! {
             not(feature = "serde_bincode"),
             not(feature = "serde_cbor"),
             not(feature = "serde_rmp"),
+            not(feature = "serde_postcard"),
         ))]
         #[cfg_attr(
-            doc,
+            feature = "docs",
             doc(cfg(all(
                 feature = "serde_json",
                 not(feature = "serde_bincode"),
                 not(feature = "serde_cbor"),
                 not(feature = "serde_rmp"),
+                not(feature = "serde_postcard"),
             )))
         )]
         pub mod json;
@@ -135,14 +156,16 @@ cfg_if! {
             not(feature = "serde_json"),
             not(feature = "serde_bincode"),
             not(feature = "serde_rmp"),
+            not(feature = "serde_postcard"),
         ))]
         #[cfg_attr(
-            doc,
+            feature = "docs",
             doc(cfg(all(
                 feature = "serde_cbor",
                 not(feature = "serde_json"),
                 not(feature = "serde_bincode"),
                 not(feature = "serde_rmp"),
+                not(feature = "serde_postcard"),
             )))
         )]
         pub mod cbor;
@@ -152,32 +175,68 @@ cfg_if! {
             not(feature = "serde_cbor"),
             not(feature = "serde_json"),
             not(feature = "serde_bincode"),
+            not(feature = "serde_postcard"),
         ))]
         #[cfg_attr(
-            doc,
+            feature = "docs",
             doc(cfg(all(
                 feature = "serde_rmp",
                 not(feature = "serde_cbor"),
                 not(feature = "serde_json"),
                 not(feature = "serde_bincode"),
+                not(feature = "serde_postcard"),
             )))
         )]
         pub mod rmp;
+
+        #[cfg(all(feature = "postcard_codec", not(feature = "serde_json")))]
+        #[cfg_attr(
+            feature = "docs",
+            doc(cfg(all(feature = "postcard_codec", not(feature = "serde_json"))))
+        )]
+        pub mod postcard;
+    }
+}
+
+cfg_if! {
+    if #[cfg(any(
+        feature = "async_std_runtime",
+        feature = "tokio_runtime",
+        feature = "docs",
+    ))] {
+        #[cfg(all(feature = "prost_codec", not(feature = "serde_json")))]
+        #[cfg_attr(
+            feature = "docs",
+            doc(cfg(all(feature = "prost_codec", not(feature = "serde_json"))))
+        )]
+        pub mod prost;
+
+        #[cfg(not(feature = "serde_json"))]
+        #[cfg_attr(feature = "docs", doc(cfg(not(feature = "serde_json"))))]
+        pub mod custom;
+
+        #[cfg(all(feature = "codec_negotiation", not(feature = "serde_json")))]
+        #[cfg_attr(
+            feature = "docs",
+            doc(cfg(all(feature = "codec_negotiation", not(feature = "serde_json"))))
+        )]
+        pub mod negotiate;
     }
 }
 
 /// Type state for AsyncRead and AsyncWrite connections (ie. raw TCP)
 #[cfg(any(feature = "async_std_runtime", feature = "tokio_runtime"))]
-pub(crate) struct ConnTypeReadWrite {}
+pub struct ConnTypeReadWrite {}
 
 /// Type state for PayloadRead and PayloadWrite connections (ie. WebSocket)
-pub(crate) struct ConnTypePayload {}
+pub struct ConnTypePayload {}
 
 /// Reserved type state for Reader/Writer for Codec
 pub struct Reserved {}
 
 /// Default codec. `Codec` is re-exported as `DefaultCodec` when one of these feature
-/// flags is toggled (`serde_bincode`, `serde_json`, `serde_cbor`, `serde_rmp`")
+/// flags is toggled (`serde_bincode`, `serde_json`, `serde_cbor`, `serde_rmp`,
+/// `serde_postcard`)
 #[cfg_attr(
     not(all(
         any( // there has to be a runtime
@@ -189,6 +248,7 @@ pub struct Reserved {}
             feature = "serde_json",
             feature = "serde_cbor",
             feature = "serde_rmp",
+            feature = "serde_postcard",
         )
     )),
     allow(dead_code)
@@ -196,9 +256,80 @@ pub struct Reserved {}
 pub struct Codec<R, W, C> {
     reader: R,
     writer: W,
+    pub(crate) frame: FrameOptions,
     conn_type: PhantomData<C>,
 }
 
+#[cfg(any(feature = "async_std_runtime", feature = "tokio_runtime"))]
+impl<R, W> Codec<R, W, ConnTypeReadWrite> {
+    /// Compresses the outbound payloads of the codec with `compression`. The inbound
+    /// payloads are decompressed whether or not this is set.
+    ///
+    /// This has no effect with the `serde_json` codec. See the `compression` module
+    /// for details.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # #[cfg(all(feature = "client", feature = "compression_lz4"))]
+    /// # {
+    /// # use tokio::net::TcpStream;
+    /// # use toy_rpc::Client;
+    /// # use toy_rpc::codec::Codec;
+    /// # use toy_rpc::compression::{Algorithm, Compression};
+    /// # async fn run(addr: &str) -> Result<(), Box<dyn std::error::Error>> {
+    /// # let stream = TcpStream::connect(addr).await?;
+    /// let codec = Codec::new(stream).with_compression(Compression::new(Algorithm::Lz4));
+    /// let client = Client::with_codec(codec);
+    /// # Ok(())
+    /// # }
+    /// # }
+    /// ```
+    pub fn with_compression(self, compression: Compression) -> Self {
+        let mut codec = self;
+        codec.frame.compression = Some(compression);
+        codec
+    }
+
+    /// Writes the `Request` and `Response` headers with the fixed encoding instead of
+    /// serde, which saves the cost of the serializer for every call. The inbound
+    /// headers are read with either encoding whether or not this is set, but a peer of
+    /// an older version can only read the serde encoding.
+    ///
+    /// This has no effect with the `serde_json` codec. See `Header::encode_fixed` for
+    /// the encoding.
+    pub fn with_fixed_headers(self) -> Self {
+        let mut codec = self;
+        codec.frame.fixed_headers = true;
+        codec
+    }
+}
+
+/// Options of the frame transport of a connection, which are set on the `Codec`
+/// and passed on to its writing half
+#[derive(Debug, Clone, Copy, Default)]
+#[cfg_attr(
+    all(not(feature = "tokio_runtime"), not(feature = "async_std_runtime")),
+    allow(dead_code)
+)]
+pub(crate) struct FrameOptions {
+    /// Compression of the outbound payloads
+    pub compression: Option<Compression>,
+    /// Whether the `Request` and `Response` headers are written with the fixed encoding
+    pub fixed_headers: bool,
+    /// Content types that a server negotiates with the clients, if any
+    #[cfg(all(
+        feature = "codec_negotiation",
+        not(feature = "serde_json"),
+        any(
+            feature = "async_std_runtime",
+            feature = "tokio_runtime",
+            feature = "docs"
+        ),
+    ))]
+    pub content_types: Option<negotiate::Supported>,
+}
+
 /// WebSocket integration for async_tungstenite, tokio_tungstenite
 impl<S, E>
     Codec<
@@ -219,12 +350,41 @@ where
         Self {
             reader,
             writer,
+            frame: FrameOptions::default(),
+            conn_type: PhantomData,
+        }
+    }
+}
+
+#[cfg(any(feature = "async_std_runtime", feature = "tokio_runtime"))]
+/// HTTP POST transport
+impl
+    Codec<
+        crate::transport::post::ChannelReader,
+        crate::transport::post::ChannelWriter,
+        ConnTypePayload,
+    >
+{
+    /// Creates a `Codec` with a connection whose payloads are carried by the requests
+    /// of an HTTP POST session. See the `transport::post` module for details.
+    #[cfg_attr(
+        not(any(feature = "client", feature = "http_hyper", feature = "http_warp")),
+        allow(dead_code)
+    )]
+    pub(crate) fn with_post_session(
+        reader: crate::transport::post::ChannelReader,
+        writer: crate::transport::post::ChannelWriter,
+    ) -> Self {
+        Self {
+            reader,
+            writer,
+            frame: FrameOptions::default(),
             conn_type: PhantomData,
         }
     }
 }
 
-#[cfg(all(feature = "http_tide"))]
+#[cfg(feature = "http_tide")]
 /// WebSocket integration with `tide`
 impl
     Codec<
@@ -243,12 +403,13 @@ impl
         Self {
             reader,
             writer,
+            frame: FrameOptions::default(),
             conn_type: PhantomData,
         }
     }
 }
 
-#[cfg(all(feature = "http_warp"))]
+#[cfg(feature = "http_warp")]
 // warp websocket
 impl<S, E>
     Codec<
@@ -268,15 +429,54 @@ where
         let writer = SinkHalf::<_, CanSink> {
             inner: writer,
             can_sink: PhantomData,
+            chunked: false,
         };
         let reader = StreamHalf::<_, CanSink> {
             inner: reader,
             can_sink: PhantomData,
+            chunked: false,
         };
 
         Self {
             reader,
             writer,
+            frame: FrameOptions::default(),
+            conn_type: PhantomData,
+        }
+    }
+
+    /// Splits the payloads into messages, once the handshake has agreed on
+    /// `chunk::PROTOCOL`
+    pub(crate) fn chunked(mut self, chunked: bool) -> Self {
+        self.reader.chunked = chunked;
+        self.writer.chunked = chunked;
+        self
+    }
+}
+
+#[cfg(all(
+    feature = "transport_quic",
+    any(
+        feature = "serde_bincode",
+        feature = "serde_cbor",
+        feature = "serde_rmp",
+        feature = "serde_postcard"
+    ),
+))]
+/// QUIC integration with `quinn`
+impl
+    Codec<crate::transport::quic::QuicReader, crate::transport::quic::QuicWriter, ConnTypeReadWrite>
+{
+    /// Creates a `Codec` with a QUIC connection. Each message id is sent on its own
+    /// bidirectional stream of the connection.
+    #[cfg_attr(feature = "docs", doc(cfg(feature = "transport_quic")))]
+    pub fn with_quic_connection(conn: quinn::Connection) -> Self {
+        let (reader, writer) = crate::transport::quic::split(conn);
+
+        Self {
+            reader,
+            writer,
+            frame: FrameOptions::default(),
             conn_type: PhantomData,
         }
     }
@@ -297,6 +497,13 @@ pub trait CodecRead: Send + Unmarshal + EraseDeserializer {
         )
     }
 
+    /// Reads the header of a message of the RPC protocol. A codec that receives
+    /// headers with the fixed encoding (see `Header::encode_fixed`) decodes them
+    /// here, since `read_header` only reads the headers serialized with serde.
+    async fn read_protocol_header(&mut self) -> Option<Result<Header, Error>> {
+        self.read_header().await
+    }
+
     /// Reads the body of the message
     async fn read_body(&mut self) -> Option<Result<Box<InboundBody>, Error>> {
         match self.read_bytes().await? {
@@ -310,6 +517,11 @@ pub trait CodecRead: Send + Unmarshal + EraseDeserializer {
 
     /// Reads the body as raw bytes
     async fn read_bytes(&mut self) -> Option<Result<Vec<u8>, Error>>;
+
+    /// Counts the frames read with `meter`, which also enforces its inbound
+    /// bandwidth limit. A codec whose transport is not metered ignores the meter.
+    /// See the `transport::bandwidth` module.
+    fn set_meter(&mut self, _meter: Arc<TrafficMeter>) {}
 }
 
 /// A codec that can write the header and body of a message
@@ -329,6 +541,11 @@ pub trait CodecWrite: Send + Marshal {
 
     /// Writes body as raw bytes
     async fn write_body_bytes(&mut self, id: MessageId, bytes: &[u8]) -> Result<(), Error>;
+
+    /// Counts the frames written with `meter`, which also enforces its outbound
+    /// bandwidth limit. A codec whose transport is not metered ignores the meter.
+    /// See the `transport::bandwidth` module.
+    fn set_meter(&mut self, _meter: Arc<TrafficMeter>) {}
 }
 
 cfg_if! {
@@ -338,7 +555,8 @@ cfg_if! {
             feature = "tokio_runtime",
             feature = "http_tide",
             feature = "http_warp",
-            feature = "http_actix_web"
+            feature = "http_actix_web",
+            feature = "wasm"
         ),
         any(
             all(
@@ -346,24 +564,35 @@ cfg_if! {
                 not(feature = "serde_json"),
                 not(feature = "serde_cbor"),
                 not(feature = "serde_rmp"),
+                not(feature = "serde_postcard"),
             ),
             all(
                 feature = "serde_cbor",
                 not(feature = "serde_json"),
                 not(feature = "serde_bincode"),
                 not(feature = "serde_rmp"),
+                not(feature = "serde_postcard"),
             ),
             all(
                 feature = "serde_json",
                 not(feature = "serde_bincode"),
                 not(feature = "serde_cbor"),
                 not(feature = "serde_rmp"),
+                not(feature = "serde_postcard"),
             ),
             all(
                 feature = "serde_rmp",
                 not(feature = "serde_cbor"),
                 not(feature = "serde_json"),
                 not(feature = "serde_bincode"),
+                not(feature = "serde_postcard"),
+            ),
+            all(
+                feature = "serde_postcard",
+                not(feature = "serde_cbor"),
+                not(feature = "serde_json"),
+                not(feature = "serde_bincode"),
+                not(feature = "serde_rmp"),
             )
         )
     ))] {
@@ -0,0 +1,477 @@
+//! Negotiation of the content type of a connection
+//!
+//! The `serde_*` feature flags choose the one `DefaultCodec` of a binary. With the
+//! `codec_negotiation` feature, a server can also serve the clients that serialize
+//! with bincode, JSON, CBOR or MessagePack over the same listener, and pick the
+//! codec of each connection at runtime.
+//!
+//! Negotiation is enabled with `ServerBuilder::content_types` on the server and
+//! `ClientBuilder::content_types` on the client. Right after the TCP connection is
+//! established, the client sends the content types it accepts, the most preferred
+//! first, and the server answers with the first of them that it supports. Both
+//! ends then use a `CustomCodec` of the chosen format, see the `codec::custom`
+//! module. A client and a server that have no content type in common fail with
+//! `Error::NoCommonContentType`.
+//!
+//! The JSON content type uses the same binary transport as the other formats, so
+//! it cannot talk to the `DefaultCodec` of a server built with `serde_json`. Only
+//! the TCP connections accepted with `Server::accept` and dialed with
+//! `ClientBuilder::dial` are negotiated.
+//!
+//! # Per-call content type
+//!
+//! A single call can also ask for its response in another content type than the one
+//! of its connection, eg. JSON for a debugging tool or a gateway on an otherwise
+//! bincode connection, with the `ACCEPT` key of the metadata of the request, whose
+//! value is the MIME type returned by `ContentType::as_str`. The server then
+//! encodes the body of the response with that content type, and marks the response
+//! with the `CONTENT_TYPE` key of its metadata so that the client decodes it
+//! accordingly. Since JSON, CBOR and MessagePack are self-describing, the response
+//! can then be read as a generic value, eg. a `serde_json::Value`, without knowing
+//! its type.
+//!
+//! Only the single responses are encoded with the requested content type, the items
+//! of the streaming responses keep the content type of the connection. A request
+//! with an unknown content type, or sent to a server without the
+//! `codec_negotiation` feature, gets a response with the content type of its
+//! connection.
+//!
+//! ```no_run
+//! # use toy_rpc::codec::negotiate::ContentType;
+//! # async fn run(client: toy_rpc::Client) -> Result<(), toy_rpc::Error> {
+//! let value: serde_json::Value = client
+//!     .prepare("Example.describe")
+//!     .accept(ContentType::Json)
+//!     .call(())
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! # Example
+//!
+//! ```no_run
+//! # use std::sync::Arc;
+//! # use tokio::net::TcpListener;
+//! # use toy_rpc::{Client, Server};
+//! # use toy_rpc::codec::negotiate::ContentType;
+//! # use toy_rpc::macros::export_impl;
+//! # struct Example;
+//! # #[export_impl]
+//! # impl Example {
+//! #     #[export_method]
+//! #     async fn echo(&self, args: String) -> Result<String, String> {
+//! #         Ok(args)
+//! #     }
+//! # }
+//! # async fn run(addr: &str) -> Result<(), Box<dyn std::error::Error>> {
+//! # let example_service = Arc::new(Example);
+//! # let listener = TcpListener::bind(addr).await?;
+//! // server
+//! let server = Server::builder()
+//!     .register(example_service)
+//!     .content_types(vec![ContentType::Bincode, ContentType::Json])
+//!     .build();
+//! server.accept(listener).await?;
+//!
+//! // client
+//! let client = Client::builder()
+//!     .content_types(vec![ContentType::Json])
+//!     .dial(addr)
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use bincode::Options;
+use cfg_if::cfg_if;
+use erased_serde as erased;
+use std::io::Cursor;
+
+use super::custom::erase_owned;
+use super::{EraseDeserializer, Marshal, Unmarshal};
+use crate::error::Error;
+use crate::metadata::Metadata;
+use crate::protocol::InboundBody;
+
+cfg_if! {
+    if #[cfg(any(
+        feature = "async_std_runtime",
+        feature = "http_tide"
+    ))] {
+        use futures::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+    } else {
+        use ::tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+    }
+}
+
+/// Magic bytes that start the negotiation
+const MAGIC: [u8; 4] = *b"TRPC";
+
+/// Key of the metadata of a request that asks for its response in a content type
+pub const ACCEPT: &str = "accept";
+
+/// Key of the metadata of a response whose body is encoded in the content type that
+/// its request asked for
+pub const CONTENT_TYPE: &str = "content-type";
+
+/// Content type of a connection, ie. the format its messages are serialized with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ContentType {
+    /// `bincode` with the varint encoding, like the `serde_bincode` codec
+    Bincode,
+    /// `serde_json`
+    Json,
+    /// `serde_cbor`
+    Cbor,
+    /// `rmp-serde`
+    MessagePack,
+}
+
+impl ContentType {
+    /// All the content types, in the default order of preference
+    pub const ALL: [ContentType; 4] = [
+        ContentType::Bincode,
+        ContentType::Cbor,
+        ContentType::MessagePack,
+        ContentType::Json,
+    ];
+
+    /// MIME type of the format
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ContentType::Bincode => "application/x-bincode",
+            ContentType::Json => "application/json",
+            ContentType::Cbor => "application/cbor",
+            ContentType::MessagePack => "application/msgpack",
+        }
+    }
+
+    /// Parses a MIME type returned by `as_str`
+    pub fn from_mime(mime: &str) -> Option<Self> {
+        let mime = mime.trim();
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|content_type| content_type.as_str().eq_ignore_ascii_case(mime))
+    }
+
+    /// Serializes a body in the content type
+    #[cfg_attr(not(feature = "server"), allow(dead_code))]
+    pub(crate) fn marshal<S: serde::Serialize>(self, val: &S) -> Result<Vec<u8>, Error> {
+        match self {
+            ContentType::Bincode => Bincode::marshal(val),
+            ContentType::Json => Json::marshal(val),
+            ContentType::Cbor => Cbor::marshal(val),
+            ContentType::MessagePack => MessagePack::marshal(val),
+        }
+    }
+
+    /// Creates the deserializer of a body in the content type
+    #[cfg_attr(not(feature = "client"), allow(dead_code))]
+    pub(crate) fn deserializer(self, buf: Vec<u8>) -> Box<InboundBody> {
+        match self {
+            ContentType::Bincode => Bincode::from_bytes(buf),
+            ContentType::Json => Json::from_bytes(buf),
+            ContentType::Cbor => Cbor::from_bytes(buf),
+            ContentType::MessagePack => MessagePack::from_bytes(buf),
+        }
+    }
+
+    fn to_byte(self) -> u8 {
+        match self {
+            ContentType::Bincode => 1,
+            ContentType::Json => 2,
+            ContentType::Cbor => 3,
+            ContentType::MessagePack => 4,
+        }
+    }
+
+    fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            1 => Some(ContentType::Bincode),
+            2 => Some(ContentType::Json),
+            3 => Some(ContentType::Cbor),
+            4 => Some(ContentType::MessagePack),
+            _ => None,
+        }
+    }
+}
+
+/// Set of the content types supported by a server
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Supported(u8);
+
+impl Supported {
+    pub fn new(content_types: impl IntoIterator<Item = ContentType>) -> Self {
+        let mask = content_types
+            .into_iter()
+            .fold(0, |mask, content_type| mask | 1 << content_type.to_byte());
+        Self(mask)
+    }
+
+    pub fn contains(&self, content_type: ContentType) -> bool {
+        self.0 & 1 << content_type.to_byte() != 0
+    }
+}
+
+/// Picks the first of the content types accepted by the client that the server
+/// supports
+fn choose(accepted: &[u8], supported: Supported) -> Option<ContentType> {
+    accepted
+        .iter()
+        .filter_map(|byte| ContentType::from_byte(*byte))
+        .find(|content_type| supported.contains(*content_type))
+}
+
+/// The content type that the body of a response is encoded with, according to the
+/// metadata of the response
+pub(crate) fn response_content_type(metadata: &Metadata) -> Option<ContentType> {
+    metadata
+        .get(CONTENT_TYPE)
+        .and_then(|mime| ContentType::from_mime(mime))
+}
+
+/// Marks the response to a request that asks for a supported content type with
+/// that content type
+#[cfg(feature = "server")]
+pub(crate) fn accept(context: &crate::metadata::Context) {
+    if let Some(content_type) = context.get(ACCEPT).and_then(ContentType::from_mime) {
+        context.set_response_metadata(CONTENT_TYPE, content_type.as_str());
+    }
+}
+
+fn invalid_data(msg: &'static str) -> Error {
+    Error::IoError(std::io::Error::new(std::io::ErrorKind::InvalidData, msg))
+}
+
+/// Sends the content types accepted by the client, the most preferred first, and
+/// returns the one chosen by the server
+pub(crate) async fn request<T>(
+    stream: &mut T,
+    accepted: &[ContentType],
+) -> Result<ContentType, Error>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut buf = MAGIC.to_vec();
+    buf.push(accepted.len() as u8);
+    buf.extend(accepted.iter().map(|content_type| content_type.to_byte()));
+    stream.write_all(&buf).await?;
+    stream.flush().await?;
+
+    let mut chosen = [0u8; 1];
+    stream.read_exact(&mut chosen).await?;
+    match chosen[0] {
+        0 => Err(Error::NoCommonContentType),
+        byte => ContentType::from_byte(byte).ok_or_else(|| invalid_data("Unknown content type")),
+    }
+}
+
+/// Reads the content types accepted by the client and answers with the first one
+/// that the server supports
+pub(crate) async fn respond<T>(stream: &mut T, supported: Supported) -> Result<ContentType, Error>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut preamble = [0u8; 5];
+    stream.read_exact(&mut preamble).await?;
+    if preamble[..4] != MAGIC {
+        return Err(invalid_data("Content type negotiation is expected"));
+    }
+    let mut accepted = vec![0u8; preamble[4] as usize];
+    stream.read_exact(&mut accepted).await?;
+
+    let chosen = choose(&accepted, supported);
+    stream
+        .write_all(&[chosen.map_or(0, ContentType::to_byte)])
+        .await?;
+    stream.flush().await?;
+    chosen.ok_or(Error::NoCommonContentType)
+}
+
+/// `bincode` with the varint encoding
+pub struct Bincode;
+/// `serde_json`
+pub struct Json;
+/// `serde_cbor`
+pub struct Cbor;
+/// `rmp-serde`
+pub struct MessagePack;
+
+fn parse_error(err: impl std::error::Error + Send + Sync + 'static) -> Error {
+    Error::ParseError(Box::new(err))
+}
+
+fn bincode_options() -> impl bincode::Options {
+    bincode::DefaultOptions::new().with_varint_encoding()
+}
+
+impl Marshal for Bincode {
+    fn marshal<S: serde::Serialize>(val: &S) -> Result<Vec<u8>, Error> {
+        bincode_options().serialize(val).map_err(parse_error)
+    }
+}
+
+impl Unmarshal for Bincode {
+    fn unmarshal<'de, D: serde::Deserialize<'de>>(buf: &'de [u8]) -> Result<D, Error> {
+        bincode_options().deserialize(buf).map_err(parse_error)
+    }
+}
+
+impl EraseDeserializer for Bincode {
+    fn from_bytes(buf: Vec<u8>) -> Box<dyn erased::Deserializer<'static> + Send> {
+        let options = bincode::DefaultOptions::new().with_varint_encoding();
+        erase_owned(bincode::Deserializer::with_reader(
+            Cursor::new(buf),
+            options,
+        ))
+    }
+}
+
+impl Marshal for Json {
+    fn marshal<S: serde::Serialize>(val: &S) -> Result<Vec<u8>, Error> {
+        serde_json::to_vec(val).map_err(parse_error)
+    }
+}
+
+impl Unmarshal for Json {
+    fn unmarshal<'de, D: serde::Deserialize<'de>>(buf: &'de [u8]) -> Result<D, Error> {
+        serde_json::from_slice(buf).map_err(parse_error)
+    }
+}
+
+impl EraseDeserializer for Json {
+    fn from_bytes(buf: Vec<u8>) -> Box<dyn erased::Deserializer<'static> + Send> {
+        erase_owned(serde_json::Deserializer::from_reader(Cursor::new(buf)))
+    }
+}
+
+impl Marshal for Cbor {
+    fn marshal<S: serde::Serialize>(val: &S) -> Result<Vec<u8>, Error> {
+        serde_cbor::to_vec(val).map_err(parse_error)
+    }
+}
+
+impl Unmarshal for Cbor {
+    fn unmarshal<'de, D: serde::Deserialize<'de>>(buf: &'de [u8]) -> Result<D, Error> {
+        serde_cbor::from_slice(buf).map_err(parse_error)
+    }
+}
+
+impl EraseDeserializer for Cbor {
+    fn from_bytes(buf: Vec<u8>) -> Box<dyn erased::Deserializer<'static> + Send> {
+        erase_owned(serde_cbor::Deserializer::from_reader(Cursor::new(buf)))
+    }
+}
+
+impl Marshal for MessagePack {
+    fn marshal<S: serde::Serialize>(val: &S) -> Result<Vec<u8>, Error> {
+        rmp_serde::to_vec(val).map_err(parse_error)
+    }
+}
+
+impl Unmarshal for MessagePack {
+    fn unmarshal<'de, D: serde::Deserialize<'de>>(buf: &'de [u8]) -> Result<D, Error> {
+        rmp_serde::from_read_ref(buf).map_err(parse_error)
+    }
+}
+
+impl EraseDeserializer for MessagePack {
+    fn from_bytes(buf: Vec<u8>) -> Box<dyn erased::Deserializer<'static> + Send> {
+        erase_owned(rmp_serde::Deserializer::new(Cursor::new(buf)))
+    }
+}
+
+/// Evaluates `$body` with `$codec` bound to a `CustomCodec` of the content type over
+/// the stream
+#[cfg_attr(not(any(feature = "client", feature = "server")), allow(unused_macros))]
+macro_rules! with_format_codec {
+    ($content_type:expr, $stream:expr, $frame:expr, |$codec:ident| $body:expr) => {{
+        use $crate::codec::custom::CustomCodec;
+        use $crate::codec::negotiate::{Bincode, Cbor, ContentType, Json, MessagePack};
+        match $content_type {
+            ContentType::Bincode => {
+                let mut $codec = CustomCodec::<_, _, Bincode>::new($stream);
+                $codec.frame = $frame;
+                $body
+            }
+            ContentType::Json => {
+                let mut $codec = CustomCodec::<_, _, Json>::new($stream);
+                $codec.frame = $frame;
+                $body
+            }
+            ContentType::Cbor => {
+                let mut $codec = CustomCodec::<_, _, Cbor>::new($stream);
+                $codec.frame = $frame;
+                $body
+            }
+            ContentType::MessagePack => {
+                let mut $codec = CustomCodec::<_, _, MessagePack>::new($stream);
+                $codec.frame = $frame;
+                $body
+            }
+        }
+    }};
+}
+#[cfg_attr(
+    not(any(feature = "client", feature = "server")),
+    allow(unused_imports)
+)]
+pub(crate) use with_format_codec;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Sample {
+        id: u32,
+        name: String,
+        values: Vec<i16>,
+    }
+
+    fn round_trip<F: Marshal + Unmarshal + EraseDeserializer>() {
+        let sample = Sample {
+            id: 7,
+            name: "sample".into(),
+            values: vec![-1, 300, i16::MIN],
+        };
+        let bytes = F::marshal(&sample).unwrap();
+        assert_eq!(F::unmarshal::<Sample>(&bytes).unwrap(), sample);
+        let mut de = F::from_bytes(bytes);
+        assert_eq!(erased::deserialize::<Sample>(&mut de).unwrap(), sample);
+    }
+
+    #[test]
+    fn formats() {
+        round_trip::<Bincode>();
+        round_trip::<Json>();
+        round_trip::<Cbor>();
+        round_trip::<MessagePack>();
+    }
+
+    #[test]
+    fn choose_content_type() {
+        let accepted: Vec<u8> = [ContentType::Json, ContentType::Cbor]
+            .iter()
+            .map(|content_type| content_type.to_byte())
+            .collect();
+        let all = Supported::new(ContentType::ALL);
+        assert_eq!(choose(&accepted, all), Some(ContentType::Json));
+        assert_eq!(
+            choose(
+                &accepted,
+                Supported::new([ContentType::Bincode, ContentType::Cbor])
+            ),
+            Some(ContentType::Cbor)
+        );
+        assert_eq!(
+            choose(&accepted, Supported::new([ContentType::Bincode])),
+            None
+        );
+        // unknown content types of a newer client are skipped
+        assert_eq!(choose(&[9, 1], all), Some(ContentType::Bincode));
+    }
+}
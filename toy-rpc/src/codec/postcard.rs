@@ -0,0 +1,242 @@
+//! Codec with `postcard` for the minimal client of `toy-rpc-core`
+//!
+//! The minimal `toy_rpc_core::client::Client` of embedded devices serializes the
+//! messages with `postcard`, which is not one of the codecs that can be chosen with
+//! the `serde_*` feature flags. A server built with any of the binary codecs can
+//! still serve these devices by serving their connections with a `PostcardCodec`,
+//! which uses the custom binary transport with `postcard` instead of the default
+//! codec.
+//!
+//! With the `serde_postcard` feature flag, the `DefaultCodec` itself serializes the
+//! messages with `postcard`, which produces smaller payloads than `bincode` as the
+//! integers are encoded with a variable length. A `PostcardCodec` is then the same
+//! as the `DefaultCodec` on the wire.
+//!
+//! The arguments are deserialized from owned buffers, so an argument cannot borrow
+//! a `&str` or a `&[u8]` from the request. `postcard` is not a self-describing
+//! format, so the types that are deserialized with `deserialize_any`, eg. untagged
+//! enums, are not supported.
+//!
+//! # Example
+//!
+//! ```no_run
+//! # use tokio::net::TcpListener;
+//! # use toy_rpc::Server;
+//! # async fn run(server: Server, listener: TcpListener) -> Result<(), Box<dyn std::error::Error>> {
+//! let (stream, _) = listener.accept().await?;
+//! let codec = toy_rpc::codec::postcard::PostcardCodec::new(stream);
+//! server.serve_codec(codec).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use ::postcard::de_flavors::Flavor;
+use cfg_if::cfg_if;
+use erased_serde as erased;
+use serde::de::Visitor;
+
+use super::{DeserializerOwned, EraseDeserializer, Marshal, Unmarshal};
+use crate::error::Error;
+use crate::macros::impl_inner_deserializer;
+
+// only the serialization is available without a runtime, eg. to the client for browsers
+cfg_if! {
+    if #[cfg(any(feature = "async_std_runtime", feature = "tokio_runtime"))] {
+        use std::marker::PhantomData;
+
+        use super::split::{CodecReadHalf, CodecWriteHalf, SplittableCodec};
+        use super::{ConnTypeReadWrite, FrameOptions};
+        use crate::transport::frame::{FrameRead, FrameWrite};
+        use crate::util::GracefulShutdown;
+    }
+}
+
+cfg_if! {
+    if #[cfg(any(
+        feature = "async_std_runtime",
+        feature = "http_tide"
+    ))] {
+        use futures::io::{AsyncRead, AsyncReadExt, AsyncWrite, BufReader, BufWriter, ReadHalf, WriteHalf};
+    } else if #[cfg(feature = "tokio_runtime")] {
+        use ::tokio::io::{split, AsyncRead, AsyncWrite, BufReader, BufWriter, ReadHalf, WriteHalf};
+    }
+}
+
+/// Codec that serializes the messages with `postcard`
+#[cfg_attr(
+    not(any(feature = "async_std_runtime", feature = "tokio_runtime")),
+    allow(dead_code)
+)]
+pub struct PostcardCodec<R, W> {
+    reader: R,
+    writer: W,
+}
+
+#[cfg(any(feature = "async_std_runtime", feature = "tokio_runtime"))]
+impl<R, W> PostcardCodec<R, W>
+where
+    R: FrameRead + Send + Unpin,
+    W: FrameWrite + Send + Unpin,
+{
+    /// Creates a `PostcardCodec` with a reader and a writer
+    pub fn with_reader_writer(reader: R, writer: W) -> Self {
+        Self { reader, writer }
+    }
+}
+
+#[cfg(any(feature = "async_std_runtime", feature = "tokio_runtime"))]
+impl<T> PostcardCodec<BufReader<ReadHalf<T>>, BufWriter<WriteHalf<T>>>
+where
+    T: AsyncRead + AsyncWrite + Send + Unpin,
+{
+    /// Creates a `PostcardCodec` with a stream that implements both `AsyncRead` and
+    /// `AsyncWrite`
+    pub fn new(stream: T) -> Self {
+        cfg_if! {
+            if #[cfg(any(
+                feature = "async_std_runtime",
+                feature = "http_tide"
+            ))] {
+                let (reader, writer) = stream.split();
+            } else {
+                let (reader, writer) = split(stream);
+            }
+        }
+        Self::with_reader_writer(BufReader::new(reader), BufWriter::new(writer))
+    }
+}
+
+#[cfg(any(feature = "async_std_runtime", feature = "tokio_runtime"))]
+impl<R, W> SplittableCodec for PostcardCodec<R, W>
+where
+    R: FrameRead + Send + Unpin,
+    W: FrameWrite + GracefulShutdown + Send + Unpin,
+{
+    type Writer = CodecWriteHalf<W, Self, ConnTypeReadWrite>;
+    type Reader = CodecReadHalf<R, Self, ConnTypeReadWrite>;
+
+    fn split(self) -> (Self::Writer, Self::Reader) {
+        (
+            CodecWriteHalf {
+                writer: self.writer,
+                frame: FrameOptions::default(),
+                marker: PhantomData,
+                conn_type: PhantomData,
+                meter: None,
+            },
+            CodecReadHalf {
+                reader: self.reader,
+                marker: PhantomData,
+                conn_type: PhantomData,
+                meter: None,
+            },
+        )
+    }
+}
+
+impl<R, W> Marshal for PostcardCodec<R, W> {
+    fn marshal<S: serde::Serialize>(val: &S) -> Result<Vec<u8>, Error> {
+        ::postcard::to_allocvec(val).map_err(|err| Error::ParseError(Box::new(err)))
+    }
+}
+
+impl<R, W> Unmarshal for PostcardCodec<R, W> {
+    fn unmarshal<'de, D: serde::Deserialize<'de>>(buf: &'de [u8]) -> Result<D, Error> {
+        ::postcard::from_bytes(buf).map_err(|err| Error::ParseError(Box::new(err)))
+    }
+}
+
+impl<R, W> EraseDeserializer for PostcardCodec<R, W> {
+    fn from_bytes(buf: Vec<u8>) -> Box<dyn erased::Deserializer<'static> + Send> {
+        let de = ::postcard::Deserializer::from_flavor(OwnedBytes { buf, pos: 0 });
+        let de_owned = DeserializerOwned::new(de);
+        Box::new(<dyn erased::Deserializer>::erase(de_owned))
+    }
+}
+
+/// Flavor of `postcard` that owns the bytes, so that the deserializer is `'static`
+struct OwnedBytes {
+    buf: Vec<u8>,
+    pos: usize,
+}
+
+impl Flavor<'static> for OwnedBytes {
+    type Remainder = Vec<u8>;
+    type Source = Vec<u8>;
+
+    fn pop(&mut self) -> ::postcard::Result<u8> {
+        let byte = *self
+            .buf
+            .get(self.pos)
+            .ok_or(::postcard::Error::DeserializeUnexpectedEnd)?;
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.buf.len() - self.pos)
+    }
+
+    /// Borrowing from the buffer is not supported
+    fn try_take_n(&mut self, _: usize) -> ::postcard::Result<&'static [u8]> {
+        Err(::postcard::Error::WontImplement)
+    }
+
+    fn try_take_n_temp<'a>(&'a mut self, ct: usize) -> ::postcard::Result<&'a [u8]>
+    where
+        'static: 'a,
+    {
+        let end = self
+            .pos
+            .checked_add(ct)
+            .filter(|end| *end <= self.buf.len())
+            .ok_or(::postcard::Error::DeserializeUnexpectedEnd)?;
+        let bytes = &self.buf[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn finalize(mut self) -> ::postcard::Result<Vec<u8>> {
+        Ok(self.buf.split_off(self.pos))
+    }
+}
+
+impl<'de> serde::Deserializer<'de> for DeserializerOwned<::postcard::Deserializer<'de, OwnedBytes>>
+where
+    OwnedBytes: Flavor<'de>,
+{
+    type Error = ::postcard::Error;
+
+    // use a macro to generate the code
+    impl_inner_deserializer!();
+}
+
+cfg_if! {
+    if #[cfg(all(
+        feature = "serde_postcard",
+        not(feature = "serde_cbor"),
+        not(feature = "serde_json"),
+        not(feature = "serde_bincode"),
+        not(feature = "serde_rmp"),
+    ))] {
+        use super::Codec;
+
+        impl<R, W, C> Marshal for Codec<R, W, C> {
+            fn marshal<S: serde::Serialize>(val: &S) -> Result<Vec<u8>, Error> {
+                <PostcardCodec<R, W> as Marshal>::marshal(val)
+            }
+        }
+
+        impl<R, W, C> Unmarshal for Codec<R, W, C> {
+            fn unmarshal<'de, D: serde::Deserialize<'de>>(buf: &'de [u8]) -> Result<D, Error> {
+                <PostcardCodec<R, W> as Unmarshal>::unmarshal(buf)
+            }
+        }
+
+        impl<R, W, C> EraseDeserializer for Codec<R, W, C> {
+            fn from_bytes(buf: Vec<u8>) -> Box<dyn erased::Deserializer<'static> + Send> {
+                <PostcardCodec<R, W> as EraseDeserializer>::from_bytes(buf)
+            }
+        }
+    }
+}
@@ -0,0 +1,427 @@
+//! Bodies encoded as protobuf messages with `prost`
+//!
+//! A type generated by `prost` does not implement the serde traits. `Protobuf<M>`
+//! wraps such a message so that it can be the argument or the reply of a call. With
+//! the codecs that come with `toy-rpc`, the encoded message is written as a byte
+//! string of the format of the codec. With the `Prost` format, which is served by a
+//! `CustomCodec`, the body of the message is the encoded protobuf message itself,
+//! which lets the payloads be read by tooling that expects protobuf. The headers and
+//! the bodies that are not a `Protobuf`, eg. the errors, remain serde types, which
+//! `Prost` serializes with `bincode` like the `serde_bincode` codec. Both ends of a
+//! connection have to use `Prost`.
+//!
+//! The methods exported with `#[export_method(prost)]` take and return `prost`
+//! messages. The handler unwraps the argument from a `Protobuf` and wraps the reply,
+//! and the generated client takes and returns the messages as well.
+//!
+//! # Example
+//!
+//! ```no_run
+//! # use tokio::net::{TcpListener, TcpStream};
+//! # use toy_rpc::{Client, Server};
+//! # use toy_rpc::codec::custom::CustomCodec;
+//! # use toy_rpc::codec::prost::Prost;
+//! # use toy_rpc::macros::export_impl;
+//! #[derive(Clone, PartialEq, prost::Message)]
+//! pub struct Greeting {
+//!     #[prost(string, tag = "1")]
+//!     pub name: String,
+//! }
+//!
+//! pub struct Greeter;
+//!
+//! #[export_impl]
+//! impl Greeter {
+//!     #[export_method(prost)]
+//!     async fn greet(&self, args: Greeting) -> Result<Greeting, String> {
+//!         Ok(Greeting { name: format!("Hello, {}", args.name) })
+//!     }
+//! }
+//!
+//! # async fn run(server: Server, listener: TcpListener, addr: &str) -> Result<(), Box<dyn std::error::Error>> {
+//! // server
+//! let (stream, _) = listener.accept().await?;
+//! server.serve_codec(CustomCodec::<_, _, Prost>::new(stream)).await?;
+//!
+//! // client
+//! let stream = TcpStream::connect(addr).await?;
+//! let client = Client::with_custom_codec::<_, Prost>(stream);
+//! let reply = client.greeter().greet(Greeting { name: "World".into() }).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use bincode::Options;
+use erased_serde as erased;
+use serde::de::{SeqAccess, Visitor};
+use serde::ser::Impossible;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::io::Cursor;
+use std::marker::PhantomData;
+
+use super::custom::erase_owned;
+use super::{EraseDeserializer, Marshal, Unmarshal};
+use crate::error::Error;
+
+/// Name of the newtype struct that a `Protobuf` is serialized as, which `Prost`
+/// looks for to write and read the encoded message as the body
+const PROTOBUF_NEWTYPE: &str = "$toy_rpc::Protobuf";
+
+/// A `prost` message that is serialized as its protobuf encoding
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Protobuf<M>(pub M);
+
+impl<M> Protobuf<M> {
+    /// Returns the wrapped message
+    pub fn into_inner(self) -> M {
+        self.0
+    }
+}
+
+impl<M> From<M> for Protobuf<M> {
+    fn from(message: M) -> Self {
+        Self(message)
+    }
+}
+
+/// The encoded message, which is serialized as bytes
+struct Encoded(Vec<u8>);
+
+impl Serialize for Encoded {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(&self.0)
+    }
+}
+
+impl<M: prost::Message> Serialize for Protobuf<M> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_newtype_struct(PROTOBUF_NEWTYPE, &Encoded(self.0.encode_to_vec()))
+    }
+}
+
+impl<'de, M: prost::Message + Default> Deserialize<'de> for Protobuf<M> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_newtype_struct(PROTOBUF_NEWTYPE, ProtobufVisitor(PhantomData))
+    }
+}
+
+struct ProtobufVisitor<M>(PhantomData<M>);
+
+impl<'de, M: prost::Message + Default> Visitor<'de> for ProtobufVisitor<M> {
+    type Value = Protobuf<M>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a protobuf encoded message")
+    }
+
+    fn visit_newtype_struct<D: Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_byte_buf(self)
+    }
+
+    fn visit_bytes<E: serde::de::Error>(self, v: &[u8]) -> Result<Self::Value, E> {
+        M::decode(v).map(Protobuf).map_err(E::custom)
+    }
+
+    fn visit_byte_buf<E: serde::de::Error>(self, v: Vec<u8>) -> Result<Self::Value, E> {
+        self.visit_bytes(&v)
+    }
+
+    // formats that write bytes as a sequence of integers
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut buf = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(byte) = seq.next_element::<u8>()? {
+            buf.push(byte);
+        }
+        self.visit_bytes(&buf)
+    }
+}
+
+/// Format of a `CustomCodec` whose bodies are protobuf messages
+///
+/// A `Protobuf` body is written as the encoded message. The headers and the other
+/// bodies are written with `bincode` with the varint encoding.
+pub struct Prost;
+
+fn parse_error(err: impl std::error::Error + Send + Sync + 'static) -> Error {
+    Error::ParseError(Box::new(err))
+}
+
+fn bincode_options() -> impl bincode::Options {
+    bincode::DefaultOptions::new().with_varint_encoding()
+}
+
+impl Marshal for Prost {
+    fn marshal<S: Serialize>(val: &S) -> Result<Vec<u8>, Error> {
+        match val.serialize(ProtobufBody { in_newtype: false }) {
+            Ok(buf) => Ok(buf),
+            Err(NotProtobuf) => bincode_options().serialize(val).map_err(parse_error),
+        }
+    }
+}
+
+impl Unmarshal for Prost {
+    fn unmarshal<'de, D: Deserialize<'de>>(buf: &'de [u8]) -> Result<D, Error> {
+        bincode_options().deserialize(buf).map_err(parse_error)
+    }
+}
+
+impl EraseDeserializer for Prost {
+    fn from_bytes(buf: Vec<u8>) -> Box<dyn erased::Deserializer<'static> + Send> {
+        Box::new(<dyn erased::Deserializer>::erase(
+            ProtobufBodyDeserializer { buf },
+        ))
+    }
+}
+
+/// The value is not a `Protobuf`, and is serialized with `bincode` instead
+#[derive(Debug)]
+struct NotProtobuf;
+
+impl fmt::Display for NotProtobuf {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Not a protobuf message")
+    }
+}
+
+impl std::error::Error for NotProtobuf {}
+
+impl serde::ser::Error for NotProtobuf {
+    fn custom<T: fmt::Display>(_msg: T) -> Self {
+        NotProtobuf
+    }
+}
+
+/// Serializer that returns the encoded message of a `Protobuf`, and fails on any
+/// other value
+struct ProtobufBody {
+    in_newtype: bool,
+}
+
+macro_rules! not_protobuf {
+    ($($method:ident($($arg:ty),*) -> $ok:ty;)*) => {
+        $(
+            fn $method(self, $(_: $arg),*) -> Result<$ok, NotProtobuf> {
+                Err(NotProtobuf)
+            }
+        )*
+    };
+}
+
+impl Serializer for ProtobufBody {
+    type Ok = Vec<u8>;
+    type Error = NotProtobuf;
+    type SerializeSeq = Impossible<Vec<u8>, NotProtobuf>;
+    type SerializeTuple = Impossible<Vec<u8>, NotProtobuf>;
+    type SerializeTupleStruct = Impossible<Vec<u8>, NotProtobuf>;
+    type SerializeTupleVariant = Impossible<Vec<u8>, NotProtobuf>;
+    type SerializeMap = Impossible<Vec<u8>, NotProtobuf>;
+    type SerializeStruct = Impossible<Vec<u8>, NotProtobuf>;
+    type SerializeStructVariant = Impossible<Vec<u8>, NotProtobuf>;
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<Vec<u8>, NotProtobuf> {
+        match self.in_newtype {
+            true => Ok(v.to_vec()),
+            false => Err(NotProtobuf),
+        }
+    }
+
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(
+        self,
+        name: &'static str,
+        value: &T,
+    ) -> Result<Vec<u8>, NotProtobuf> {
+        match !self.in_newtype && name == PROTOBUF_NEWTYPE {
+            true => value.serialize(ProtobufBody { in_newtype: true }),
+            false => Err(NotProtobuf),
+        }
+    }
+
+    fn serialize_some<T: ?Sized + Serialize>(self, _: &T) -> Result<Vec<u8>, NotProtobuf> {
+        Err(NotProtobuf)
+    }
+
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(
+        self,
+        _: &'static str,
+        _: u32,
+        _: &'static str,
+        _: &T,
+    ) -> Result<Vec<u8>, NotProtobuf> {
+        Err(NotProtobuf)
+    }
+
+    not_protobuf! {
+        serialize_bool(bool) -> Vec<u8>;
+        serialize_i8(i8) -> Vec<u8>;
+        serialize_i16(i16) -> Vec<u8>;
+        serialize_i32(i32) -> Vec<u8>;
+        serialize_i64(i64) -> Vec<u8>;
+        serialize_u8(u8) -> Vec<u8>;
+        serialize_u16(u16) -> Vec<u8>;
+        serialize_u32(u32) -> Vec<u8>;
+        serialize_u64(u64) -> Vec<u8>;
+        serialize_f32(f32) -> Vec<u8>;
+        serialize_f64(f64) -> Vec<u8>;
+        serialize_char(char) -> Vec<u8>;
+        serialize_str(&str) -> Vec<u8>;
+        serialize_none() -> Vec<u8>;
+        serialize_unit() -> Vec<u8>;
+        serialize_unit_struct(&'static str) -> Vec<u8>;
+        serialize_unit_variant(&'static str, u32, &'static str) -> Vec<u8>;
+        serialize_seq(Option<usize>) -> Self::SerializeSeq;
+        serialize_tuple(usize) -> Self::SerializeTuple;
+        serialize_tuple_struct(&'static str, usize) -> Self::SerializeTupleStruct;
+        serialize_tuple_variant(&'static str, u32, &'static str, usize) -> Self::SerializeTupleVariant;
+        serialize_map(Option<usize>) -> Self::SerializeMap;
+        serialize_struct(&'static str, usize) -> Self::SerializeStruct;
+        serialize_struct_variant(&'static str, u32, &'static str, usize) -> Self::SerializeStructVariant;
+    }
+}
+
+/// Deserializer of a body that hands the bytes to a `Protobuf`, and reads any other
+/// type with `bincode`
+struct ProtobufBodyDeserializer {
+    buf: Vec<u8>,
+}
+
+impl ProtobufBodyDeserializer {
+    fn bincode(self) -> Box<dyn erased::Deserializer<'static> + Send> {
+        erase_owned(bincode::Deserializer::with_reader(
+            Cursor::new(self.buf),
+            bincode_options(),
+        ))
+    }
+}
+
+/// The encoded message of a `Protobuf` body
+struct EncodedDeserializer(Vec<u8>);
+
+impl<'de> Deserializer<'de> for EncodedDeserializer {
+    type Error = erased::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_byte_buf(self.0)
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map struct enum identifier ignored_any
+    }
+}
+
+macro_rules! forward_to_bincode {
+    ($($method:ident($($arg:ident: $ty:ty),*);)*) => {
+        $(
+            fn $method<V: Visitor<'static>>(self, $($arg: $ty,)* visitor: V) -> Result<V::Value, Self::Error> {
+                self.bincode().$method($($arg,)* visitor)
+            }
+        )*
+    };
+}
+
+impl Deserializer<'static> for ProtobufBodyDeserializer {
+    type Error = erased::Error;
+
+    fn deserialize_newtype_struct<V: Visitor<'static>>(
+        self,
+        name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        match name == PROTOBUF_NEWTYPE {
+            true => visitor.visit_newtype_struct(EncodedDeserializer(self.buf)),
+            false => self.bincode().deserialize_newtype_struct(name, visitor),
+        }
+    }
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+
+    forward_to_bincode! {
+        deserialize_any();
+        deserialize_bool();
+        deserialize_i8();
+        deserialize_i16();
+        deserialize_i32();
+        deserialize_i64();
+        deserialize_i128();
+        deserialize_u8();
+        deserialize_u16();
+        deserialize_u32();
+        deserialize_u64();
+        deserialize_u128();
+        deserialize_f32();
+        deserialize_f64();
+        deserialize_char();
+        deserialize_str();
+        deserialize_string();
+        deserialize_bytes();
+        deserialize_byte_buf();
+        deserialize_option();
+        deserialize_unit();
+        deserialize_unit_struct(name: &'static str);
+        deserialize_seq();
+        deserialize_tuple(len: usize);
+        deserialize_tuple_struct(name: &'static str, len: usize);
+        deserialize_map();
+        deserialize_struct(name: &'static str, fields: &'static [&'static str]);
+        deserialize_enum(name: &'static str, variants: &'static [&'static str]);
+        deserialize_identifier();
+        deserialize_ignored_any();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    struct Reading {
+        #[prost(string, tag = "1")]
+        sensor: String,
+        #[prost(sint64, repeated, tag = "2")]
+        values: Vec<i64>,
+    }
+
+    fn reading() -> Reading {
+        Reading {
+            sensor: "thermometer".into(),
+            values: vec![-3, 0, 21],
+        }
+    }
+
+    #[test]
+    fn prost_body_is_the_encoded_message() {
+        let body = Protobuf(reading());
+        let erased: &(dyn erased::Serialize + Send + Sync) = &body;
+        let buf = Prost::marshal(&erased).unwrap();
+        assert_eq!(buf, prost::Message::encode_to_vec(&reading()));
+
+        let mut de = Prost::from_bytes(buf);
+        let decoded: Protobuf<Reading> = erased::deserialize(&mut de).unwrap();
+        assert_eq!(decoded.into_inner(), reading());
+    }
+
+    #[test]
+    fn prost_serde_bodies_use_bincode() {
+        let body = (Some("error".to_string()), 7u16);
+        let buf = Prost::marshal(&body).unwrap();
+        assert_eq!(buf, bincode_options().serialize(&body).unwrap());
+
+        let mut de = Prost::from_bytes(buf);
+        let decoded: (Option<String>, u16) = erased::deserialize(&mut de).unwrap();
+        assert_eq!(decoded, body);
+    }
+
+    #[test]
+    fn protobuf_in_serde_formats() {
+        let buf = bincode_options().serialize(&Protobuf(reading())).unwrap();
+        let decoded: Protobuf<Reading> = bincode_options().deserialize(&buf).unwrap();
+        assert_eq!(decoded.0, reading());
+    }
+}
@@ -9,6 +9,8 @@ cfg_if! {
 
     } else if #[cfg(feature = "serde_bincode")] {
 
+    } else if #[cfg(feature = "serde_postcard")] {
+
     } else {
         use erased_serde as erased;
         use serde::de::Visitor;
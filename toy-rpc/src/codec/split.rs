@@ -8,18 +8,23 @@ use crate::util::GracefulShutdown;
 
 use super::*;
 
+/// Reading half of a split `Codec`
 #[allow(dead_code)]
-pub(crate) struct CodecReadHalf<R, C, CT> {
-    pub reader: R,
-    pub marker: PhantomData<C>,
-    pub conn_type: PhantomData<CT>,
+pub struct CodecReadHalf<R, C, CT> {
+    pub(crate) reader: R,
+    pub(crate) marker: PhantomData<C>,
+    pub(crate) conn_type: PhantomData<CT>,
+    pub(crate) meter: Option<Arc<TrafficMeter>>,
 }
 
+/// Writing half of a split `Codec`
 #[allow(dead_code)]
-pub(crate) struct CodecWriteHalf<W, C, CT> {
-    pub writer: W,
-    pub marker: PhantomData<C>,
-    pub conn_type: PhantomData<CT>,
+pub struct CodecWriteHalf<W, C, CT> {
+    pub(crate) writer: W,
+    pub(crate) frame: FrameOptions,
+    pub(crate) marker: PhantomData<C>,
+    pub(crate) conn_type: PhantomData<CT>,
+    pub(crate) meter: Option<Arc<TrafficMeter>>,
 }
 
 impl<W, C, CT> Marshal for CodecWriteHalf<W, C, CT>
@@ -49,6 +54,23 @@ where
     }
 }
 
+#[cfg(any(feature = "tokio_runtime", feature = "async_std_runtime"))]
+#[async_trait]
+impl<W, C, Conn> GracefulShutdown for CodecWriteHalf<W, C, Conn>
+where
+    W: GracefulShutdown + Send,
+    C: Send,
+    Conn: Send,
+{
+    async fn close(&mut self) {
+        self.writer.close().await;
+    }
+
+    async fn close_with(&mut self, code: u16, reason: &str) {
+        self.writer.close_with(code, reason).await;
+    }
+}
+
 /// Split a Codec into a writing half and a reading half
 pub trait SplittableCodec {
     /// Type of the writing half
@@ -66,28 +88,68 @@ pub trait SplittableCodec {
 cfg_if! {
     if #[cfg(all(
         any(feature = "async_std_runtime", feature = "tokio_runtime"),
-        any(
-            all(
-                feature = "serde_bincode",
-                not(feature = "serde_json"),
-                not(feature = "serde_cbor"),
-                not(feature = "serde_rmp"),
-            ),
-            all(
-                feature = "serde_cbor",
-                not(feature = "serde_json"),
-                not(feature = "serde_bincode"),
-                not(feature = "serde_rmp"),
-            ),
-            all(
-                feature = "serde_rmp",
-                not(feature = "serde_cbor"),
-                not(feature = "serde_json"),
-                not(feature = "serde_bincode"),
-            )
-        )
+        not(feature = "serde_json"),
     ))] {
-        use crate::transport::frame::{PayloadType, FrameRead, FrameWrite, FrameHeader};
+        use crate::compression::Compression;
+        use crate::protocol::Header;
+        use crate::transport::bandwidth::frame_len;
+        use crate::transport::frame::{PayloadType, FrameRead, FrameWrite, FrameHeader, Frame};
+
+        /// Size of the buffer on the stack that a header is encoded into with the fixed
+        /// encoding. A longer header is serialized with the codec instead.
+        const FIXED_HEADER_BUF_LEN: usize = 256;
+
+        /// Writes a frame once the bandwidth limit of `meter` allows it
+        async fn write_metered<W>(
+            writer: &mut W,
+            meter: Option<&TrafficMeter>,
+            frame_header: FrameHeader,
+            payload: &[u8],
+        ) -> Result<(), Error>
+        where
+            W: FrameWrite + Send,
+        {
+            if let Some(meter) = meter {
+                meter.write(frame_len(payload.len())).await;
+            }
+            writer.write_frame(frame_header, payload).await
+        }
+
+        /// Writes a frame with its payload compressed if `compression` applies to it
+        async fn write_compressed<W>(
+            writer: &mut W,
+            meter: Option<&TrafficMeter>,
+            compression: Option<&Compression>,
+            frame_header: FrameHeader,
+            payload: &[u8],
+        ) -> Result<(), Error>
+        where
+            W: FrameWrite + Send,
+        {
+            match compression.and_then(|compression| compression.compress(payload)) {
+                Some((id, compressed)) => {
+                    let mut frame_header = frame_header.with_compression(id);
+                    frame_header.payload_len = compressed.len() as u32;
+                    write_metered(writer, meter, frame_header, &compressed).await
+                }
+                None => write_metered(writer, meter, frame_header, payload).await,
+            }
+        }
+
+        impl<R, C> CodecReadHalf<R, C, ConnTypeReadWrite>
+        where
+            R: FrameRead + Send + Unpin,
+        {
+            /// Reads a frame, and waits until the bandwidth limit of the meter allows
+            /// reading the next one
+            async fn read_metered(&mut self) -> Option<Result<Frame, Error>> {
+                let frame = self.reader.read_frame().await?;
+                if let (Ok(frame), Some(meter)) = (&frame, &self.meter) {
+                    meter.read(frame_len(frame.payload_len as usize)).await;
+                }
+                Some(frame)
+            }
+        }
 
         #[async_trait]
         impl<R, C> CodecRead for CodecReadHalf<R, C, ConnTypeReadWrite>
@@ -95,12 +157,32 @@ cfg_if! {
             R: FrameRead + Send + Unpin,
             C: Unmarshal + EraseDeserializer + Send
         {
+            async fn read_protocol_header(&mut self) -> Option<Result<Header, Error>> {
+                let frame = match self.read_metered().await? {
+                    Ok(frame) => frame,
+                    Err(err) => return Some(Err(err)),
+                };
+                match frame.payload_type {
+                    PayloadType::FixedHeader => Some(Header::decode_fixed(&frame.payload).ok_or_else(|| {
+                        Error::IoError(std::io::Error::new(
+                            std::io::ErrorKind::InvalidData,
+                            "Invalid header with the fixed encoding",
+                        ))
+                    })),
+                    _ => Some(Self::unmarshal(&frame.payload)),
+                }
+            }
+
             async fn read_bytes(&mut self) -> Option<Result<Vec<u8>, Error>> {
-                self.reader.read_frame().await
+                self.read_metered().await
                     .map(|res| {
                         res.map(|f| f.payload)
                     })
             }
+
+            fn set_meter(&mut self, meter: Arc<TrafficMeter>) {
+                self.meter = Some(meter);
+            }
         }
 
         #[async_trait]
@@ -113,14 +195,19 @@ cfg_if! {
             where
                 H: serde::Serialize + Metadata + Send,
             {
-                let writer = &mut self.writer;
-
                 let id = header.get_id();
+                if self.frame.fixed_headers {
+                    let mut buf = [0; FIXED_HEADER_BUF_LEN];
+                    if let Some(len) = header.encode_fixed(&mut buf) {
+                        let frame_header = FrameHeader::new(id, 0, PayloadType::FixedHeader, len as u32);
+                        return write_metered(&mut self.writer, self.meter.as_deref(), frame_header, &buf[..len]).await
+                    }
+                }
                 let buf = Self::marshal(&header)?;
                 // let frame = Frame::new(id, 0, PayloadType::Header, buf);
                 let frame_header = FrameHeader::new(id, 0, PayloadType::Header, buf.len() as u32);
 
-                writer.write_frame(frame_header, &buf).await
+                write_compressed(&mut self.writer, self.meter.as_deref(), self.frame.compression.as_ref(), frame_header, &buf).await
             }
 
             async fn write_body(
@@ -128,24 +215,28 @@ cfg_if! {
                 id: MessageId,
                 body: &(dyn erased::Serialize + Send + Sync),
             ) -> Result<(), Error> {
-                let writer = &mut self.writer;
                 let buf = Self::marshal(&body)?;
                 // let frame = Frame::new(id.to_owned(), 1, PayloadType::Data, buf.to_owned());
                 let frame_header = FrameHeader::new(id, 1, PayloadType::Data, buf.len() as u32);
-                writer.write_frame(frame_header, &buf).await
+                write_compressed(&mut self.writer, self.meter.as_deref(), self.frame.compression.as_ref(), frame_header, &buf).await
             }
 
             async fn write_body_bytes(&mut self, id: MessageId, bytes: &[u8]) -> Result<(), Error> {
                 // let frame = Frame::new(*id, 1, PayloadType::Data, bytes);
                 let frame_header = FrameHeader::new(id, 1, PayloadType::Data, bytes.len() as u32);
-                self.writer.write_frame(frame_header, bytes).await
+                write_compressed(&mut self.writer, self.meter.as_deref(), self.frame.compression.as_ref(), frame_header, bytes).await
+            }
+
+            fn set_meter(&mut self, meter: Arc<TrafficMeter>) {
+                self.meter = Some(meter);
             }
         }
 
         impl<R, W> SplittableCodec for Codec<R, W, ConnTypeReadWrite>
         where
             R: FrameRead + Send + Unpin,
-            W: FrameWrite + GracefulShutdown + Send + Unpin
+            W: FrameWrite + GracefulShutdown + Send + Unpin,
+            Self: Marshal + Unmarshal + EraseDeserializer,
         {
             type Writer = CodecWriteHalf::<W, Self, ConnTypeReadWrite>;
             type Reader = CodecReadHalf::<R, Self, ConnTypeReadWrite>;
@@ -154,13 +245,16 @@ cfg_if! {
                 (
                     CodecWriteHalf::<W, Self, ConnTypeReadWrite> {
                         writer: self.writer,
+                        frame: self.frame,
                         marker: PhantomData,
                         conn_type: PhantomData,
+                        meter: None,
                     },
                     CodecReadHalf::<R, Self, ConnTypeReadWrite> {
                         reader: self.reader,
                         marker: PhantomData,
-                        conn_type: PhantomData
+                        conn_type: PhantomData,
+                        meter: None,
                     }
                 )
             }
@@ -183,29 +277,53 @@ cfg_if! {
                 not(feature = "serde_json"),
                 not(feature = "serde_cbor"),
                 not(feature = "serde_rmp"),
+                not(feature = "serde_postcard"),
             ),
             all(
                 feature = "serde_cbor",
                 not(feature = "serde_json"),
                 not(feature = "serde_bincode"),
                 not(feature = "serde_rmp"),
+                not(feature = "serde_postcard"),
             ),
             all(
                 feature = "serde_json",
                 not(feature = "serde_bincode"),
                 not(feature = "serde_cbor"),
                 not(feature = "serde_rmp"),
+                not(feature = "serde_postcard"),
             ),
             all(
                 feature = "serde_rmp",
                 not(feature = "serde_cbor"),
                 not(feature = "serde_json"),
                 not(feature = "serde_bincode"),
+                not(feature = "serde_postcard"),
+            ),
+            all(
+                feature = "serde_postcard",
+                not(feature = "serde_cbor"),
+                not(feature = "serde_json"),
+                not(feature = "serde_bincode"),
+                not(feature = "serde_rmp"),
             )
         )
     ))] {
         use crate::transport::{PayloadRead, PayloadWrite};
 
+        impl<W, C> CodecWriteHalf<W, C, ConnTypePayload>
+        where
+            W: PayloadWrite + Send,
+        {
+            /// Writes a payload once the bandwidth limit of the meter allows it
+            async fn write_metered(&mut self, payload: &[u8]) -> Result<(), Error> {
+                if let Some(meter) = &self.meter {
+                    meter.write(payload.len()).await;
+                }
+                self.writer.write_payload(payload).await
+            }
+        }
+
         #[async_trait]
         impl<R, C> CodecRead for CodecReadHalf<R, C, ConnTypePayload>
         where
@@ -213,7 +331,15 @@ cfg_if! {
             C: Unmarshal + EraseDeserializer + Send
         {
             async fn read_bytes(&mut self) -> Option<Result<Vec<u8>, Error>> {
-                self.reader.read_payload().await
+                let payload = self.reader.read_payload().await?;
+                if let (Ok(payload), Some(meter)) = (&payload, &self.meter) {
+                    meter.read(payload.len()).await;
+                }
+                Some(payload)
+            }
+
+            fn set_meter(&mut self, meter: Arc<TrafficMeter>) {
+                self.meter = Some(meter);
             }
         }
 
@@ -227,9 +353,8 @@ cfg_if! {
             where
                 H: serde::Serialize + Metadata + Send,
             {
-                let writer = &mut self.writer;
                 let buf = Self::marshal(&header)?;
-                writer.write_payload(&buf).await
+                self.write_metered(&buf).await
             }
 
             async fn write_body(
@@ -238,24 +363,15 @@ cfg_if! {
                 body: &(dyn erased::Serialize + Send + Sync),
             ) -> Result<(), Error> {
                 let buf = Self::marshal(&body)?;
-                let writer = &mut self.writer;
-                writer.write_payload(&buf).await
+                self.write_metered(&buf).await
             }
 
             async fn write_body_bytes(&mut self, _: MessageId, bytes: &[u8]) -> Result<(), Error> {
-                self.writer.write_payload(bytes).await
+                self.write_metered(bytes).await
             }
-        }
 
-        #[async_trait]
-        impl<W, C, Conn> GracefulShutdown for CodecWriteHalf<W, C, Conn>
-        where
-            W: GracefulShutdown + Send,
-            C: Send,
-            Conn: Send,
-        {
-            async fn close(&mut self) {
-                self.writer.close().await;
+            fn set_meter(&mut self, meter: Arc<TrafficMeter>) {
+                self.meter = Some(meter);
             }
         }
 
@@ -271,13 +387,16 @@ cfg_if! {
                 (
                     CodecWriteHalf::<W, Self, ConnTypePayload> {
                         writer: self.writer,
+                        frame: FrameOptions::default(),
                         marker: PhantomData,
                         conn_type: PhantomData,
+                        meter: None,
                     },
                     CodecReadHalf::<R, Self, ConnTypePayload> {
                         reader: self.reader,
                         marker: PhantomData,
-                        conn_type: PhantomData
+                        conn_type: PhantomData,
+                        meter: None,
                     }
                 )
             }
@@ -22,6 +22,7 @@ where
         Self {
             reader,
             writer,
+            frame: FrameOptions::default(),
             conn_type: PhantomData,
         }
     }
@@ -50,12 +51,12 @@ where
     async fn close(&mut self) {
         match self.writer.flush().await {
             Ok(()) => (),
-            Err(e) => log::error!("Error closing connection: {}", e),
+            Err(e) => error!("Error closing connection: {}", e),
         };
 
         match self.writer.shutdown().await {
             Ok(()) => (),
-            Err(e) => log::error!("Error closing connection: {}", e),
+            Err(e) => error!("Error closing connection: {}", e),
         };
     }
 }
@@ -69,4 +70,8 @@ where
     async fn close(&mut self) {
         self.writer.close().await;
     }
+
+    async fn close_with(&mut self, code: u16, reason: &str) {
+        self.writer.close_with(code, reason).await;
+    }
 }
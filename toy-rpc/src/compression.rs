@@ -0,0 +1,324 @@
+//! Compression of the payloads of the frame transport
+//!
+//! A `Compression` set with `ServerBuilder::compression`, `ClientBuilder::compression`
+//! or `Codec::with_compression` compresses the header and body of every outbound
+//! message that is at least `min_size` bytes long once serialized. The algorithm is
+//! recorded in the frame header, and every inbound frame is decompressed according to
+//! its header whether or not the receiving side compresses its own messages, so the
+//! services and the calls are not aware of the compression.
+//!
+//! Each algorithm is enabled by its own feature flag, and both peers must enable the
+//! feature of the algorithm in use. A frame compressed with an algorithm that is not
+//! enabled fails to be read.
+//!
+//! - `compression_gzip`: `Algorithm::Gzip` with `flate2`
+//! - `compression_zstd`: `Algorithm::Zstd` with `zstd`
+//! - `compression_lz4`: `Algorithm::Lz4` with `lz4_flex`
+//!
+//! The compression only applies to the frame transport, ie. to the TCP, TLS and QUIC
+//! connections with the `serde_bincode`, `serde_cbor`, `serde_rmp` and `serde_postcard`
+//! codecs. The WebSocket connections and the `serde_json` codec are never compressed.
+//!
+//! # Example
+//!
+//! ```no_run
+//! # #[cfg(all(feature = "server", feature = "client", feature = "tokio_runtime", not(feature = "async_std_runtime"), feature = "compression_zstd"))]
+//! # {
+//! # use std::sync::Arc;
+//! # use toy_rpc::{Client, Server};
+//! # use toy_rpc::macros::export_impl;
+//! # struct Example;
+//! # #[export_impl]
+//! # impl Example {
+//! #     #[export_method]
+//! #     async fn echo(&self, args: String) -> Result<String, String> {
+//! #         Ok(args)
+//! #     }
+//! # }
+//! # use toy_rpc::compression::{Algorithm, Compression};
+//! # async fn run(addr: &str) -> Result<(), Box<dyn std::error::Error>> {
+//! # let example_service = Arc::new(Example);
+//! let server = Server::builder()
+//!     .register(example_service)
+//!     .compression(Compression::new(Algorithm::Zstd).min_size(4 * 1024))
+//!     .build();
+//!
+//! let client = Client::builder()
+//!     .compression(Compression::new(Algorithm::Zstd))
+//!     .dial(addr)
+//!     .await?;
+//! # Ok(())
+//! # }
+//! # }
+//! ```
+
+#![cfg_attr(
+    all(not(feature = "tokio_runtime"), not(feature = "async_std_runtime")),
+    allow(dead_code)
+)]
+
+#[cfg(all(
+    not(feature = "serde_json"),
+    any(feature = "async_std_runtime", feature = "tokio_runtime")
+))]
+use std::io::ErrorKind;
+
+#[cfg(all(
+    not(feature = "serde_json"),
+    any(feature = "async_std_runtime", feature = "tokio_runtime")
+))]
+use crate::error::Error;
+
+/// Default of `Compression::min_size`
+pub const DEFAULT_MIN_SIZE: usize = 1024;
+
+/// Compression algorithm of the payloads
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    /// gzip with the default level
+    #[cfg(feature = "compression_gzip")]
+    #[cfg_attr(feature = "docs", doc(cfg(feature = "compression_gzip")))]
+    Gzip,
+    /// zstd with the default level
+    #[cfg(feature = "compression_zstd")]
+    #[cfg_attr(feature = "docs", doc(cfg(feature = "compression_zstd")))]
+    Zstd,
+    /// LZ4 block format with the size of the payload prepended
+    #[cfg(feature = "compression_lz4")]
+    #[cfg_attr(feature = "docs", doc(cfg(feature = "compression_lz4")))]
+    Lz4,
+}
+
+// the payloads are only compressed by the frame transport
+#[cfg(all(
+    not(feature = "serde_json"),
+    any(feature = "async_std_runtime", feature = "tokio_runtime")
+))]
+impl Algorithm {
+    /// Id of the algorithm in the frame header
+    fn id(self) -> u8 {
+        match self {
+            #[cfg(feature = "compression_gzip")]
+            Self::Gzip => 1,
+            #[cfg(feature = "compression_zstd")]
+            Self::Zstd => 2,
+            #[cfg(feature = "compression_lz4")]
+            Self::Lz4 => 3,
+        }
+    }
+
+    fn from_id(id: u8) -> Option<Self> {
+        match id {
+            #[cfg(feature = "compression_gzip")]
+            1 => Some(Self::Gzip),
+            #[cfg(feature = "compression_zstd")]
+            2 => Some(Self::Zstd),
+            #[cfg(feature = "compression_lz4")]
+            3 => Some(Self::Lz4),
+            _ => None,
+        }
+    }
+
+    #[cfg_attr(
+        not(any(
+            feature = "compression_gzip",
+            feature = "compression_zstd",
+            feature = "compression_lz4"
+        )),
+        allow(unused_variables)
+    )]
+    fn compress(self, payload: &[u8]) -> Option<Vec<u8>> {
+        match self {
+            #[cfg(feature = "compression_gzip")]
+            Self::Gzip => {
+                use std::io::Write;
+
+                let mut encoder =
+                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(payload).ok()?;
+                encoder.finish().ok()
+            }
+            #[cfg(feature = "compression_zstd")]
+            Self::Zstd => zstd::bulk::compress(payload, 0).ok(),
+            #[cfg(feature = "compression_lz4")]
+            Self::Lz4 => Some(lz4_flex::compress_prepend_size(payload)),
+        }
+    }
+
+    #[cfg_attr(
+        not(any(
+            feature = "compression_gzip",
+            feature = "compression_zstd",
+            feature = "compression_lz4"
+        )),
+        allow(unused_variables)
+    )]
+    fn decompress(self, payload: &[u8], max_len: usize) -> Result<Vec<u8>, Error> {
+        match self {
+            #[cfg(feature = "compression_gzip")]
+            Self::Gzip => {
+                use std::io::Read;
+
+                let mut buf = Vec::new();
+                flate2::read::GzDecoder::new(payload)
+                    .take(max_len as u64 + 1)
+                    .read_to_end(&mut buf)
+                    .map_err(|err| invalid_data(format!("Invalid gzip payload: {}", err)))?;
+                Ok(buf)
+            }
+            #[cfg(feature = "compression_zstd")]
+            Self::Zstd => {
+                use std::io::Read;
+
+                let mut buf = Vec::new();
+                zstd::stream::read::Decoder::new(payload)
+                    .and_then(|decoder| decoder.take(max_len as u64 + 1).read_to_end(&mut buf))
+                    .map_err(|err| invalid_data(format!("Invalid zstd payload: {}", err)))?;
+                Ok(buf)
+            }
+            #[cfg(feature = "compression_lz4")]
+            Self::Lz4 => {
+                let len = payload
+                    .get(..4)
+                    .map(|len| u32::from_le_bytes([len[0], len[1], len[2], len[3]]) as usize)
+                    .ok_or_else(|| invalid_data("Invalid lz4 payload: missing size".into()))?;
+                if len > max_len {
+                    return Err(too_long(max_len));
+                }
+                lz4_flex::decompress_size_prepended(payload)
+                    .map_err(|err| invalid_data(format!("Invalid lz4 payload: {}", err)))
+            }
+        }
+    }
+}
+
+/// Compression of the outbound payloads of a connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Compression {
+    /// Algorithm of the compression
+    pub algorithm: Algorithm,
+    /// Size in bytes from which a payload is compressed. Smaller payloads are sent as
+    /// they are, since compressing them would save little if anything.
+    pub min_size: usize,
+}
+
+impl Compression {
+    /// Compresses the payloads of at least `DEFAULT_MIN_SIZE` bytes with `algorithm`
+    pub fn new(algorithm: Algorithm) -> Self {
+        Self {
+            algorithm,
+            min_size: DEFAULT_MIN_SIZE,
+        }
+    }
+
+    /// Sets the size in bytes from which a payload is compressed
+    pub fn min_size(self, bytes: usize) -> Self {
+        let mut compression = self;
+        compression.min_size = bytes;
+        compression
+    }
+
+    /// Compresses `payload`, returns the id of the algorithm and the compressed payload,
+    /// or `None` if the payload is too small or does not shrink
+    #[cfg(all(
+        not(feature = "serde_json"),
+        any(feature = "async_std_runtime", feature = "tokio_runtime")
+    ))]
+    pub(crate) fn compress(&self, payload: &[u8]) -> Option<(u8, Vec<u8>)> {
+        if payload.len() < self.min_size {
+            return None;
+        }
+        let compressed = self.algorithm.compress(payload)?;
+        if compressed.len() < payload.len() {
+            Some((self.algorithm.id(), compressed))
+        } else {
+            None
+        }
+    }
+}
+
+/// Decompresses a payload compressed with the algorithm of id `id`. The decompressed
+/// payload may not be longer than `max_len` bytes.
+#[cfg(all(
+    not(feature = "serde_json"),
+    any(feature = "async_std_runtime", feature = "tokio_runtime")
+))]
+pub(crate) fn decompress(id: u8, payload: &[u8], max_len: usize) -> Result<Vec<u8>, Error> {
+    let algorithm = Algorithm::from_id(id).ok_or_else(|| {
+        invalid_data(format!(
+            "Payload is compressed with an unsupported algorithm (id {})",
+            id
+        ))
+    })?;
+    let decompressed = algorithm.decompress(payload, max_len)?;
+    if decompressed.len() > max_len {
+        return Err(too_long(max_len));
+    }
+    Ok(decompressed)
+}
+
+#[cfg(all(
+    not(feature = "serde_json"),
+    any(feature = "async_std_runtime", feature = "tokio_runtime")
+))]
+fn too_long(max_len: usize) -> Error {
+    invalid_data(format!(
+        "Decompressed payload length exceeded maximum. Max is {}",
+        max_len
+    ))
+}
+
+#[cfg(all(
+    not(feature = "serde_json"),
+    any(feature = "async_std_runtime", feature = "tokio_runtime")
+))]
+fn invalid_data(msg: String) -> Error {
+    Error::IoError(std::io::Error::new(ErrorKind::InvalidData, msg))
+}
+
+#[cfg(all(
+    test,
+    not(feature = "serde_json"),
+    any(feature = "async_std_runtime", feature = "tokio_runtime"),
+    any(
+        feature = "compression_gzip",
+        feature = "compression_zstd",
+        feature = "compression_lz4"
+    )
+))]
+mod tests {
+    use super::*;
+
+    fn algorithms() -> Vec<Algorithm> {
+        vec![
+            #[cfg(feature = "compression_gzip")]
+            Algorithm::Gzip,
+            #[cfg(feature = "compression_zstd")]
+            Algorithm::Zstd,
+            #[cfg(feature = "compression_lz4")]
+            Algorithm::Lz4,
+        ]
+    }
+
+    #[test]
+    fn round_trip() {
+        let payload = "toy-rpc ".repeat(1024).into_bytes();
+        for algorithm in algorithms() {
+            let (id, compressed) = Compression::new(algorithm).compress(&payload).unwrap();
+            assert!(compressed.len() < payload.len());
+            assert_eq!(Algorithm::from_id(id), Some(algorithm));
+            assert_eq!(decompress(id, &compressed, payload.len()).unwrap(), payload);
+            assert!(decompress(id, &compressed, payload.len() - 1).is_err());
+        }
+    }
+
+    #[test]
+    fn small_payloads_are_not_compressed() {
+        for algorithm in algorithms() {
+            let compression = Compression::new(algorithm);
+            assert!(compression.compress(&[0; DEFAULT_MIN_SIZE - 1]).is_none());
+            assert!(compression.min_size(0).compress(b"").is_none());
+        }
+        assert!(decompress(15, b"payload", 1024).is_err());
+    }
+}
@@ -0,0 +1,156 @@
+//! Low-level connection API
+//!
+//! A `Connection` exposes the codec of a connection directly, without the
+//! client or server brokers. Every inbound message is handed to the user as is,
+//! and arbitrary `Header` and body pairs can be sent. This allows prototyping new
+//! kinds of messages on top of the transport and codec machinery.
+
+use erased_serde as erased;
+
+use crate::{
+    codec::{split::SplittableCodec, CodecRead, CodecWrite},
+    error::Error,
+    message::Metadata,
+    protocol::{Header, InboundBody},
+    util::GracefulShutdown,
+};
+
+/// A connection over a codec that sends and receives raw messages
+///
+/// # Example
+///
+/// ```no_run
+/// # #[cfg(all(feature = "tokio_runtime", not(feature = "async_std_runtime")))]
+/// # {
+/// # use tokio::net::TcpStream;
+/// # use toy_rpc::codec::DefaultCodec;
+/// # use toy_rpc::connection::Connection;
+/// # use toy_rpc::protocol::Header;
+/// # async fn run(addr: &str) -> Result<(), Box<dyn std::error::Error>> {
+/// let stream = TcpStream::connect(addr).await?;
+/// let mut conn = Connection::new(DefaultCodec::new(stream));
+/// let header = Header::Ext { id: 0, content: "presence".into(), marker: 1 };
+/// conn.send(header, &()).await?;
+/// while let Some(msg) = conn.recv().await {
+///     let (header, body) = msg?;
+///     println!("{:?}", header);
+/// }
+/// # Ok(())
+/// # }
+/// # }
+/// ```
+pub struct Connection<C: SplittableCodec> {
+    writer: ConnectionWriter<C::Writer>,
+    reader: ConnectionReader<C::Reader>,
+}
+
+impl<C: SplittableCodec> Connection<C> {
+    /// Creates a `Connection` over a codec
+    pub fn new(codec: C) -> Self {
+        let (writer, reader) = codec.split();
+        Self {
+            writer: ConnectionWriter { writer },
+            reader: ConnectionReader { reader },
+        }
+    }
+
+    /// Sends a message with a serializable body
+    pub async fn send(
+        &mut self,
+        header: Header,
+        body: &(dyn erased::Serialize + Send + Sync),
+    ) -> Result<(), Error> {
+        self.writer.send(header, body).await
+    }
+
+    /// Sends a message whose body is already serialized
+    pub async fn send_bytes(&mut self, header: Header, body: &[u8]) -> Result<(), Error> {
+        self.writer.send_bytes(header, body).await
+    }
+
+    /// Receives the next message. Returns `None` if the connection is closed
+    pub async fn recv(&mut self) -> Option<Result<(Header, Box<InboundBody>), Error>> {
+        self.reader.recv().await
+    }
+
+    /// Receives the next message with the body as raw bytes. Returns `None` if
+    /// the connection is closed
+    pub async fn recv_bytes(&mut self) -> Option<Result<(Header, Vec<u8>), Error>> {
+        self.reader.recv_bytes().await
+    }
+
+    /// Closes the connection
+    pub async fn close(&mut self) {
+        self.writer.close().await
+    }
+
+    /// Splits the connection into a writing half and a reading half so that they
+    /// can be used from different tasks
+    pub fn split(self) -> (ConnectionWriter<C::Writer>, ConnectionReader<C::Reader>) {
+        (self.writer, self.reader)
+    }
+}
+
+/// Writing half of a `Connection`
+pub struct ConnectionWriter<W> {
+    writer: W,
+}
+
+impl<W: CodecWrite + GracefulShutdown> ConnectionWriter<W> {
+    /// Sends a message with a serializable body
+    pub async fn send(
+        &mut self,
+        header: Header,
+        body: &(dyn erased::Serialize + Send + Sync),
+    ) -> Result<(), Error> {
+        let id = header.get_id();
+        self.writer.write_header(header).await?;
+        self.writer.write_body(id, body).await
+    }
+
+    /// Sends a message whose body is already serialized
+    pub async fn send_bytes(&mut self, header: Header, body: &[u8]) -> Result<(), Error> {
+        let id = header.get_id();
+        self.writer.write_header(header).await?;
+        self.writer.write_body_bytes(id, body).await
+    }
+
+    /// Closes the connection
+    pub async fn close(&mut self) {
+        self.writer.close().await
+    }
+}
+
+/// Reading half of a `Connection`
+pub struct ConnectionReader<R> {
+    reader: R,
+}
+
+impl<R: CodecRead> ConnectionReader<R> {
+    /// Receives the next message. Returns `None` if the connection is closed
+    pub async fn recv(&mut self) -> Option<Result<(Header, Box<InboundBody>), Error>> {
+        let header = match self.reader.read_protocol_header().await? {
+            Ok(header) => header,
+            Err(err) => return Some(Err(err)),
+        };
+        let body = match self.reader.read_body().await? {
+            Ok(body) => body,
+            Err(err) => return Some(Err(err)),
+        };
+        Some(Ok((header, body)))
+    }
+
+    /// Receives the next message with the body as raw bytes. Returns `None` if
+    /// the connection is closed
+    pub async fn recv_bytes(&mut self) -> Option<Result<(Header, Vec<u8>), Error>> {
+        let header = match self.reader.read_protocol_header().await? {
+            Ok(header) => header,
+            Err(err) => return Some(Err(err)),
+        };
+        let body = match self.reader.read_bytes().await? {
+            Ok(body) => body,
+            Err(err) => return Some(Err(err)),
+        };
+        Some(Ok((header, body)))
+    }
+}
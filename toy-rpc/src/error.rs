@@ -2,15 +2,56 @@
 
 use std::{fmt::Debug, io::ErrorKind};
 
-use crate::message::{ErrorMessage, MessageId};
+use crate::message::{ErrorMessage, MessageId, RemoteError};
+use crate::timing::ServerTimeout;
 
 /// Custom error type
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     /// Errors with IO including that from the transport layer
+    ///
+    /// The failures to connect and the connections reset by the peer have their own
+    /// variants.
     #[error("{0:?}")]
     IoError(#[from] std::io::Error),
 
+    /// The connection to the server timed out, or did not complete within the timeout
+    /// set with `ClientBuilder::connect_timeout`
+    #[error("Connection attempt timed out")]
+    ConnectTimeout,
+
+    /// The server refused the connection
+    #[error("Connection refused")]
+    ConnectionRefused,
+
+    /// The connection was reset or closed by the peer
+    #[error("Connection reset by the peer")]
+    ConnectionReset,
+
+    /// The TLS handshake failed
+    #[error("TLS handshake failed: {0}")]
+    TlsHandshake(String),
+
+    /// The WebSocket handshake failed, for example because the server did not
+    /// upgrade the HTTP connection
+    #[error("WebSocket upgrade failed: {0}")]
+    WsUpgradeFailed(String),
+
+    /// The TLS handshake did not complete within the timeout set with
+    /// `ClientBuilder::tls_handshake_timeout`
+    #[error("TLS handshake timed out")]
+    TlsHandshakeTimeout,
+
+    /// The WebSocket or QUIC handshake did not complete within the timeout set with
+    /// `ClientBuilder::handshake_timeout`
+    #[error("Handshake timed out")]
+    HandshakeTimeout,
+
+    /// The client and the server have no content type in common. See the
+    /// `codec::negotiate` module.
+    #[error("No common content type")]
+    NoCommonContentType,
+
     /// Errors with serialization/deserialization
     #[error("{0}")]
     ParseError(Box<dyn std::error::Error + Send + Sync>),
@@ -38,7 +79,7 @@ pub enum Error {
     /// Cancellation error when an RPC call is cancelled
     #[error("Request is canceled")]
     Canceled(Option<MessageId>),
-    
+
     /// Timeout error when an RPC request timesout
     ///
     /// The timeout is tracked independently on the client and the server.
@@ -47,6 +88,35 @@ pub enum Error {
     /// error.
     #[error("Request reached timeout")]
     Timeout(Option<MessageId>),
+
+    /// The server rejected a publish or subscribe on a topic
+    #[error("Topic is rejected: {0}")]
+    TopicRejected(String),
+
+    /// The type tag of a published item does not match the item type of the
+    /// subscriber
+    #[error("Topic type mismatch: {0}")]
+    TopicTypeMismatch(String),
+
+    /// The serialized argument or response of a call exceeds the size limit of the
+    /// method
+    #[error("Payload is too large: {0}")]
+    PayloadTooLarge(String),
+
+    /// The signature of a response is missing, made with an unknown key or does not
+    /// match the response. See the `signing` module.
+    #[error("Invalid signature: {0}")]
+    InvalidSignature(String),
+
+    /// The server responded with an error of a kind added in a later version, which
+    /// is kept with its message
+    #[error("Remote error: {0}")]
+    Remote(RemoteError),
+
+    /// The server stopped waiting for the handler of the call, either while the
+    /// call was queued or while the handler was executing. See the `timing` module.
+    #[error("Server timeout: {0}")]
+    ServerTimeout(ServerTimeout),
 }
 
 impl Error {
@@ -56,6 +126,75 @@ impl Error {
             ErrorMessage::ServiceNotFound => Self::ServiceNotFound,
             ErrorMessage::MethodNotFound => Self::MethodNotFound,
             ErrorMessage::ExecutionError(s) => Self::ExecutionError(s),
+            ErrorMessage::TopicRejected(s) => Self::TopicRejected(s),
+            ErrorMessage::PayloadTooLarge(s) => Self::PayloadTooLarge(s),
+            ErrorMessage::Timeout(msg) => match ServerTimeout::decode(&msg) {
+                Some(timeout) => Self::ServerTimeout(timeout),
+                None => Self::Remote(RemoteError {
+                    kind: String::from("Timeout"),
+                    payload: msg,
+                }),
+            },
+            ErrorMessage::Remote(err) => Self::Remote(err),
+        }
+    }
+
+    /// Classifies an IO error of an established connection
+    pub(crate) fn from_transport(err: std::io::Error) -> Self {
+        match err.kind() {
+            ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted | ErrorKind::BrokenPipe => {
+                Self::ConnectionReset
+            }
+            _ => Self::IoError(err),
+        }
+    }
+
+    /// Classifies an IO error of a connection attempt
+    #[cfg_attr(
+        all(not(feature = "tokio_runtime"), not(feature = "async_std_runtime")),
+        allow(dead_code)
+    )]
+    pub(crate) fn from_connect(err: std::io::Error) -> Self {
+        match err.kind() {
+            ErrorKind::ConnectionRefused => Self::ConnectionRefused,
+            ErrorKind::TimedOut => Self::ConnectTimeout,
+            _ => Self::from_transport(err),
+        }
+    }
+
+    /// Classifies an error of a WebSocket handshake
+    #[cfg_attr(
+        all(not(feature = "tokio_runtime"), not(feature = "async_std_runtime")),
+        allow(dead_code)
+    )]
+    pub(crate) fn from_ws_handshake(err: tungstenite::Error) -> Self {
+        match err {
+            tungstenite::Error::Io(err) => Self::from_connect(err),
+            tungstenite::Error::Tls(err) => Self::TlsHandshake(err.to_string()),
+            err => Self::WsUpgradeFailed(err.to_string()),
+        }
+    }
+
+    /// Classifies an error of a QUIC connection
+    #[cfg(feature = "transport_quic")]
+    pub(crate) fn from_quic(err: quinn::ConnectionError) -> Self {
+        use quinn::ConnectionError;
+        match err {
+            ConnectionError::TimedOut => Self::ConnectTimeout,
+            ConnectionError::Reset
+            | ConnectionError::ConnectionClosed(_)
+            | ConnectionError::ApplicationClosed(_)
+            | ConnectionError::LocallyClosed => Self::ConnectionReset,
+            err => Self::Internal(Box::new(err)),
+        }
+    }
+
+    /// Classifies an error of a QUIC handshake
+    #[cfg(feature = "transport_quic")]
+    pub(crate) fn from_quic_handshake(err: quinn::ConnectionError) -> Self {
+        match err {
+            quinn::ConnectionError::TransportError(err) => Self::TlsHandshake(err.to_string()),
+            err => Self::from_quic(err),
         }
     }
 }
@@ -94,6 +233,13 @@ impl From<serde_cbor::Error> for Error {
     }
 }
 
+/// Convert from the error of a frame of the custom binary transport
+impl From<toy_rpc_core::frame::FrameError> for Error {
+    fn from(err: toy_rpc_core::frame::FrameError) -> Self {
+        Error::ParseError(Box::new(err))
+    }
+}
+
 impl From<url::ParseError> for Error {
     fn from(err: url::ParseError) -> Self {
         Error::ParseError(Box::new(err))
@@ -132,7 +278,14 @@ impl From<Error> for actix_web::Error {
 
 impl From<tungstenite::Error> for crate::error::Error {
     fn from(err: tungstenite::Error) -> Self {
-        Self::IoError(std::io::Error::new(ErrorKind::InvalidData, err.to_string()))
+        match err {
+            tungstenite::Error::ConnectionClosed | tungstenite::Error::AlreadyClosed => {
+                Self::ConnectionReset
+            }
+            tungstenite::Error::Io(err) => Self::from_transport(err),
+            tungstenite::Error::Tls(err) => Self::TlsHandshake(err.to_string()),
+            err => Self::IoError(std::io::Error::new(ErrorKind::InvalidData, err.to_string())),
+        }
     }
 }
 
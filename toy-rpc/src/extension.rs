@@ -0,0 +1,35 @@
+//! Extensions to the message protocol
+//!
+//! An extension rides on the reserved `Header::Ext` variant. Each extension is
+//! identified by the `marker` field of the header and carries its payload in the
+//! `content` field, which allows experimental features to share the existing
+//! connection without changing the `Header` enum.
+//!
+//! Handlers are registered with `ServerBuilder::register_extension` on the server
+//! and `Client::register_extension` on the client. A handler is called with the
+//! `content` of every `Header::Ext` message with a matching `marker`, and the
+//! returned `Some(content)`, if any, is sent back to the peer with the same `id`
+//! and `marker`. Messages with an unknown `marker` are dropped.
+//!
+//! A handler that always replies should not be registered on both ends with the
+//! same `marker`, otherwise the two handlers would keep replying to each other.
+//!
+//! The marker `metadata::METADATA_MARKER` is reserved for the metadata of the calls
+//! and `HEARTBEAT_MARKER` for the heartbeats of the clients in low-power mode. The
+//! marker `u32::MAX - 2` is reserved for the signatures of the responses, see the
+//! `signing` module.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Marker of the `Header::Ext` messages that a client in low-power mode sends to keep
+/// the connection alive. The server drops them without calling any extension.
+pub const HEARTBEAT_MARKER: u32 = u32::MAX - 1;
+
+/// Handler of the `Header::Ext` messages of one `marker`
+pub type ExtensionHandler = Arc<dyn Fn(String) -> Option<String> + Send + Sync + 'static>;
+
+/// Hashmap of extension handlers.
+///
+/// The keys are the markers and the values are `ExtensionHandler`s
+pub type ExtensionMap = HashMap<u32, ExtensionHandler>;
@@ -56,6 +56,12 @@
 //! - `http_tide`: enables `tide` integration on the server side. This also enables `async_std_runtime`
 //! - `http_actix_web`: enables `actix-web` integration on the server side. This also enables `tokio_runtime`
 //! - `http_warp`: enables integration with `warp` on the server side. This also enables `tokio_runtime`
+//! - `http_hyper`: enables `Server::serve_hyper`, which accepts the WebSocket connections over
+//!   HTTP with `hyper` alone, without a web framework. This also enables `tokio_runtime`.
+//!   Along with `http_warp`, it serves the HTTP POST transport of `Client::dial_http_post` too
+//! - `wasm`: enables `client::wasm::WasmClient`, a client for browsers that uses the
+//!   `WebSocket` of `web-sys` instead of a runtime, when the crate is compiled for `wasm32`.
+//!   This also enables `client`
 //!
 //! Choice of RPC server or client (both can be enabled at the same time)
 //!
@@ -65,29 +71,75 @@
 //! Choice of serialization/deserialzation (only one should be enabled at a time)
 //!
 //! - `serde_bincode`: (default) the default codec will use `bincode`
-//!     for serialization/deserialization
+//!   for serialization/deserialization
 //! - `serde_json`: the default codec will use `serde_json`
-//!     for `json` serialization/deserialization
+//!   for `json` serialization/deserialization
 //! - `serde_cbor`: the default codec will use `serde_cbor`
-//!     for serialization/deserialization
+//!   for serialization/deserialization
 //! - `serde_rmp`: the default codec will use `rmp-serde`
-//!     for serialization/deserialization
+//!   for serialization/deserialization
+//! - `serde_postcard`: the default codec will use `postcard`
+//!   for serialization/deserialization, which produces smaller payloads, eg. for
+//!   embedded clients. This also enables `postcard_codec`
+//! - `canonical`: the `serde_json` and `serde_cbor` codecs encode maps with their entries
+//!   sorted by key, so that the same value is always encoded into the same bytes regardless
+//!   of the iteration order of a `HashMap`, eg. for signing, caching or deduplication.
+//!   This has no effect on the `serde_bincode`, `serde_rmp` and `serde_postcard` codecs
+//! - `postcard_codec`: enables `codec::postcard::PostcardCodec`, which a server uses to
+//!   serve the minimal client of `toy-rpc-core` on embedded devices. This is not a
+//!   default codec and is only available with `serde_bincode`, `serde_cbor`, `serde_rmp`
+//!   or `serde_postcard`
+//! - `codec_negotiation`: lets a server serve the clients of bincode, JSON, CBOR and
+//!   MessagePack over the same listener, with the codec of each connection negotiated at
+//!   runtime. See the `codec::negotiate` module. This is not available with `serde_json`
+//! - `prost_codec`: enables `codec::prost`, whose `Protobuf` wrapper sends `prost`
+//!   messages as the arguments and replies of the methods exported with
+//!   `#[export_method(prost)]`, and whose `Prost` format writes them as plain protobuf
+//!   bodies with a `CustomCodec`. This is not available with `serde_json`
+//!
+//! Any other format that works with serde can be plugged in with a
+//! `codec::custom::CustomCodec`, which does not need any of the `serde_*` feature flags but
+//! is not available with `serde_json`.
 //!
 //! TLS support
 //!
 //! - `tls`: enables TLS support
+//! - `transport_quic`: enables the QUIC transport with `quinn`, which always uses TLS.
+//!   This also enables `tokio_runtime`. The QUIC transport is only available with
+//!   `serde_bincode`, `serde_cbor`, `serde_rmp` or `serde_postcard`
+//! - `signing`: lets a server sign its responses with HMAC-SHA256 or Ed25519 and a
+//!   client verify them, eg. through untrusted relays without end-to-end TLS. See the
+//!   `signing` module
+//!
+//! Compression of the payloads of the frame transport, see the `compression` module
+//!
+//! - `compression_gzip`: enables gzip with `flate2`
+//! - `compression_zstd`: enables zstd with `zstd`
+//! - `compression_lz4`: enables LZ4 with `lz4_flex`
+//!
+//! Diagnostics
+//!
+//! - `logging`: (default) emits the diagnostic events with `log`
+//! - `tracing`: emits the diagnostic events with `tracing` instead, and instruments the
+//!   calls handled by the server with a span
+//!
+//! Without either of them, the diagnostic events are removed at compile time.
+//!
+//! - `metrics`: the server counts the calls, the errors and the calls in flight of every
+//!   method and keeps histograms of their latencies, which `Server::metrics` returns and
+//!   the HTTP integrations can serve to Prometheus. See the `server::metrics` module
 //!
 //! Other trivial feature flags are listed below, and they are likely of no actual usage for you.
 //! - `docs`
 //! - `std`: `serde/std`. There is no actual usage right now.
 //!
-//! By default, only `serde_bincode` feature is enabled.
+//! By default, only the `serde_bincode` and `logging` features are enabled.
 //! You must enable at least one runtime feature flag and the `server` and/or `client` to have something usable.
 //!
 //! ## Default features
 //!
 //! ```toml
-//! default = ["serde_bincode"]
+//! default = ["serde_bincode", "logging"]
 //! ```
 //!
 //! # Integration
@@ -101,13 +153,27 @@
 //! A quickstart example with `tokio` runtime is provided in the [Book/Quickstart](https://minghuaw.github.io/toy-rpc/02_quickstart.html).
 //!
 
+#[macro_use]
+mod logging;
+
+pub mod capability;
+mod clock;
 pub mod codec;
+pub mod compression;
+pub mod connection;
 pub mod error;
+pub mod extension;
 pub mod macros;
 pub mod message;
+pub mod metadata;
+pub mod pagination;
+pub mod payload;
 pub mod protocol;
 pub mod pubsub;
 pub mod service;
+pub mod streaming;
+pub mod timing;
+pub mod transaction;
 pub mod transport;
 pub mod util;
 
@@ -116,6 +182,7 @@ pub mod util;
     feature = "http_actix_web",
     feature = "http_warp",
     feature = "http_tide",
+    feature = "http_hyper",
     feature = "client"
 ))]
 pub const DEFAULT_RPC_PATH: &str = "_rpc_";
@@ -130,6 +197,12 @@ pub mod server;
 #[cfg(feature = "server")]
 pub use server::{builder::ServerBuilder, Server};
 
+#[cfg(feature = "signing")]
+pub mod signing;
+
+#[cfg(feature = "test-util")]
+pub mod test_util;
+
 /// Type alias for `std::result::Result<T, toy_rpc::error::Error>`
 pub type Result<T, E = error::Error> = std::result::Result<T, E>;
 
@@ -0,0 +1,76 @@
+//! Diagnostic events of the crate
+//!
+//! Every event goes through the macros of this module, which forward it to
+//!
+//! - `tracing` if the `tracing` feature is enabled,
+//! - otherwise `log` if the `logging` feature is enabled, which is the default,
+//! - otherwise nowhere. The events are removed at compile time, and their arguments
+//!   are only type-checked.
+//!
+//! The levels are used as follows.
+//!
+//! - `error`: a failure of the server or the client itself, such as a broken
+//!   connection or a response that cannot be written
+//! - `warn`: a misbehaving peer or a dropped message, such as a malformed request or
+//!   a full subscriber
+//! - `info`: a change of the state of a connection, a server or a topic
+//! - `debug`: the handling of each message
+//! - `trace`: the contents of each message
+
+#[cfg(feature = "tracing")]
+macro_rules! event {
+    ($level:ident, $($arg:tt)+) => {
+        tracing::$level!($($arg)+)
+    };
+}
+
+#[cfg(all(feature = "logging", not(feature = "tracing")))]
+macro_rules! event {
+    ($level:ident, $($arg:tt)+) => {
+        log::$level!($($arg)+)
+    };
+}
+
+#[cfg(not(any(feature = "logging", feature = "tracing")))]
+macro_rules! event {
+    ($level:ident, $($arg:tt)+) => {
+        if false {
+            let _ = format_args!($($arg)+);
+        }
+    };
+}
+
+#[allow(unused_macros)]
+macro_rules! error {
+    ($($arg:tt)+) => {
+        event!(error, $($arg)+)
+    };
+}
+
+#[allow(unused_macros)]
+macro_rules! warn {
+    ($($arg:tt)+) => {
+        event!(warn, $($arg)+)
+    };
+}
+
+#[allow(unused_macros)]
+macro_rules! info {
+    ($($arg:tt)+) => {
+        event!(info, $($arg)+)
+    };
+}
+
+#[allow(unused_macros)]
+macro_rules! debug {
+    ($($arg:tt)+) => {
+        event!(debug, $($arg)+)
+    };
+}
+
+#[allow(unused_macros)]
+macro_rules! trace {
+    ($($arg:tt)+) => {
+        event!(trace, $($arg)+)
+    };
+}
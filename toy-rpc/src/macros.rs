@@ -1,6 +1,6 @@
 //! Re-export of proc_macros defined in `toy_rpc_macros`
 
-pub use toy_rpc_macros::{export_impl, export_trait, export_trait_impl};
+pub use toy_rpc_macros::{export_impl, export_topics, export_trait, export_trait_impl};
 
 #[cfg(all(
     any(
@@ -8,7 +8,8 @@ pub use toy_rpc_macros::{export_impl, export_trait, export_trait_impl};
         feature = "tokio_runtime",
         feature = "http_tide",
         feature = "http_warp",
-        feature = "http_actix_web"
+        feature = "http_actix_web",
+        feature = "wasm"
     ),
     any(
         all(
@@ -16,24 +17,35 @@ pub use toy_rpc_macros::{export_impl, export_trait, export_trait_impl};
             not(feature = "serde_json"),
             not(feature = "serde_cbor"),
             not(feature = "serde_rmp"),
+            not(feature = "serde_postcard"),
         ),
         all(
             feature = "serde_cbor",
             not(feature = "serde_json"),
             not(feature = "serde_bincode"),
             not(feature = "serde_rmp"),
+            not(feature = "serde_postcard"),
         ),
         all(
             feature = "serde_json",
             not(feature = "serde_bincode"),
             not(feature = "serde_cbor"),
             not(feature = "serde_rmp"),
+            not(feature = "serde_postcard"),
         ),
         all(
             feature = "serde_rmp",
             not(feature = "serde_cbor"),
             not(feature = "serde_json"),
             not(feature = "serde_bincode"),
+            not(feature = "serde_postcard"),
+        ),
+        all(
+            feature = "serde_postcard",
+            not(feature = "serde_cbor"),
+            not(feature = "serde_json"),
+            not(feature = "serde_bincode"),
+            not(feature = "serde_rmp"),
         )
     )
 ))]
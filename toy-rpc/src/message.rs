@@ -1,10 +1,9 @@
 //! ErrorMessage from server to client
 use cfg_if::cfg_if;
-use serde::{Deserialize, Serialize};
 use std::sync::atomic::AtomicU16;
 
-/// Type of message id is u16
-pub type MessageId = u16;
+pub(crate) use toy_rpc_core::message::ErrorMessage;
+pub use toy_rpc_core::message::{MessageId, RemoteError};
 
 /// Atomic type of MessageId
 pub type AtomicMessageId = AtomicU16;
@@ -13,15 +12,13 @@ pub type AtomicMessageId = AtomicU16;
 pub trait Metadata {
     /// Gets the id from the metadata
     fn get_id(&self) -> MessageId;
-}
 
-/// The Error message that will be sent over for a error response
-#[derive(Serialize, Deserialize)]
-pub(crate) enum ErrorMessage {
-    InvalidArgument,
-    ServiceNotFound,
-    MethodNotFound,
-    ExecutionError(String),
+    /// Encodes the metadata into `buf` without serde, and returns the number of bytes
+    /// written. Returns `None` if the metadata has no fixed encoding or if `buf` is
+    /// too short, in which case it is serialized with the codec.
+    fn encode_fixed(&self, _buf: &mut [u8]) -> Option<usize> {
+        None
+    }
 }
 
 cfg_if! {
@@ -31,26 +28,40 @@ cfg_if! {
     ))] {
         /// Token indicating a cancellation request
         #[cfg(any(feature = "server", feature = "client"))]
-        pub(crate) const CANCELLATION_TOKEN: &str = "RPC_TASK_CANCELLATION";
-        #[cfg(any(feature = "server", feature = "client"))]
-        pub(crate) const CANCELLATION_TOKEN_DELIM: &str = ".";
+        pub(crate) use toy_rpc_core::message::{CANCELLATION_TOKEN, CANCELLATION_TOKEN_DELIM};
 
         #[cfg(feature = "server")]
         use crate::{error::Error};
 
         #[cfg(feature = "server")]
-        impl ErrorMessage {
-            pub(crate) fn from_err(err: Error) -> Result<Self, Error> {
+        impl std::convert::TryFrom<Error> for ErrorMessage {
+            type Error = Error;
+
+            fn try_from(err: Error) -> Result<Self, Error> {
                 match err {
                     Error::InvalidArgument => Ok(Self::InvalidArgument),
                     Error::ServiceNotFound => Ok(Self::ServiceNotFound),
                     Error::MethodNotFound => Ok(Self::MethodNotFound),
                     Error::ExecutionError(s) => Ok(Self::ExecutionError(s)),
+                    Error::TopicRejected(s) => Ok(Self::TopicRejected(s)),
+                    Error::PayloadTooLarge(s) => Ok(Self::PayloadTooLarge(s)),
+                    Error::Remote(err) => Ok(Self::Remote(err)),
+                    Error::ServerTimeout(timeout) => Ok(Self::Timeout(timeout.encode())),
                     e @ Error::IoError(_) => Err(e),
+                    e @ Error::ConnectTimeout => Err(e),
+                    e @ Error::ConnectionRefused => Err(e),
+                    e @ Error::ConnectionReset => Err(e),
+                    e @ Error::TlsHandshake(_) => Err(e),
+                    e @ Error::WsUpgradeFailed(_) => Err(e),
+                    e @ Error::TlsHandshakeTimeout => Err(e),
+                    e @ Error::HandshakeTimeout => Err(e),
+                    e @ Error::NoCommonContentType => Err(e),
                     e @ Error::ParseError(_) => Err(e),
                     e @ Error::Internal(_) => Err(e),
                     e @ Error::Canceled(_) => Err(e),
                     e @ Error::Timeout(_) => Err(e),
+                    e @ Error::TopicTypeMismatch(_) => Err(e),
+                    e @ Error::InvalidSignature(_) => Err(e),
                 }
             }
         }
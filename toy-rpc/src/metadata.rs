@@ -0,0 +1,368 @@
+//! Key-value metadata attached to the calls
+//!
+//! Along with its argument, a call may carry `Metadata`, eg. an authentication
+//! token, the id of a trace or a deadline, and the server may attach `Metadata` to
+//! its response. The metadata rides on a `Header::Ext` message with the marker
+//! `METADATA_MARKER` and the id of the call, which is written right before the
+//! request or the response, so the `Header` is unchanged and the peers that do not
+//! know about the metadata simply drop it.
+//!
+//! On the client, the metadata is sent with `Client::call_with_metadata`, which also
+//! returns the metadata of the response, or by a `Layer` for every call through
+//! `Request::metadata`.
+//!
+//! On the server, a method exported with `#[export_impl]` gets the `Context` of the
+//! call if it takes a `Context` before its argument. The `Context` holds the
+//! metadata of the request and collects the metadata of the response. The metadata
+//! of the response is only sent for the methods returning a single response. The
+//! `Context` also hands the method the shared resources set with
+//! `ServerBuilder::data`.
+//!
+//! # Example
+//!
+//! ```no_run
+//! # #[cfg(all(feature = "server", feature = "client", feature = "tokio_runtime", not(feature = "async_std_runtime")))]
+//! # {
+//! use toy_rpc::metadata::{Context, Metadata};
+//! # use toy_rpc::{Client, Error};
+//! # use toy_rpc::macros::export_impl;
+//! # struct Echo;
+//!
+//! #[export_impl]
+//! impl Echo {
+//!     #[export_method]
+//!     async fn echo(&self, context: Context, args: String) -> Result<String, Error> {
+//!         if context.get("token") != Some("secret") {
+//!             return Err(Error::ExecutionError("Unauthorized".into()));
+//!         }
+//!         context.set_response_metadata("served-by", "echo-1");
+//!         Ok(args)
+//!     }
+//! }
+//!
+//! # async fn run(client: Client) -> Result<(), Error> {
+//! let mut metadata = Metadata::new();
+//! metadata.insert("token".into(), "secret".into());
+//! let (reply, metadata): (String, Metadata) = client
+//!     .call_with_metadata("Echo.echo", "hello".to_string(), metadata)
+//!     .await?;
+//! assert_eq!(metadata.get("served-by").map(String::as_str), Some("echo-1"));
+//! # Ok(())
+//! # }
+//! # }
+//! ```
+
+use std::collections::HashMap;
+
+#[cfg(feature = "server")]
+use std::{
+    cell::RefCell,
+    sync::{Arc, Mutex},
+};
+
+#[cfg(feature = "server")]
+use crate::message::MessageId;
+#[cfg(feature = "server")]
+use crate::server::data::DataMap;
+#[cfg(feature = "server")]
+use crate::timing::Stopwatch;
+
+#[cfg(all(
+    feature = "server",
+    any(
+        all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+        all(
+            feature = "tokio_runtime",
+            not(feature = "async_std_runtime"),
+            not(feature = "http_actix_web")
+        )
+    )
+))]
+use crate::server::peer::Peer;
+
+/// Key-value metadata of a request or of a response
+pub type Metadata = HashMap<String, String>;
+
+/// Marker of the `Header::Ext` messages that carry the metadata of a call. An
+/// extension registered with this marker is never called.
+pub const METADATA_MARKER: u32 = u32::MAX;
+
+/// Encodes the metadata as the content of a `Header::Ext` message, with one
+/// `key=value` line per entry. `%`, `=` and line breaks are percent-encoded.
+#[cfg_attr(
+    all(not(feature = "tokio_runtime"), not(feature = "async_std_runtime")),
+    allow(dead_code)
+)]
+pub(crate) fn encode(metadata: &Metadata) -> String {
+    let mut content = String::new();
+    for (key, value) in metadata {
+        if !content.is_empty() {
+            content.push('\n');
+        }
+        escape_into(&mut content, key);
+        content.push('=');
+        escape_into(&mut content, value);
+    }
+    content
+}
+
+/// Decodes the content of a `Header::Ext` message. The malformed lines are skipped.
+#[cfg_attr(
+    all(not(feature = "tokio_runtime"), not(feature = "async_std_runtime")),
+    allow(dead_code)
+)]
+pub(crate) fn decode(content: &str) -> Metadata {
+    content
+        .split('\n')
+        .filter_map(|line| line.split_once('='))
+        .map(|(key, value)| (unescape(key), unescape(value)))
+        .collect()
+}
+
+#[cfg_attr(
+    all(not(feature = "tokio_runtime"), not(feature = "async_std_runtime")),
+    allow(dead_code)
+)]
+fn escape_into(content: &mut String, s: &str) {
+    for c in s.chars() {
+        match c {
+            '%' => content.push_str("%25"),
+            '=' => content.push_str("%3D"),
+            '\n' => content.push_str("%0A"),
+            '\r' => content.push_str("%0D"),
+            c => content.push(c),
+        }
+    }
+}
+
+#[cfg_attr(
+    all(not(feature = "tokio_runtime"), not(feature = "async_std_runtime")),
+    allow(dead_code)
+)]
+fn unescape(s: &str) -> String {
+    let mut unescaped = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(start) = rest.find('%') {
+        unescaped.push_str(&rest[..start]);
+        let escaped = rest
+            .get(start + 1..start + 3)
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) if byte.is_ascii() => {
+                unescaped.push(byte as char);
+                rest = &rest[start + 3..];
+            }
+            _ => {
+                unescaped.push('%');
+                rest = &rest[start + 1..];
+            }
+        }
+    }
+    unescaped.push_str(rest);
+    unescaped
+}
+
+/// Context of a call handled by the server. See the module documentation.
+#[cfg(feature = "server")]
+#[derive(Debug, Clone, Default)]
+pub struct Context {
+    inner: Arc<ContextInner>,
+}
+
+#[cfg(feature = "server")]
+#[derive(Debug, Default)]
+struct ContextInner {
+    id: MessageId,
+    service_method: String,
+    metadata: Metadata,
+    response: Mutex<Metadata>,
+    // shared resources set on the server, which are attached when the handler is
+    // created
+    data: Mutex<Option<Arc<DataMap>>>,
+    // time spent queued and executing, from the creation of the context
+    stopwatch: Stopwatch,
+    // connection of the call, whose client may serve its own services
+    #[cfg(any(
+        all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+        all(
+            feature = "tokio_runtime",
+            not(feature = "async_std_runtime"),
+            not(feature = "http_actix_web")
+        )
+    ))]
+    peer: Option<Peer>,
+}
+
+#[cfg(feature = "server")]
+thread_local! {
+    // context of the call whose handler is being created
+    static CURRENT: RefCell<Option<Context>> = const { RefCell::new(None) };
+}
+
+#[cfg(feature = "server")]
+impl Context {
+    pub(crate) fn new(id: MessageId, service_method: String, metadata: Metadata) -> Self {
+        Self {
+            inner: Arc::new(ContextInner {
+                id,
+                service_method,
+                metadata,
+                response: Mutex::new(Metadata::new()),
+                data: Mutex::new(None),
+                stopwatch: Stopwatch::default(),
+                #[cfg(any(
+                    all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+                    all(
+                        feature = "tokio_runtime",
+                        not(feature = "async_std_runtime"),
+                        not(feature = "http_actix_web")
+                    )
+                ))]
+                peer: None,
+            }),
+        }
+    }
+
+    /// Context of a call over a connection whose client may serve its own services
+    #[cfg(any(
+        all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+        all(
+            feature = "tokio_runtime",
+            not(feature = "async_std_runtime"),
+            not(feature = "http_actix_web")
+        )
+    ))]
+    pub(crate) fn with_peer(
+        id: MessageId,
+        service_method: String,
+        metadata: Metadata,
+        peer: Peer,
+    ) -> Self {
+        let mut context = Self::new(id, service_method, metadata);
+        // the context is not shared yet
+        if let Some(inner) = Arc::get_mut(&mut context.inner) {
+            inner.peer = Some(peer);
+        }
+        context
+    }
+
+    /// Returns the context of the call whose handler is being created. This is
+    /// called by the handlers generated by `#[export_impl]`.
+    #[doc(hidden)]
+    pub fn current() -> Self {
+        CURRENT.with(|current| current.borrow().clone().unwrap_or_default())
+    }
+
+    /// ID of the call
+    pub fn id(&self) -> MessageId {
+        self.inner.id
+    }
+
+    /// Name of the service and method, eg. `"Arith.add"`
+    pub fn service_method(&self) -> &str {
+        &self.inner.service_method
+    }
+
+    /// Metadata of the request
+    pub fn metadata(&self) -> &Metadata {
+        &self.inner.metadata
+    }
+
+    /// Value of a key of the metadata of the request
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.inner.metadata.get(key).map(String::as_str)
+    }
+
+    /// Handle to call the services of the client of the call, eg. an agent that
+    /// dialed out to the server. This is `None` for the calls that are not read
+    /// from a connection. See the `server::peer` module for details.
+    #[cfg(any(
+        all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+        all(
+            feature = "tokio_runtime",
+            not(feature = "async_std_runtime"),
+            not(feature = "http_actix_web")
+        )
+    ))]
+    pub fn peer(&self) -> Option<Peer> {
+        self.inner.peer.clone()
+    }
+
+    /// Shared resource of type `T` set with `ServerBuilder::data`, eg. a database
+    /// pool. This is `None` if no value of type `T` was set on the server.
+    pub fn data<T: Send + Sync + 'static>(&self) -> Option<Arc<T>> {
+        match self.inner.data.lock() {
+            Ok(data) => data.as_ref().and_then(|data| data.get::<T>()),
+            Err(_) => None,
+        }
+    }
+
+    /// Attaches the shared resources of the server
+    pub(crate) fn set_data(&self, data: Arc<DataMap>) {
+        if let Ok(mut current) = self.inner.data.lock() {
+            *current = Some(data);
+        }
+    }
+
+    /// Measures the time that the call spends queued and executing
+    pub(crate) fn stopwatch(&self) -> &Stopwatch {
+        &self.inner.stopwatch
+    }
+
+    /// Attaches a key-value pair to the metadata of the response
+    pub fn set_response_metadata(&self, key: impl ToString, value: impl ToString) {
+        if let Ok(mut response) = self.inner.response.lock() {
+            response.insert(key.to_string(), value.to_string());
+        }
+    }
+
+    /// Takes the metadata of the response collected so far
+    pub(crate) fn take_response_metadata(&self) -> Metadata {
+        match self.inner.response.lock() {
+            Ok(mut response) => std::mem::take(&mut *response),
+            Err(_) => Metadata::new(),
+        }
+    }
+
+    /// Makes this context the current one while the handler of the call is created
+    pub(crate) fn scope<R>(&self, f: impl FnOnce() -> R) -> R {
+        let previous = CURRENT.with(|current| current.replace(Some(self.clone())));
+        let result = f();
+        CURRENT.with(|current| *current.borrow_mut() = previous);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn metadata_round_trips() {
+        let mut metadata = Metadata::new();
+        metadata.insert("token".into(), "a=b%c".into());
+        metadata.insert("multi\nline".into(), "x\r\ny".into());
+        metadata.insert("empty".into(), "".into());
+        assert_eq!(decode(&encode(&metadata)), metadata);
+        assert!(decode(&encode(&Metadata::new())).is_empty());
+    }
+
+    #[test]
+    fn malformed_content_is_skipped() {
+        let metadata = decode("key=value\nno separator\nbad=%zz%4");
+        assert_eq!(metadata.len(), 2);
+        assert_eq!(metadata["key"], "value");
+        assert_eq!(metadata["bad"], "%zz%4");
+    }
+
+    #[cfg(feature = "server")]
+    #[test]
+    fn context_is_current_in_scope() {
+        let context = Context::new(7, "Echo.echo".into(), Metadata::new());
+        let current = context.scope(Context::current);
+        assert_eq!(current.id(), 7);
+        current.set_response_metadata("key", "value");
+        assert_eq!(context.take_response_metadata()["key"], "value");
+        assert_eq!(Context::current().id(), 0);
+    }
+}
@@ -0,0 +1,206 @@
+//! Cursor pagination of large result sets
+//!
+//! Instead of returning a large result set in one response, a method can take a
+//! `PageRequest` and return one `Page` of the results at a time, along with an
+//! opaque `Cursor` to the next page. `paginate` and `paginate_stream` cut a page out
+//! of an iterator or a stream.
+//!
+//! ```no_run
+//! # #[cfg(all(feature = "server", feature = "client", feature = "tokio_runtime", not(feature = "async_std_runtime")))]
+//! # {
+//! # use toy_rpc::Error;
+//! # use toy_rpc::macros::export_impl;
+//! # use toy_rpc::pagination::{paginate, Page, PageRequest};
+//! # #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+//! # struct Product {
+//! #     name: String,
+//! # }
+//! # struct Catalog {
+//! #     products: Vec<Product>,
+//! # }
+//! #[export_impl]
+//! impl Catalog {
+//!     #[export_method]
+//!     async fn products(&self, request: PageRequest) -> Result<Page<Product>, Error> {
+//!         paginate(self.products.iter().cloned(), &request)
+//!     }
+//! }
+//! # }
+//! ```
+//!
+//! On the client side, `Client::paginate` returns a stream of all the items that
+//! requests the following pages as it is consumed. The generated client has a
+//! `<method>_stream` method for every method that returns a `Page`.
+//!
+//! ```no_run
+//! # #[cfg(all(feature = "server", feature = "client", feature = "tokio_runtime", not(feature = "async_std_runtime")))]
+//! # {
+//! # use toy_rpc::Error;
+//! # use toy_rpc::macros::export_impl;
+//! # use toy_rpc::pagination::{paginate, Page, PageRequest};
+//! # #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+//! # struct Product {
+//! #     name: String,
+//! # }
+//! # struct Catalog {
+//! #     products: Vec<Product>,
+//! # }
+//! # use futures::StreamExt;
+//! # #[export_impl]
+//! # impl Catalog {
+//! #     #[export_method]
+//! #     async fn products(&self, request: PageRequest) -> Result<Page<Product>, Error> {
+//! #         paginate(self.products.iter().cloned(), &request)
+//! #     }
+//! # }
+//! # async fn run(client: toy_rpc::Client) -> Result<(), toy_rpc::Error> {
+//! let catalog = client.catalog();
+//! let mut products = catalog.products_stream(100);
+//! while let Some(product) = products.next().await {
+//!     println!("{:?}", product?);
+//! }
+//! # Ok(())
+//! # }
+//! # }
+//! ```
+//!
+//! The cursor holds the offset of the next page, so items inserted into or removed
+//! from the result set between two requests shift the following pages.
+
+use futures::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::pin::Pin;
+
+use crate::Error;
+
+/// Stream of all the items of a paginated method, returned by `Client::paginate`
+pub type PageStream<'c, T> = Pin<Box<dyn Stream<Item = Result<T, Error>> + Send + 'c>>;
+
+/// Opaque position of a page in a result set
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Cursor(String);
+
+impl Cursor {
+    fn at(offset: usize) -> Self {
+        Self(offset.to_string())
+    }
+
+    fn offset(&self) -> Result<usize, Error> {
+        self.0.parse().map_err(|_| Error::InvalidArgument)
+    }
+}
+
+/// Arguments of a paginated method
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PageRequest {
+    /// Cursor to the requested page. `None` requests the first page.
+    pub cursor: Option<Cursor>,
+    /// Maximum number of items in the page
+    pub limit: usize,
+}
+
+impl PageRequest {
+    /// Requests the first page
+    pub fn first(limit: usize) -> Self {
+        Self {
+            cursor: None,
+            limit,
+        }
+    }
+
+    fn range(&self) -> Result<(usize, usize), Error> {
+        if self.limit == 0 {
+            return Err(Error::InvalidArgument);
+        }
+        let offset = match &self.cursor {
+            Some(cursor) => cursor.offset()?,
+            None => 0,
+        };
+        Ok((offset, self.limit))
+    }
+}
+
+/// A page of a result set
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Page<T> {
+    /// Items in the page
+    pub items: Vec<T>,
+    /// Cursor to the next page. `None` if this is the last page.
+    pub next: Option<Cursor>,
+}
+
+impl<T> Page<T> {
+    // one more item than the limit is taken to find out whether there is a next page
+    fn cut(mut items: Vec<T>, offset: usize, limit: usize) -> Self {
+        let next = if items.len() > limit {
+            items.truncate(limit);
+            Some(Cursor::at(offset + limit))
+        } else {
+            None
+        };
+        Self { items, next }
+    }
+}
+
+/// Returns the page of `items` requested by `request`
+///
+/// Returns `Error::InvalidArgument` if the limit is zero or the cursor is invalid.
+pub fn paginate<I>(items: I, request: &PageRequest) -> Result<Page<I::Item>, Error>
+where
+    I: IntoIterator,
+{
+    let (offset, limit) = request.range()?;
+    let items = items.into_iter().skip(offset).take(limit + 1).collect();
+    Ok(Page::cut(items, offset, limit))
+}
+
+/// Returns the page of a stream of items requested by `request`
+///
+/// Returns `Error::InvalidArgument` if the limit is zero or the cursor is invalid.
+pub async fn paginate_stream<S>(items: S, request: &PageRequest) -> Result<Page<S::Item>, Error>
+where
+    S: Stream,
+{
+    let (offset, limit) = request.range()?;
+    let items = items.skip(offset).take(limit + 1).collect().await;
+    Ok(Page::cut(items, offset, limit))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pages() {
+        let request = PageRequest::first(2);
+        let first = paginate(0..5, &request).unwrap();
+        assert_eq!(first.items, vec![0, 1]);
+
+        let request = PageRequest {
+            cursor: first.next,
+            limit: 3,
+        };
+        let last = paginate(0..5, &request).unwrap();
+        assert_eq!(last.items, vec![2, 3, 4]);
+        assert_eq!(last.next, None);
+
+        // a page that ends with the result set
+        let page = futures::executor::block_on(paginate_stream(
+            futures::stream::iter(0..4),
+            &PageRequest::first(2),
+        ))
+        .unwrap();
+        let request = PageRequest {
+            cursor: page.next,
+            limit: 2,
+        };
+        assert_eq!(paginate(0..4, &request).unwrap().next, None);
+
+        assert!(paginate(0..4, &PageRequest::first(0)).is_err());
+        let request = PageRequest {
+            cursor: Some(Cursor("invalid".into())),
+            limit: 2,
+        };
+        assert!(paginate(0..4, &request).is_err());
+    }
+}
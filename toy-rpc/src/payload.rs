@@ -0,0 +1,254 @@
+//! Size accounting and limits of the payloads of RPC calls
+//!
+//! The server and the client count the bytes of the serialized arguments and
+//! responses of every method, which can be read with `Server::payload_stats` and
+//! `Client::payload_stats`, so that a regression in the size of the payloads is
+//! noticed before it becomes a problem.
+//!
+//! A `SizeLimit` can be set on a whole service with `"Service"` or on a single method
+//! with `"Service.method"`. The limit of a method takes precedence over the limit of
+//! its service. A call that exceeds a limit fails with `Error::PayloadTooLarge`.
+//!
+//! - On the server, set with `ServerBuilder::size_limit`, a request that is too large
+//!   is not executed and a response that is too large is replaced by the error.
+//! - On the client, set with `Client::set_size_limit`, a request that is too large is
+//!   not sent and a response that is too large is discarded.
+//!
+//! The size of the items of a streaming response counts towards the response size
+//! of the method, and the limit applies to each item.
+//!
+//! # Example
+//!
+//! ```no_run
+//! # #[cfg(all(feature = "server", feature = "client", feature = "tokio_runtime", not(feature = "async_std_runtime")))]
+//! # {
+//! # use std::sync::Arc;
+//! # use toy_rpc::{Client, Server};
+//! # use toy_rpc::macros::export_impl;
+//! # struct Example;
+//! # #[export_impl]
+//! # impl Example {
+//! #     #[export_method]
+//! #     async fn echo(&self, args: String) -> Result<String, String> {
+//! #         Ok(args)
+//! #     }
+//! # }
+//! # use toy_rpc::payload::SizeLimit;
+//! # let example_service = Arc::new(Example);
+//! let server = Server::builder()
+//!     .register(example_service)
+//!     .size_limit("Example", SizeLimit::default().max_request(64 * 1024))
+//!     .size_limit("Example.upload", SizeLimit::default().max_request(16 * 1024 * 1024))
+//!     .build();
+//!
+//! for (service_method, stats) in server.payload_stats() {
+//!     println!("{}: {} calls, {} bytes in", service_method, stats.calls, stats.request_bytes);
+//! }
+//! # }
+//! ```
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::error::Error;
+
+/// Maximum sizes in bytes of the serialized argument and response of a method
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SizeLimit {
+    /// Maximum size of the serialized argument. There is no limit if `None`.
+    pub max_request: Option<usize>,
+    /// Maximum size of the serialized response. There is no limit if `None`.
+    pub max_response: Option<usize>,
+}
+
+impl SizeLimit {
+    /// Sets the maximum size of the serialized argument
+    pub fn max_request(self, bytes: usize) -> Self {
+        let mut limit = self;
+        limit.max_request = Some(bytes);
+        limit
+    }
+
+    /// Sets the maximum size of the serialized response
+    pub fn max_response(self, bytes: usize) -> Self {
+        let mut limit = self;
+        limit.max_response = Some(bytes);
+        limit
+    }
+}
+
+/// Numbers of calls and bytes of the payloads of a method
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PayloadStats {
+    /// Number of requests
+    pub calls: u64,
+    /// Total size of the serialized arguments
+    pub request_bytes: u64,
+    /// Total size of the serialized responses
+    pub response_bytes: u64,
+    /// Size of the largest serialized argument
+    pub largest_request: usize,
+    /// Size of the largest serialized response
+    pub largest_response: usize,
+}
+
+/// Size limits keyed by `"Service"` or `"Service.method"`
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SizeLimits {
+    limits: HashMap<String, SizeLimit>,
+}
+
+#[cfg_attr(
+    all(not(feature = "tokio_runtime"), not(feature = "async_std_runtime")),
+    allow(dead_code)
+)]
+impl SizeLimits {
+    pub fn insert(&mut self, service_method: String, limit: SizeLimit) {
+        self.limits.insert(service_method, limit);
+    }
+
+    /// Returns the limit of the method, or of its service if the method has none
+    pub fn get(&self, service_method: &str) -> SizeLimit {
+        if let Some(limit) = self.limits.get(service_method) {
+            return *limit;
+        }
+        service_method
+            .split_once('.')
+            .and_then(|(service, _)| self.limits.get(service))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Checks the size of a serialized argument
+    pub fn check_request(&self, service_method: &str, bytes: usize) -> Result<(), Error> {
+        Self::check(
+            "request",
+            service_method,
+            bytes,
+            self.get(service_method).max_request,
+        )
+    }
+
+    /// Checks the size of a serialized response
+    pub fn check_response(&self, service_method: &str, bytes: usize) -> Result<(), Error> {
+        Self::check(
+            "response",
+            service_method,
+            bytes,
+            self.get(service_method).max_response,
+        )
+    }
+
+    fn check(
+        kind: &str,
+        service_method: &str,
+        bytes: usize,
+        max: Option<usize>,
+    ) -> Result<(), Error> {
+        match max {
+            Some(max) if bytes > max => Err(Error::PayloadTooLarge(format!(
+                "{} of {} is {} bytes, the limit is {} bytes",
+                kind, service_method, bytes, max
+            ))),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Size limits and payload sizes of every method, shared by all the connections
+#[cfg(feature = "server")]
+#[derive(Debug, Default)]
+#[cfg_attr(
+    all(not(feature = "tokio_runtime"), not(feature = "async_std_runtime")),
+    allow(dead_code)
+)]
+pub(crate) struct PayloadAccounting {
+    pub limits: SizeLimits,
+    pub counters: PayloadCounters,
+}
+
+/// Payload sizes of every method
+#[derive(Debug, Default)]
+pub(crate) struct PayloadCounters {
+    stats: Mutex<HashMap<String, PayloadStats>>,
+}
+
+#[cfg_attr(
+    all(not(feature = "tokio_runtime"), not(feature = "async_std_runtime")),
+    allow(dead_code)
+)]
+impl PayloadCounters {
+    fn update(&self, service_method: &str, f: impl FnOnce(&mut PayloadStats)) {
+        let mut stats = match self.stats.lock() {
+            Ok(stats) => stats,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        match stats.get_mut(service_method) {
+            Some(entry) => f(entry),
+            None => f(stats.entry(service_method.to_string()).or_default()),
+        }
+    }
+
+    pub fn record_request(&self, service_method: &str, bytes: usize) {
+        self.update(service_method, |stats| {
+            stats.calls += 1;
+            stats.request_bytes += bytes as u64;
+            stats.largest_request = stats.largest_request.max(bytes);
+        })
+    }
+
+    pub fn record_response(&self, service_method: &str, bytes: usize) {
+        self.update(service_method, |stats| {
+            stats.response_bytes += bytes as u64;
+            stats.largest_response = stats.largest_response.max(bytes);
+        })
+    }
+
+    pub fn snapshot(&self) -> HashMap<String, PayloadStats> {
+        match self.stats.lock() {
+            Ok(stats) => stats.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn method_limit_takes_precedence() {
+        let mut limits = SizeLimits::default();
+        limits.insert("Example".into(), SizeLimit::default().max_request(10));
+        limits.insert(
+            "Example.upload".into(),
+            SizeLimit::default().max_response(20),
+        );
+
+        assert_eq!(limits.get("Example.echo").max_request, Some(10));
+        assert_eq!(limits.get("Example.upload").max_request, None);
+        assert!(limits.check_request("Example.echo", 10).is_ok());
+        assert!(matches!(
+            limits.check_request("Example.echo", 11),
+            Err(Error::PayloadTooLarge(_))
+        ));
+        assert!(limits.check_request("Example.upload", 11).is_ok());
+        assert!(limits.check_response("Example.upload", 21).is_err());
+        assert!(limits.check_request("Other.echo", usize::MAX).is_ok());
+    }
+
+    #[test]
+    fn counts_payloads_per_method() {
+        let counters = PayloadCounters::default();
+        counters.record_request("Example.echo", 3);
+        counters.record_request("Example.echo", 5);
+        counters.record_response("Example.echo", 7);
+
+        let stats = counters.snapshot()["Example.echo"];
+        assert_eq!(stats.calls, 2);
+        assert_eq!(stats.request_bytes, 8);
+        assert_eq!(stats.largest_request, 5);
+        assert_eq!(stats.response_bytes, 7);
+        assert_eq!(stats.largest_response, 7);
+    }
+}
@@ -1,5 +1,28 @@
 //! PubSub support
-use serde::{de::DeserializeOwned, Serialize};
+//!
+//! Publishers on both the client and the server side can include a type tag with
+//! every item by calling `with_type_tag`. A subscriber checks the tag against its
+//! own item type and yields `Error::TopicTypeMismatch` instead of a confusing
+//! deserialization error, or a wrong item, when the publisher and the subscriber
+//! disagree about the item type of the topic. Items without a tag are not checked.
+//!
+//! A client can also subscribe to a topic pattern with `Client::subscriber_pattern`.
+//! Topic names are split into `/`-separated segments. A `*` in a segment of the
+//! pattern matches any characters within that segment, and a last segment of `**`
+//! matches all the remaining segments. For example `sensor/*` matches
+//! `sensor/kitchen` but not `sensor/kitchen/temp`, which is matched by `sensor/**`.
+//! Topic names containing `*` are reserved for patterns.
+//!
+//! The server acknowledges a publication that is published with `publish_with_ack`
+//! with a `PublishReport` of its delivery, which the publisher collects in its
+//! `PublisherStats` (see `client::pubsub::Publisher::with_stats`).
+//!
+//! A message that the server fails to deliver to a subscriber is published to the
+//! dead letter topic of its topic, if there is one, wrapped in a `DeadLetter`.
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::time::SystemTime;
+
+use crate::Error;
 
 /// Trait for PubSub Topic
 pub trait Topic {
@@ -9,3 +32,179 @@ pub trait Topic {
     /// Name of the topic
     fn topic() -> String;
 }
+
+/// Outcome of the delivery of a publication on the server, which is the body of the
+/// `Ack` of a publication that the publisher waits for
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PublishReport {
+    /// Number of subscribers of the topic and of the patterns that match it that
+    /// the publication is delivered to
+    pub subscribers: u32,
+    /// Number of subscribers whose buffer is full and that dropped the publication,
+    /// or their oldest item, according to the `Backpressure` of the topic
+    pub dropped: u32,
+}
+
+/// Reason of a failed delivery of a publication to a subscriber
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DeliveryFailure {
+    /// The buffer of the subscriber is full and the message is dropped
+    Full,
+    /// The subscriber is disconnected
+    Disconnected,
+    /// The subscriber did not acknowledge the message within the maximum number of
+    /// delivery attempts
+    Unacknowledged,
+}
+
+/// A message that the server failed to deliver to a subscriber, which is published
+/// to the dead letter topic of the topic of the message
+///
+/// The item type of a dead letter topic is `DeadLetter`. The failed message is kept
+/// as published, and can be unmarshalled with the codec of the server.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeadLetter {
+    /// Topic the message was published to
+    pub topic: String,
+    /// Id of the subscriber that the message failed to be delivered to
+    pub client_id: u64,
+    /// Why the delivery failed
+    pub reason: DeliveryFailure,
+    /// Time of the failed delivery
+    pub failed_at: SystemTime,
+    /// The message marshalled by the codec of the server
+    pub content: Vec<u8>,
+}
+
+/// Type tag of the item type of topic `T`
+///
+/// The tag is the 64-bit FNV-1a hash of the name of `T::Item` as returned by
+/// `std::any::type_name`. The name is not guaranteed to be the same across compiler
+/// versions, so the publishers and subscribers should be built with the same
+/// toolchain when the type tags are used.
+pub fn type_tag<T: Topic>() -> u64 {
+    item_type_tag::<T::Item>()
+}
+
+/// Type tag of item type `V`, which is the type tag of every topic whose item type
+/// is `V`. It is used by the publishers and subscribers of topics named at runtime.
+pub fn item_type_tag<V>() -> u64 {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0100_0000_01b3;
+    std::any::type_name::<V>()
+        .bytes()
+        .fold(OFFSET_BASIS, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(PRIME)
+        })
+}
+
+/// Verifies the type tag of a received item, if there is one, against topic `T`
+#[cfg_attr(not(any(feature = "server", feature = "client")), allow(dead_code))]
+pub(crate) fn check_type_tag<T: Topic>(tag: Option<u64>) -> Result<(), Error> {
+    match tag {
+        Some(tag) if tag != type_tag::<T>() => Err(Error::TopicTypeMismatch(format!(
+            "item on topic {} is not of type {}",
+            T::topic(),
+            std::any::type_name::<T::Item>()
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Verifies the type tag of an item received on `topic`, if there is one, against
+/// item type `V`
+#[cfg_attr(not(feature = "client"), allow(dead_code))]
+pub(crate) fn check_item_type_tag<V>(topic: &str, tag: Option<u64>) -> Result<(), Error> {
+    match tag {
+        Some(tag) if tag != item_type_tag::<V>() => Err(Error::TopicTypeMismatch(format!(
+            "item on topic {} is not of type {}",
+            topic,
+            std::any::type_name::<V>()
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Returns whether a topic name is a pattern
+pub fn is_pattern(topic: &str) -> bool {
+    topic.contains('*')
+}
+
+/// Returns whether a topic matches a pattern. A topic that is not a pattern only
+/// matches itself.
+pub fn matches(pattern: &str, topic: &str) -> bool {
+    let mut segments = topic.split('/');
+    let mut patterns = pattern.split('/').peekable();
+    while let Some(pattern) = patterns.next() {
+        if pattern == "**" && patterns.peek().is_none() {
+            // matches one or more remaining segments
+            return segments.next().is_some();
+        }
+        match segments.next() {
+            Some(segment) if matches_segment(pattern.as_bytes(), segment.as_bytes()) => {}
+            _ => return false,
+        }
+    }
+    segments.next().is_none()
+}
+
+/// Matches a single segment against a glob where `*` matches any characters
+fn matches_segment(pattern: &[u8], segment: &[u8]) -> bool {
+    let (mut p, mut s) = (0, 0);
+    // position of the last `*` in the pattern and the position in the segment it
+    // was matched at, to backtrack to when the rest does not match
+    let mut star = None;
+    while s < segment.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                star = Some((p, s));
+                p += 1;
+            }
+            Some(c) if *c == segment[s] => {
+                p += 1;
+                s += 1;
+            }
+            _ => match star {
+                Some((star_p, star_s)) => {
+                    p = star_p + 1;
+                    s = star_s + 1;
+                    star = Some((star_p, star_s + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == b'*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn topic_patterns() {
+        assert!(matches("sensor/*", "sensor/kitchen"));
+        assert!(!matches("sensor/*", "sensor/kitchen/temp"));
+        assert!(!matches("sensor/*", "sensor"));
+        assert!(!matches("sensor/*", "actuator/kitchen"));
+
+        assert!(matches("sensor/**", "sensor/kitchen"));
+        assert!(matches("sensor/**", "sensor/kitchen/temp"));
+        assert!(!matches("sensor/**", "sensor"));
+
+        assert!(matches("sensor/*/temp", "sensor/kitchen/temp"));
+        assert!(!matches("sensor/*/temp", "sensor/kitchen/humidity"));
+
+        assert!(matches("sensor-*", "sensor-1"));
+        assert!(matches("*-temp", "kitchen-temp"));
+        assert!(matches("s*n*r", "sensor"));
+        assert!(!matches("s*n*r", "sensors"));
+        assert!(matches("*", "sensor"));
+        assert!(!matches("*", "sensor/kitchen"));
+
+        assert!(matches("sensor", "sensor"));
+        assert!(!matches("sensor", "sensor/kitchen"));
+        assert!(is_pattern("sensor/*"));
+        assert!(!is_pattern("sensor"));
+    }
+}
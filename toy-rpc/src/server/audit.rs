@@ -0,0 +1,239 @@
+//! Audit log of RPC calls
+//!
+//! When an `AuditSink` is set with `ServerBuilder::audit_sink`, an `AuditRecord` is
+//! emitted for every RPC call handled by the server, including calls to services or
+//! methods that are not found and calls that are canceled. This is separate from the
+//! debug logging done with the `log` crate.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use toy_rpc::server::audit::FileAuditSink;
+//! # use std::sync::Arc;
+//! # use toy_rpc::Server;
+//! # use toy_rpc::macros::export_impl;
+//! # struct Example;
+//! # #[export_impl]
+//! # impl Example {
+//! #     #[export_method]
+//! #     async fn echo(&self, args: String) -> Result<String, String> {
+//! #         Ok(args)
+//! #     }
+//! # }
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! # let example_service = Arc::new(Example);
+//!
+//! let server = Server::builder()
+//!     .register(example_service)
+//!     .audit_sink(FileAuditSink::open("audit.log")?)
+//!     .build();
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{LineWriter, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::error::Error;
+use crate::message::MessageId;
+
+use super::ClientId;
+
+/// Audit record of a single RPC call
+#[derive(Debug, Clone)]
+pub struct AuditRecord {
+    /// Identity of the caller, which is the ID of the client connection
+    pub client_id: u64,
+    /// Name of the service
+    pub service: String,
+    /// Name of the method
+    pub method: String,
+    /// Digest of the serialized argument. This is a 64-bit FNV-1a hash, which allows
+    /// comparing arguments without storing them but is NOT cryptographically secure.
+    pub args_digest: u64,
+    /// `Ok(())` if the call succeeded, otherwise the error message
+    pub result: Result<(), String>,
+    /// Time at which the request was received
+    pub timestamp: SystemTime,
+    /// Time taken to handle the call
+    pub elapsed: Duration,
+}
+
+/// Destination of the `AuditRecord`s
+///
+/// `record` is called from the tasks handling the connections, so an implementation
+/// should avoid blocking for too long. This is implemented for closures, which can be
+/// used to forward the records elsewhere (eg. to another RPC server), and for
+/// `flume::Sender<AuditRecord>`.
+pub trait AuditSink: Send + Sync + 'static {
+    /// Records a call
+    fn record(&self, record: AuditRecord);
+}
+
+impl<F> AuditSink for F
+where
+    F: Fn(AuditRecord) + Send + Sync + 'static,
+{
+    fn record(&self, record: AuditRecord) {
+        self(record)
+    }
+}
+
+impl AuditSink for flume::Sender<AuditRecord> {
+    fn record(&self, record: AuditRecord) {
+        self.send(record)
+            .unwrap_or_else(|err| error!("Failed to send audit record: {}", err));
+    }
+}
+
+/// `AuditSink` that appends one line per record to a file
+///
+/// Each line contains the timestamp in milliseconds since the UNIX epoch, the client
+/// ID, the service and method, the digest of the argument in hex, the result and the
+/// elapsed time in microseconds, separated by spaces. For example
+///
+/// ```text
+/// 1622505600000 1 Arith.add 9e3779b97f4a7c15 ok 120
+/// 1622505600002 1 Arith.divide 3c6ef372fe94f82b err:"Divided by zero" 85
+/// ```
+#[derive(Debug)]
+pub struct FileAuditSink {
+    file: Mutex<LineWriter<File>>,
+}
+
+impl FileAuditSink {
+    /// Opens the file at `path` for appending, creating it if it does not exist
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            file: Mutex::new(LineWriter::new(file)),
+        })
+    }
+}
+
+impl AuditSink for FileAuditSink {
+    fn record(&self, record: AuditRecord) {
+        let timestamp = record
+            .timestamp
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        let result = match &record.result {
+            Ok(_) => "ok".to_string(),
+            Err(err) => format!("err:{:?}", err),
+        };
+        let mut file = match self.file.lock() {
+            Ok(file) => file,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        writeln!(
+            file,
+            "{} {} {}.{} {:016x} {} {}",
+            timestamp,
+            record.client_id,
+            record.service,
+            record.method,
+            record.args_digest,
+            result,
+            record.elapsed.as_micros()
+        )
+        .unwrap_or_else(|err| error!("Failed to write audit record: {}", err));
+    }
+}
+
+/// 64-bit FNV-1a hash of the serialized argument
+pub(crate) fn digest(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, b| {
+        (hash ^ *b as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+/// Information of a call that is kept until the call finishes
+pub(crate) struct PendingCall {
+    service_method: String,
+    args_digest: u64,
+    timestamp: SystemTime,
+    started: Instant,
+}
+
+impl PendingCall {
+    pub fn new(service_method: &str, args: &[u8]) -> Self {
+        Self {
+            service_method: service_method.to_string(),
+            args_digest: digest(args),
+            timestamp: SystemTime::now(),
+            started: Instant::now(),
+        }
+    }
+}
+
+/// Audit sink and the calls in progress of a single connection
+pub(crate) struct Auditor {
+    sink: Arc<dyn AuditSink>,
+    client_id: ClientId,
+    pending: HashMap<MessageId, PendingCall>,
+}
+
+impl Auditor {
+    pub fn new(sink: Arc<dyn AuditSink>, client_id: ClientId) -> Self {
+        Self {
+            sink,
+            client_id,
+            pending: HashMap::new(),
+        }
+    }
+
+    /// Keeps track of a call until it finishes
+    pub fn start(&mut self, id: MessageId, call: PendingCall) {
+        self.pending.insert(id, call);
+    }
+
+    /// Records a call that has finished. Calls that were not started are ignored.
+    pub fn finish<T>(&mut self, id: MessageId, result: &Result<T, Error>) {
+        if let Some(call) = self.pending.remove(&id) {
+            self.record(call, result)
+        }
+    }
+
+    /// Records a call that finished without being started, eg. if the service
+    /// was not found
+    pub fn record<T>(&self, call: PendingCall, result: &Result<T, Error>) {
+        let (service, method) = match call.service_method.split_once('.') {
+            Some((service, method)) => (service.to_string(), method.to_string()),
+            None => (call.service_method, String::new()),
+        };
+        self.sink.record(AuditRecord {
+            client_id: self.client_id,
+            service,
+            method,
+            args_digest: call.args_digest,
+            result: result.as_ref().map(|_| ()).map_err(|err| err.to_string()),
+            timestamp: call.timestamp,
+            elapsed: call.started.elapsed(),
+        })
+    }
+
+    /// Records all calls in progress as canceled
+    pub fn cancel_all(&mut self) {
+        let pending: Vec<_> = self.pending.drain().collect();
+        for (id, call) in pending {
+            self.record::<()>(call, &Err(Error::Canceled(Some(id))));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fnv1a_digest() {
+        assert_eq!(digest(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(digest(b"a"), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(digest(b"foobar"), 0x8594_4171_f739_67e8);
+    }
+}
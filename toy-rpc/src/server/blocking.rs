@@ -0,0 +1,166 @@
+//! Execution of blocking handlers
+//!
+//! The methods exported with `#[export_method(blocking)]` are executed on the
+//! blocking thread pool of the runtime with `spawn_blocking`, so that a CPU-heavy or
+//! blocking method (eg. image processing or compression) does not stall the executor
+//! that drives all the other connections. `ServerBuilder::blocking_pool` limits the
+//! number of blocking methods executed at the same time. The calls over the limit
+//! wait for a thread to become available, which counts as time queued rather than
+//! executing (see the `timing` module).
+//!
+//! A blocking method keeps running until it returns even if its call times out or
+//! is canceled, since a blocking thread cannot be interrupted.
+//!
+//! # Example
+//!
+//! ```no_run
+//! # use toy_rpc::{Error, Server};
+//! # use toy_rpc::macros::export_impl;
+//! # struct Imaging;
+//! # impl Imaging {
+//! #     fn new() -> Self {
+//! #         Imaging
+//! #     }
+//! # }
+//! # fn resize(image: &[u8], width: u32, height: u32) -> Result<Vec<u8>, Error> {
+//! #     Ok(image.to_vec())
+//! # }
+//! #[export_impl]
+//! impl Imaging {
+//!     #[export_method(blocking)]
+//!     async fn thumbnail(&self, image: Vec<u8>) -> Result<Vec<u8>, Error> {
+//!         resize(&image, 64, 64)
+//!     }
+//! }
+//!
+//! let server = Server::builder()
+//!     .register(Imaging::new())
+//!     .blocking_pool(4)
+//!     .build();
+//! ```
+
+use erased_serde as erased;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use crate::metadata::Context;
+use crate::service::{ArcAsyncServiceCall, AsyncServiceMap, HandlerResultFut};
+
+#[cfg(all(feature = "async_std_runtime", not(feature = "tokio_runtime")))]
+use ::async_std::task;
+#[cfg(all(feature = "tokio_runtime", not(feature = "async_std_runtime")))]
+use ::tokio::task;
+
+/// Limits the number of blocking methods executed at the same time
+#[derive(Clone)]
+struct Slots {
+    taken: flume::Sender<()>,
+    released: flume::Receiver<()>,
+}
+
+impl Slots {
+    fn new(threads: usize) -> Self {
+        let (taken, released) = flume::bounded(threads);
+        Self { taken, released }
+    }
+}
+
+/// Releases a slot when the blocking method returns
+struct Slot(Option<Slots>);
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        if let Some(slots) = &self.0 {
+            let _ = slots.released.try_recv();
+        }
+    }
+}
+
+/// Wraps the services whose methods are keyed by `"Service.method"` so that the
+/// methods are executed on the blocking thread pool
+pub(crate) fn apply(
+    services: &mut AsyncServiceMap,
+    methods: &HashSet<String>,
+    threads: Option<usize>,
+) {
+    let slots = threads.map(|threads| Slots::new(threads.max(1)));
+    let mut by_service: HashMap<&str, HashSet<String>> = HashMap::new();
+    for name in methods {
+        if let Some((service, method)) = name.split_once('.') {
+            by_service
+                .entry(service)
+                .or_default()
+                .insert(method.to_string());
+        }
+    }
+
+    for (service, methods) in by_service {
+        if let Some(call) = services.get_mut(service) {
+            let inner = call.clone();
+            *call = with_blocking(inner, methods, slots.clone());
+        }
+    }
+}
+
+fn with_blocking(
+    inner: ArcAsyncServiceCall,
+    methods: HashSet<String>,
+    slots: Option<Slots>,
+) -> ArcAsyncServiceCall {
+    let call = move |method_name: String,
+                     deserializer: Box<dyn erased::Deserializer<'static> + Send>|
+          -> HandlerResultFut {
+        let blocking = methods.contains(&method_name);
+        let fut = inner(method_name, deserializer);
+        match blocking {
+            true => spawn_blocking(fut, slots.clone(), Context::current()),
+            false => fut,
+        }
+    };
+    Arc::new(call)
+}
+
+/// Executes the handler on a thread of the blocking pool of the runtime. The call
+/// is queued until the handler starts on the thread.
+fn spawn_blocking(
+    fut: HandlerResultFut,
+    slots: Option<Slots>,
+    context: Context,
+) -> HandlerResultFut {
+    context.stopwatch().defer_start();
+    Box::pin(async move {
+        if let Some(slots) = &slots {
+            slots.taken.send_async(()).await?;
+        }
+        let slot = Slot(slots);
+
+        #[cfg(all(feature = "tokio_runtime", not(feature = "async_std_runtime")))]
+        let result = {
+            let handle = ::tokio::runtime::Handle::current();
+            task::spawn_blocking(move || {
+                let _slot = slot;
+                context.stopwatch().start();
+                handle.block_on(fut)
+            })
+            .await?
+        };
+        #[cfg(all(feature = "async_std_runtime", not(feature = "tokio_runtime")))]
+        let result = task::spawn_blocking(move || {
+            let _slot = slot;
+            context.stopwatch().start();
+            task::block_on(fut)
+        })
+        .await;
+        #[cfg(not(any(
+            all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+            all(feature = "async_std_runtime", not(feature = "tokio_runtime"))
+        )))]
+        let result = {
+            let _slot = slot;
+            context.stopwatch().start();
+            fut.await
+        };
+
+        result
+    })
+}
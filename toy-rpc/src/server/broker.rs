@@ -3,11 +3,16 @@ use std::future::Future;
 use std::sync::Arc;
 use std::time::Duration;
 
-use crate::protocol::InboundBody;
-use crate::service::{ArcAsyncServiceCall, HandlerResult};
+use crate::metadata::{Context, Metadata};
+use crate::protocol::{InboundBody, OutboundBody};
+use crate::pubsub::PublishReport;
+use crate::service::{ArcAsyncServiceCall, HandlerResult, HandlerResultFut};
+use crate::timing::{ServerTimeout, TimeoutPhase, TimingPolicy, EXECUTED, QUEUED};
 
 use crate::{error::Error, message::MessageId};
 
+use super::audit::PendingCall;
+
 cfg_if::cfg_if! {
     if #[cfg(not(feature = "http_actix_web"))] {
         use std::collections::HashMap;
@@ -15,12 +20,26 @@ cfg_if::cfg_if! {
         use flume::Sender;
         use brw::{Running, Broker};
         use futures::sink::{Sink, SinkExt};
+        use futures::{future, StreamExt};
+        use futures::future::Either;
+
+        use crate::service::Success;
 
         use crate::server::pubsub::PubSubResponder;
 
+        use futures::channel::oneshot;
+
+        use crate::message::AtomicMessageId;
+
         use super::ClientId;
+        use super::audit::Auditor;
+        use super::flow::OutboundQueue;
+        use super::peer::{Peer, PeerCalls, PeerResult};
         use super::pubsub::PubSubItem;
-        use super::writer::ServerWriterItem;
+        use super::shutdown::SHUTDOWN_REASON;
+        use super::writer::{metadata_item, ServerWriterItem};
+        #[cfg(feature = "metrics")]
+        use super::metrics::{CallMeter, MetricsRegistry};
     }
 }
 
@@ -40,17 +59,91 @@ use ::tokio::task::JoinHandle;
 pub(crate) struct ServerBroker {
     pub client_id: ClientId,
     pub executions: HashMap<MessageId, JoinHandle<()>>,
+    // service method of the calls in flight, for the payload accounting of the responses
+    pub methods: HashMap<MessageId, String>,
+    // context of the calls in flight, which collects the metadata of the responses
+    pub contexts: HashMap<MessageId, Context>,
     pub pubsub_broker: Sender<PubSubItem>,
+    pub auditor: Option<Auditor>,
+    // whether the server is shutting down
+    pub closing: bool,
+    pub outbound: Arc<OutboundQueue>,
+    // calls to the services of the client
+    pub peer_calls: PeerCalls,
+    pub peer_count: Arc<AtomicMessageId>,
+    // limits of the phases of the calls
+    pub timing: TimingPolicy,
+    #[cfg(feature = "metrics")]
+    pub meter: Option<CallMeter>,
 }
 
 #[cfg(not(feature = "http_actix_web"))]
 impl ServerBroker {
-    pub fn new(client_id: ClientId, pubsub_broker: Sender<PubSubItem>) -> Self {
+    pub fn new(
+        client_id: ClientId,
+        pubsub_broker: Sender<PubSubItem>,
+        auditor: Option<Auditor>,
+        outbound: Arc<OutboundQueue>,
+        timing: TimingPolicy,
+    ) -> Self {
         Self {
             client_id,
             executions: HashMap::new(),
+            methods: HashMap::new(),
+            contexts: HashMap::new(),
             pubsub_broker,
+            auditor,
+            closing: false,
+            outbound,
+            peer_calls: PeerCalls::default(),
+            peer_count: Arc::new(AtomicMessageId::new(0)),
+            timing,
+            #[cfg(feature = "metrics")]
+            meter: None,
+        }
+    }
+
+    /// Records the calls in `metrics`
+    #[cfg(feature = "metrics")]
+    pub fn record_metrics(self, metrics: Arc<MetricsRegistry>) -> Self {
+        let mut broker = self;
+        broker.meter = Some(CallMeter::new(metrics));
+        broker
+    }
+
+    /// Stops the calls in flight
+    async fn stop_executions(&mut self) {
+        for (_, handle) in self.executions.drain() {
+            debug!("Stopping execution as the connection is closed");
+            #[cfg(all(feature = "tokio_runtime", not(feature = "async_std_runtime")))]
+            handle.abort();
+            #[cfg(all(feature = "async_std_runtime", not(feature = "tokio_runtime")))]
+            handle.cancel().await;
+        }
+        self.methods.clear();
+        self.contexts.clear();
+        self.peer_calls.clear();
+        if let Some(auditor) = &mut self.auditor {
+            auditor.cancel_all();
+        }
+        #[cfg(feature = "metrics")]
+        if let Some(meter) = &mut self.meter {
+            meter.cancel_all();
+        }
+    }
+
+    /// Stops the calls in flight and closes the connection because the server is
+    /// shutting down
+    async fn close<W>(&mut self, writer: &mut W) -> Running<Result<(), Error>>
+    where
+        W: Sink<ServerWriterItem, Error = flume::SendError<ServerWriterItem>> + Send + Unpin,
+    {
+        self.stop_executions().await;
+        if let Err(err) = writer.send(ServerWriterItem::Close).await {
+            error!("{}", err);
         }
+        debug!("Client connection is closed for shutdown");
+        Running::Stop
     }
 }
 
@@ -60,25 +153,45 @@ pub(crate) enum ServerBrokerItem {
     Request {
         call: ArcAsyncServiceCall,
         id: MessageId,
+        service_method: String,
         method: String,
         duration: Duration,
         deserializer: Box<InboundBody>,
+        metadata: Metadata,
+        // Information for the audit log if enabled
+        audit: Option<PendingCall>,
     },
     Response {
         id: MessageId,
         result: HandlerResult,
     },
+    // An item of a streaming response
+    StreamItem {
+        id: MessageId,
+        result: Result<Box<OutboundBody>, Error>,
+    },
+    // The end of a streaming response
+    StreamEnd(MessageId),
     Cancel(MessageId),
     // A new publish from the client publisher
     Publish {
         id: MessageId,
         topic: String,
         content: Vec<u8>,
+        tag: Option<u64>,
+        // whether the publisher waits for the publication to be accepted
+        ack: bool,
     },
+    // The client subscriber acknowledged a publication
+    Ack(MessageId),
+    // The PubSubBroker accepted a publication that the publisher waits for
+    Accepted(MessageId, PublishReport),
     // A new subscribe from the client subscriber
     Subscribe {
         id: MessageId,
         topic: String,
+        // number of past messages to replay
+        replay: Option<usize>,
     },
     Unsubscribe {
         id: MessageId,
@@ -89,8 +202,48 @@ pub(crate) enum ServerBrokerItem {
         id: MessageId,
         topic: String,
         content: Arc<Vec<u8>>,
+        tag: Option<u64>,
+        // whether the subscriber must acknowledge the publication
+        ack: bool,
+    },
+    // The PubSubBroker rejected a subscription
+    Rejected {
+        id: MessageId,
+        topic: String,
+        error: Error,
+    },
+    // A reply to a protocol extension message. The session actor of the actix-web
+    // integration replies to the extension messages by itself.
+    #[cfg_attr(feature = "http_actix_web", allow(dead_code))]
+    Ext {
+        id: MessageId,
+        marker: u32,
+        content: String,
     },
+    // The server is shutting down, close the connection once the calls in flight
+    // are finished or the grace period is over
+    Shutdown(Duration),
+    // The grace period of a shutdown is over
+    Close,
     Stop,
+    // A call to a service of the client
+    #[cfg(not(feature = "http_actix_web"))]
+    PeerRequest {
+        id: MessageId,
+        service_method: String,
+        duration: Duration,
+        body: Box<OutboundBody>,
+        reply: oneshot::Sender<PeerResult>,
+    },
+    // The response of the client to a call to its services
+    #[cfg(not(feature = "http_actix_web"))]
+    PeerResponse {
+        id: MessageId,
+        result: PeerResult,
+    },
+    // A call to a service of the client has timed out
+    #[cfg(not(feature = "http_actix_web"))]
+    PeerCancel(MessageId),
 }
 
 #[cfg(not(feature = "http_actix_web"))]
@@ -105,29 +258,117 @@ impl Broker for ServerBroker {
         &mut self,
         ctx: &Arc<brw::Context<Self::Item>>,
         item: Self::Item,
-        mut writer: W,
+        writer: W,
     ) -> Running<Result<Self::Ok, Self::Error>>
     where
         W: Sink<Self::WriterItem, Error = flume::SendError<Self::WriterItem>> + Send + Unpin,
     {
+        let outbound = self.outbound.clone();
+        let mut writer = writer.with(move |item| {
+            outbound.push();
+            future::ready(Ok::<_, flume::SendError<Self::WriterItem>>(item))
+        });
         match item {
             ServerBrokerItem::Request {
                 call,
                 id,
+                service_method,
                 method,
                 duration,
                 deserializer,
+                metadata,
+                audit,
             } => {
-                let fut = call(method, deserializer);
+                if self.closing {
+                    let result = Err(Error::ExecutionError(SHUTDOWN_REASON.into()));
+                    if let (Some(auditor), Some(audit)) = (&self.auditor, audit) {
+                        auditor.record(audit, &result);
+                    }
+                    #[cfg(feature = "metrics")]
+                    if let Some(meter) = &mut self.meter {
+                        meter.record(id, &service_method, &result);
+                    }
+                    let msg = ServerWriterItem::Response {
+                        id,
+                        result,
+                        service_method: Some(service_method),
+                    };
+                    return Running::Continue(writer.send(msg).await.map_err(|err| err.into()));
+                }
+                if let (Some(auditor), Some(audit)) = (&mut self.auditor, audit) {
+                    auditor.start(id, audit);
+                }
+                #[cfg(feature = "metrics")]
+                if let Some(meter) = &mut self.meter {
+                    meter.start(id, &service_method);
+                }
+                let peer = Peer::new(self.client_id, ctx.broker.clone(), self.peer_count.clone());
+                let context = Context::with_peer(id, service_method.clone(), metadata, peer);
+                #[cfg(all(feature = "codec_negotiation", not(feature = "serde_json")))]
+                crate::codec::negotiate::accept(&context);
+                let fut = instrument_call(
+                    self.client_id,
+                    id,
+                    &service_method,
+                    start_call(&call, method, deserializer, &context),
+                );
                 let _broker = ctx.broker.clone();
-                let handle = handle_request(_broker, duration, id, fut);
+                let handle = handle_request(_broker, duration, self.timing, &context, id, fut);
                 self.executions.insert(id, handle);
+                self.methods.insert(id, service_method);
+                self.contexts.insert(id, context);
                 Running::Continue(Ok(()))
             }
             ServerBrokerItem::Response { id, result } => {
                 self.executions.remove(&id);
-                let msg = ServerWriterItem::Response { id, result };
+                if let Some(auditor) = &mut self.auditor {
+                    auditor.finish(id, &result);
+                }
+                #[cfg(feature = "metrics")]
+                if let Some(meter) = &mut self.meter {
+                    meter.finish(id, &result);
+                }
+                let service_method = self.methods.remove(&id);
+                let mut res: Result<(), Error> = Ok(());
+                if let Some(msg) = metadata_item(id, self.contexts.remove(&id)) {
+                    res = writer.send(msg).await.map_err(|err| err.into());
+                }
+                let msg = ServerWriterItem::Response {
+                    id,
+                    result,
+                    service_method,
+                };
+                let res = res.and(writer.send(msg).await.map_err(|err| err.into()));
+                if self.closing && self.executions.is_empty() {
+                    return self.close(&mut writer).await;
+                }
+                Running::Continue(res)
+            }
+            ServerBrokerItem::StreamItem { id, result } => {
+                let service_method = self.methods.get(&id).cloned();
+                let msg = ServerWriterItem::StreamItem {
+                    id,
+                    result,
+                    service_method,
+                };
+                Running::Continue(writer.send(msg).await.map_err(|err| err.into()))
+            }
+            ServerBrokerItem::StreamEnd(id) => {
+                self.executions.remove(&id);
+                self.methods.remove(&id);
+                self.contexts.remove(&id);
+                if let Some(auditor) = &mut self.auditor {
+                    auditor.finish::<()>(id, &Ok(()));
+                }
+                #[cfg(feature = "metrics")]
+                if let Some(meter) = &mut self.meter {
+                    meter.finish::<()>(id, &Ok(()));
+                }
+                let msg = ServerWriterItem::StreamEnd(id);
                 let res: Result<(), Error> = writer.send(msg).await.map_err(|err| err.into());
+                if self.closing && self.executions.is_empty() {
+                    return self.close(&mut writer).await;
+                }
                 Running::Continue(res)
             }
             ServerBrokerItem::Cancel(id) => {
@@ -137,16 +378,34 @@ impl Broker for ServerBroker {
                     #[cfg(all(feature = "async_std_runtime", not(feature = "tokio_runtime")))]
                     handle.cancel().await;
                 }
+                self.methods.remove(&id);
+                self.contexts.remove(&id);
+                if let Some(auditor) = &mut self.auditor {
+                    auditor.finish::<()>(id, &Err(Error::Canceled(Some(id))));
+                }
+                #[cfg(feature = "metrics")]
+                if let Some(meter) = &mut self.meter {
+                    meter.finish::<()>(id, &Err(Error::Canceled(Some(id))));
+                }
 
                 Running::Continue(Ok(()))
             }
-            ServerBrokerItem::Publish { id, topic, content } => {
+            ServerBrokerItem::Publish {
+                id,
+                topic,
+                content,
+                tag,
+                ack,
+            } => {
                 // Publish is the PubSub message from client to server
                 let content = Arc::new(content);
                 let msg = PubSubItem::Publish {
                     msg_id: id,
                     topic,
                     content,
+                    tag,
+                    publisher: Some(PubSubResponder::Sender(ctx.broker.clone())),
+                    ack,
                 };
                 Running::Continue(
                     self.pubsub_broker
@@ -155,13 +414,31 @@ impl Broker for ServerBroker {
                         .map_err(|err| err.into()),
                 )
             }
-            ServerBrokerItem::Subscribe { id, topic } => {
-                log::debug!("Message ID: {}, Subscribe to topic: {}", &id, &topic);
+            ServerBrokerItem::Ack(id) => {
+                let msg = PubSubItem::Ack {
+                    client_id: self.client_id,
+                    msg_id: id,
+                };
+                Running::Continue(
+                    self.pubsub_broker
+                        .send_async(msg)
+                        .await
+                        .map_err(|err| err.into()),
+                )
+            }
+            ServerBrokerItem::Accepted(id, report) => {
+                let msg = ServerWriterItem::Ack(id, report);
+                Running::Continue(writer.send(msg).await.map_err(|err| err.into()))
+            }
+            ServerBrokerItem::Subscribe { id, topic, replay } => {
+                debug!("Message ID: {}, Subscribe to topic: {}", &id, &topic);
                 let sender = PubSubResponder::Sender(ctx.broker.clone());
                 let msg = PubSubItem::Subscribe {
                     client_id: self.client_id,
+                    msg_id: id,
                     topic,
                     sender,
+                    replay,
                 };
                 Running::Continue(
                     self.pubsub_broker
@@ -171,7 +448,7 @@ impl Broker for ServerBroker {
                 )
             }
             ServerBrokerItem::Unsubscribe { id, topic } => {
-                log::debug!("Message ID: {}, Unsubscribe from topic: {}", &id, &topic);
+                debug!("Message ID: {}, Unsubscribe from topic: {}", &id, &topic);
                 let msg = PubSubItem::Unsubscribe {
                     client_id: self.client_id,
                     topic,
@@ -183,20 +460,82 @@ impl Broker for ServerBroker {
                         .map_err(|err| err.into()),
                 )
             }
-            ServerBrokerItem::Publication { id, topic, content } => {
+            ServerBrokerItem::Publication {
+                id,
+                topic,
+                content,
+                tag,
+                ack,
+            } => {
                 // Publication is the PubSub message from server to client
-                let msg = ServerWriterItem::Publication { id, topic, content };
+                let msg = ServerWriterItem::Publication {
+                    id,
+                    topic,
+                    content,
+                    tag,
+                    ack,
+                };
                 Running::Continue(writer.send(msg).await.map_err(|err| err.into()))
             }
-            ServerBrokerItem::Stop => {
-                for (_, handle) in self.executions.drain() {
-                    log::debug!("Stopping execution as client is disconnected");
-                    #[cfg(all(feature = "tokio_runtime", not(feature = "async_std_runtime")))]
-                    handle.abort();
-                    #[cfg(all(feature = "async_std_runtime", not(feature = "tokio_runtime")))]
-                    handle.cancel().await;
+            ServerBrokerItem::Rejected { id, topic, error } => {
+                let msg = ServerWriterItem::Rejected { id, topic, error };
+                Running::Continue(writer.send(msg).await.map_err(|err| err.into()))
+            }
+            ServerBrokerItem::Ext {
+                id,
+                marker,
+                content,
+            } => {
+                let msg = ServerWriterItem::Ext {
+                    id,
+                    marker,
+                    content,
+                };
+                Running::Continue(writer.send(msg).await.map_err(|err| err.into()))
+            }
+            ServerBrokerItem::Shutdown(grace) => {
+                debug!(
+                    "Shutting down with {} calls in flight",
+                    self.executions.len()
+                );
+                self.closing = true;
+                if self.executions.is_empty() {
+                    return self.close(&mut writer).await;
                 }
-                log::debug!("Client connection is closed");
+                close_after(ctx.broker.clone(), grace);
+                Running::Continue(Ok(()))
+            }
+            ServerBrokerItem::PeerRequest {
+                id,
+                service_method,
+                duration,
+                body,
+                reply,
+            } => {
+                self.peer_calls.insert(id, reply);
+                let msg = ServerWriterItem::Request {
+                    id,
+                    service_method,
+                    duration,
+                    body,
+                };
+                Running::Continue(writer.send(msg).await.map_err(|err| err.into()))
+            }
+            ServerBrokerItem::PeerResponse { id, result } => {
+                self.peer_calls.respond(id, result);
+                Running::Continue(Ok(()))
+            }
+            ServerBrokerItem::PeerCancel(id) => {
+                if !self.peer_calls.remove(id) {
+                    return Running::Continue(Ok(()));
+                }
+                let msg = ServerWriterItem::Cancel(id);
+                Running::Continue(writer.send(msg).await.map_err(|err| err.into()))
+            }
+            ServerBrokerItem::Close => self.close(&mut writer).await,
+            ServerBrokerItem::Stop => {
+                self.stop_executions().await;
+                debug!("Client connection is closed");
                 Running::Stop
             }
         }
@@ -208,15 +547,15 @@ impl Broker for ServerBroker {
 fn handle_request(
     broker: Sender<ServerBrokerItem>,
     duration: Duration,
+    timing: TimingPolicy,
+    context: &Context,
     id: MessageId,
     fut: impl Future<Output = HandlerResult> + Send + 'static,
 ) -> ::async_std::task::JoinHandle<()> {
+    let context = context.clone();
     ::async_std::task::spawn(async move {
-        let result = execute_timed_call(id, duration, fut).await;
-        broker
-            .send_async(ServerBrokerItem::Response { id, result })
-            .await
-            .unwrap_or_else(|e| log::error!("{}", e));
+        let result = execute_timed_call(id, duration, timing, &context, fut).await;
+        respond(broker, id, result).await;
     })
 }
 
@@ -229,32 +568,117 @@ fn handle_request(
 fn handle_request(
     broker: Sender<ServerBrokerItem>,
     duration: Duration,
+    timing: TimingPolicy,
+    context: &Context,
     id: MessageId,
     fut: impl Future<Output = HandlerResult> + Send + 'static,
 ) -> ::tokio::task::JoinHandle<()> {
+    let context = context.clone();
     ::tokio::task::spawn(async move {
-        let result = execute_timed_call(id, duration, fut).await;
-        broker
-            .send_async(ServerBrokerItem::Response { id, result })
-            .await
-            .unwrap_or_else(|e| log::error!("{}", e));
+        let result = execute_timed_call(id, duration, timing, &context, fut).await;
+        respond(broker, id, result).await;
     })
 }
 
+/// Sends the result of a call to the broker. The items of a streaming response are
+/// sent one by one as they are produced.
+#[cfg(not(feature = "http_actix_web"))]
+async fn respond(broker: Sender<ServerBrokerItem>, id: MessageId, result: HandlerResult) {
+    let item = match result {
+        Ok(Success::Stream(mut items)) => {
+            while let Some(result) = items.next().await {
+                let item = ServerBrokerItem::StreamItem { id, result };
+                if let Err(err) = broker.send_async(item).await {
+                    error!("{}", err);
+                    return;
+                }
+                // gives a cancellation the chance to abort a stream that is always ready
+                #[cfg(all(feature = "tokio_runtime", not(feature = "async_std_runtime")))]
+                ::tokio::task::yield_now().await;
+                #[cfg(all(feature = "async_std_runtime", not(feature = "tokio_runtime")))]
+                ::async_std::task::yield_now().await;
+            }
+            ServerBrokerItem::StreamEnd(id)
+        }
+        result => ServerBrokerItem::Response { id, result },
+    };
+    broker
+        .send_async(item)
+        .await
+        .unwrap_or_else(|e| error!("{}", e));
+}
+
+/// Sends `Close` to the broker once the grace period of a shutdown is over
+#[cfg(all(feature = "async_std_runtime", not(feature = "tokio_runtime")))]
+fn close_after(broker: Sender<ServerBrokerItem>, grace: Duration) {
+    ::async_std::task::spawn(async move {
+        crate::clock::sleep(grace).await;
+        // the connection may have been closed already
+        let _ = broker.send_async(ServerBrokerItem::Close).await;
+    });
+}
+
+/// Sends `Close` to the broker once the grace period of a shutdown is over
+#[cfg(all(
+    feature = "tokio_runtime",
+    not(feature = "async_std_runtime"),
+    not(feature = "http_actix_web")
+))]
+fn close_after(broker: Sender<ServerBrokerItem>, grace: Duration) {
+    ::tokio::task::spawn(async move {
+        crate::clock::sleep(grace).await;
+        // the connection may have been closed already
+        let _ = broker.send_async(ServerBrokerItem::Close).await;
+    });
+}
+
+/// Instruments the execution of a call with a span if the `tracing` feature is enabled
+#[cfg(feature = "tracing")]
+pub(crate) fn instrument_call<F: Future>(
+    client_id: super::ClientId,
+    id: MessageId,
+    service_method: &str,
+    fut: F,
+) -> tracing::instrument::Instrumented<F> {
+    use tracing::Instrument;
+    fut.instrument(tracing::info_span!("call", client_id, id, service_method))
+}
+
+/// Instruments the execution of a call with a span if the `tracing` feature is enabled
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn instrument_call<F: Future>(
+    _client_id: super::ClientId,
+    _id: MessageId,
+    _service_method: &str,
+    fut: F,
+) -> F {
+    fut
+}
+
+/// Creates the future executing a call, with the context of the call as the current
+/// one so that the handler can take it
+pub(crate) fn start_call(
+    call: &ArcAsyncServiceCall,
+    method: String,
+    deserializer: Box<InboundBody>,
+    context: &Context,
+) -> HandlerResultFut {
+    context.scope(|| call(method, deserializer))
+}
+
 pub(crate) async fn execute_call(
     id: MessageId,
     fut: impl Future<Output = HandlerResult>,
 ) -> HandlerResult {
     let result: HandlerResult = fut.await.map_err(|err| {
-        log::error!(
+        debug!(
             "Error found executing request id: {}, error msg: {}",
-            &id,
-            &err
+            &id, &err
         );
         match err {
             // if serde cannot parse request, the argument is likely mistaken
             Error::ParseError(e) => {
-                log::error!("ParseError {:?}", e);
+                warn!("ParseError {:?}", e);
                 Error::InvalidArgument
             }
             e => e,
@@ -263,21 +687,55 @@ pub(crate) async fn execute_call(
     result
 }
 
+/// Executes a call within the limits of the time it is queued and the time its
+/// handler executes, which are both bounded by the timeout of its request
 #[cfg(not(feature = "http_actix_web"))]
 pub(crate) async fn execute_timed_call(
     id: MessageId,
     duration: Duration,
+    timing: TimingPolicy,
+    context: &Context,
     fut: impl Future<Output = HandlerResult>,
 ) -> HandlerResult {
-    #[cfg(all(feature = "async_std_runtime", not(feature = "tokio_runtime")))]
-    match ::async_std::future::timeout(duration, execute_call(id, fut)).await {
-        Ok(res) => res,
-        Err(_) => Err(Error::Timeout(Some(id))),
-    }
+    let stopwatch = context.stopwatch();
+    stopwatch.start_task();
+    let execution = Box::pin(execute_call(id, fut));
+    // the handler of a blocking method may return before its start is seen
+    let queued = future::select(execution, Box::pin(stopwatch.started()));
+    let result = match crate::clock::timeout(timing.queue_limit(duration), queued).await {
+        Ok(Either::Left((res, _))) => Ok(res),
+        Ok(Either::Right((_, execution))) => {
+            let limit = timing.execution_limit(duration, stopwatch.queued());
+            crate::clock::timeout(limit, execution)
+                .await
+                .map_err(|_| TimeoutPhase::Execution)
+        }
+        Err(_) => Err(TimeoutPhase::Queue),
+    };
+    finish_timed_call(id, timing, context, result)
+}
 
-    #[cfg(all(feature = "tokio_runtime", not(feature = "async_std_runtime"),))]
-    match ::tokio::time::timeout(duration, execute_call(id, fut)).await {
+/// Reports the timing of a call that executed within its limits, or fails it with
+/// the phase it was stopped in
+pub(crate) fn finish_timed_call(
+    id: MessageId,
+    timing: TimingPolicy,
+    context: &Context,
+    result: Result<HandlerResult, TimeoutPhase>,
+) -> HandlerResult {
+    let spent = context.stopwatch().finish();
+    if timing.report {
+        context.set_response_metadata(QUEUED, spent.queued.as_micros());
+        context.set_response_metadata(EXECUTED, spent.executed.as_micros());
+    }
+    match result {
         Ok(res) => res,
-        Err(_) => Err(Error::Timeout(Some(id))),
+        Err(phase) => {
+            debug!("Request id: {} timed out in the {} phase", id, phase);
+            Err(Error::ServerTimeout(ServerTimeout {
+                phase,
+                timing: spent,
+            }))
+        }
     }
 }
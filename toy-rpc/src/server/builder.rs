@@ -1,24 +1,273 @@
 //! Builder of the Server
 
 use erased_serde as erased;
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
 
 #[cfg(any(
     feature = "docs",
     all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
     all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
 ))]
-use super::Server;
+use super::{
+    audit::AuditSink,
+    flow::FlowControl,
+    lifecycle::{ConnectionInfo, LifecycleHooks},
+    pubsub::{Backpressure, Retention},
+    source::TopicSource,
+    store::BrokerStore,
+    topics::TopicRegistry,
+    Server,
+};
+#[cfg(all(
+    feature = "codec_negotiation",
+    not(feature = "serde_json"),
+    any(
+        feature = "docs",
+        all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+        all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+    ),
+))]
+use crate::codec::negotiate::{ContentType, Supported};
+#[cfg(any(
+    feature = "docs",
+    all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+    all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+))]
+use crate::{
+    codec::FrameOptions, compression::Compression, timing::TimingPolicy,
+    transport::bandwidth::BandwidthLimit,
+};
 
+use super::data::{self, DataMap};
+use super::guard::{self, DeserializeLimits};
+use super::naming::NameNormalizer;
+use super::profile::{self, Profiler};
+use super::recovery::{self, Repair};
+use super::schema::{self, BodyPolicy};
 use crate::{
+    capability::{self, Capabilities},
+    extension::ExtensionMap,
+    payload::{SizeLimit, SizeLimits},
     service::{build_service, AsyncServiceMap, HandleService, HandlerResultFut, Service},
-    util::RegisterService,
+    transaction::{self, Transactional},
+    util::{IntoService, RegisterService},
 };
 
 /// Server builder
 pub struct ServerBuilder {
     /// Registered services
     pub services: AsyncServiceMap,
+
+    /// Registered protocol extensions
+    pub extensions: ExtensionMap,
+
+    /// Deserialization policies keyed by `"Service"` or `"Service.method"`
+    pub(crate) body_policies: HashMap<String, BodyPolicy>,
+
+    /// Hooks repairing malformed arguments keyed by `"Service"` or `"Service.method"`
+    pub(crate) repairs: HashMap<String, Arc<dyn Repair>>,
+
+    /// Capabilities keyed by the name of the service
+    pub(crate) capabilities: HashMap<String, Capabilities>,
+
+    /// Payload size limits keyed by `"Service"` or `"Service.method"`
+    pub(crate) size_limits: SizeLimits,
+
+    /// Methods exported with `#[export_method(blocking)]` keyed by `"Service.method"`
+    pub(crate) blocking_methods: HashSet<String>,
+
+    /// Maximum number of blocking methods executed at the same time
+    pub(crate) blocking_threads: Option<usize>,
+
+    /// Limits of the arguments of all methods
+    pub(crate) deserialize_limits: Option<DeserializeLimits>,
+
+    /// Names of the methods of each registered service
+    pub(crate) method_names: HashMap<&'static str, Vec<&'static str>>,
+
+    /// Rules mapping the names of the requests to the registered names
+    pub(crate) name_normalizer: Option<NameNormalizer>,
+
+    /// Callbacks around the execution of the handlers
+    pub(crate) profiler: Option<Arc<dyn Profiler>>,
+
+    /// Shared resources of the handlers, keyed by their type
+    pub(crate) data: DataMap,
+
+    #[cfg(any(
+        feature = "docs",
+        all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+        all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+    ))]
+    pub(crate) store: Option<Box<dyn BrokerStore>>,
+
+    #[cfg(any(
+        feature = "docs",
+        all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+        all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+    ))]
+    pub(crate) audit: Option<Arc<dyn AuditSink>>,
+
+    #[cfg(any(
+        feature = "docs",
+        all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+        all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+    ))]
+    pub(crate) frame: FrameOptions,
+
+    #[cfg(any(
+        feature = "docs",
+        all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+        all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+    ))]
+    pub(crate) topics: TopicRegistry,
+
+    #[cfg(any(
+        feature = "docs",
+        all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+        all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+    ))]
+    pub(crate) topic_retention: HashMap<String, Retention>,
+
+    #[cfg(any(
+        feature = "docs",
+        all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+        all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+    ))]
+    pub(crate) topic_backpressure: HashMap<String, Backpressure>,
+
+    #[cfg(any(
+        feature = "docs",
+        all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+        all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+    ))]
+    pub(crate) topic_sources: HashMap<String, Arc<dyn TopicSource>>,
+
+    #[cfg(any(
+        feature = "docs",
+        all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+        all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+    ))]
+    pub(crate) topic_idle_timeout: Option<std::time::Duration>,
+
+    #[cfg(any(
+        feature = "docs",
+        all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+        all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+    ))]
+    pub(crate) redelivery_timeout: std::time::Duration,
+
+    #[cfg(any(
+        feature = "docs",
+        all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+        all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+    ))]
+    pub(crate) max_delivery_attempts: Option<u32>,
+
+    #[cfg(any(
+        feature = "docs",
+        all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+        all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+    ))]
+    pub(crate) flow_control: Option<FlowControl>,
+
+    #[cfg(any(
+        feature = "docs",
+        all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+        all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+    ))]
+    pub(crate) outbound_bandwidth: Option<BandwidthLimit>,
+
+    #[cfg(any(
+        feature = "docs",
+        all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+        all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+    ))]
+    pub(crate) inbound_bandwidth: Option<BandwidthLimit>,
+
+    #[cfg(any(
+        feature = "docs",
+        all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+        all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+    ))]
+    pub(crate) lifecycle: LifecycleHooks,
+
+    #[cfg(any(
+        feature = "docs",
+        all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+        all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+    ))]
+    pub(crate) timing: TimingPolicy,
+
+    #[cfg(any(
+        feature = "docs",
+        all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+        all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+    ))]
+    pub(crate) overload_threshold: Option<usize>,
+
+    #[cfg(all(
+        feature = "signing",
+        any(
+            feature = "docs",
+            all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+            all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+        ),
+    ))]
+    pub(crate) signer: Option<crate::signing::ResponseSigner>,
+
+    #[cfg(all(
+        feature = "metrics",
+        any(
+            feature = "docs",
+            all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+            all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+        ),
+    ))]
+    pub(crate) export_metrics: bool,
+
+    #[cfg(any(
+        all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+        all(
+            feature = "tokio_runtime",
+            not(feature = "async_std_runtime"),
+            not(feature = "http_actix_web")
+        )
+    ))]
+    pub(crate) proxy_protocol: bool,
+
+    #[cfg(any(
+        all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+        all(
+            feature = "tokio_runtime",
+            not(feature = "async_std_runtime"),
+            not(feature = "http_actix_web")
+        )
+    ))]
+    pub(crate) connection_filter: Option<super::filter::ConnectionFilter>,
+
+    #[cfg(any(
+        all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+        all(
+            feature = "tokio_runtime",
+            not(feature = "async_std_runtime"),
+            not(feature = "http_actix_web")
+        )
+    ))]
+    pub(crate) max_connections: Option<usize>,
+
+    #[cfg(any(
+        all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+        all(
+            feature = "tokio_runtime",
+            not(feature = "async_std_runtime"),
+            not(feature = "http_actix_web")
+        )
+    ))]
+    pub(crate) max_connections_per_ip: Option<usize>,
 }
 
 impl ServerBuilder {
@@ -26,50 +275,519 @@ impl ServerBuilder {
     pub fn new() -> Self {
         ServerBuilder {
             services: HashMap::new(),
+            extensions: HashMap::new(),
+            body_policies: HashMap::new(),
+            repairs: HashMap::new(),
+            capabilities: HashMap::new(),
+            size_limits: SizeLimits::default(),
+            blocking_methods: HashSet::new(),
+            blocking_threads: None,
+            deserialize_limits: None,
+            method_names: HashMap::new(),
+            name_normalizer: None,
+            profiler: None,
+            data: DataMap::default(),
+            #[cfg(any(
+                feature = "docs",
+                all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+                all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+            ))]
+            store: None,
+            #[cfg(any(
+                feature = "docs",
+                all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+                all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+            ))]
+            audit: None,
+            #[cfg(any(
+                feature = "docs",
+                all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+                all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+            ))]
+            frame: FrameOptions::default(),
+            #[cfg(any(
+                feature = "docs",
+                all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+                all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+            ))]
+            topics: TopicRegistry::default(),
+            #[cfg(any(
+                feature = "docs",
+                all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+                all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+            ))]
+            topic_retention: HashMap::new(),
+            #[cfg(any(
+                feature = "docs",
+                all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+                all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+            ))]
+            topic_backpressure: HashMap::new(),
+            #[cfg(any(
+                feature = "docs",
+                all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+                all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+            ))]
+            topic_sources: HashMap::new(),
+            #[cfg(any(
+                feature = "docs",
+                all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+                all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+            ))]
+            topic_idle_timeout: None,
+            #[cfg(any(
+                feature = "docs",
+                all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+                all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+            ))]
+            redelivery_timeout: super::pubsub::DEFAULT_REDELIVERY_TIMEOUT,
+            #[cfg(any(
+                feature = "docs",
+                all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+                all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+            ))]
+            max_delivery_attempts: None,
+            #[cfg(any(
+                feature = "docs",
+                all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+                all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+            ))]
+            flow_control: None,
+            #[cfg(any(
+                feature = "docs",
+                all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+                all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+            ))]
+            outbound_bandwidth: None,
+            #[cfg(any(
+                feature = "docs",
+                all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+                all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+            ))]
+            inbound_bandwidth: None,
+            #[cfg(any(
+                feature = "docs",
+                all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+                all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+            ))]
+            lifecycle: LifecycleHooks::default(),
+            #[cfg(any(
+                feature = "docs",
+                all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+                all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+            ))]
+            timing: TimingPolicy::default(),
+            #[cfg(any(
+                feature = "docs",
+                all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+                all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+            ))]
+            overload_threshold: None,
+            #[cfg(all(
+                feature = "signing",
+                any(
+                    feature = "docs",
+                    all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+                    all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+                ),
+            ))]
+            signer: None,
+            #[cfg(all(
+                feature = "metrics",
+                any(
+                    feature = "docs",
+                    all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+                    all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+                ),
+            ))]
+            export_metrics: false,
+            #[cfg(any(
+                all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+                all(
+                    feature = "tokio_runtime",
+                    not(feature = "async_std_runtime"),
+                    not(feature = "http_actix_web")
+                )
+            ))]
+            proxy_protocol: false,
+            #[cfg(any(
+                all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+                all(
+                    feature = "tokio_runtime",
+                    not(feature = "async_std_runtime"),
+                    not(feature = "http_actix_web")
+                )
+            ))]
+            connection_filter: None,
+            #[cfg(any(
+                all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+                all(
+                    feature = "tokio_runtime",
+                    not(feature = "async_std_runtime"),
+                    not(feature = "http_actix_web")
+                )
+            ))]
+            max_connections: None,
+            #[cfg(any(
+                all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+                all(
+                    feature = "tokio_runtime",
+                    not(feature = "async_std_runtime"),
+                    not(feature = "http_actix_web")
+                )
+            ))]
+            max_connections_per_ip: None,
         }
     }
 
     /// Registers a new service to the `Server` with the default name.
     ///
     /// Internally the `Service` object will be built using the supplied `service`
-    /// , which is the state of the `Service` object
+    /// , which is the state of the `Service` object. The `service` can be passed
+    /// either by value or as an `Arc`.
     ///
     /// # Example
     ///
-    /// ```rust
+    /// ```no_run
+    /// # #[cfg(any(feature = "async_std_runtime", feature = "tokio_runtime"))]
+    /// # {
+    /// # use std::sync::Arc;
+    /// # use toy_rpc::Server;
+    /// # use toy_rpc::macros::export_impl;
+    /// # struct Foo { }
+    /// # #[export_impl]
+    /// # impl Foo {
+    /// #     #[export_method]
+    /// #     async fn echo(&self, args: String) -> Result<String, String> {
+    /// #         Ok(args)
+    /// #     }
+    /// # }
+    /// # struct Bar { }
+    /// # #[export_impl]
+    /// # impl Bar {
+    /// #     #[export_method]
+    /// #     async fn echo(&self, args: String) -> Result<String, String> {
+    /// #         Ok(args)
+    /// #     }
+    /// # }
     /// let foo = Arc::new(Foo { });
     /// // construct server
     /// let server = Server::builder()
     ///     .register(foo) // this will register `foo` with the default service name `Foo`
+    ///     .register(Bar { }) // this will be wrapped in an `Arc` internally
     ///     .build();
+    /// # }
     /// ```
-    pub fn register<S>(self, service: Arc<S>) -> Self
+    pub fn register<S>(self, service: S) -> Self
     where
-        S: RegisterService + Send + Sync + 'static,
+        S: IntoService,
     {
-        self.register_with_name(S::default_name(), service)
+        self.register_with_name(S::Service::default_name(), service)
     }
 
     /// Register a a service with a name. This allows registering multiple instances
-    /// of the same type on the server.
+    /// of the same type, or multiple implementations of the same trait, on the server.
     ///
     /// # Example
     ///
-    /// ```rust
+    /// ```no_run
+    /// # #[cfg(any(feature = "async_std_runtime", feature = "tokio_runtime"))]
+    /// # {
+    /// # use std::sync::Arc;
+    /// # use async_trait::async_trait;
+    /// # use toy_rpc::Server;
+    /// # use toy_rpc::macros::{export_impl, export_trait, export_trait_impl};
+    /// # struct Foo { }
+    /// # #[export_impl]
+    /// # impl Foo {
+    /// #     #[export_method]
+    /// #     async fn echo(&self, args: String) -> Result<String, String> {
+    /// #         Ok(args)
+    /// #     }
+    /// # }
+    /// # #[async_trait]
+    /// # #[export_trait]
+    /// # pub trait Arith {
+    /// #     #[export_method]
+    /// #     async fn add(&self, args: (i32, i32)) -> Result<i32, String>;
+    /// # }
+    /// # struct Abacus { }
+    /// # #[async_trait]
+    /// # #[export_trait_impl]
+    /// # impl Arith for Abacus {
+    /// #     async fn add(&self, args: (i32, i32)) -> Result<i32, String> {
+    /// #         Ok(args.0 + args.1)
+    /// #     }
+    /// # }
+    /// # struct Calculator { }
+    /// # #[async_trait]
+    /// # #[export_trait_impl]
+    /// # impl Arith for Calculator {
+    /// #     async fn add(&self, args: (i32, i32)) -> Result<i32, String> {
+    /// #         Ok(args.0 + args.1)
+    /// #     }
+    /// # }
     /// let foo1 = Arc::new(Foo { });
     /// let foo2 = Arc::new(Foo { });
     /// // construct server
     /// let server = Server::builder()
     ///     .register(foo1) // this will register `foo1` with the default service name `Foo`
     ///     .register_with_name("Foo2", foo2) // this will register `foo2` with the service name `Foo2`
+    ///     // `Abacus` and `Calculator` both implement the `Arith` trait
+    ///     .register(Abacus { }) // this will register with the default service name `Arith`
+    ///     .register_with_name("Calculator", Calculator { })
     ///     .build();
+    /// # }
     /// ```
-    pub fn register_with_name<S>(self, name: &'static str, service: Arc<S>) -> Self
+    pub fn register_with_name<S>(self, name: &'static str, service: S) -> Self
     where
-        S: RegisterService + Send + Sync + 'static,
+        S: IntoService,
     {
-        let service = build_service(service, S::handlers());
+        let service = build_service(service.into_service(), S::Service::handlers());
         self.register_service(name, service)
+            .register_blocking_methods(name, S::Service::blocking_methods())
+    }
+
+    /// Registers a service that takes part in transactions with the default name.
+    /// Committing or rolling back a transaction that the service took part in calls
+    /// its `Transactional` hook. See the `transaction` module for details.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # #[cfg(any(feature = "async_std_runtime", feature = "tokio_runtime"))]
+    /// # {
+    /// # use async_trait::async_trait;
+    /// # use toy_rpc::{Error, Server};
+    /// # use toy_rpc::macros::export_impl;
+    /// # use toy_rpc::transaction::{TransactionId, Transactional};
+    /// # struct Bank;
+    /// # impl Bank {
+    /// #     fn new() -> Self {
+    /// #         Bank
+    /// #     }
+    /// # }
+    /// # #[export_impl]
+    /// # impl Bank {
+    /// #     #[export_method]
+    /// #     async fn echo(&self, args: String) -> Result<String, String> {
+    /// #         Ok(args)
+    /// #     }
+    /// # }
+    /// # #[async_trait]
+    /// # impl Transactional for Bank {
+    /// #     async fn commit(&self, transaction: TransactionId) -> Result<(), Error> {
+    /// #         Ok(())
+    /// #     }
+    /// #     async fn rollback(&self, transaction: TransactionId) -> Result<(), Error> {
+    /// #         Ok(())
+    /// #     }
+    /// # }
+    /// let server = Server::builder()
+    ///     .register_transactional(Bank::new())
+    ///     .build();
+    /// # }
+    /// ```
+    pub fn register_transactional<S>(self, service: S) -> Self
+    where
+        S: IntoService,
+        S::Service: Transactional,
+    {
+        self.register_transactional_with_name(S::Service::default_name(), service)
+    }
+
+    /// Registers a service that takes part in transactions with a name
+    pub fn register_transactional_with_name<S>(self, name: &'static str, service: S) -> Self
+    where
+        S: IntoService,
+        S::Service: Transactional,
+    {
+        let mut handlers = S::Service::handlers();
+        handlers.insert(
+            transaction::COMMIT_METHOD,
+            transaction::commit_handler::<S::Service>,
+        );
+        handlers.insert(
+            transaction::ROLLBACK_METHOD,
+            transaction::rollback_handler::<S::Service>,
+        );
+        let service = build_service(service.into_service(), handlers);
+        self.register_service(name, service)
+            .register_blocking_methods(name, S::Service::blocking_methods())
+    }
+
+    /// Sets the deserialization policy of the arguments of every method of a service
+    /// with `"Service"`, or of a single method with `"Service.method"`. The policy of
+    /// a method takes precedence over the policy of its service. See the `schema`
+    /// module for details.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # #[cfg(any(feature = "async_std_runtime", feature = "tokio_runtime"))]
+    /// # {
+    /// # use std::sync::Arc;
+    /// # use toy_rpc::Server;
+    /// # use toy_rpc::macros::export_impl;
+    /// # struct Example;
+    /// # #[export_impl]
+    /// # impl Example {
+    /// #     #[export_method]
+    /// #     async fn echo(&self, args: String) -> Result<String, String> {
+    /// #         Ok(args)
+    /// #     }
+    /// # }
+    /// # let example_service = Arc::new(Example);
+    /// # use toy_rpc::server::schema::BodyPolicy;
+    /// let server = Server::builder()
+    ///     .register(example_service)
+    ///     .body_policy("Example", BodyPolicy::tolerant())
+    ///     .body_policy("Example.transfer", BodyPolicy::strict())
+    ///     .build();
+    /// # }
+    /// ```
+    pub fn body_policy(self, service_method: impl ToString, policy: BodyPolicy) -> Self {
+        let mut builder = self;
+        builder
+            .body_policies
+            .insert(service_method.to_string(), policy);
+        builder
+    }
+
+    /// Sets the hook that repairs the scalars of the wrong type in the arguments of
+    /// every method of a service with `"Service"`, or of a single method with
+    /// `"Service.method"`, before the request fails with `Error::InvalidArgument`.
+    /// The hook of a method takes precedence over the hook of its service. See the
+    /// `recovery` module for details.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # #[cfg(any(feature = "async_std_runtime", feature = "tokio_runtime"))]
+    /// # {
+    /// # use std::sync::Arc;
+    /// # use toy_rpc::Server;
+    /// # use toy_rpc::macros::export_impl;
+    /// # struct Example;
+    /// # #[export_impl]
+    /// # impl Example {
+    /// #     #[export_method]
+    /// #     async fn echo(&self, args: String) -> Result<String, String> {
+    /// #         Ok(args)
+    /// #     }
+    /// # }
+    /// # let example_service = Arc::new(Example);
+    /// # use toy_rpc::server::recovery::Lenient;
+    /// let server = Server::builder()
+    ///     .register(example_service)
+    ///     .argument_recovery("Example", Lenient)
+    ///     .build();
+    /// # }
+    /// ```
+    pub fn argument_recovery(self, service_method: impl ToString, repair: impl Repair) -> Self {
+        let mut builder = self;
+        builder
+            .repairs
+            .insert(service_method.to_string(), Arc::new(repair));
+        builder
+    }
+
+    /// Declares the capabilities of a service, which clients fetch with
+    /// `Client::capabilities`. Declaring the capabilities of a service again
+    /// replaces the previous ones. See the `capability` module for details.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # #[cfg(any(feature = "async_std_runtime", feature = "tokio_runtime"))]
+    /// # {
+    /// # use std::sync::Arc;
+    /// # use toy_rpc::Server;
+    /// # use toy_rpc::macros::export_impl;
+    /// # struct Example;
+    /// # #[export_impl]
+    /// # impl Example {
+    /// #     #[export_method]
+    /// #     async fn echo(&self, args: String) -> Result<String, String> {
+    /// #         Ok(args)
+    /// #     }
+    /// # }
+    /// # let example_service = Arc::new(Example);
+    /// # use toy_rpc::capability::Capabilities;
+    /// let server = Server::builder()
+    ///     .register(example_service)
+    ///     .capabilities("Example", Capabilities::new().enable("supports_streaming"))
+    ///     .build();
+    /// # }
+    /// ```
+    pub fn capabilities(self, service: impl ToString, capabilities: Capabilities) -> Self {
+        let mut builder = self;
+        builder
+            .capabilities
+            .insert(service.to_string(), capabilities);
+        builder
+    }
+
+    /// Sets the maximum sizes of the serialized arguments and responses of all the
+    /// methods of a service with `"Service"`, or of a single method with
+    /// `"Service.method"`. The limit of a method takes precedence over the limit of
+    /// its service. See the `payload` module for details.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # #[cfg(any(feature = "async_std_runtime", feature = "tokio_runtime"))]
+    /// # {
+    /// # use std::sync::Arc;
+    /// # use toy_rpc::Server;
+    /// # use toy_rpc::macros::export_impl;
+    /// # struct Example;
+    /// # #[export_impl]
+    /// # impl Example {
+    /// #     #[export_method]
+    /// #     async fn echo(&self, args: String) -> Result<String, String> {
+    /// #         Ok(args)
+    /// #     }
+    /// # }
+    /// # let example_service = Arc::new(Example);
+    /// # use toy_rpc::payload::SizeLimit;
+    /// let server = Server::builder()
+    ///     .register(example_service)
+    ///     .size_limit("Example", SizeLimit::default().max_request(64 * 1024))
+    ///     .size_limit("Example.download", SizeLimit::default().max_response(1024 * 1024))
+    ///     .build();
+    /// # }
+    /// ```
+    pub fn size_limit(self, service_method: impl ToString, limit: SizeLimit) -> Self {
+        let mut builder = self;
+        builder
+            .size_limits
+            .insert(service_method.to_string(), limit);
+        builder
+    }
+
+    /// Registers a handler of the protocol extension identified by `marker`.
+    /// See the `extension` module for details.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # #[cfg(any(feature = "async_std_runtime", feature = "tokio_runtime"))]
+    /// # {
+    /// # use toy_rpc::Server;
+    /// let server = Server::builder()
+    ///     .register_extension(1, |content| Some(format!("pong: {}", content)))
+    ///     .build();
+    /// # }
+    /// ```
+    pub fn register_extension<F>(self, marker: u32, handler: F) -> Self
+    where
+        F: Fn(String) -> Option<String> + Send + Sync + 'static,
+    {
+        debug!("Registering extension: {}", marker);
+        let mut builder = self;
+        builder.extensions.insert(marker, Arc::new(handler));
+        builder
     }
 
     /// Register a `Service` instance. This allows registering multiple instances
@@ -77,27 +795,62 @@ impl ServerBuilder {
     ///
     /// # Example
     ///
-    /// ```rust
+    /// ```no_run
+    /// # #[cfg(any(feature = "async_std_runtime", feature = "tokio_runtime"))]
+    /// # {
+    /// # use std::sync::Arc;
+    /// # use toy_rpc::Server;
+    /// # use toy_rpc::macros::export_impl;
+    /// # struct Foo { }
+    /// # #[export_impl]
+    /// # impl Foo {
+    /// #     #[export_method]
+    /// #     async fn echo(&self, args: String) -> Result<String, String> {
+    /// #         Ok(args)
+    /// #     }
+    /// # }
     /// let foo1 = Arc::new(Foo { });
     /// let foo2 = Arc::new(Foo { });
     ///
     /// // construct server
     /// let server = Server::builder()
     ///     .register(foo1) // this will register `foo1` with the default service name `Foo`
-    ///     .register_service("Foo2", foo2) // this will register `foo2` with the service name `Foo2`
+    ///     .register_with_name("Foo2", foo2) // this will register `foo2` with the service name `Foo2`
     ///     .build();
+    /// # }
     /// ```
     fn register_service<S>(self, name: &'static str, service: Service<S>) -> Self
     where
-        S: Send + Sync + 'static,
+        S: ?Sized + Send + Sync + 'static,
     {
+        let method_names = service.method_names();
         let call = move |method_name: String,
                          _deserializer: Box<(dyn erased::Deserializer<'static> + Send)>|
               -> HandlerResultFut { service.call(&method_name, _deserializer) };
 
-        log::debug!("Registering service: {}", name);
+        debug!("Registering service: {}", name);
         let mut builder = self;
-        builder.services.insert(name, Arc::new(call));
+        builder.method_names.insert(name, method_names);
+        if builder.services.insert(name, Arc::new(call)).is_some() {
+            warn!(
+                "Service {} is registered more than once, the previous one is replaced",
+                name
+            );
+        }
+        builder
+    }
+
+    fn register_blocking_methods(self, name: &'static str, methods: Vec<&'static str>) -> Self {
+        let mut builder = self;
+        let prefix = format!("{}.", name);
+        builder
+            .blocking_methods
+            .retain(|service_method| !service_method.starts_with(&prefix));
+        builder.blocking_methods.extend(
+            methods
+                .into_iter()
+                .map(|method| format!("{}{}", prefix, method)),
+        );
         builder
     }
 }
@@ -108,18 +861,1057 @@ impl ServerBuilder {
     all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
 ))]
 impl ServerBuilder {
-    /// Builds an RPC `Server`
+    /// Sets the storage backend of the pubsub broker. The topics and their retained
+    /// messages are kept in memory by default.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use toy_rpc::Server;
+    /// use toy_rpc::server::store::FileStore;
+    ///
+    /// # fn main() -> Result<(), toy_rpc::Error> {
+    /// let server = Server::builder()
+    ///     .broker_store(FileStore::open("broker.log")?)
+    ///     .build();
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn broker_store(self, store: impl BrokerStore) -> Self {
+        let mut builder = self;
+        builder.store = Some(Box::new(store));
+        builder
+    }
+
+    /// Sets the sink of the audit log. An `AuditRecord` is sent to the sink for every
+    /// RPC call handled by the server. See the `audit` module for details.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use std::sync::Arc;
+    /// # use toy_rpc::Server;
+    /// # use toy_rpc::macros::export_impl;
+    /// # struct Example;
+    /// # #[export_impl]
+    /// # impl Example {
+    /// #     #[export_method]
+    /// #     async fn echo(&self, args: String) -> Result<String, String> {
+    /// #         Ok(args)
+    /// #     }
+    /// # }
+    /// # let example_service = Arc::new(Example);
+    /// # use toy_rpc::server::audit::AuditRecord;
+    /// let (tx, rx) = flume::unbounded::<AuditRecord>();
+    /// let server = Server::builder()
+    ///     .register(example_service)
+    ///     .audit_sink(tx)
+    ///     .build();
+    /// ```
+    pub fn audit_sink(self, sink: impl AuditSink) -> Self {
+        let mut builder = self;
+        builder.audit = Some(Arc::new(sink));
+        builder
+    }
+
+    /// Compresses the outbound payloads of the connections accepted by the server and
+    /// of the streams served with `serve_stream`. The inbound payloads are decompressed
+    /// whether or not this is set.
+    ///
+    /// This has no effect on WebSocket connections or with the `serde_json` codec. See
+    /// the `compression` module for details.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # #[cfg(feature = "compression_zstd")]
+    /// # {
+    /// # use std::sync::Arc;
+    /// # use toy_rpc::Server;
+    /// # use toy_rpc::macros::export_impl;
+    /// # struct Example;
+    /// # #[export_impl]
+    /// # impl Example {
+    /// #     #[export_method]
+    /// #     async fn echo(&self, args: String) -> Result<String, String> {
+    /// #         Ok(args)
+    /// #     }
+    /// # }
+    /// # let example_service = Arc::new(Example);
+    /// # use toy_rpc::compression::{Algorithm, Compression};
+    /// let server = Server::builder()
+    ///     .register(example_service)
+    ///     .compression(Compression::new(Algorithm::Zstd))
+    ///     .build();
+    /// # }
+    /// ```
+    pub fn compression(self, compression: Compression) -> Self {
+        let mut builder = self;
+        builder.frame.compression = Some(compression);
+        builder
+    }
+
+    /// Writes the `Request` and `Response` headers with the fixed encoding instead of
+    /// serde on the connections accepted by the server and on the streams served with
+    /// `serve_stream`. The inbound headers are read with either encoding whether or
+    /// not this is set.
+    ///
+    /// This is disabled by default, since the clients of an older version can only
+    /// read the serde encoding. It has no effect on WebSocket connections or with the
+    /// `serde_json` codec. See `Header::encode_fixed` for the encoding.
+    pub fn fixed_headers(self, enabled: bool) -> Self {
+        let mut builder = self;
+        builder.frame.fixed_headers = enabled;
+        builder
+    }
+
+    /// Negotiates the content type of the TCP connections accepted by the server
+    /// among `content_types`, instead of serving them with the default codec. Each
+    /// connection is served with the first content type accepted by the client that
+    /// is in `content_types`. See the `codec::negotiate` module for details.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use std::sync::Arc;
+    /// # use toy_rpc::Server;
+    /// # use toy_rpc::macros::export_impl;
+    /// # struct Example;
+    /// # #[export_impl]
+    /// # impl Example {
+    /// #     #[export_method]
+    /// #     async fn echo(&self, args: String) -> Result<String, String> {
+    /// #         Ok(args)
+    /// #     }
+    /// # }
+    /// # let example_service = Arc::new(Example);
+    /// # use toy_rpc::codec::negotiate::ContentType;
+    /// let server = Server::builder()
+    ///     .register(example_service)
+    ///     .content_types(vec![ContentType::Bincode, ContentType::Json])
+    ///     .build();
+    /// ```
+    #[cfg(all(feature = "codec_negotiation", not(feature = "serde_json")))]
+    #[cfg_attr(
+        feature = "docs",
+        doc(cfg(all(feature = "codec_negotiation", not(feature = "serde_json"))))
+    )]
+    pub fn content_types(self, content_types: impl IntoIterator<Item = ContentType>) -> Self {
+        let mut builder = self;
+        builder.frame.content_types = Some(Supported::new(content_types));
+        builder
+    }
+
+    /// Declares a topic and the name of its item type. Once a topic is declared, only
+    /// the declared topics can be published to or subscribed to. See the `topics`
+    /// module for details.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use std::sync::Arc;
+    /// # use toy_rpc::Server;
+    /// # use toy_rpc::macros::export_impl;
+    /// # struct Example;
+    /// # #[export_impl]
+    /// # impl Example {
+    /// #     #[export_method]
+    /// #     async fn echo(&self, args: String) -> Result<String, String> {
+    /// #         Ok(args)
+    /// #     }
+    /// # }
+    /// # let example_service = Arc::new(Example);
+    /// # use toy_rpc::pubsub::Topic;
+    /// # struct Count;
+    /// # impl Topic for Count {
+    /// #     type Item = u32;
+    /// #     fn topic() -> String {
+    /// #         "count".into()
+    /// #     }
+    /// # }
+    /// let server = Server::builder()
+    ///     .register(example_service)
+    ///     .declare_topic::<Count>()
+    ///     .build();
+    /// ```
+    pub fn declare_topic<T: crate::pubsub::Topic>(self) -> Self {
+        let mut builder = self;
+        builder.topics.declare::<T>();
+        builder
+    }
+
+    /// Sets the retention policy of topic `T`. The retained messages are delivered to
+    /// a subscriber as soon as it subscribes to the topic, so that late subscribers
+    /// receive the messages published before they subscribed. The topic is created
+    /// when the server is built and is never removed as an idle topic.
+    ///
+    /// The retention policy set here overrides the one loaded from the broker store,
+    /// and can be changed later with `TopicAdmin::set_retention`.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use std::sync::Arc;
+    /// # use toy_rpc::Server;
+    /// # use toy_rpc::macros::export_impl;
+    /// # struct Example;
+    /// # #[export_impl]
+    /// # impl Example {
+    /// #     #[export_method]
+    /// #     async fn echo(&self, args: String) -> Result<String, String> {
+    /// #         Ok(args)
+    /// #     }
+    /// # }
+    /// # let example_service = Arc::new(Example);
+    /// # use toy_rpc::pubsub::Topic;
+    /// # use toy_rpc::server::pubsub::Retention;
+    /// # struct Config;
+    /// # impl Topic for Config {
+    /// #     type Item = String;
+    /// #     fn topic() -> String {
+    /// #         "config".into()
+    /// #     }
+    /// # }
+    /// let server = Server::builder()
+    ///     .register(example_service)
+    ///     .topic_retention::<Config>(Retention::LastN(1))
+    ///     .build();
+    /// ```
+    pub fn topic_retention<T: crate::pubsub::Topic>(self, retention: Retention) -> Self {
+        let mut builder = self;
+        builder.topic_retention.insert(T::topic(), retention);
+        builder
+    }
+
+    /// Sets what the server does when the buffer of a subscriber of topic `T` is
+    /// full, so that a slow subscriber does not hold back the others. The newest
+    /// item is dropped by default.
+    ///
+    /// The policy applies to the subscribers with a bounded buffer, which are the
+    /// subscribers on the server side and the clients connected through the
+    /// `actix-web` integration. It also applies to the subscribers of the patterns
+    /// that match the topic.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use std::sync::Arc;
+    /// # use toy_rpc::Server;
+    /// # use toy_rpc::macros::export_impl;
+    /// # struct Example;
+    /// # #[export_impl]
+    /// # impl Example {
+    /// #     #[export_method]
+    /// #     async fn echo(&self, args: String) -> Result<String, String> {
+    /// #         Ok(args)
+    /// #     }
+    /// # }
+    /// # let example_service = Arc::new(Example);
+    /// # use toy_rpc::pubsub::Topic;
+    /// # use toy_rpc::server::pubsub::Backpressure;
+    /// # struct Status;
+    /// # impl Topic for Status {
+    /// #     type Item = String;
+    /// #     fn topic() -> String {
+    /// #         "status".into()
+    /// #     }
+    /// # }
+    /// let server = Server::builder()
+    ///     .register(example_service)
+    ///     .topic_backpressure::<Status>(Backpressure::DropOldest)
+    ///     .build();
+    /// ```
+    pub fn topic_backpressure<T: crate::pubsub::Topic>(self, policy: Backpressure) -> Self {
+        let mut builder = self;
+        builder.topic_backpressure.insert(T::topic(), policy);
+        builder
+    }
+
+    /// Serves the history of topic `T` from an external store, such as a Kafka topic
+    /// or a Postgres table, to the subscribers that ask to replay more messages than
+    /// the topic retains. See the `source` module for details.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use async_trait::async_trait;
+    /// # use toy_rpc::{Error, Server};
+    /// # use toy_rpc::pubsub::Topic;
+    /// # use toy_rpc::server::{pubsub::Retention, source::TopicSource};
+    /// # struct Order;
+    /// # impl Topic for Order {
+    /// #     type Item = String;
+    /// #     fn topic() -> String {
+    /// #         "order".into()
+    /// #     }
+    /// # }
+    /// # struct Events;
+    /// # #[async_trait]
+    /// # impl TopicSource for Events {
+    /// #     async fn read_last(&self, topic: &str, count: usize) -> Result<Vec<Vec<u8>>, Error> {
+    /// #         Ok(Vec::new())
+    /// #     }
+    /// # }
+    /// # let events = Events;
+    /// let server = Server::builder()
+    ///     .topic_retention::<Order>(Retention::LastN(100))
+    ///     .topic_source::<Order>(events)
+    ///     .build();
+    /// ```
+    pub fn topic_source<T: crate::pubsub::Topic>(self, source: impl TopicSource) -> Self {
+        let mut builder = self;
+        builder.topic_sources.insert(T::topic(), Arc::new(source));
+        builder
+    }
+
+    /// Removes the topics that have had no subscribers and no publications for
+    /// `timeout`, along with the subscribers that are disconnected. Topics created
+    /// with `TopicAdmin::create`, topics loaded from the broker store and topics with
+    /// retained messages are kept.
+    ///
+    /// Idle topics are kept forever by default. The numbers of topics and subscribers
+    /// can be read with `TopicAdmin::stats`.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use std::sync::Arc;
+    /// # use toy_rpc::Server;
+    /// # use toy_rpc::macros::export_impl;
+    /// # struct Example;
+    /// # #[export_impl]
+    /// # impl Example {
+    /// #     #[export_method]
+    /// #     async fn echo(&self, args: String) -> Result<String, String> {
+    /// #         Ok(args)
+    /// #     }
+    /// # }
+    /// # let example_service = Arc::new(Example);
+    /// # use std::time::Duration;
+    /// let server = Server::builder()
+    ///     .register(example_service)
+    ///     .topic_idle_timeout(Duration::from_secs(600))
+    ///     .build();
+    /// ```
+    pub fn topic_idle_timeout(self, timeout: std::time::Duration) -> Self {
+        let mut builder = self;
+        builder.topic_idle_timeout = Some(timeout);
+        builder
+    }
+
+    /// Sets the time after which a publication is delivered again to a subscriber
+    /// that has not acknowledged it. Only the publications published with
+    /// `Publisher::publish_with_ack` are acknowledged by the subscribers, and they are
+    /// redelivered until the subscriber acknowledges them or unsubscribes from the
+    /// topic, or until they are delivered `max_delivery_attempts` times.
+    ///
+    /// The default is 10 seconds.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use std::sync::Arc;
+    /// # use toy_rpc::Server;
+    /// # use toy_rpc::macros::export_impl;
+    /// # struct Example;
+    /// # #[export_impl]
+    /// # impl Example {
+    /// #     #[export_method]
+    /// #     async fn echo(&self, args: String) -> Result<String, String> {
+    /// #         Ok(args)
+    /// #     }
+    /// # }
+    /// # let example_service = Arc::new(Example);
+    /// # use std::time::Duration;
+    /// let server = Server::builder()
+    ///     .register(example_service)
+    ///     .redelivery_timeout(Duration::from_secs(30))
+    ///     .build();
+    /// ```
+    pub fn redelivery_timeout(self, timeout: std::time::Duration) -> Self {
+        let mut builder = self;
+        builder.redelivery_timeout = timeout;
+        builder
+    }
+
+    /// Sets the number of times a publication is delivered to a subscriber that does
+    /// not acknowledge it, counting the first delivery. A publication that is still
+    /// not acknowledged after the last attempt is published to the dead letter topic
+    /// of its topic with `DeliveryFailure::Unacknowledged` (see
+    /// `TopicAdmin::set_dead_letter`). An `attempts` of zero is taken as one.
+    ///
+    /// The publications are redelivered without limit by default.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use std::sync::Arc;
+    /// # use toy_rpc::Server;
+    /// # use toy_rpc::macros::export_impl;
+    /// # struct Example;
+    /// # #[export_impl]
+    /// # impl Example {
+    /// #     #[export_method]
+    /// #     async fn echo(&self, args: String) -> Result<String, String> {
+    /// #         Ok(args)
+    /// #     }
+    /// # }
+    /// # let example_service = Arc::new(Example);
+    /// let server = Server::builder()
+    ///     .register(example_service)
+    ///     .max_delivery_attempts(5)
+    ///     .build();
+    /// ```
+    pub fn max_delivery_attempts(self, attempts: u32) -> Self {
+        let mut builder = self;
+        builder.max_delivery_attempts = Some(attempts.max(1));
+        builder
+    }
+
+    /// Bounds the number of messages waiting to be written to each client. A client
+    /// whose queue reaches the high watermark is paused or disconnected depending on
+    /// the policy. The queues are not bounded by default. See the `flow` module for
+    /// details.
+    ///
+    /// This is not available with `http_actix_web`, whose session actors write to
+    /// the clients through the mailboxes of actix instead of the outbound queues.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use std::sync::Arc;
+    /// # use toy_rpc::Server;
+    /// # use toy_rpc::macros::export_impl;
+    /// # struct Example;
+    /// # #[export_impl]
+    /// # impl Example {
+    /// #     #[export_method]
+    /// #     async fn echo(&self, args: String) -> Result<String, String> {
+    /// #         Ok(args)
+    /// #     }
+    /// # }
+    /// # let example_service = Arc::new(Example);
+    /// # use toy_rpc::server::flow::{FlowControl, SlowClientPolicy};
+    /// let server = Server::builder()
+    ///     .register(example_service)
+    ///     .flow_control(FlowControl::new(256, 64, SlowClientPolicy::Pause))
+    ///     .build();
+    /// ```
+    #[cfg(any(feature = "docs", not(feature = "http_actix_web")))]
+    #[cfg_attr(feature = "docs", doc(cfg(not(feature = "http_actix_web"))))]
+    pub fn flow_control(self, flow_control: FlowControl) -> Self {
+        let mut builder = self;
+        builder.flow_control = Some(flow_control);
+        builder
+    }
+
+    /// Limits the rate at which the frames are written to each client, so that a
+    /// client downloading in bulk does not starve the other clients sharing the
+    /// uplink of the server. The bandwidth is not limited by default. See the
+    /// `transport::bandwidth` module for details.
+    ///
+    /// This is not available with `http_actix_web`, whose session actors read and
+    /// write the WebSocket messages through actix instead of a metered transport.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use std::sync::Arc;
+    /// # use toy_rpc::Server;
+    /// # use toy_rpc::macros::export_impl;
+    /// # struct Example;
+    /// # #[export_impl]
+    /// # impl Example {
+    /// #     #[export_method]
+    /// #     async fn echo(&self, args: String) -> Result<String, String> {
+    /// #         Ok(args)
+    /// #     }
+    /// # }
+    /// # let example_service = Arc::new(Example);
+    /// # use toy_rpc::transport::bandwidth::BandwidthLimit;
+    /// let server = Server::builder()
+    ///     .register(example_service)
+    ///     // 1 MiB/s per client, with bursts of up to 256 KiB
+    ///     .outbound_bandwidth(BandwidthLimit::new(1024 * 1024, 256 * 1024))
+    ///     .build();
+    /// ```
+    #[cfg(any(feature = "docs", not(feature = "http_actix_web")))]
+    #[cfg_attr(feature = "docs", doc(cfg(not(feature = "http_actix_web"))))]
+    pub fn outbound_bandwidth(self, limit: BandwidthLimit) -> Self {
+        let mut builder = self;
+        builder.outbound_bandwidth = Some(limit);
+        builder
+    }
+
+    /// Limits the rate at which the frames are read from each client. Once a
+    /// client is over the limit, the server stops reading from its connection for
+    /// a while, which slows the client down. The bandwidth is not limited by
+    /// default. See the `transport::bandwidth` module for details.
+    ///
+    /// This is not available with `http_actix_web`, see `outbound_bandwidth`.
+    #[cfg(any(feature = "docs", not(feature = "http_actix_web")))]
+    #[cfg_attr(feature = "docs", doc(cfg(not(feature = "http_actix_web"))))]
+    pub fn inbound_bandwidth(self, limit: BandwidthLimit) -> Self {
+        let mut builder = self;
+        builder.inbound_bandwidth = Some(limit);
+        builder
+    }
+
+    /// Sets the callback called each time a connection starts being served, with
+    /// the ID of the connection and the address of the peer. See the `lifecycle`
+    /// module for details.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use std::sync::Arc;
+    /// # use toy_rpc::Server;
+    /// # use toy_rpc::macros::export_impl;
+    /// # struct Example;
+    /// # #[export_impl]
+    /// # impl Example {
+    /// #     #[export_method]
+    /// #     async fn echo(&self, args: String) -> Result<String, String> {
+    /// #         Ok(args)
+    /// #     }
+    /// # }
+    /// # let example_service = Arc::new(Example);
+    /// # use std::sync::atomic::{AtomicI64, Ordering};
+    /// # use toy_rpc::server::lifecycle::ConnectionInfo;
+    /// # struct Gauge(AtomicI64);
+    /// # impl Gauge {
+    /// #     fn inc(&self) {
+    /// #         self.0.fetch_add(1, Ordering::Relaxed);
+    /// #     }
+    /// #     fn dec(&self) {
+    /// #         self.0.fetch_sub(1, Ordering::Relaxed);
+    /// #     }
+    /// # }
+    /// # #[allow(non_upper_case_globals)]
+    /// # static sessions: Gauge = Gauge(AtomicI64::new(0));
+    /// let server = Server::builder()
+    ///     .register(example_service)
+    ///     .on_connect(|info: &ConnectionInfo| sessions.inc())
+    ///     .on_disconnect(|info: &ConnectionInfo| sessions.dec())
+    ///     .build();
+    /// ```
+    pub fn on_connect<F>(self, callback: F) -> Self
+    where
+        F: Fn(&ConnectionInfo) + Send + Sync + 'static,
+    {
+        let mut builder = self;
+        builder.lifecycle.on_connect = Some(Arc::new(callback));
+        builder
+    }
+
+    /// Sets the callback called each time a connection stops being served, with
+    /// the time it has been served for and the number of calls served on it. See
+    /// the `lifecycle` module for details.
+    pub fn on_disconnect<F>(self, callback: F) -> Self
+    where
+        F: Fn(&ConnectionInfo) + Send + Sync + 'static,
+    {
+        let mut builder = self;
+        builder.lifecycle.on_disconnect = Some(Arc::new(callback));
+        builder
+    }
+
+    /// Limits the time that a call may wait for its handler to start executing, eg.
+    /// for a thread of the blocking pool. A call over the limit fails with
+    /// `Error::ServerTimeout` without being executed. The calls are only limited by
+    /// the timeout of their requests by default. See the `timing` module for details.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use std::time::Duration;
+    /// # use toy_rpc::Server;
+    /// # use toy_rpc::macros::export_impl;
+    /// # struct Imaging;
+    /// # impl Imaging {
+    /// #     fn new() -> Self {
+    /// #         Imaging
+    /// #     }
+    /// # }
+    /// # #[export_impl]
+    /// # impl Imaging {
+    /// #     #[export_method]
+    /// #     async fn echo(&self, args: String) -> Result<String, String> {
+    /// #         Ok(args)
+    /// #     }
+    /// # }
+    /// let server = Server::builder()
+    ///     .register(Imaging::new())
+    ///     .blocking_pool(4)
+    ///     // fail fast rather than pile up the calls when the pool is saturated
+    ///     .queue_timeout(Duration::from_millis(200))
+    ///     .build();
+    /// ```
+    pub fn queue_timeout(self, timeout: std::time::Duration) -> Self {
+        let mut builder = self;
+        builder.timing.queue_timeout = Some(timeout);
+        builder
+    }
+
+    /// Limits the time that the handler of a call may execute. A call over the
+    /// limit fails with `Error::ServerTimeout`. The handlers are only limited by the
+    /// timeout of their requests by default. See the `timing` module for details.
+    ///
+    /// A blocking method keeps running after its call times out, see the `blocking`
+    /// module.
+    pub fn execution_timeout(self, timeout: std::time::Duration) -> Self {
+        let mut builder = self;
+        builder.timing.execution_timeout = Some(timeout);
+        builder
+    }
+
+    /// Attaches the time that each call was queued and executing to the metadata of
+    /// its response, which the client reads with `Timing::from_metadata`. Only the
+    /// calls returning a single response are covered. This is disabled by default.
+    pub fn report_timing(self, enabled: bool) -> Self {
+        let mut builder = self;
+        builder.timing.report = enabled;
+        builder
+    }
+
+    /// Reports the server as overloaded to the health checks once it serves
+    /// `connections` connections at the same time. The server keeps serving the new
+    /// connections. The server is only reported as overloaded with a threshold.
+    /// See the `health` module for details.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use std::sync::Arc;
+    /// # use toy_rpc::Server;
+    /// # use toy_rpc::macros::export_impl;
+    /// # struct Example;
+    /// # #[export_impl]
+    /// # impl Example {
+    /// #     #[export_method]
+    /// #     async fn echo(&self, args: String) -> Result<String, String> {
+    /// #         Ok(args)
+    /// #     }
+    /// # }
+    /// # let example_service = Arc::new(Example);
+    /// # use toy_rpc::server::health::Health;
+    /// let server = Server::builder()
+    ///     .register(example_service)
+    ///     .overload_threshold(10_000)
+    ///     .build();
+    /// assert_eq!(server.health(), Health::Accepting);
+    /// ```
+    pub fn overload_threshold(self, connections: usize) -> Self {
+        let mut builder = self;
+        builder.overload_threshold = Some(connections);
+        builder
+    }
+
+    /// Signs the responses and the items of the streaming responses, so that the
+    /// clients can detect the responses tampered with by a relay. See the `signing`
+    /// module for details.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use std::sync::Arc;
+    /// # use toy_rpc::Server;
+    /// # use toy_rpc::macros::export_impl;
+    /// # struct Example;
+    /// # #[export_impl]
+    /// # impl Example {
+    /// #     #[export_method]
+    /// #     async fn echo(&self, args: String) -> Result<String, String> {
+    /// #         Ok(args)
+    /// #     }
+    /// # }
+    /// # let example_service = Arc::new(Example);
+    /// # use toy_rpc::signing::ResponseSigner;
+    /// # let secret = [0u8; 32];
+    /// let server = Server::builder()
+    ///     .register(example_service)
+    ///     .sign_responses(ResponseSigner::hmac_sha256("server-1", &secret))
+    ///     .build();
+    /// ```
+    #[cfg(feature = "signing")]
+    #[cfg_attr(feature = "docs", doc(cfg(feature = "signing")))]
+    pub fn sign_responses(self, signer: crate::signing::ResponseSigner) -> Self {
+        let mut builder = self;
+        builder.signer = Some(signer);
+        builder
+    }
+
+    /// Serves the metrics of the calls with the HTTP integrations, at `METRICS_PATH`
+    /// next to the RPC path. The metrics are only available with `Server::metrics`
+    /// by default. See the `metrics` module for details.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use std::sync::Arc;
+    /// # use toy_rpc::Server;
+    /// # use toy_rpc::macros::export_impl;
+    /// # use warp::Filter;
+    /// # struct Example;
+    /// # #[export_impl]
+    /// # impl Example {
+    /// #     #[export_method]
+    /// #     async fn echo(&self, args: String) -> Result<String, String> {
+    /// #         Ok(args)
+    /// #     }
+    /// # }
+    /// # async fn run() {
+    /// # let example_service = Arc::new(Example);
+    /// let server = Server::builder()
+    ///     .register(example_service)
+    ///     .export_metrics(true)
+    ///     .build();
+    /// let routes = warp::path("rpc").and(server.handle_http());
+    /// // metrics will be served at "http://127.0.0.1:8080/rpc/metrics"
+    /// warp::serve(routes).run(([127, 0, 0, 1], 8080)).await;
+    /// # }
+    /// ```
+    #[cfg(feature = "metrics")]
+    #[cfg_attr(feature = "docs", doc(cfg(feature = "metrics")))]
+    pub fn export_metrics(self, enabled: bool) -> Self {
+        let mut builder = self;
+        builder.export_metrics = enabled;
+        builder
+    }
+
+    /// Limits the number of methods exported with `#[export_method(blocking)]` that
+    /// are executed at the same time. The calls over the limit wait for one of them to
+    /// return. There is no limit other than the size of the blocking thread pool of
+    /// the runtime by default. See the `blocking` module for details.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use toy_rpc::Server;
+    /// # use toy_rpc::macros::export_impl;
+    /// # struct Imaging;
+    /// # impl Imaging {
+    /// #     fn new() -> Self {
+    /// #         Imaging
+    /// #     }
+    /// # }
+    /// # #[export_impl]
+    /// # impl Imaging {
+    /// #     #[export_method]
+    /// #     async fn echo(&self, args: String) -> Result<String, String> {
+    /// #         Ok(args)
+    /// #     }
+    /// # }
+    /// let server = Server::builder()
+    ///     .register(Imaging::new())
+    ///     .blocking_pool(4)
+    ///     .build();
+    /// ```
+    pub fn blocking_pool(self, threads: usize) -> Self {
+        let mut builder = self;
+        builder.blocking_threads = Some(threads);
+        builder
+    }
+
+    /// Bounds the nesting and the lengths of the arguments of all methods. A request
+    /// whose argument exceeds a limit fails with `Error::InvalidArgument`. There is
+    /// no limit by default. See the `guard` module for details.
     ///
     /// # Example
     ///
+    /// ```no_run
+    /// # use std::sync::Arc;
+    /// # use toy_rpc::Server;
+    /// # use toy_rpc::macros::export_impl;
+    /// # struct Example;
+    /// # #[export_impl]
+    /// # impl Example {
+    /// #     #[export_method]
+    /// #     async fn echo(&self, args: String) -> Result<String, String> {
+    /// #         Ok(args)
+    /// #     }
+    /// # }
+    /// # let example_service = Arc::new(Example);
+    /// # use toy_rpc::server::guard::DeserializeLimits;
+    /// let server = Server::builder()
+    ///     .register(example_service)
+    ///     .deserialize_limits(DeserializeLimits::default().max_depth(32))
+    ///     .build();
+    /// ```
+    pub fn deserialize_limits(self, limits: DeserializeLimits) -> Self {
+        let mut builder = self;
+        builder.deserialize_limits = Some(limits);
+        builder
+    }
+
+    /// Maps the `"Service.method"` names of the requests that do not match a
+    /// registered name, eg. `"Arith.addNumbers"` sent by a JavaScript client, to the
+    /// registered names with the rules of the normalizer. The names must match
+    /// exactly by default. See the `naming` module for details.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use std::sync::Arc;
+    /// # use toy_rpc::Server;
+    /// # use toy_rpc::macros::export_impl;
+    /// # struct Example;
+    /// # #[export_impl]
+    /// # impl Example {
+    /// #     #[export_method]
+    /// #     async fn echo(&self, args: String) -> Result<String, String> {
+    /// #         Ok(args)
+    /// #     }
+    /// # }
+    /// # let example_service = Arc::new(Example);
+    /// # use toy_rpc::server::naming::NameNormalizer;
+    /// let server = Server::builder()
+    ///     .register(example_service)
+    ///     .normalize_names(NameNormalizer::default().case_insensitive(true).snake_case(true))
+    ///     .build();
+    /// ```
+    pub fn normalize_names(self, normalizer: NameNormalizer) -> Self {
+        let mut builder = self;
+        builder.name_normalizer = Some(normalizer);
+        builder
+    }
+
+    /// Sets the callbacks called each time the handler of a call starts and stops
+    /// running, with the name of the method and the ID of the call. The handlers
+    /// are not profiled by default. See the `profile` module for details.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use std::sync::Arc;
+    /// # use toy_rpc::Server;
+    /// # use toy_rpc::macros::export_impl;
+    /// # struct Example;
+    /// # #[export_impl]
+    /// # impl Example {
+    /// #     #[export_method]
+    /// #     async fn echo(&self, args: String) -> Result<String, String> {
+    /// #         Ok(args)
+    /// #     }
+    /// # }
+    /// # let example_service = Arc::new(Example);
+    /// # use toy_rpc::message::MessageId;
+    /// # mod cpu_timer {
+    /// #     pub fn start(service_method: &str, id: toy_rpc::message::MessageId) {}
+    /// #     pub fn stop(service_method: &str, id: toy_rpc::message::MessageId) {}
+    /// # }
+    /// let server = Server::builder()
+    ///     .register(example_service)
+    ///     .profiler((
+    ///         |service_method: &str, id: MessageId| cpu_timer::start(service_method, id),
+    ///         |service_method: &str, id: MessageId| cpu_timer::stop(service_method, id),
+    ///     ))
+    ///     .build();
+    /// ```
+    pub fn profiler(self, profiler: impl Profiler) -> Self {
+        let mut builder = self;
+        builder.profiler = Some(Arc::new(profiler));
+        builder
+    }
+
+    /// Shares `value` with the handlers of all services, which get it from the
+    /// `Context` of their calls with `Context::data::<T>()`, so that a service does
+    /// not have to capture every shared resource itself. A value replaces the value
+    /// of the same type set before.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use std::sync::Arc;
+    /// # use toy_rpc::Server;
+    /// # use toy_rpc::macros::export_impl;
+    /// # struct Example;
+    /// # #[export_impl]
+    /// # impl Example {
+    /// #     #[export_method]
+    /// #     async fn echo(&self, args: String) -> Result<String, String> {
+    /// #         Ok(args)
+    /// #     }
+    /// # }
+    /// # struct DbPool;
+    /// # impl DbPool {
+    /// #     async fn connect(url: &str) -> Result<Self, toy_rpc::Error> {
+    /// #         Ok(DbPool)
+    /// #     }
+    /// # }
+    /// # async fn run(url: &str) -> Result<(), toy_rpc::Error> {
+    /// # let example_service = Arc::new(Example);
+    /// let server = Server::builder()
+    ///     .register(example_service)
+    ///     .data(DbPool::connect(url).await?)
+    ///     .build();
+    /// # Ok(())
+    /// # }
     /// ```
+    pub fn data<T: Send + Sync + 'static>(self, value: T) -> Self {
+        let mut builder = self;
+        builder.data.insert(value);
+        builder
+    }
+
+    /// Builds an RPC `Server`
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use std::sync::Arc;
+    /// # use toy_rpc::{Server, ServerBuilder};
+    /// # use toy_rpc::macros::export_impl;
+    /// # struct EchoService { }
+    /// # #[export_impl]
+    /// # impl EchoService {
+    /// #     #[export_method]
+    /// #     async fn echo(&self, args: String) -> Result<String, String> {
+    /// #         Ok(args)
+    /// #     }
+    /// # }
     /// let echo_service = Arc::new(EchoService { });
     /// let builder: ServerBuilder = Server::builder()
     ///     .register(echo_service);
-    /// let server: Server = builder.build();        
+    /// let server: Server = builder.build();
     /// ```
     pub fn build(self) -> Server {
-        Server::from_builder(self)
+        let mut builder = self;
+        capability::apply(&mut builder.services, &builder.capabilities);
+        schema::apply(&mut builder.services, &builder.body_policies);
+        recovery::apply(&mut builder.services, &builder.repairs);
+        guard::apply(&mut builder.services, builder.deserialize_limits);
+        super::blocking::apply(
+            &mut builder.services,
+            &builder.blocking_methods,
+            builder.blocking_threads,
+        );
+        profile::apply(&mut builder.services, builder.profiler.take());
+        data::apply(&mut builder.services, std::mem::take(&mut builder.data));
+        Server::from_builder(builder)
+    }
+}
+
+#[cfg(any(
+    all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+    all(
+        feature = "tokio_runtime",
+        not(feature = "async_std_runtime"),
+        not(feature = "http_actix_web")
+    )
+))]
+impl ServerBuilder {
+    /// Expects a HAProxy PROXY protocol header (version 1 or 2) at the beginning of
+    /// every connection accepted by `accept`, `accept_with_tls_config` and
+    /// `accept_websocket`. The address of the real client behind the load balancer
+    /// is then used in the logs. Connections without a valid header are dropped.
+    ///
+    /// This is disabled by default.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use std::sync::Arc;
+    /// # use toy_rpc::Server;
+    /// # use toy_rpc::macros::export_impl;
+    /// # struct Example;
+    /// # #[export_impl]
+    /// # impl Example {
+    /// #     #[export_method]
+    /// #     async fn echo(&self, args: String) -> Result<String, String> {
+    /// #         Ok(args)
+    /// #     }
+    /// # }
+    /// # let example_service = Arc::new(Example);
+    /// let server = Server::builder()
+    ///     .register(example_service)
+    ///     .proxy_protocol(true)
+    ///     .build();
+    /// ```
+    pub fn proxy_protocol(self, enabled: bool) -> Self {
+        let mut builder = self;
+        builder.proxy_protocol = enabled;
+        builder
+    }
+
+    /// Sets a filter that is evaluated on the address of the peer of every connection
+    /// accepted by `accept`, `accept_with_tls_config` and `accept_websocket` before
+    /// the codec is constructed. The connection is dropped if the filter returns `false`.
+    ///
+    /// If `proxy_protocol` is enabled, the filter is evaluated on the address of the
+    /// real client. See the `filter` module for CIDR allow and deny lists.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use std::sync::Arc;
+    /// # use toy_rpc::Server;
+    /// # use toy_rpc::macros::export_impl;
+    /// # struct Example;
+    /// # #[export_impl]
+    /// # impl Example {
+    /// #     #[export_method]
+    /// #     async fn echo(&self, args: String) -> Result<String, String> {
+    /// #         Ok(args)
+    /// #     }
+    /// # }
+    /// # let example_service = Arc::new(Example);
+    /// let server = Server::builder()
+    ///     .register(example_service)
+    ///     .connection_filter(|peer_addr| peer_addr.ip().is_loopback())
+    ///     .build();
+    /// ```
+    pub fn connection_filter<F>(self, filter: F) -> Self
+    where
+        F: Fn(&std::net::SocketAddr) -> bool + Send + Sync + 'static,
+    {
+        let mut builder = self;
+        builder.connection_filter = Some(Arc::new(filter));
+        builder
+    }
+
+    /// Sets the maximum number of connections served at the same time. A connection
+    /// accepted once the limit is reached is closed right away and the rejection is
+    /// logged.
+    ///
+    /// There is no limit by default. See the `limit` module for details.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use std::sync::Arc;
+    /// # use toy_rpc::Server;
+    /// # use toy_rpc::macros::export_impl;
+    /// # struct Example;
+    /// # #[export_impl]
+    /// # impl Example {
+    /// #     #[export_method]
+    /// #     async fn echo(&self, args: String) -> Result<String, String> {
+    /// #         Ok(args)
+    /// #     }
+    /// # }
+    /// # let example_service = Arc::new(Example);
+    /// let server = Server::builder()
+    ///     .register(example_service)
+    ///     .max_connections(10_000)
+    ///     .build();
+    /// ```
+    pub fn max_connections(self, max: usize) -> Self {
+        let mut builder = self;
+        builder.max_connections = Some(max);
+        builder
+    }
+
+    /// Sets the maximum number of connections served at the same time from a single
+    /// IP address. A connection accepted once the limit is reached is closed right
+    /// away and the rejection is logged.
+    ///
+    /// There is no limit by default. The limit applies to the address of the socket,
+    /// which is the address of the load balancer if `proxy_protocol` is enabled.
+    pub fn max_connections_per_ip(self, max: usize) -> Self {
+        let mut builder = self;
+        builder.max_connections_per_ip = Some(max);
+        builder
     }
 }
 
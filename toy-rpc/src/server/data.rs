@@ -0,0 +1,86 @@
+//! Shared resources of the handlers
+//!
+//! The values set with `ServerBuilder::data`, eg. a database pool or a cache, are
+//! kept by their type and handed to every call through its `Context`, so that a
+//! method exported with `#[export_impl]` gets them with `Context::data` instead of
+//! capturing them in its service.
+
+use erased_serde as erased;
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::metadata::Context;
+use crate::service::{ArcAsyncServiceCall, AsyncServiceMap, HandlerResultFut};
+
+/// Values of different types, keyed by their type
+#[derive(Default)]
+pub(crate) struct DataMap {
+    values: HashMap<TypeId, Arc<dyn Any + Send + Sync>>,
+}
+
+impl std::fmt::Debug for DataMap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DataMap")
+            .field("len", &self.values.len())
+            .finish()
+    }
+}
+
+impl DataMap {
+    /// Inserts a value, replacing the value of the same type if any
+    pub fn insert<T: Send + Sync + 'static>(&mut self, value: T) {
+        self.values.insert(TypeId::of::<T>(), Arc::new(value));
+    }
+
+    /// Returns the value of type `T` if any
+    pub fn get<T: Send + Sync + 'static>(&self) -> Option<Arc<T>> {
+        self.values
+            .get(&TypeId::of::<T>())
+            .cloned()
+            .and_then(|value| value.downcast::<T>().ok())
+    }
+}
+
+/// Wraps the handlers of all services so that the contexts of their calls hold
+/// `data`, if there is any
+pub(crate) fn apply(services: &mut AsyncServiceMap, data: DataMap) {
+    if data.values.is_empty() {
+        return;
+    }
+    let data = Arc::new(data);
+    for call in services.values_mut() {
+        let inner = call.clone();
+        *call = with_data(inner, data.clone());
+    }
+}
+
+fn with_data(inner: ArcAsyncServiceCall, data: Arc<DataMap>) -> ArcAsyncServiceCall {
+    let call = move |method_name: String,
+                     deserializer: Box<dyn erased::Deserializer<'static> + Send>|
+          -> HandlerResultFut {
+        // the context of the call is the current one while the handler is created
+        Context::current().set_data(data.clone());
+        inner(method_name, deserializer)
+    };
+    Arc::new(call)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_are_keyed_by_type() {
+        let mut data = DataMap::default();
+        data.insert(7u32);
+        data.insert(String::from("pool"));
+        data.insert(8u32);
+        assert_eq!(data.get::<u32>().as_deref(), Some(&8));
+        assert_eq!(
+            data.get::<String>().as_deref().map(String::as_str),
+            Some("pool")
+        );
+        assert!(data.get::<u64>().is_none());
+    }
+}
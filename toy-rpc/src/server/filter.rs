@@ -0,0 +1,201 @@
+//! Filtering of incoming connections
+//!
+//! A connection filter is evaluated on the address of the peer before anything
+//! else is done with an accepted connection. If PROXY protocol is enabled, the
+//! filter is evaluated on the address of the real client. See
+//! `ServerBuilder::connection_filter`.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use toy_rpc::server::filter::{allow_list, Cidr};
+//! # use std::sync::Arc;
+//! # use toy_rpc::Server;
+//! # use toy_rpc::macros::export_impl;
+//! # struct Example;
+//! # #[export_impl]
+//! # impl Example {
+//! #     #[export_method]
+//! #     async fn echo(&self, args: String) -> Result<String, String> {
+//! #         Ok(args)
+//! #     }
+//! # }
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! # let example_service = Arc::new(Example);
+//!
+//! let server = Server::builder()
+//!     .register(example_service)
+//!     .connection_filter(allow_list(vec![
+//!         "10.0.0.0/8".parse::<Cidr>()?,
+//!         "127.0.0.1".parse::<Cidr>()?,
+//!     ]))
+//!     .build();
+//! # Ok(())
+//! # }
+//! ```
+
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use crate::error::Error;
+
+/// A connection filter. A connection is accepted if the filter returns `true`
+pub type ConnectionFilter = Arc<dyn Fn(&SocketAddr) -> bool + Send + Sync>;
+
+/// A block of IP addresses in CIDR notation, eg. `192.168.0.0/16` or `fd00::/8`
+///
+/// A single address without the prefix length is also accepted when parsing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl Cidr {
+    /// Creates a new `Cidr`. Returns an error if `prefix_len` is longer than the address
+    pub fn new(addr: IpAddr, prefix_len: u8) -> Result<Self, Error> {
+        let max_len = match addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        if prefix_len > max_len {
+            return Err(Error::Internal(
+                format!("Invalid prefix length {} for {}", prefix_len, addr).into(),
+            ));
+        }
+        Ok(Self { addr, prefix_len })
+    }
+
+    /// Returns whether `ip` is in the block. IPv4-mapped IPv6 addresses are
+    /// compared as IPv4 addresses.
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(*ip),
+            IpAddr::V4(_) => *ip,
+        };
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                prefix_eq(&net.octets(), &ip.octets(), self.prefix_len)
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                prefix_eq(&net.octets(), &ip.octets(), self.prefix_len)
+            }
+            _ => false,
+        }
+    }
+}
+
+fn prefix_eq(a: &[u8], b: &[u8], prefix_len: u8) -> bool {
+    let bytes = (prefix_len / 8) as usize;
+    let bits = prefix_len % 8;
+    if a[..bytes] != b[..bytes] {
+        return false;
+    }
+    if bits == 0 {
+        return true;
+    }
+    let mask = 0xffu8 << (8 - bits);
+    a[bytes] & mask == b[bytes] & mask
+}
+
+impl FromStr for Cidr {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::Internal(format!("Invalid CIDR: {}", s).into());
+        let (addr, prefix_len) = match s.split_once('/') {
+            Some((addr, len)) => {
+                let addr: IpAddr = addr.parse().map_err(|_| invalid())?;
+                (addr, len.parse().map_err(|_| invalid())?)
+            }
+            None => {
+                let addr: IpAddr = s.parse().map_err(|_| invalid())?;
+                let len = if addr.is_ipv4() { 32 } else { 128 };
+                (addr, len)
+            }
+        };
+        Self::new(addr, prefix_len)
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix_len)
+    }
+}
+
+/// Creates a connection filter that only accepts peers in one of the `cidrs`
+pub fn allow_list<I>(cidrs: I) -> impl Fn(&SocketAddr) -> bool + Send + Sync + 'static
+where
+    I: IntoIterator<Item = Cidr>,
+{
+    let cidrs: Vec<Cidr> = cidrs.into_iter().collect();
+    move |addr| cidrs.iter().any(|cidr| cidr.contains(&addr.ip()))
+}
+
+/// Creates a connection filter that rejects peers in any of the `cidrs`
+pub fn deny_list<I>(cidrs: I) -> impl Fn(&SocketAddr) -> bool + Send + Sync + 'static
+where
+    I: IntoIterator<Item = Cidr>,
+{
+    let cidrs: Vec<Cidr> = cidrs.into_iter().collect();
+    move |addr| !cidrs.iter().any(|cidr| cidr.contains(&addr.ip()))
+}
+
+/// Returns whether a connection from `peer_addr` should be accepted
+pub(crate) fn is_allowed(filter: Option<&ConnectionFilter>, peer_addr: &SocketAddr) -> bool {
+    match filter {
+        Some(filter) if !filter(peer_addr) => {
+            info!("Rejecting incoming connection from {}", peer_addr);
+            false
+        }
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_cidr() {
+        let cidr: Cidr = "192.168.0.0/16".parse().unwrap();
+        assert_eq!(cidr.to_string(), "192.168.0.0/16");
+        let cidr: Cidr = "::1".parse().unwrap();
+        assert_eq!(cidr.to_string(), "::1/128");
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert!("10.0.0/8".parse::<Cidr>().is_err());
+    }
+
+    #[test]
+    fn cidr_contains() {
+        let cidr: Cidr = "10.1.128.0/17".parse().unwrap();
+        assert!(cidr.contains(&"10.1.200.3".parse().unwrap()));
+        assert!(!cidr.contains(&"10.1.127.255".parse().unwrap()));
+        assert!(cidr.contains(&"::ffff:10.1.128.1".parse().unwrap()));
+
+        let cidr: Cidr = "fd00::/8".parse().unwrap();
+        assert!(cidr.contains(&"fd12::1".parse().unwrap()));
+        assert!(!cidr.contains(&"10.1.128.1".parse().unwrap()));
+
+        let any: Cidr = "0.0.0.0/0".parse().unwrap();
+        assert!(any.contains(&"8.8.8.8".parse().unwrap()));
+    }
+
+    #[test]
+    fn allow_and_deny_lists() {
+        let local: Cidr = "127.0.0.0/8".parse().unwrap();
+        let addr: SocketAddr = "127.0.0.1:23333".parse().unwrap();
+        let remote: SocketAddr = "203.0.113.7:23333".parse().unwrap();
+
+        let allow = allow_list(vec![local]);
+        assert!(allow(&addr));
+        assert!(!allow(&remote));
+
+        let deny = deny_list(vec![local]);
+        assert!(!deny(&addr));
+        assert!(deny(&remote));
+    }
+}
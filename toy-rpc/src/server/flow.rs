@@ -0,0 +1,208 @@
+//! Flow control of the messages written to slow clients
+//!
+//! The responses, the items of the streaming responses and the publications of a
+//! connection wait in an outbound queue until they are written. A client that
+//! cannot keep up, like a browser on a slow network, makes the queue grow without a
+//! limit. With `ServerBuilder::flow_control`, the server watches the number of
+//! messages in the queue of every connection. Once it reaches the high watermark,
+//! the server either
+//!
+//! - pauses reading, and therefore dispatching, the requests of the client until
+//!   the queue is drained down to the low watermark, or
+//! - closes the connection.
+//!
+//! This applies to the WebSocket connections served by the `warp` and `tide`
+//! integrations as well as the connections accepted by the server itself. With
+//! `http_actix_web`, the session actors write through the mailboxes of actix rather
+//! than the outbound queues, so `ServerBuilder::flow_control` is not available.
+//!
+//! # Example
+//!
+//! ```no_run
+//! # #[cfg(not(feature = "http_actix_web"))]
+//! # {
+//! # use std::sync::Arc;
+//! # use toy_rpc::Server;
+//! # use toy_rpc::macros::export_impl;
+//! # struct Example;
+//! # #[export_impl]
+//! # impl Example {
+//! #     #[export_method]
+//! #     async fn echo(&self, args: String) -> Result<String, String> {
+//! #         Ok(args)
+//! #     }
+//! # }
+//! # use toy_rpc::server::flow::{FlowControl, SlowClientPolicy};
+//! # let example_service = Arc::new(Example);
+//! let server = Server::builder()
+//!     .register(example_service)
+//!     .flow_control(FlowControl::new(256, 64, SlowClientPolicy::Pause))
+//!     .build();
+//! # }
+//! ```
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use flume::{Receiver, Sender};
+
+/// What the server does with a client whose outbound queue reaches the high watermark
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SlowClientPolicy {
+    /// Stops reading the requests of the client until its queue is drained down to
+    /// the low watermark. The calls in flight and the publications are still written.
+    Pause,
+    /// Closes the connection
+    Disconnect,
+}
+
+/// Watermarks of the outbound queue of each connection, in number of messages
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FlowControl {
+    /// Number of queued messages at which the policy takes effect
+    pub high_watermark: usize,
+    /// Number of queued messages at which a paused client is resumed
+    pub low_watermark: usize,
+    /// What the server does with a slow client
+    pub policy: SlowClientPolicy,
+}
+
+impl FlowControl {
+    /// Creates the flow control. The low watermark is capped at the high watermark.
+    pub fn new(high_watermark: usize, low_watermark: usize, policy: SlowClientPolicy) -> Self {
+        Self {
+            high_watermark,
+            low_watermark: low_watermark.min(high_watermark),
+            policy,
+        }
+    }
+}
+
+/// Outbound queue of a connection shared by its reader, broker and writer
+#[derive(Debug)]
+#[cfg_attr(feature = "http_actix_web", allow(dead_code))]
+pub(crate) struct OutboundQueue {
+    control: Option<FlowControl>,
+    queued: AtomicUsize,
+    paused: AtomicBool,
+    resume_tx: Sender<()>,
+    resume_rx: Receiver<()>,
+    // dropped to wake up everyone waiting for the connection to be closed
+    close_tx: Mutex<Option<Sender<()>>>,
+    close_rx: Receiver<()>,
+}
+
+#[cfg_attr(feature = "http_actix_web", allow(dead_code))]
+impl OutboundQueue {
+    pub fn new(control: Option<FlowControl>) -> Self {
+        let (resume_tx, resume_rx) = flume::bounded(1);
+        let (close_tx, close_rx) = flume::bounded(1);
+        Self {
+            control,
+            queued: AtomicUsize::new(0),
+            paused: AtomicBool::new(false),
+            resume_tx,
+            resume_rx,
+            close_tx: Mutex::new(Some(close_tx)),
+            close_rx,
+        }
+    }
+
+    /// Number of messages waiting to be written
+    pub fn len(&self) -> usize {
+        self.queued.load(Ordering::SeqCst)
+    }
+
+    /// Counts a message sent to the writer
+    pub fn push(&self) {
+        let queued = self.queued.fetch_add(1, Ordering::SeqCst) + 1;
+        if let Some(control) = self.control {
+            if control.policy == SlowClientPolicy::Disconnect && queued >= control.high_watermark {
+                warn!(
+                    "Closing the connection of a slow client with {} queued messages",
+                    queued
+                );
+                self.close();
+            }
+        }
+    }
+
+    /// Counts a message taken by the writer, and resumes a paused client once the
+    /// queue is drained down to the low watermark
+    pub fn pop(&self) {
+        let queued = self.queued.fetch_sub(1, Ordering::SeqCst) - 1;
+        if let Some(control) = self.control {
+            if queued <= control.low_watermark && self.paused.swap(false, Ordering::SeqCst) {
+                let _ = self.resume_tx.try_send(());
+            }
+        }
+    }
+
+    /// Waits until the client may send more requests. Returns `false` if the
+    /// connection is closed instead.
+    pub async fn ready(&self) -> bool {
+        let control = match self.control {
+            Some(control) if control.policy == SlowClientPolicy::Pause => control,
+            _ => return !self.is_closed(),
+        };
+        if self.len() < control.high_watermark {
+            return !self.is_closed();
+        }
+        debug!("Pausing a slow client with {} queued messages", self.len());
+        self.paused.store(true, Ordering::SeqCst);
+        // the queue may have been drained before the client is marked as paused
+        if self.len() <= control.low_watermark && self.paused.swap(false, Ordering::SeqCst) {
+            return true;
+        }
+        futures::select! {
+            _ = self.resume_rx.recv_async() => true,
+            _ = self.close_rx.recv_async() => false,
+        }
+    }
+
+    /// Closes the connection
+    pub fn close(&self) {
+        if let Ok(mut close_tx) = self.close_tx.lock() {
+            close_tx.take();
+        }
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.close_rx.is_disconnected()
+    }
+
+    /// Resolves once the connection is closed
+    pub async fn closed(&self) {
+        let _ = self.close_rx.recv_async().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::FutureExt;
+
+    #[test]
+    fn watermarks() {
+        let queue = OutboundQueue::new(Some(FlowControl::new(3, 1, SlowClientPolicy::Pause)));
+        for _ in 0..3 {
+            queue.push();
+        }
+        let mut ready = Box::pin(queue.ready());
+        let waker = futures::task::noop_waker();
+        let mut cx = std::task::Context::from_waker(&waker);
+        assert!(ready.poll_unpin(&mut cx).is_pending());
+
+        queue.pop();
+        assert!(ready.poll_unpin(&mut cx).is_pending());
+        queue.pop();
+        assert_eq!(ready.poll_unpin(&mut cx), std::task::Poll::Ready(true));
+        assert_eq!(queue.len(), 1);
+
+        let queue = OutboundQueue::new(Some(FlowControl::new(2, 1, SlowClientPolicy::Disconnect)));
+        queue.push();
+        assert!(!queue.is_closed());
+        queue.push();
+        assert!(queue.is_closed());
+    }
+}
@@ -0,0 +1,651 @@
+//! Guards against hostile arguments
+//!
+//! A deeply nested argument can exhaust the stack of the task deserializing it, and
+//! a very long sequence or string can exhaust the memory of the server. The
+//! `DeserializeLimits` set with `ServerBuilder::deserialize_limits` bound the
+//! arguments of every method of the server:
+//!
+//! - `max_depth` bounds the nesting of sequences, maps and enums
+//! - `max_str_len` bounds the length in bytes of each string and byte array
+//! - `max_seq_len` bounds the number of elements of each sequence and map
+//!
+//! The arguments are checked as they are deserialized. A nested value beyond the
+//! maximum depth is never parsed, and neither are the elements of a sequence or a
+//! map whose length is encoded up front and is over the maximum. A string or a byte
+//! array is only checked once the codec has read it though, so `max_str_len` keeps
+//! the long values away from the handlers but does not bound the memory used to read
+//! them. That memory is bounded by the size of the whole request, see
+//! `ServerBuilder::size_limit`. A request exceeding a limit fails with
+//! `Error::InvalidArgument`. There is no limit by default.
+//!
+//! The items of a `RequestStream` are not checked.
+//!
+//! # Example
+//!
+//! ```no_run
+//! # #[cfg(any(feature = "async_std_runtime", feature = "tokio_runtime"))]
+//! # {
+//! use toy_rpc::server::guard::DeserializeLimits;
+//! # use std::sync::Arc;
+//! # use toy_rpc::Server;
+//! # use toy_rpc::macros::export_impl;
+//! # struct Example;
+//! # #[export_impl]
+//! # impl Example {
+//! #     #[export_method]
+//! #     async fn echo(&self, args: String) -> Result<String, String> {
+//! #         Ok(args)
+//! #     }
+//! # }
+//! # let example_service = Arc::new(Example);
+//!
+//! let server = Server::builder()
+//!     .register(example_service)
+//!     .deserialize_limits(
+//!         DeserializeLimits::default()
+//!             .max_depth(32)
+//!             .max_str_len(64 * 1024)
+//!             .max_seq_len(10_000),
+//!     )
+//!     .build();
+//! # }
+//! ```
+
+use erased_serde as erased;
+use serde::de::{
+    self, DeserializeSeed, Deserializer, EnumAccess, MapAccess, SeqAccess, VariantAccess, Visitor,
+};
+use std::fmt;
+use std::sync::Arc;
+
+use crate::service::{ArcAsyncServiceCall, AsyncServiceMap, HandlerResultFut};
+
+/// Limits of the arguments of the methods of a server
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DeserializeLimits {
+    /// Maximum nesting of sequences, maps and enums. There is no limit if `None`.
+    pub max_depth: Option<usize>,
+    /// Maximum length in bytes of a string or a byte array. There is no limit if `None`.
+    pub max_str_len: Option<usize>,
+    /// Maximum number of elements of a sequence or a map. There is no limit if `None`.
+    pub max_seq_len: Option<usize>,
+}
+
+impl DeserializeLimits {
+    /// Sets the maximum nesting of sequences, maps and enums
+    pub fn max_depth(self, depth: usize) -> Self {
+        let mut limits = self;
+        limits.max_depth = Some(depth);
+        limits
+    }
+
+    /// Sets the maximum length in bytes of a string or a byte array
+    pub fn max_str_len(self, len: usize) -> Self {
+        let mut limits = self;
+        limits.max_str_len = Some(len);
+        limits
+    }
+
+    /// Sets the maximum number of elements of a sequence or a map
+    pub fn max_seq_len(self, len: usize) -> Self {
+        let mut limits = self;
+        limits.max_seq_len = Some(len);
+        limits
+    }
+
+    fn check_depth<E: de::Error>(&self, depth: usize) -> Result<(), E> {
+        match self.max_depth {
+            Some(max) if depth > max => Err(E::custom(format!(
+                "Argument is nested deeper than {} levels",
+                max
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    fn check_str_len<E: de::Error>(&self, len: usize) -> Result<(), E> {
+        match self.max_str_len {
+            Some(max) if len > max => Err(E::custom(format!(
+                "Argument has a string of {} bytes, the limit is {} bytes",
+                len, max
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    fn check_seq_len<E: de::Error>(&self, len: usize) -> Result<(), E> {
+        match self.max_seq_len {
+            Some(max) if len > max => Err(E::custom(format!(
+                "Argument has a sequence of more than {} elements",
+                max
+            ))),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Wraps every service so that the arguments are checked against `limits`
+pub(crate) fn apply(services: &mut AsyncServiceMap, limits: Option<DeserializeLimits>) {
+    let limits = match limits {
+        Some(limits) if limits != DeserializeLimits::default() => limits,
+        _ => return,
+    };
+    for call in services.values_mut() {
+        let inner = call.clone();
+        *call = with_limits(inner, limits);
+    }
+}
+
+fn with_limits(inner: ArcAsyncServiceCall, limits: DeserializeLimits) -> ArcAsyncServiceCall {
+    let call = move |method_name: String,
+                     deserializer: Box<dyn erased::Deserializer<'static> + Send>|
+          -> HandlerResultFut {
+        let deserializer = <dyn erased::Deserializer>::erase(Bounded::new(deserializer, limits));
+        inner(method_name, Box::new(deserializer))
+    };
+    Arc::new(call)
+}
+
+/// Deserializer that checks the values it deserializes against `DeserializeLimits`.
+/// `depth` is the number of sequences, maps and enums the value is nested in.
+struct Bounded<D> {
+    inner: D,
+    limits: DeserializeLimits,
+    depth: usize,
+}
+
+impl<D> Bounded<D> {
+    fn new(inner: D, limits: DeserializeLimits) -> Self {
+        Self {
+            inner,
+            limits,
+            depth: 0,
+        }
+    }
+
+    fn visit<V>(&self, visitor: V) -> BoundedVisitor<V> {
+        BoundedVisitor {
+            inner: visitor,
+            limits: self.limits,
+            depth: self.depth,
+        }
+    }
+}
+
+macro_rules! forward_deserialize {
+    ($($method:ident)*) => {$(
+        fn $method<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+        where
+            V: Visitor<'de>,
+        {
+            self.limits.check_depth(self.depth)?;
+            let visitor = self.visit(visitor);
+            self.inner.$method(visitor)
+        }
+    )*};
+}
+
+impl<'de, D> Deserializer<'de> for Bounded<D>
+where
+    D: Deserializer<'de>,
+{
+    type Error = D::Error;
+
+    forward_deserialize! {
+        deserialize_any deserialize_bool
+        deserialize_i8 deserialize_i16 deserialize_i32 deserialize_i64 deserialize_i128
+        deserialize_u8 deserialize_u16 deserialize_u32 deserialize_u64 deserialize_u128
+        deserialize_f32 deserialize_f64 deserialize_char deserialize_str deserialize_string
+        deserialize_bytes deserialize_byte_buf deserialize_option deserialize_unit
+        deserialize_seq deserialize_map deserialize_identifier deserialize_ignored_any
+    }
+
+    fn deserialize_unit_struct<V>(
+        self,
+        name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.limits.check_depth(self.depth)?;
+        let visitor = self.visit(visitor);
+        self.inner.deserialize_unit_struct(name, visitor)
+    }
+
+    fn deserialize_newtype_struct<V>(
+        self,
+        name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.limits.check_depth(self.depth)?;
+        let visitor = self.visit(visitor);
+        self.inner.deserialize_newtype_struct(name, visitor)
+    }
+
+    fn deserialize_tuple<V>(self, len: usize, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.limits.check_depth(self.depth)?;
+        let visitor = self.visit(visitor);
+        self.inner.deserialize_tuple(len, visitor)
+    }
+
+    fn deserialize_tuple_struct<V>(
+        self,
+        name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.limits.check_depth(self.depth)?;
+        let visitor = self.visit(visitor);
+        self.inner.deserialize_tuple_struct(name, len, visitor)
+    }
+
+    fn deserialize_struct<V>(
+        self,
+        name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.limits.check_depth(self.depth)?;
+        let visitor = self.visit(visitor);
+        self.inner.deserialize_struct(name, fields, visitor)
+    }
+
+    fn deserialize_enum<V>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.limits.check_depth(self.depth)?;
+        let visitor = self.visit(visitor);
+        self.inner.deserialize_enum(name, variants, visitor)
+    }
+
+    fn is_human_readable(&self) -> bool {
+        self.inner.is_human_readable()
+    }
+}
+
+/// Seed that deserializes with a `Bounded` deserializer
+struct BoundedSeed<S> {
+    inner: S,
+    limits: DeserializeLimits,
+    depth: usize,
+}
+
+impl<'de, S> DeserializeSeed<'de> for BoundedSeed<S>
+where
+    S: DeserializeSeed<'de>,
+{
+    type Value = S::Value;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        self.inner.deserialize(Bounded {
+            inner: deserializer,
+            limits: self.limits,
+            depth: self.depth,
+        })
+    }
+}
+
+/// Visitor that checks the strings and passes the limits on to the nested values
+struct BoundedVisitor<V> {
+    inner: V,
+    limits: DeserializeLimits,
+    depth: usize,
+}
+
+impl<V> BoundedVisitor<V> {
+    fn wrap<D>(&self, deserializer: D) -> Bounded<D> {
+        Bounded {
+            inner: deserializer,
+            limits: self.limits,
+            depth: self.depth,
+        }
+    }
+}
+
+macro_rules! forward_visit {
+    ($($method:ident: $ty:ty)*) => {$(
+        fn $method<E>(self, v: $ty) -> Result<Self::Value, E>
+        where
+            E: de::Error,
+        {
+            self.inner.$method(v)
+        }
+    )*};
+}
+
+macro_rules! forward_visit_str {
+    ($($method:ident: $ty:ty)*) => {$(
+        fn $method<E>(self, v: $ty) -> Result<Self::Value, E>
+        where
+            E: de::Error,
+        {
+            self.limits.check_str_len(v.len())?;
+            self.inner.$method(v)
+        }
+    )*};
+}
+
+impl<'de, V> Visitor<'de> for BoundedVisitor<V>
+where
+    V: Visitor<'de>,
+{
+    type Value = V::Value;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        self.inner.expecting(formatter)
+    }
+
+    forward_visit! {
+        visit_bool: bool
+        visit_i8: i8 visit_i16: i16 visit_i32: i32 visit_i64: i64 visit_i128: i128
+        visit_u8: u8 visit_u16: u16 visit_u32: u32 visit_u64: u64 visit_u128: u128
+        visit_f32: f32 visit_f64: f64 visit_char: char
+    }
+
+    forward_visit_str! {
+        visit_str: &str visit_borrowed_str: &'de str visit_string: String
+        visit_bytes: &[u8] visit_borrowed_bytes: &'de [u8] visit_byte_buf: Vec<u8>
+    }
+
+    fn visit_none<E>(self) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        self.inner.visit_none()
+    }
+
+    fn visit_unit<E>(self) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        self.inner.visit_unit()
+    }
+
+    fn visit_some<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let deserializer = self.wrap(deserializer);
+        self.inner.visit_some(deserializer)
+    }
+
+    fn visit_newtype_struct<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let deserializer = self.wrap(deserializer);
+        self.inner.visit_newtype_struct(deserializer)
+    }
+
+    fn visit_seq<A>(self, seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        if let Some(len) = seq.size_hint() {
+            self.limits.check_seq_len(len)?;
+        }
+        self.inner.visit_seq(BoundedSeq {
+            inner: seq,
+            limits: self.limits,
+            depth: self.depth + 1,
+            len: 0,
+        })
+    }
+
+    fn visit_map<A>(self, map: A) -> Result<Self::Value, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        if let Some(len) = map.size_hint() {
+            self.limits.check_seq_len(len)?;
+        }
+        self.inner.visit_map(BoundedMap {
+            inner: map,
+            limits: self.limits,
+            depth: self.depth + 1,
+            len: 0,
+        })
+    }
+
+    fn visit_enum<A>(self, data: A) -> Result<Self::Value, A::Error>
+    where
+        A: EnumAccess<'de>,
+    {
+        self.inner.visit_enum(BoundedEnum {
+            inner: data,
+            limits: self.limits,
+            depth: self.depth + 1,
+        })
+    }
+}
+
+struct BoundedSeq<A> {
+    inner: A,
+    limits: DeserializeLimits,
+    depth: usize,
+    // number of elements so far
+    len: usize,
+}
+
+impl<'de, A> SeqAccess<'de> for BoundedSeq<A>
+where
+    A: SeqAccess<'de>,
+{
+    type Error = A::Error;
+
+    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>, Self::Error>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        self.len += 1;
+        let element = self.inner.next_element_seed(BoundedSeed {
+            inner: seed,
+            limits: self.limits,
+            depth: self.depth,
+        })?;
+        if element.is_some() {
+            self.limits.check_seq_len(self.len)?;
+        }
+        Ok(element)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        self.inner.size_hint()
+    }
+}
+
+struct BoundedMap<A> {
+    inner: A,
+    limits: DeserializeLimits,
+    depth: usize,
+    // number of entries so far
+    len: usize,
+}
+
+impl<'de, A> MapAccess<'de> for BoundedMap<A>
+where
+    A: MapAccess<'de>,
+{
+    type Error = A::Error;
+
+    fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>, Self::Error>
+    where
+        K: DeserializeSeed<'de>,
+    {
+        self.len += 1;
+        let key = self.inner.next_key_seed(BoundedSeed {
+            inner: seed,
+            limits: self.limits,
+            depth: self.depth,
+        })?;
+        if key.is_some() {
+            self.limits.check_seq_len(self.len)?;
+        }
+        Ok(key)
+    }
+
+    fn next_value_seed<T>(&mut self, seed: T) -> Result<T::Value, Self::Error>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        self.inner.next_value_seed(BoundedSeed {
+            inner: seed,
+            limits: self.limits,
+            depth: self.depth,
+        })
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        self.inner.size_hint()
+    }
+}
+
+struct BoundedEnum<A> {
+    inner: A,
+    limits: DeserializeLimits,
+    depth: usize,
+}
+
+impl<'de, A> EnumAccess<'de> for BoundedEnum<A>
+where
+    A: EnumAccess<'de>,
+{
+    type Error = A::Error;
+    type Variant = BoundedVariant<A::Variant>;
+
+    fn variant_seed<V>(self, seed: V) -> Result<(V::Value, Self::Variant), Self::Error>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        let (limits, depth) = (self.limits, self.depth);
+        let (value, variant) = self.inner.variant_seed(BoundedSeed {
+            inner: seed,
+            limits,
+            depth,
+        })?;
+        Ok((
+            value,
+            BoundedVariant {
+                inner: variant,
+                limits,
+                depth,
+            },
+        ))
+    }
+}
+
+struct BoundedVariant<A> {
+    inner: A,
+    limits: DeserializeLimits,
+    depth: usize,
+}
+
+impl<A> BoundedVariant<A> {
+    fn visit<V>(&self, visitor: V) -> BoundedVisitor<V> {
+        BoundedVisitor {
+            inner: visitor,
+            limits: self.limits,
+            depth: self.depth,
+        }
+    }
+}
+
+impl<'de, A> VariantAccess<'de> for BoundedVariant<A>
+where
+    A: VariantAccess<'de>,
+{
+    type Error = A::Error;
+
+    fn unit_variant(self) -> Result<(), Self::Error> {
+        self.inner.unit_variant()
+    }
+
+    fn newtype_variant_seed<T>(self, seed: T) -> Result<T::Value, Self::Error>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        self.inner.newtype_variant_seed(BoundedSeed {
+            inner: seed,
+            limits: self.limits,
+            depth: self.depth,
+        })
+    }
+
+    fn tuple_variant<V>(self, len: usize, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.limits.check_depth(self.depth)?;
+        let visitor = self.visit(visitor);
+        self.inner.tuple_variant(len, visitor)
+    }
+
+    fn struct_variant<V>(
+        self,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.limits.check_depth(self.depth)?;
+        let visitor = self.visit(visitor);
+        self.inner.struct_variant(fields, visitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::de::value::Error;
+    use serde::de::IntoDeserializer;
+    use serde::Deserialize;
+
+    fn parse<T>(value: T, limits: DeserializeLimits) -> Result<T, Error>
+    where
+        T: IntoDeserializer<'static, Error> + for<'de> Deserialize<'de>,
+    {
+        T::deserialize(Bounded::new(value.into_deserializer(), limits))
+    }
+
+    #[test]
+    fn nesting_is_bounded() {
+        let nested = vec![vec![vec![1u8]]];
+        let limits = DeserializeLimits::default().max_depth(3);
+        assert_eq!(parse(nested.clone(), limits).unwrap(), nested);
+        let limits = DeserializeLimits::default().max_depth(2);
+        assert!(parse(nested, limits).is_err());
+    }
+
+    #[test]
+    fn lengths_are_bounded() {
+        let limits = DeserializeLimits::default().max_str_len(3).max_seq_len(2);
+        assert!(parse(String::from("abc"), limits).is_ok());
+        assert!(parse(String::from("abcd"), limits).is_err());
+        assert!(parse(vec![1u32, 2], limits).is_ok());
+        assert!(parse(vec![1u32, 2, 3], limits).is_err());
+        assert!(parse(vec![String::from("abcd")], limits).is_err());
+    }
+}
@@ -0,0 +1,153 @@
+//! Health of the RPC subsystem for the health checks of load balancers
+//!
+//! A `Server` is healthy from the point of view of a load balancer only while it
+//! accepts new connections and calls. `Server::health` tells whether it does:
+//!
+//! - `Health::Draining` once the server is shutting down (see the `shutdown` module)
+//! - `Health::Overloaded` while the server serves `ServerBuilder::overload_threshold`
+//!   connections or more. The server keeps serving the new connections, so that the
+//!   load balancer can route the traffic elsewhere without any connection being
+//!   refused. The connections of every transport and HTTP integration count.
+//! - `Health::Accepting` otherwise
+//!
+//! A `HealthProbe`, obtained with `Server::health_probe`, reads the health of the
+//! server without keeping the server alive. The HTTP integrations plug the health
+//! into the endpoints of the web frameworks, which answer `200 OK` only if the
+//! server is accepting, and `503 Service Unavailable` otherwise.
+//!
+//! - With `actix-web`, `Health` is an extractor, which reads the `web::Data<Server>`
+//!   of the app, and a responder.
+//! - With `warp`, `Server::health_filter` extracts the `Health`, which is a reply.
+//! - With `tide`, a `HealthProbe` is a middleware answering the requests with a
+//!   `503 Service Unavailable` unless the server is accepting.
+//!
+//! # Example
+//!
+//! ```no_run
+//! # #[cfg(feature = "http_warp")]
+//! # {
+//! # use std::sync::Arc;
+//! # use toy_rpc::Server;
+//! # use toy_rpc::macros::export_impl;
+//! # struct Example;
+//! # #[export_impl]
+//! # impl Example {
+//! #     #[export_method]
+//! #     async fn echo(&self, args: String) -> Result<String, String> {
+//! #         Ok(args)
+//! #     }
+//! # }
+//! # use warp::Filter;
+//! # async fn run() {
+//! # let example_service = Arc::new(Example);
+//! let server = Server::builder()
+//!     .register(example_service)
+//!     .overload_threshold(10_000)
+//!     .build();
+//! let health = warp::path("healthz").and(server.health_filter());
+//! let routes = warp::path("rpc").and(server.handle_http()).or(health);
+//! warp::serve(routes).run(([127, 0, 0, 1], 8080)).await;
+//! # }
+//! # }
+//! ```
+
+use std::fmt;
+
+use super::shutdown::ShutdownHandle;
+
+/// Whether the server accepts new connections and calls
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Health {
+    /// The server accepts new connections and calls
+    Accepting,
+    /// The server is shutting down
+    Draining,
+    /// The server serves as many connections as its `overload_threshold`
+    Overloaded,
+}
+
+impl Health {
+    /// Whether the load balancers should route new traffic to the server
+    pub fn is_ready(&self) -> bool {
+        matches!(self, Self::Accepting)
+    }
+
+    /// Returns the name of the state, eg. `"draining"`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Accepting => "accepting",
+            Self::Draining => "draining",
+            Self::Overloaded => "overloaded",
+        }
+    }
+}
+
+impl fmt::Display for Health {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Reads the health of a `Server`
+///
+/// The probe is obtained with `Server::health_probe` and can be cloned.
+#[derive(Clone, Default)]
+pub struct HealthProbe {
+    shutdown: ShutdownHandle,
+    overload_threshold: Option<usize>,
+}
+
+impl HealthProbe {
+    pub(crate) fn new(shutdown: ShutdownHandle, overload_threshold: Option<usize>) -> Self {
+        Self {
+            shutdown,
+            overload_threshold,
+        }
+    }
+
+    /// Returns the current health of the server
+    pub fn health(&self) -> Health {
+        if self.shutdown.is_shutting_down() {
+            return Health::Draining;
+        }
+        match self.overload_threshold {
+            Some(threshold) if self.shutdown.active_sessions() >= threshold => Health::Overloaded,
+            _ => Health::Accepting,
+        }
+    }
+}
+
+impl fmt::Debug for HealthProbe {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HealthProbe")
+            .field("overload_threshold", &self.overload_threshold)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn health_follows_the_sessions() {
+        let shutdown = ShutdownHandle::default();
+        let probe = HealthProbe::new(shutdown.clone(), Some(2));
+        assert_eq!(probe.health(), Health::Accepting);
+
+        let first = shutdown.register(1, |_| {});
+        assert_eq!(probe.health(), Health::Accepting);
+        let second = shutdown.register(2, |_| {});
+        assert_eq!(probe.health(), Health::Overloaded);
+        assert!(!probe.health().is_ready());
+        drop(second);
+        assert_eq!(probe.health(), Health::Accepting);
+        assert!(probe.health().is_ready());
+
+        drop(first);
+        futures::executor::block_on(shutdown.shutdown(Duration::ZERO));
+        assert_eq!(probe.health(), Health::Draining);
+        assert_eq!(probe.health().to_string(), "draining");
+    }
+}
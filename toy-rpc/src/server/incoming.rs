@@ -0,0 +1,202 @@
+//! Accept loop exposed as a stream of connections
+//!
+//! `Server::incoming` turns a `TcpListener` into a stream of `IncomingConnection`s,
+//! so the application decides what to do with each connection before it is served.
+//! A connection can be inspected, eg. its peer address, and then either accepted
+//! with the default options, accepted with `AcceptOptions`, or rejected. Once
+//! accepted, a connection is served in its own task exactly like the connections
+//! accepted by `Server::accept`, `Server::accept_websocket` or
+//! `Server::accept_with_tls_config`, including the PROXY protocol, the connection
+//! filter and the connection limits configured on the `ServerBuilder`.
+//!
+//! The errors of the listener are logged and skipped.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use toy_rpc::server::incoming::AcceptOptions;
+//! # use std::collections::HashSet;
+//! # use std::net::IpAddr;
+//! # use futures::StreamExt;
+//! # #[cfg(feature = "async_std_runtime")]
+//! # use async_std::net::TcpListener;
+//! # #[cfg(feature = "tokio_runtime")]
+//! # use tokio::net::TcpListener;
+//! # use toy_rpc::Server;
+//! # async fn run(server: Server, addr: &str, banned: HashSet<IpAddr>) -> Result<(), Box<dyn std::error::Error>> {
+//!
+//! let listener = TcpListener::bind(addr).await?;
+//! let mut incoming = server.incoming(listener);
+//! while let Some(conn) = incoming.next().await {
+//!     match conn.peer_addr().ip() {
+//!         ip if banned.contains(&ip) => conn.reject(),
+//!         ip if ip.is_loopback() => conn.accept(),
+//!         _ => conn.accept_with_options(AcceptOptions::default().websocket().nodelay(true)),
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::net::SocketAddr;
+
+#[cfg(feature = "tls")]
+use rustls::ServerConfig;
+#[cfg(feature = "tls")]
+use std::sync::Arc;
+
+#[cfg(all(feature = "async_std_runtime", not(feature = "tokio_runtime")))]
+use ::async_std::net::TcpStream;
+#[cfg(all(feature = "tokio_runtime", not(feature = "async_std_runtime")))]
+use ::tokio::net::TcpStream;
+
+use super::Server;
+
+/// Transport protocol of an accepted connection
+#[derive(Clone)]
+pub(crate) enum Transport {
+    Tcp,
+    WebSocket,
+    #[cfg(feature = "tls")]
+    Tls(Arc<ServerConfig>),
+}
+
+/// Options of a connection accepted with `IncomingConnection::accept_with_options`
+///
+/// The connection is served over raw TCP by default.
+#[derive(Clone)]
+pub struct AcceptOptions {
+    pub(crate) transport: Transport,
+    pub(crate) nodelay: Option<bool>,
+}
+
+impl Default for AcceptOptions {
+    fn default() -> Self {
+        Self {
+            transport: Transport::Tcp,
+            nodelay: None,
+        }
+    }
+}
+
+impl AcceptOptions {
+    /// Serves the connection with the WebSocket transport, like
+    /// `Server::accept_websocket`
+    pub fn websocket(self) -> Self {
+        let mut options = self;
+        options.transport = Transport::WebSocket;
+        options
+    }
+
+    /// Serves the connection over TLS with the configuration, like
+    /// `Server::accept_with_tls_config`. The configuration can be chosen per
+    /// connection, eg. to present a different certificate.
+    #[cfg(feature = "tls")]
+    #[cfg_attr(feature = "docs", doc(cfg(feature = "tls")))]
+    pub fn tls_config(self, config: impl Into<Arc<ServerConfig>>) -> Self {
+        let mut options = self;
+        options.transport = Transport::Tls(config.into());
+        options
+    }
+
+    /// Sets the `TCP_NODELAY` option of the socket. The option of the socket is
+    /// left unchanged by default.
+    pub fn nodelay(self, enabled: bool) -> Self {
+        let mut options = self;
+        options.nodelay = Some(enabled);
+        options
+    }
+}
+
+/// A connection yielded by `Server::incoming` that is not served yet
+///
+/// The connection is closed if it is dropped without being accepted.
+pub struct IncomingConnection<'a> {
+    pub(crate) server: &'a Server,
+    pub(crate) stream: TcpStream,
+    pub(crate) peer_addr: SocketAddr,
+}
+
+impl<'a> IncomingConnection<'a> {
+    pub(crate) fn new(server: &'a Server, stream: TcpStream) -> Option<Self> {
+        match stream.peer_addr() {
+            Ok(peer_addr) => Some(Self {
+                server,
+                stream,
+                peer_addr,
+            }),
+            Err(err) => {
+                error!("{}", err);
+                None
+            }
+        }
+    }
+
+    /// Address of the peer. This is the address of the load balancer if the PROXY
+    /// protocol is enabled, as the header is only read once the connection is
+    /// accepted.
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.peer_addr
+    }
+
+    /// The socket of the connection, eg. to read its local address or its options
+    pub fn stream(&self) -> &TcpStream {
+        &self.stream
+    }
+
+    /// Serves the connection over raw TCP in a new task
+    ///
+    /// This is enabled if and only if **exactly one** of the the following feature
+    /// flag is turned on
+    /// - `serde_bincode`
+    /// - `serde_json`
+    /// - `serde_cbor`
+    /// - `serde_rmp`
+    /// - `serde_postcard`
+    #[cfg(any(
+        any(feature = "docs", doc),
+        all(
+            feature = "serde_bincode",
+            not(feature = "serde_json"),
+            not(feature = "serde_cbor"),
+            not(feature = "serde_rmp"),
+            not(feature = "serde_postcard"),
+        ),
+        all(
+            feature = "serde_cbor",
+            not(feature = "serde_json"),
+            not(feature = "serde_bincode"),
+            not(feature = "serde_rmp"),
+            not(feature = "serde_postcard"),
+        ),
+        all(
+            feature = "serde_json",
+            not(feature = "serde_bincode"),
+            not(feature = "serde_cbor"),
+            not(feature = "serde_rmp"),
+            not(feature = "serde_postcard"),
+        ),
+        all(
+            feature = "serde_rmp",
+            not(feature = "serde_cbor"),
+            not(feature = "serde_json"),
+            not(feature = "serde_bincode"),
+            not(feature = "serde_postcard"),
+        ),
+        all(
+            feature = "serde_postcard",
+            not(feature = "serde_cbor"),
+            not(feature = "serde_json"),
+            not(feature = "serde_bincode"),
+            not(feature = "serde_rmp"),
+        ),
+    ))]
+    pub fn accept(self) {
+        self.accept_with_options(AcceptOptions::default())
+    }
+
+    /// Closes the connection without serving it
+    pub fn reject(self) {
+        info!("Rejecting incoming connection from {}", self.peer_addr);
+    }
+}
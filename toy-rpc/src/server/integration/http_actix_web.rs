@@ -5,9 +5,11 @@ use actix_web::{web, HttpRequest, HttpResponse};
 use actix_web_actors::ws;
 use cfg_if::cfg_if;
 use flume::Sender;
-use futures::FutureExt;
+use futures::future::{self, Either};
+use futures::{FutureExt, StreamExt};
 use std::{
     collections::HashMap,
+    convert::TryFrom,
     future::Future,
     marker::PhantomData,
     pin::Pin,
@@ -15,22 +17,39 @@ use std::{
     time::Duration,
 };
 
+#[cfg(all(feature = "codec_negotiation", not(feature = "serde_json")))]
+use crate::codec::negotiate::{self, ContentType};
+#[cfg(feature = "metrics")]
+use crate::server::metrics::{self, CallMeter, MetricsRegistry};
+#[cfg(feature = "signing")]
+use crate::signing::{ResponseSigner, Signed, SIGNATURE_MARKER};
 use crate::{
     codec::{EraseDeserializer, Marshal, Unmarshal},
     error::Error,
+    extension::ExtensionMap,
     message::{ErrorMessage, MessageId},
-    protocol::Header,
+    metadata::{Context as CallContext, Metadata, METADATA_MARKER},
+    payload::PayloadAccounting,
+    protocol::{Header, InboundBody, OutboundBody},
     server::{
+        audit::{AuditSink, Auditor, PendingCall},
         broker::ServerBrokerItem,
+        health::Health,
+        naming::Routes,
         pubsub::{PubSubItem, PubSubResponder},
-        reader::{get_service, handle_cancel},
-        writer::ServerWriterItem,
+        reader::{get_service, handle_cancel, handle_extension, replay_count, stash_metadata},
+        shutdown::{SessionGuard, ShutdownHandle, GOING_AWAY, SHUTDOWN_REASON},
+        stats::{ConnectionGuard, ConnectionRegistry},
+        writer::{metadata_item, publication_header, ServerWriterItem},
         ClientId,
     },
-    service::{AsyncServiceMap, HandlerResult},
+    service::{HandlerResult, Success},
+    streaming::SinkArgument,
+    timing::{TimeoutPhase, TimingPolicy},
+    transport::ws::chunk::{self, Reassembly},
 };
 
-use crate::server::broker::execute_call;
+use crate::server::broker::{execute_call, finish_timed_call, instrument_call, start_call};
 
 // =============================================================================
 // `WsMessageActor`
@@ -43,9 +62,31 @@ use crate::server::broker::execute_call;
 pub struct WsMessageActor<C> {
     client_id: ClientId,
     pubsub_broker: Sender<PubSubItem>,
-    services: Arc<AsyncServiceMap>,
+    services: Arc<Routes>,
+    extensions: Arc<ExtensionMap>,
+    audit: Option<Arc<dyn AuditSink>>,
+    auditor: Option<Auditor>,
+    payload: Arc<PayloadAccounting>,
     manager: Option<Recipient<ServerBrokerItem>>,
+    // arguments of the calls that are sent by a `ClientSink`
+    sinks: HashMap<MessageId, SinkArgument>,
+    // metadata of the requests that are not read yet
+    metadata: HashMap<MessageId, Metadata>,
+    shutdown: ShutdownHandle,
+    session: Option<SessionGuard>,
+    connections: ConnectionRegistry,
+    // keeps the session registered, which calls the lifecycle hooks
+    connection: Option<ConnectionGuard>,
     req_header: Option<Header>,
+    // binary messages of the payload being read
+    chunks: Reassembly,
+    #[cfg(feature = "signing")]
+    signer: Option<Arc<ResponseSigner>>,
+    #[cfg(feature = "metrics")]
+    metrics: Arc<MetricsRegistry>,
+    // content type that the next response is encoded with instead of the codec's
+    #[cfg(all(feature = "codec_negotiation", not(feature = "serde_json")))]
+    content_type: Option<(MessageId, ContentType)>,
     marker: PhantomData<C>,
 }
 
@@ -54,7 +95,70 @@ impl<C> WsMessageActor<C> {
         if let Some(ref manager) = self.manager {
             manager
                 .do_send(item)
-                .unwrap_or_else(|err| log::error!("{}", err));
+                .unwrap_or_else(|err| error!("{}", err));
+        }
+    }
+}
+
+impl<C> WsMessageActor<C>
+where
+    C: Marshal + Unmarshal + Unpin + 'static,
+{
+    /// Looks up the service of a request and passes the call to the manager. The
+    /// argument of a `SinkRequest` is passed to the handler instead of the body.
+    fn request(
+        &mut self,
+        id: MessageId,
+        service_method: String,
+        timeout: Duration,
+        buf: &[u8],
+        deserializer: Box<InboundBody>,
+        ctx: &mut <Self as Actor>::Context,
+    ) {
+        let service_method = self.services.resolve(service_method);
+        let metadata = self.metadata.remove(&id).unwrap_or_default();
+        let audit = self
+            .auditor
+            .as_ref()
+            .map(|_| PendingCall::new(&service_method, buf));
+        self.payload
+            .counters
+            .record_request(&service_method, buf.len());
+        if let Some(connection) = &self.connection {
+            connection.counters().call();
+        }
+        let checked = self
+            .payload
+            .limits
+            .check_request(&service_method, buf.len());
+        match checked.and_then(|_| get_service(&self.services, service_method.clone())) {
+            Ok((call, method)) => {
+                let item = ServerBrokerItem::Request {
+                    call,
+                    id,
+                    service_method,
+                    method,
+                    duration: timeout,
+                    deserializer,
+                    metadata,
+                    audit,
+                };
+                self.send_to_manager(item);
+            }
+            Err(err) => {
+                warn!("{}", &err);
+                let result = Err(err);
+                if let (Some(auditor), Some(audit)) = (&self.auditor, audit) {
+                    auditor.record(audit, &result);
+                }
+                let item = ServerWriterItem::Response {
+                    id,
+                    result,
+                    service_method: None,
+                };
+                self.send_via_context(item, ctx)
+                    .unwrap_or_else(|err| error!("{}", err));
+            }
         }
     }
 }
@@ -67,15 +171,37 @@ where
 
     /// Start a new `ExecutionManager`
     fn started(&mut self, ctx: &mut Self::Context) {
+        let connection = self.connections.register(self.client_id);
+        let timing = connection.timing();
+        self.connection = Some(connection);
         let responder: Recipient<ServerWriterItem> = ctx.address().recipient();
         let manager = ExecutionBroker {
             client_id: self.client_id,
+            timing,
             responder,
             pubsub_broker: self.pubsub_broker.clone(),
             executions: HashMap::new(),
+            methods: HashMap::new(),
+            contexts: HashMap::new(),
+            auditor: self
+                .audit
+                .clone()
+                .map(|sink| Auditor::new(sink, self.client_id)),
+            closing: false,
+            #[cfg(feature = "metrics")]
+            meter: CallMeter::new(self.metrics.clone()),
         };
+        self.auditor = self
+            .audit
+            .clone()
+            .map(|sink| Auditor::new(sink, self.client_id));
         let addr = manager.start();
 
+        let recipient: Recipient<ServerBrokerItem> = addr.clone().recipient();
+        self.session = Some(self.shutdown.register(self.client_id, move |grace| {
+            // the session may have ended already
+            let _ = recipient.do_send(ServerBrokerItem::Shutdown(grace));
+        }));
         self.manager = Some(addr.recipient());
     }
 
@@ -83,6 +209,10 @@ where
         self.send_to_manager(ServerBrokerItem::Stop);
         Running::Stop
     }
+
+    fn stopped(&mut self, _: &mut Self::Context) {
+        self.connection.take();
+    }
 }
 
 impl<C> StreamHandler<Result<ws::Message, ws::ProtocolError>> for WsMessageActor<C>
@@ -94,7 +224,7 @@ where
             Ok(ws::Message::Ping(msg)) => ctx.pong(&msg),
             Ok(ws::Message::Pong(_)) => {}
             Ok(ws::Message::Text(text)) => {
-                log::error!(
+                warn!(
                     "Received Text message: {} while expecting a binary message",
                     text
                 );
@@ -102,86 +232,154 @@ where
             Ok(ws::Message::Continuation(_)) => {}
             Ok(ws::Message::Nop) => {}
             Ok(ws::Message::Close(_)) => {
-                log::debug!("Received closing message");
+                debug!("Received closing message");
                 ctx.stop();
             }
-            Ok(ws::Message::Binary(buf)) => match self.req_header.take() {
-                None => match C::unmarshal(&buf) {
-                    Ok(h) => {
-                        self.req_header.get_or_insert(h);
-                    }
-                    Err(err) => {
-                        log::error!("Failed to unmarshal request header: {}", err);
+            Ok(ws::Message::Binary(buf)) => match self.chunks.push(buf.to_vec()) {
+                Ok(Some(payload)) => self.handle_payload(payload, ctx),
+                Ok(None) => {}
+                Err(err) => error!("{}", err),
+            },
+            Err(err) => {
+                error!("{}", err);
+            }
+        }
+    }
+}
+
+impl<C> WsMessageActor<C>
+where
+    C: Marshal + Unmarshal + EraseDeserializer + Unpin + 'static,
+{
+    /// Handles a payload reassembled from the binary messages
+    fn handle_payload(&mut self, buf: Vec<u8>, ctx: &mut <Self as Actor>::Context) {
+        match self.req_header.take() {
+            None => match C::unmarshal(&buf) {
+                Ok(h) => {
+                    self.req_header.get_or_insert(h);
+                }
+                Err(err) => {
+                    warn!("Failed to unmarshal request header: {}", err);
+                }
+            },
+            Some(header) => match header {
+                Header::Request {
+                    id,
+                    service_method,
+                    timeout,
+                } => {
+                    let deserializer = C::from_bytes(buf.to_vec());
+                    self.request(id, service_method, timeout, &buf, deserializer, ctx);
+                }
+                Header::SinkRequest {
+                    id,
+                    service_method,
+                    timeout,
+                } => {
+                    let (argument, deserializer) = SinkArgument::open();
+                    self.sinks.insert(id, argument);
+                    self.request(id, service_method, timeout, &buf, deserializer, ctx);
+                }
+                Header::SinkItem(id) => {
+                    if let Some(argument) = self.sinks.get(&id) {
+                        argument.push(C::from_bytes(buf.to_vec()));
                     }
-                },
-                Some(header) => match header {
-                    Header::Request {
-                        id,
-                        service_method,
-                        timeout,
-                    } => {
-                        let deserializer = C::from_bytes(buf.to_vec());
-                        match get_service(&self.services, service_method) {
-                            Ok((call, method)) => {
-                                let item = ServerBrokerItem::Request {
-                                    call,
-                                    id,
-                                    method,
-                                    duration: timeout,
-                                    deserializer,
-                                };
-                                self.send_to_manager(item);
-                            }
-                            Err(err) => {
-                                log::error!("{}", &err);
-                                let item = ServerWriterItem::Response {
-                                    id,
-                                    result: Err(err),
-                                };
-                                Self::send_via_context(item, ctx)
-                                    .unwrap_or_else(|err| log::error!("{}", err));
-                            }
+                }
+                Header::SinkEnd(id) => {
+                    // dropping the argument ends the stream of the handler
+                    self.sinks.remove(&id);
+                }
+                Header::Response { id, is_ok } => {
+                    warn!("Server received Response {{id: {}, is_ok: {}}}", id, is_ok);
+                }
+                Header::Cancel(id) => {
+                    let deserializer = C::from_bytes(buf.to_vec());
+                    match handle_cancel(id, deserializer) {
+                        Ok(_) => {
+                            self.sinks.remove(&id);
+                            let item = ServerBrokerItem::Cancel(id);
+                            self.send_to_manager(item);
                         }
-                    }
-                    Header::Response { id, is_ok } => {
-                        log::error!("Server received Response {{id: {}, is_ok: {}}}", id, is_ok);
-                    }
-                    Header::Cancel(id) => {
-                        let deserializer = C::from_bytes(buf.to_vec());
-                        match handle_cancel(id, deserializer) {
-                            Ok(_) => {
-                                let item = ServerBrokerItem::Cancel(id);
-                                self.send_to_manager(item);
-                            }
-                            Err(err) => {
-                                let item = ServerWriterItem::Response {
-                                    id,
-                                    result: Err(err),
-                                };
-                                Self::send_via_context(item, ctx)
-                                    .unwrap_or_else(|err| log::error!("{}", err));
-                            }
+                        Err(err) => {
+                            let item = ServerWriterItem::Response {
+                                id,
+                                result: Err(err),
+                                service_method: None,
+                            };
+                            self.send_via_context(item, ctx)
+                                .unwrap_or_else(|err| error!("{}", err));
                         }
                     }
-                    Header::Publish { id, topic } => {
-                        let content = buf.to_vec();
-                        self.send_to_manager(ServerBrokerItem::Publish { id, topic, content });
-                    }
-                    Header::Subscribe { id, topic } => {
-                        self.send_to_manager(ServerBrokerItem::Subscribe { id, topic });
-                    }
-                    Header::Unsubscribe { id, topic } => {
-                        self.send_to_manager(ServerBrokerItem::Unsubscribe { id, topic });
+                }
+                Header::Publish { id, topic } => {
+                    let content = buf.to_vec();
+                    self.send_to_manager(ServerBrokerItem::Publish {
+                        id,
+                        topic,
+                        content,
+                        tag: None,
+                        ack: false,
+                    });
+                }
+                Header::TaggedPublish { id, topic, tag } => {
+                    let content = buf.to_vec();
+                    self.send_to_manager(ServerBrokerItem::Publish {
+                        id,
+                        topic,
+                        content,
+                        tag: Some(tag),
+                        ack: false,
+                    });
+                }
+                Header::AckedPublish { id, topic, tag } => {
+                    let content = buf.to_vec();
+                    self.send_to_manager(ServerBrokerItem::Publish {
+                        id,
+                        topic,
+                        content,
+                        tag,
+                        ack: true,
+                    });
+                }
+                Header::Subscribe { id, topic } => {
+                    let mut body = C::from_bytes(buf.to_vec());
+                    let replay = replay_count(&mut body);
+                    self.send_to_manager(ServerBrokerItem::Subscribe { id, topic, replay });
+                }
+                Header::Unsubscribe { id, topic } => {
+                    self.send_to_manager(ServerBrokerItem::Unsubscribe { id, topic });
+                }
+                Header::Ack(id) => {
+                    self.send_to_manager(ServerBrokerItem::Ack(id));
+                }
+                Header::Produce { .. } => {}
+                Header::Consume { .. } => {}
+                Header::Reject { .. } => {}
+                Header::StreamItem { .. } => {}
+                Header::StreamEnd(_) => {}
+                Header::Ext {
+                    id,
+                    content,
+                    marker,
+                } if marker == METADATA_MARKER => {
+                    stash_metadata(&mut self.metadata, id, &content);
+                }
+                Header::Ext {
+                    id,
+                    content,
+                    marker,
+                } => {
+                    if let Some(content) = handle_extension(&self.extensions, marker, content) {
+                        let item = ServerWriterItem::Ext {
+                            id,
+                            marker,
+                            content,
+                        };
+                        self.send_via_context(item, ctx)
+                            .unwrap_or_else(|err| error!("{}", err));
                     }
-                    Header::Ack(_) => {}
-                    Header::Produce { .. } => {}
-                    Header::Consume { .. } => {}
-                    Header::Ext { .. } => {}
-                },
+                }
             },
-            Err(err) => {
-                log::error!("{}", err);
-            }
         }
     }
 }
@@ -193,7 +391,8 @@ where
     type Result = ();
 
     fn handle(&mut self, msg: ServerWriterItem, ctx: &mut Self::Context) -> Self::Result {
-        Self::send_via_context(msg, ctx).unwrap_or_else(|err| log::error!("{}", err));
+        self.send_via_context(msg, ctx)
+            .unwrap_or_else(|err| error!("{}", err));
     }
 }
 
@@ -201,43 +400,193 @@ impl<C> WsMessageActor<C>
 where
     C: Marshal + Unmarshal + Unpin + 'static,
 {
+    /// Writes a payload as a sequence of binary messages
+    fn write_payload(&self, ctx: &mut <Self as Actor>::Context, payload: &[u8]) {
+        for chunk in chunk::split(payload, self.chunks.chunked()) {
+            ctx.binary(chunk);
+        }
+    }
+
     fn send_via_context(
+        &mut self,
         item: ServerWriterItem,
         ctx: &mut <Self as Actor>::Context,
     ) -> Result<(), Error> {
         match item {
-            ServerWriterItem::Response { id, result } => {
-                match result {
-                    Ok(body) => {
-                        log::trace!("Message {} Success", &id);
-                        let header = Header::Response { id, is_ok: true };
-                        let buf = C::marshal(&header)?;
-                        ctx.binary(buf);
-
-                        let buf = C::marshal(&body)?;
-                        ctx.binary(buf);
-                    }
-                    Err(err) => {
-                        log::trace!("Message {} Error", id.clone());
-                        let header = Header::Response { id, is_ok: false };
-                        let msg = ErrorMessage::from_err(err)?;
-
-                        // compose error response header
-                        let buf = C::marshal(&header)?;
-                        ctx.binary(buf);
-                        let buf = C::marshal(&msg)?;
-                        ctx.binary(buf);
-                    }
+            ServerWriterItem::Response {
+                id,
+                result,
+                service_method,
+            } => {
+                let result = result.and_then(Success::into_reply);
+                let header = |is_ok| Header::Response { id, is_ok };
+                self.send_result(id, result, service_method, header, ctx)?;
+            }
+            ServerWriterItem::StreamItem {
+                id,
+                result,
+                service_method,
+            } => {
+                let header = |is_ok| Header::StreamItem { id, is_ok };
+                self.send_result(id, result, service_method, header, ctx)?;
+            }
+            ServerWriterItem::StreamEnd(id) => {
+                let buf = C::marshal(&Header::StreamEnd(id))?;
+                self.write_payload(ctx, &buf);
+                let buf = C::marshal(&())?;
+                self.write_payload(ctx, &buf);
+            }
+            ServerWriterItem::Publication {
+                id,
+                topic,
+                content,
+                tag,
+                ack,
+            } => {
+                let header = publication_header(id, topic, tag, ack);
+                let buf = C::marshal(&header)?;
+                self.write_payload(ctx, &buf);
+                self.write_payload(ctx, &content);
+            }
+            ServerWriterItem::Ext {
+                id,
+                marker,
+                content,
+            } => {
+                #[cfg(all(feature = "codec_negotiation", not(feature = "serde_json")))]
+                if marker == METADATA_MARKER {
+                    let metadata = crate::metadata::decode(&content);
+                    self.content_type = negotiate::response_content_type(&metadata)
+                        .map(|content_type| (id, content_type));
+                }
+                let header = Header::Ext {
+                    id,
+                    content,
+                    marker,
                 };
+                let buf = C::marshal(&header)?;
+                self.write_payload(ctx, &buf);
+                let buf = C::marshal(&())?;
+                self.write_payload(ctx, &buf);
+            }
+            ServerWriterItem::Ack(id, report) => {
+                let buf = C::marshal(&Header::Ack(id))?;
+                self.write_payload(ctx, &buf);
+                let buf = C::marshal(&report)?;
+                self.write_payload(ctx, &buf);
+            }
+            ServerWriterItem::Rejected { id, topic, error } => {
+                let header = Header::Reject { id, topic };
+                let msg = ErrorMessage::try_from(error)?;
+                let buf = C::marshal(&header)?;
+                self.write_payload(ctx, &buf);
+                let buf = C::marshal(&msg)?;
+                self.write_payload(ctx, &buf);
+            }
+            ServerWriterItem::Close => {
+                ctx.close(Some(ws::CloseReason {
+                    code: ws::CloseCode::from(GOING_AWAY),
+                    description: Some(SHUTDOWN_REASON.to_string()),
+                }));
+                ctx.stop();
+            }
+        }
+
+        Ok(())
+    }
+
+    fn send_result(
+        &mut self,
+        id: MessageId,
+        result: Result<Box<OutboundBody>, Error>,
+        service_method: Option<String>,
+        header: impl FnOnce(bool) -> Header,
+        ctx: &mut <Self as Actor>::Context,
+    ) -> Result<(), Error> {
+        let result = match (result, service_method) {
+            (Ok(body), Some(service_method)) => {
+                let buf = self.marshal(id, &body)?;
+                self.payload
+                    .counters
+                    .record_response(&service_method, buf.len());
+                self.payload
+                    .limits
+                    .check_response(&service_method, buf.len())
+                    .map(|_| buf)
+            }
+            (Ok(body), None) => self.marshal(id, &body),
+            (Err(err), _) => Err(err),
+        };
+        match result {
+            Ok(body) => {
+                trace!("Message {} Success", &id);
+                let header = header(true);
+                #[cfg(feature = "signing")]
+                self.send_signature(&header, &body, ctx)?;
+                let buf = C::marshal(&header)?;
+                self.write_payload(ctx, &buf);
+                self.write_payload(ctx, &body);
             }
-            ServerWriterItem::Publication { id, topic, content } => {
-                let header = Header::Publish { id, topic };
+            Err(err) => {
+                trace!("Message {} Error", id.clone());
+                let msg = ErrorMessage::try_from(err)?;
+                let body = self.marshal(id, &msg)?;
+
+                // compose error response header
+                let header = header(false);
+                #[cfg(feature = "signing")]
+                self.send_signature(&header, &body, ctx)?;
                 let buf = C::marshal(&header)?;
-                ctx.binary(buf);
-                ctx.binary(content.to_vec());
+                self.write_payload(ctx, &buf);
+                self.write_payload(ctx, &body);
+            }
+        };
+        Ok(())
+    }
+
+    /// Serializes the body of a response, with the content type that its request
+    /// asked for if there is one
+    #[cfg_attr(
+        not(all(feature = "codec_negotiation", not(feature = "serde_json"))),
+        allow(unused_variables)
+    )]
+    fn marshal<S: serde::Serialize>(&mut self, id: MessageId, body: &S) -> Result<Vec<u8>, Error> {
+        #[cfg(all(feature = "codec_negotiation", not(feature = "serde_json")))]
+        if let Some((encoded, content_type)) = self.content_type {
+            if encoded == id {
+                self.content_type = None;
+                return content_type.marshal(body);
             }
         }
+        C::marshal(body)
+    }
 
+    /// Sends the signature of a response right before it, if the responses are signed
+    #[cfg(feature = "signing")]
+    fn send_signature(
+        &self,
+        header: &Header,
+        body: &[u8],
+        ctx: &mut <Self as Actor>::Context,
+    ) -> Result<(), Error> {
+        let signer = match &self.signer {
+            Some(signer) => signer,
+            None => return Ok(()),
+        };
+        let (kind, id, is_ok) = match *header {
+            Header::Response { id, is_ok } => (Signed::Response, id, is_ok),
+            Header::StreamItem { id, is_ok } => (Signed::StreamItem, id, is_ok),
+            _ => return Ok(()),
+        };
+        let header = Header::Ext {
+            id,
+            content: signer.sign(kind, id, is_ok, body),
+            marker: SIGNATURE_MARKER,
+        };
+        let buf = C::marshal(&header)?;
+        self.write_payload(ctx, &buf);
+        let buf = C::marshal(&())?;
+        self.write_payload(ctx, &buf);
         Ok(())
     }
 }
@@ -252,9 +601,30 @@ where
 /// `ExecutionActor`
 struct ExecutionBroker {
     client_id: ClientId,
+    timing: TimingPolicy,
     responder: Recipient<ServerWriterItem>,
     pubsub_broker: Sender<PubSubItem>,
     executions: HashMap<MessageId, Sender<()>>,
+    // service methods of the calls in flight, for the payload accounting
+    methods: HashMap<MessageId, String>,
+    // context of the calls in flight, which collects the metadata of the responses
+    contexts: HashMap<MessageId, CallContext>,
+    auditor: Option<Auditor>,
+    // whether the server is shutting down
+    closing: bool,
+    #[cfg(feature = "metrics")]
+    meter: CallMeter,
+}
+
+impl ExecutionBroker {
+    /// Closes the connection because the server is shutting down. The calls in
+    /// flight are stopped with the broker.
+    fn close(&mut self, ctx: &mut Context<Self>) {
+        self.responder
+            .do_send(ServerWriterItem::Close)
+            .unwrap_or_else(|err| error!("{}", err));
+        ctx.stop();
+    }
 }
 
 impl Actor for ExecutionBroker {
@@ -262,8 +632,13 @@ impl Actor for ExecutionBroker {
 
     fn stopping(&mut self, _: &mut Self::Context) -> Running {
         for (_, tx) in self.executions.drain() {
-            tx.send(()).unwrap_or_else(|err| log::error!("{}", err));
+            tx.send(()).unwrap_or_else(|err| error!("{}", err));
+        }
+        if let Some(auditor) = &mut self.auditor {
+            auditor.cancel_all();
         }
+        #[cfg(feature = "metrics")]
+        self.meter.cancel_all();
 
         Running::Stop
     }
@@ -277,19 +652,67 @@ impl actix::Handler<ServerBrokerItem> for ExecutionBroker {
             ServerBrokerItem::Request {
                 call,
                 id,
+                service_method,
                 method,
                 duration,
                 deserializer,
+                metadata,
+                audit,
             } => {
-                let call_fut = call(method, deserializer);
+                if self.closing {
+                    let result = Err(Error::ExecutionError(SHUTDOWN_REASON.into()));
+                    if let (Some(auditor), Some(audit)) = (&self.auditor, audit) {
+                        auditor.record(audit, &result);
+                    }
+                    #[cfg(feature = "metrics")]
+                    self.meter.record(id, &service_method, &result);
+                    self.responder
+                        .do_send(ServerWriterItem::Response {
+                            id,
+                            result,
+                            service_method: Some(service_method),
+                        })
+                        .unwrap_or_else(|e| error!("{}", e));
+                    return;
+                }
+                if let (Some(auditor), Some(audit)) = (&mut self.auditor, audit) {
+                    auditor.start(id, audit);
+                }
+                #[cfg(feature = "metrics")]
+                self.meter.start(id, &service_method);
+                let context = CallContext::new(id, service_method.clone(), metadata);
+                #[cfg(all(feature = "codec_negotiation", not(feature = "serde_json")))]
+                negotiate::accept(&context);
+                let call_fut = instrument_call(
+                    self.client_id,
+                    id,
+                    &service_method,
+                    start_call(&call, method, deserializer, &context),
+                );
+                self.methods.insert(id, service_method);
+                self.contexts.insert(id, context.clone());
                 let broker = ctx.address().recipient();
+                let timing = self.timing;
 
                 let fut: Pin<Box<dyn Future<Output = ()>>> = Box::pin(async move {
-                    let result = execute_timed_call(id, duration, call_fut).await;
-                    let item = ServerBrokerItem::Response { id, result };
-                    broker
-                        .do_send(item)
-                        .unwrap_or_else(|e| log::error!("{}", e));
+                    let result = execute_timed_call(id, duration, timing, &context, call_fut).await;
+                    // the items of a streaming response are sent as they are produced
+                    let item = match result {
+                        Ok(Success::Stream(mut items)) => {
+                            while let Some(result) = items.next().await {
+                                let item = ServerBrokerItem::StreamItem { id, result };
+                                if let Err(err) = broker.do_send(item) {
+                                    error!("{}", err);
+                                    return;
+                                }
+                                // gives a cancellation the chance to abort the stream
+                                ::tokio::task::yield_now().await;
+                            }
+                            ServerBrokerItem::StreamEnd(id)
+                        }
+                        result => ServerBrokerItem::Response { id, result },
+                    };
+                    broker.do_send(item).unwrap_or_else(|e| error!("{}", e));
                 });
                 let (tx, rx) = flume::bounded(1);
                 self.executions.insert(id, tx);
@@ -307,56 +730,175 @@ impl actix::Handler<ServerBrokerItem> for ExecutionBroker {
             }
             ServerBrokerItem::Response { id, result } => {
                 self.executions.remove(&id);
-                let msg = ServerWriterItem::Response { id, result };
+                if let Some(auditor) = &mut self.auditor {
+                    auditor.finish(id, &result);
+                }
+                #[cfg(feature = "metrics")]
+                self.meter.finish(id, &result);
+                if let Some(msg) = metadata_item(id, self.contexts.remove(&id)) {
+                    self.responder
+                        .do_send(msg)
+                        .unwrap_or_else(|e| error!("{}", e));
+                }
+                let msg = ServerWriterItem::Response {
+                    id,
+                    result,
+                    service_method: self.methods.remove(&id),
+                };
                 self.responder
                     .do_send(msg)
-                    .unwrap_or_else(|e| log::error!("{}", e));
+                    .unwrap_or_else(|e| error!("{}", e));
+                if self.closing && self.executions.is_empty() {
+                    self.close(ctx);
+                }
+            }
+            ServerBrokerItem::StreamItem { id, result } => {
+                self.responder
+                    .do_send(ServerWriterItem::StreamItem {
+                        id,
+                        result,
+                        service_method: self.methods.get(&id).cloned(),
+                    })
+                    .unwrap_or_else(|e| error!("{}", e));
+            }
+            ServerBrokerItem::StreamEnd(id) => {
+                self.executions.remove(&id);
+                self.methods.remove(&id);
+                self.contexts.remove(&id);
+                if let Some(auditor) = &mut self.auditor {
+                    auditor.finish::<()>(id, &Ok(()));
+                }
+                #[cfg(feature = "metrics")]
+                self.meter.finish::<()>(id, &Ok(()));
+                self.responder
+                    .do_send(ServerWriterItem::StreamEnd(id))
+                    .unwrap_or_else(|e| error!("{}", e));
+                if self.closing && self.executions.is_empty() {
+                    self.close(ctx);
+                }
             }
             ServerBrokerItem::Cancel(id) => {
-                log::debug!("Sending Cancel({})", &id);
+                debug!("Sending Cancel({})", &id);
+                self.methods.remove(&id);
+                self.contexts.remove(&id);
                 if let Some(exec) = self.executions.remove(&id) {
-                    exec.send(()).unwrap_or_else(|e| log::error!("{}", e));
+                    exec.send(()).unwrap_or_else(|e| error!("{}", e));
                 }
+                if let Some(auditor) = &mut self.auditor {
+                    auditor.finish::<()>(id, &Err(Error::Canceled(Some(id))));
+                }
+                #[cfg(feature = "metrics")]
+                self.meter.finish::<()>(id, &Err(Error::Canceled(Some(id))));
             }
-            ServerBrokerItem::Publish { id, topic, content } => {
+            ServerBrokerItem::Publish {
+                id,
+                topic,
+                content,
+                tag,
+                ack,
+            } => {
                 let content = Arc::new(content);
                 let msg = PubSubItem::Publish {
                     msg_id: id,
                     topic,
                     content,
+                    tag,
+                    publisher: Some(PubSubResponder::Recipient(ctx.address().recipient())),
+                    ack,
+                };
+                self.pubsub_broker
+                    .send(msg)
+                    .unwrap_or_else(|err| error!("{}", err));
+            }
+            ServerBrokerItem::Ack(id) => {
+                let msg = PubSubItem::Ack {
+                    client_id: self.client_id,
+                    msg_id: id,
                 };
                 self.pubsub_broker
                     .send(msg)
-                    .unwrap_or_else(|err| log::error!("{}", err));
+                    .unwrap_or_else(|err| error!("{}", err));
             }
-            ServerBrokerItem::Subscribe { id, topic } => {
-                log::debug!("Message ID: {}, Subscribe to topic: {}", &id, &topic);
+            ServerBrokerItem::Accepted(id, report) => {
+                self.responder
+                    .do_send(ServerWriterItem::Ack(id, report))
+                    .unwrap_or_else(|err| error!("{}", err));
+            }
+            ServerBrokerItem::Subscribe { id, topic, replay } => {
+                debug!("Message ID: {}, Subscribe to topic: {}", &id, &topic);
                 let sender = PubSubResponder::Recipient(ctx.address().recipient());
                 let msg = PubSubItem::Subscribe {
                     client_id: self.client_id,
+                    msg_id: id,
                     topic,
                     sender,
+                    replay,
                 };
                 self.pubsub_broker
                     .send(msg)
-                    .unwrap_or_else(|err| log::error!("{}", err));
+                    .unwrap_or_else(|err| error!("{}", err));
             }
             ServerBrokerItem::Unsubscribe { id, topic } => {
-                log::debug!("Message ID: {}, Unsubscribe from topic: {}", &id, &topic);
+                debug!("Message ID: {}, Unsubscribe from topic: {}", &id, &topic);
                 let msg = PubSubItem::Unsubscribe {
                     client_id: self.client_id,
                     topic,
                 };
                 self.pubsub_broker
                     .send(msg)
-                    .unwrap_or_else(|err| log::error!("{}", err));
+                    .unwrap_or_else(|err| error!("{}", err));
+            }
+            ServerBrokerItem::Publication {
+                id,
+                topic,
+                content,
+                tag,
+                ack,
+            } => {
+                let msg = ServerWriterItem::Publication {
+                    id,
+                    topic,
+                    content,
+                    tag,
+                    ack,
+                };
+                self.responder
+                    .do_send(msg)
+                    .unwrap_or_else(|err| error!("{}", err));
+            }
+            ServerBrokerItem::Rejected { id, topic, error } => {
+                let msg = ServerWriterItem::Rejected { id, topic, error };
+                self.responder
+                    .do_send(msg)
+                    .unwrap_or_else(|err| error!("{}", err));
             }
-            ServerBrokerItem::Publication { id, topic, content } => {
-                let msg = ServerWriterItem::Publication { id, topic, content };
+            ServerBrokerItem::Ext {
+                id,
+                marker,
+                content,
+            } => {
+                let msg = ServerWriterItem::Ext {
+                    id,
+                    marker,
+                    content,
+                };
                 self.responder
                     .do_send(msg)
-                    .unwrap_or_else(|err| log::error!("{}", err));
+                    .unwrap_or_else(|err| error!("{}", err));
             }
+            ServerBrokerItem::Shutdown(grace) => {
+                debug!(
+                    "Shutting down with {} calls in flight",
+                    self.executions.len()
+                );
+                self.closing = true;
+                if self.executions.is_empty() {
+                    self.close(ctx);
+                } else {
+                    ctx.notify_later(ServerBrokerItem::Close, grace);
+                }
+            }
+            ServerBrokerItem::Close => self.close(ctx),
             ServerBrokerItem::Stop => {
                 ctx.stop();
             }
@@ -369,11 +911,81 @@ impl actix::Handler<ServerBrokerItem> for ExecutionBroker {
 async fn execute_timed_call(
     id: MessageId,
     duration: Duration,
+    timing: TimingPolicy,
+    context: &CallContext,
     fut: impl Future<Output = HandlerResult>,
 ) -> HandlerResult {
-    match actix_rt::time::timeout(duration, execute_call(id, fut)).await {
-        Ok(res) => res,
-        Err(_) => Err(Error::Timeout(Some(id))),
+    let stopwatch = context.stopwatch();
+    stopwatch.start_task();
+    let execution = Box::pin(execute_call(id, fut));
+    // the handler of a blocking method may return before its start is seen
+    let queued = future::select(execution, Box::pin(stopwatch.started()));
+    let result = match actix_rt::time::timeout(timing.queue_limit(duration), queued).await {
+        Ok(Either::Left((res, _))) => Ok(res),
+        Ok(Either::Right((_, execution))) => {
+            let limit = timing.execution_limit(duration, stopwatch.queued());
+            actix_rt::time::timeout(limit, execution)
+                .await
+                .map_err(|_| TimeoutPhase::Execution)
+        }
+        Err(_) => Err(TimeoutPhase::Queue),
+    };
+    finish_timed_call(id, timing, context, result)
+}
+
+// =============================================================================
+// Health
+// =============================================================================
+
+/// Extracts the health of the `web::Data<Server>` of the app. See the `health`
+/// module for details.
+///
+/// # Example
+///
+/// ```no_run
+/// # use actix_web::{web, App, HttpServer};
+/// # use toy_rpc::Server;
+/// # use toy_rpc::server::health::Health;
+/// # fn run(server: Server) {
+/// let app_data = web::Data::new(server);
+///
+/// HttpServer::new(move || {
+///     App::new()
+///         .app_data(app_data.clone())
+///         .route("/healthz", web::get().to(|health: Health| async move { health }))
+///         .service(web::scope("/rpc/").configure(toy_rpc::Server::scope_config))
+/// })
+/// # ;
+/// # }
+/// ```
+impl actix_web::FromRequest for Health {
+    type Error = actix_web::Error;
+    type Future = futures::future::Ready<Result<Self, Self::Error>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _: &mut actix_web::dev::Payload) -> Self::Future {
+        let health = match req.app_data::<web::Data<crate::server::Server>>() {
+            Some(server) => Ok(server.health()),
+            None => Err(actix_web::error::ErrorInternalServerError(
+                "The server is not in the data of the app",
+            )),
+        };
+        futures::future::ready(health)
+    }
+}
+
+impl actix_web::Responder for Health {
+    type Error = actix_web::Error;
+    type Future = futures::future::Ready<Result<HttpResponse, Self::Error>>;
+
+    /// Answers `200 OK` if the server is accepting, `503 Service Unavailable`
+    /// otherwise, with the name of the state as the body
+    fn respond_to(self, _: &HttpRequest) -> Self::Future {
+        let mut response = match self.is_ready() {
+            true => HttpResponse::Ok(),
+            false => HttpResponse::ServiceUnavailable(),
+        };
+        futures::future::ready(Ok(response.body(self.as_str())))
     }
 }
 
@@ -387,24 +999,35 @@ cfg_if! {
             not(feature = "serde_json"),
             not(feature = "serde_cbor"),
             not(feature = "serde_rmp"),
+            not(feature = "serde_postcard"),
         ),
         all(
             feature = "serde_cbor",
             not(feature = "serde_json"),
             not(feature = "serde_bincode"),
             not(feature = "serde_rmp"),
+            not(feature = "serde_postcard"),
         ),
         all(
             feature = "serde_json",
             not(feature = "serde_bincode"),
             not(feature = "serde_cbor"),
             not(feature = "serde_rmp"),
+            not(feature = "serde_postcard"),
         ),
         all(
             feature = "serde_rmp",
             not(feature = "serde_cbor"),
             not(feature = "serde_json"),
             not(feature = "serde_bincode"),
+            not(feature = "serde_postcard"),
+        ),
+        all(
+            feature = "serde_postcard",
+            not(feature = "serde_cbor"),
+            not(feature = "serde_json"),
+            not(feature = "serde_bincode"),
+            not(feature = "serde_rmp"),
         ),
         feature = "docs"
     ))] {
@@ -417,18 +1040,55 @@ cfg_if! {
             stream: web::Payload,
         ) -> Result<HttpResponse, actix_web::Error> {
             let services = state.services.clone();
+            let extensions = state.extensions.clone();
             let client_id = state.client_counter.fetch_add(1, Ordering::Relaxed);
             let pubsub_broker = state.pubsub_tx.clone();
+            let audit = state.audit.clone();
+            let payload = state.payload.clone();
+            let shutdown = state.shutdown.clone();
+            let chunked = chunk::offered(
+                req.headers()
+                    .get_all(chunk::PROTOCOL_HEADER)
+                    .filter_map(|value| value.to_str().ok()),
+            );
             let ws_actor: WsMessageActor<DefaultCodec<Vec<u8>, Vec<u8>, ConnTypePayload>>
                 = WsMessageActor {
                     client_id,
                     pubsub_broker,
                     services,
+                    extensions,
+                    audit,
+                    auditor: None,
+                    payload,
                     manager: None,
+                    sinks: HashMap::new(),
+                    metadata: HashMap::new(),
+                    shutdown,
+                    session: None,
+                    connections: state.connections.peer(req.peer_addr()),
+                    connection: None,
                     req_header: None,
+                    chunks: Reassembly::new(chunked),
+                    #[cfg(feature = "signing")]
+                    signer: state.connections.signer(),
+                    #[cfg(feature = "metrics")]
+                    metrics: state.connections.metrics(),
+                    #[cfg(all(feature = "codec_negotiation", not(feature = "serde_json")))]
+                    content_type: None,
                     marker: PhantomData,
                 };
-            ws::start(ws_actor, &req, stream)
+            ws::start_with_protocols(ws_actor, &[chunk::PROTOCOL], &req, stream)
+        }
+
+        #[cfg(feature = "metrics")]
+        async fn serve_metrics(state: web::Data<Server>) -> HttpResponse {
+            let registry = state.connections.metrics();
+            if !registry.exported() {
+                return HttpResponse::NotFound().finish();
+            }
+            HttpResponse::Ok()
+                .content_type(metrics::CONTENT_TYPE)
+                .body(registry.snapshot().encode_prometheus())
         }
 
         impl Server {
@@ -437,6 +1097,8 @@ cfg_if! {
             /// with the `actix-web` feature turned on.
             ///
             /// The `DEFAULT_RPC_PATH` will be appended to the end of the scope's path.
+            /// With `ServerBuilder::export_metrics`, the metrics of the calls are served
+            /// at `METRICS_PATH` next to it.
             ///
             /// This is enabled
             /// if and only if **exactly one** of the the following feature flag is turned on
@@ -444,10 +1106,23 @@ cfg_if! {
             /// - `serde_json`
             /// - `serde_cbor`
             /// - `serde_rmp`
+            /// - `serde_postcard`
             ///
             /// # Example
             ///
-            /// ```
+            /// ```no_run
+            /// # use std::sync::Arc;
+            /// # use toy_rpc::Server;
+            /// # use toy_rpc::macros::export_impl;
+            /// # struct Example { }
+            /// # #[export_impl]
+            /// # impl Example {
+            /// #     #[export_method]
+            /// #     async fn echo(&self, args: String) -> Result<String, String> {
+            /// #         Ok(args)
+            /// #     }
+            /// # }
+            /// # use actix_web::{web, App, HttpServer};
             /// let example_service = Arc::new(Example { });
             /// let server = Server::builder()
             ///     .register(example_service)
@@ -464,17 +1139,22 @@ cfg_if! {
             ///             )
             ///     }
             /// )
+            /// # ;
             /// ```
             #[cfg(any(feature = "http_actix_web", feature = "docs"))]
             #[cfg_attr(feature = "docs", doc(cfg(feature = "http_actix_web")))]
             pub fn scope_config(cfg: &mut web::ServiceConfig) {
-                cfg.service(
-                    web::scope("/")
-                        .service(
-                            web::resource(crate::DEFAULT_RPC_PATH)
-                                .route(web::get().to(index))
-                        )
+                let scope = web::scope("/")
+                    .service(
+                        web::resource(crate::DEFAULT_RPC_PATH)
+                            .route(web::get().to(index))
+                    );
+                #[cfg(feature = "metrics")]
+                let scope = scope.service(
+                    web::resource(metrics::METRICS_PATH)
+                        .route(web::get().to(serve_metrics))
                 );
+                cfg.service(scope);
             }
 
             /// A conevience function that calls the corresponding http handling
@@ -492,10 +1172,23 @@ cfg_if! {
             /// - `serde_json`
             /// - `serde_cbor`
             /// - `serde_rmp`
+            /// - `serde_postcard`
             ///
             /// # Example
             ///
-            /// ```
+            /// ```no_run
+            /// # use std::sync::Arc;
+            /// # use toy_rpc::Server;
+            /// # use toy_rpc::macros::export_impl;
+            /// # struct Example { }
+            /// # #[export_impl]
+            /// # impl Example {
+            /// #     #[export_method]
+            /// #     async fn echo(&self, args: String) -> Result<String, String> {
+            /// #         Ok(args)
+            /// #     }
+            /// # }
+            /// # use actix_web::{web, App, HttpServer};
             /// let example_service = Arc::new(Example { });
             /// let server = Server::builder()
             ///     .register(example_service)
@@ -512,6 +1205,7 @@ cfg_if! {
             ///             )
             ///     }
             /// )
+            /// # ;
             /// ```
             #[cfg(any(all(feature = "http_actix_web", not(feature = "http_tide"),), feature = "docs"))]
             #[cfg_attr(
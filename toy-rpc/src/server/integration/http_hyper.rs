@@ -0,0 +1,372 @@
+//! This module implements integration with `hyper` without any web framework.
+use cfg_if::cfg_if;
+
+cfg_if! {
+    if #[cfg(any(
+        any(feature = "docs", doc),
+        all(
+            feature = "serde_bincode",
+            not(feature = "serde_json"),
+            not(feature = "serde_cbor"),
+            not(feature = "serde_rmp"),
+            not(feature = "serde_postcard"),
+        ),
+        all(
+            feature = "serde_cbor",
+            not(feature = "serde_json"),
+            not(feature = "serde_bincode"),
+            not(feature = "serde_rmp"),
+            not(feature = "serde_postcard"),
+        ),
+        all(
+            feature = "serde_json",
+            not(feature = "serde_bincode"),
+            not(feature = "serde_cbor"),
+            not(feature = "serde_rmp"),
+            not(feature = "serde_postcard"),
+        ),
+        all(
+            feature = "serde_rmp",
+            not(feature = "serde_cbor"),
+            not(feature = "serde_json"),
+            not(feature = "serde_bincode"),
+            not(feature = "serde_postcard"),
+        ),
+        all(
+            feature = "serde_postcard",
+            not(feature = "serde_cbor"),
+            not(feature = "serde_json"),
+            not(feature = "serde_bincode"),
+            not(feature = "serde_rmp"),
+        ),
+    ))] {
+        use std::convert::Infallible;
+        use std::net::SocketAddr;
+        use std::sync::atomic::Ordering;
+        use std::sync::Arc;
+
+        use async_tungstenite::{tokio::TokioAdapter, WebSocketStream};
+        use hyper::header::{self, HeaderMap, HeaderValue};
+        use hyper::server::conn::{AddrIncoming, AddrStream};
+        use hyper::service::{make_service_fn, service_fn};
+        use hyper::{Body, Method, Request, Response, StatusCode};
+        use tungstenite::handshake::derive_accept_key;
+        use tungstenite::protocol::Role;
+
+        use crate::codec::DefaultCodec;
+        use crate::error::Error;
+        use crate::server::post::{PostError, PostSessions};
+        use crate::server::{start_broker_reader_writer, Server};
+        use crate::transport::post::{CLOSE_HEADER, INPUT_HEADER, MAX_BODY_LEN, SEQ_HEADER, SESSION_HEADER};
+        use crate::transport::ws::{chunk, WebSocketConn};
+        #[cfg(feature = "metrics")]
+        use crate::server::metrics;
+
+        fn hyper_error(err: hyper::Error) -> Error {
+            Error::IoError(std::io::Error::other(err))
+        }
+
+        /// Whether the comma separated values of the header `name` contain `token`
+        fn header_contains(headers: &HeaderMap, name: header::HeaderName, token: &str) -> bool {
+            headers
+                .get_all(name)
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .flat_map(|value| value.split(','))
+                .any(|value| value.trim().eq_ignore_ascii_case(token))
+        }
+
+        fn status(code: StatusCode) -> Response<Body> {
+            let mut response = Response::new(Body::empty());
+            *response.status_mut() = code;
+            response
+        }
+
+        /// Reads the body of a request of the HTTP POST transport, which is refused
+        /// once it is longer than `MAX_BODY_LEN`
+        async fn read_body(mut body: Body) -> Result<Vec<u8>, StatusCode> {
+            use hyper::body::HttpBody;
+
+            if body.size_hint().lower() > MAX_BODY_LEN as u64 {
+                return Err(StatusCode::PAYLOAD_TOO_LARGE)
+            }
+            let mut buf = Vec::new();
+            while let Some(chunk) = body.data().await {
+                let chunk = chunk.map_err(|err| {
+                    error!("{}", err);
+                    StatusCode::BAD_REQUEST
+                })?;
+                if buf.len() + chunk.len() > MAX_BODY_LEN {
+                    return Err(StatusCode::PAYLOAD_TOO_LARGE)
+                }
+                buf.extend_from_slice(&chunk);
+            }
+            Ok(buf)
+        }
+
+        /// The following impl block is controlled by feature flag. It is enabled
+        /// if and only if **exactly one** of the the following feature flag is turned on
+        /// - `serde_bincode`
+        /// - `serde_json`
+        /// - `serde_cbor`
+        /// - `serde_rmp`
+        /// - `serde_postcard`
+        impl Server {
+            /// Serves RPC over WebSocket with `hyper` at `addr`, without a web framework
+            ///
+            /// The WebSocket connections are upgraded from the HTTP requests whose
+            /// path ends with `DEFAULT_RPC_PATH`, which is the path that
+            /// `Client::dial_http` appends to its URL. The POST requests to the same
+            /// path are served with the HTTP POST transport of `Client::dial_http_post`,
+            /// for the clients behind proxies that block the WebSocket upgrades. With
+            /// `ServerBuilder::export_metrics`, the GET requests whose path ends with
+            /// `METRICS_PATH` get the metrics of the calls. The other requests get a
+            /// `404 Not Found`. The connection filter and the
+            /// connection limits of the server apply to the WebSocket connections and
+            /// to the POST sessions. This returns once `hyper` fails to accept
+            /// connections.
+            ///
+            /// This is enabled
+            /// if and only if **exactly one** of the the following feature flag is turned on
+            /// - `serde_bincode`
+            /// - `serde_json`
+            /// - `serde_cbor`
+            /// - `serde_rmp`
+            /// - `serde_postcard`
+            ///
+            /// # Example
+            ///
+            /// ```no_run
+            /// # use std::sync::Arc;
+            /// # use toy_rpc::{Client, Server};
+            /// # use toy_rpc::macros::export_impl;
+            /// # struct Example { }
+            /// # #[export_impl]
+            /// # impl Example {
+            /// #     #[export_method]
+            /// #     async fn echo(&self, args: String) -> Result<String, String> {
+            /// #         Ok(args)
+            /// #     }
+            /// # }
+            /// # async fn run() -> Result<(), toy_rpc::Error> {
+            /// # let example_service = Arc::new(Example { });
+            /// let server = Server::builder()
+            ///     .register(example_service)
+            ///     .build();
+            /// // RPC will be served at "ws://127.0.0.1:8080/rpc/_rpc_"
+            /// server.serve_hyper(([127, 0, 0, 1], 8080)).await?;
+            ///
+            /// // client
+            /// let client = Client::dial_http("ws://127.0.0.1:8080/rpc/").await?;
+            /// # Ok(())
+            /// # }
+            /// ```
+            #[cfg_attr(feature = "docs", doc(cfg(feature = "http_hyper")))]
+            pub async fn serve_hyper(&self, addr: impl Into<SocketAddr>) -> Result<(), Error> {
+                let incoming = AddrIncoming::bind(&addr.into()).map_err(hyper_error)?;
+                self.serve_hyper_incoming(incoming).await
+            }
+
+            /// Serves RPC over WebSocket with `hyper` on a listener that is already
+            /// bound, eg. to an ephemeral port. See `serve_hyper` for details.
+            #[cfg_attr(feature = "docs", doc(cfg(feature = "http_hyper")))]
+            pub async fn serve_hyper_listener(&self, listener: std::net::TcpListener) -> Result<(), Error> {
+                listener.set_nonblocking(true)?;
+                let listener = tokio::net::TcpListener::from_std(listener)?;
+                let incoming = AddrIncoming::from_listener(listener).map_err(hyper_error)?;
+                self.serve_hyper_incoming(incoming).await
+            }
+
+            async fn serve_hyper_incoming(&self, incoming: AddrIncoming) -> Result<(), Error> {
+                // a `Server` stops the pubsub broker when it is dropped, so there is a
+                // single clone shared by the requests
+                let server = Arc::new(self.clone());
+                let sessions = Arc::new(PostSessions::default());
+                let make_service = make_service_fn(move |conn: &AddrStream| {
+                    let server = server.clone();
+                    let sessions = sessions.clone();
+                    let peer_addr = conn.remote_addr();
+                    async move {
+                        Ok::<_, Infallible>(service_fn(move |req| {
+                            let server = server.clone();
+                            let sessions = sessions.clone();
+                            async move { Ok::<_, Infallible>(server.hyper_handle(req, peer_addr, &sessions).await) }
+                        }))
+                    }
+                });
+                hyper::Server::builder(incoming)
+                    .serve(make_service)
+                    .await
+                    .map_err(hyper_error)
+            }
+
+            async fn hyper_handle(&self, req: Request<Body>, peer_addr: SocketAddr, sessions: &PostSessions) -> Response<Body> {
+                #[cfg(feature = "metrics")]
+                if req.method() == Method::GET && req.uri().path().rsplit('/').next() == Some(metrics::METRICS_PATH) {
+                    return self.hyper_metrics()
+                }
+                if req.uri().path().rsplit('/').next() != Some(crate::DEFAULT_RPC_PATH) {
+                    return status(StatusCode::NOT_FOUND)
+                }
+                match *req.method() {
+                    Method::POST => self.hyper_post(req, peer_addr, sessions).await,
+                    _ => self.hyper_upgrade(req, peer_addr),
+                }
+            }
+
+            /// Answers a request for the metrics, which are not found unless they are
+            /// exported
+            #[cfg(feature = "metrics")]
+            fn hyper_metrics(&self) -> Response<Body> {
+                let registry = self.connections.metrics();
+                if !registry.exported() {
+                    return status(StatusCode::NOT_FOUND)
+                }
+                let mut response = Response::new(Body::from(registry.snapshot().encode_prometheus()));
+                response.headers_mut().insert(header::CONTENT_TYPE, HeaderValue::from_static(metrics::CONTENT_TYPE));
+                response
+            }
+
+            /// Answers a request of the HTTP POST transport. A request without a
+            /// session opens one, whose connection is served in a new task.
+            async fn hyper_post(&self, req: Request<Body>, peer_addr: SocketAddr, sessions: &PostSessions) -> Response<Body> {
+                let headers = req.headers();
+                let session = headers
+                    .get(SESSION_HEADER)
+                    .and_then(|value| value.to_str().ok())
+                    .map(String::from);
+                let input = headers
+                    .get(INPUT_HEADER)
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(|value| value.parse::<u64>().ok());
+                let close = headers.contains_key(CLOSE_HEADER);
+
+                let id = match session {
+                    Some(id) => id,
+                    None => {
+                        if !crate::server::filter::is_allowed(self.connection_filter.as_ref(), &peer_addr) {
+                            return status(StatusCode::FORBIDDEN)
+                        }
+                        let permit = match self.limits.try_acquire(&peer_addr) {
+                            Some(permit) => permit,
+                            None => return status(StatusCode::SERVICE_UNAVAILABLE),
+                        };
+                        let (id, codec) = sessions.open();
+
+                        let client_id = self.client_counter.fetch_add(1, Ordering::Relaxed);
+                        let context = self.connection_context().peer(peer_addr);
+                        debug!("Opened HTTP POST session with {}", peer_addr);
+                        tokio::task::spawn(permit.hold(async move {
+                            let fut = start_broker_reader_writer(codec, client_id, context);
+                            fut.await.unwrap_or_else(|e| error!("{}", e));
+                            info!("Client disconnected from {}", peer_addr);
+                        }));
+
+                        let mut response = status(StatusCode::OK);
+                        let headers = response.headers_mut();
+                        if let Ok(id) = HeaderValue::from_str(&id) {
+                            headers.insert(SESSION_HEADER, id);
+                        }
+                        headers.insert(SEQ_HEADER, HeaderValue::from(0u64));
+                        return response
+                    }
+                };
+                if close {
+                    match sessions.close(&id, input) {
+                        Ok(()) => {}
+                        Err(PostError::UnknownSession) => return status(StatusCode::NOT_FOUND),
+                        Err(PostError::BadRequest) => return status(StatusCode::BAD_REQUEST),
+                    }
+                    let mut response = status(StatusCode::OK);
+                    response.headers_mut().insert(CLOSE_HEADER, HeaderValue::from_static("1"));
+                    return response
+                }
+
+                let body = match read_body(req.into_body()).await {
+                    Ok(body) => body,
+                    Err(code) => return status(code),
+                };
+                match sessions.exchange(&id, input, &body).await {
+                    Ok(exchanged) => {
+                        let mut response = Response::new(Body::from(exchanged.body));
+                        let headers = response.headers_mut();
+                        headers.insert(SEQ_HEADER, HeaderValue::from(exchanged.seq));
+                        if exchanged.closed {
+                            headers.insert(CLOSE_HEADER, HeaderValue::from_static("1"));
+                        }
+                        response
+                    }
+                    Err(PostError::UnknownSession) => status(StatusCode::NOT_FOUND),
+                    Err(PostError::BadRequest) => status(StatusCode::BAD_REQUEST),
+                }
+            }
+
+            /// Answers the WebSocket handshake of `req` and serves the upgraded
+            /// connection in a new task
+            fn hyper_upgrade(&self, mut req: Request<Body>, peer_addr: SocketAddr) -> Response<Body> {
+                let headers = req.headers();
+                let key = match headers.get(header::SEC_WEBSOCKET_KEY) {
+                    Some(key) if req.method() == Method::GET
+                        && header_contains(headers, header::CONNECTION, "upgrade")
+                        && header_contains(headers, header::UPGRADE, "websocket")
+                        && headers.get(header::SEC_WEBSOCKET_VERSION).map(|v| v.as_bytes()) == Some(b"13") => key,
+                    _ => return status(StatusCode::BAD_REQUEST),
+                };
+                if !crate::server::filter::is_allowed(self.connection_filter.as_ref(), &peer_addr) {
+                    return status(StatusCode::FORBIDDEN)
+                }
+                let permit = match self.limits.try_acquire(&peer_addr) {
+                    Some(permit) => permit,
+                    None => return status(StatusCode::SERVICE_UNAVAILABLE),
+                };
+                let accept = derive_accept_key(key.as_bytes());
+                let chunked = chunk::offered(
+                    headers
+                        .get_all(header::SEC_WEBSOCKET_PROTOCOL)
+                        .iter()
+                        .filter_map(|value| value.to_str().ok()),
+                );
+
+                let client_id = self.client_counter.fetch_add(1, Ordering::Relaxed);
+                let context = self.connection_context().peer(peer_addr);
+                let upgrade = hyper::upgrade::on(&mut req);
+                tokio::task::spawn(permit.hold(async move {
+                    let upgraded = match upgrade.await {
+                        Ok(upgraded) => upgraded,
+                        Err(err) => return error!("{}", err),
+                    };
+                    // `Upgraded` is not `Sync`, so the connection is taken back out of it
+                    // along with the bytes that hyper may have read past the handshake
+                    let parts = match upgraded.downcast::<AddrStream>() {
+                        Ok(parts) => parts,
+                        Err(_) => return error!("Unexpected connection type upgraded by hyper"),
+                    };
+                    debug!("Established WebSocket connection with {}", peer_addr);
+                    let ws_stream = WebSocketStream::from_partially_read(
+                        TokioAdapter::new(parts.io),
+                        parts.read_buf.to_vec(),
+                        Role::Server,
+                        None,
+                    ).await;
+                    let codec = DefaultCodec::with_websocket(WebSocketConn::new(ws_stream).chunked(chunked));
+
+                    let fut = start_broker_reader_writer(codec, client_id, context);
+                    fut.await.unwrap_or_else(|e| error!("{}", e));
+                    info!("Client disconnected from {}", peer_addr);
+                }));
+
+                let mut response = status(StatusCode::SWITCHING_PROTOCOLS);
+                let headers = response.headers_mut();
+                headers.insert(header::CONNECTION, HeaderValue::from_static("Upgrade"));
+                headers.insert(header::UPGRADE, HeaderValue::from_static("websocket"));
+                if let Ok(accept) = HeaderValue::from_str(&accept) {
+                    headers.insert(header::SEC_WEBSOCKET_ACCEPT, accept);
+                }
+                if chunked {
+                    headers.insert(header::SEC_WEBSOCKET_PROTOCOL, HeaderValue::from_static(chunk::PROTOCOL));
+                }
+                response
+            }
+        }
+    }
+}
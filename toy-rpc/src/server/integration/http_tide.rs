@@ -3,7 +3,7 @@ use cfg_if::cfg_if;
 use tide_websockets as tide_ws;
 
 use crate::server::Server;
-use crate::transport::ws::WebSocketConn;
+use crate::transport::ws::{chunk, WebSocketConn};
 
 cfg_if! {
     if #[cfg(any(
@@ -13,24 +13,35 @@ cfg_if! {
             not(feature = "serde_json"),
             not(feature = "serde_cbor"),
             not(feature = "serde_rmp"),
+            not(feature = "serde_postcard"),
         ),
         all(
             feature = "serde_cbor",
             not(feature = "serde_json"),
             not(feature = "serde_bincode"),
             not(feature = "serde_rmp"),
+            not(feature = "serde_postcard"),
         ),
         all(
             feature = "serde_json",
             not(feature = "serde_bincode"),
             not(feature = "serde_cbor"),
             not(feature = "serde_rmp"),
+            not(feature = "serde_postcard"),
         ),
         all(
             feature = "serde_rmp",
             not(feature = "serde_cbor"),
             not(feature = "serde_json"),
             not(feature = "serde_bincode"),
+            not(feature = "serde_postcard"),
+        ),
+        all(
+            feature = "serde_postcard",
+            not(feature = "serde_cbor"),
+            not(feature = "serde_json"),
+            not(feature = "serde_bincode"),
+            not(feature = "serde_rmp"),
         ),
     ))] {
         use std::sync::atomic::Ordering;
@@ -38,6 +49,8 @@ cfg_if! {
         use crate::codec::DefaultCodec;
         use crate::DEFAULT_RPC_PATH;
         use crate::server::start_broker_reader_writer;
+        #[cfg(feature = "metrics")]
+        use crate::server::metrics;
 
         /// The following impl block is controlled by feature flag. It is enabled
         /// if and only if **exactly one** of the the following feature flag is turned on
@@ -45,6 +58,7 @@ cfg_if! {
         /// - `serde_json`
         /// - `serde_cbor`
         /// - `serde_rmp`
+        /// - `serde_postcard`
         impl Server {
             #[cfg(any(feature = "http_tide", feature = "docs"))]
             #[cfg_attr(feature = "docs", doc(cfg(feature = "http_tide")))]
@@ -53,7 +67,8 @@ cfg_if! {
             /// with `tide` feature turned on
             ///
             /// The endpoint will be created with `DEFAULT_RPC_PATH` appended to the
-            /// end of the nested `tide` endpoint.
+            /// end of the nested `tide` endpoint. With `ServerBuilder::export_metrics`,
+            /// the metrics of the calls are served at `METRICS_PATH` next to it.
             ///
             /// This is enabled
             /// if and only if **exactly one** of the the following feature flag is turned on
@@ -61,10 +76,23 @@ cfg_if! {
             /// - `serde_json`
             /// - `serde_cbor`
             /// - `serde_rmp`
+            /// - `serde_postcard`
             ///
             /// # Example
             ///
-            /// ```
+            /// ```no_run
+            /// # use std::sync::Arc;
+            /// # use toy_rpc::Server;
+            /// # use toy_rpc::macros::export_impl;
+            /// # struct FooService { }
+            /// # #[export_impl]
+            /// # impl FooService {
+            /// #     #[export_method]
+            /// #     async fn echo(&self, args: String) -> Result<String, String> {
+            /// #         Ok(args)
+            /// #     }
+            /// # }
+            /// # async fn run() -> Result<(), std::io::Error> {
             /// let foo_service = Arc::new(FooService { });
             /// let server = Server::builder()
             ///     .register(foo_service)
@@ -75,27 +103,46 @@ cfg_if! {
             /// // the network path must end with a slash "/"
             /// app.at("/rpc/").nest(server.into_endpoint());
             /// app.listen("127.0.0.1:8080").await?;
+            /// # Ok(())
+            /// # }
             /// ```
             ///
             pub fn into_endpoint(self) -> tide::Server<Server> {
+                #[cfg(feature = "metrics")]
+                let exported = self.connections.metrics().exported();
                 let mut app = tide::Server::with_state(self);
                 // let mut app = tide::Server::new();
                 app.at(DEFAULT_RPC_PATH)
                     // .connect(|_| async move { Ok("CONNECT request is received") })
                     .get(tide_ws::WebSocket::new(
                         |req: tide::Request<Server>, ws_stream| async move {
-                            let ws_stream = WebSocketConn::new_without_sink(ws_stream);
+                            let chunked = req
+                                .header(chunk::PROTOCOL_HEADER)
+                                .map_or(false, |values| chunk::offered(values.iter().map(|value| value.as_str())));
+                            let ws_stream = WebSocketConn::new_without_sink(ws_stream).chunked(chunked);
                             let codec = DefaultCodec::with_tide_websocket(ws_stream);
-                            let services = req.state().services.clone();
                             let client_id = req.state().client_counter.fetch_add(1, Ordering::Relaxed);
-                            let pubsub_broker = req.state().pubsub_tx.clone();
+                            let peer_addr = req.peer_addr().and_then(|addr| addr.parse().ok());
+                            let context = req.state().connection_context().peer(peer_addr);
 
-                            let fut = start_broker_reader_writer(codec, services, client_id, pubsub_broker);
-                            log::trace!("Client disconnected.");
+                            let fut = start_broker_reader_writer(codec, client_id, context);
+                            trace!("Client disconnected.");
                             fut.await?;
                             Ok(())
                         },
-                    ));
+                    ).with_protocols(&[chunk::PROTOCOL]));
+                #[cfg(feature = "metrics")]
+                if exported {
+                    app.at(metrics::METRICS_PATH)
+                        .get(|req: tide::Request<Server>| async move {
+                            let body = req.state().metrics().encode_prometheus();
+                            let response = tide::Response::builder(tide::StatusCode::Ok)
+                                .content_type(metrics::CONTENT_TYPE)
+                                .body(body)
+                                .build();
+                            Ok(response)
+                        });
+                }
 
                 app
             }
@@ -131,10 +178,23 @@ cfg_if! {
             /// - `serde_json`
             /// - `serde_cbor`
             /// - `serde_rmp`
+            /// - `serde_postcard`
             ///
             /// # Example
             ///
-            /// ```
+            /// ```no_run
+            /// # use std::sync::Arc;
+            /// # use toy_rpc::Server;
+            /// # use toy_rpc::macros::export_impl;
+            /// # struct FooService { }
+            /// # #[export_impl]
+            /// # impl FooService {
+            /// #     #[export_method]
+            /// #     async fn echo(&self, args: String) -> Result<String, String> {
+            /// #         Ok(args)
+            /// #     }
+            /// # }
+            /// # async fn run() -> Result<(), std::io::Error> {
             /// let foo_service = Arc::new(FooService { });
             /// let server = Server::builder()
             ///     .register(foo_service)
@@ -145,6 +205,8 @@ cfg_if! {
             /// // the network path must end with a slash "/"
             /// app.at("/rpc/").nest(server.handle_http());
             /// app.listen("127.0.0.1:8080").await?;
+            /// # Ok(())
+            /// # }
             /// ```
             pub fn handle_http(self) -> tide::Server<Server> {
                 self.into_endpoint()
@@ -152,3 +214,50 @@ cfg_if! {
         }
     }
 }
+
+use crate::server::health::{Health, HealthProbe};
+
+impl From<Health> for tide::Response {
+    /// Answers `200 OK` if the server is accepting, `503 Service Unavailable`
+    /// otherwise, with the name of the state as the body
+    fn from(health: Health) -> Self {
+        let status = match health.is_ready() {
+            true => tide::StatusCode::Ok,
+            false => tide::StatusCode::ServiceUnavailable,
+        };
+        tide::Response::builder(status)
+            .body(health.as_str())
+            .build()
+    }
+}
+
+/// Answers the requests with a `503 Service Unavailable` unless the server is
+/// accepting. See the `health` module for details.
+///
+/// # Example
+///
+/// ```no_run
+/// # use toy_rpc::Server;
+/// # use toy_rpc::server::health::Health;
+/// # fn run(server: Server) {
+/// let mut app = tide::new();
+/// app.at("/healthz")
+///     .with(server.health_probe())
+///     .get(|_| async { Ok(Health::Accepting) });
+/// app.at("/rpc/").nest(server.handle_http());
+/// # }
+/// ```
+#[async_trait::async_trait]
+impl<State: Clone + Send + Sync + 'static> tide::Middleware<State> for HealthProbe {
+    async fn handle(
+        &self,
+        request: tide::Request<State>,
+        next: tide::Next<'_, State>,
+    ) -> tide::Result {
+        let health = self.health();
+        match health.is_ready() {
+            true => Ok(next.run(request).await),
+            false => Ok(health.into()),
+        }
+    }
+}
@@ -9,32 +9,59 @@ cfg_if! {
             not(feature = "serde_json"),
             not(feature = "serde_cbor"),
             not(feature = "serde_rmp"),
+            not(feature = "serde_postcard"),
         ),
         all(
             feature = "serde_cbor",
             not(feature = "serde_json"),
             not(feature = "serde_bincode"),
             not(feature = "serde_rmp"),
+            not(feature = "serde_postcard"),
         ),
         all(
             feature = "serde_json",
             not(feature = "serde_bincode"),
             not(feature = "serde_cbor"),
             not(feature = "serde_rmp"),
+            not(feature = "serde_postcard"),
         ),
         all(
             feature = "serde_rmp",
             not(feature = "serde_cbor"),
             not(feature = "serde_json"),
             not(feature = "serde_bincode"),
+            not(feature = "serde_postcard"),
+        ),
+        all(
+            feature = "serde_postcard",
+            not(feature = "serde_cbor"),
+            not(feature = "serde_json"),
+            not(feature = "serde_bincode"),
+            not(feature = "serde_rmp"),
         ),
     ))] {
+        use std::convert::Infallible;
+        use std::net::SocketAddr;
         use std::sync::{Arc, atomic::Ordering};
         use warp::{Filter, Reply, filters::BoxedFilter};
+        use warp::http::{HeaderValue, StatusCode};
+        use warp::hyper::body::Bytes;
+        use warp::reply::Response;
 
         use crate::{server::Server};
         use crate::codec::DefaultCodec;
+        use crate::server::post::{PostError, PostSessions};
         use crate::server::start_broker_reader_writer;
+        use crate::transport::post::{CLOSE_HEADER, INPUT_HEADER, MAX_BODY_LEN, SEQ_HEADER, SESSION_HEADER};
+        use crate::transport::ws::chunk;
+        #[cfg(feature = "metrics")]
+        use crate::server::metrics;
+
+        fn status(code: StatusCode) -> Response {
+            let mut response = Response::default();
+            *response.status_mut() = code;
+            response
+        }
 
         /// The following impl block is controlled by feature flag. It is enabled
         /// if and only if **exactly one** of the the following feature flag is turned on
@@ -42,18 +69,118 @@ cfg_if! {
         /// - `serde_json`
         /// - `serde_cbor`
         /// - `serde_rmp`
+        /// - `serde_postcard`
         impl Server {
             /// WebSocket handler for integration with `warp`
-            fn warp_websocket_handler(state: Arc<Self>, ws: warp::ws::Ws) -> impl warp::Reply {
-                ws.on_upgrade(|websocket| async move {
-                    let codec = DefaultCodec::with_warp_websocket(websocket);
-                    let services = state.services.clone();
+            fn warp_websocket_handler(
+                state: Arc<Self>,
+                peer_addr: Option<SocketAddr>,
+                protocols: Option<String>,
+                ws: warp::ws::Ws,
+            ) -> Response {
+                let chunked = protocols.map_or(false, |protocols| chunk::offered(Some(protocols.as_str())));
+                let mut response = ws.on_upgrade(move |websocket| async move {
+                    let codec = DefaultCodec::with_warp_websocket(websocket).chunked(chunked);
                     let client_id = state.client_counter.fetch_add(1, Ordering::Relaxed);
-                    let pubsub_broker = state.pubsub_tx.clone();
+                    let context = state.connection_context().peer(peer_addr);
+
+                    let fut = start_broker_reader_writer(codec, client_id, context);
+                    fut.await.unwrap_or_else(|e| error!("{}", e));
+                }).into_response();
+                if chunked {
+                    response.headers_mut().insert(chunk::PROTOCOL_HEADER, HeaderValue::from_static(chunk::PROTOCOL));
+                }
+                response
+            }
 
-                    let fut = start_broker_reader_writer(codec, services, client_id, pubsub_broker);
-                    fut.await.unwrap_or_else(|e| log::error!("{}", e));
-                })
+            /// Handler of the HTTP POST transport for integration with `warp`. A
+            /// request without a session opens one, whose connection is served in a
+            /// new task.
+            async fn warp_post_handler(
+                state: Arc<Self>,
+                sessions: Arc<PostSessions>,
+                peer_addr: Option<SocketAddr>,
+                session: Option<String>,
+                input: Option<u64>,
+                close: Option<String>,
+                body: Bytes,
+            ) -> Result<Response, Infallible> {
+                let id = match session {
+                    Some(id) => id,
+                    None => {
+                        // the session is counted against the limits of its peer, which
+                        // warp leaves out only for the connections that are not over TCP
+                        let peer_addr = match peer_addr {
+                            Some(peer_addr) => peer_addr,
+                            None => return Ok(status(StatusCode::FORBIDDEN)),
+                        };
+                        if !crate::server::filter::is_allowed(state.connection_filter.as_ref(), &peer_addr) {
+                            return Ok(status(StatusCode::FORBIDDEN))
+                        }
+                        let permit = match state.limits.try_acquire(&peer_addr) {
+                            Some(permit) => permit,
+                            None => return Ok(status(StatusCode::SERVICE_UNAVAILABLE)),
+                        };
+                        let (id, codec) = sessions.open();
+                        let client_id = state.client_counter.fetch_add(1, Ordering::Relaxed);
+                        let context = state.connection_context().peer(peer_addr);
+                        debug!("Opened HTTP POST session with {}", peer_addr);
+                        tokio::task::spawn(permit.hold(async move {
+                            let fut = start_broker_reader_writer(codec, client_id, context);
+                            fut.await.unwrap_or_else(|e| error!("{}", e));
+                            info!("Client disconnected from {}", peer_addr);
+                        }));
+
+                        let mut response = status(StatusCode::OK);
+                        let headers = response.headers_mut();
+                        if let Ok(id) = HeaderValue::from_str(&id) {
+                            headers.insert(SESSION_HEADER, id);
+                        }
+                        headers.insert(SEQ_HEADER, HeaderValue::from(0u64));
+                        return Ok(response)
+                    }
+                };
+                if close.is_some() {
+                    match sessions.close(&id, input) {
+                        Ok(()) => {}
+                        Err(PostError::UnknownSession) => return Ok(status(StatusCode::NOT_FOUND)),
+                        Err(PostError::BadRequest) => return Ok(status(StatusCode::BAD_REQUEST)),
+                    }
+                    let mut response = status(StatusCode::OK);
+                    response.headers_mut().insert(CLOSE_HEADER, HeaderValue::from_static("1"));
+                    return Ok(response)
+                }
+
+                let response = match sessions.exchange(&id, input, &body).await {
+                    Ok(exchanged) => {
+                        let mut response = Response::new(exchanged.body.into());
+                        let headers = response.headers_mut();
+                        headers.insert(SEQ_HEADER, HeaderValue::from(exchanged.seq));
+                        if exchanged.closed {
+                            headers.insert(CLOSE_HEADER, HeaderValue::from_static("1"));
+                        }
+                        response
+                    }
+                    Err(PostError::UnknownSession) => status(StatusCode::NOT_FOUND),
+                    Err(PostError::BadRequest) => status(StatusCode::BAD_REQUEST),
+                };
+                Ok(response)
+            }
+
+            /// Handler of the metrics for integration with `warp`, which leaves the
+            /// request to the other filters unless the metrics are exported
+            #[cfg(feature = "metrics")]
+            async fn warp_metrics_handler(state: Arc<Self>) -> Result<Response, warp::Rejection> {
+                let registry = state.connections.metrics();
+                if !registry.exported() {
+                    return Err(warp::reject::not_found())
+                }
+                let mut response = Response::new(registry.snapshot().encode_prometheus().into());
+                response.headers_mut().insert(
+                    warp::http::header::CONTENT_TYPE,
+                    HeaderValue::from_static(metrics::CONTENT_TYPE),
+                );
+                Ok(response)
             }
 
             /// Returns the `DEFAULT_RPC_PATH`
@@ -64,9 +191,27 @@ cfg_if! {
             /// Consumes `Server` and returns a `warp::filters::BoxedFilter`
             /// which can be chained with `warp` filters
             ///
+            /// The POST requests to the RPC path are served with the HTTP POST transport
+            /// of `Client::dial_http_post`, for the clients behind proxies that block the
+            /// WebSocket upgrades. With `ServerBuilder::export_metrics`, the metrics of
+            /// the calls are served at `METRICS_PATH` next to the RPC path.
+            ///
             /// # Example
             ///
-            /// ```rust
+            /// ```no_run
+            /// # use std::sync::Arc;
+            /// # use toy_rpc::Server;
+            /// # use toy_rpc::macros::export_impl;
+            /// # struct FooService { }
+            /// # #[export_impl]
+            /// # impl FooService {
+            /// #     #[export_method]
+            /// #     async fn echo(&self, args: String) -> Result<String, String> {
+            /// #         Ok(args)
+            /// #     }
+            /// # }
+            /// # use warp::Filter;
+            /// # async fn run() {
             /// let foo_service = Arc::new(FooService { });
             /// let server = Server::builder()
             ///     .register(foo_service)
@@ -75,18 +220,44 @@ cfg_if! {
             ///     .and(server.into_boxed_filter());
             /// // RPC will be served at "ws://127.0.0.1/rpc/_rpc_"
             /// warp::serve(routes).run(([127, 0, 0, 1], 8080)).await;
+            /// # }
             /// ```
             pub fn into_boxed_filter(self) -> BoxedFilter<(impl Reply,)> {
                 let state = Arc::new(self);
                 let state = warp::any().map(move || state.clone());
+                let sessions = Arc::new(PostSessions::default());
+                let sessions = warp::any().map(move || sessions.clone());
+
+                let post_route = warp::path(Server::handler_path())
+                    .and(warp::post())
+                    .and(state.clone())
+                    .and(sessions)
+                    .and(warp::addr::remote())
+                    .and(warp::header::optional::<String>(SESSION_HEADER))
+                    .and(warp::header::optional::<u64>(INPUT_HEADER))
+                    .and(warp::header::optional::<String>(CLOSE_HEADER))
+                    .and(warp::body::content_length_limit(MAX_BODY_LEN as u64))
+                    .and(warp::body::bytes())
+                    .and_then(Server::warp_post_handler);
+
+                #[cfg(feature = "metrics")]
+                let metrics_route = warp::path(metrics::METRICS_PATH)
+                    .and(warp::path::end())
+                    .and(warp::get())
+                    .and(state.clone())
+                    .and_then(Server::warp_metrics_handler);
 
                 let rpc_route = warp::path(Server::handler_path())
                     .and(state)
+                    .and(warp::addr::remote())
+                    .and(warp::header::optional::<String>(chunk::PROTOCOL_HEADER))
                     .and(warp::ws())
                     .map(Server::warp_websocket_handler)
-                    .boxed();
+                    .or(post_route);
+                #[cfg(feature = "metrics")]
+                let rpc_route = rpc_route.or(metrics_route);
 
-                rpc_route
+                rpc_route.boxed()
             }
 
             #[cfg(any(
@@ -120,10 +291,24 @@ cfg_if! {
             /// - `serde_json`
             /// - `serde_cbor`
             /// - `serde_rmp`
+            /// - `serde_postcard`
             ///
             /// # Example
             ///
-            /// ```rust
+            /// ```no_run
+            /// # use std::sync::Arc;
+            /// # use toy_rpc::Server;
+            /// # use toy_rpc::macros::export_impl;
+            /// # struct FooService { }
+            /// # #[export_impl]
+            /// # impl FooService {
+            /// #     #[export_method]
+            /// #     async fn echo(&self, args: String) -> Result<String, String> {
+            /// #         Ok(args)
+            /// #     }
+            /// # }
+            /// # use warp::Filter;
+            /// # async fn run() {
             /// let foo_service = Arc::new(FooService { });
             /// let server = Server::builder()
             ///     .register(foo_service)
@@ -132,6 +317,7 @@ cfg_if! {
             ///     .and(server.handle_http());
             /// // RPC will be served at "ws://127.0.0.1/rpc/_rpc_"
             /// warp::serve(routes).run(([127, 0, 0, 1], 8080)).await;
+            /// # }
             /// ```
             pub fn handle_http(self) -> BoxedFilter<(impl Reply,)> {
                 self.into_boxed_filter()
@@ -139,3 +325,42 @@ cfg_if! {
         }
     }
 }
+
+use crate::server::health::Health;
+
+impl warp::Reply for Health {
+    /// Answers `200 OK` if the server is accepting, `503 Service Unavailable`
+    /// otherwise, with the name of the state as the body
+    fn into_response(self) -> warp::reply::Response {
+        let status = match self.is_ready() {
+            true => warp::http::StatusCode::OK,
+            false => warp::http::StatusCode::SERVICE_UNAVAILABLE,
+        };
+        warp::reply::with_status(self.as_str(), status).into_response()
+    }
+}
+
+impl crate::server::Server {
+    /// Returns a `warp::filters::BoxedFilter` that extracts the health of the
+    /// server, which can be the reply of a health route. See the `health` module
+    /// for details.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use toy_rpc::Server;
+    /// # use warp::Filter;
+    /// # async fn run(server: Server) {
+    /// let health = warp::path("healthz").and(server.health_filter());
+    /// let routes = warp::path("rpc").and(server.handle_http()).or(health);
+    /// warp::serve(routes).run(([127, 0, 0, 1], 8080)).await;
+    /// # }
+    /// ```
+    #[cfg_attr(feature = "docs", doc(cfg(feature = "http_warp")))]
+    pub fn health_filter(&self) -> warp::filters::BoxedFilter<(Health,)> {
+        use warp::Filter;
+
+        let probe = self.health_probe();
+        warp::any().map(move || probe.health()).boxed()
+    }
+}
@@ -1,11 +1,15 @@
 #[cfg(all(feature = "http_actix_web"))]
-#[cfg_attr(doc, doc(cfg(feature = "http_actix_web")))]
+#[cfg_attr(feature = "docs", doc(cfg(feature = "http_actix_web")))]
 mod http_actix_web;
 
 #[cfg(feature = "http_tide")]
-#[cfg_attr(doc, doc(cfg(feature = "http_tide")))]
+#[cfg_attr(feature = "docs", doc(cfg(feature = "http_tide")))]
 mod http_tide;
 
 #[cfg(all(feature = "http_warp"))]
-#[cfg_attr(doc, doc(cfg(feature = "http_warp")))]
+#[cfg_attr(feature = "docs", doc(cfg(feature = "http_warp")))]
 mod http_warp;
+
+#[cfg(all(feature = "http_hyper", not(feature = "http_actix_web")))]
+#[cfg_attr(feature = "docs", doc(cfg(feature = "http_hyper")))]
+mod http_hyper;
@@ -0,0 +1,80 @@
+//! Callbacks on the connections of the server
+//!
+//! The callbacks set with `ServerBuilder::on_connect` and `ServerBuilder::on_disconnect`
+//! are called with a `ConnectionInfo` each time a connection starts and stops being
+//! served, so that the sessions can be tracked, eg. to log them or to keep a gauge
+//! of the clients. A connection that is rejected by the connection filter or the
+//! connection limits is never served, and neither callback is called for it.
+//!
+//! The callbacks are called from the task serving the connection, which waits for
+//! them to return, so they should not block.
+//!
+//! - The `client_id` is the key of the connection in `Server::connection_stats`,
+//!   and the `client_id` of the `AuditRecord`s of its calls.
+//! - The `peer_addr` is the address of the real client if the PROXY protocol is
+//!   enabled. It is `None` for the connections served with `Server::serve_codec`
+//!   and `Server::serve_stream`, whose transport is unknown to the server. The
+//!   address of a connection handed over by the `actix-web` integration is the
+//!   one of its HTTP request.
+//!
+//! # Example
+//!
+//! ```no_run
+//! # use std::sync::Arc;
+//! # use toy_rpc::Server;
+//! # use toy_rpc::macros::export_impl;
+//! # struct Example;
+//! # #[export_impl]
+//! # impl Example {
+//! #     #[export_method]
+//! #     async fn echo(&self, args: String) -> Result<String, String> {
+//! #         Ok(args)
+//! #     }
+//! # }
+//! # use toy_rpc::server::lifecycle::ConnectionInfo;
+//! # let example_service = Arc::new(Example);
+//! let server = Server::builder()
+//!     .register(example_service)
+//!     .on_connect(|info: &ConnectionInfo| log::info!("{:?} connected", info.peer_addr))
+//!     .on_disconnect(|info: &ConnectionInfo| {
+//!         log::info!("{:?} served {} calls in {:?}", info.peer_addr, info.calls, info.duration)
+//!     })
+//!     .build();
+//! ```
+
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// A connection that starts or stops being served
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectionInfo {
+    /// ID of the connection
+    pub client_id: u64,
+    /// Address of the peer, if known
+    pub peer_addr: Option<SocketAddr>,
+    /// Time that the connection has been served for, which is zero when it starts
+    pub duration: Duration,
+    /// Number of calls served on the connection, which is zero when it starts
+    pub calls: u64,
+}
+
+/// A callback on the connections
+pub(crate) type ConnectionHook = Arc<dyn Fn(&ConnectionInfo) + Send + Sync>;
+
+/// Callbacks called when the connections start and stop being served
+#[derive(Clone, Default)]
+pub(crate) struct LifecycleHooks {
+    pub on_connect: Option<ConnectionHook>,
+    pub on_disconnect: Option<ConnectionHook>,
+}
+
+impl fmt::Debug for LifecycleHooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LifecycleHooks")
+            .field("on_connect", &self.on_connect.is_some())
+            .field("on_disconnect", &self.on_disconnect.is_some())
+            .finish()
+    }
+}
@@ -0,0 +1,228 @@
+//! Limits on the number of open connections
+//!
+//! `ServerBuilder::max_connections` caps the number of connections served at the
+//! same time, and `ServerBuilder::max_connections_per_ip` caps the number of
+//! connections from a single IP address. The limits are checked in the accept loop
+//! right after a connection is accepted, so a connection over a limit is closed
+//! before a task is spawned for it. The rejection is logged with the
+//! `ConnectionRejection`, and the connection counts again as soon as it is closed.
+//!
+//! The limits cover the connections accepted by `accept`, `accept_with_tls_config`,
+//! `accept_websocket`, `accept_quic` and the ones accepted from `incoming`. The
+//! per-IP limit is checked on the address of the socket, which is the address of
+//! the load balancer if the PROXY protocol is enabled. The connections served with
+//! `serve_stream` or `serve_codec` are not counted.
+//!
+//! # Example
+//!
+//! ```no_run
+//! # use std::sync::Arc;
+//! # use toy_rpc::Server;
+//! # use toy_rpc::macros::export_impl;
+//! # struct Example;
+//! # #[export_impl]
+//! # impl Example {
+//! #     #[export_method]
+//! #     async fn echo(&self, args: String) -> Result<String, String> {
+//! #         Ok(args)
+//! #     }
+//! # }
+//! # let example_service = Arc::new(Example);
+//! let server = Server::builder()
+//!     .register(example_service)
+//!     .max_connections(10_000)
+//!     .max_connections_per_ip(16)
+//!     .build();
+//! ```
+
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// Reason why an accepted connection is closed without being served
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionRejection {
+    /// The server already serves `ServerBuilder::max_connections` connections
+    MaxConnections {
+        /// Address of the peer
+        peer_addr: SocketAddr,
+        /// The limit that is reached
+        limit: usize,
+    },
+    /// The IP address already has `ServerBuilder::max_connections_per_ip` connections
+    MaxConnectionsPerIp {
+        /// Address of the peer
+        peer_addr: SocketAddr,
+        /// The limit that is reached
+        limit: usize,
+    },
+}
+
+impl fmt::Display for ConnectionRejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MaxConnections { peer_addr, limit } => write!(
+                f,
+                "Rejecting incoming connection from {}: limit of {} connections reached",
+                peer_addr, limit
+            ),
+            Self::MaxConnectionsPerIp { peer_addr, limit } => write!(
+                f,
+                "Rejecting incoming connection from {}: limit of {} connections per IP reached",
+                peer_addr, limit
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ConnectionRejection {}
+
+#[derive(Debug, Default)]
+struct OpenConnections {
+    total: usize,
+    per_ip: HashMap<IpAddr, usize>,
+}
+
+/// Counts the open connections against the limits of the server
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ConnectionLimits {
+    max_connections: Option<usize>,
+    max_connections_per_ip: Option<usize>,
+    open: Arc<Mutex<OpenConnections>>,
+}
+
+impl ConnectionLimits {
+    pub fn new(max_connections: Option<usize>, max_connections_per_ip: Option<usize>) -> Self {
+        Self {
+            max_connections,
+            max_connections_per_ip,
+            open: Default::default(),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, OpenConnections> {
+        match self.open.lock() {
+            Ok(open) => open,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    /// Counts a new connection from `peer_addr`. The connection counts until the
+    /// returned permit is dropped.
+    pub fn acquire(&self, peer_addr: &SocketAddr) -> Result<ConnectionPermit, ConnectionRejection> {
+        let ip = peer_addr.ip();
+        let mut open = self.lock();
+        match self.max_connections {
+            Some(limit) if open.total >= limit => {
+                return Err(ConnectionRejection::MaxConnections {
+                    peer_addr: *peer_addr,
+                    limit,
+                })
+            }
+            _ => {}
+        }
+        let from_ip = open.per_ip.get(&ip).copied().unwrap_or_default();
+        match self.max_connections_per_ip {
+            Some(limit) if from_ip >= limit => {
+                return Err(ConnectionRejection::MaxConnectionsPerIp {
+                    peer_addr: *peer_addr,
+                    limit,
+                })
+            }
+            _ => {}
+        }
+        open.total += 1;
+        open.per_ip.insert(ip, from_ip + 1);
+        Ok(ConnectionPermit {
+            limits: self.clone(),
+            ip,
+        })
+    }
+
+    /// Counts a new connection, logs the rejection if a limit is reached
+    pub fn try_acquire(&self, peer_addr: &SocketAddr) -> Option<ConnectionPermit> {
+        match self.acquire(peer_addr) {
+            Ok(permit) => Some(permit),
+            Err(rejection) => {
+                warn!("{}", rejection);
+                None
+            }
+        }
+    }
+}
+
+/// Keeps a connection counted until it is dropped
+pub(crate) struct ConnectionPermit {
+    limits: ConnectionLimits,
+    ip: IpAddr,
+}
+
+impl ConnectionPermit {
+    /// Keeps the connection counted until `fut` completes
+    pub async fn hold<F: Future>(self, fut: F) -> F::Output {
+        let _permit = self;
+        fut.await
+    }
+}
+
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        let mut open = self.limits.lock();
+        open.total -= 1;
+        if let Some(count) = open.per_ip.get_mut(&self.ip) {
+            *count -= 1;
+            if *count == 0 {
+                open.per_ip.remove(&self.ip);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits_are_released() {
+        let limits = ConnectionLimits::new(Some(3), Some(2));
+        let a: SocketAddr = "10.0.0.1:40000".parse().unwrap();
+        let b: SocketAddr = "10.0.0.2:40000".parse().unwrap();
+
+        let a1 = limits.acquire(&a).unwrap();
+        let _a2 = limits.acquire(&a).unwrap();
+        assert_eq!(
+            limits.acquire(&a).err(),
+            Some(ConnectionRejection::MaxConnectionsPerIp {
+                peer_addr: a,
+                limit: 2
+            })
+        );
+        let _b1 = limits.acquire(&b).unwrap();
+        assert_eq!(
+            limits.acquire(&b).err(),
+            Some(ConnectionRejection::MaxConnections {
+                peer_addr: b,
+                limit: 3
+            })
+        );
+
+        drop(a1);
+        let _b2 = limits.acquire(&b).unwrap();
+        assert!(matches!(
+            limits.acquire(&a),
+            Err(ConnectionRejection::MaxConnections { .. })
+        ));
+    }
+
+    #[test]
+    fn unlimited_by_default() {
+        let limits = ConnectionLimits::default();
+        let addr: SocketAddr = "127.0.0.1:40000".parse().unwrap();
+        let permits: Vec<_> = (0..100).map(|_| limits.acquire(&addr).unwrap()).collect();
+        assert_eq!(limits.lock().per_ip[&addr.ip()], 100);
+        drop(permits);
+        assert!(limits.lock().per_ip.is_empty());
+    }
+}
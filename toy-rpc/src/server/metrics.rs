@@ -0,0 +1,424 @@
+//! Prometheus metrics of the calls
+//!
+//! With the `metrics` feature, the server counts the calls, the errors and the calls
+//! in flight of every method, and keeps a histogram of their latencies.
+//! `Server::metrics` returns a `MetricsSnapshot`, which renders in the text format
+//! of Prometheus with `MetricsSnapshot::encode_prometheus`.
+//!
+//! - Only the methods of the registered services are covered, so that the clients
+//!   calling made up names cannot blow up the number of series.
+//! - A call is counted once its method is found. A request rejected before, eg.
+//!   because it is too large, is not counted.
+//! - The latency runs from the request being passed to the handler until the
+//!   response, or the end of a streaming response, is passed to the writer. A
+//!   streaming call is in flight until then.
+//! - A call that fails, times out or is canceled counts as an error.
+//!
+//! With `ServerBuilder::export_metrics`, the HTTP integrations also answer the GET
+//! requests to `METRICS_PATH` next to the RPC path, eg. `http://127.0.0.1:8080/rpc/metrics`
+//! if the RPC is served at `ws://127.0.0.1:8080/rpc/_rpc_`.
+//!
+//! # Example
+//!
+//! ```no_run
+//! # use std::sync::Arc;
+//! # use toy_rpc::Server;
+//! # use toy_rpc::macros::export_impl;
+//! # struct Example;
+//! # #[export_impl]
+//! # impl Example {
+//! #     #[export_method]
+//! #     async fn echo(&self, args: String) -> Result<String, String> {
+//! #         Ok(args)
+//! #     }
+//! # }
+//! # let example_service = Arc::new(Example);
+//! let server = Server::builder()
+//!     .register(example_service)
+//!     .export_metrics(true)
+//!     .build();
+//!
+//! for (service_method, metrics) in server.metrics().methods {
+//!     println!("{}: {} calls, {} errors", service_method, metrics.calls, metrics.errors);
+//! }
+//! ```
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::clock;
+use crate::error::Error;
+use crate::message::MessageId;
+
+/// Path of the metrics served by the HTTP integrations, next to `DEFAULT_RPC_PATH`
+pub const METRICS_PATH: &str = "metrics";
+
+/// Content type of the metrics encoded with `MetricsSnapshot::encode_prometheus`
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Upper bounds of the buckets of the latency histograms
+pub const LATENCY_BUCKETS: [Duration; 11] = [
+    Duration::from_millis(5),
+    Duration::from_millis(10),
+    Duration::from_millis(25),
+    Duration::from_millis(50),
+    Duration::from_millis(100),
+    Duration::from_millis(250),
+    Duration::from_millis(500),
+    Duration::from_secs(1),
+    Duration::from_millis(2500),
+    Duration::from_secs(5),
+    Duration::from_secs(10),
+];
+
+/// Histogram of the latencies of the calls of a method
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Histogram {
+    /// Number of calls whose latency is at most the bound of the same index in
+    /// `LATENCY_BUCKETS`
+    pub buckets: [u64; LATENCY_BUCKETS.len()],
+    /// Number of calls
+    pub count: u64,
+    /// Sum of the latencies
+    pub sum: Duration,
+}
+
+/// Metrics of a method
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MethodMetrics {
+    /// Number of calls
+    pub calls: u64,
+    /// Number of calls that failed
+    pub errors: u64,
+    /// Number of calls being handled
+    pub in_flight: u64,
+    /// Latencies of the calls that are finished
+    pub latency: Histogram,
+}
+
+/// Metrics of every method, keyed by `"Service.method"`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MetricsSnapshot {
+    /// Metrics of the methods
+    pub methods: HashMap<String, MethodMetrics>,
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+impl MetricsSnapshot {
+    /// Encodes the metrics in the text format of Prometheus, which is served with
+    /// the `CONTENT_TYPE`
+    pub fn encode_prometheus(&self) -> String {
+        // sorted so that the output is stable
+        let methods: BTreeMap<_, _> = self
+            .methods
+            .iter()
+            .map(|(service_method, metrics)| {
+                let (service, method) = service_method
+                    .split_once('.')
+                    .unwrap_or((service_method, ""));
+                let labels = format!(
+                    "service=\"{}\",method=\"{}\"",
+                    escape_label(service),
+                    escape_label(method)
+                );
+                (labels, metrics)
+            })
+            .collect();
+
+        let mut out = String::new();
+        let mut family = |name: &str, kind: &str, help: &str, value: fn(&MethodMetrics) -> u64| {
+            let _ = writeln!(out, "# HELP toy_rpc_{} {}", name, help);
+            let _ = writeln!(out, "# TYPE toy_rpc_{} {}", name, kind);
+            for (labels, metrics) in &methods {
+                let _ = writeln!(out, "toy_rpc_{}{{{}}} {}", name, labels, value(metrics));
+            }
+        };
+        family("calls_total", "counter", "Number of calls", |m| m.calls);
+        family(
+            "errors_total",
+            "counter",
+            "Number of calls that failed",
+            |m| m.errors,
+        );
+        family("in_flight", "gauge", "Number of calls being handled", |m| {
+            m.in_flight
+        });
+
+        let name = "toy_rpc_call_duration_seconds";
+        let _ = writeln!(out, "# HELP {} Latency of the calls", name);
+        let _ = writeln!(out, "# TYPE {} histogram", name);
+        for (labels, metrics) in &methods {
+            let latency = &metrics.latency;
+            for (bound, count) in LATENCY_BUCKETS.iter().zip(latency.buckets.iter()) {
+                let _ = writeln!(
+                    out,
+                    "{}_bucket{{{},le=\"{}\"}} {}",
+                    name,
+                    labels,
+                    bound.as_secs_f64(),
+                    count
+                );
+            }
+            let _ = writeln!(
+                out,
+                "{}_bucket{{{},le=\"+Inf\"}} {}",
+                name, labels, latency.count
+            );
+            let _ = writeln!(
+                out,
+                "{}_sum{{{}}} {}",
+                name,
+                labels,
+                latency.sum.as_secs_f64()
+            );
+            let _ = writeln!(out, "{}_count{{{}}} {}", name, labels, latency.count);
+        }
+        out
+    }
+}
+
+/// Counters of a method shared by all the connections
+#[derive(Debug, Default)]
+struct MethodCounters {
+    calls: AtomicU64,
+    errors: AtomicU64,
+    in_flight: AtomicU64,
+    // the buckets are not cumulative, unlike those of a `Histogram`
+    buckets: [AtomicU64; LATENCY_BUCKETS.len()],
+    finished: AtomicU64,
+    sum_micros: AtomicU64,
+}
+
+impl MethodCounters {
+    fn observe(&self, latency: Duration, ok: bool) {
+        if !ok {
+            self.errors.fetch_add(1, Ordering::Relaxed);
+        }
+        if let Some(index) = LATENCY_BUCKETS.iter().position(|bound| latency <= *bound) {
+            self.buckets[index].fetch_add(1, Ordering::Relaxed);
+        }
+        self.sum_micros
+            .fetch_add(latency.as_micros() as u64, Ordering::Relaxed);
+        self.finished.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> MethodMetrics {
+        let mut latency = Histogram {
+            count: self.finished.load(Ordering::Relaxed),
+            sum: Duration::from_micros(self.sum_micros.load(Ordering::Relaxed)),
+            ..Default::default()
+        };
+        let mut cumulative = 0;
+        for (bucket, count) in latency.buckets.iter_mut().zip(self.buckets.iter()) {
+            cumulative += count.load(Ordering::Relaxed);
+            *bucket = cumulative;
+        }
+        MethodMetrics {
+            calls: self.calls.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            in_flight: self.in_flight.load(Ordering::Relaxed),
+            latency,
+        }
+    }
+}
+
+/// Counters of every registered method
+#[derive(Debug, Default)]
+pub(crate) struct MetricsRegistry {
+    methods: HashMap<String, Arc<MethodCounters>>,
+    // whether the HTTP integrations serve the metrics
+    exported: bool,
+}
+
+impl MetricsRegistry {
+    /// Creates the counters of the methods of the services in `services`
+    pub fn new<'a>(
+        method_names: &HashMap<&'static str, Vec<&'static str>>,
+        services: impl IntoIterator<Item = &'a &'static str>,
+        exported: bool,
+    ) -> Self {
+        let methods = services
+            .into_iter()
+            .filter_map(|service| Some((*service, method_names.get(service)?)))
+            .flat_map(|(service, methods)| {
+                methods
+                    .iter()
+                    .map(move |method| (format!("{}.{}", service, method), Default::default()))
+            })
+            .collect();
+        Self { methods, exported }
+    }
+
+    #[cfg_attr(
+        not(any(
+            feature = "http_tide",
+            feature = "http_warp",
+            feature = "http_hyper",
+            feature = "http_actix_web"
+        )),
+        allow(dead_code)
+    )]
+    pub fn exported(&self) -> bool {
+        self.exported
+    }
+
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        let methods = self
+            .methods
+            .iter()
+            .map(|(service_method, counters)| (service_method.clone(), counters.snapshot()))
+            .collect();
+        MetricsSnapshot { methods }
+    }
+}
+
+/// A call in flight, which stops being counted as such when it is dropped
+struct InFlight {
+    counters: Arc<MethodCounters>,
+    started_at: Instant,
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.counters.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Records the calls of a connection
+pub(crate) struct CallMeter {
+    registry: Arc<MetricsRegistry>,
+    in_flight: HashMap<MessageId, InFlight>,
+}
+
+impl CallMeter {
+    pub fn new(registry: Arc<MetricsRegistry>) -> Self {
+        Self {
+            registry,
+            in_flight: HashMap::new(),
+        }
+    }
+
+    /// Records the start of a call
+    pub fn start(&mut self, id: MessageId, service_method: &str) {
+        if let Some(counters) = self.registry.methods.get(service_method) {
+            counters.calls.fetch_add(1, Ordering::Relaxed);
+            counters.in_flight.fetch_add(1, Ordering::Relaxed);
+            let call = InFlight {
+                counters: counters.clone(),
+                started_at: clock::now(),
+            };
+            self.in_flight.insert(id, call);
+        }
+    }
+
+    /// Records the result of a call started with `start`
+    pub fn finish<T>(&mut self, id: MessageId, result: &Result<T, Error>) {
+        if let Some(call) = self.in_flight.remove(&id) {
+            let latency = clock::now().saturating_duration_since(call.started_at);
+            call.counters.observe(latency, result.is_ok());
+        }
+    }
+
+    /// Records a call that finishes without being started
+    pub fn record<T>(&mut self, id: MessageId, service_method: &str, result: &Result<T, Error>) {
+        self.start(id, service_method);
+        self.finish(id, result);
+    }
+
+    /// Records the calls in flight as canceled
+    pub fn cancel_all(&mut self) {
+        let ids: Vec<_> = self.in_flight.keys().copied().collect();
+        for id in ids {
+            self.finish::<()>(id, &Err(Error::Canceled(Some(id))));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registry() -> Arc<MetricsRegistry> {
+        let mut method_names = HashMap::new();
+        method_names.insert("Echo", vec!["echo", "fail"]);
+        method_names.insert("Other", vec!["other"]);
+        Arc::new(MetricsRegistry::new(&method_names, &["Echo"], false))
+    }
+
+    #[test]
+    fn calls_are_recorded() {
+        let registry = registry();
+        let mut meter = CallMeter::new(registry.clone());
+        meter.start(1, "Echo.echo");
+        meter.start(2, "Echo.echo");
+        meter.start(3, "Echo.fail");
+        // not a registered method
+        meter.start(4, "Echo.unknown");
+        assert_eq!(registry.snapshot().methods["Echo.echo"].in_flight, 2);
+
+        meter.finish::<()>(1, &Ok(()));
+        meter.finish::<()>(3, &Err(Error::ExecutionError("failed".into())));
+        meter.cancel_all();
+
+        let snapshot = registry.snapshot();
+        assert_eq!(snapshot.methods.len(), 2);
+        let echo = snapshot.methods["Echo.echo"];
+        assert_eq!(echo.calls, 2);
+        assert_eq!(echo.errors, 1);
+        assert_eq!(echo.in_flight, 0);
+        assert_eq!(echo.latency.count, 2);
+        assert_eq!(echo.latency.buckets[LATENCY_BUCKETS.len() - 1], 2);
+        assert_eq!(snapshot.methods["Echo.fail"].errors, 1);
+    }
+
+    #[test]
+    fn snapshots_are_encoded() {
+        let mut latency = Histogram {
+            count: 3,
+            sum: Duration::from_millis(1520),
+            ..Default::default()
+        };
+        latency.buckets[1..].iter_mut().for_each(|count| *count = 1);
+        latency.buckets[8..].iter_mut().for_each(|count| *count = 2);
+        let mut snapshot = MetricsSnapshot::default();
+        snapshot.methods.insert(
+            "Echo.echo".into(),
+            MethodMetrics {
+                calls: 4,
+                errors: 1,
+                in_flight: 1,
+                latency,
+            },
+        );
+
+        let encoded = snapshot.encode_prometheus();
+        let lines: Vec<_> = encoded.lines().collect();
+        assert!(lines.contains(&"# TYPE toy_rpc_calls_total counter"));
+        assert!(lines.contains(&"toy_rpc_calls_total{service=\"Echo\",method=\"echo\"} 4"));
+        assert!(lines.contains(&"toy_rpc_errors_total{service=\"Echo\",method=\"echo\"} 1"));
+        assert!(lines.contains(&"toy_rpc_in_flight{service=\"Echo\",method=\"echo\"} 1"));
+        assert!(lines.contains(
+            &"toy_rpc_call_duration_seconds_bucket{service=\"Echo\",method=\"echo\",le=\"0.005\"} 0"
+        ));
+        assert!(lines.contains(
+            &"toy_rpc_call_duration_seconds_bucket{service=\"Echo\",method=\"echo\",le=\"2.5\"} 2"
+        ));
+        assert!(lines.contains(
+            &"toy_rpc_call_duration_seconds_bucket{service=\"Echo\",method=\"echo\",le=\"+Inf\"} 3"
+        ));
+        assert!(lines
+            .contains(&"toy_rpc_call_duration_seconds_sum{service=\"Echo\",method=\"echo\"} 1.52"));
+        assert!(lines
+            .contains(&"toy_rpc_call_duration_seconds_count{service=\"Echo\",method=\"echo\"} 3"));
+        assert_eq!(escape_label("a\"b\\c\n"), "a\\\"b\\\\c\\n");
+    }
+}
@@ -5,7 +5,7 @@
 use cfg_if::cfg_if;
 use std::sync::{atomic::AtomicU64, Arc};
 
-use crate::service::AsyncServiceMap;
+use crate::extension::ExtensionMap;
 
 cfg_if! {
     if #[cfg(any(
@@ -15,25 +15,46 @@ cfg_if! {
     ))] {
         use flume::Sender;
         mod integration;
-        mod broker;
+        pub(crate) mod broker;
         mod reader;
         mod writer;
+        pub mod blocking;
 
         pub mod pubsub;
         use pubsub::{PubSubBroker, PubSubItem};
+        pub mod store;
+        pub mod source;
+        pub mod audit;
+        pub mod stats;
+        use stats::{ConnectionRegistry, ConnectionStats};
+        pub mod lifecycle;
+        pub mod flow;
+        pub mod shutdown;
+        use shutdown::ShutdownHandle;
+        pub mod health;
+        use health::{Health, HealthProbe};
+        pub mod topics;
+        use topics::TopicRegistry;
+        #[cfg(feature = "metrics")]
+        pub mod metrics;
+        #[cfg(any(
+            all(feature = "http_hyper", not(feature = "http_actix_web")),
+            feature = "http_warp",
+        ))]
+        pub(crate) mod post;
+        use std::collections::HashMap;
+        use crate::payload::{PayloadAccounting, PayloadStats};
     }
 }
 
 #[cfg(any(
     feature = "docs",
-    doc,
     all(feature = "async_std_runtime", not(feature = "tokio_runtime"),)
 ))]
 mod async_std;
 
 #[cfg(any(
     feature = "docs",
-    doc,
     all(
         feature = "tokio_runtime",
         not(feature = "async_std_runtime"),
@@ -42,24 +63,78 @@ mod async_std;
 ))]
 mod tokio;
 
+#[cfg(any(
+    all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+    all(
+        feature = "tokio_runtime",
+        not(feature = "async_std_runtime"),
+        not(feature = "http_actix_web")
+    )
+))]
+pub mod filter;
+#[cfg(any(
+    all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+    all(
+        feature = "tokio_runtime",
+        not(feature = "async_std_runtime"),
+        not(feature = "http_actix_web")
+    )
+))]
+pub mod incoming;
+#[cfg(any(
+    all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+    all(
+        feature = "tokio_runtime",
+        not(feature = "async_std_runtime"),
+        not(feature = "http_actix_web")
+    )
+))]
+pub mod limit;
+#[cfg(any(
+    all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+    all(
+        feature = "tokio_runtime",
+        not(feature = "async_std_runtime"),
+        not(feature = "http_actix_web")
+    )
+))]
+pub mod peer;
+#[cfg(any(
+    all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+    all(
+        feature = "tokio_runtime",
+        not(feature = "async_std_runtime"),
+        not(feature = "http_actix_web")
+    )
+))]
+mod proxy;
+
 pub mod builder;
 use builder::ServerBuilder;
+pub(crate) mod data;
+pub mod guard;
+pub mod naming;
+pub mod profile;
+pub mod recovery;
+pub mod schema;
+use naming::Routes;
 
 pub(crate) type ClientId = u64;
 pub(crate) type AtomicClientId = AtomicU64;
 
-/// Client ID 0 is reserved for publisher and subscriber on the server side.
-/// Remote client have their ID starting from `RESERVED_CLIENT_ID + 1`
+/// Client ID 0 is reserved for the publishers on the server side.
+/// Remote clients and server side subscribers have their ID starting from `RESERVED_CLIENT_ID + 1`
 pub const RESERVED_CLIENT_ID: ClientId = 0;
 
 /// RPC Server
 ///
-/// ```
+/// ```no_run
 /// const DEFAULT_RPC_PATH: &str = "_rpc_";
 /// ```
 #[derive(Clone)]
 pub struct Server {
-    services: Arc<AsyncServiceMap>,
+    services: Arc<Routes>,
+    extensions: Arc<ExtensionMap>,
     client_counter: Arc<AtomicClientId>, // monotomically increase counter
 
     #[cfg(any(
@@ -68,6 +143,85 @@ pub struct Server {
         all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
     ))]
     pubsub_tx: Sender<PubSubItem>,
+
+    #[cfg(any(
+        feature = "docs",
+        all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+        all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+    ))]
+    audit: Option<Arc<dyn audit::AuditSink>>,
+
+    #[cfg(any(
+        feature = "docs",
+        all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+        all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+    ))]
+    // the connections handed over by actix-web are framed by its session actor
+    #[cfg_attr(feature = "http_actix_web", allow(dead_code))]
+    frame: crate::codec::FrameOptions,
+
+    #[cfg(any(
+        feature = "docs",
+        all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+        all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+    ))]
+    payload: Arc<PayloadAccounting>,
+
+    #[cfg(any(
+        feature = "docs",
+        all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+        all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+    ))]
+    connections: ConnectionRegistry,
+
+    #[cfg(any(
+        feature = "docs",
+        all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+        all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+    ))]
+    shutdown: ShutdownHandle,
+
+    #[cfg(any(
+        feature = "docs",
+        all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+        all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+    ))]
+    overload_threshold: Option<usize>,
+
+    #[cfg(any(
+        feature = "docs",
+        all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+        all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+    ))]
+    topics: Arc<TopicRegistry>,
+
+    #[cfg(any(
+        all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+        all(
+            feature = "tokio_runtime",
+            not(feature = "async_std_runtime"),
+            not(feature = "http_actix_web")
+        )
+    ))]
+    proxy_protocol: bool,
+    #[cfg(any(
+        all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+        all(
+            feature = "tokio_runtime",
+            not(feature = "async_std_runtime"),
+            not(feature = "http_actix_web")
+        )
+    ))]
+    connection_filter: Option<filter::ConnectionFilter>,
+    #[cfg(any(
+        all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+        all(
+            feature = "tokio_runtime",
+            not(feature = "async_std_runtime"),
+            not(feature = "http_actix_web")
+        )
+    ))]
+    limits: limit::ConnectionLimits,
 }
 
 #[cfg(any(
@@ -78,7 +232,7 @@ pub struct Server {
 impl Drop for Server {
     fn drop(&mut self) {
         if let Err(err) = self.pubsub_tx.send(PubSubItem::Stop) {
-            log::error!("{}", err);
+            error!("{}", err);
         }
     }
 }
@@ -88,8 +242,8 @@ impl Server {
     ///
     /// Example
     ///
-    /// ```rust
-    /// use toy_rpc::server::{ServerBuilder, Server};
+    /// ```no_run
+    /// use toy_rpc::{Server, ServerBuilder};
     ///
     /// let builder: ServerBuilder = Server::builder();
     /// ```
@@ -109,46 +263,308 @@ cfg_if! {
         impl Server {
             /// Builds a Server from a ServerBuilder
             pub fn from_builder(builder: ServerBuilder) -> Self {
-                let services = Arc::new(builder.services);
+                #[cfg(feature = "metrics")]
+                let metrics = metrics::MetricsRegistry::new(
+                    &builder.method_names,
+                    builder.services.keys(),
+                    builder.export_metrics,
+                );
+                let services = Arc::new(Routes::new(
+                    builder.services,
+                    builder.method_names,
+                    builder.name_normalizer,
+                ));
+                let extensions = Arc::new(builder.extensions);
                 let (tx, rx) = flume::unbounded();
 
-                let pubsub_broker = PubSubBroker::new(rx);
+                let store = builder.store.unwrap_or_else(|| Box::new(store::MemoryStore::new()));
+                let topics = Arc::new(builder.topics);
+                let connections = ConnectionRegistry::new(builder.flow_control)
+                    .bandwidth(builder.outbound_bandwidth, builder.inbound_bandwidth)
+                    .hooks(builder.lifecycle)
+                    .timing(builder.timing);
+                #[cfg(feature = "signing")]
+                let connections = connections.sign_responses(builder.signer);
+                #[cfg(feature = "metrics")]
+                let connections = connections.record_metrics(metrics);
+                let pubsub_broker = PubSubBroker::new(
+                    rx,
+                    store,
+                    topics.clone(),
+                    builder.topic_retention,
+                    builder.topic_backpressure,
+                    builder.topic_sources,
+                    builder.topic_idle_timeout,
+                    builder.redelivery_timeout,
+                    builder.max_delivery_attempts,
+                );
                 pubsub_broker.spawn();
 
                 Self {
                     client_counter: Arc::new(AtomicClientId::new(RESERVED_CLIENT_ID + 1)),
                     services,
-                    pubsub_tx: tx
+                    extensions,
+                    pubsub_tx: tx,
+                    audit: builder.audit,
+                    frame: builder.frame,
+                    payload: Arc::new(PayloadAccounting {
+                        limits: builder.size_limits,
+                        counters: Default::default(),
+                    }),
+                    connections,
+                    shutdown: ShutdownHandle::default(),
+                    overload_threshold: builder.overload_threshold,
+                    topics,
+                    #[cfg(any(
+                        all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+                        all(
+                            feature = "tokio_runtime",
+                            not(feature = "async_std_runtime"),
+                            not(feature = "http_actix_web")
+                        )
+                    ))]
+                    proxy_protocol: builder.proxy_protocol,
+                    #[cfg(any(
+                        all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+                        all(
+                            feature = "tokio_runtime",
+                            not(feature = "async_std_runtime"),
+                            not(feature = "http_actix_web")
+                        )
+                    ))]
+                    connection_filter: builder.connection_filter,
+                    #[cfg(any(
+                        all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+                        all(
+                            feature = "tokio_runtime",
+                            not(feature = "async_std_runtime"),
+                            not(feature = "http_actix_web")
+                        )
+                    ))]
+                    limits: limit::ConnectionLimits::new(
+                        builder.max_connections,
+                        builder.max_connections_per_ip,
+                    ),
                 }
             }
+
+            /// Returns a handle to shut down all the sessions of the server
+            ///
+            /// See the [`shutdown`](shutdown/index.html) module for details.
+            pub fn shutdown_handle(&self) -> ShutdownHandle {
+                self.shutdown.clone()
+            }
+
+            /// Returns whether the server accepts new connections and calls, is
+            /// shutting down or is overloaded
+            ///
+            /// See the [`health`](health/index.html) module for details.
+            pub fn health(&self) -> Health {
+                self.health_probe().health()
+            }
+
+            /// Returns a probe that reads the health of the server, without keeping
+            /// the server alive
+            ///
+            /// See the [`health`](health/index.html) module for details.
+            pub fn health_probe(&self) -> HealthProbe {
+                HealthProbe::new(self.shutdown.clone(), self.overload_threshold)
+            }
+
+            /// Returns the numbers of calls and bytes of the payloads of every method
+            /// called so far, keyed by `"Service.method"`
+            ///
+            /// See the [`payload`](../payload/index.html) module for details.
+            pub fn payload_stats(&self) -> HashMap<String, PayloadStats> {
+                self.payload.counters.snapshot()
+            }
+
+            /// Returns the numbers of messages, bytes and codec errors of every open
+            /// connection, keyed by client ID
+            ///
+            /// See the [`stats`](stats/index.html) module for details.
+            pub fn connection_stats(&self) -> HashMap<u64, ConnectionStats> {
+                self.connections.snapshot()
+            }
+
+            /// Returns the numbers of calls, errors and calls in flight, and the
+            /// latencies, of every method of the registered services, keyed by
+            /// `"Service.method"`
+            ///
+            /// See the [`metrics`](metrics/index.html) module for details.
+            #[cfg(feature = "metrics")]
+            #[cfg_attr(feature = "docs", doc(cfg(feature = "metrics")))]
+            pub fn metrics(&self) -> metrics::MetricsSnapshot {
+                self.connections.metrics().snapshot()
+            }
+        }
+
+        #[cfg(any(feature = "docs", not(feature = "http_actix_web")))]
+        impl Server {
+            /// Serves a connection with the specified codec
+            ///
+            /// This allows connections that are accepted outside of the `Server` (eg. custom
+            /// TLS, proxy protocols or tunnels) to be served by the full server pipeline.
+            /// Any codec that implements `SplittableCodec`, including one defined outside of
+            /// this crate, can be used. This returns when the connection is closed.
+            ///
+            /// Unlike the other methods that serve connections, this does not need any of
+            /// the `serde_*` feature flags, eg. with a `codec::custom::CustomCodec`.
+            ///
+            /// Example
+            ///
+            /// ```no_run
+            /// # use std::sync::Arc;
+            /// # #[cfg(feature = "async_std_runtime")]
+            /// # use async_std::net::TcpListener;
+            /// # #[cfg(feature = "tokio_runtime")]
+            /// # use tokio::net::TcpListener;
+            /// # use toy_rpc::Server;
+            /// # use toy_rpc::macros::export_impl;
+            /// # struct Example;
+            /// # #[export_impl]
+            /// # impl Example {
+            /// #     #[export_method]
+            /// #     async fn echo(&self, args: String) -> Result<String, String> {
+            /// #         Ok(args)
+            /// #     }
+            /// # }
+            /// # async fn run(listener: TcpListener) {
+            /// # let example_service = Arc::new(Example);
+            /// let server = Server::builder()
+            ///     .register(example_service)
+            ///     .build();
+            /// let (stream, _) = listener.accept().await.unwrap();
+            /// let codec = toy_rpc::codec::Codec::new(stream);
+            /// server.serve_codec(codec).await.unwrap();
+            /// # }
+            /// ```
+            #[cfg_attr(feature = "docs", doc(cfg(all(feature = "async_std_runtime", not(feature = "tokio_runtime")))))]
+            #[cfg_attr(feature = "docs", doc(cfg(all(feature = "tokio_runtime", not(feature = "async_std_runtime")))))]
+            pub async fn serve_codec<C>(&self, codec: C) -> Result<(), crate::Error>
+            where
+                C: crate::codec::split::SplittableCodec + Send + 'static,
+            {
+                let client_id = self.client_counter.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                start_broker_reader_writer(codec, client_id, self.connection_context()).await
+            }
+
+            /// Clones the state that a new connection is served with
+            pub(crate) fn connection_context(&self) -> ConnectionContext {
+                ConnectionContext {
+                    services: self.services.clone(),
+                    extensions: self.extensions.clone(),
+                    pubsub_tx: self.pubsub_tx.clone(),
+                    audit: self.audit.clone(),
+                    payload: self.payload.clone(),
+                    connections: self.connections.clone(),
+                    shutdown: self.shutdown.clone(),
+                    frame: self.frame,
+                    #[cfg(any(
+                        all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+                        all(
+                            feature = "tokio_runtime",
+                            not(feature = "async_std_runtime"),
+                            not(feature = "http_actix_web")
+                        )
+                    ))]
+                    proxy_protocol: self.proxy_protocol,
+                    #[cfg(any(
+                        all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+                        all(
+                            feature = "tokio_runtime",
+                            not(feature = "async_std_runtime"),
+                            not(feature = "http_actix_web")
+                        )
+                    ))]
+                    connection_filter: self.connection_filter.clone(),
+                }
+            }
+        }
+
+        /// The state of the `Server` that a connection is served with, which is
+        /// cloned for every accepted connection
+        #[cfg(any(feature = "docs", not(feature = "http_actix_web")))]
+        #[derive(Clone)]
+        pub(crate) struct ConnectionContext {
+            pub services: Arc<Routes>,
+            pub extensions: Arc<ExtensionMap>,
+            pub pubsub_tx: Sender<PubSubItem>,
+            pub audit: Option<Arc<dyn audit::AuditSink>>,
+            pub payload: Arc<PayloadAccounting>,
+            pub connections: ConnectionRegistry,
+            pub shutdown: ShutdownHandle,
+            // only read when the server frames the connection itself
+            pub frame: crate::codec::FrameOptions,
+            #[cfg(any(
+                all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+                all(
+                    feature = "tokio_runtime",
+                    not(feature = "async_std_runtime"),
+                    not(feature = "http_actix_web")
+                )
+            ))]
+            pub proxy_protocol: bool,
+            #[cfg(any(
+                all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+                all(
+                    feature = "tokio_runtime",
+                    not(feature = "async_std_runtime"),
+                    not(feature = "http_actix_web")
+                )
+            ))]
+            pub connection_filter: Option<filter::ConnectionFilter>,
+        }
+
+        #[cfg(any(feature = "docs", not(feature = "http_actix_web")))]
+        impl ConnectionContext {
+            /// Records the address of the peer on the connection
+            pub fn peer(mut self, peer_addr: impl Into<Option<std::net::SocketAddr>>) -> Self {
+                self.connections = self.connections.peer(peer_addr);
+                self
+            }
         }
 
         // Spawn tasks for the reader/broker/writer loops
-        #[cfg(any(
-            feature = "docs",
-            all(
-                any(
-                    feature = "serde_bincode",
-                    feature = "serde_json",
-                    feature = "serde_cbor",
-                    feature = "serde_rmp",
-                ),
-                not(feature = "http_actix_web")
-            )
-        ))]
+        #[cfg(any(feature = "docs", not(feature = "http_actix_web")))]
         pub(crate) async fn start_broker_reader_writer(
             codec: impl crate::codec::split::SplittableCodec + 'static,
-            services: Arc<AsyncServiceMap>,
             client_id: ClientId,
-            pubsub_tx: Sender<PubSubItem>,
+            context: ConnectionContext,
         ) -> Result<(), crate::Error> {
-            let (writer, reader) = codec.split();
+            use crate::codec::{CodecRead, CodecWrite};
+
+            let ConnectionContext {
+                services,
+                extensions,
+                pubsub_tx,
+                audit,
+                payload,
+                connections,
+                shutdown,
+                ..
+            } = context;
+
+            let (mut writer, mut reader) = codec.split();
+            let connection = connections.register(client_id);
+            writer.set_meter(connection.meter());
+            reader.set_meter(connection.meter());
 
-            let reader = reader::ServerReader::new(reader, services);
-            let writer = writer::ServerWriter::new(writer);
-            let broker = broker::ServerBroker::new(client_id, pubsub_tx);
+            let reader_auditor = audit.clone().map(|sink| audit::Auditor::new(sink, client_id));
+            let reader = reader::ServerReader::new(reader, services, extensions, reader_auditor, payload.clone(), connection.counters(), connection.outbound());
+            let writer = writer::ServerWriter::new(writer, payload, connection.counters(), connection.outbound());
+            #[cfg(feature = "signing")]
+            let writer = writer.sign_responses(connection.signer());
+            let broker_auditor = audit.map(|sink| audit::Auditor::new(sink, client_id));
+            let broker = broker::ServerBroker::new(client_id, pubsub_tx, broker_auditor, connection.outbound(), connection.timing());
+            #[cfg(feature = "metrics")]
+            let broker = broker.record_metrics(connection.metrics());
 
-            let (broker_handle, _) = brw::spawn(broker, reader, writer);
+            let (broker_handle, broker_tx) = brw::spawn(broker, reader, writer);
+            let _session = shutdown.register(client_id, move |grace| {
+                // the session may have ended already
+                let _ = broker_tx.send(broker::ServerBrokerItem::Shutdown(grace));
+            });
             let _ = broker_handle.await;
             Ok(())
         }
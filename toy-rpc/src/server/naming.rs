@@ -0,0 +1,350 @@
+//! Normalized routing of the `"Service.method"` names
+//!
+//! The clients generated by other tooling do not always name the methods the way
+//! they are written in Rust. A JavaScript client may call `"Arith.addNumbers"` for
+//! the method `add_numbers`, or append the version of its schema to the name, eg.
+//! `"Arith.add_v2"`. The `NameNormalizer` set with `ServerBuilder::normalize_names`
+//! maps such names to the registered ones without an alias per method:
+//!
+//! - `case_insensitive` folds the case of the names, so `"arith.ADD"` calls
+//!   `Arith.add`
+//! - `snake_case` converts the camelCase and kebab-case names to snake_case, so
+//!   `"Arith.addNumbers"` and `"Arith.add-numbers"` call `Arith.add_numbers`
+//! - `strip_version` removes a trailing version, written as `_v2`, `-v2` or `V2`,
+//!   so `"Arith.add_v2"` and `"Arith.addV2"` call `Arith.add`
+//!
+//! The rules are applied to both the name of the service and the name of the
+//! method, and to both the names in the requests and the names that are registered.
+//! A name that matches a registered one exactly is never normalized. If two
+//! registered names are the same once normalized, neither of them can be called by
+//! its normalized name, and a warning is logged when the server is built.
+//!
+//! The name is resolved before anything else, so the size limits, the policies, the
+//! metrics and the audit log all see the registered name. The names are not
+//! normalized by default.
+//!
+//! # Example
+//!
+//! ```no_run
+//! # #[cfg(any(feature = "async_std_runtime", feature = "tokio_runtime"))]
+//! # {
+//! use toy_rpc::server::naming::NameNormalizer;
+//! # use std::sync::Arc;
+//! # use toy_rpc::Server;
+//! # use toy_rpc::macros::export_impl;
+//! # struct Example;
+//! # #[export_impl]
+//! # impl Example {
+//! #     #[export_method]
+//! #     async fn echo(&self, args: String) -> Result<String, String> {
+//! #         Ok(args)
+//! #     }
+//! # }
+//! # let example_service = Arc::new(Example);
+//!
+//! let server = Server::builder()
+//!     .register(example_service)
+//!     .normalize_names(NameNormalizer::default().snake_case(true).strip_version(true))
+//!     .build();
+//! # }
+//! ```
+
+use std::collections::{HashMap, HashSet};
+use std::ops::Deref;
+
+use crate::service::AsyncServiceMap;
+
+/// Rules applied to the names of the services and methods before they are looked up
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NameNormalizer {
+    /// Whether the case of the names is folded
+    pub case_insensitive: bool,
+    /// Whether the camelCase and kebab-case names are converted to snake_case
+    pub snake_case: bool,
+    /// Whether a trailing version like `_v2` is removed
+    pub strip_version: bool,
+}
+
+impl NameNormalizer {
+    /// Sets whether the case of the names is folded
+    pub fn case_insensitive(self, enabled: bool) -> Self {
+        let mut normalizer = self;
+        normalizer.case_insensitive = enabled;
+        normalizer
+    }
+
+    /// Sets whether the camelCase and kebab-case names are converted to snake_case.
+    /// The converted names are in lower case.
+    pub fn snake_case(self, enabled: bool) -> Self {
+        let mut normalizer = self;
+        normalizer.snake_case = enabled;
+        normalizer
+    }
+
+    /// Sets whether a trailing version like `_v2`, `-v2` or `V2` is removed
+    pub fn strip_version(self, enabled: bool) -> Self {
+        let mut normalizer = self;
+        normalizer.strip_version = enabled;
+        normalizer
+    }
+
+    /// Applies the rules to a name
+    pub fn normalize(&self, name: &str) -> String {
+        let mut name = match self.snake_case {
+            true => to_snake_case(name),
+            false => name.to_string(),
+        };
+        if self.strip_version {
+            let len = version_start(&name);
+            name.truncate(len);
+        }
+        if self.case_insensitive {
+            name = name.to_lowercase();
+        }
+        name
+    }
+}
+
+/// Inserts an underscore at each word boundary of a camelCase name and converts it
+/// to lower case, eg. `getHTTPStatus2` becomes `get_http_status2`
+fn to_snake_case(name: &str) -> String {
+    let chars: Vec<char> = name.chars().collect();
+    let mut snake = String::with_capacity(name.len() + 4);
+    for (i, &c) in chars.iter().enumerate() {
+        if c == '-' {
+            snake.push('_');
+            continue;
+        }
+        if c.is_uppercase() && i > 0 {
+            let prev = chars[i - 1];
+            let next_is_lower = chars.get(i + 1).is_some_and(|c| c.is_lowercase());
+            let boundary = prev.is_lowercase()
+                || prev.is_ascii_digit()
+                || (prev.is_uppercase() && next_is_lower);
+            if boundary && !snake.ends_with('_') {
+                snake.push('_');
+            }
+        }
+        snake.extend(c.to_lowercase());
+    }
+    snake
+}
+
+/// Returns the length of the name without its trailing version, if any. The version
+/// is a `v` and some digits preceded by `_` or `-`, or a `V` and some digits at a
+/// camelCase word boundary.
+fn version_start(name: &str) -> usize {
+    let digits = name.len() - name.trim_end_matches(|c: char| c.is_ascii_digit()).len();
+    if digits == 0 {
+        return name.len();
+    }
+    let rest = &name[..name.len() - digits];
+    let mut chars = rest.chars().rev();
+    let (v, prev) = (chars.next(), chars.next());
+    let start = match (v, prev) {
+        (Some('v'), Some('_')) | (Some('v'), Some('-')) => rest.len() - 2,
+        (Some('V'), Some('_')) | (Some('V'), Some('-')) => rest.len() - 2,
+        (Some('V'), Some(prev)) if prev.is_lowercase() || prev.is_ascii_digit() => rest.len() - 1,
+        _ => return name.len(),
+    };
+    match start {
+        0 => name.len(),
+        start => start,
+    }
+}
+
+/// The registered names keyed by their normalized form
+struct Names {
+    exact: HashSet<&'static str>,
+    // `None` if several registered names have the same normalized form
+    normalized: HashMap<String, Option<&'static str>>,
+}
+
+impl Names {
+    fn new(
+        names: impl IntoIterator<Item = &'static str>,
+        normalizer: &NameNormalizer,
+        scope: &str,
+    ) -> Self {
+        let exact: HashSet<&'static str> = names.into_iter().collect();
+        let mut normalized = HashMap::new();
+        for &name in &exact {
+            normalized
+                .entry(normalizer.normalize(name))
+                .and_modify(|registered: &mut Option<&'static str>| {
+                    if let Some(registered) = registered.take() {
+                        warn!(
+                            "{}{} and {}{} have the same normalized name",
+                            scope, registered, scope, name
+                        );
+                    }
+                })
+                .or_insert(Some(name));
+        }
+        Self { exact, normalized }
+    }
+
+    fn resolve(&self, name: &str, normalizer: &NameNormalizer) -> Option<&'static str> {
+        match self.exact.get(name) {
+            Some(name) => Some(*name),
+            None => self
+                .normalized
+                .get(&normalizer.normalize(name))
+                .copied()
+                .flatten(),
+        }
+    }
+}
+
+/// The normalized names of the services and of their methods
+struct NameTable {
+    normalizer: NameNormalizer,
+    services: Names,
+    methods: HashMap<&'static str, Names>,
+}
+
+/// The services of a server along with the table resolving the names of the
+/// requests to the registered names
+pub(crate) struct Routes {
+    services: AsyncServiceMap,
+    names: Option<NameTable>,
+}
+
+impl Routes {
+    pub(crate) fn new(
+        services: AsyncServiceMap,
+        methods: HashMap<&'static str, Vec<&'static str>>,
+        normalizer: Option<NameNormalizer>,
+    ) -> Self {
+        let names = normalizer.map(|normalizer| {
+            let methods = methods
+                .into_iter()
+                .filter(|(service, _)| services.contains_key(service))
+                .map(|(service, methods)| {
+                    let scope = format!("{}.", service);
+                    (service, Names::new(methods, &normalizer, &scope))
+                })
+                .collect();
+            NameTable {
+                normalizer,
+                services: Names::new(services.keys().copied(), &normalizer, ""),
+                methods,
+            }
+        });
+        Self { services, names }
+    }
+
+    /// Maps the `"Service.method"` name of a request to the registered name. The
+    /// name is returned as is if there is no registered name it maps to.
+    pub(crate) fn resolve(&self, service_method: String) -> String {
+        let names = match &self.names {
+            Some(names) => names,
+            None => return service_method,
+        };
+        let (service, method) = match service_method.split_once('.') {
+            Some(parts) => parts,
+            None => return service_method,
+        };
+        let service = match names.services.resolve(service, &names.normalizer) {
+            Some(service) => service,
+            None => return service_method,
+        };
+        let method = names
+            .methods
+            .get(service)
+            .and_then(|methods| methods.resolve(method, &names.normalizer))
+            .unwrap_or(method);
+        format!("{}.{}", service, method)
+    }
+}
+
+impl Deref for Routes {
+    type Target = AsyncServiceMap;
+
+    fn deref(&self) -> &Self::Target {
+        &self.services
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::{ArcAsyncServiceCall, HandlerResultFut};
+    use std::sync::Arc;
+
+    fn routes(normalizer: NameNormalizer) -> Routes {
+        let call: ArcAsyncServiceCall = Arc::new(|_, _| -> HandlerResultFut {
+            Box::pin(async { Err(crate::error::Error::MethodNotFound) })
+        });
+        let mut services = AsyncServiceMap::new();
+        services.insert("Arith", call.clone());
+        services.insert("CommonTest", call);
+        let mut methods = HashMap::new();
+        methods.insert("Arith", vec!["add", "add_numbers", "get", "get_v1"]);
+        methods.insert("CommonTest", vec!["get_magic_i16", "echo", "Echo"]);
+        Routes::new(services, methods, Some(normalizer))
+    }
+
+    #[test]
+    fn names_are_normalized() {
+        let normalizer = NameNormalizer::default();
+        assert_eq!(normalizer.normalize("addNumbers"), "addNumbers");
+
+        let normalizer = normalizer.snake_case(true);
+        assert_eq!(normalizer.normalize("addNumbers"), "add_numbers");
+        assert_eq!(normalizer.normalize("add-numbers"), "add_numbers");
+        assert_eq!(normalizer.normalize("getHTTPStatus2"), "get_http_status2");
+        assert_eq!(normalizer.normalize("getMagicI16"), "get_magic_i16");
+        assert_eq!(normalizer.normalize("get_magic_i16"), "get_magic_i16");
+        assert_eq!(normalizer.normalize("CommonTest"), "common_test");
+
+        let normalizer = NameNormalizer::default().strip_version(true);
+        assert_eq!(normalizer.normalize("add_v2"), "add");
+        assert_eq!(normalizer.normalize("add-V10"), "add");
+        assert_eq!(normalizer.normalize("addV2"), "add");
+        assert_eq!(normalizer.normalize("dev2"), "dev2");
+        assert_eq!(normalizer.normalize("get_magic_i16"), "get_magic_i16");
+        assert_eq!(normalizer.normalize("_v2"), "_v2");
+
+        let normalizer = NameNormalizer::default().case_insensitive(true);
+        assert_eq!(normalizer.normalize("ADD"), "add");
+    }
+
+    #[test]
+    fn requests_are_resolved() {
+        let routes = routes(
+            NameNormalizer::default()
+                .case_insensitive(true)
+                .snake_case(true)
+                .strip_version(true),
+        );
+        assert_eq!(routes.resolve("Arith.add".into()), "Arith.add");
+        assert_eq!(routes.resolve("arith.ADD".into()), "Arith.add");
+        assert_eq!(
+            routes.resolve("Arith.addNumbers".into()),
+            "Arith.add_numbers"
+        );
+        assert_eq!(routes.resolve("Arith.addV2".into()), "Arith.add");
+        assert_eq!(
+            routes.resolve("commonTest.getMagicI16".into()),
+            "CommonTest.get_magic_i16"
+        );
+        // `get` and `get_v1`, as well as `echo` and `Echo`, are ambiguous once
+        // normalized, but can still be called by their exact names
+        assert_eq!(routes.resolve("Arith.get_v1".into()), "Arith.get_v1");
+        assert_eq!(routes.resolve("Arith.get_v2".into()), "Arith.get_v2");
+        assert_eq!(routes.resolve("CommonTest.Echo".into()), "CommonTest.Echo");
+        assert_eq!(routes.resolve("CommonTest.ECHO".into()), "CommonTest.ECHO");
+        // unknown names are returned as is
+        assert_eq!(routes.resolve("Other.add".into()), "Other.add");
+        assert_eq!(routes.resolve("Arith.sub".into()), "Arith.sub");
+        assert_eq!(routes.resolve("Arith".into()), "Arith");
+    }
+
+    #[test]
+    fn names_are_exact_by_default() {
+        let services = AsyncServiceMap::new();
+        let routes = Routes::new(services, HashMap::new(), None);
+        assert_eq!(routes.resolve("arith.ADD".into()), "arith.ADD");
+    }
+}
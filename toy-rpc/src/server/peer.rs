@@ -0,0 +1,191 @@
+//! Calls from the server to the services of a connected client
+//!
+//! A client may serve its own services over the connection it dialed out, see
+//! `ClientBuilder::serve`, so that an agent behind a NAT or a firewall can both call
+//! the server and receive calls from it without accepting any inbound connection.
+//! On the server, the calls to the services of a client are made through the `Peer`
+//! of its connection, which a handler gets from `Context::peer`. The `Peer` can be
+//! kept after the handler returns, eg. in a registry of the connected agents, and
+//! its calls fail once the connection is closed.
+//!
+//! The calls to a client carry neither metadata nor streams.
+//!
+//! # Example
+//!
+//! ```no_run
+//! # use std::collections::HashMap;
+//! # use futures::lock::Mutex;
+//! # use toy_rpc::Error;
+//! # use toy_rpc::macros::export_impl;
+//! # use toy_rpc::metadata::Context;
+//! # use toy_rpc::server::peer::Peer;
+//! # struct Hub {
+//! #     agents: Mutex<HashMap<String, (Peer, String)>>,
+//! # }
+//! #[export_impl]
+//! impl Hub {
+//!     #[export_method]
+//!     async fn register(&self, context: Context, name: String) -> Result<(), Error> {
+//!         let peer = context
+//!             .peer()
+//!             .ok_or_else(|| Error::ExecutionError("Not a duplex connection".into()))?;
+//!         let version: String = peer.call("Agent.version", ()).await?;
+//!         self.agents.lock().await.insert(name, (peer, version));
+//!         Ok(())
+//!     }
+//! }
+//! ```
+
+use flume::Sender;
+use futures::channel::oneshot;
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::error::Error;
+use crate::message::{AtomicMessageId, ErrorMessage, MessageId};
+use crate::protocol::{InboundBody, OutboundBody};
+
+use super::broker::ServerBrokerItem;
+use super::ClientId;
+
+/// Timeout of the calls made with `Peer::call`
+const DEFAULT_TIMEOUT_SECONDS: u64 = 10;
+
+/// Body of the response of a client, which is the error if the call failed
+pub(crate) type PeerResult = Result<Box<InboundBody>, Box<InboundBody>>;
+
+/// Handle to call the services of a connected client. See the module documentation.
+#[derive(Debug, Clone)]
+pub struct Peer {
+    client_id: ClientId,
+    broker: Sender<ServerBrokerItem>,
+    count: Arc<AtomicMessageId>,
+}
+
+impl Peer {
+    pub(crate) fn new(
+        client_id: ClientId,
+        broker: Sender<ServerBrokerItem>,
+        count: Arc<AtomicMessageId>,
+    ) -> Self {
+        Self {
+            client_id,
+            broker,
+            count,
+        }
+    }
+
+    /// ID of the connection of the client on the server
+    pub fn client_id(&self) -> u64 {
+        self.client_id
+    }
+
+    /// Calls a service of the client, which times out after 10 seconds
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use toy_rpc::server::peer::Peer;
+    /// # async fn run(peer: Peer) -> Result<(), toy_rpc::Error> {
+    /// let reply: String = peer.call("Agent.echo", "hello".to_string()).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn call<Req, Res>(
+        &self,
+        service_method: impl ToString,
+        args: Req,
+    ) -> Result<Res, Error>
+    where
+        Req: serde::Serialize + Send + Sync + 'static,
+        Res: DeserializeOwned,
+    {
+        let duration = Duration::from_secs(DEFAULT_TIMEOUT_SECONDS);
+        self.call_with_timeout(service_method, args, duration).await
+    }
+
+    /// Calls a service of the client with a timeout. The call is canceled on the
+    /// client once it times out.
+    pub async fn call_with_timeout<Req, Res>(
+        &self,
+        service_method: impl ToString,
+        args: Req,
+        duration: Duration,
+    ) -> Result<Res, Error>
+    where
+        Req: serde::Serialize + Send + Sync + 'static,
+        Res: DeserializeOwned,
+    {
+        let id = self.count.fetch_add(1, Ordering::Relaxed);
+        let (reply, response) = oneshot::channel();
+        let item = ServerBrokerItem::PeerRequest {
+            id,
+            service_method: service_method.to_string(),
+            duration,
+            body: Box::new(args) as Box<OutboundBody>,
+            reply,
+        };
+        self.broker.send_async(item).await?;
+
+        match crate::clock::timeout(duration, response).await {
+            Ok(Ok(result)) => deserialize_result(result),
+            // the connection is closed
+            Ok(Err(_)) => Err(Error::Canceled(Some(id))),
+            Err(_) => {
+                // the connection may have been closed already
+                let _ = self
+                    .broker
+                    .send_async(ServerBrokerItem::PeerCancel(id))
+                    .await;
+                Err(Error::Timeout(Some(id)))
+            }
+        }
+    }
+}
+
+fn deserialize_result<Res: DeserializeOwned>(result: PeerResult) -> Result<Res, Error> {
+    match result {
+        Ok(mut body) => {
+            erased_serde::deserialize(&mut body).map_err(|err| Error::ParseError(Box::new(err)))
+        }
+        Err(mut body) => match erased_serde::deserialize::<ErrorMessage>(&mut body) {
+            Ok(msg) => Err(Error::from_err_msg(msg)),
+            Err(err) => Err(Error::ParseError(Box::new(err))),
+        },
+    }
+}
+
+/// Calls to the client that are waiting for their responses
+#[derive(Default)]
+pub(crate) struct PeerCalls {
+    pending: HashMap<MessageId, oneshot::Sender<PeerResult>>,
+}
+
+impl PeerCalls {
+    pub fn insert(&mut self, id: MessageId, reply: oneshot::Sender<PeerResult>) {
+        self.pending.insert(id, reply);
+    }
+
+    /// Passes the response of the client to the caller, if it still waits for it
+    pub fn respond(&mut self, id: MessageId, result: PeerResult) {
+        match self.pending.remove(&id) {
+            Some(reply) => {
+                let _ = reply.send(result);
+            }
+            None => debug!("Received the response of an unknown call {}", id),
+        }
+    }
+
+    /// Removes a call, returns whether it was waiting for its response
+    pub fn remove(&mut self, id: MessageId) -> bool {
+        self.pending.remove(&id).is_some()
+    }
+
+    /// Fails all the calls once the connection is closed
+    pub fn clear(&mut self) {
+        self.pending.clear();
+    }
+}
@@ -0,0 +1,323 @@
+//! Sessions of the HTTP POST transport on the server side
+//!
+//! The HTTP integrations hand the POST requests to the RPC path over to
+//! `PostSessions`, which keeps the connection of each session. See the
+//! `transport::post` module for the protocol.
+
+use flume::{Receiver, Sender};
+use futures::channel::oneshot;
+use futures::future::{select, Either};
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::BuildHasher;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Instant;
+
+use crate::clock;
+use crate::codec::{Codec, ConnTypePayload};
+use crate::transport::post::{
+    self, ChannelReader, ChannelWriter, MAX_PENDING_INPUTS, POLL_TIMEOUT, SESSION_IDLE_TIMEOUT,
+};
+
+/// Codec of the connection of a session
+pub(crate) type SessionCodec = Codec<ChannelReader, ChannelWriter, ConnTypePayload>;
+
+/// Why a request of a session is not answered with payloads
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum PostError {
+    /// The session is unknown, or was closed
+    UnknownSession,
+    /// The body of the request is not a sequence of payloads, or the request is too
+    /// far ahead of those read so far
+    BadRequest,
+}
+
+/// Payloads written by the server, to be sent in the response to a request
+#[derive(Debug)]
+pub(crate) struct PostResponse {
+    pub seq: u64,
+    pub body: Vec<u8>,
+    /// The connection of the session is closed
+    pub closed: bool,
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    match mutex.lock() {
+        Ok(guard) => guard,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
+
+/// Payloads of the requests, which are read by the connection in the order they
+/// were written by the client
+struct Inbound {
+    // taken when the session is closed, which ends the connection
+    tx: Option<Sender<Vec<u8>>>,
+    next: u64,
+    pending: BTreeMap<u64, Vec<Vec<u8>>>,
+    // number of the request that closes the session
+    close_at: Option<u64>,
+}
+
+impl Inbound {
+    fn new(tx: Sender<Vec<u8>>) -> Self {
+        Self {
+            tx: Some(tx),
+            next: 1,
+            pending: BTreeMap::new(),
+            close_at: None,
+        }
+    }
+
+    /// Whether request number `input` is within `MAX_PENDING_INPUTS` of the next
+    /// request to read, which bounds the payloads kept until then
+    fn accepts(&self, input: u64) -> bool {
+        input < self.next.saturating_add(MAX_PENDING_INPUTS)
+    }
+}
+
+struct Session {
+    inbound: Mutex<Inbound>,
+    // the request that holds the lock receives the payloads written by the server
+    outbound: futures::lock::Mutex<Receiver<Vec<u8>>>,
+    seq: AtomicU64,
+    // releases the request held by the session
+    release: Mutex<Option<oneshot::Sender<()>>>,
+    last_seen: Mutex<Instant>,
+}
+
+impl Session {
+    fn touch(&self) {
+        *lock(&self.last_seen) = clock::now();
+    }
+
+    fn is_idle(&self, now: Instant) -> bool {
+        now.saturating_duration_since(*lock(&self.last_seen)) > SESSION_IDLE_TIMEOUT
+    }
+
+    /// Passes the payloads of request number `input` to the connection once those
+    /// of the requests before have been. Returns whether the request that closes
+    /// the session has been reached.
+    fn deliver(&self, input: u64, payloads: Vec<Vec<u8>>) -> Result<bool, PostError> {
+        let mut inbound = lock(&self.inbound);
+        if !inbound.accepts(input) {
+            return Err(PostError::BadRequest);
+        }
+        if input >= inbound.next {
+            inbound.pending.insert(input, payloads);
+        }
+        loop {
+            let next = inbound.next;
+            let payloads = match inbound.pending.remove(&next) {
+                Some(payloads) => payloads,
+                None => break,
+            };
+            if let Some(tx) = &inbound.tx {
+                for payload in payloads {
+                    let _ = tx.send(payload);
+                }
+            }
+            inbound.next += 1;
+        }
+        Ok(inbound
+            .close_at
+            .is_some_and(|close_at| inbound.next > close_at))
+    }
+
+    /// Ends the connection and releases the request held by the session
+    fn shutdown(&self) {
+        lock(&self.inbound).tx.take();
+        if let Some(release) = lock(&self.release).take() {
+            let _ = release.send(());
+        }
+    }
+}
+
+/// Closes the sessions that have been idle for too long, which ends their connections
+fn sweep(sessions: &mut HashMap<String, Arc<Session>>) {
+    let now = clock::now();
+    sessions.retain(|_, session| !session.is_idle(now));
+}
+
+/// Sessions of the HTTP POST transport of an HTTP integration
+#[derive(Default)]
+pub(crate) struct PostSessions {
+    sessions: Mutex<HashMap<String, Arc<Session>>>,
+    // keys the ids of the sessions so that they cannot be guessed
+    keys: RandomState,
+    count: AtomicU64,
+}
+
+impl PostSessions {
+    /// Opens a session. Returns its id and the codec of its connection, which the
+    /// integration serves like the other connections.
+    pub fn open(&self) -> (String, SessionCodec) {
+        let (reader, writer, inbound, outbound) = post::channel();
+        let session = Session {
+            inbound: Mutex::new(Inbound::new(inbound)),
+            outbound: futures::lock::Mutex::new(outbound),
+            // the response that opens the session is number 0
+            seq: AtomicU64::new(1),
+            release: Mutex::new(None),
+            last_seen: Mutex::new(clock::now()),
+        };
+        let id = self.new_id();
+        let mut sessions = lock(&self.sessions);
+        sweep(&mut sessions);
+        sessions.insert(id.clone(), Arc::new(session));
+        (id, SessionCodec::with_post_session(reader, writer))
+    }
+
+    fn new_id(&self) -> String {
+        let count = self.count.fetch_add(1, Ordering::Relaxed);
+        let half = |salt: u64| self.keys.hash_one((count, salt, clock::system_now()));
+        format!("{:016x}{:016x}", half(0), half(1))
+    }
+
+    /// Closes a session, which ends its connection, once the requests numbered
+    /// before `input` have been passed to the connection
+    pub fn close(&self, id: &str, input: Option<u64>) -> Result<(), PostError> {
+        let session = lock(&self.sessions)
+            .get(id)
+            .cloned()
+            .ok_or(PostError::UnknownSession)?;
+        if let Some(input) = input {
+            {
+                let mut inbound = lock(&session.inbound);
+                if !inbound.accepts(input) {
+                    return Err(PostError::BadRequest);
+                }
+                inbound.close_at = Some(input);
+            }
+            if !session.deliver(input, Vec::new())? {
+                return Ok(());
+            }
+        }
+        self.remove(id);
+        Ok(())
+    }
+
+    fn remove(&self, id: &str) {
+        if let Some(session) = lock(&self.sessions).remove(id) {
+            session.shutdown();
+        }
+    }
+
+    /// Passes the payloads of a request to the connection of the session, and waits
+    /// for the payloads to send back in the response. The payloads of a request
+    /// without an `input` number are ignored.
+    pub async fn exchange(
+        &self,
+        id: &str,
+        input: Option<u64>,
+        body: &[u8],
+    ) -> Result<PostResponse, PostError> {
+        let session = {
+            let mut sessions = lock(&self.sessions);
+            sweep(&mut sessions);
+            sessions.get(id).cloned().ok_or(PostError::UnknownSession)?
+        };
+        session.touch();
+        let payloads = post::decode_batch(body).map_err(|_| PostError::BadRequest)?;
+        if let Some(input) = input {
+            if session.deliver(input, payloads)? {
+                self.remove(id);
+            }
+        }
+
+        // this request is held from now on
+        let (release, released) = oneshot::channel();
+        if let Some(previous) = lock(&session.release).replace(release) {
+            let _ = previous.send(());
+        }
+        let outbound = session.outbound.lock().await;
+        let mut payloads = Vec::new();
+        let wait = Box::pin(clock::timeout(POLL_TIMEOUT, outbound.recv_async()));
+        let mut closed = match select(released, wait).await {
+            Either::Left(_) => false,
+            Either::Right((Ok(Ok(payload)), _)) => {
+                payloads.push(payload);
+                false
+            }
+            Either::Right((Ok(Err(_)), _)) => true,
+            Either::Right((Err(_), _)) => false,
+        };
+        payloads.extend(outbound.try_iter());
+        closed |= outbound.is_disconnected() && outbound.is_empty();
+        let seq = session.seq.fetch_add(1, Ordering::Relaxed);
+        drop(outbound);
+
+        session.touch();
+        if closed {
+            self.remove(id);
+        }
+        Ok(PostResponse {
+            seq,
+            body: post::encode_batch(&payloads),
+            closed,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::PayloadRead;
+
+    #[test]
+    fn inputs_are_read_in_order() {
+        let (mut reader, _writer, inbound, _outbound) = post::channel();
+        let session = Session {
+            inbound: Mutex::new(Inbound::new(inbound)),
+            outbound: futures::lock::Mutex::new(flume::unbounded().1),
+            seq: AtomicU64::new(1),
+            release: Mutex::new(None),
+            last_seen: Mutex::new(clock::now()),
+        };
+        lock(&session.inbound).close_at = Some(4);
+        assert!(!session
+            .deliver(2, vec![b"b".to_vec(), b"c".to_vec()])
+            .unwrap());
+        assert!(!session.deliver(1, vec![b"a".to_vec()]).unwrap());
+        // a request that is sent again is ignored
+        assert!(!session.deliver(1, vec![b"a".to_vec()]).unwrap());
+        assert!(!session.deliver(4, Vec::new()).unwrap());
+        assert!(session.deliver(3, vec![b"d".to_vec()]).unwrap());
+        session.shutdown();
+
+        let read = futures::executor::block_on(async {
+            let mut read = Vec::new();
+            while let Some(Ok(payload)) = reader.read_payload().await {
+                read.push(payload);
+            }
+            read
+        });
+        assert_eq!(
+            read,
+            vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec(), b"d".to_vec()]
+        );
+    }
+
+    #[test]
+    fn inputs_too_far_ahead_are_refused() {
+        let (_reader, _writer, inbound, _outbound) = post::channel();
+        let session = Session {
+            inbound: Mutex::new(Inbound::new(inbound)),
+            outbound: futures::lock::Mutex::new(flume::unbounded().1),
+            seq: AtomicU64::new(1),
+            release: Mutex::new(None),
+            last_seen: Mutex::new(clock::now()),
+        };
+        assert!(!session
+            .deliver(MAX_PENDING_INPUTS, vec![b"a".to_vec()])
+            .unwrap());
+        assert_eq!(
+            session.deliver(MAX_PENDING_INPUTS + 1, vec![b"b".to_vec()]),
+            Err(PostError::BadRequest)
+        );
+        assert!(!session.deliver(1, Vec::new()).unwrap());
+        assert!(!session.deliver(MAX_PENDING_INPUTS + 1, Vec::new()).unwrap());
+        assert_eq!(lock(&session.inbound).pending.len(), 2);
+    }
+}
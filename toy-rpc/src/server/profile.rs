@@ -0,0 +1,232 @@
+//! Profiling hooks around the execution of the handlers
+//!
+//! A `Profiler` set with `ServerBuilder::profiler` is called with the
+//! `"Service.method"` name and the ID of a call each time the handler of the call
+//! starts and stops running on a thread. A handler starts running when it is
+//! created and each time its future is polled, and stops once it returns or awaits.
+//! The time between a `start` and the following `stop` is therefore spent running
+//! the handler on the calling thread, which is what the thread CPU timers and the
+//! allocation counters of a profiler measure, eg. the samples of `pprof` can be
+//! tagged with the method between `start` and `stop`.
+//!
+//! `start` and `stop` are always called in pairs on the same thread, including when
+//! a call is canceled or times out. The items of a streaming response are produced
+//! after the handler returns and are not profiled. A method exported with
+//! `#[export_method(blocking)]` runs on the blocking pool, so only the time spent
+//! handing it over to the pool is profiled.
+//!
+//! # Example
+//!
+//! ```no_run
+//! # #[cfg(any(feature = "async_std_runtime", feature = "tokio_runtime"))]
+//! # {
+//! use std::cell::Cell;
+//! use std::time::Instant;
+//! # use std::sync::Arc;
+//! # use toy_rpc::Server;
+//! # use toy_rpc::macros::export_impl;
+//! # struct Example;
+//! # #[export_impl]
+//! # impl Example {
+//! #     #[export_method]
+//! #     async fn echo(&self, args: String) -> Result<String, String> {
+//! #         Ok(args)
+//! #     }
+//! # }
+//! # use toy_rpc::message::MessageId;
+//!
+//! thread_local! {
+//!     static STARTED: Cell<Option<Instant>> = Cell::new(None);
+//! }
+//!
+//! # let example_service = Arc::new(Example);
+//! let server = Server::builder()
+//!     .register(example_service)
+//!     .profiler((
+//!         |_: &str, _: MessageId| STARTED.with(|started| started.set(Some(Instant::now()))),
+//!         |service_method: &str, id: MessageId| {
+//!             if let Some(started) = STARTED.with(|started| started.take()) {
+//!                 println!("{} {} ran for {:?}", service_method, id, started.elapsed());
+//!             }
+//!         },
+//!     ))
+//!     .build();
+//! # }
+//! ```
+
+use erased_serde as erased;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context as TaskContext, Poll};
+
+use crate::message::MessageId;
+use crate::metadata::Context;
+use crate::service::{ArcAsyncServiceCall, AsyncServiceMap, HandlerResult, HandlerResultFut};
+
+/// Callbacks around the execution of the handlers
+///
+/// The callbacks are called on the threads running the handlers, so an
+/// implementation should be cheap. This is implemented for a pair of closures, the
+/// first one being `start` and the second one `stop`.
+pub trait Profiler: Send + Sync + 'static {
+    /// Called when the handler of a call starts running on the current thread
+    fn start(&self, service_method: &str, id: MessageId);
+
+    /// Called when the handler of a call stops running on the current thread
+    fn stop(&self, service_method: &str, id: MessageId);
+}
+
+impl<S, T> Profiler for (S, T)
+where
+    S: Fn(&str, MessageId) + Send + Sync + 'static,
+    T: Fn(&str, MessageId) + Send + Sync + 'static,
+{
+    fn start(&self, service_method: &str, id: MessageId) {
+        (self.0)(service_method, id)
+    }
+
+    fn stop(&self, service_method: &str, id: MessageId) {
+        (self.1)(service_method, id)
+    }
+}
+
+/// Wraps the handlers of all services with the profiler, if any
+pub(crate) fn apply(services: &mut AsyncServiceMap, profiler: Option<Arc<dyn Profiler>>) {
+    let profiler = match profiler {
+        Some(profiler) => profiler,
+        None => return,
+    };
+    for call in services.values_mut() {
+        let inner = call.clone();
+        *call = with_profiler(inner, profiler.clone());
+    }
+}
+
+fn with_profiler(inner: ArcAsyncServiceCall, profiler: Arc<dyn Profiler>) -> ArcAsyncServiceCall {
+    let call = move |method_name: String,
+                     deserializer: Box<dyn erased::Deserializer<'static> + Send>|
+          -> HandlerResultFut {
+        // the context of the call is the current one while the handler is created
+        let context = Context::current();
+        let fut = {
+            let _running = Running::start(profiler.as_ref(), &context);
+            inner(method_name, deserializer)
+        };
+        Box::pin(Profiled {
+            fut,
+            profiler: profiler.clone(),
+            context,
+        })
+    };
+    Arc::new(call)
+}
+
+/// Calls `stop` when dropped, so that `start` and `stop` are paired even if the
+/// handler panics
+struct Running<'a> {
+    profiler: &'a dyn Profiler,
+    context: &'a Context,
+}
+
+impl<'a> Running<'a> {
+    fn start(profiler: &'a dyn Profiler, context: &'a Context) -> Self {
+        profiler.start(context.service_method(), context.id());
+        Self { profiler, context }
+    }
+}
+
+impl<'a> Drop for Running<'a> {
+    fn drop(&mut self) {
+        self.profiler
+            .stop(self.context.service_method(), self.context.id());
+    }
+}
+
+/// Handler future whose polls are profiled
+struct Profiled {
+    fut: HandlerResultFut,
+    profiler: Arc<dyn Profiler>,
+    context: Context,
+}
+
+impl Future for Profiled {
+    type Output = HandlerResult;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let _running = Running::start(this.profiler.as_ref(), &this.context);
+        this.fut.as_mut().poll(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+    use crate::metadata::Metadata;
+    use serde::de::IntoDeserializer;
+    use std::sync::Mutex;
+
+    fn start_call(services: &AsyncServiceMap, id: MessageId, method: &str) -> HandlerResultFut {
+        let context = Context::new(id, format!("Echo.{}", method), Metadata::new());
+        let deserializer: serde::de::value::UnitDeserializer<serde::de::value::Error> =
+            ().into_deserializer();
+        let deserializer = Box::new(<dyn erased::Deserializer>::erase(deserializer));
+        context.scope(|| services["Echo"](method.to_string(), deserializer))
+    }
+
+    #[test]
+    fn handlers_are_profiled() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let (start, stop) = (events.clone(), events.clone());
+        let profiler: Arc<dyn Profiler> = Arc::new((
+            move |service_method: &str, id: MessageId| {
+                let event = format!("start {} {}", service_method, id);
+                start.lock().unwrap().push(event)
+            },
+            move |service_method: &str, id: MessageId| {
+                let event = format!("stop {} {}", service_method, id);
+                stop.lock().unwrap().push(event)
+            },
+        ));
+        let call: ArcAsyncServiceCall = Arc::new(|method, _| -> HandlerResultFut {
+            Box::pin(async move {
+                match method.as_str() {
+                    "pending" => futures::future::pending().await,
+                    _ => Err(Error::MethodNotFound),
+                }
+            })
+        });
+        let mut services = AsyncServiceMap::new();
+        services.insert("Echo", call);
+        apply(&mut services, Some(profiler));
+
+        let waker = futures::task::noop_waker();
+        let mut cx = TaskContext::from_waker(&waker);
+        let mut fut = start_call(&services, 3, "echo");
+        assert!(matches!(
+            fut.as_mut().poll(&mut cx),
+            Poll::Ready(Err(Error::MethodNotFound))
+        ));
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![
+                "start Echo.echo 3",
+                "stop Echo.echo 3",
+                "start Echo.echo 3",
+                "stop Echo.echo 3"
+            ]
+        );
+
+        // a handler that is canceled stops with its last poll
+        events.lock().unwrap().clear();
+        let mut fut = start_call(&services, 4, "pending");
+        assert!(fut.as_mut().poll(&mut cx).is_pending());
+        assert!(fut.as_mut().poll(&mut cx).is_pending());
+        drop(fut);
+        let events = events.lock().unwrap();
+        assert_eq!(events.len(), 6);
+        assert_eq!(events[5], "stop Echo.pending 4");
+    }
+}
@@ -0,0 +1,223 @@
+//! HAProxy PROXY protocol
+//!
+//! When the server sits behind a load balancer, the peer address of an accepted
+//! TCP connection is the address of the load balancer. If the load balancer is
+//! configured to send a PROXY protocol header (version 1 or 2) at the beginning of
+//! each connection, the server can read the address of the real client from it.
+//! See `ServerBuilder::proxy_protocol`.
+
+use std::io::ErrorKind;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+#[cfg(all(feature = "async_std_runtime", not(feature = "tokio_runtime")))]
+use futures::io::{AsyncRead, AsyncReadExt};
+#[cfg(all(feature = "tokio_runtime", not(feature = "async_std_runtime")))]
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::error::Error;
+
+/// Signature of the PROXY protocol version 2 header
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+/// Maximum length of a PROXY protocol version 1 header including the CRLF
+const V1_MAX_LEN: usize = 107;
+
+/// Addresses carried in a PROXY protocol header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ProxyHeader {
+    /// Address of the real client. This is `None` if the load balancer does not know
+    /// the address (`UNKNOWN` in version 1 and `LOCAL` in version 2)
+    pub source: Option<SocketAddr>,
+    /// Address the real client connected to
+    pub destination: Option<SocketAddr>,
+}
+
+/// Reads a PROXY protocol header from the beginning of a stream. Nothing beyond
+/// the header is consumed.
+pub(crate) async fn read_proxy_header<S>(stream: &mut S) -> Result<ProxyHeader, Error>
+where
+    S: AsyncRead + Unpin,
+{
+    // the shortest version 1 header "PROXY UNKNOWN\r\n" is longer than this
+    let mut buf = vec![0u8; V2_SIGNATURE.len()];
+    stream.read_exact(&mut buf).await?;
+
+    if buf == V2_SIGNATURE {
+        let mut head = [0u8; 4];
+        stream.read_exact(&mut head).await?;
+        let len = u16::from_be_bytes([head[2], head[3]]) as usize;
+        let mut addrs = vec![0u8; len];
+        stream.read_exact(&mut addrs).await?;
+        parse_v2(head[0], head[1], &addrs)
+    } else if buf.starts_with(b"PROXY ") {
+        while !buf.ends_with(b"\r\n") {
+            if buf.len() >= V1_MAX_LEN {
+                return Err(invalid("PROXY protocol v1 header is too long"));
+            }
+            let mut byte = [0u8; 1];
+            stream.read_exact(&mut byte).await?;
+            buf.push(byte[0]);
+        }
+        parse_v1(&buf[..buf.len() - 2])
+    } else {
+        Err(invalid("Missing PROXY protocol header"))
+    }
+}
+
+/// Returns the address of the real client if `proxy_protocol` is enabled, otherwise
+/// returns `peer_addr`
+pub(crate) async fn resolve_peer_addr<S>(
+    stream: &mut S,
+    proxy_protocol: bool,
+    peer_addr: SocketAddr,
+) -> Result<SocketAddr, Error>
+where
+    S: AsyncRead + Unpin,
+{
+    if !proxy_protocol {
+        return Ok(peer_addr);
+    }
+
+    let header = read_proxy_header(stream).await?;
+    debug!(
+        "PROXY protocol header from {}: {:?} -> {:?}",
+        peer_addr, header.source, header.destination
+    );
+    let addr = header.source.unwrap_or(peer_addr);
+    info!(
+        "Accepting incoming connection from {} via proxy {}",
+        addr, peer_addr
+    );
+    Ok(addr)
+}
+
+fn invalid(msg: &str) -> Error {
+    std::io::Error::new(ErrorKind::InvalidData, msg).into()
+}
+
+/// Parses a version 1 header without the trailing CRLF
+fn parse_v1(line: &[u8]) -> Result<ProxyHeader, Error> {
+    let line =
+        std::str::from_utf8(line).map_err(|_| invalid("PROXY protocol v1 header is not ASCII"))?;
+    let mut fields = line.split(' ').skip(1);
+    match fields.next() {
+        Some("UNKNOWN") => Ok(ProxyHeader {
+            source: None,
+            destination: None,
+        }),
+        Some("TCP4") | Some("TCP6") => {
+            let fields: Vec<&str> = fields.collect();
+            if fields.len() != 4 {
+                return Err(invalid("Invalid PROXY protocol v1 header"));
+            }
+            let parse_addr = |ip: &str, port: &str| -> Result<SocketAddr, Error> {
+                let ip: IpAddr = ip
+                    .parse()
+                    .map_err(|_| invalid("Invalid address in PROXY protocol v1 header"))?;
+                let port: u16 = port
+                    .parse()
+                    .map_err(|_| invalid("Invalid port in PROXY protocol v1 header"))?;
+                Ok(SocketAddr::new(ip, port))
+            };
+            Ok(ProxyHeader {
+                source: Some(parse_addr(fields[0], fields[2])?),
+                destination: Some(parse_addr(fields[1], fields[3])?),
+            })
+        }
+        _ => Err(invalid("Unsupported protocol in PROXY protocol v1 header")),
+    }
+}
+
+/// Parses the address block of a version 2 header
+fn parse_v2(ver_cmd: u8, family: u8, addrs: &[u8]) -> Result<ProxyHeader, Error> {
+    if ver_cmd >> 4 != 2 {
+        return Err(invalid("Unsupported PROXY protocol version"));
+    }
+    let unknown = ProxyHeader {
+        source: None,
+        destination: None,
+    };
+    match ver_cmd & 0x0f {
+        // LOCAL, eg. health checks from the load balancer itself
+        0x0 => return Ok(unknown),
+        0x1 => {}
+        _ => return Err(invalid("Unsupported PROXY protocol v2 command")),
+    }
+
+    let port = |b: &[u8]| u16::from_be_bytes([b[0], b[1]]);
+    match family {
+        // TCP over IPv4
+        0x11 if addrs.len() >= 12 => {
+            let src = Ipv4Addr::new(addrs[0], addrs[1], addrs[2], addrs[3]);
+            let dst = Ipv4Addr::new(addrs[4], addrs[5], addrs[6], addrs[7]);
+            Ok(ProxyHeader {
+                source: Some(SocketAddr::new(src.into(), port(&addrs[8..10]))),
+                destination: Some(SocketAddr::new(dst.into(), port(&addrs[10..12]))),
+            })
+        }
+        // TCP over IPv6
+        0x21 if addrs.len() >= 36 => {
+            let mut src = [0u8; 16];
+            src.copy_from_slice(&addrs[0..16]);
+            let mut dst = [0u8; 16];
+            dst.copy_from_slice(&addrs[16..32]);
+            Ok(ProxyHeader {
+                source: Some(SocketAddr::new(
+                    Ipv6Addr::from(src).into(),
+                    port(&addrs[32..34]),
+                )),
+                destination: Some(SocketAddr::new(
+                    Ipv6Addr::from(dst).into(),
+                    port(&addrs[34..36]),
+                )),
+            })
+        }
+        0x11 | 0x21 => Err(invalid("Truncated PROXY protocol v2 header")),
+        // UNSPEC, UDP and unix sockets carry no usable TCP address
+        _ => Ok(unknown),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn v1_tcp4() {
+        let header = parse_v1(b"PROXY TCP4 192.168.0.1 192.168.0.11 56324 443").unwrap();
+        assert_eq!(header.source, Some("192.168.0.1:56324".parse().unwrap()));
+        assert_eq!(
+            header.destination,
+            Some("192.168.0.11:443".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn v1_tcp6_and_unknown() {
+        let header = parse_v1(b"PROXY TCP6 ::1 ::2 4000 23333").unwrap();
+        assert_eq!(header.source, Some("[::1]:4000".parse().unwrap()));
+
+        let header = parse_v1(b"PROXY UNKNOWN").unwrap();
+        assert_eq!(header.source, None);
+    }
+
+    #[test]
+    fn v1_invalid() {
+        assert!(parse_v1(b"PROXY TCP4 192.168.0.1 56324 443").is_err());
+        assert!(parse_v1(b"PROXY UDP4 1.1.1.1 2.2.2.2 1 2").is_err());
+    }
+
+    #[test]
+    fn v2_tcp4() {
+        let addrs = [10, 0, 0, 1, 10, 0, 0, 2, 0x1f, 0x90, 0x01, 0xbb];
+        let header = parse_v2(0x21, 0x11, &addrs).unwrap();
+        assert_eq!(header.source, Some("10.0.0.1:8080".parse().unwrap()));
+        assert_eq!(header.destination, Some("10.0.0.2:443".parse().unwrap()));
+    }
+
+    #[test]
+    fn v2_local_and_invalid() {
+        assert_eq!(parse_v2(0x20, 0x00, &[]).unwrap().source, None);
+        assert!(parse_v2(0x11, 0x11, &[]).is_err());
+        assert!(parse_v2(0x21, 0x11, &[10, 0, 0, 1]).is_err());
+    }
+}
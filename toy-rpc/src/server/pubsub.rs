@@ -1,31 +1,40 @@
 //! PubSub impl on the server side
 
 use flume::r#async::{RecvStream, SendSink};
-use flume::{Receiver, Sender};
-use futures::{Sink, Stream};
+use flume::{Receiver, Sender, TrySendError};
+use futures::channel::oneshot;
+use futures::{Sink, SinkExt, Stream};
 use pin_project::pin_project;
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
 use std::marker::PhantomData;
 use std::pin::Pin;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use std::task::{Context, Poll};
+use std::time::{Duration, Instant, SystemTime};
 
 #[cfg(feature = "http_actix_web")]
 use actix::Recipient;
 
+use crate::clock;
 use crate::codec::{Marshal, Reserved, Unmarshal};
 use crate::error::Error;
 use crate::message::{AtomicMessageId, MessageId};
-use crate::pubsub::Topic;
+use crate::pubsub::{
+    self, check_type_tag, type_tag, DeadLetter, DeliveryFailure, PublishReport, Topic,
+};
 
-#[cfg(not(feature = "http_actix_web"))]
-use super::RESERVED_CLIENT_ID;
+use super::source::TopicSource;
+use super::store::{BrokerStore, StoredTopic};
+use super::topics::TopicRegistry;
 use super::{broker::ServerBrokerItem, ClientId, Server};
 
 pub(crate) enum PubSubResponder {
-    #[cfg(not(feature = "http_actix_web"))]
+    // the connections handed over by actix-web subscribe through a `Recipient`
+    #[cfg_attr(feature = "http_actix_web", allow(dead_code))]
     Sender(Sender<ServerBrokerItem>),
+    // a subscriber on the server side, whose oldest item can be dropped by the broker
+    Local(Sender<ServerBrokerItem>, Receiver<ServerBrokerItem>),
     #[cfg(feature = "http_actix_web")]
     Recipient(Recipient<ServerBrokerItem>),
 }
@@ -35,29 +44,677 @@ pub(crate) enum PubSubItem {
         msg_id: MessageId,
         topic: String,
         content: Arc<Vec<u8>>,
+        tag: Option<u64>,
+        // remote publisher, which is notified if the publication is rejected
+        publisher: Option<PubSubResponder>,
+        // whether the publisher is notified once the publication is accepted, and the
+        // subscribers must acknowledge the publication
+        ack: bool,
+    },
+    Ack {
+        client_id: ClientId,
+        // id of the delivery of the publication to the subscriber
+        msg_id: MessageId,
+    },
+    PublishAt {
+        deliver_at: Instant,
+        expires_at: Option<Instant>,
+        msg_id: MessageId,
+        topic: String,
+        content: Arc<Vec<u8>>,
+        tag: Option<u64>,
     },
     Subscribe {
         client_id: ClientId,
+        // id of the subscribe message, which is sent back if the subscription is rejected
+        msg_id: MessageId,
         topic: String,
         sender: PubSubResponder,
+        // number of past messages that the subscriber asks to replay
+        replay: Option<usize>,
     },
     Unsubscribe {
         client_id: ClientId,
         topic: String,
     },
+    CreateTopic {
+        topic: String,
+        done: oneshot::Sender<bool>,
+    },
+    DeleteTopic {
+        topic: String,
+        done: oneshot::Sender<bool>,
+    },
+    SetRetention {
+        topic: String,
+        retention: Retention,
+        done: oneshot::Sender<bool>,
+    },
+    SetDeadLetter {
+        topic: String,
+        dead_letter: Option<String>,
+        done: oneshot::Sender<bool>,
+    },
+    SetTtl {
+        topic: String,
+        ttl: Option<Duration>,
+        done: oneshot::Sender<bool>,
+    },
+    ListTopics {
+        done: oneshot::Sender<Vec<TopicInfo>>,
+    },
+    Stats {
+        done: oneshot::Sender<PubSubStats>,
+    },
     Stop,
 }
 
+/// A retained message with its type tag and the time at which it expires
+type RetainedMessage = (MessageId, Arc<Vec<u8>>, Option<u64>, Option<Instant>);
+
+/// Bookkeeping of a single topic in the `PubSubBroker`
+#[derive(Default)]
+struct TopicEntry {
+    subscribers: BTreeMap<ClientId, PubSubResponder>,
+    retention: Retention,
+    // the type tags of retained messages are not persisted by the store
+    retained: VecDeque<RetainedMessage>,
+    dead_letter: Option<String>,
+    dead_lettered: u64,
+    ttl: Option<Duration>,
+    // topics created with `TopicAdmin` or loaded from the store are never collected
+    pinned: bool,
+    last_active: Option<Instant>,
+}
+
+impl TopicEntry {
+    fn touch(&mut self) {
+        self.last_active = Some(clock::now());
+    }
+
+    /// Returns whether the topic can be collected after being idle for `idle_timeout`
+    fn is_idle(&self, idle_timeout: Duration) -> bool {
+        !self.pinned
+            && self.subscribers.is_empty()
+            && self.retained.is_empty()
+            && self.last_active.is_none_or(|t| t.elapsed() >= idle_timeout)
+    }
+
+    fn info(&self, name: &str) -> TopicInfo {
+        TopicInfo {
+            name: name.to_string(),
+            subscribers: self.subscribers.len(),
+            retention: self.retention,
+            retained: self.retained.len(),
+            dead_letter: self.dead_letter.clone(),
+            dead_lettered: self.dead_lettered,
+            ttl: self.ttl,
+        }
+    }
+
+    /// Time at which a message published now expires, taking both the
+    /// message's own expiration and the TTL of the topic into account
+    fn expires_at(&self, expires_at: Option<Instant>) -> Option<Instant> {
+        let topic_expires_at = self.ttl.map(|ttl| clock::now() + ttl);
+        match (expires_at, topic_expires_at) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    fn retain(
+        &mut self,
+        topic: &str,
+        msg_id: MessageId,
+        content: Arc<Vec<u8>>,
+        tag: Option<u64>,
+        expires_at: Option<Instant>,
+        store: &mut dyn BrokerStore,
+    ) -> Result<(), Error> {
+        if let Retention::LastN(n) = self.retention {
+            let stored_expires_at = expires_at.map(to_system_time);
+            store.push_retained(topic, msg_id, &content, stored_expires_at)?;
+            self.retained.push_back((msg_id, content, tag, expires_at));
+            self.truncate_retained(topic, n, store)?;
+        }
+        Ok(())
+    }
+
+    /// Sets the retention policy and drops the retained messages over the new limit
+    fn set_retention(
+        &mut self,
+        topic: &str,
+        retention: Retention,
+        store: &mut dyn BrokerStore,
+    ) -> Result<(), Error> {
+        self.retention = retention;
+        let n = match retention {
+            Retention::None => 0,
+            Retention::LastN(n) => n,
+        };
+        store.put_topic(topic, retention)?;
+        self.truncate_retained(topic, n, store)
+    }
+
+    fn truncate_retained(
+        &mut self,
+        topic: &str,
+        n: usize,
+        store: &mut dyn BrokerStore,
+    ) -> Result<(), Error> {
+        while self.retained.len() > n {
+            store.pop_retained(topic)?;
+            self.retained.pop_front();
+        }
+        Ok(())
+    }
+
+    /// Removes the expired messages at the front of the retained messages
+    fn purge_expired(&mut self, topic: &str, store: &mut dyn BrokerStore) -> Result<(), Error> {
+        let now = clock::now();
+        while let Some((_, _, _, Some(expires_at))) = self.retained.front() {
+            if *expires_at > now {
+                break;
+            }
+            store.pop_retained(topic)?;
+            self.retained.pop_front();
+        }
+        Ok(())
+    }
+}
+
+/// Returns `true` if a message has expired
+fn is_expired(expires_at: Option<Instant>) -> bool {
+    expires_at.is_some_and(|t| t <= clock::now())
+}
+
+/// Converts the time at which a message expires to the wall clock, which is what
+/// the `BrokerStore` keeps across restarts
+fn to_system_time(expires_at: Instant) -> SystemTime {
+    clock::system_now() + expires_at.saturating_duration_since(clock::now())
+}
+
+/// Converts the time at which a message expires, as kept by the `BrokerStore`, back
+/// to the monotonic clock
+fn from_system_time(expires_at: SystemTime) -> Instant {
+    let remaining = expires_at
+        .duration_since(clock::system_now())
+        .unwrap_or_default();
+    clock::now() + remaining
+}
+
+/// Default time after which a publication that is not acknowledged by a subscriber
+/// is delivered again
+pub(crate) const DEFAULT_REDELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A publication delivered to a subscriber that has not acknowledged it yet
+struct Unacked {
+    topic: String,
+    content: Arc<Vec<u8>>,
+    tag: Option<u64>,
+    expires_at: Option<Instant>,
+    redeliver_at: Instant,
+    // number of times the publication has been delivered to the subscriber
+    attempts: u32,
+}
+
+/// Publications that must be acknowledged by the subscribers. Every delivery of
+/// such a publication has its own id, which the subscriber sends back in the `Ack`.
+struct Acks {
+    unacked: HashMap<(ClientId, MessageId), Unacked>,
+    // ordered by the redelivery time. An entry is stale if the publication has been
+    // acknowledged or rescheduled since.
+    schedule: BTreeSet<(Instant, ClientId, MessageId)>,
+    next_id: MessageId,
+    timeout: Duration,
+    max_attempts: Option<u32>,
+    redelivered: u64,
+}
+
+impl Acks {
+    fn new(timeout: Duration, max_attempts: Option<u32>) -> Self {
+        Self {
+            unacked: HashMap::new(),
+            schedule: BTreeSet::new(),
+            next_id: 0,
+            timeout,
+            max_attempts,
+            redelivered: 0,
+        }
+    }
+
+    /// Tracks the delivery of a publication to a subscriber and returns its id. The
+    /// ids of the deliveries to the subscriber that are not acknowledged yet are
+    /// skipped, unless all of them are in use.
+    fn track(
+        &mut self,
+        client_id: ClientId,
+        topic: &str,
+        content: Arc<Vec<u8>>,
+        tag: Option<u64>,
+        expires_at: Option<Instant>,
+    ) -> MessageId {
+        let start = self.next_id;
+        let id = (0..=MessageId::MAX)
+            .map(|offset| start.wrapping_add(offset))
+            .find(|id| !self.unacked.contains_key(&(client_id, *id)))
+            .unwrap_or(start);
+        self.next_id = id.wrapping_add(1);
+        let redeliver_at = clock::now() + self.timeout;
+        let unacked = Unacked {
+            topic: topic.to_string(),
+            content,
+            tag,
+            expires_at,
+            redeliver_at,
+            attempts: 1,
+        };
+        self.unacked.insert((client_id, id), unacked);
+        self.schedule.insert((redeliver_at, client_id, id));
+        id
+    }
+
+    fn ack(&mut self, client_id: ClientId, id: MessageId) {
+        if self.unacked.remove(&(client_id, id)).is_none() {
+            debug!(
+                "Client {} acknowledged an unknown publication {}",
+                client_id, id
+            );
+        }
+    }
+
+    fn next_due(&self) -> Option<Instant> {
+        self.schedule.iter().next().map(|(at, _, _)| *at)
+    }
+
+    /// Removes the deliveries that are due for redelivery from the schedule
+    fn take_due(&mut self, now: Instant) -> Vec<(ClientId, MessageId)> {
+        let mut due = Vec::new();
+        while let Some(first) = self.schedule.iter().next().cloned() {
+            let (at, client_id, id) = first;
+            if at > now {
+                break;
+            }
+            self.schedule.remove(&first);
+            let current = self
+                .unacked
+                .get(&(client_id, id))
+                .is_some_and(|unacked| unacked.redeliver_at == at);
+            if current {
+                due.push((client_id, id));
+            }
+        }
+        due
+    }
+
+    fn reschedule(&mut self, key: (ClientId, MessageId), now: Instant) {
+        if let Some(unacked) = self.unacked.get_mut(&key) {
+            unacked.redeliver_at = now + self.timeout;
+            unacked.attempts += 1;
+            self.schedule.insert((unacked.redeliver_at, key.0, key.1));
+            self.redelivered += 1;
+        }
+    }
+
+    /// Whether a publication has been delivered as many times as allowed
+    fn is_exhausted(&self, unacked: &Unacked) -> bool {
+        self.max_attempts
+            .is_some_and(|max_attempts| unacked.attempts >= max_attempts)
+    }
+
+    fn remove_topic(&mut self, topic: &str) {
+        self.unacked.retain(|_, unacked| unacked.topic != topic);
+    }
+}
+
+impl From<StoredTopic> for TopicEntry {
+    fn from(stored: StoredTopic) -> Self {
+        Self {
+            subscribers: BTreeMap::new(),
+            retention: stored.retention,
+            retained: stored
+                .retained
+                .into_iter()
+                .map(|(id, content, expires_at)| {
+                    let expires_at = expires_at.map(from_system_time);
+                    (id, Arc::new(content), None, expires_at)
+                })
+                .collect(),
+            dead_letter: stored.dead_letter,
+            dead_lettered: 0,
+            ttl: stored.ttl,
+            pinned: true,
+            last_active: None,
+        }
+    }
+}
+
+impl PubSubResponder {
+    fn is_connected(&self) -> bool {
+        match self {
+            PubSubResponder::Sender(tx) => !tx.is_disconnected(),
+            // the channel is kept open by the receiver held by the broker, and the
+            // subscriber unsubscribes when it is dropped
+            PubSubResponder::Local(..) => true,
+            #[cfg(feature = "http_actix_web")]
+            PubSubResponder::Recipient(tx) => tx.connected(),
+        }
+    }
+}
+
+/// Sends a publication to a subscriber without waiting. The publication is given
+/// back, boxed, if it is not sent.
+fn try_send_publication(
+    sender: &PubSubResponder,
+    msg: ServerBrokerItem,
+) -> Result<(), Box<TrySendError<ServerBrokerItem>>> {
+    let result = match sender {
+        PubSubResponder::Sender(tx) | PubSubResponder::Local(tx, _) => tx.try_send(msg),
+        #[cfg(feature = "http_actix_web")]
+        PubSubResponder::Recipient(tx) => tx.try_send(msg).map_err(|err| match err {
+            actix::prelude::SendError::Full(msg) => TrySendError::Full(msg),
+            actix::prelude::SendError::Closed(msg) => TrySendError::Disconnected(msg),
+        }),
+    };
+    result.map_err(Box::new)
+}
+
+/// Sends a publication to a subscriber
+fn send_publication(
+    sender: &PubSubResponder,
+    msg: ServerBrokerItem,
+) -> Result<(), DeliveryFailure> {
+    try_send_publication(sender, msg).map_err(|err| match *err {
+        TrySendError::Full(_) => DeliveryFailure::Full,
+        TrySendError::Disconnected(_) => DeliveryFailure::Disconnected,
+    })
+}
+
+/// A publication being delivered to the subscribers of a topic
+struct Outgoing<'a> {
+    msg_id: MessageId,
+    topic: &'a str,
+    content: Arc<Vec<u8>>,
+    tag: Option<u64>,
+    expires_at: Option<Instant>,
+    ack: bool,
+    backpressure: Backpressure,
+}
+
+/// Publications waiting for room in the buffers of the subscribers
+type Blocked = Vec<(Sender<ServerBrokerItem>, ServerBrokerItem)>;
+
+/// Outcome of delivering a publication to the subscribers of a topic
+#[derive(Default)]
+struct DeliveryReport {
+    /// The subscribers that the delivery failed to
+    failed: Vec<(ClientId, DeliveryFailure)>,
+    blocked: Blocked,
+    /// What the publisher is told about the delivery
+    publish: PublishReport,
+}
+
+/// Waits until the subscribers have room for the blocked publications
+async fn send_blocked(blocked: Blocked) {
+    for (tx, msg) in blocked {
+        // the subscriber may be dropped while waiting
+        let _ = tx.send_async(msg).await;
+    }
+}
+
+impl Outgoing<'_> {
+    /// Sends the publication to a subscriber, handling a full buffer according to
+    /// the backpressure policy of the topic. Returns whether the subscriber is kept.
+    fn send_to(
+        &self,
+        acks: &mut Acks,
+        client_id: ClientId,
+        sender: &PubSubResponder,
+        report: &mut DeliveryReport,
+    ) -> bool {
+        let id = match self.ack {
+            true => acks.track(
+                client_id,
+                self.topic,
+                self.content.clone(),
+                self.tag,
+                self.expires_at,
+            ),
+            false => self.msg_id,
+        };
+        let msg = ServerBrokerItem::Publication {
+            id,
+            topic: self.topic.to_string(),
+            content: self.content.clone(),
+            tag: self.tag,
+            ack: self.ack,
+        };
+        let err = match try_send_publication(sender, msg) {
+            Ok(_) => {
+                report.publish.subscribers += 1;
+                return true;
+            }
+            Err(err) => err,
+        };
+        let msg = match *err {
+            TrySendError::Full(msg) => msg,
+            TrySendError::Disconnected(_) => {
+                info!("Client is disconnected, removing from subscriptions");
+                report
+                    .failed
+                    .push((client_id, DeliveryFailure::Disconnected));
+                return false;
+            }
+        };
+        match (self.backpressure, sender) {
+            (Backpressure::Block, PubSubResponder::Sender(tx))
+            | (Backpressure::Block, PubSubResponder::Local(tx, _)) => {
+                report.blocked.push((tx.clone(), msg));
+                report.publish.subscribers += 1;
+                true
+            }
+            // the mailbox of an actor takes the message regardless of its capacity
+            #[cfg(feature = "http_actix_web")]
+            (Backpressure::Block, PubSubResponder::Recipient(tx)) => {
+                let kept = tx.do_send(msg).is_ok();
+                report.publish.subscribers += kept as u32;
+                kept
+            }
+            (Backpressure::DropOldest, PubSubResponder::Local(tx, rx)) => {
+                debug!(
+                    "Subscriber {} of topic {} is full, dropping the oldest message",
+                    client_id, self.topic
+                );
+                let _ = rx.try_recv();
+                let _ = tx.try_send(msg);
+                report.publish.subscribers += 1;
+                report.publish.dropped += 1;
+                true
+            }
+            (Backpressure::Disconnect, _) => {
+                debug!(
+                    "Subscriber {} of topic {} is full, removing from subscriptions",
+                    client_id, self.topic
+                );
+                report.failed.push((client_id, DeliveryFailure::Full));
+                report.publish.dropped += 1;
+                false
+            }
+            // the subscriber receives the message again when it is redelivered
+            _ if self.ack => {
+                report.publish.subscribers += 1;
+                true
+            }
+            _ => {
+                debug!(
+                    "Subscriber {} of topic {} is full, dropping message {}",
+                    client_id, self.topic, self.msg_id
+                );
+                report.failed.push((client_id, DeliveryFailure::Full));
+                report.publish.dropped += 1;
+                true
+            }
+        }
+    }
+}
+
+/// Sends the retained messages of a topic that have not expired to a new subscriber,
+/// or only the last `replay` of them. Returns whether the subscriber is still connected.
+fn send_retained(
+    topic: &str,
+    entry: &TopicEntry,
+    replay: Option<usize>,
+    sender: &PubSubResponder,
+) -> bool {
+    let live = entry
+        .retained
+        .iter()
+        .filter(|(_, _, _, expires_at)| !is_expired(*expires_at));
+    let skip = replay.map_or(0, |n| live.clone().count().saturating_sub(n));
+    live.skip(skip).all(|(id, content, tag, _)| {
+        let msg = ServerBrokerItem::Publication {
+            id: *id,
+            topic: topic.to_string(),
+            content: content.clone(),
+            tag: *tag,
+            ack: false,
+        };
+        send_publication(sender, msg) != Err(DeliveryFailure::Disconnected)
+    })
+}
+
+/// Sends the history of a topic read from its `TopicSource` to a new subscriber.
+/// Returns whether the subscriber is still connected.
+fn send_history(topic: &str, history: Vec<Vec<u8>>, sender: &PubSubResponder) -> bool {
+    history.into_iter().enumerate().all(|(i, content)| {
+        let msg = ServerBrokerItem::Publication {
+            // the messages of the source have no id of their own
+            id: i as MessageId,
+            topic: topic.to_string(),
+            content: Arc::new(content),
+            tag: None,
+            ack: false,
+        };
+        send_publication(sender, msg) != Err(DeliveryFailure::Disconnected)
+    })
+}
+
+/// Envelopes of the failed deliveries of a publication, which are published to the
+/// dead letter topic of its topic
+struct DeadLetters {
+    topic: String,
+    letters: Vec<DeadLetter>,
+}
+
+/// Subscribers of the topic patterns, keyed by the pattern
+type PatternSubscribers = HashMap<String, BTreeMap<ClientId, PubSubResponder>>;
+
+/// Finds the subscriber that receives the publications on `topic` for a client,
+/// either on the topic itself or on a pattern that matches it
+fn find_subscriber<'a>(
+    topics: &'a HashMap<String, TopicEntry>,
+    patterns: &'a PatternSubscribers,
+    topic: &str,
+    client_id: ClientId,
+) -> Option<&'a PubSubResponder> {
+    topics
+        .get(topic)
+        .and_then(|entry| entry.subscribers.get(&client_id))
+        .or_else(|| {
+            patterns
+                .iter()
+                .filter(|(pattern, _)| pubsub::matches(pattern, topic))
+                .find_map(|(_, subscribers)| subscribers.get(&client_id))
+        })
+}
+
+/// A publication held by the `PubSubBroker` until it is due
+type DelayedPublication = (
+    MessageId,
+    String,
+    Arc<Vec<u8>>,
+    Option<u64>,
+    Option<Instant>,
+);
+
+/// Receives the next item from the listener. Returns `None` if no item is
+/// received within `duration`
+async fn recv_timeout(
+    listener: &Receiver<PubSubItem>,
+    duration: Duration,
+) -> Option<Result<PubSubItem, flume::RecvError>> {
+    crate::clock::timeout(duration, listener.recv_async())
+        .await
+        .ok()
+}
+
 pub(crate) struct PubSubBroker {
     listener: Receiver<PubSubItem>,
-    subscriptions: HashMap<String, BTreeMap<ClientId, PubSubResponder>>,
+    topics: HashMap<String, TopicEntry>,
+    patterns: PatternSubscribers,
+    registry: Arc<TopicRegistry>,
+    backpressure: HashMap<String, Backpressure>,
+    store: Box<dyn BrokerStore>,
+    sources: HashMap<String, Arc<dyn TopicSource>>,
+    // ordered by the delivery time, ties are broken by the order of arrival
+    delayed: BTreeMap<(Instant, u64), DelayedPublication>,
+    delayed_seq: u64,
+    idle_timeout: Option<Duration>,
+    next_collection: Option<Instant>,
+    collected_topics: u64,
+    pruned_subscribers: u64,
+    acks: Acks,
 }
 
+/// Shortest interval between two collections of idle topics
+const MIN_COLLECTION_INTERVAL: Duration = Duration::from_millis(10);
+
 impl PubSubBroker {
-    pub fn new(listener: Receiver<PubSubItem>) -> Self {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        listener: Receiver<PubSubItem>,
+        mut store: Box<dyn BrokerStore>,
+        registry: Arc<TopicRegistry>,
+        retention: HashMap<String, Retention>,
+        backpressure: HashMap<String, Backpressure>,
+        sources: HashMap<String, Arc<dyn TopicSource>>,
+        idle_timeout: Option<Duration>,
+        redelivery_timeout: Duration,
+        max_delivery_attempts: Option<u32>,
+    ) -> Self {
+        let mut topics: HashMap<String, TopicEntry> = match store.load() {
+            Ok(topics) => topics
+                .into_iter()
+                .map(|(name, stored)| (name, TopicEntry::from(stored)))
+                .collect(),
+            Err(err) => {
+                error!("Failed to load topics from the broker store: {}", err);
+                HashMap::new()
+            }
+        };
+        // the retention set on the builder overrides the one loaded from the store
+        for (topic, retention) in retention {
+            let entry = topics.entry(topic.clone()).or_default();
+            entry.pinned = true;
+            if let Err(err) = entry.set_retention(&topic, retention, &mut *store) {
+                error!("{}", err);
+            }
+        }
         Self {
             listener,
-            subscriptions: HashMap::new(),
+            topics,
+            patterns: HashMap::new(),
+            registry,
+            backpressure,
+            store,
+            sources,
+            delayed: BTreeMap::new(),
+            delayed_seq: 0,
+            idle_timeout,
+            next_collection: idle_timeout.map(|_| clock::now()),
+            collected_topics: 0,
+            pruned_subscribers: 0,
+            acks: Acks::new(redelivery_timeout, max_delivery_attempts),
         }
     }
 
@@ -80,70 +737,548 @@ impl PubSubBroker {
     }
 
     pub async fn pubsub_loop(mut self) {
-        while let Ok(item) = self.listener.recv_async().await {
-            match item {
-                PubSubItem::Publish {
-                    msg_id,
-                    topic,
-                    content,
-                } => {
-                    if let Some(entry) = self.subscriptions.get_mut(&topic) {
-                        entry.retain(|_, sender| {
-                            let msg = ServerBrokerItem::Publication{
-                                id: msg_id,
-                                topic: topic.clone(),
-                                content: content.clone()
-                            };
+        loop {
+            self.publish_due().await;
+            self.redeliver_due().await;
+            self.collect_idle();
+            let item = match self.next_due() {
+                Some(due) => match recv_timeout(&self.listener, due).await {
+                    Some(Ok(item)) => item,
+                    Some(Err(_)) => return,
+                    None => continue,
+                },
+                None => match self.listener.recv_async().await {
+                    Ok(item) => item,
+                    Err(_) => return,
+                },
+            };
+            if !self.handle_item(item).await {
+                return;
+            }
+        }
+    }
 
-                            match sender {
-                                #[cfg(not(feature = "http_actix_web"))]
-                                PubSubResponder::Sender(tx) => {
-                                    if let Err(err) = tx.try_send(msg) {
-                                        if let flume::TrySendError::Disconnected(_) = err {
-                                            log::error!("Client is disconnected, removing from subscriptions");
-                                            return false
-                                        }
-                                    }
-                                },
-                                #[cfg(feature = "http_actix_web")]
-                                PubSubResponder::Recipient(tx) => {
-                                    if let Err(err) = tx.try_send(msg) {
-                                        if let actix::prelude::SendError::Closed(_) = err {
-                                            log::error!("Client is disconnected, removing from subscriptions");
-                                            return false
-                                        }
-                                    }
-                                }
-                            }
-                            true
-                        })
+    /// Duration until the next delayed publication, the next redelivery or the next
+    /// collection of idle topics is due
+    fn next_due(&self) -> Option<Duration> {
+        let next_delayed = self
+            .delayed
+            .keys()
+            .next()
+            .map(|(deliver_at, _)| *deliver_at);
+        let next = [next_delayed, self.acks.next_due(), self.next_collection]
+            .iter()
+            .flatten()
+            .min()
+            .copied();
+        next.map(|t| t.saturating_duration_since(clock::now()))
+    }
+
+    /// Delivers again the publications that the subscribers have not acknowledged
+    /// in time. The publications to the subscribers that are gone and the expired
+    /// publications are dropped, and the publications that have been delivered
+    /// `max_delivery_attempts` times are published to the dead letter topic.
+    async fn redeliver_due(&mut self) {
+        let now = clock::now();
+        let mut exhausted = Vec::new();
+        for key in self.acks.take_due(now) {
+            let unacked = match self.acks.unacked.get(&key) {
+                Some(unacked) => unacked,
+                None => continue,
+            };
+            if self.acks.is_exhausted(unacked) {
+                if let Some(unacked) = self.acks.unacked.remove(&key) {
+                    exhausted.push((key, unacked));
+                }
+                continue;
+            }
+            let sender = find_subscriber(&self.topics, &self.patterns, &unacked.topic, key.0);
+            let redelivered = match sender {
+                Some(sender) if !is_expired(unacked.expires_at) => {
+                    debug!("Redelivering publication {} to client {}", key.1, key.0);
+                    let msg = ServerBrokerItem::Publication {
+                        id: key.1,
+                        topic: unacked.topic.clone(),
+                        content: unacked.content.clone(),
+                        tag: unacked.tag,
+                        ack: true,
+                    };
+                    send_publication(sender, msg) != Err(DeliveryFailure::Disconnected)
+                }
+                _ => false,
+            };
+            match redelivered {
+                true => self.acks.reschedule(key, now),
+                false => {
+                    self.acks.unacked.remove(&key);
+                }
+            }
+        }
+        for ((client_id, id), unacked) in exhausted {
+            debug!(
+                "Publication {} to client {} is not acknowledged after {} attempts",
+                id, client_id, unacked.attempts
+            );
+            let entry = match self.topics.get_mut(&unacked.topic) {
+                Some(entry) => entry,
+                None => continue,
+            };
+            entry.dead_lettered += 1;
+            let dead_letters = match &entry.dead_letter {
+                Some(dead_letter) => DeadLetters {
+                    topic: dead_letter.clone(),
+                    letters: vec![DeadLetter {
+                        topic: unacked.topic.clone(),
+                        client_id,
+                        reason: DeliveryFailure::Unacknowledged,
+                        failed_at: clock::system_now(),
+                        content: unacked.content.to_vec(),
+                    }],
+                },
+                None => continue,
+            };
+            let expires_at = unacked.expires_at;
+            self.publish_dead_letters(id, &unacked.topic, dead_letters, expires_at, true)
+                .await;
+        }
+    }
+
+    /// Removes the subscribers that are disconnected and the topics that have been
+    /// idle for `idle_timeout`, if the collection is due
+    fn collect_idle(&mut self) {
+        let (idle_timeout, next_collection) = match (self.idle_timeout, self.next_collection) {
+            (Some(idle_timeout), Some(next_collection)) => (idle_timeout, next_collection),
+            _ => return,
+        };
+        let now = clock::now();
+        if next_collection > now {
+            return;
+        }
+        self.next_collection = Some(now + (idle_timeout / 2).max(MIN_COLLECTION_INTERVAL));
+
+        let mut pruned = 0;
+        for entry in self.topics.values_mut() {
+            let before = entry.subscribers.len();
+            entry.subscribers.retain(|_, sender| sender.is_connected());
+            pruned += before - entry.subscribers.len();
+        }
+        for subscribers in self.patterns.values_mut() {
+            let before = subscribers.len();
+            subscribers.retain(|_, sender| sender.is_connected());
+            pruned += before - subscribers.len();
+        }
+        self.patterns
+            .retain(|_, subscribers| !subscribers.is_empty());
+        let before = self.topics.len();
+        self.topics.retain(|topic, entry| {
+            let idle = entry.is_idle(idle_timeout);
+            if idle {
+                debug!("Removing idle topic {}", topic);
+            }
+            !idle
+        });
+        self.pruned_subscribers += pruned as u64;
+        self.collected_topics += (before - self.topics.len()) as u64;
+    }
+
+    fn stats(&self) -> PubSubStats {
+        PubSubStats {
+            topics: self.topics.len(),
+            subscribers: self.topics.values().map(|e| e.subscribers.len()).sum(),
+            pattern_subscribers: self.patterns.values().map(|s| s.len()).sum(),
+            collected_topics: self.collected_topics,
+            pruned_subscribers: self.pruned_subscribers,
+            unacked: self.acks.unacked.len(),
+            redelivered: self.acks.redelivered,
+        }
+    }
+
+    /// Publishes all delayed publications that are due
+    async fn publish_due(&mut self) {
+        let now = clock::now();
+        while let Some(key) = self.delayed.keys().next().cloned() {
+            if key.0 > now {
+                break;
+            }
+            if let Some((msg_id, topic, content, tag, expires_at)) = self.delayed.remove(&key) {
+                self.publish(msg_id, topic, content, tag, expires_at, false)
+                    .await;
+            }
+        }
+    }
+
+    /// Publishes a message to a topic. Every failed delivery of the message to one
+    /// of the subscribers is published to the dead letter topic as a `DeadLetter`.
+    ///
+    /// Returns once the subscribers of a topic with the `Block` policy have room for
+    /// the message, with the report of the delivery to the topic for the publisher.
+    async fn publish(
+        &mut self,
+        msg_id: MessageId,
+        topic: String,
+        content: Arc<Vec<u8>>,
+        tag: Option<u64>,
+        expires_at: Option<Instant>,
+        ack: bool,
+    ) -> PublishReport {
+        let (dead_letters, blocked, report) =
+            self.deliver(msg_id, &topic, content, tag, expires_at, ack);
+        send_blocked(blocked).await;
+        if let Some(dead_letters) = dead_letters {
+            self.publish_dead_letters(msg_id, &topic, dead_letters, expires_at, ack)
+                .await;
+        }
+        report
+    }
+
+    /// Publishes the dead letters of a message of `topic` to its dead letter topic
+    async fn publish_dead_letters(
+        &mut self,
+        msg_id: MessageId,
+        topic: &str,
+        dead_letters: DeadLetters,
+        expires_at: Option<Instant>,
+        ack: bool,
+    ) {
+        if dead_letters.topic == topic {
+            return;
+        }
+        for letter in dead_letters.letters {
+            let content = match marshal_dead_letter(&letter) {
+                Ok(content) => Arc::new(content),
+                Err(err) => {
+                    error!("Failed to marshal a dead letter: {}", err);
+                    continue;
+                }
+            };
+            // failed deliveries of dead letters are not routed any further
+            let (_, blocked, _) =
+                self.deliver(msg_id, &dead_letters.topic, content, None, expires_at, ack);
+            send_blocked(blocked).await;
+        }
+    }
+
+    /// Delivers a message to the subscribers of a topic and of the patterns that
+    /// match it. Returns the dead letters of the failed deliveries if the topic has a
+    /// dead letter topic, the messages to the subscribers that must be waited for,
+    /// and the report of the delivery for the publisher.
+    ///
+    /// A client receives the message only once even if it subscribes to both the
+    /// topic and the patterns. A message that must be acknowledged is delivered to
+    /// each subscriber with an id of its own, and it is not a failed delivery if it
+    /// is dropped by a full subscriber since it is delivered again later.
+    fn deliver(
+        &mut self,
+        msg_id: MessageId,
+        topic: &str,
+        content: Arc<Vec<u8>>,
+        tag: Option<u64>,
+        expires_at: Option<Instant>,
+        ack: bool,
+    ) -> (Option<DeadLetters>, Blocked, PublishReport) {
+        let mut entry = self.topics.get_mut(topic);
+        let expires_at = match entry.as_mut() {
+            Some(entry) => {
+                entry.touch();
+                entry.expires_at(expires_at)
+            }
+            None => expires_at,
+        };
+        if is_expired(expires_at) {
+            debug!("Message {} of topic {} has expired", msg_id, topic);
+            return (None, Vec::new(), PublishReport::default());
+        }
+        let outgoing = Outgoing {
+            msg_id,
+            topic,
+            content,
+            tag,
+            expires_at,
+            ack,
+            backpressure: self.backpressure.get(topic).copied().unwrap_or_default(),
+        };
+        let mut report = DeliveryReport::default();
+        let mut delivered = BTreeSet::new();
+        let acks = &mut self.acks;
+        if let Some(entry) = entry.as_mut() {
+            entry.subscribers.retain(|client_id, sender| {
+                delivered.insert(*client_id);
+                outgoing.send_to(acks, *client_id, sender, &mut report)
+            });
+        }
+        for (pattern, subscribers) in self.patterns.iter_mut() {
+            if !pubsub::matches(pattern, topic) {
+                continue;
+            }
+            subscribers.retain(|client_id, sender| {
+                !delivered.insert(*client_id)
+                    || outgoing.send_to(acks, *client_id, sender, &mut report)
+            });
+        }
+        self.patterns
+            .retain(|_, subscribers| !subscribers.is_empty());
+
+        let DeliveryReport {
+            failed,
+            blocked,
+            publish,
+        } = report;
+        let entry = match entry {
+            Some(entry) => entry,
+            None => return (None, blocked, publish),
+        };
+        let Outgoing { content, .. } = outgoing;
+        if !failed.is_empty() {
+            entry.dead_lettered += 1;
+        }
+        let dead_letters = match &entry.dead_letter {
+            Some(dead_letter) if !failed.is_empty() => {
+                let failed_at = clock::system_now();
+                let letters = failed
+                    .into_iter()
+                    .map(|(client_id, reason)| DeadLetter {
+                        topic: topic.to_string(),
+                        client_id,
+                        reason,
+                        failed_at,
+                        content: content.to_vec(),
+                    })
+                    .collect();
+                Some(DeadLetters {
+                    topic: dead_letter.clone(),
+                    letters,
+                })
+            }
+            _ => None,
+        };
+        if let Err(err) = entry.retain(topic, msg_id, content, tag, expires_at, &mut *self.store) {
+            error!("{}", err);
+        }
+        (dead_letters, blocked, publish)
+    }
+
+    /// Drops the deliveries to a client that are no longer subscribed to after it
+    /// unsubscribed from a topic or a pattern
+    fn remove_unsubscribed(&mut self, client_id: ClientId) {
+        let (topics, patterns) = (&self.topics, &self.patterns);
+        self.acks.unacked.retain(|key, unacked| {
+            key.0 != client_id
+                || find_subscriber(topics, patterns, &unacked.topic, client_id).is_some()
+        });
+    }
+
+    /// Handles an item. Returns `false` if the broker should stop
+    async fn handle_item(&mut self, item: PubSubItem) -> bool {
+        match item {
+            PubSubItem::Publish {
+                msg_id,
+                topic,
+                content,
+                tag,
+                publisher,
+                ack,
+            } => match self.registry.check(&topic) {
+                Ok(_) => {
+                    let report = self.publish(msg_id, topic, content, tag, None, ack).await;
+                    if let (true, Some(publisher)) = (ack, publisher) {
+                        let msg = ServerBrokerItem::Accepted(msg_id, report);
+                        let _ = send_publication(&publisher, msg);
                     }
                 }
-                PubSubItem::Subscribe {
-                    client_id,
-                    topic,
-                    sender,
-                } => match self.subscriptions.get_mut(&topic) {
-                    Some(entry) => {
-                        entry.insert(client_id, sender);
+                Err(error) => {
+                    debug!("Rejecting publication to topic {:?}: {}", &topic, error);
+                    if let Some(publisher) = publisher {
+                        let msg = ServerBrokerItem::Rejected {
+                            id: msg_id,
+                            topic,
+                            error,
+                        };
+                        let _ = send_publication(&publisher, msg);
                     }
-                    None => {
-                        let mut entry = BTreeMap::new();
-                        entry.insert(client_id, sender);
-                        self.subscriptions.insert(topic, entry);
+                }
+            },
+            PubSubItem::PublishAt {
+                deliver_at,
+                expires_at,
+                msg_id,
+                topic,
+                content,
+                tag,
+            } => {
+                let seq = self.delayed_seq;
+                self.delayed_seq = self.delayed_seq.wrapping_add(1);
+                self.delayed
+                    .insert((deliver_at, seq), (msg_id, topic, content, tag, expires_at));
+            }
+            PubSubItem::Subscribe {
+                client_id,
+                msg_id,
+                topic,
+                sender,
+                replay,
+            } => {
+                let checked = match pubsub::is_pattern(&topic) {
+                    true => self.registry.check_pattern(&topic),
+                    false => self.registry.check(&topic),
+                };
+                if let Err(error) = checked {
+                    debug!("Rejecting subscription to topic {:?}: {}", &topic, error);
+                    let msg = ServerBrokerItem::Rejected {
+                        id: msg_id,
+                        topic,
+                        error,
+                    };
+                    // the subscriber is dropped after receiving the rejection
+                    let _ = send_publication(&sender, msg);
+                    return true;
+                }
+                if pubsub::is_pattern(&topic) {
+                    // late subscribers receive the retained messages of all the
+                    // matching topics first
+                    let mut connected = true;
+                    for (name, entry) in self.topics.iter_mut() {
+                        if !connected || !pubsub::matches(&topic, name) {
+                            continue;
+                        }
+                        if let Err(err) = entry.purge_expired(name, &mut *self.store) {
+                            error!("{}", err);
+                        }
+                        connected = send_retained(name, entry, None, &sender);
                     }
-                },
-                PubSubItem::Unsubscribe { client_id, topic } => {
-                    match self.subscriptions.get_mut(&topic) {
-                        Some(entry) => {
-                            entry.remove(&client_id);
+                    if connected {
+                        self.patterns
+                            .entry(topic)
+                            .or_default()
+                            .insert(client_id, sender);
+                    }
+                    return true;
+                }
+                let entry = self.topics.entry(topic.clone()).or_default();
+                entry.touch();
+                if let Err(err) = entry.purge_expired(&topic, &mut *self.store) {
+                    error!("{}", err);
+                }
+                // a subscriber that asks to replay more messages than are retained
+                // receives the history of the source of the topic instead
+                let source = match replay {
+                    Some(n) if n > entry.retained.len() => self.sources.get(&topic).cloned(),
+                    _ => None,
+                };
+                let history = match source {
+                    Some(source) => match source.read_last(&topic, replay.unwrap_or(0)).await {
+                        Ok(history) => Some(history),
+                        Err(err) => {
+                            error!("Failed to read the history of topic {}: {}", &topic, err);
+                            None
                         }
-                        None => {}
+                    },
+                    None => None,
+                };
+                let entry = self.topics.entry(topic.clone()).or_default();
+                // late subscribers receive the retained messages that have not expired first
+                let connected = match history {
+                    Some(history) => send_history(&topic, history, &sender),
+                    None => send_retained(&topic, entry, replay, &sender),
+                };
+                if connected {
+                    entry.subscribers.insert(client_id, sender);
+                }
+            }
+            PubSubItem::Unsubscribe { client_id, topic } => {
+                if let Some(entry) = self.topics.get_mut(&topic) {
+                    entry.subscribers.remove(&client_id);
+                    entry.touch();
+                }
+                if let Some(subscribers) = self.patterns.get_mut(&topic) {
+                    subscribers.remove(&client_id);
+                    if subscribers.is_empty() {
+                        self.patterns.remove(&topic);
+                    }
+                }
+                self.remove_unsubscribed(client_id);
+            }
+            PubSubItem::Ack { client_id, msg_id } => self.acks.ack(client_id, msg_id),
+            PubSubItem::CreateTopic { topic, done } => {
+                let created = !self.topics.contains_key(&topic);
+                if created {
+                    if let Err(err) = self.store.put_topic(&topic, Retention::None) {
+                        error!("{}", err);
+                    }
+                }
+                self.topics.entry(topic).or_default().pinned = true;
+                let _ = done.send(created);
+            }
+            PubSubItem::DeleteTopic { topic, done } => {
+                let deleted = self.topics.remove(&topic).is_some();
+                self.acks.remove_topic(&topic);
+                if deleted {
+                    if let Err(err) = self.store.remove_topic(&topic) {
+                        error!("{}", err);
                     }
                 }
-                PubSubItem::Stop => return,
+                let _ = done.send(deleted);
+            }
+            PubSubItem::SetRetention {
+                topic,
+                retention,
+                done,
+            } => {
+                let found = match self.topics.get_mut(&topic) {
+                    Some(entry) => {
+                        if let Err(err) = entry.set_retention(&topic, retention, &mut *self.store) {
+                            error!("{}", err);
+                        }
+                        true
+                    }
+                    None => false,
+                };
+                let _ = done.send(found);
+            }
+            PubSubItem::SetDeadLetter {
+                topic,
+                dead_letter,
+                done,
+            } => {
+                let found = match self.topics.get_mut(&topic) {
+                    Some(entry) => {
+                        if let Err(err) = self.store.put_dead_letter(&topic, dead_letter.as_deref())
+                        {
+                            error!("{}", err);
+                        }
+                        entry.dead_letter = dead_letter;
+                        true
+                    }
+                    None => false,
+                };
+                let _ = done.send(found);
             }
+            PubSubItem::SetTtl { topic, ttl, done } => {
+                let found = match self.topics.get_mut(&topic) {
+                    Some(entry) => {
+                        if let Err(err) = self.store.put_ttl(&topic, ttl) {
+                            error!("{}", err);
+                        }
+                        entry.ttl = ttl;
+                        true
+                    }
+                    None => false,
+                };
+                let _ = done.send(found);
+            }
+            PubSubItem::ListTopics { done } => {
+                let list = self
+                    .topics
+                    .iter()
+                    .map(|(name, entry)| entry.info(name))
+                    .collect();
+                let _ = done.send(list);
+            }
+            PubSubItem::Stats { done } => {
+                let _ = done.send(self.stats());
+            }
+            PubSubItem::Stop => return false,
         }
+        true
     }
 }
 
@@ -151,25 +1286,285 @@ impl PubSubBroker {
 /*                                 Public API                                 */
 /* -------------------------------------------------------------------------- */
 
+/// Retention policy of a topic
+///
+/// Retained messages are delivered to a subscriber as soon as it subscribes to the topic
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Retention {
+    /// Messages are not retained (default)
+    #[default]
+    None,
+    /// The last `n` messages published to the topic are retained
+    LastN(usize),
+}
+
+/// What the server does when the buffer of a subscriber is full
+///
+/// A publication that must be acknowledged is not lost when it is dropped, since it
+/// is delivered again after the redelivery timeout.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Backpressure {
+    /// Waits until the subscriber has room for the message. The other publications
+    /// are held back in the meantime, so a single slow subscriber slows down every
+    /// topic.
+    Block,
+    /// Drops the oldest message in the buffer to make room for the new one. A
+    /// subscriber that is a remote client drops the newest message instead.
+    DropOldest,
+    /// Drops the new message (default)
+    #[default]
+    DropNewest,
+    /// Removes the subscriber from the topic. A subscriber on the server side ends
+    /// after receiving the messages in its buffer.
+    Disconnect,
+}
+
+/// Information of a topic on the server
+#[derive(Debug, Clone)]
+pub struct TopicInfo {
+    /// Name of the topic
+    pub name: String,
+    /// Number of subscribers, including both remote and server side subscribers
+    pub subscribers: usize,
+    /// Retention policy of the topic
+    pub retention: Retention,
+    /// Number of messages currently retained
+    pub retained: usize,
+    /// Topic that receives the messages which failed to be delivered
+    pub dead_letter: Option<String>,
+    /// Number of messages which failed to be delivered to at least one subscriber
+    pub dead_lettered: u64,
+    /// Default time-to-live of the messages published to the topic
+    pub ttl: Option<Duration>,
+}
+
+/// Numbers of topics and subscribers on the server
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PubSubStats {
+    /// Number of topics
+    pub topics: usize,
+    /// Number of subscribers on all topics, including both remote and server side
+    /// subscribers
+    pub subscribers: usize,
+    /// Number of subscribers on all topic patterns
+    pub pattern_subscribers: usize,
+    /// Number of idle topics that have been removed
+    pub collected_topics: u64,
+    /// Number of disconnected subscribers that have been removed by the collection of
+    /// idle topics
+    pub pruned_subscribers: u64,
+    /// Number of publications delivered to the subscribers that have not been
+    /// acknowledged yet
+    pub unacked: usize,
+    /// Number of publications delivered again because they were not acknowledged in
+    /// time
+    pub redelivered: u64,
+}
+
+/// Manages the topics on the server from server side code
+///
+/// A `TopicAdmin` can be obtained from `Server::topic_admin()`
+#[derive(Clone)]
+pub struct TopicAdmin {
+    pubsub_tx: Sender<PubSubItem>,
+}
+
+impl TopicAdmin {
+    async fn request<T>(&self, item: PubSubItem, done: oneshot::Receiver<T>) -> Result<T, Error> {
+        self.pubsub_tx.send_async(item).await?;
+        done.await
+            .map_err(|_| Error::Internal("PubSub broker is stopped".into()))
+    }
+
+    /// Creates a topic. Returns `false` if the topic already exists.
+    pub async fn create(&self, topic: impl ToString) -> Result<bool, Error> {
+        let (done, rx) = oneshot::channel();
+        let topic = topic.to_string();
+        self.request(PubSubItem::CreateTopic { topic, done }, rx)
+            .await
+    }
+
+    /// Deletes a topic and drops all of its subscribers and retained messages.
+    /// Returns `false` if the topic is not found.
+    pub async fn delete(&self, topic: impl ToString) -> Result<bool, Error> {
+        let (done, rx) = oneshot::channel();
+        let topic = topic.to_string();
+        self.request(PubSubItem::DeleteTopic { topic, done }, rx)
+            .await
+    }
+
+    /// Sets the retention policy of a topic. Returns `false` if the topic is not found.
+    pub async fn set_retention(
+        &self,
+        topic: impl ToString,
+        retention: Retention,
+    ) -> Result<bool, Error> {
+        let (done, rx) = oneshot::channel();
+        let topic = topic.to_string();
+        self.request(
+            PubSubItem::SetRetention {
+                topic,
+                retention,
+                done,
+            },
+            rx,
+        )
+        .await
+    }
+
+    /// Sets the dead letter topic of a topic. Returns `false` if the topic is not found.
+    ///
+    /// A message that could not be delivered to one of the subscribers, either
+    /// because the subscriber's buffer is full, the subscriber is disconnected or
+    /// the subscriber did not acknowledge it within the maximum number of delivery
+    /// attempts (see `ServerBuilder::max_delivery_attempts`), is published to the dead letter topic in a `DeadLetter`, which tells the
+    /// subscriber, the reason and the time of the failure. The item type of the
+    /// dead letter topic is therefore `DeadLetter`, and retention can be enabled on
+    /// it so that the failed messages can be inspected and replayed later. The
+    /// dead letter topic is kept by the `BrokerStore`.
+    ///
+    /// Passing `None` disables dead lettering for the topic.
+    pub async fn set_dead_letter(
+        &self,
+        topic: impl ToString,
+        dead_letter: Option<impl ToString>,
+    ) -> Result<bool, Error> {
+        let (done, rx) = oneshot::channel();
+        let topic = topic.to_string();
+        let dead_letter = dead_letter.map(|t| t.to_string());
+        self.request(
+            PubSubItem::SetDeadLetter {
+                topic,
+                dead_letter,
+                done,
+            },
+            rx,
+        )
+        .await
+    }
+
+    /// Sets the default time-to-live of the messages published to a topic.
+    /// Returns `false` if the topic is not found.
+    ///
+    /// Expired messages are dropped by the broker instead of being delivered, and
+    /// expired retained messages are not delivered to late subscribers. If a message
+    /// also has its own TTL, the shorter one applies. Passing `None` removes the
+    /// default TTL. The TTL, and the time at which the retained messages expire,
+    /// are kept by the `BrokerStore`.
+    pub async fn set_ttl(
+        &self,
+        topic: impl ToString,
+        ttl: Option<Duration>,
+    ) -> Result<bool, Error> {
+        let (done, rx) = oneshot::channel();
+        let topic = topic.to_string();
+        self.request(PubSubItem::SetTtl { topic, ttl, done }, rx)
+            .await
+    }
+
+    /// Lists all topics on the server
+    pub async fn list(&self) -> Result<Vec<TopicInfo>, Error> {
+        let (done, rx) = oneshot::channel();
+        self.request(PubSubItem::ListTopics { done }, rx).await
+    }
+
+    /// Gets the numbers of topics and subscribers
+    pub async fn stats(&self) -> Result<PubSubStats, Error> {
+        let (done, rx) = oneshot::channel();
+        self.request(PubSubItem::Stats { done }, rx).await
+    }
+
+    /// Gets the information of a topic
+    pub async fn info(&self, topic: impl ToString) -> Result<Option<TopicInfo>, Error> {
+        let topic = topic.to_string();
+        let list = self.list().await?;
+        Ok(list.into_iter().find(|info| info.name == topic))
+    }
+
+    /// Gets the number of subscribers of a topic. Returns `0` if the topic is not found.
+    pub async fn subscriber_count(&self, topic: impl ToString) -> Result<usize, Error> {
+        Ok(self.info(topic).await?.map_or(0, |info| info.subscribers))
+    }
+}
+
+impl Server {
+    /// Creates a `TopicAdmin` which manages the topics on this server
+    pub fn topic_admin(&self) -> TopicAdmin {
+        TopicAdmin {
+            pubsub_tx: self.pubsub_tx.clone(),
+        }
+    }
+}
+
 /// Publisher on the server side
 #[pin_project]
 pub struct Publisher<T: Topic, C: Marshal> {
     #[pin]
     inner: SendSink<'static, PubSubItem>,
     counter: AtomicMessageId,
+    registry: Arc<TopicRegistry>,
+    tag: Option<u64>,
     marker: PhantomData<T>,
     codec: PhantomData<C>,
 }
 
-impl<T: Topic, C: Marshal> From<Sender<PubSubItem>> for Publisher<T, C> {
-    fn from(inner: Sender<PubSubItem>) -> Self {
+impl<T: Topic, C: Marshal> Publisher<T, C> {
+    pub(crate) fn new(inner: Sender<PubSubItem>, registry: Arc<TopicRegistry>) -> Self {
         Self {
             inner: inner.into_sink(),
             counter: AtomicMessageId::new(0),
+            registry,
+            tag: None,
             marker: PhantomData,
             codec: PhantomData,
         }
     }
+
+    /// Includes the type tag of `T::Item` with every published item so that the
+    /// subscribers can verify the item type. See `pubsub::type_tag`.
+    pub fn with_type_tag(mut self) -> Self {
+        self.tag = Some(type_tag::<T>());
+        self
+    }
+
+    /// Publishes a message after `delay`. The message is held by the server
+    /// until it is due and then delivered to the subscribers at that time.
+    pub async fn send_delayed(&mut self, item: T::Item, delay: Duration) -> Result<(), Error> {
+        self.send_at(item, clock::now() + delay).await
+    }
+
+    /// Publishes a message at `deliver_at`. A message whose `deliver_at` is in the
+    /// past is delivered right away.
+    pub async fn send_at(&mut self, item: T::Item, deliver_at: Instant) -> Result<(), Error> {
+        self.send_publication(item, deliver_at, None).await
+    }
+
+    /// Publishes a message that expires after `ttl`. The message is dropped by the
+    /// server instead of being delivered once it has expired.
+    pub async fn send_with_ttl(&mut self, item: T::Item, ttl: Duration) -> Result<(), Error> {
+        let now = clock::now();
+        self.send_publication(item, now, Some(now + ttl)).await
+    }
+
+    async fn send_publication(
+        &mut self,
+        item: T::Item,
+        deliver_at: Instant,
+        expires_at: Option<Instant>,
+    ) -> Result<(), Error> {
+        self.registry.check_type::<T>()?;
+        let msg_id = self.counter.fetch_add(1, Ordering::Relaxed);
+        let content = Arc::new(C::marshal(&item)?);
+        let item = PubSubItem::PublishAt {
+            deliver_at,
+            expires_at,
+            msg_id,
+            topic: T::topic(),
+            content,
+            tag: self.tag,
+        };
+        self.inner.send(item).await.map_err(|err| err.into())
+    }
 }
 
 impl<T: Topic, C: Marshal> Sink<T::Item> for Publisher<T, C> {
@@ -185,6 +1580,7 @@ impl<T: Topic, C: Marshal> Sink<T::Item> for Publisher<T, C> {
 
     fn start_send(self: Pin<&mut Self>, item: T::Item) -> Result<(), Self::Error> {
         let this = self.project();
+        this.registry.check_type::<T>()?;
         let topic = T::topic();
         let msg_id = this.counter.fetch_add(1, Ordering::Relaxed);
         let body = C::marshal(&item)?;
@@ -193,6 +1589,9 @@ impl<T: Topic, C: Marshal> Sink<T::Item> for Publisher<T, C> {
             msg_id,
             topic,
             content,
+            tag: *this.tag,
+            publisher: None,
+            ack: false,
         };
         this.inner.start_send(item).map_err(|err| err.into())
     }
@@ -214,27 +1613,53 @@ impl<T: Topic, C: Marshal> Sink<T::Item> for Publisher<T, C> {
     }
 }
 
-/// Subscriber on the client side
-#[pin_project]
+/// Subscriber on the server side
+///
+/// Each subscriber has its own subscription on the `PubSubBroker`, and it is
+/// unsubscribed from the topic when the subscriber is dropped.
+#[pin_project(PinnedDrop)]
 pub struct Subscriber<T: Topic, C: Unmarshal> {
     #[pin]
     inner: RecvStream<'static, ServerBrokerItem>,
     topic: String,
+    client_id: ClientId,
+    pubsub_tx: Sender<PubSubItem>,
     marker: PhantomData<T>,
     codec: PhantomData<C>,
 }
 
-impl<T: Topic, C: Unmarshal> From<Receiver<ServerBrokerItem>> for Subscriber<T, C> {
-    fn from(inner: Receiver<ServerBrokerItem>) -> Self {
+impl<T: Topic, C: Unmarshal> Subscriber<T, C> {
+    pub(crate) fn new(
+        inner: Receiver<ServerBrokerItem>,
+        client_id: ClientId,
+        pubsub_tx: Sender<PubSubItem>,
+    ) -> Self {
         Self {
             inner: inner.into_stream(),
             topic: T::topic(),
+            client_id,
+            pubsub_tx,
             marker: PhantomData,
             codec: PhantomData,
         }
     }
 }
 
+#[pin_project::pinned_drop]
+impl<T: Topic, C: Unmarshal> PinnedDrop for Subscriber<T, C> {
+    fn drop(self: Pin<&mut Self>) {
+        let this = self.project();
+        let item = PubSubItem::Unsubscribe {
+            client_id: *this.client_id,
+            topic: this.topic.clone(),
+        };
+        // The broker may have already stopped if the server is dropped
+        if this.pubsub_tx.send(item).is_err() {
+            debug!("PubSub broker is stopped");
+        }
+    }
+}
+
 impl<T: Topic, C: Unmarshal> Stream for Subscriber<T, C> {
     type Item = Result<T::Item, Error>;
 
@@ -245,16 +1670,29 @@ impl<T: Topic, C: Unmarshal> Stream for Subscriber<T, C> {
             Poll::Ready(opt) => match opt {
                 Some(item) => match item {
                     ServerBrokerItem::Publication {
-                        id: _,
+                        id,
                         topic,
                         content,
+                        tag,
+                        ack,
                     } => {
+                        if ack {
+                            let item = PubSubItem::Ack {
+                                client_id: *this.client_id,
+                                msg_id: id,
+                            };
+                            if this.pubsub_tx.send(item).is_err() {
+                                debug!("PubSub broker is stopped");
+                            }
+                        }
                         let result = match &topic == this.topic {
-                            true => C::unmarshal(&content),
+                            true => check_type_tag::<T>(tag).and_then(|_| C::unmarshal(&content)),
                             false => Err(Error::Internal("Mismatched topic".into())),
                         };
                         Poll::Ready(Some(result))
                     }
+                    // the stream ends after the rejection
+                    ServerBrokerItem::Rejected { error, .. } => Poll::Ready(Some(Err(error))),
                     _ => {
                         let result = Err(Error::Internal("Invalid PubSub item".into()));
                         Poll::Ready(Some(result))
@@ -274,52 +1712,103 @@ cfg_if::cfg_if! {
             not(feature = "serde_json"),
             not(feature = "serde_cbor"),
             not(feature = "serde_rmp"),
+            not(feature = "serde_postcard"),
         ),
         all(
             feature = "serde_cbor",
             not(feature = "serde_json"),
             not(feature = "serde_bincode"),
             not(feature = "serde_rmp"),
+            not(feature = "serde_postcard"),
         ),
         all(
             feature = "serde_json",
             not(feature = "serde_bincode"),
             not(feature = "serde_cbor"),
             not(feature = "serde_rmp"),
+            not(feature = "serde_postcard"),
         ),
         all(
             feature = "serde_rmp",
             not(feature = "serde_cbor"),
             not(feature = "serde_json"),
             not(feature = "serde_bincode"),
+            not(feature = "serde_postcard"),
+        ),
+        all(
+            feature = "serde_postcard",
+            not(feature = "serde_cbor"),
+            not(feature = "serde_json"),
+            not(feature = "serde_bincode"),
+            not(feature = "serde_rmp"),
         ),
     ))] {
         use crate::codec::DefaultCodec;
 
         type PhantomCodec = DefaultCodec<Reserved, Reserved, Reserved>;
 
+        /// Marshals a dead letter with the codec of the server
+        fn marshal_dead_letter(letter: &DeadLetter) -> Result<Vec<u8>, Error> {
+            PhantomCodec::marshal(letter)
+        }
+
         impl Server {
             /// Creates a new publihser on a topic
             pub fn publisher<T: Topic>(&self) -> Publisher<T, PhantomCodec> {
                 let tx = self.pubsub_tx.clone();
-                Publisher::from(tx)
+                Publisher::new(tx, self.topics.clone())
             }
 
             /// Creates a new subscriber on a topic
             ///
-            /// Multiple subscribers can be created on the server side
-            #[cfg(not(feature = "http_actix_web"))]
-            #[cfg_attr(feature = "docs", doc(cfg(not(feature = "http_actix_web"))))]
+            /// Multiple subscribers can be created on the server side, and each of them
+            /// receives the same messages as the remote subscribers on the topic.
+            /// Messages are dropped for a subscriber whose buffer of size `cap` is full.
             pub fn subscriber<T: Topic>(&self, cap: usize) -> Result<Subscriber<T, PhantomCodec>, Error> {
+                self.topics.check_type::<T>()?;
                 let (sender, rx) = flume::bounded(cap);
-                let client_id = RESERVED_CLIENT_ID;
+                // server side subscribers share the id space with the remote clients
+                let client_id = self.client_counter.fetch_add(1, Ordering::Relaxed);
                 let topic = T::topic();
-                let sender = PubSubResponder::Sender(sender);
-                self.pubsub_tx.send(PubSubItem::Subscribe{client_id, topic, sender})?;
+                let sender = PubSubResponder::Local(sender, rx.clone());
+                self.pubsub_tx.send(PubSubItem::Subscribe{client_id, msg_id: 0, topic, sender, replay: None})?;
                 Ok(
-                    Subscriber::from(rx)
+                    Subscriber::new(rx, client_id, self.pubsub_tx.clone())
                 )
             }
         }
+    } else {
+        /// Dead letters are marshalled by the default codec, which requires exactly
+        /// one of the `serde_*` features
+        fn marshal_dead_letter(_: &DeadLetter) -> Result<Vec<u8>, Error> {
+            Err(Error::Internal("Dead letters require a default codec".into()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delivery_ids_in_use_are_skipped() {
+        let mut acks = Acks::new(Duration::from_secs(10), None);
+        let content = Arc::new(Vec::new());
+        assert_eq!(acks.track(1, "Count", content.clone(), None, None), 0);
+        assert_eq!(acks.track(1, "Count", content.clone(), None, None), 1);
+
+        // the ids wrap around and skip the deliveries that are not acknowledged
+        acks.next_id = MessageId::MAX;
+        assert_eq!(
+            acks.track(1, "Count", content.clone(), None, None),
+            MessageId::MAX
+        );
+        assert_eq!(acks.track(1, "Count", content.clone(), None, None), 2);
+        acks.ack(1, 0);
+        acks.next_id = 0;
+        assert_eq!(acks.track(1, "Count", content.clone(), None, None), 0);
+        // the ids of another client are independent
+        acks.next_id = 0;
+        assert_eq!(acks.track(2, "Count", content, None, None), 0);
     }
 }
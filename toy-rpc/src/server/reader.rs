@@ -1,31 +1,130 @@
 use brw::{Reader, Running};
 use futures::sink::{Sink, SinkExt};
-use std::sync::Arc;
+use futures::FutureExt;
+use std::{collections::HashMap, sync::Arc, time::Duration};
 
 use crate::{
     codec::CodecRead,
     error::Error,
+    extension::{ExtensionMap, HEARTBEAT_MARKER},
     message::{MessageId, CANCELLATION_TOKEN, CANCELLATION_TOKEN_DELIM},
-    service::{ArcAsyncServiceCall, AsyncServiceMap},
+    metadata::{self, Metadata, METADATA_MARKER},
+    payload::PayloadAccounting,
+    service::ArcAsyncServiceCall,
+    streaming::SinkArgument,
 };
 
+use super::audit::{Auditor, PendingCall};
 use super::broker::ServerBrokerItem;
+use super::flow::OutboundQueue;
+use super::naming::Routes;
+use super::stats::ConnectionCounters;
 use crate::protocol::{Header, InboundBody};
 
+/// Maximum number of requests whose metadata is read before the request itself
+const MAX_PENDING_METADATA: usize = 1024;
+
 pub(crate) struct ServerReader<T> {
     reader: T,
-    services: Arc<AsyncServiceMap>,
+    services: Arc<Routes>,
+    extensions: Arc<ExtensionMap>,
+    auditor: Option<Auditor>,
+    payload: Arc<PayloadAccounting>,
+    stats: Arc<ConnectionCounters>,
+    outbound: Arc<OutboundQueue>,
+    // arguments of the calls that are sent by a `ClientSink`
+    sinks: HashMap<MessageId, SinkArgument>,
+    // metadata of the requests that are not read yet
+    metadata: HashMap<MessageId, Metadata>,
 }
 
 impl<T: CodecRead> ServerReader<T> {
     #[cfg(not(feature = "http_actix_web"))]
-    pub fn new(reader: T, services: Arc<AsyncServiceMap>) -> Self {
-        Self { reader, services }
+    pub fn new(
+        reader: T,
+        services: Arc<Routes>,
+        extensions: Arc<ExtensionMap>,
+        auditor: Option<Auditor>,
+        payload: Arc<PayloadAccounting>,
+        stats: Arc<ConnectionCounters>,
+        outbound: Arc<OutboundQueue>,
+    ) -> Self {
+        Self {
+            reader,
+            services,
+            extensions,
+            auditor,
+            payload,
+            stats,
+            outbound,
+            sinks: HashMap::new(),
+            metadata: HashMap::new(),
+        }
+    }
+
+    /// Looks up the service of a request and passes the call to the broker. The
+    /// argument of a `SinkRequest` is passed to the handler instead of the body.
+    #[allow(clippy::too_many_arguments)]
+    async fn request<B>(
+        &mut self,
+        broker: &mut B,
+        id: MessageId,
+        service_method: String,
+        timeout: Duration,
+        bytes: Vec<u8>,
+        argument: Option<Box<InboundBody>>,
+    ) -> Result<(), Error>
+    where
+        B: Sink<ServerBrokerItem, Error = flume::SendError<ServerBrokerItem>> + Send + Unpin,
+    {
+        let service_method = self.services.resolve(service_method);
+        let metadata = self.metadata.remove(&id).unwrap_or_default();
+        let audit = self
+            .auditor
+            .as_ref()
+            .map(|_| PendingCall::new(&service_method, &bytes));
+        self.payload
+            .counters
+            .record_request(&service_method, bytes.len());
+        self.stats.call();
+        self.stats.body_read(bytes.len());
+        let checked = self
+            .payload
+            .limits
+            .check_request(&service_method, bytes.len());
+        let deserializer = match argument {
+            Some(argument) => argument,
+            None => T::from_bytes(bytes),
+        };
+        match checked.and_then(|_| get_service(&self.services, service_method.clone())) {
+            Ok((call, method)) => {
+                let msg = ServerBrokerItem::Request {
+                    call,
+                    id,
+                    service_method,
+                    method,
+                    duration: timeout,
+                    deserializer,
+                    metadata,
+                    audit,
+                };
+                broker.send(msg).await.map_err(|err| err.into())
+            }
+            Err(err) => {
+                warn!("{}", &err);
+                let result = Err(err);
+                if let (Some(auditor), Some(audit)) = (&self.auditor, audit) {
+                    auditor.record(audit, &result);
+                }
+                let msg = ServerBrokerItem::Response { id, result };
+                broker.send(msg).await.map_err(|err| err.into())
+            }
+        }
     }
 }
 
 pub(crate) fn get_service(
-    services: &Arc<AsyncServiceMap>,
+    services: &Arc<Routes>,
     service_method: String,
 ) -> Result<(ArcAsyncServiceCall, String), Error> {
     // split service and method
@@ -57,6 +156,48 @@ pub(crate) fn handle_cancel(
     }
 }
 
+/// Reads the number of past messages that a subscriber asks to replay from the
+/// body of a subscribe message. Older clients send `()`, which is read as `None`.
+pub(crate) fn replay_count(deserializer: &mut Box<InboundBody>) -> Option<usize> {
+    erased_serde::deserialize::<Option<u64>>(deserializer)
+        .ok()
+        .flatten()
+        .map(|n| n as usize)
+}
+
+/// Calls the handler of an extension message. Returns the content of the reply if any
+pub(crate) fn handle_extension(
+    extensions: &Arc<ExtensionMap>,
+    marker: u32,
+    content: String,
+) -> Option<String> {
+    if marker == HEARTBEAT_MARKER {
+        trace!("Received a heartbeat");
+        return None;
+    }
+    match extensions.get(&marker) {
+        Some(handler) => handler(content),
+        None => {
+            warn!("Extension not found for marker: {}", marker);
+            None
+        }
+    }
+}
+
+/// Keeps the metadata of a request until the request is read. The metadata is
+/// dropped if too many requests have metadata that is not used yet.
+pub(crate) fn stash_metadata(
+    pending: &mut HashMap<MessageId, Metadata>,
+    id: MessageId,
+    content: &str,
+) {
+    if pending.len() >= MAX_PENDING_METADATA && !pending.contains_key(&id) {
+        warn!("Dropping the metadata of request {}", id);
+        return;
+    }
+    pending.insert(id, metadata::decode(content));
+}
+
 fn is_correct_cancellation_token(id: MessageId, token: &str) -> bool {
     match token.find(CANCELLATION_TOKEN_DELIM) {
         Some(ind) => {
@@ -82,12 +223,29 @@ impl<T: CodecRead> Reader for ServerReader<T> {
     where
         B: Sink<Self::BrokerItem, Error = flume::SendError<Self::BrokerItem>> + Send + Unpin,
     {
-        if let Some(header) = self.reader.read_header().await {
+        // a slow client is not read until its outbound queue is drained
+        if !self.outbound.ready().await {
+            return Running::Stop;
+        }
+        let header = {
+            let read = self.reader.read_protocol_header().fuse();
+            let closed = self.outbound.closed().fuse();
+            futures::pin_mut!(read, closed);
+            futures::select_biased! {
+                _ = closed => return Running::Stop,
+                header = read => header,
+            }
+        };
+        if let Some(header) = header {
             let header: Header = match header {
                 Ok(header) => header,
-                Err(err) => return Running::Continue(Err(err)),
+                Err(err) => {
+                    self.stats.unmarshal_error();
+                    return Running::Continue(Err(err));
+                }
             };
-            log::debug!("{:?}", &header);
+            self.stats.message_read();
+            debug!("{:?}", &header);
 
             match header {
                 Header::Request {
@@ -95,6 +253,61 @@ impl<T: CodecRead> Reader for ServerReader<T> {
                     service_method,
                     timeout,
                 } => {
+                    let bytes = match self.reader.read_bytes().await {
+                        Some(Ok(bytes)) => bytes,
+                        Some(Err(err)) => return Running::Continue(Err(err)),
+                        None => return Running::Stop,
+                    };
+                    let res = self
+                        .request(&mut broker, id, service_method, timeout, bytes, None)
+                        .await;
+                    Running::Continue(res)
+                }
+                Header::SinkRequest {
+                    id,
+                    service_method,
+                    timeout,
+                } => {
+                    let bytes = match self.reader.read_bytes().await {
+                        Some(Ok(bytes)) => bytes,
+                        Some(Err(err)) => return Running::Continue(Err(err)),
+                        None => return Running::Stop,
+                    };
+                    let (argument, deserializer) = SinkArgument::open();
+                    self.sinks.insert(id, argument);
+                    let res = self
+                        .request(
+                            &mut broker,
+                            id,
+                            service_method,
+                            timeout,
+                            bytes,
+                            Some(deserializer),
+                        )
+                        .await;
+                    Running::Continue(res)
+                }
+                Header::SinkItem(id) => {
+                    let bytes = match self.reader.read_bytes().await {
+                        Some(Ok(bytes)) => bytes,
+                        Some(Err(err)) => return Running::Continue(Err(err)),
+                        None => return Running::Stop,
+                    };
+                    self.stats.body_read(bytes.len());
+                    if let Some(argument) = self.sinks.get(&id) {
+                        argument.push(T::from_bytes(bytes));
+                    }
+                    Running::Continue(Ok(()))
+                }
+                Header::SinkEnd(id) => {
+                    let _ = self.reader.read_bytes().await;
+                    // dropping the argument ends the stream of the handler
+                    self.sinks.remove(&id);
+                    Running::Continue(Ok(()))
+                }
+                // the response of the client to a call to its services
+                #[cfg(not(feature = "http_actix_web"))]
+                Header::Response { id, is_ok } => {
                     let deserializer = match self.reader.read_body().await {
                         Some(res) => match res {
                             Ok(de) => de,
@@ -102,27 +315,14 @@ impl<T: CodecRead> Reader for ServerReader<T> {
                         },
                         None => return Running::Stop,
                     };
-                    match get_service(&self.services, service_method) {
-                        Ok((call, method)) => {
-                            let msg = ServerBrokerItem::Request {
-                                call,
-                                id,
-                                method,
-                                duration: timeout,
-                                deserializer,
-                            };
-                            Running::Continue(broker.send(msg).await.map_err(|err| err.into()))
-                        }
-                        Err(err) => {
-                            log::error!("{}", &err);
-                            let msg = ServerBrokerItem::Response {
-                                id,
-                                result: Err(err),
-                            };
-                            Running::Continue(broker.send(msg).await.map_err(|err| err.into()))
-                        }
-                    }
+                    let result = match is_ok {
+                        true => Ok(deserializer),
+                        false => Err(deserializer),
+                    };
+                    let msg = ServerBrokerItem::PeerResponse { id, result };
+                    Running::Continue(broker.send(msg).await.map_err(|err| err.into()))
                 }
+                #[cfg(feature = "http_actix_web")]
                 Header::Response { id, is_ok } => {
                     let _ = match self.reader.read_body().await {
                         Some(res) => match res {
@@ -145,6 +345,7 @@ impl<T: CodecRead> Reader for ServerReader<T> {
                     };
                     match handle_cancel(id, deserializer) {
                         Ok(_) => {
+                            self.sinks.remove(&id);
                             let msg = ServerBrokerItem::Cancel(id);
                             Running::Continue(broker.send(msg).await.map_err(|err| err.into()))
                         }
@@ -165,34 +366,82 @@ impl<T: CodecRead> Reader for ServerReader<T> {
                         },
                         None => return Running::Stop,
                     };
+                    self.stats.body_read(content.len());
+                    let msg = ServerBrokerItem::Publish {
+                        id,
+                        topic,
+                        content,
+                        tag: None,
+                        ack: false,
+                    };
+                    Running::Continue(broker.send(msg).await.map_err(|err| err.into()))
+                }
+                Header::TaggedPublish { id, topic, tag } => {
+                    let content = match self.reader.read_bytes().await {
+                        Some(res) => match res {
+                            Ok(b) => b,
+                            Err(err) => return Running::Continue(Err(err)),
+                        },
+                        None => return Running::Stop,
+                    };
+                    self.stats.body_read(content.len());
+                    let msg = ServerBrokerItem::Publish {
+                        id,
+                        topic,
+                        content,
+                        tag: Some(tag),
+                        ack: false,
+                    };
+                    Running::Continue(broker.send(msg).await.map_err(|err| err.into()))
+                }
+                Header::AckedPublish { id, topic, tag } => {
+                    let content = match self.reader.read_bytes().await {
+                        Some(res) => match res {
+                            Ok(b) => b,
+                            Err(err) => return Running::Continue(Err(err)),
+                        },
+                        None => return Running::Stop,
+                    };
+                    self.stats.body_read(content.len());
+                    let msg = ServerBrokerItem::Publish {
+                        id,
+                        topic,
+                        content,
+                        tag,
+                        ack: true,
+                    };
+                    Running::Continue(broker.send(msg).await.map_err(|err| err.into()))
+                }
+                Header::Subscribe { id, topic } => {
+                    let replay = match self.reader.read_body().await {
+                        Some(Ok(mut body)) => replay_count(&mut body),
+                        _ => None,
+                    };
                     Running::Continue(
                         broker
-                            .send(ServerBrokerItem::Publish { id, topic, content })
+                            .send(ServerBrokerItem::Subscribe { id, topic, replay })
                             .await
                             .map_err(|err| err.into()),
                     )
                 }
-                Header::Subscribe { id, topic } => {
+                Header::Unsubscribe { id, topic } => {
                     let _ = self.reader.read_bytes().await;
                     Running::Continue(
                         broker
-                            .send(ServerBrokerItem::Subscribe { id, topic })
+                            .send(ServerBrokerItem::Unsubscribe { id, topic })
                             .await
                             .map_err(|err| err.into()),
                     )
                 }
-                Header::Unsubscribe { id, topic } => {
+                Header::Ack(id) => {
                     let _ = self.reader.read_bytes().await;
                     Running::Continue(
                         broker
-                            .send(ServerBrokerItem::Unsubscribe { id, topic })
+                            .send(ServerBrokerItem::Ack(id))
                             .await
                             .map_err(|err| err.into()),
                     )
                 }
-                Header::Ack(_) => Running::Continue(Err(Error::Internal(
-                    "Unexpected Header type (Header::Ack)".into(),
-                ))),
                 Header::Produce {
                     id: _,
                     topic: _,
@@ -203,13 +452,53 @@ impl<T: CodecRead> Reader for ServerReader<T> {
                 Header::Consume { id: _, topic: _ } => Running::Continue(Err(Error::Internal(
                     "Unexpected Header type (Header::Consume)".into(),
                 ))),
+                Header::Reject { id: _, topic: _ } => {
+                    let _ = self.reader.read_bytes().await;
+                    Running::Continue(Err(Error::Internal(
+                        "Unexpected Header type (Header::Reject)".into(),
+                    )))
+                }
+                Header::StreamItem { id: _, is_ok: _ } => {
+                    let _ = self.reader.read_bytes().await;
+                    Running::Continue(Err(Error::Internal(
+                        "Unexpected Header type (Header::StreamItem)".into(),
+                    )))
+                }
+                Header::StreamEnd(_) => {
+                    let _ = self.reader.read_bytes().await;
+                    Running::Continue(Err(Error::Internal(
+                        "Unexpected Header type (Header::StreamEnd)".into(),
+                    )))
+                }
                 Header::Ext {
-                    id: _,
-                    content: _,
-                    marker: _,
-                } => Running::Continue(Err(Error::Internal(
-                    "Unexpected Header type (Header::Ext)".into(),
-                ))),
+                    id,
+                    content,
+                    marker,
+                } if marker == METADATA_MARKER => {
+                    let _ = self.reader.read_bytes().await;
+                    stash_metadata(&mut self.metadata, id, &content);
+                    Running::Continue(Ok(()))
+                }
+                Header::Ext {
+                    id,
+                    content,
+                    marker,
+                } => {
+                    let _ = self.reader.read_bytes().await;
+                    match handle_extension(&self.extensions, marker, content) {
+                        Some(content) => Running::Continue(
+                            broker
+                                .send(ServerBrokerItem::Ext {
+                                    id,
+                                    marker,
+                                    content,
+                                })
+                                .await
+                                .map_err(|err| err.into()),
+                        ),
+                        None => Running::Continue(Ok(())),
+                    }
+                }
             }
         } else {
             if broker.send(ServerBrokerItem::Stop).await.is_ok() {}
@@ -219,7 +508,7 @@ impl<T: CodecRead> Reader for ServerReader<T> {
 
     async fn handle_result(res: Result<Self::Ok, Self::Error>) -> Running<()> {
         if let Err(err) = res {
-            log::error!("{:?}", err);
+            error!("{:?}", err);
         }
         Running::Continue(())
     }
@@ -0,0 +1,753 @@
+//! Recovery of malformed arguments
+//!
+//! Clients generated from loosely-typed languages often send a scalar of the wrong
+//! type, eg. the string `"5"` for a numeric field or `1` for a string field. Such an
+//! argument normally fails to deserialize and the request fails with
+//! `Error::InvalidArgument`. A `Repair` hook set with `ServerBuilder::argument_recovery`
+//! for a service or a single method is given the scalars that do not match the type
+//! of the argument, and either returns a replacement or rejects them, in which case
+//! the request fails as before. The hook of a method takes precedence over the hook
+//! of its service.
+//!
+//! `Lenient` is the hook that parses strings into booleans and numbers, and formats
+//! booleans and numbers into strings. Any closure of the signature
+//! `Fn(Expected, Scalar) -> Option<Scalar>` is a hook as well.
+//!
+//! The hooks apply to the scalars nested in the arguments as well. Only the formats
+//! that describe the type of their values (eg. `serde_json`, `serde_cbor` and
+//! `rmp-serde`) can tell that a scalar has the wrong type. With `bincode` and
+//! `postcard` the hooks have no effect.
+//!
+//! # Example
+//!
+//! ```no_run
+//! # #[cfg(any(feature = "async_std_runtime", feature = "tokio_runtime"))]
+//! # {
+//! use toy_rpc::server::recovery::{Expected, Lenient, Repair, Scalar};
+//! # use std::sync::Arc;
+//! # use toy_rpc::Server;
+//! # use toy_rpc::macros::export_impl;
+//! # struct Example;
+//! # #[export_impl]
+//! # impl Example {
+//! #     #[export_method]
+//! #     async fn echo(&self, args: String) -> Result<String, String> {
+//! #         Ok(args)
+//! #     }
+//! # }
+//! # let example_service = Arc::new(Example);
+//!
+//! let server = Server::builder()
+//!     .register(example_service)
+//!     // accept `{"count": "5"}` for `count: u32` on every method of `Example`
+//!     .argument_recovery("Example", Lenient)
+//!     // and treat an empty string as zero on `Example.resize`
+//!     .argument_recovery("Example.resize", |expected, found| match (expected, found) {
+//!         (Expected::Integer, Scalar::String(s)) if s.is_empty() => Some(Scalar::U64(0)),
+//!         (expected, found) => Lenient.repair(expected, found),
+//!     })
+//!     .build();
+//! # }
+//! ```
+
+use erased_serde as erased;
+use serde::de::{
+    self, DeserializeSeed, Deserializer, EnumAccess, MapAccess, SeqAccess, Unexpected,
+    VariantAccess, Visitor,
+};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+use crate::service::{ArcAsyncServiceCall, AsyncServiceMap, HandlerResultFut};
+
+/// Kind of scalar that the argument expects
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Expected {
+    /// A boolean
+    Bool,
+    /// An integer of any width
+    Integer,
+    /// A floating point number
+    Float,
+    /// A string
+    String,
+}
+
+/// A scalar found in an argument, or returned by a `Repair` hook in its place
+#[derive(Debug, Clone, PartialEq)]
+pub enum Scalar {
+    /// A boolean
+    Bool(bool),
+    /// A signed integer
+    I64(i64),
+    /// An unsigned integer
+    U64(u64),
+    /// A floating point number
+    F64(f64),
+    /// A string
+    String(String),
+}
+
+impl Scalar {
+    fn unexpected(&self) -> Unexpected<'_> {
+        match self {
+            Scalar::Bool(v) => Unexpected::Bool(*v),
+            Scalar::I64(v) => Unexpected::Signed(*v),
+            Scalar::U64(v) => Unexpected::Unsigned(*v),
+            Scalar::F64(v) => Unexpected::Float(*v),
+            Scalar::String(v) => Unexpected::Str(v),
+        }
+    }
+
+    fn visit<'de, V, E>(self, visitor: V) -> Result<V::Value, E>
+    where
+        V: Visitor<'de>,
+        E: de::Error,
+    {
+        match self {
+            Scalar::Bool(v) => visitor.visit_bool(v),
+            Scalar::I64(v) => visitor.visit_i64(v),
+            Scalar::U64(v) => visitor.visit_u64(v),
+            Scalar::F64(v) => visitor.visit_f64(v),
+            Scalar::String(v) => visitor.visit_string(v),
+        }
+    }
+}
+
+/// Hook that repairs the scalars of an argument that do not have the expected type
+pub trait Repair: Send + Sync + 'static {
+    /// Returns the scalar that replaces `found`, or `None` to reject the argument
+    fn repair(&self, expected: Expected, found: Scalar) -> Option<Scalar>;
+}
+
+impl<F> Repair for F
+where
+    F: Fn(Expected, Scalar) -> Option<Scalar> + Send + Sync + 'static,
+{
+    fn repair(&self, expected: Expected, found: Scalar) -> Option<Scalar> {
+        self(expected, found)
+    }
+}
+
+/// Parses strings into booleans and numbers, and formats booleans and numbers into
+/// strings. Leading and trailing whitespace is ignored when parsing.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Lenient;
+
+impl Repair for Lenient {
+    fn repair(&self, expected: Expected, found: Scalar) -> Option<Scalar> {
+        match (expected, found) {
+            (Expected::Bool, Scalar::String(s)) => s.trim().parse().ok().map(Scalar::Bool),
+            (Expected::Integer, Scalar::String(s)) => {
+                let s = s.trim();
+                s.parse()
+                    .map(Scalar::I64)
+                    .or_else(|_| s.parse().map(Scalar::U64))
+                    .ok()
+            }
+            (Expected::Float, Scalar::String(s)) => s.trim().parse().ok().map(Scalar::F64),
+            (Expected::String, Scalar::Bool(v)) => Some(Scalar::String(v.to_string())),
+            (Expected::String, Scalar::I64(v)) => Some(Scalar::String(v.to_string())),
+            (Expected::String, Scalar::U64(v)) => Some(Scalar::String(v.to_string())),
+            (Expected::String, Scalar::F64(v)) => Some(Scalar::String(v.to_string())),
+            _ => None,
+        }
+    }
+}
+
+/// Hooks of a service, keyed by method name. The hook under `None` applies to the
+/// methods without one of their own.
+type ServiceRepairs = HashMap<Option<String>, Arc<dyn Repair>>;
+
+/// Wraps the services with the hooks keyed by `"Service"` or `"Service.method"`
+pub(crate) fn apply(services: &mut AsyncServiceMap, repairs: &HashMap<String, Arc<dyn Repair>>) {
+    let mut by_service: HashMap<&str, ServiceRepairs> = HashMap::new();
+    for (name, repair) in repairs {
+        let (service, method) = match name.find('.') {
+            Some(pos) => (&name[..pos], Some(name[pos + 1..].to_string())),
+            None => (&name[..], None),
+        };
+        by_service
+            .entry(service)
+            .or_default()
+            .insert(method, repair.clone());
+    }
+
+    for (service, repairs) in by_service {
+        let call = match services.get_mut(service) {
+            Some(call) => call,
+            None => {
+                warn!("Argument recovery is set on unknown service {}", service);
+                continue;
+            }
+        };
+        let inner = call.clone();
+        *call = with_repairs(inner, repairs);
+    }
+}
+
+fn with_repairs(inner: ArcAsyncServiceCall, repairs: ServiceRepairs) -> ArcAsyncServiceCall {
+    let call = move |method_name: String,
+                     deserializer: Box<dyn erased::Deserializer<'static> + Send>|
+          -> HandlerResultFut {
+        let repair = repairs
+            .get(&Some(method_name.clone()))
+            .or_else(|| repairs.get(&None));
+        match repair {
+            Some(repair) => {
+                let deserializer = <dyn erased::Deserializer>::erase(Recovering::new(
+                    deserializer,
+                    repair.clone(),
+                ));
+                inner(method_name, Box::new(deserializer))
+            }
+            None => inner(method_name, deserializer),
+        }
+    };
+    Arc::new(call)
+}
+
+/// Deserializer that passes the scalars of the wrong type to a `Repair` hook
+struct Recovering<D> {
+    inner: D,
+    repair: Arc<dyn Repair>,
+}
+
+impl<D> Recovering<D> {
+    fn new(inner: D, repair: Arc<dyn Repair>) -> Self {
+        Self { inner, repair }
+    }
+
+    fn visit<V>(&self, visitor: V, expected: Option<Expected>) -> RecoveringVisitor<V> {
+        RecoveringVisitor {
+            inner: visitor,
+            repair: self.repair.clone(),
+            expected,
+        }
+    }
+}
+
+macro_rules! forward_deserialize {
+    ($($method:ident)*) => {$(
+        fn $method<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+        where
+            V: Visitor<'de>,
+        {
+            let visitor = self.visit(visitor, None);
+            self.inner.$method(visitor)
+        }
+    )*};
+}
+
+// `serde_json` rejects a scalar of the wrong type before calling the visitor, so a
+// human readable format is asked for whatever scalar it has instead
+macro_rules! deserialize_scalar {
+    ($($method:ident: $expected:expr)*) => {$(
+        fn $method<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+        where
+            V: Visitor<'de>,
+        {
+            let visitor = self.visit(visitor, Some($expected));
+            match self.inner.is_human_readable() {
+                true => self.inner.deserialize_any(visitor),
+                false => self.inner.$method(visitor),
+            }
+        }
+    )*};
+}
+
+impl<'de, D> Deserializer<'de> for Recovering<D>
+where
+    D: Deserializer<'de>,
+{
+    type Error = D::Error;
+
+    forward_deserialize! {
+        deserialize_any deserialize_char
+        deserialize_bytes deserialize_byte_buf deserialize_option deserialize_unit
+        deserialize_seq deserialize_map deserialize_identifier deserialize_ignored_any
+    }
+
+    deserialize_scalar! {
+        deserialize_bool: Expected::Bool
+        deserialize_i8: Expected::Integer deserialize_i16: Expected::Integer
+        deserialize_i32: Expected::Integer deserialize_i64: Expected::Integer
+        deserialize_i128: Expected::Integer
+        deserialize_u8: Expected::Integer deserialize_u16: Expected::Integer
+        deserialize_u32: Expected::Integer deserialize_u64: Expected::Integer
+        deserialize_u128: Expected::Integer
+        deserialize_f32: Expected::Float deserialize_f64: Expected::Float
+        deserialize_str: Expected::String deserialize_string: Expected::String
+    }
+
+    fn deserialize_unit_struct<V>(
+        self,
+        name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        let visitor = self.visit(visitor, None);
+        self.inner.deserialize_unit_struct(name, visitor)
+    }
+
+    fn deserialize_newtype_struct<V>(
+        self,
+        name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        let visitor = self.visit(visitor, None);
+        self.inner.deserialize_newtype_struct(name, visitor)
+    }
+
+    fn deserialize_tuple<V>(self, len: usize, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        let visitor = self.visit(visitor, None);
+        self.inner.deserialize_tuple(len, visitor)
+    }
+
+    fn deserialize_tuple_struct<V>(
+        self,
+        name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        let visitor = self.visit(visitor, None);
+        self.inner.deserialize_tuple_struct(name, len, visitor)
+    }
+
+    fn deserialize_struct<V>(
+        self,
+        name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        let visitor = self.visit(visitor, None);
+        self.inner.deserialize_struct(name, fields, visitor)
+    }
+
+    fn deserialize_enum<V>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        let visitor = self.visit(visitor, None);
+        self.inner.deserialize_enum(name, variants, visitor)
+    }
+
+    fn is_human_readable(&self) -> bool {
+        self.inner.is_human_readable()
+    }
+}
+
+/// Seed that deserializes with a `Recovering` deserializer
+struct RecoveringSeed<S> {
+    inner: S,
+    repair: Arc<dyn Repair>,
+}
+
+impl<'de, S> DeserializeSeed<'de> for RecoveringSeed<S>
+where
+    S: DeserializeSeed<'de>,
+{
+    type Value = S::Value;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        self.inner
+            .deserialize(Recovering::new(deserializer, self.repair))
+    }
+}
+
+/// Visitor that repairs the scalars that do not match `expected`, and passes the
+/// hook on to the nested values
+struct RecoveringVisitor<V> {
+    inner: V,
+    repair: Arc<dyn Repair>,
+    expected: Option<Expected>,
+}
+
+impl<V> RecoveringVisitor<V> {
+    fn wrap<D>(&self, deserializer: D) -> Recovering<D> {
+        Recovering::new(deserializer, self.repair.clone())
+    }
+
+    /// Returns whether a scalar of the kind `found` is accepted as is. An integer is
+    /// accepted where a float is expected.
+    fn accepts(&self, found: Expected) -> bool {
+        match self.expected {
+            None => true,
+            Some(Expected::Float) => matches!(found, Expected::Float | Expected::Integer),
+            Some(expected) => expected == found,
+        }
+    }
+
+    fn repair<'de, E>(self, found: Scalar) -> Result<V::Value, E>
+    where
+        V: Visitor<'de>,
+        E: de::Error,
+    {
+        let expected = match self.expected {
+            Some(expected) => expected,
+            None => return found.visit(self.inner),
+        };
+        match self.repair.repair(expected, found.clone()) {
+            Some(repaired) => repaired.visit(self.inner),
+            None => Err(E::invalid_type(found.unexpected(), &self.inner)),
+        }
+    }
+}
+
+macro_rules! visit_scalar {
+    ($($method:ident: $ty:ty => $kind:expr, $scalar:expr;)*) => {$(
+        fn $method<E>(self, v: $ty) -> Result<Self::Value, E>
+        where
+            E: de::Error,
+        {
+            match self.accepts($kind) {
+                true => self.inner.$method(v),
+                false => self.repair($scalar(v)),
+            }
+        }
+    )*};
+}
+
+macro_rules! forward_visit {
+    ($($method:ident: $ty:ty)*) => {$(
+        fn $method<E>(self, v: $ty) -> Result<Self::Value, E>
+        where
+            E: de::Error,
+        {
+            self.inner.$method(v)
+        }
+    )*};
+}
+
+impl<'de, V> Visitor<'de> for RecoveringVisitor<V>
+where
+    V: Visitor<'de>,
+{
+    type Value = V::Value;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        self.inner.expecting(formatter)
+    }
+
+    visit_scalar! {
+        visit_bool: bool => Expected::Bool, Scalar::Bool;
+        visit_i8: i8 => Expected::Integer, |v| Scalar::I64(v as i64);
+        visit_i16: i16 => Expected::Integer, |v| Scalar::I64(v as i64);
+        visit_i32: i32 => Expected::Integer, |v| Scalar::I64(v as i64);
+        visit_i64: i64 => Expected::Integer, Scalar::I64;
+        visit_u8: u8 => Expected::Integer, |v| Scalar::U64(v as u64);
+        visit_u16: u16 => Expected::Integer, |v| Scalar::U64(v as u64);
+        visit_u32: u32 => Expected::Integer, |v| Scalar::U64(v as u64);
+        visit_u64: u64 => Expected::Integer, Scalar::U64;
+        visit_f32: f32 => Expected::Float, |v| Scalar::F64(v as f64);
+        visit_f64: f64 => Expected::Float, Scalar::F64;
+        visit_str: &str => Expected::String, |v: &str| Scalar::String(v.to_string());
+        visit_borrowed_str: &'de str => Expected::String, |v: &str| Scalar::String(v.to_string());
+        visit_string: String => Expected::String, Scalar::String;
+    }
+
+    forward_visit! {
+        visit_i128: i128 visit_u128: u128 visit_char: char
+        visit_bytes: &[u8] visit_borrowed_bytes: &'de [u8] visit_byte_buf: Vec<u8>
+    }
+
+    fn visit_none<E>(self) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        self.inner.visit_none()
+    }
+
+    fn visit_unit<E>(self) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        self.inner.visit_unit()
+    }
+
+    fn visit_some<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let deserializer = self.wrap(deserializer);
+        self.inner.visit_some(deserializer)
+    }
+
+    fn visit_newtype_struct<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let deserializer = self.wrap(deserializer);
+        self.inner.visit_newtype_struct(deserializer)
+    }
+
+    fn visit_seq<A>(self, seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let repair = self.repair.clone();
+        self.inner.visit_seq(RecoveringSeq { inner: seq, repair })
+    }
+
+    fn visit_map<A>(self, map: A) -> Result<Self::Value, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let repair = self.repair.clone();
+        self.inner.visit_map(RecoveringMap { inner: map, repair })
+    }
+
+    fn visit_enum<A>(self, data: A) -> Result<Self::Value, A::Error>
+    where
+        A: EnumAccess<'de>,
+    {
+        let repair = self.repair.clone();
+        self.inner.visit_enum(RecoveringEnum {
+            inner: data,
+            repair,
+        })
+    }
+}
+
+struct RecoveringSeq<A> {
+    inner: A,
+    repair: Arc<dyn Repair>,
+}
+
+impl<'de, A> SeqAccess<'de> for RecoveringSeq<A>
+where
+    A: SeqAccess<'de>,
+{
+    type Error = A::Error;
+
+    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>, Self::Error>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        self.inner.next_element_seed(RecoveringSeed {
+            inner: seed,
+            repair: self.repair.clone(),
+        })
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        self.inner.size_hint()
+    }
+}
+
+struct RecoveringMap<A> {
+    inner: A,
+    repair: Arc<dyn Repair>,
+}
+
+impl<'de, A> MapAccess<'de> for RecoveringMap<A>
+where
+    A: MapAccess<'de>,
+{
+    type Error = A::Error;
+
+    fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>, Self::Error>
+    where
+        K: DeserializeSeed<'de>,
+    {
+        self.inner.next_key_seed(RecoveringSeed {
+            inner: seed,
+            repair: self.repair.clone(),
+        })
+    }
+
+    fn next_value_seed<T>(&mut self, seed: T) -> Result<T::Value, Self::Error>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        self.inner.next_value_seed(RecoveringSeed {
+            inner: seed,
+            repair: self.repair.clone(),
+        })
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        self.inner.size_hint()
+    }
+}
+
+struct RecoveringEnum<A> {
+    inner: A,
+    repair: Arc<dyn Repair>,
+}
+
+impl<'de, A> EnumAccess<'de> for RecoveringEnum<A>
+where
+    A: EnumAccess<'de>,
+{
+    type Error = A::Error;
+    type Variant = RecoveringVariant<A::Variant>;
+
+    fn variant_seed<V>(self, seed: V) -> Result<(V::Value, Self::Variant), Self::Error>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        let repair = self.repair;
+        let (value, variant) = self.inner.variant_seed(RecoveringSeed {
+            inner: seed,
+            repair: repair.clone(),
+        })?;
+        Ok((
+            value,
+            RecoveringVariant {
+                inner: variant,
+                repair,
+            },
+        ))
+    }
+}
+
+struct RecoveringVariant<A> {
+    inner: A,
+    repair: Arc<dyn Repair>,
+}
+
+impl<A> RecoveringVariant<A> {
+    fn visit<V>(&self, visitor: V) -> RecoveringVisitor<V> {
+        RecoveringVisitor {
+            inner: visitor,
+            repair: self.repair.clone(),
+            expected: None,
+        }
+    }
+}
+
+impl<'de, A> VariantAccess<'de> for RecoveringVariant<A>
+where
+    A: VariantAccess<'de>,
+{
+    type Error = A::Error;
+
+    fn unit_variant(self) -> Result<(), Self::Error> {
+        self.inner.unit_variant()
+    }
+
+    fn newtype_variant_seed<T>(self, seed: T) -> Result<T::Value, Self::Error>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        self.inner.newtype_variant_seed(RecoveringSeed {
+            inner: seed,
+            repair: self.repair,
+        })
+    }
+
+    fn tuple_variant<V>(self, len: usize, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        let visitor = self.visit(visitor);
+        self.inner.tuple_variant(len, visitor)
+    }
+
+    fn struct_variant<V>(
+        self,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        let visitor = self.visit(visitor);
+        self.inner.struct_variant(fields, visitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::de::value::{Error, MapDeserializer};
+    use serde::de::IntoDeserializer;
+    use serde::Deserialize;
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Resize {
+        width: u32,
+        scale: f64,
+        keep_ratio: bool,
+        label: String,
+    }
+
+    fn parse<'de, T, D>(deserializer: D, repair: impl Repair) -> Result<T, Error>
+    where
+        T: Deserialize<'de>,
+        D: Deserializer<'de, Error = Error>,
+    {
+        T::deserialize(Recovering::new(deserializer, Arc::new(repair)))
+    }
+
+    #[test]
+    fn lenient_coerces_scalars() {
+        let value: u32 = parse(" 5".into_deserializer(), Lenient).unwrap();
+        assert_eq!(value, 5);
+        let value: i8 = parse("-3".into_deserializer(), Lenient).unwrap();
+        assert_eq!(value, -3);
+        let value: String = parse(7u64.into_deserializer(), Lenient).unwrap();
+        assert_eq!(value, "7");
+        // the repaired value is still checked against the type
+        assert!(parse::<u8, _>("300".into_deserializer(), Lenient).is_err());
+        assert!(parse::<u32, _>("five".into_deserializer(), Lenient).is_err());
+    }
+
+    #[test]
+    fn lenient_coerces_nested_fields() {
+        let fields = vec![
+            ("width", "640"),
+            ("scale", "1.5"),
+            ("keep_ratio", "true"),
+            ("label", "640"),
+        ];
+        let value: Resize = parse(MapDeserializer::new(fields.into_iter()), Lenient).unwrap();
+        assert_eq!(
+            value,
+            Resize {
+                width: 640,
+                scale: 1.5,
+                keep_ratio: true,
+                label: "640".into(),
+            }
+        );
+    }
+
+    #[test]
+    fn custom_repair() {
+        let empty_is_zero = |expected, found| match (expected, found) {
+            (Expected::Integer, Scalar::String(s)) if s.is_empty() => Some(Scalar::U64(0)),
+            _ => None,
+        };
+        let value: u32 = parse("".into_deserializer(), empty_is_zero).unwrap();
+        assert_eq!(value, 0);
+        assert!(parse::<u32, _>("5".into_deserializer(), empty_is_zero).is_err());
+        // values of the expected type are not passed to the hook
+        let value: u32 = parse(5u32.into_deserializer(), empty_is_zero).unwrap();
+        assert_eq!(value, 5);
+    }
+}
@@ -0,0 +1,693 @@
+//! Schema evolution tolerance of request bodies
+//!
+//! During a rolling upgrade, the server receives the arguments of a method from
+//! clients built against both the old and the new version of the argument struct.
+//! How strictly the fields of the structs are checked is normally decided by the
+//! `serde` attributes of the structs and the codec. A `BodyPolicy` set with
+//! `ServerBuilder::body_policy` overrides this for a service or a single method:
+//!
+//! - `UnknownFields::Deny` rejects fields that the struct does not declare (eg. sent
+//!   by a newer client), whereas `UnknownFields::Ignore` skips them.
+//! - `MissingFields::Deny` requires every declared field to be present (eg. not sent
+//!   by an older client), whereas `MissingFields::Default` lets `Option` fields and
+//!   fields with `#[serde(default)]` fall back to their defaults.
+//!
+//! The policies apply to the structs nested in the arguments as well. A request
+//! violating the policy fails with `Error::ParseError`.
+//!
+//! Only codecs that encode structs as maps with field names (eg. `serde_json`) can
+//! tell the fields apart. With codecs that encode structs as sequences (eg. `bincode`
+//! and the default of `rmp-serde`) the policies have no effect. Structs inside
+//! untagged or internally tagged enums and flattened fields are buffered by `serde`
+//! before they are deserialized and are not checked either.
+//!
+//! # Example
+//!
+//! ```no_run
+//! # #[cfg(any(feature = "async_std_runtime", feature = "tokio_runtime"))]
+//! # {
+//! use toy_rpc::server::schema::{BodyPolicy, MissingFields, UnknownFields};
+//! # use std::sync::Arc;
+//! # use toy_rpc::Server;
+//! # use toy_rpc::macros::export_impl;
+//! # struct Example;
+//! # #[export_impl]
+//! # impl Example {
+//! #     #[export_method]
+//! #     async fn echo(&self, args: String) -> Result<String, String> {
+//! #         Ok(args)
+//! #     }
+//! # }
+//! # let example_service = Arc::new(Example);
+//!
+//! let server = Server::builder()
+//!     .register(example_service)
+//!     // accept arguments of both old and new clients on every method of `Example`
+//!     .body_policy("Example", BodyPolicy::tolerant())
+//!     // except on `Example.transfer`, which must receive exactly the current struct
+//!     .body_policy("Example.transfer", BodyPolicy::strict())
+//!     .build();
+//! # }
+//! ```
+
+use erased_serde as erased;
+use serde::de::{
+    self, DeserializeSeed, Deserializer, EnumAccess, IntoDeserializer, MapAccess, SeqAccess,
+    VariantAccess, Visitor,
+};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+use crate::service::{ArcAsyncServiceCall, AsyncServiceMap, HandlerResultFut};
+
+/// How fields that are not declared by the argument struct are handled
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum UnknownFields {
+    /// Skip the unknown fields
+    #[default]
+    Ignore,
+    /// Reject a request with an unknown field
+    Deny,
+}
+
+/// How fields that are declared by the argument struct but missing in the request
+/// are handled
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MissingFields {
+    /// Use `None` for a missing `Option` field and the default of a field with
+    /// `#[serde(default)]`. Other missing fields are still rejected.
+    #[default]
+    Default,
+    /// Reject a request with any missing field
+    Deny,
+}
+
+/// Deserialization policy of the arguments of a method
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BodyPolicy {
+    /// Handling of fields that are not declared by the struct
+    pub unknown_fields: UnknownFields,
+    /// Handling of declared fields that are missing
+    pub missing_fields: MissingFields,
+}
+
+impl BodyPolicy {
+    /// Ignores unknown fields and uses the defaults of missing fields
+    pub fn tolerant() -> Self {
+        Self::default()
+    }
+
+    /// Rejects both unknown and missing fields
+    pub fn strict() -> Self {
+        Self {
+            unknown_fields: UnknownFields::Deny,
+            missing_fields: MissingFields::Deny,
+        }
+    }
+}
+
+/// Policies of a service, keyed by method name. The policy under `None` applies to
+/// the methods without one of their own.
+type ServicePolicies = HashMap<Option<String>, BodyPolicy>;
+
+/// Wraps the services with the policies keyed by `"Service"` or `"Service.method"`
+pub(crate) fn apply(services: &mut AsyncServiceMap, policies: &HashMap<String, BodyPolicy>) {
+    let mut by_service: HashMap<&str, ServicePolicies> = HashMap::new();
+    for (name, policy) in policies {
+        let (service, method) = match name.find('.') {
+            Some(pos) => (&name[..pos], Some(name[pos + 1..].to_string())),
+            None => (&name[..], None),
+        };
+        by_service
+            .entry(service)
+            .or_default()
+            .insert(method, *policy);
+    }
+
+    for (service, policies) in by_service {
+        let call = match services.get_mut(service) {
+            Some(call) => call,
+            None => {
+                warn!("Body policy is set on unknown service {}", service);
+                continue;
+            }
+        };
+        let inner = call.clone();
+        *call = with_policies(inner, policies);
+    }
+}
+
+fn with_policies(inner: ArcAsyncServiceCall, policies: ServicePolicies) -> ArcAsyncServiceCall {
+    let call = move |method_name: String,
+                     deserializer: Box<dyn erased::Deserializer<'static> + Send>|
+          -> HandlerResultFut {
+        let policy = policies
+            .get(&Some(method_name.clone()))
+            .or_else(|| policies.get(&None));
+        match policy {
+            Some(policy) => {
+                let deserializer =
+                    <dyn erased::Deserializer>::erase(Checked::new(deserializer, *policy));
+                inner(method_name, Box::new(deserializer))
+            }
+            None => inner(method_name, deserializer),
+        }
+    };
+    Arc::new(call)
+}
+
+/// Deserializer that checks the structs it deserializes against a `BodyPolicy`
+struct Checked<D> {
+    inner: D,
+    policy: BodyPolicy,
+}
+
+impl<D> Checked<D> {
+    fn new(inner: D, policy: BodyPolicy) -> Self {
+        Self { inner, policy }
+    }
+
+    fn visit<V>(&self, visitor: V) -> CheckedVisitor<V> {
+        CheckedVisitor {
+            inner: visitor,
+            policy: self.policy,
+            fields: None,
+        }
+    }
+}
+
+macro_rules! forward_deserialize {
+    ($($method:ident)*) => {$(
+        fn $method<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+        where
+            V: Visitor<'de>,
+        {
+            let visitor = self.visit(visitor);
+            self.inner.$method(visitor)
+        }
+    )*};
+}
+
+impl<'de, D> Deserializer<'de> for Checked<D>
+where
+    D: Deserializer<'de>,
+{
+    type Error = D::Error;
+
+    forward_deserialize! {
+        deserialize_any deserialize_bool
+        deserialize_i8 deserialize_i16 deserialize_i32 deserialize_i64 deserialize_i128
+        deserialize_u8 deserialize_u16 deserialize_u32 deserialize_u64 deserialize_u128
+        deserialize_f32 deserialize_f64 deserialize_char deserialize_str deserialize_string
+        deserialize_bytes deserialize_byte_buf deserialize_option deserialize_unit
+        deserialize_seq deserialize_map deserialize_identifier deserialize_ignored_any
+    }
+
+    fn deserialize_unit_struct<V>(
+        self,
+        name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        let visitor = self.visit(visitor);
+        self.inner.deserialize_unit_struct(name, visitor)
+    }
+
+    fn deserialize_newtype_struct<V>(
+        self,
+        name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        let visitor = self.visit(visitor);
+        self.inner.deserialize_newtype_struct(name, visitor)
+    }
+
+    fn deserialize_tuple<V>(self, len: usize, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        let visitor = self.visit(visitor);
+        self.inner.deserialize_tuple(len, visitor)
+    }
+
+    fn deserialize_tuple_struct<V>(
+        self,
+        name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        let visitor = self.visit(visitor);
+        self.inner.deserialize_tuple_struct(name, len, visitor)
+    }
+
+    fn deserialize_struct<V>(
+        self,
+        name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        let mut visitor = self.visit(visitor);
+        visitor.fields = Some(fields);
+        self.inner.deserialize_struct(name, fields, visitor)
+    }
+
+    fn deserialize_enum<V>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        let visitor = self.visit(visitor);
+        self.inner.deserialize_enum(name, variants, visitor)
+    }
+
+    fn is_human_readable(&self) -> bool {
+        self.inner.is_human_readable()
+    }
+}
+
+/// Seed that deserializes with a `Checked` deserializer
+struct CheckedSeed<S> {
+    inner: S,
+    policy: BodyPolicy,
+}
+
+impl<'de, S> DeserializeSeed<'de> for CheckedSeed<S>
+where
+    S: DeserializeSeed<'de>,
+{
+    type Value = S::Value;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        self.inner
+            .deserialize(Checked::new(deserializer, self.policy))
+    }
+}
+
+/// Visitor that passes the policy on to the nested values. `fields` is set when a
+/// struct is visited.
+struct CheckedVisitor<V> {
+    inner: V,
+    policy: BodyPolicy,
+    fields: Option<&'static [&'static str]>,
+}
+
+macro_rules! forward_visit {
+    ($($method:ident: $ty:ty)*) => {$(
+        fn $method<E>(self, v: $ty) -> Result<Self::Value, E>
+        where
+            E: de::Error,
+        {
+            self.inner.$method(v)
+        }
+    )*};
+}
+
+impl<'de, V> Visitor<'de> for CheckedVisitor<V>
+where
+    V: Visitor<'de>,
+{
+    type Value = V::Value;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        self.inner.expecting(formatter)
+    }
+
+    forward_visit! {
+        visit_bool: bool
+        visit_i8: i8 visit_i16: i16 visit_i32: i32 visit_i64: i64 visit_i128: i128
+        visit_u8: u8 visit_u16: u16 visit_u32: u32 visit_u64: u64 visit_u128: u128
+        visit_f32: f32 visit_f64: f64 visit_char: char
+        visit_str: &str visit_borrowed_str: &'de str visit_string: String
+        visit_bytes: &[u8] visit_borrowed_bytes: &'de [u8] visit_byte_buf: Vec<u8>
+    }
+
+    fn visit_none<E>(self) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        self.inner.visit_none()
+    }
+
+    fn visit_unit<E>(self) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        self.inner.visit_unit()
+    }
+
+    fn visit_some<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        self.inner
+            .visit_some(Checked::new(deserializer, self.policy))
+    }
+
+    fn visit_newtype_struct<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        self.inner
+            .visit_newtype_struct(Checked::new(deserializer, self.policy))
+    }
+
+    fn visit_seq<A>(self, seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        self.inner.visit_seq(CheckedSeq {
+            inner: seq,
+            policy: self.policy,
+        })
+    }
+
+    fn visit_map<A>(self, map: A) -> Result<Self::Value, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let seen = self.fields.map(|fields| vec![false; fields.len()]);
+        self.inner.visit_map(CheckedMap {
+            inner: map,
+            policy: self.policy,
+            fields: self.fields,
+            seen,
+        })
+    }
+
+    fn visit_enum<A>(self, data: A) -> Result<Self::Value, A::Error>
+    where
+        A: EnumAccess<'de>,
+    {
+        self.inner.visit_enum(CheckedEnum {
+            inner: data,
+            policy: self.policy,
+        })
+    }
+}
+
+struct CheckedSeq<A> {
+    inner: A,
+    policy: BodyPolicy,
+}
+
+impl<'de, A> SeqAccess<'de> for CheckedSeq<A>
+where
+    A: SeqAccess<'de>,
+{
+    type Error = A::Error;
+
+    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>, Self::Error>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        self.inner.next_element_seed(CheckedSeed {
+            inner: seed,
+            policy: self.policy,
+        })
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        self.inner.size_hint()
+    }
+}
+
+/// Map of a struct if `fields` is set, otherwise a map of any keys
+struct CheckedMap<A> {
+    inner: A,
+    policy: BodyPolicy,
+    fields: Option<&'static [&'static str]>,
+    // whether each field has been seen
+    seen: Option<Vec<bool>>,
+}
+
+/// Field name of a struct, which may also be encoded as the index of the field
+enum FieldKey {
+    Name(String),
+    Bytes(Vec<u8>),
+    Index(u64),
+}
+
+impl FieldKey {
+    fn position(&self, fields: &[&str]) -> Option<usize> {
+        match self {
+            FieldKey::Name(name) => fields.iter().position(|field| field == name),
+            FieldKey::Bytes(bytes) => fields
+                .iter()
+                .position(|field| field.as_bytes() == &bytes[..]),
+            FieldKey::Index(index) => Some(*index as usize).filter(|index| *index < fields.len()),
+        }
+    }
+}
+
+impl fmt::Display for FieldKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FieldKey::Name(name) => write!(f, "{}", name),
+            FieldKey::Bytes(bytes) => write!(f, "{}", String::from_utf8_lossy(bytes)),
+            FieldKey::Index(index) => write!(f, "{}", index),
+        }
+    }
+}
+
+impl<'de> de::Deserialize<'de> for FieldKey {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct FieldKeyVisitor;
+
+        impl<'de> Visitor<'de> for FieldKeyVisitor {
+            type Value = FieldKey;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a field identifier")
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<FieldKey, E> {
+                Ok(FieldKey::Name(v.to_string()))
+            }
+
+            fn visit_string<E: de::Error>(self, v: String) -> Result<FieldKey, E> {
+                Ok(FieldKey::Name(v))
+            }
+
+            fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<FieldKey, E> {
+                Ok(FieldKey::Bytes(v.to_vec()))
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<FieldKey, E> {
+                Ok(FieldKey::Index(v))
+            }
+        }
+
+        deserializer.deserialize_identifier(FieldKeyVisitor)
+    }
+}
+
+impl<'de, A> MapAccess<'de> for CheckedMap<A>
+where
+    A: MapAccess<'de>,
+{
+    type Error = A::Error;
+
+    fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>, Self::Error>
+    where
+        K: DeserializeSeed<'de>,
+    {
+        let (fields, seen) = match (self.fields, &mut self.seen) {
+            (Some(fields), Some(seen)) => (fields, seen),
+            _ => {
+                return self.inner.next_key_seed(CheckedSeed {
+                    inner: seed,
+                    policy: self.policy,
+                })
+            }
+        };
+
+        let key: FieldKey = match self.inner.next_key()? {
+            Some(key) => key,
+            None => {
+                if self.policy.missing_fields == MissingFields::Deny {
+                    if let Some(pos) = seen.iter().position(|seen| !seen) {
+                        return Err(de::Error::missing_field(fields[pos]));
+                    }
+                }
+                return Ok(None);
+            }
+        };
+        match key.position(fields) {
+            Some(pos) => seen[pos] = true,
+            None if self.policy.unknown_fields == UnknownFields::Deny => {
+                return Err(de::Error::unknown_field(&key.to_string(), fields));
+            }
+            None => {}
+        }
+
+        // the key is handed over to the struct as it was received
+        match key {
+            FieldKey::Name(name) => seed.deserialize(name.into_deserializer()).map(Some),
+            FieldKey::Bytes(bytes) => seed
+                .deserialize(de::value::BytesDeserializer::new(&bytes))
+                .map(Some),
+            FieldKey::Index(index) => seed.deserialize(index.into_deserializer()).map(Some),
+        }
+    }
+
+    fn next_value_seed<T>(&mut self, seed: T) -> Result<T::Value, Self::Error>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        self.inner.next_value_seed(CheckedSeed {
+            inner: seed,
+            policy: self.policy,
+        })
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        self.inner.size_hint()
+    }
+}
+
+struct CheckedEnum<A> {
+    inner: A,
+    policy: BodyPolicy,
+}
+
+impl<'de, A> EnumAccess<'de> for CheckedEnum<A>
+where
+    A: EnumAccess<'de>,
+{
+    type Error = A::Error;
+    type Variant = CheckedVariant<A::Variant>;
+
+    fn variant_seed<V>(self, seed: V) -> Result<(V::Value, Self::Variant), Self::Error>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        let policy = self.policy;
+        let (value, variant) = self.inner.variant_seed(seed)?;
+        Ok((
+            value,
+            CheckedVariant {
+                inner: variant,
+                policy,
+            },
+        ))
+    }
+}
+
+struct CheckedVariant<A> {
+    inner: A,
+    policy: BodyPolicy,
+}
+
+impl<'de, A> VariantAccess<'de> for CheckedVariant<A>
+where
+    A: VariantAccess<'de>,
+{
+    type Error = A::Error;
+
+    fn unit_variant(self) -> Result<(), Self::Error> {
+        self.inner.unit_variant()
+    }
+
+    fn newtype_variant_seed<T>(self, seed: T) -> Result<T::Value, Self::Error>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        self.inner.newtype_variant_seed(CheckedSeed {
+            inner: seed,
+            policy: self.policy,
+        })
+    }
+
+    fn tuple_variant<V>(self, len: usize, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        let visitor = CheckedVisitor {
+            inner: visitor,
+            policy: self.policy,
+            fields: None,
+        };
+        self.inner.tuple_variant(len, visitor)
+    }
+
+    fn struct_variant<V>(
+        self,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        let visitor = CheckedVisitor {
+            inner: visitor,
+            policy: self.policy,
+            fields: Some(fields),
+        };
+        self.inner.struct_variant(fields, visitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::de::value::{Error, MapDeserializer};
+    use serde::Deserialize;
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Args {
+        name: String,
+        #[serde(default)]
+        nickname: String,
+    }
+
+    fn parse(entries: &[(&'static str, &'static str)], policy: BodyPolicy) -> Result<Args, Error> {
+        let map = MapDeserializer::new(entries.iter().copied());
+        Args::deserialize(Checked::new(map, policy))
+    }
+
+    #[test]
+    fn body_policies() {
+        let current = [("name", "a"), ("nickname", "b")];
+        let newer = [("name", "a"), ("nickname", "b"), ("age", "1")];
+        let older = [("name", "a")];
+
+        let tolerant = BodyPolicy::tolerant();
+        assert!(parse(&current, tolerant).is_ok());
+        assert!(parse(&newer, tolerant).is_ok());
+        assert_eq!(parse(&older, tolerant).unwrap().nickname, "");
+
+        let strict = BodyPolicy::strict();
+        assert!(parse(&current, strict).is_ok());
+        assert!(parse(&newer, strict).is_err());
+        assert!(parse(&older, strict).is_err());
+
+        let newer_only = BodyPolicy {
+            unknown_fields: UnknownFields::Ignore,
+            missing_fields: MissingFields::Deny,
+        };
+        assert!(parse(&newer, newer_only).is_ok());
+        assert!(parse(&older, newer_only).is_err());
+    }
+}
@@ -0,0 +1,168 @@
+//! Structured shutdown of the server
+//!
+//! Every connection served by a `Server`, whether it is accepted by the server
+//! itself or handed over by the `actix-web`, `tide` or `warp` integration, is
+//! registered as a session. `ShutdownHandle::shutdown` stops the sessions from
+//! starting new calls, lets the calls in flight finish within a grace period, and
+//! then closes the connections. WebSocket connections are closed with the close
+//! code `GOING_AWAY` so that the clients can tell a shutdown from a dropped
+//! connection. Calls that are still running when the grace period is over are
+//! canceled.
+//!
+//! When the HTTP server shuts down, the RPC sessions should be shut down first,
+//! eg. with `warp`
+//!
+//! ```no_run
+//! # #[cfg(feature = "http_warp")]
+//! # {
+//! # use std::sync::Arc;
+//! # use toy_rpc::Server;
+//! # use toy_rpc::macros::export_impl;
+//! # struct Foo;
+//! # #[export_impl]
+//! # impl Foo {
+//! #     #[export_method]
+//! #     async fn echo(&self, args: String) -> Result<String, String> {
+//! #         Ok(args)
+//! #     }
+//! # }
+//! # use std::time::Duration;
+//! # use warp::Filter;
+//! # async fn run() {
+//! # let foo_service = Arc::new(Foo);
+//! let server = Server::builder()
+//!     .register(foo_service)
+//!     .build();
+//! let shutdown = server.shutdown_handle();
+//! let routes = warp::path("rpc")
+//!     .and(server.handle_http());
+//! let (_, serving) = warp::serve(routes)
+//!     .bind_with_graceful_shutdown(([127, 0, 0, 1], 8080), async move {
+//!         tokio::signal::ctrl_c().await.ok();
+//!         shutdown.shutdown(Duration::from_secs(10)).await;
+//!     });
+//! serving.await;
+//! # }
+//! # }
+//! ```
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use flume::{Receiver, Sender};
+
+use super::ClientId;
+
+/// WebSocket close code sent to the clients when the server shuts down
+pub const GOING_AWAY: u16 = 1001;
+
+/// WebSocket close reason sent to the clients when the server shuts down
+pub const SHUTDOWN_REASON: &str = "Server is shutting down";
+
+type ShutdownFn = Box<dyn Fn(Duration) + Send + 'static>;
+
+struct Session {
+    shutdown: ShutdownFn,
+    // disconnected when the session ends
+    closed: Receiver<()>,
+}
+
+#[derive(Default)]
+struct Registry {
+    grace: Option<Duration>,
+    sessions: HashMap<ClientId, Session>,
+}
+
+/// Handle to shut down all the sessions of a `Server`
+///
+/// The handle is obtained with `Server::shutdown_handle` and can be cloned.
+#[derive(Clone, Default)]
+pub struct ShutdownHandle {
+    registry: Arc<Mutex<Registry>>,
+}
+
+impl ShutdownHandle {
+    fn lock(&self) -> MutexGuard<'_, Registry> {
+        match self.registry.lock() {
+            Ok(registry) => registry,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    /// Registers a session. `shutdown` is called with the grace period when the
+    /// server shuts down, right away if it is already shutting down. The session is
+    /// unregistered when the returned guard is dropped.
+    pub(crate) fn register(
+        &self,
+        client_id: ClientId,
+        shutdown: impl Fn(Duration) + Send + 'static,
+    ) -> SessionGuard {
+        let (tx, rx) = flume::bounded(1);
+        let mut registry = self.lock();
+        match registry.grace {
+            Some(grace) => shutdown(grace),
+            None => {
+                let session = Session {
+                    shutdown: Box::new(shutdown),
+                    closed: rx,
+                };
+                registry.sessions.insert(client_id, session);
+            }
+        }
+        SessionGuard {
+            handle: self.clone(),
+            client_id,
+            _closed: tx,
+        }
+    }
+
+    /// Returns the number of open sessions that are not being shut down
+    pub fn active_sessions(&self) -> usize {
+        self.lock().sessions.len()
+    }
+
+    /// Returns whether `shutdown` has been called
+    pub fn is_shutting_down(&self) -> bool {
+        self.lock().grace.is_some()
+    }
+
+    /// Shuts down all the sessions and waits until they are closed
+    ///
+    /// New calls are rejected with an `ExecutionError`, and each session is closed
+    /// as soon as its calls in flight are finished, or when `grace` is over. Sessions
+    /// that are opened afterwards are closed right away.
+    pub async fn shutdown(&self, grace: Duration) {
+        let sessions: Vec<Session> = {
+            let mut registry = self.lock();
+            registry.grace = Some(grace);
+            registry
+                .sessions
+                .drain()
+                .map(|(_, session)| session)
+                .collect()
+        };
+        info!("Shutting down {} sessions", sessions.len());
+
+        for session in &sessions {
+            (session.shutdown)(grace);
+        }
+        for session in sessions {
+            // returns an error once the session is closed
+            let _ = session.closed.recv_async().await;
+        }
+    }
+}
+
+/// Keeps a session registered until it is dropped
+pub(crate) struct SessionGuard {
+    handle: ShutdownHandle,
+    client_id: ClientId,
+    _closed: Sender<()>,
+}
+
+impl Drop for SessionGuard {
+    fn drop(&mut self) {
+        self.handle.lock().sessions.remove(&self.client_id);
+    }
+}
@@ -0,0 +1,58 @@
+//! History of the pubsub topics kept in an external store
+//!
+//! The `PubSubBroker` only retains the last messages of a topic allowed by its
+//! `Retention`. A `TopicSource` serves a longer history of a topic from an external
+//! store, such as a Kafka topic or a Postgres table, to the subscribers that ask to
+//! replay more messages than the broker retains (see `Client::subscriber_with_replay`).
+//! The broker only reads from the source, and the store is expected to be written
+//! by whatever produces the events, eg. a server side `Subscriber` of the topic.
+//!
+//! # Example
+//!
+//! ```no_run
+//! # use async_trait::async_trait;
+//! # use toy_rpc::{Error, Server};
+//! # use toy_rpc::server::{pubsub::Retention, source::TopicSource};
+//! # struct PgPool;
+//! # struct Order;
+//! # impl toy_rpc::pubsub::Topic for Order {
+//! #     type Item = String;
+//! #     fn topic() -> String {
+//! #         "order".into()
+//! #     }
+//! # }
+//! struct Events { pool: PgPool }
+//!
+//! #[async_trait]
+//! impl TopicSource for Events {
+//!     async fn read_last(&self, topic: &str, count: usize) -> Result<Vec<Vec<u8>>, Error> {
+//!         // SELECT content FROM events WHERE topic = $1 ORDER BY seq DESC LIMIT $2
+//! #         unimplemented!()
+//!     }
+//! }
+//!
+//! # let pool = PgPool;
+//! let server = Server::builder()
+//!     .topic_retention::<Order>(Retention::LastN(100))
+//!     .topic_source::<Order>(Events { pool })
+//!     .build();
+//! ```
+
+use async_trait::async_trait;
+
+use crate::error::Error;
+
+/// External store of the history of a topic
+///
+/// The source is read from within the `PubSubBroker` loop, which waits for the
+/// history before handling the next item, so an implementation should not take
+/// too long to answer.
+#[async_trait]
+pub trait TopicSource: Send + Sync + 'static {
+    /// Reads the last `count` messages published to `topic`, from the oldest to the
+    /// newest. Fewer messages are returned if the history is shorter.
+    ///
+    /// A message is the content of a publication, that is the item serialized with
+    /// the codec of the server.
+    async fn read_last(&self, topic: &str, count: usize) -> Result<Vec<Vec<u8>>, Error>;
+}
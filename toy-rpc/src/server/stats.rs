@@ -0,0 +1,417 @@
+//! Statistics of the traffic of each connection
+//!
+//! The server counts the messages, the bytes and the codec errors of every open
+//! connection, which can be read with `Server::connection_stats`, so that the
+//! clients sending malformed or abnormally large traffic can be identified. The
+//! connections are keyed by their client ID, which is also the `client_id` of the
+//! `AuditRecord`s of their calls. A connection is removed once it is closed.
+//!
+//! - The calls are the requests of methods read, whether or not the method exists.
+//! - The bodies read are the arguments of the requests, the items of the streaming
+//!   arguments and the contents of the publications.
+//! - The bodies written are the responses, the items of the streaming responses and
+//!   the publications.
+//! - An unmarshal error is a header that cannot be deserialized, or a request whose
+//!   argument is rejected with `Error::InvalidArgument`.
+//! - A marshal error is a response that cannot be serialized.
+//! - The traffic is counted in frames and bytes on the wire by the transport, see
+//!   the `transport::bandwidth` module. Unlike the bodies, it also covers the
+//!   headers of the messages and of the frames, and the compression.
+//!
+//! Only the calls of the connections handed over by the `actix-web` integration are
+//! counted.
+//! See the `lifecycle` module for the callbacks on the connections.
+//!
+//! # Example
+//!
+//! ```no_run
+//! # use toy_rpc::Server;
+//! # fn run(server: Server) {
+//! for (client_id, stats) in server.connection_stats() {
+//!     if stats.unmarshal_errors > 0 || stats.average_body_read() > 1024 * 1024 {
+//!         log::warn!("Client {}: {:?}", client_id, stats);
+//!     }
+//! }
+//! # }
+//! ```
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Instant;
+
+use super::flow::{FlowControl, OutboundQueue};
+use super::lifecycle::{ConnectionInfo, LifecycleHooks};
+#[cfg(feature = "metrics")]
+use super::metrics::MetricsRegistry;
+use super::ClientId;
+use crate::clock;
+#[cfg(feature = "signing")]
+use crate::signing::ResponseSigner;
+use crate::timing::TimingPolicy;
+use crate::transport::bandwidth::{BandwidthLimit, TrafficMeter, TrafficStats};
+
+/// Numbers of messages, bytes and codec errors of a connection
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ConnectionStats {
+    /// Number of calls
+    pub calls: u64,
+    /// Number of messages read
+    pub messages_read: u64,
+    /// Number of messages written
+    pub messages_written: u64,
+    /// Number of bodies read that count towards `body_bytes_read`
+    pub bodies_read: u64,
+    /// Total size of the bodies read
+    pub body_bytes_read: u64,
+    /// Number of bodies written that count towards `body_bytes_written`
+    pub bodies_written: u64,
+    /// Total size of the bodies written
+    pub body_bytes_written: u64,
+    /// Number of headers and arguments that could not be deserialized
+    pub unmarshal_errors: u64,
+    /// Number of responses that could not be serialized
+    pub marshal_errors: u64,
+    /// Frames and bytes read and written by the transport
+    pub traffic: TrafficStats,
+}
+
+impl ConnectionStats {
+    /// Returns the average size of the bodies read, or `0` if none is read
+    pub fn average_body_read(&self) -> u64 {
+        self.body_bytes_read
+            .checked_div(self.bodies_read)
+            .unwrap_or_default()
+    }
+
+    /// Returns the average size of the bodies written, or `0` if none is written
+    pub fn average_body_written(&self) -> u64 {
+        self.body_bytes_written
+            .checked_div(self.bodies_written)
+            .unwrap_or_default()
+    }
+}
+
+/// Counters of a connection shared by its reader and writer
+#[derive(Debug, Default)]
+#[cfg_attr(feature = "http_actix_web", allow(dead_code))]
+pub(crate) struct ConnectionCounters {
+    calls: AtomicU64,
+    messages_read: AtomicU64,
+    messages_written: AtomicU64,
+    bodies_read: AtomicU64,
+    body_bytes_read: AtomicU64,
+    bodies_written: AtomicU64,
+    body_bytes_written: AtomicU64,
+    unmarshal_errors: AtomicU64,
+    marshal_errors: AtomicU64,
+}
+
+#[cfg_attr(feature = "http_actix_web", allow(dead_code))]
+impl ConnectionCounters {
+    pub fn call(&self) {
+        self.calls.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn message_read(&self) {
+        self.messages_read.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn message_written(&self) {
+        self.messages_written.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn body_read(&self, bytes: usize) {
+        self.bodies_read.fetch_add(1, Ordering::Relaxed);
+        self.body_bytes_read
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn body_written(&self, bytes: usize) {
+        self.bodies_written.fetch_add(1, Ordering::Relaxed);
+        self.body_bytes_written
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn unmarshal_error(&self) {
+        self.unmarshal_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn marshal_error(&self) {
+        self.marshal_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> ConnectionStats {
+        ConnectionStats {
+            calls: self.calls.load(Ordering::Relaxed),
+            messages_read: self.messages_read.load(Ordering::Relaxed),
+            messages_written: self.messages_written.load(Ordering::Relaxed),
+            bodies_read: self.bodies_read.load(Ordering::Relaxed),
+            body_bytes_read: self.body_bytes_read.load(Ordering::Relaxed),
+            bodies_written: self.bodies_written.load(Ordering::Relaxed),
+            body_bytes_written: self.body_bytes_written.load(Ordering::Relaxed),
+            unmarshal_errors: self.unmarshal_errors.load(Ordering::Relaxed),
+            marshal_errors: self.marshal_errors.load(Ordering::Relaxed),
+            traffic: TrafficStats::default(),
+        }
+    }
+}
+
+type Meters = (Arc<ConnectionCounters>, Arc<TrafficMeter>);
+
+/// Counters of the open connections of a server
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ConnectionRegistry {
+    connections: Arc<Mutex<HashMap<ClientId, Meters>>>,
+    flow_control: Option<FlowControl>,
+    outbound_bandwidth: Option<BandwidthLimit>,
+    inbound_bandwidth: Option<BandwidthLimit>,
+    #[cfg(feature = "signing")]
+    signer: Option<Arc<ResponseSigner>>,
+    hooks: LifecycleHooks,
+    timing: TimingPolicy,
+    #[cfg(feature = "metrics")]
+    metrics: Arc<MetricsRegistry>,
+    // address of the peer of the next connection registered
+    peer_addr: Option<SocketAddr>,
+}
+
+#[cfg_attr(feature = "http_actix_web", allow(dead_code))]
+impl ConnectionRegistry {
+    pub fn new(flow_control: Option<FlowControl>) -> Self {
+        Self {
+            connections: Default::default(),
+            flow_control,
+            outbound_bandwidth: None,
+            inbound_bandwidth: None,
+            #[cfg(feature = "signing")]
+            signer: None,
+            hooks: LifecycleHooks::default(),
+            timing: TimingPolicy::default(),
+            #[cfg(feature = "metrics")]
+            metrics: Default::default(),
+            peer_addr: None,
+        }
+    }
+
+    /// Calls the hooks when the connections are registered and unregistered
+    pub fn hooks(self, hooks: LifecycleHooks) -> Self {
+        let mut registry = self;
+        registry.hooks = hooks;
+        registry
+    }
+
+    /// Limits the phases of the calls on every connection
+    pub fn timing(self, timing: TimingPolicy) -> Self {
+        let mut registry = self;
+        registry.timing = timing;
+        registry
+    }
+
+    /// Sets the address of the peer of the connections registered with the
+    /// returned registry
+    pub fn peer(&self, peer_addr: impl Into<Option<SocketAddr>>) -> Self {
+        let mut registry = self.clone();
+        registry.peer_addr = peer_addr.into();
+        registry
+    }
+
+    /// Limits the bandwidth of every connection
+    pub fn bandwidth(
+        self,
+        outbound: Option<BandwidthLimit>,
+        inbound: Option<BandwidthLimit>,
+    ) -> Self {
+        let mut registry = self;
+        registry.outbound_bandwidth = outbound;
+        registry.inbound_bandwidth = inbound;
+        registry
+    }
+
+    /// Signs the responses written to every connection
+    #[cfg(feature = "signing")]
+    pub fn sign_responses(self, signer: Option<ResponseSigner>) -> Self {
+        let mut registry = self;
+        registry.signer = signer.map(Arc::new);
+        registry
+    }
+
+    /// The key that the responses are signed with
+    #[cfg(feature = "signing")]
+    pub fn signer(&self) -> Option<Arc<ResponseSigner>> {
+        self.signer.clone()
+    }
+
+    /// Records the calls of every connection in `metrics`
+    #[cfg(feature = "metrics")]
+    pub fn record_metrics(self, metrics: MetricsRegistry) -> Self {
+        let mut registry = self;
+        registry.metrics = Arc::new(metrics);
+        registry
+    }
+
+    /// The metrics that the calls are recorded in
+    #[cfg(feature = "metrics")]
+    pub fn metrics(&self) -> Arc<MetricsRegistry> {
+        self.metrics.clone()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<ClientId, Meters>> {
+        match self.connections.lock() {
+            Ok(connections) => connections,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    /// Registers a connection. The connection is unregistered when the returned
+    /// guard is dropped.
+    pub fn register(&self, client_id: ClientId) -> ConnectionGuard {
+        let counters = Arc::new(ConnectionCounters::default());
+        let meter = Arc::new(TrafficMeter::new(
+            self.outbound_bandwidth,
+            self.inbound_bandwidth,
+        ));
+        self.lock()
+            .insert(client_id, (counters.clone(), meter.clone()));
+        let guard = ConnectionGuard {
+            registry: self.clone(),
+            client_id,
+            counters,
+            meter,
+            outbound: Arc::new(OutboundQueue::new(self.flow_control)),
+            connected_at: clock::now(),
+        };
+        if let Some(on_connect) = &self.hooks.on_connect {
+            on_connect(&guard.info());
+        }
+        guard
+    }
+
+    pub fn snapshot(&self) -> HashMap<ClientId, ConnectionStats> {
+        self.lock()
+            .iter()
+            .map(|(client_id, (counters, meter))| {
+                let stats = ConnectionStats {
+                    traffic: meter.stats(),
+                    ..counters.snapshot()
+                };
+                (*client_id, stats)
+            })
+            .collect()
+    }
+}
+
+/// Keeps a connection registered until it is dropped
+#[cfg_attr(feature = "http_actix_web", allow(dead_code))]
+pub(crate) struct ConnectionGuard {
+    registry: ConnectionRegistry,
+    client_id: ClientId,
+    counters: Arc<ConnectionCounters>,
+    meter: Arc<TrafficMeter>,
+    outbound: Arc<OutboundQueue>,
+    connected_at: Instant,
+}
+
+#[cfg_attr(feature = "http_actix_web", allow(dead_code))]
+impl ConnectionGuard {
+    fn info(&self) -> ConnectionInfo {
+        ConnectionInfo {
+            client_id: self.client_id,
+            peer_addr: self.registry.peer_addr,
+            duration: clock::now().saturating_duration_since(self.connected_at),
+            calls: self.counters.calls.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn counters(&self) -> Arc<ConnectionCounters> {
+        self.counters.clone()
+    }
+
+    pub fn meter(&self) -> Arc<TrafficMeter> {
+        self.meter.clone()
+    }
+
+    pub fn outbound(&self) -> Arc<OutboundQueue> {
+        self.outbound.clone()
+    }
+
+    #[cfg(feature = "signing")]
+    pub fn signer(&self) -> Option<Arc<ResponseSigner>> {
+        self.registry.signer()
+    }
+
+    pub fn timing(&self) -> TimingPolicy {
+        self.registry.timing
+    }
+
+    #[cfg(feature = "metrics")]
+    pub fn metrics(&self) -> Arc<MetricsRegistry> {
+        self.registry.metrics()
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.registry.lock().remove(&self.client_id);
+        if let Some(on_disconnect) = &self.registry.hooks.on_disconnect {
+            on_disconnect(&self.info());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn connections_are_unregistered() {
+        let registry = ConnectionRegistry::default();
+        let guard = registry.register(1);
+        let counters = guard.counters();
+        counters.message_read();
+        counters.body_read(10);
+        counters.body_read(30);
+        counters.unmarshal_error();
+
+        let stats = registry.snapshot()[&1];
+        assert_eq!(stats.messages_read, 1);
+        assert_eq!(stats.average_body_read(), 20);
+        assert_eq!(stats.average_body_written(), 0);
+        assert_eq!(stats.unmarshal_errors, 1);
+
+        drop(guard);
+        assert!(registry.snapshot().is_empty());
+    }
+
+    #[test]
+    fn hooks_are_called() {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let on_connect = tx.clone();
+        let registry = ConnectionRegistry::default().hooks(LifecycleHooks {
+            on_connect: Some(Arc::new(move |info: &ConnectionInfo| {
+                on_connect.send(("connect", *info)).unwrap()
+            })),
+            on_disconnect: Some(Arc::new(move |info: &ConnectionInfo| {
+                tx.send(("disconnect", *info)).unwrap()
+            })),
+        });
+        let peer_addr: SocketAddr = "127.0.0.1:23333".parse().unwrap();
+        let guard = registry.peer(peer_addr).register(7);
+        let (event, info) = rx.try_recv().unwrap();
+        assert_eq!(event, "connect");
+        assert_eq!(info.client_id, 7);
+        assert_eq!(info.peer_addr, Some(peer_addr));
+        assert_eq!(info.calls, 0);
+
+        guard.counters().call();
+        guard.counters().call();
+        assert_eq!(registry.snapshot()[&7].calls, 2);
+        assert!(rx.try_recv().is_err());
+        drop(guard);
+        let (event, info) = rx.try_recv().unwrap();
+        assert_eq!(event, "disconnect");
+        assert_eq!(info.peer_addr, Some(peer_addr));
+        assert_eq!(info.calls, 2);
+    }
+}
@@ -0,0 +1,436 @@
+//! Storage of the `PubSubBroker` state
+//!
+//! The `PubSubBroker` keeps the topics and their retained messages in a
+//! `BrokerStore`, along with their dead letter topics and TTLs. The default `MemoryStore` keeps everything in memory, while
+//! `FileStore` appends every change to a log file so that the state survives
+//! a server restart.
+
+use std::collections::{HashMap, VecDeque};
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::error::Error;
+use crate::message::MessageId;
+
+use super::pubsub::Retention;
+
+/// A topic loaded from a `BrokerStore`
+#[derive(Debug, Clone, Default)]
+pub struct StoredTopic {
+    /// Retention policy of the topic
+    pub retention: Retention,
+    /// Retained messages from the oldest to the newest, with the time at which they
+    /// expire
+    pub retained: VecDeque<(MessageId, Vec<u8>, Option<SystemTime>)>,
+    /// Dead letter topic of the topic
+    pub dead_letter: Option<String>,
+    /// Default time-to-live of the messages published to the topic
+    pub ttl: Option<Duration>,
+}
+
+/// Storage backend of the `PubSubBroker`
+///
+/// All methods are called from within the `PubSubBroker` loop, so an implementation
+/// should avoid blocking for too long.
+pub trait BrokerStore: Send + 'static {
+    /// Loads all topics in the store. This is called once when the server is built.
+    fn load(&mut self) -> Result<HashMap<String, StoredTopic>, Error>;
+
+    /// Creates a topic or updates its retention policy
+    fn put_topic(&mut self, topic: &str, retention: Retention) -> Result<(), Error>;
+
+    /// Removes a topic and all of its retained messages
+    fn remove_topic(&mut self, topic: &str) -> Result<(), Error>;
+
+    /// Sets or removes the dead letter topic of a topic
+    fn put_dead_letter(&mut self, topic: &str, dead_letter: Option<&str>) -> Result<(), Error>;
+
+    /// Sets or removes the default time-to-live of the messages of a topic
+    fn put_ttl(&mut self, topic: &str, ttl: Option<Duration>) -> Result<(), Error>;
+
+    /// Appends a retained message to a topic
+    fn push_retained(
+        &mut self,
+        topic: &str,
+        msg_id: MessageId,
+        content: &[u8],
+        expires_at: Option<SystemTime>,
+    ) -> Result<(), Error>;
+
+    /// Removes the oldest retained message of a topic
+    fn pop_retained(&mut self, topic: &str) -> Result<(), Error>;
+}
+
+/// In-memory `BrokerStore`. This is the default store and nothing is persisted.
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    topics: HashMap<String, StoredTopic>,
+}
+
+impl MemoryStore {
+    /// Creates an empty `MemoryStore`
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl BrokerStore for MemoryStore {
+    fn load(&mut self) -> Result<HashMap<String, StoredTopic>, Error> {
+        Ok(self.topics.clone())
+    }
+
+    fn put_topic(&mut self, topic: &str, retention: Retention) -> Result<(), Error> {
+        self.topics.entry(topic.to_string()).or_default().retention = retention;
+        Ok(())
+    }
+
+    fn remove_topic(&mut self, topic: &str) -> Result<(), Error> {
+        self.topics.remove(topic);
+        Ok(())
+    }
+
+    fn put_dead_letter(&mut self, topic: &str, dead_letter: Option<&str>) -> Result<(), Error> {
+        self.topics
+            .entry(topic.to_string())
+            .or_default()
+            .dead_letter = dead_letter.map(|t| t.to_string());
+        Ok(())
+    }
+
+    fn put_ttl(&mut self, topic: &str, ttl: Option<Duration>) -> Result<(), Error> {
+        self.topics.entry(topic.to_string()).or_default().ttl = ttl;
+        Ok(())
+    }
+
+    fn push_retained(
+        &mut self,
+        topic: &str,
+        msg_id: MessageId,
+        content: &[u8],
+        expires_at: Option<SystemTime>,
+    ) -> Result<(), Error> {
+        self.topics
+            .entry(topic.to_string())
+            .or_default()
+            .retained
+            .push_back((msg_id, content.to_vec(), expires_at));
+        Ok(())
+    }
+
+    fn pop_retained(&mut self, topic: &str) -> Result<(), Error> {
+        if let Some(entry) = self.topics.get_mut(topic) {
+            entry.retained.pop_front();
+        }
+        Ok(())
+    }
+}
+
+const OP_PUT_TOPIC: u8 = 1;
+const OP_REMOVE_TOPIC: u8 = 2;
+const OP_PUSH_RETAINED: u8 = 3;
+const OP_POP_RETAINED: u8 = 4;
+const OP_PUT_DEAD_LETTER: u8 = 5;
+const OP_PUT_TTL: u8 = 6;
+
+/// `BrokerStore` backed by an append-only log file
+///
+/// Every change is appended to the log and flushed before returning. The log is
+/// replayed and compacted when the store is opened.
+///
+/// The writes are blocking and are done right in the loop of the `PubSubBroker`,
+/// which stalls the publications of every topic while the file system is busy, so
+/// the log is best kept on a local disk. A flush hands the record over to the OS
+/// without waiting for it to reach the disk.
+///
+/// # Example
+///
+/// ```no_run
+/// # use toy_rpc::Server;
+/// # use toy_rpc::server::store::FileStore;
+/// # fn main() -> Result<(), toy_rpc::Error> {
+/// let store = FileStore::open("broker.log")?;
+/// let server = Server::builder()
+///     .broker_store(store)
+///     .build();
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct FileStore {
+    path: PathBuf,
+    file: BufWriter<File>,
+    state: MemoryStore,
+}
+
+impl FileStore {
+    /// Opens the log file at `path`, creating it if it does not exist
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref().to_path_buf();
+        let state = match File::open(&path) {
+            Ok(file) => replay(BufReader::new(file))?,
+            Err(err) if err.kind() == ErrorKind::NotFound => MemoryStore::new(),
+            Err(err) => return Err(err.into()),
+        };
+
+        // compact the log by writing a snapshot of the current state
+        let tmp = path.with_extension("tmp");
+        let mut file = BufWriter::new(File::create(&tmp)?);
+        for (topic, entry) in state.topics.iter() {
+            write_put_topic(&mut file, topic, entry.retention)?;
+            if let Some(dead_letter) = &entry.dead_letter {
+                write_put_dead_letter(&mut file, topic, Some(dead_letter))?;
+            }
+            if let Some(ttl) = entry.ttl {
+                write_put_ttl(&mut file, topic, Some(ttl))?;
+            }
+            for (msg_id, content, expires_at) in entry.retained.iter() {
+                write_push_retained(&mut file, topic, *msg_id, content, *expires_at)?;
+            }
+        }
+        file.flush()?;
+        file.get_ref().sync_all()?;
+        drop(file);
+        std::fs::rename(&tmp, &path)?;
+
+        let file = OpenOptions::new().append(true).open(&path)?;
+        Ok(Self {
+            path,
+            file: BufWriter::new(file),
+            state,
+        })
+    }
+
+    /// Path of the log file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl BrokerStore for FileStore {
+    fn load(&mut self) -> Result<HashMap<String, StoredTopic>, Error> {
+        self.state.load()
+    }
+
+    fn put_topic(&mut self, topic: &str, retention: Retention) -> Result<(), Error> {
+        write_put_topic(&mut self.file, topic, retention)?;
+        self.file.flush()?;
+        self.state.put_topic(topic, retention)
+    }
+
+    fn remove_topic(&mut self, topic: &str) -> Result<(), Error> {
+        self.file.write_all(&[OP_REMOVE_TOPIC])?;
+        write_bytes(&mut self.file, topic.as_bytes())?;
+        self.file.flush()?;
+        self.state.remove_topic(topic)
+    }
+
+    fn put_dead_letter(&mut self, topic: &str, dead_letter: Option<&str>) -> Result<(), Error> {
+        write_put_dead_letter(&mut self.file, topic, dead_letter)?;
+        self.file.flush()?;
+        self.state.put_dead_letter(topic, dead_letter)
+    }
+
+    fn put_ttl(&mut self, topic: &str, ttl: Option<Duration>) -> Result<(), Error> {
+        write_put_ttl(&mut self.file, topic, ttl)?;
+        self.file.flush()?;
+        self.state.put_ttl(topic, ttl)
+    }
+
+    fn push_retained(
+        &mut self,
+        topic: &str,
+        msg_id: MessageId,
+        content: &[u8],
+        expires_at: Option<SystemTime>,
+    ) -> Result<(), Error> {
+        write_push_retained(&mut self.file, topic, msg_id, content, expires_at)?;
+        self.file.flush()?;
+        self.state.push_retained(topic, msg_id, content, expires_at)
+    }
+
+    fn pop_retained(&mut self, topic: &str) -> Result<(), Error> {
+        self.file.write_all(&[OP_POP_RETAINED])?;
+        write_bytes(&mut self.file, topic.as_bytes())?;
+        self.file.flush()?;
+        self.state.pop_retained(topic)
+    }
+}
+
+fn write_bytes(w: &mut impl Write, bytes: &[u8]) -> std::io::Result<()> {
+    w.write_all(&(bytes.len() as u32).to_le_bytes())?;
+    w.write_all(bytes)
+}
+
+fn write_put_topic(w: &mut impl Write, topic: &str, retention: Retention) -> std::io::Result<()> {
+    w.write_all(&[OP_PUT_TOPIC])?;
+    write_bytes(w, topic.as_bytes())?;
+    let n = match retention {
+        Retention::None => u64::MAX,
+        Retention::LastN(n) => n as u64,
+    };
+    w.write_all(&n.to_le_bytes())
+}
+
+fn write_put_dead_letter(
+    w: &mut impl Write,
+    topic: &str,
+    dead_letter: Option<&str>,
+) -> std::io::Result<()> {
+    w.write_all(&[OP_PUT_DEAD_LETTER])?;
+    write_bytes(w, topic.as_bytes())?;
+    match dead_letter {
+        Some(dead_letter) => {
+            w.write_all(&[1])?;
+            write_bytes(w, dead_letter.as_bytes())
+        }
+        None => w.write_all(&[0]),
+    }
+}
+
+fn write_put_ttl(w: &mut impl Write, topic: &str, ttl: Option<Duration>) -> std::io::Result<()> {
+    w.write_all(&[OP_PUT_TTL])?;
+    write_bytes(w, topic.as_bytes())?;
+    write_nanos(w, ttl)
+}
+
+fn write_push_retained(
+    w: &mut impl Write,
+    topic: &str,
+    msg_id: MessageId,
+    content: &[u8],
+    expires_at: Option<SystemTime>,
+) -> std::io::Result<()> {
+    w.write_all(&[OP_PUSH_RETAINED])?;
+    write_bytes(w, topic.as_bytes())?;
+    w.write_all(&msg_id.to_le_bytes())?;
+    write_bytes(w, content)?;
+    // the time at which the message expires is kept as the time since the epoch
+    let expires_at = expires_at.map(|t| t.duration_since(UNIX_EPOCH).unwrap_or_default());
+    write_nanos(w, expires_at)
+}
+
+/// Writes a duration in nanoseconds, `u64::MAX` stands for `None`
+fn write_nanos(w: &mut impl Write, duration: Option<Duration>) -> std::io::Result<()> {
+    let nanos = duration.map_or(u64::MAX, |d| d.as_nanos().min(u64::MAX as u128 - 1) as u64);
+    w.write_all(&nanos.to_le_bytes())
+}
+
+fn read_nanos(r: &mut impl Read) -> std::io::Result<Option<Duration>> {
+    let mut nanos = [0u8; 8];
+    r.read_exact(&mut nanos)?;
+    match u64::from_le_bytes(nanos) {
+        u64::MAX => Ok(None),
+        nanos => Ok(Some(Duration::from_nanos(nanos))),
+    }
+}
+
+/// Reads bytes prefixed with their length. The buffer grows with the bytes that are
+/// actually read, so that a corrupt length cannot allocate more than what is left
+/// of the log, and a length past the end of the log reads as a truncated record.
+fn read_bytes(r: &mut impl Read) -> std::io::Result<Vec<u8>> {
+    let mut len = [0u8; 4];
+    r.read_exact(&mut len)?;
+    let len = u32::from_le_bytes(len) as u64;
+    let mut buf = Vec::new();
+    Read::by_ref(r).take(len).read_to_end(&mut buf)?;
+    if (buf.len() as u64) < len {
+        return Err(ErrorKind::UnexpectedEof.into());
+    }
+    Ok(buf)
+}
+
+fn read_topic(r: &mut impl Read) -> Result<String, Error> {
+    String::from_utf8(read_bytes(r)?)
+        .map_err(|err| Error::Internal(format!("Invalid topic in log: {}", err).into()))
+}
+
+/// Replays a log. A truncated record at the end of the log, which could be
+/// left by a crash in the middle of a write, is discarded.
+fn replay(mut r: impl Read) -> Result<MemoryStore, Error> {
+    let mut state = MemoryStore::new();
+    loop {
+        let mut op = [0u8; 1];
+        match r.read_exact(&mut op) {
+            Ok(_) => {}
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => break,
+            Err(err) => return Err(err.into()),
+        }
+        let result = match op[0] {
+            OP_PUT_TOPIC => read_topic(&mut r).and_then(|topic| {
+                let mut n = [0u8; 8];
+                r.read_exact(&mut n)?;
+                let retention = match u64::from_le_bytes(n) {
+                    u64::MAX => Retention::None,
+                    n => Retention::LastN(n as usize),
+                };
+                state.put_topic(&topic, retention)
+            }),
+            OP_REMOVE_TOPIC => read_topic(&mut r).and_then(|topic| state.remove_topic(&topic)),
+            OP_PUSH_RETAINED => read_topic(&mut r).and_then(|topic| {
+                let mut msg_id = [0u8; 2];
+                r.read_exact(&mut msg_id)?;
+                let content = read_bytes(&mut r)?;
+                let expires_at = read_nanos(&mut r)?.map(|d| UNIX_EPOCH + d);
+                let msg_id = MessageId::from_le_bytes(msg_id);
+                state.push_retained(&topic, msg_id, &content, expires_at)
+            }),
+            OP_POP_RETAINED => read_topic(&mut r).and_then(|topic| state.pop_retained(&topic)),
+            OP_PUT_DEAD_LETTER => read_topic(&mut r).and_then(|topic| {
+                let mut some = [0u8; 1];
+                r.read_exact(&mut some)?;
+                let dead_letter = match some[0] {
+                    0 => None,
+                    _ => Some(read_topic(&mut r)?),
+                };
+                state.put_dead_letter(&topic, dead_letter.as_deref())
+            }),
+            OP_PUT_TTL => read_topic(&mut r).and_then(|topic| {
+                let ttl = read_nanos(&mut r)?;
+                state.put_ttl(&topic, ttl)
+            }),
+            op => {
+                return Err(Error::Internal(
+                    format!("Unknown operation in log: {}", op).into(),
+                ))
+            }
+        };
+        match result {
+            Ok(_) => {}
+            Err(Error::IoError(err)) if err.kind() == ErrorKind::UnexpectedEof => {
+                warn!("Discarding truncated record at the end of the log");
+                break;
+            }
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn corrupt_lengths_read_as_truncated_records() {
+        let mut log = Vec::new();
+        write_put_topic(&mut log, "Count", Retention::LastN(2)).unwrap();
+        write_push_retained(&mut log, "Count", 1, b"one", None).unwrap();
+        // a record claiming far more content than there is left in the log
+        log.push(OP_PUSH_RETAINED);
+        write_bytes(&mut log, b"Count").unwrap();
+        log.extend_from_slice(&2u16.to_le_bytes());
+        log.extend_from_slice(&u32::MAX.to_le_bytes());
+        log.extend_from_slice(b"two");
+
+        let mut state = replay(&log[..]).unwrap();
+        let topics = state.load().unwrap();
+        let retained: Vec<_> = topics["Count"]
+            .retained
+            .iter()
+            .map(|(msg_id, content, _)| (*msg_id, content.clone()))
+            .collect();
+        assert_eq!(retained, vec![(1, b"one".to_vec())]);
+    }
+}
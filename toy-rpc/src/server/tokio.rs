@@ -11,44 +11,55 @@ cfg_if! {
             not(feature = "serde_json"),
             not(feature = "serde_cbor"),
             not(feature = "serde_rmp"),
+            not(feature = "serde_postcard"),
         ),
         all(
             feature = "serde_cbor",
             not(feature = "serde_json"),
             not(feature = "serde_bincode"),
             not(feature = "serde_rmp"),
+            not(feature = "serde_postcard"),
         ),
         all(
             feature = "serde_json",
             not(feature = "serde_bincode"),
             not(feature = "serde_cbor"),
             not(feature = "serde_rmp"),
+            not(feature = "serde_postcard"),
         ),
         all(
             feature = "serde_rmp",
             not(feature = "serde_cbor"),
             not(feature = "serde_json"),
             not(feature = "serde_bincode"),
+            not(feature = "serde_postcard"),
+        ),
+        all(
+            feature = "serde_postcard",
+            not(feature = "serde_cbor"),
+            not(feature = "serde_json"),
+            not(feature = "serde_bincode"),
+            not(feature = "serde_rmp"),
         ),
     ))] {
-        use std::sync::Arc;
         use ::tokio::net::{TcpListener, TcpStream};
-        use futures::{StreamExt};
+        use futures::{future, Stream, StreamExt};
         use ::tokio::task::{self};
         use tokio::io::{AsyncRead, AsyncWrite};
-        use flume::Sender;
         use std::sync::atomic::Ordering;
 
+        #[cfg(feature = "tls")]
+        use std::sync::Arc;
         #[cfg(feature = "tls")]
         use tokio_rustls::{TlsAcceptor};
         #[cfg(feature = "tls")]
         use rustls::ServerConfig;
 
         use crate::error::Error;
-        use crate::transport::ws::WebSocketConn;
-        use crate::codec::split::SplittableCodec;
+        use crate::transport::ws::{accept_protocol, WebSocketConn};
         use crate::codec::DefaultCodec;
-        use super::{AsyncServiceMap, Server, ClientId, pubsub::PubSubItem};
+        use super::incoming::{AcceptOptions, IncomingConnection, Transport};
+        use super::{Server, ClientId, ConnectionContext};
 
         /// The following impl block is controlled by feature flag. It is enabled
         /// if and only if **exactly one** of the the following feature flag is turned on
@@ -56,6 +67,7 @@ cfg_if! {
         /// - `serde_json`
         /// - `serde_cbor`
         /// - `serde_rmp`
+        /// - `serde_postcard`
         impl Server {
 
             /// Accepts connections on an `tokio::net::TcpListener` and serves requests to default
@@ -67,16 +79,31 @@ cfg_if! {
             /// - `serde_json`
             /// - `serde_cbor`
             /// - `serde_rmp`
+            /// - `serde_postcard`
             ///
             /// # Example
             ///
-            /// ```rust
+            /// ```no_run
+            /// # use std::sync::Arc;
+            /// # use toy_rpc::Server;
+            /// # use toy_rpc::macros::export_impl;
+            /// # struct ExampleService {}
+            /// # #[export_impl]
+            /// # impl ExampleService {
+            /// #     #[export_method]
+            /// #     async fn echo(&self, args: String) -> Result<String, String> {
+            /// #         Ok(args)
+            /// #     }
+            /// # }
+            /// # async fn run(addr: &str) -> Result<(), Box<dyn std::error::Error>> {
             /// let example_service = Arc::new(ExampleService {});
             /// let server = Server::builder()
             ///     .register(example_service)
             ///     .build();
             /// let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
             /// server.accept(listener).await.unwrap();
+            /// # Ok(())
+            /// # }
             /// ```
             ///
             /// See `toy-rpc/examples/tokio_tcp/` for the example
@@ -86,12 +113,16 @@ cfg_if! {
 
                 while let Some(conn) = incoming.next().await {
                     let stream = conn?;
-                    log::info!("Accepting incoming connection from {}", stream.peer_addr()?);
+                    let peer_addr = stream.peer_addr()?;
+                    let permit = match self.limits.try_acquire(&peer_addr) {
+                        Some(permit) => permit,
+                        None => continue,
+                    };
+                    info!("Accepting incoming connection from {}", peer_addr);
 
                     let client_id = self.client_counter.fetch_add(1, Ordering::Relaxed);
-                    let pubsub_broker = self.pubsub_tx.clone();
                     task::spawn(
-                        serve_tcp_connection(stream, self.services.clone(), client_id, pubsub_broker)
+                        permit.hold(serve_tcp_connection(stream, client_id, self.connection_context()))
                     );
                 }
 
@@ -110,12 +141,62 @@ cfg_if! {
 
                 while let Some(conn) = incoming.next().await {
                     let stream = conn?;
+                    let permit = match self.limits.try_acquire(&stream.peer_addr()?) {
+                        Some(permit) => permit,
+                        None => continue,
+                    };
                     let acceptor = acceptor.clone();
 
                     let client_id = self.client_counter.fetch_add(1, Ordering::Relaxed);
-                    let pubsub_broker = self.pubsub_tx.clone();
                     task::spawn(
-                        serve_tls_connection(stream, acceptor, self.services.clone(), client_id, pubsub_broker)
+                        permit.hold(serve_tls_connection(stream, acceptor, client_id, self.connection_context()))
+                    );
+                }
+
+                Ok(())
+            }
+
+            /// Accepts QUIC connections on a `quinn::Endpoint` and serves requests to default
+            /// server for each incoming connection
+            ///
+            /// Each message id has its own QUIC stream, so a large or slow call does not hold
+            /// back the other calls of the connection. TLS is configured with the
+            /// `quinn::ServerConfig` of the endpoint. The connection filter is applied to the
+            /// remote address, and the PROXY protocol is not supported. This returns when the
+            /// endpoint is closed.
+            ///
+            /// This is enabled if `transport_quic` and **exactly one** of the following feature
+            /// flag is turned on
+            /// - `serde_bincode`
+            /// - `serde_cbor`
+            /// - `serde_rmp`
+            /// - `serde_postcard`
+            ///
+            /// # Example
+            ///
+            /// ```no_run
+            /// # use std::net::SocketAddr;
+            /// # use toy_rpc::Server;
+            /// # async fn run(server: Server, cert_chain: Vec<quinn_rustls::Certificate>, key: quinn_rustls::PrivateKey, addr: SocketAddr) -> Result<(), Box<dyn std::error::Error>> {
+            /// let server_config = quinn::ServerConfig::with_single_cert(cert_chain, key)?;
+            /// let endpoint = quinn::Endpoint::server(server_config, addr)?;
+            /// server.accept_quic(endpoint).await.unwrap();
+            /// # Ok(())
+            /// # }
+            /// ```
+            #[cfg(all(feature = "transport_quic", not(feature = "serde_json")))]
+            #[cfg_attr(feature = "docs", doc(cfg(feature = "transport_quic")))]
+            pub async fn accept_quic(&self, endpoint: quinn::Endpoint) -> Result<(), Error> {
+                while let Some(connecting) = endpoint.accept().await {
+                    let permit = match self.limits.try_acquire(&connecting.remote_address()) {
+                        Some(permit) => permit,
+                        None => continue,
+                    };
+                    info!("Accepting incoming QUIC connection from {}", connecting.remote_address());
+
+                    let client_id = self.client_counter.fetch_add(1, Ordering::Relaxed);
+                    task::spawn(
+                        permit.hold(serve_quic_connection(connecting, client_id, self.connection_context()))
                     );
                 }
 
@@ -131,16 +212,31 @@ cfg_if! {
             /// - `serde_json`
             /// - `serde_cbor`
             /// - `serde_rmp`
+            /// - `serde_postcard`
             ///
             /// # Example
             ///
-            /// ```rust
+            /// ```no_run
+            /// # use std::sync::Arc;
+            /// # use toy_rpc::Server;
+            /// # use toy_rpc::macros::export_impl;
+            /// # struct ExampleService {}
+            /// # #[export_impl]
+            /// # impl ExampleService {
+            /// #     #[export_method]
+            /// #     async fn echo(&self, args: String) -> Result<String, String> {
+            /// #         Ok(args)
+            /// #     }
+            /// # }
+            /// # async fn run(addr: &str) -> Result<(), Box<dyn std::error::Error>> {
             /// let example_service = Arc::new(ExampleService {});
             /// let server = Server::builder()
             ///     .register(example_service)
             ///     .build();
             /// let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
             /// server.accept_websocket(listener).await.unwrap();
+            /// # Ok(())
+            /// # }
             /// ```
             #[cfg_attr(feature = "docs", doc(cfg(feature = "tokio_runtime")))]
             pub async fn accept_websocket(&self, listener: TcpListener) -> Result<(), Error> {
@@ -148,18 +244,65 @@ cfg_if! {
 
                 while let Some(conn) = incoming.next().await {
                     let stream = conn?;
-                    log::info!("Accepting incoming connection from {}", stream.peer_addr()?);
+                    let peer_addr = stream.peer_addr()?;
+                    let permit = match self.limits.try_acquire(&peer_addr) {
+                        Some(permit) => permit,
+                        None => continue,
+                    };
+                    info!("Accepting incoming connection from {}", peer_addr);
 
                     let client_id = self.client_counter.fetch_add(1, Ordering::Relaxed);
-                    let pubsub_broker = self.pubsub_tx.clone();
                     task::spawn(
-                        accept_ws_connection(stream, self.services.clone(), client_id, pubsub_broker)
+                        permit.hold(accept_ws_connection(stream, client_id, self.connection_context()))
                     );
                 }
 
                 Ok(())
             }
 
+            /// Turns a `tokio::net::TcpListener` into a stream of the incoming connections,
+            /// which can be inspected and then accepted with some options or rejected. The
+            /// accepted connections are served in their own tasks.
+            ///
+            /// See the [`incoming`](incoming/index.html) module for details.
+            ///
+            /// This is enabled
+            /// if and only if **exactly one** of the the following feature flag is turned on
+            /// - `serde_bincode`
+            /// - `serde_json`
+            /// - `serde_cbor`
+            /// - `serde_rmp`
+            /// - `serde_postcard`
+            ///
+            /// # Example
+            ///
+            /// ```no_run
+            /// # use futures::StreamExt;
+            /// # use toy_rpc::Server;
+            /// # use toy_rpc::server::incoming::AcceptOptions;
+            /// # async fn run(server: Server, addr: &str) -> Result<(), Box<dyn std::error::Error>> {
+            /// let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
+            /// let mut incoming = server.incoming(listener);
+            /// while let Some(conn) = incoming.next().await {
+            ///     conn.accept_with_options(AcceptOptions::default().nodelay(true));
+            /// }
+            /// # Ok(())
+            /// # }
+            /// ```
+            #[cfg_attr(feature = "docs", doc(cfg(feature = "tokio_runtime")))]
+            pub fn incoming(&self, listener: TcpListener) -> impl Stream<Item = IncomingConnection<'_>> + Unpin + '_ {
+                tokio_stream::wrappers::TcpListenerStream::new(listener).filter_map(move |conn| {
+                    let conn = match conn {
+                        Ok(stream) => IncomingConnection::new(self, stream),
+                        Err(err) => {
+                            error!("{}", err);
+                            None
+                        }
+                    };
+                    future::ready(conn)
+                })
+            }
+
             /// Serves a single connection using the default codec
             ///
             /// This is enabled
@@ -168,16 +311,31 @@ cfg_if! {
             /// - `serde_json`
             /// - `serde_cbor`
             /// - `serde_rmp`
+            /// - `serde_postcard`
             ///
             /// Example
             ///
-            /// ```rust
+            /// ```no_run
+            /// # use std::sync::Arc;
+            /// # use toy_rpc::Server;
+            /// # use toy_rpc::macros::export_impl;
+            /// # struct ExampleService {}
+            /// # #[export_impl]
+            /// # impl ExampleService {
+            /// #     #[export_method]
+            /// #     async fn echo(&self, args: String) -> Result<String, String> {
+            /// #         Ok(args)
+            /// #     }
+            /// # }
+            /// # async fn run(addr: &str) -> Result<(), Box<dyn std::error::Error>> {
             /// let example_service = ExampleService {};
             /// let server = Server::builder()
             ///     .register(example_service)
             ///     .build();
             /// let conn = tokio::net::TcpStream::connect(addr).await.unwrap();
-            /// server.serve_conn(conn).await.unwrap();
+            /// server.serve_stream(conn).await.unwrap();
+            /// # Ok(())
+            /// # }
             /// ```
             // #[deprecated(
             //     since = "0.7.3",
@@ -195,32 +353,57 @@ cfg_if! {
                 T: AsyncRead + AsyncWrite + Send + Unpin + 'static
             {
                 // let ret = serve_readwrite_stream(stream, self.services.clone()).await;
-                let codec = DefaultCodec::new(stream);
+                let mut codec = DefaultCodec::new(stream);
+                codec.frame = self.frame;
                 let ret = self.serve_codec(codec).await;
-                log::info!("Client disconnected from stream");
+                info!("Client disconnected from stream");
                 ret
             }
+        }
 
-            /// This is like serve_conn except that it uses a specified codec
-            ///
-            /// Example
+        impl IncomingConnection<'_> {
+            /// Serves the connection in a new task with the options
             ///
-            /// ```rust
-            /// let stream = TcpStream::connect("127.0.0.1:8080").await.unwrap();
-            /// let codec = toy_rpc::codec::Codec::new(stream);
-            /// let server = Server::builder()
-            ///     .register(example_service)
-            ///     .build();
-            /// server.serve_codec(codec).await.unwrap();
-            /// ```
+            /// This is enabled
+            /// if and only if **exactly one** of the the following feature flag is turned on
+            /// - `serde_bincode`
+            /// - `serde_json`
+            /// - `serde_cbor`
+            /// - `serde_rmp`
+            /// - `serde_postcard`
             #[cfg_attr(feature = "docs", doc(cfg(feature = "tokio_runtime")))]
-            pub async fn serve_codec<C>(&self, codec: C) -> Result<(), Error>
-            where
-                C: SplittableCodec + Send + 'static,
-            {
-                let client_id = self.client_counter.fetch_add(1, Ordering::Relaxed);
-                let pubsub_broker = self.pubsub_tx.clone();
-                super::start_broker_reader_writer(codec, self.services.clone(), client_id, pubsub_broker).await
+            pub fn accept_with_options(self, options: AcceptOptions) {
+                let IncomingConnection { server, stream, peer_addr } = self;
+                let permit = match server.limits.try_acquire(&peer_addr) {
+                    Some(permit) => permit,
+                    None => return,
+                };
+                info!("Accepting incoming connection from {}", peer_addr);
+                if let Some(nodelay) = options.nodelay {
+                    if let Err(err) = stream.set_nodelay(nodelay) {
+                        error!("{}", err);
+                    }
+                }
+
+                let client_id = server.client_counter.fetch_add(1, Ordering::Relaxed);
+                match options.transport {
+                    Transport::Tcp => {
+                        task::spawn(
+                            permit.hold(serve_tcp_connection(stream, client_id, server.connection_context()))
+                        );
+                    }
+                    Transport::WebSocket => {
+                        task::spawn(
+                            permit.hold(accept_ws_connection(stream, client_id, server.connection_context()))
+                        );
+                    }
+                    #[cfg(feature = "tls")]
+                    Transport::Tls(config) => {
+                        task::spawn(
+                            permit.hold(serve_tls_connection(stream, TlsAcceptor::from(config), client_id, server.connection_context()))
+                        );
+                    }
+                }
             }
         }
 
@@ -228,51 +411,107 @@ cfg_if! {
         async fn serve_tls_connection(
             stream: TcpStream,
             acceptor: TlsAcceptor,
-            services: Arc<AsyncServiceMap>,
             client_id: ClientId,
-            pubsub_broker: Sender<PubSubItem>
+            context: ConnectionContext,
         ) -> Result<(), Error> {
+            let mut stream = stream;
             let peer_addr = stream.peer_addr()?;
+            let peer_addr = super::proxy::resolve_peer_addr(&mut stream, context.proxy_protocol, peer_addr).await?;
+            if !super::filter::is_allowed(context.connection_filter.as_ref(), &peer_addr) {
+                return Ok(())
+            }
+            let context = context.peer(peer_addr);
             let tls_stream = acceptor.accept(stream).await?;
             // let ret = serve_readwrite_stream(tls_stream, services).await;
-            let codec = DefaultCodec::new(tls_stream);
-            let ret = super::start_broker_reader_writer(codec, services, client_id, pubsub_broker).await;
-            log::info!("Client disconnected from {}", peer_addr);
+            let mut codec = DefaultCodec::new(tls_stream);
+            codec.frame = context.frame;
+            let ret = super::start_broker_reader_writer(codec, client_id, context).await;
+            info!("Client disconnected from {}", peer_addr);
+            ret
+        }
+
+        #[cfg(all(feature = "transport_quic", not(feature = "serde_json")))]
+        async fn serve_quic_connection(
+            connecting: quinn::Connecting,
+            client_id: ClientId,
+            context: ConnectionContext,
+        ) -> Result<(), Error> {
+            let peer_addr = connecting.remote_address();
+            if !super::filter::is_allowed(context.connection_filter.as_ref(), &peer_addr) {
+                return Ok(())
+            }
+            let context = context.peer(peer_addr);
+            let conn = connecting.await.map_err(Error::from_quic_handshake)?;
+            let mut codec = DefaultCodec::with_quic_connection(conn);
+            codec.frame = context.frame;
+            let ret = super::start_broker_reader_writer(codec, client_id, context).await;
+            info!("Client disconnected from {}", peer_addr);
             ret
         }
 
         /// Serves a single connection
         async fn serve_tcp_connection(
             stream: TcpStream,
-            services: Arc<AsyncServiceMap>,
             client_id: ClientId,
-            pubsub_broker: Sender<PubSubItem>
+            context: ConnectionContext,
         ) -> Result<(), Error> {
-            let _peer_addr = stream.peer_addr()?;
+            let mut stream = stream;
+            let peer_addr = stream.peer_addr()?;
+            let peer_addr = super::proxy::resolve_peer_addr(&mut stream, context.proxy_protocol, peer_addr).await?;
+            if !super::filter::is_allowed(context.connection_filter.as_ref(), &peer_addr) {
+                return Ok(())
+            }
+            let context = context.peer(peer_addr);
+            #[cfg(all(feature = "codec_negotiation", not(feature = "serde_json")))]
+            if let Some(supported) = context.frame.content_types {
+                let content_type = crate::codec::negotiate::respond(&mut stream, supported).await?;
+                debug!("Serving {} with {}", peer_addr, content_type.as_str());
+                let ret = crate::codec::negotiate::with_format_codec!(content_type, stream, context.frame, |codec| {
+                    super::start_broker_reader_writer(codec, client_id, context).await
+                });
+                info!("Client disconnected from {}", peer_addr);
+                return ret
+            }
             // let ret = serve_readwrite_stream(stream, services, client_id, pubsub_broker);
-            let codec = DefaultCodec::new(stream);
-            let ret = super::start_broker_reader_writer(codec, services, client_id, pubsub_broker).await;
-            log::info!("Client disconnected from {}", _peer_addr);
+            let mut codec = DefaultCodec::new(stream);
+            codec.frame = context.frame;
+            let ret = super::start_broker_reader_writer(codec, client_id, context).await;
+            info!("Client disconnected from {}", peer_addr);
             ret
         }
 
         async fn accept_ws_connection(
             stream: TcpStream,
-            services: Arc<AsyncServiceMap>,
             client_id: ClientId,
-            pubsub_broker: Sender<PubSubItem>
+            context: ConnectionContext,
         ) {
-            let ws_stream = async_tungstenite::tokio::accept_async(stream).await
-                    .expect("Error during the websocket handshake occurred");
-                log::debug!("Established WebSocket connection.");
+            let mut stream = stream;
+            let peer_addr = match stream.peer_addr() {
+                Ok(addr) => addr,
+                Err(err) => return error!("{}", err),
+            };
+            let peer_addr = match super::proxy::resolve_peer_addr(&mut stream, context.proxy_protocol, peer_addr).await {
+                Ok(addr) => addr,
+                Err(err) => return error!("{}", err),
+            };
+            if !super::filter::is_allowed(context.connection_filter.as_ref(), &peer_addr) {
+                return
+            }
+            let context = context.peer(peer_addr);
+            let mut chunked = false;
+            let ws_stream = match async_tungstenite::tokio::accept_hdr_async(stream, accept_protocol(&mut chunked)).await {
+                Ok(ws_stream) => ws_stream,
+                Err(err) => return error!("{}", Error::from_ws_handshake(err)),
+            };
+            debug!("Established WebSocket connection.");
 
-            let ws_stream = WebSocketConn::new(ws_stream);
+            let ws_stream = WebSocketConn::new(ws_stream).chunked(chunked);
             let codec = DefaultCodec::with_websocket(ws_stream);
 
-            if let Err(err) = super::start_broker_reader_writer(codec, services, client_id, pubsub_broker).await {
-                log::error!("{}", err);
+            if let Err(err) = super::start_broker_reader_writer(codec, client_id, context).await {
+                error!("{}", err);
             }
-            log::info!("Client disconnected from WebSocket connection");
+            info!("Client disconnected from WebSocket connection");
         }
     }
 }
@@ -0,0 +1,197 @@
+//! Strict topic mode
+//!
+//! By default any topic name can be published to or subscribed to, so a typo in a
+//! topic name silently creates a new topic that nobody publishes to. Once a topic is
+//! declared with `ServerBuilder::declare_topic`, the server only accepts the declared
+//! topics:
+//!
+//! - a subscription to an unknown topic is rejected, and the subscriber receives
+//!   `Error::TopicRejected`
+//! - a subscription to a topic pattern that does not match any declared topic is
+//!   rejected in the same way
+//! - a publication to an unknown topic is dropped. A publisher on the server side
+//!   gets `Error::TopicRejected`, and the rejection is logged by a remote client.
+//!
+//! The name of the item type is declared along with the topic. Publishers and
+//! subscribers on the server side are checked against it, which catches two `Topic`
+//! types that share a name but not an item type.
+//!
+//! # Example
+//!
+//! ```no_run
+//! # use std::sync::Arc;
+//! # use toy_rpc::Server;
+//! # use toy_rpc::macros::export_impl;
+//! # struct Example;
+//! # #[export_impl]
+//! # impl Example {
+//! #     #[export_method]
+//! #     async fn echo(&self, args: String) -> Result<String, String> {
+//! #         Ok(args)
+//! #     }
+//! # }
+//! # struct Count;
+//! # impl toy_rpc::pubsub::Topic for Count {
+//! #     type Item = String;
+//! #     fn topic() -> String {
+//! #         "count".into()
+//! #     }
+//! # }
+//! # struct Status;
+//! # impl toy_rpc::pubsub::Topic for Status {
+//! #     type Item = String;
+//! #     fn topic() -> String {
+//! #         "status".into()
+//! #     }
+//! # }
+//! # let example_service = Arc::new(Example);
+//! let server = Server::builder()
+//!     .register(example_service)
+//!     .declare_topic::<Count>()
+//!     .declare_topic::<Status>()
+//!     .build();
+//! ```
+
+use std::collections::HashMap;
+
+use crate::{
+    error::Error,
+    pubsub::{self, Topic},
+};
+
+/// Topics declared on a server
+#[derive(Debug, Clone, Default)]
+pub struct TopicRegistry {
+    // topic name -> name of the item type
+    declared: HashMap<String, &'static str>,
+}
+
+impl TopicRegistry {
+    /// Declares topic `T`
+    pub fn declare<T: Topic>(&mut self) {
+        self.declared
+            .insert(T::topic(), std::any::type_name::<T::Item>());
+    }
+
+    /// Returns whether only the declared topics are accepted
+    pub fn is_strict(&self) -> bool {
+        !self.declared.is_empty()
+    }
+
+    /// Returns the name of the item type of a declared topic
+    pub fn item_type(&self, topic: &str) -> Option<&'static str> {
+        self.declared.get(topic).copied()
+    }
+
+    /// Checks whether a topic can be published to or subscribed to
+    pub(crate) fn check(&self, topic: &str) -> Result<(), Error> {
+        if topic.is_empty() {
+            return Err(Error::TopicRejected("Topic name is empty".into()));
+        }
+        if pubsub::is_pattern(topic) {
+            return Err(Error::TopicRejected(format!(
+                "Topic name {} is a pattern",
+                topic
+            )));
+        }
+        if self.is_strict() && !self.declared.contains_key(topic) {
+            return Err(Error::TopicRejected(format!("Unknown topic: {}", topic)));
+        }
+        Ok(())
+    }
+
+    /// Checks whether a topic pattern can be subscribed to
+    pub(crate) fn check_pattern(&self, pattern: &str) -> Result<(), Error> {
+        if pattern.is_empty() {
+            return Err(Error::TopicRejected("Topic pattern is empty".into()));
+        }
+        if self.is_strict()
+            && !self
+                .declared
+                .keys()
+                .any(|topic| pubsub::matches(pattern, topic))
+        {
+            return Err(Error::TopicRejected(format!(
+                "Pattern {} does not match any topic",
+                pattern
+            )));
+        }
+        Ok(())
+    }
+
+    /// Checks topic `T` and its item type
+    pub(crate) fn check_type<T: Topic>(&self) -> Result<(), Error> {
+        let topic = T::topic();
+        self.check(&topic)?;
+        match self.item_type(&topic) {
+            Some(declared) if declared != std::any::type_name::<T::Item>() => {
+                Err(Error::TopicRejected(format!(
+                    "Topic {} is declared with item type {}, not {}",
+                    topic,
+                    declared,
+                    std::any::type_name::<T::Item>()
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Count;
+
+    impl Topic for Count {
+        type Item = u32;
+
+        fn topic() -> String {
+            "Count".into()
+        }
+    }
+
+    struct CountAsText;
+
+    impl Topic for CountAsText {
+        type Item = String;
+
+        fn topic() -> String {
+            "Count".into()
+        }
+    }
+
+    #[test]
+    fn strict_topics() {
+        let mut registry = TopicRegistry::default();
+        assert!(registry.check("Typo").is_ok());
+        assert!(registry.check("").is_err());
+
+        registry.declare::<Count>();
+        assert!(registry.is_strict());
+        assert!(registry.check("Count").is_ok());
+        assert!(matches!(
+            registry.check("Typo"),
+            Err(Error::TopicRejected(_))
+        ));
+        assert!(registry.check_type::<Count>().is_ok());
+        assert!(matches!(
+            registry.check_type::<CountAsText>(),
+            Err(Error::TopicRejected(_))
+        ));
+    }
+
+    #[test]
+    fn topic_patterns() {
+        let mut registry = TopicRegistry::default();
+        assert!(registry.check("Count/*").is_err());
+        assert!(registry.check_pattern("Count/*").is_ok());
+
+        registry.declare::<Count>();
+        assert!(registry.check_pattern("Co*").is_ok());
+        assert!(matches!(
+            registry.check_pattern("Typo/*"),
+            Err(Error::TopicRejected(_))
+        ));
+    }
+}
@@ -1,90 +1,414 @@
+use std::convert::TryFrom;
 use std::sync::Arc;
 
 use brw::{Running, Writer};
+use futures::FutureExt;
 
 use crate::{
     codec::CodecWrite,
     error::Error,
     message::{ErrorMessage, MessageId},
-    service::HandlerResult,
+    metadata::{self, Context, METADATA_MARKER},
+    payload::PayloadAccounting,
+    protocol::OutboundBody,
+    pubsub::PublishReport,
+    service::{HandlerResult, Success},
+    util::GracefulShutdown,
 };
 
+use super::flow::OutboundQueue;
+use super::shutdown::{GOING_AWAY, SHUTDOWN_REASON};
+use super::stats::ConnectionCounters;
+#[cfg(all(feature = "codec_negotiation", not(feature = "serde_json")))]
+use crate::codec::negotiate::{self, ContentType};
+#[cfg(feature = "signing")]
+use crate::signing::{ResponseSigner, Signed, SIGNATURE_MARKER};
+
 use crate::protocol::Header;
 
+#[cfg(not(feature = "http_actix_web"))]
+use crate::message::{CANCELLATION_TOKEN, CANCELLATION_TOKEN_DELIM};
+
 #[cfg_attr(feature = "http_actix_web", derive(actix::Message))]
 #[cfg_attr(feature = "http_actix_web", rtype(result = "()"))]
 pub(crate) enum ServerWriterItem {
     Response {
         id: MessageId,
         result: HandlerResult,
+        /// Service method of the request, for the payload accounting
+        service_method: Option<String>,
     },
     /// Publish subscription item to client
     Publication {
         id: MessageId,
         topic: String,
         content: Arc<Vec<u8>>,
+        tag: Option<u64>,
+        /// Whether the client must acknowledge the publication
+        ack: bool,
+    },
+    /// Acknowledges a publication to the client publisher with the report of its
+    /// delivery
+    Ack(MessageId, PublishReport),
+    /// Rejection of a subscription
+    Rejected {
+        id: MessageId,
+        topic: String,
+        error: Error,
+    },
+    /// Item of a streaming response
+    StreamItem {
+        id: MessageId,
+        result: Result<Box<OutboundBody>, Error>,
+        service_method: Option<String>,
+    },
+    /// End of a streaming response
+    StreamEnd(MessageId),
+    /// Protocol extension message to client
+    Ext {
+        id: MessageId,
+        marker: u32,
+        content: String,
     },
+    /// Closes the connection because the server is shutting down
+    Close,
+    /// Call to a service of the client
+    #[cfg(not(feature = "http_actix_web"))]
+    Request {
+        id: MessageId,
+        service_method: String,
+        duration: std::time::Duration,
+        body: Box<OutboundBody>,
+    },
+    /// Cancels a call to a service of the client
+    #[cfg(not(feature = "http_actix_web"))]
+    Cancel(MessageId),
+}
+
+/// The message carrying the metadata of a response, if the handler attached any
+pub(crate) fn metadata_item(id: MessageId, context: Option<Context>) -> Option<ServerWriterItem> {
+    let metadata = context?.take_response_metadata();
+    match metadata.is_empty() {
+        true => None,
+        false => Some(ServerWriterItem::Ext {
+            id,
+            marker: METADATA_MARKER,
+            content: metadata::encode(&metadata),
+        }),
+    }
+}
+
+/// Header of a publication, which carries the type tag if there is one
+pub(crate) fn publication_header(
+    id: MessageId,
+    topic: String,
+    tag: Option<u64>,
+    ack: bool,
+) -> Header {
+    match (tag, ack) {
+        (tag, true) => Header::AckedPublish { id, topic, tag },
+        (Some(tag), false) => Header::TaggedPublish { id, topic, tag },
+        (None, false) => Header::Publish { id, topic },
+    }
 }
 
 pub(crate) struct ServerWriter<W> {
     writer: W,
+    payload: Arc<PayloadAccounting>,
+    stats: Arc<ConnectionCounters>,
+    outbound: Arc<OutboundQueue>,
+    #[cfg(feature = "signing")]
+    signer: Option<Arc<ResponseSigner>>,
+    // content type that the next response is encoded with instead of the codec's
+    #[cfg(all(feature = "codec_negotiation", not(feature = "serde_json")))]
+    content_type: Option<(MessageId, ContentType)>,
 }
 
 impl<W: CodecWrite> ServerWriter<W> {
     #[cfg(not(feature = "http_actix_web"))]
-    pub fn new(writer: W) -> Self {
-        Self { writer }
+    pub fn new(
+        writer: W,
+        payload: Arc<PayloadAccounting>,
+        stats: Arc<ConnectionCounters>,
+        outbound: Arc<OutboundQueue>,
+    ) -> Self {
+        Self {
+            writer,
+            payload,
+            stats,
+            outbound,
+            #[cfg(feature = "signing")]
+            signer: None,
+            #[cfg(all(feature = "codec_negotiation", not(feature = "serde_json")))]
+            content_type: None,
+        }
+    }
+
+    /// Signs the responses with the key of the server
+    #[cfg(all(feature = "signing", not(feature = "http_actix_web")))]
+    pub fn sign_responses(self, signer: Option<Arc<ResponseSigner>>) -> Self {
+        let mut writer = self;
+        writer.signer = signer;
+        writer
+    }
+
+    #[cfg_attr(
+        not(all(feature = "codec_negotiation", not(feature = "serde_json"))),
+        allow(unused_variables)
+    )]
+    fn marshal(&mut self, id: MessageId, body: &OutboundBody) -> Result<Vec<u8>, Error> {
+        #[cfg(all(feature = "codec_negotiation", not(feature = "serde_json")))]
+        let bytes = match self.content_type {
+            Some((encoded, content_type)) if encoded == id => {
+                self.content_type = None;
+                content_type.marshal(&body)
+            }
+            _ => W::marshal(&body),
+        };
+        #[cfg(not(all(feature = "codec_negotiation", not(feature = "serde_json"))))]
+        let bytes = W::marshal(&body);
+        if bytes.is_err() {
+            self.stats.marshal_error();
+        }
+        bytes
+    }
+
+    async fn write_response(
+        &mut self,
+        id: MessageId,
+        result: HandlerResult,
+        service_method: Option<String>,
+    ) -> Result<(), Error> {
+        let result = result.and_then(Success::into_reply);
+        self.write_result(id, result, service_method, |is_ok| Header::Response {
+            id,
+            is_ok,
+        })
+        .await
     }
 
-    async fn write_response(&mut self, id: MessageId, result: HandlerResult) -> Result<(), Error> {
+    async fn write_stream_item(
+        &mut self,
+        id: MessageId,
+        result: Result<Box<OutboundBody>, Error>,
+        service_method: Option<String>,
+    ) -> Result<(), Error> {
+        self.write_result(id, result, service_method, |is_ok| Header::StreamItem {
+            id,
+            is_ok,
+        })
+        .await
+    }
+
+    async fn write_result(
+        &mut self,
+        id: MessageId,
+        result: Result<Box<OutboundBody>, Error>,
+        service_method: Option<String>,
+        header: impl FnOnce(bool) -> Header,
+    ) -> Result<(), Error> {
+        if let Err(Error::InvalidArgument) = result {
+            self.stats.unmarshal_error();
+        }
+        let result = match (result, service_method) {
+            (Ok(body), Some(service_method)) => {
+                let bytes = self.marshal(id, body.as_ref())?;
+                self.payload
+                    .counters
+                    .record_response(&service_method, bytes.len());
+                self.payload
+                    .limits
+                    .check_response(&service_method, bytes.len())
+                    .map(|_| bytes)
+            }
+            (Ok(body), None) => self.marshal(id, body.as_ref()),
+            (Err(err), _) => Err(err),
+        };
         match result {
-            Ok(body) => {
-                log::trace!("Message {} Success", &id);
-                let header = Header::Response { id, is_ok: true };
+            Ok(bytes) => {
+                trace!("Message {} Success", &id);
+                let header = header(true);
+                #[cfg(feature = "signing")]
+                self.write_signature(&header, &bytes).await?;
                 self.writer.write_header(header).await?;
-                self.writer.write_body(id, &body).await
+                self.stats.body_written(bytes.len());
+                self.writer.write_body_bytes(id, &bytes).await
             }
             Err(err) => {
-                log::trace!("Message {} Error", &id);
-                let header = Header::Response { id, is_ok: false };
-                let msg = ErrorMessage::from_err(err)?;
+                trace!("Message {} Error", &id);
+                let msg = ErrorMessage::try_from(err)?;
+                let bytes = self.marshal(id, &msg)?;
+                let header = header(false);
+                #[cfg(feature = "signing")]
+                self.write_signature(&header, &bytes).await?;
                 self.writer.write_header(header).await?;
-                self.writer.write_body(id, &msg).await
+                self.writer.write_body_bytes(id, &bytes).await
             }
         }
     }
 
+    /// Writes the signature of a response right before it, if the responses are signed
+    #[cfg(feature = "signing")]
+    async fn write_signature(&mut self, header: &Header, bytes: &[u8]) -> Result<(), Error> {
+        let signer = match &self.signer {
+            Some(signer) => signer,
+            None => return Ok(()),
+        };
+        let (kind, id, is_ok) = match *header {
+            Header::Response { id, is_ok } => (Signed::Response, id, is_ok),
+            Header::StreamItem { id, is_ok } => (Signed::StreamItem, id, is_ok),
+            _ => return Ok(()),
+        };
+        let header = Header::Ext {
+            id,
+            content: signer.sign(kind, id, is_ok, bytes),
+            marker: SIGNATURE_MARKER,
+        };
+        self.writer.write_header(header).await?;
+        self.writer.write_body(id, &()).await
+    }
+
     async fn write_publication(
         &mut self,
         id: MessageId,
         topic: String,
         content: &[u8],
+        tag: Option<u64>,
+        ack: bool,
     ) -> Result<(), Error> {
-        let header = Header::Publish { id, topic };
+        let header = publication_header(id, topic, tag, ack);
         self.writer.write_header(header).await?;
+        self.stats.body_written(content.len());
         self.writer.write_body_bytes(id, &content).await
     }
 }
 
+impl<W: CodecWrite + GracefulShutdown> ServerWriter<W> {
+    async fn write(&mut self, item: ServerWriterItem) -> Running<Result<(), Error>> {
+        let res = match item {
+            ServerWriterItem::Response {
+                id,
+                result,
+                service_method,
+            } => self.write_response(id, result, service_method).await,
+            ServerWriterItem::Publication {
+                id,
+                topic,
+                content,
+                tag,
+                ack,
+            } => self.write_publication(id, topic, &content, tag, ack).await,
+            ServerWriterItem::Ack(id, report) => {
+                match self.writer.write_header(Header::Ack(id)).await {
+                    Ok(_) => self.writer.write_body(id, &report).await,
+                    Err(err) => Err(err),
+                }
+            }
+            ServerWriterItem::Rejected { id, topic, error } => {
+                let header = Header::Reject { id, topic };
+                match ErrorMessage::try_from(error) {
+                    Ok(msg) => match self.writer.write_header(header).await {
+                        Ok(_) => self.writer.write_body(id, &msg).await,
+                        Err(err) => Err(err),
+                    },
+                    Err(err) => Err(err),
+                }
+            }
+            ServerWriterItem::StreamItem {
+                id,
+                result,
+                service_method,
+            } => self.write_stream_item(id, result, service_method).await,
+            ServerWriterItem::StreamEnd(id) => {
+                match self.writer.write_header(Header::StreamEnd(id)).await {
+                    Ok(_) => self.writer.write_body(id, &()).await,
+                    Err(err) => Err(err),
+                }
+            }
+            ServerWriterItem::Ext {
+                id,
+                marker,
+                content,
+            } => {
+                #[cfg(all(feature = "codec_negotiation", not(feature = "serde_json")))]
+                if marker == METADATA_MARKER {
+                    let metadata = metadata::decode(&content);
+                    self.content_type = negotiate::response_content_type(&metadata)
+                        .map(|content_type| (id, content_type));
+                }
+                let header = Header::Ext {
+                    id,
+                    content,
+                    marker,
+                };
+                match self.writer.write_header(header).await {
+                    Ok(_) => self.writer.write_body(id, &()).await,
+                    Err(err) => Err(err),
+                }
+            }
+            ServerWriterItem::Close => {
+                self.writer.close_with(GOING_AWAY, SHUTDOWN_REASON).await;
+                return Running::Stop;
+            }
+            #[cfg(not(feature = "http_actix_web"))]
+            ServerWriterItem::Request {
+                id,
+                service_method,
+                duration,
+                body,
+            } => {
+                let header = Header::Request {
+                    id,
+                    service_method,
+                    timeout: duration,
+                };
+                match self.writer.write_header(header).await {
+                    Ok(_) => self.writer.write_body(id, &body).await,
+                    Err(err) => Err(err),
+                }
+            }
+            #[cfg(not(feature = "http_actix_web"))]
+            ServerWriterItem::Cancel(id) => {
+                let token = format!("{}{}{}", CANCELLATION_TOKEN, CANCELLATION_TOKEN_DELIM, id);
+                match self.writer.write_header(Header::Cancel(id)).await {
+                    Ok(_) => self.writer.write_body(id, &token).await,
+                    Err(err) => Err(err),
+                }
+            }
+        };
+        if res.is_ok() {
+            self.stats.message_written();
+        }
+        Running::Continue(res)
+    }
+}
+
 #[async_trait::async_trait]
-impl<W: CodecWrite> Writer for ServerWriter<W> {
+impl<W: CodecWrite + GracefulShutdown> Writer for ServerWriter<W> {
     type Item = ServerWriterItem;
     type Ok = ();
     type Error = Error;
 
     async fn op(&mut self, item: Self::Item) -> Running<Result<Self::Ok, Self::Error>> {
-        let res = match item {
-            ServerWriterItem::Response { id, result } => self.write_response(id, result).await,
-            ServerWriterItem::Publication { id, topic, content } => {
-                self.write_publication(id, topic, &content).await
+        let outbound = self.outbound.clone();
+        // the messages still queued are dropped if a slow client is disconnected
+        let write = self.write(item).fuse();
+        let closed = outbound.closed().fuse();
+        futures::pin_mut!(write, closed);
+        let running = futures::select_biased! {
+            _ = closed => {
+                debug!("Dropping {} queued messages of a slow client", outbound.len());
+                Running::Stop
             }
+            running = write => running,
         };
-        Running::Continue(res)
+        outbound.pop();
+        running
     }
 
     async fn handle_result(res: Result<Self::Ok, Self::Error>) -> Running<()> {
         if let Err(err) = res {
-            log::error!("{:?}", err);
+            error!("{:?}", err);
         }
         Running::Continue(())
     }
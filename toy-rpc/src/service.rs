@@ -3,6 +3,7 @@
 use async_trait::async_trait;
 use erased_serde as erased;
 use futures::future::Future;
+use futures::{Stream, StreamExt};
 use std::collections::HashMap;
 use std::marker::PhantomData;
 use std::pin::Pin;
@@ -10,10 +11,56 @@ use std::sync::Arc;
 
 use crate::error::Error;
 use crate::protocol::OutboundBody;
+use crate::streaming::RpcStream;
+
+/// Stream of the items of a streaming response
+pub type ItemStream = Pin<Box<dyn Stream<Item = Result<Box<OutboundBody>, Error>> + Send>>;
 
 /// Ok type of HandlerResult
-// pub(crate) type Success = Box<dyn erased::Serialize + Send + Sync + 'static>;
-pub(crate) type Success = Box<OutboundBody>;
+pub enum Success {
+    /// Content of a single response
+    Reply(Box<OutboundBody>),
+    /// Items of a streaming response
+    Stream(ItemStream),
+}
+
+impl Success {
+    /// Returns the content of a single response
+    #[cfg(feature = "server")]
+    pub(crate) fn into_reply(self) -> Result<Box<OutboundBody>, Error> {
+        match self {
+            Success::Reply(body) => Ok(body),
+            Success::Stream(_) => Err(Error::Internal(
+                "A stream cannot be sent as a single response".into(),
+            )),
+        }
+    }
+}
+
+/// Converts the `Ok` value of an exported method into `Success`
+pub trait IntoSuccess {
+    /// Performs the conversion
+    fn into_success(self) -> Success;
+}
+
+impl<T> IntoSuccess for T
+where
+    T: serde::Serialize + Send + Sync + 'static,
+{
+    fn into_success(self) -> Success {
+        Success::Reply(Box::new(self))
+    }
+}
+
+impl<T> IntoSuccess for RpcStream<T>
+where
+    T: serde::Serialize + Send + Sync + 'static,
+{
+    fn into_success(self) -> Success {
+        let items = self.map(|item| item.map(|item| Box::new(item) as Box<OutboundBody>));
+        Success::Stream(Box::pin(items))
+    }
+}
 
 /// Return type of RPC handler
 pub type HandlerResult = Result<Success, Error>;
@@ -42,7 +89,7 @@ pub type AsyncServiceMap = HashMap<&'static str, ArcAsyncServiceCall>;
 /// A RPC service that can hold an internal state
 pub struct Service<State>
 where
-    State: Send + Sync + 'static,
+    State: ?Sized + Send + Sync + 'static,
 {
     state: Arc<State>,
     handlers: HashMap<&'static str, AsyncHandler<State>>,
@@ -50,12 +97,18 @@ where
 
 impl<State> Service<State>
 where
-    State: Send + Sync + 'static,
+    State: ?Sized + Send + Sync + 'static,
 {
     /// Creates a `ServiceBuilder`
     pub fn builder() -> ServiceBuilder<State, BuilderUninitialized> {
         ServiceBuilder::new()
     }
+
+    /// Names of the registered methods
+    #[cfg(feature = "server")]
+    pub(crate) fn method_names(&self) -> Vec<&'static str> {
+        self.handlers.keys().copied().collect()
+    }
 }
 
 /// The `HandleService` trait provides the method `call` which will execute the
@@ -63,7 +116,7 @@ where
 #[async_trait]
 pub trait HandleService<State>
 where
-    State: Send + Sync + 'static,
+    State: ?Sized + Send + Sync + 'static,
 {
     /// Returns a `Arc` of the internal state
     fn get_state(&self) -> Arc<State>;
@@ -88,7 +141,7 @@ where
 
 impl<State> HandleService<State> for Service<State>
 where
-    State: Send + Sync + 'static,
+    State: ?Sized + Send + Sync + 'static,
 {
     fn get_state(&self) -> Arc<State> {
         self.state.clone()
@@ -111,7 +164,7 @@ pub struct BuilderReady;
 /// A `Service` can be built without any handler but cannot be built without internal state.
 pub struct ServiceBuilder<State, BuilderMode>
 where
-    State: Send + Sync + 'static,
+    State: ?Sized + Send + Sync + 'static,
 {
     /// Internal state of the builder, which will be the internal state of the `Service`
     pub state: Option<Arc<State>>,
@@ -125,7 +178,7 @@ where
 
 impl<State> ServiceBuilder<State, BuilderUninitialized>
 where
-    State: Send + Sync + 'static,
+    State: ?Sized + Send + Sync + 'static,
 {
     /// Creates a new builder without any internal state.
     pub fn new() -> ServiceBuilder<State, BuilderUninitialized> {
@@ -150,7 +203,7 @@ where
 
 impl<State> Default for ServiceBuilder<State, BuilderUninitialized>
 where
-    State: Send + Sync + 'static,
+    State: ?Sized + Send + Sync + 'static,
 {
     fn default() -> Self {
         Self::new()
@@ -159,7 +212,7 @@ where
 
 impl<State, BuilderMode> ServiceBuilder<State, BuilderMode>
 where
-    State: Send + Sync + 'static,
+    State: ?Sized + Send + Sync + 'static,
 {
     /// Register the internal state
     pub fn register_state(self, s: Arc<State>) -> ServiceBuilder<State, BuilderReady> {
@@ -192,7 +245,7 @@ where
 
 impl<State> ServiceBuilder<State, BuilderReady>
 where
-    State: Send + Sync + 'static,
+    State: ?Sized + Send + Sync + 'static,
 {
     /// Build a `Service`
     pub fn build(mut self) -> Service<State> {
@@ -209,7 +262,7 @@ pub fn build_service<State>(
     handlers: HashMap<&'static str, AsyncHandler<State>>,
 ) -> Service<State>
 where
-    State: Send + Sync + 'static,
+    State: ?Sized + Send + Sync + 'static,
 {
     Service::builder()
         .register_state(state)
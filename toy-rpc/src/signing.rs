@@ -0,0 +1,366 @@
+//! Signatures of the responses, for clients behind untrusted relays
+//!
+//! A server signs the body of every response and of every item of a streaming
+//! response with the `ResponseSigner` set with `ServerBuilder::sign_responses`, so that
+//! a client that receives its responses through a relay or a proxy can detect that
+//! they were tampered with even without end-to-end TLS. The signature is sent in a
+//! `Header::Ext` message with the `SIGNATURE_MARKER` right before the response.
+//!
+//! A client verifies the signatures with the `ResponseVerifier` set with
+//! `ClientBuilder::verify_responses`, which holds the keys of the servers it trusts
+//! by the key ID of each server identity. A response whose signature is missing, made
+//! with an unknown key or does not match its body fails the call with
+//! `Error::InvalidSignature`, and an item of a streaming response fails the rest of
+//! the stream. A client without a verifier ignores the signatures.
+//!
+//! Two algorithms are supported:
+//!
+//! - HMAC-SHA256 with a secret shared by the server and its clients
+//! - Ed25519, where the clients only hold the public key of the server
+//!
+//! The signature covers the ID of the message, whether the call has succeeded and
+//! the serialized body, so a relay cannot swap the responses of two calls or turn an
+//! error into a success. The publications of the pubsub are not signed. A signed
+//! response can still be replayed over another connection that reuses the same
+//! message ID.
+//!
+//! # Example
+//!
+//! ```no_run
+//! # use std::sync::Arc;
+//! # use toy_rpc::{Client, Server};
+//! # use toy_rpc::macros::export_impl;
+//! # struct Example;
+//! # #[export_impl]
+//! # impl Example {
+//! #     #[export_method]
+//! #     async fn echo(&self, args: String) -> Result<String, String> {
+//! #         Ok(args)
+//! #     }
+//! # }
+//! # use toy_rpc::signing::{ResponseSigner, ResponseVerifier};
+//! # async fn run(addr: &str, seed: [u8; 32]) -> Result<(), Box<dyn std::error::Error>> {
+//! # let example_service = Arc::new(Example);
+//! let signer = ResponseSigner::ed25519_from_seed("server-1", &seed)?;
+//! let public_key = signer.public_key().unwrap().to_vec();
+//! let server = Server::builder()
+//!     .register(example_service)
+//!     .sign_responses(signer)
+//!     .build();
+//!
+//! let client = Client::builder()
+//!     .verify_responses(ResponseVerifier::new().ed25519("server-1", &public_key))
+//!     .dial(addr)
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::HashMap;
+use std::fmt;
+
+use ring::{hmac, signature};
+
+use crate::error::Error;
+use crate::message::MessageId;
+
+/// Marker of the `Header::Ext` messages that carry the signature of the next response
+pub const SIGNATURE_MARKER: u32 = u32::MAX - 2;
+
+/// Prefix of the signed bytes, so that the keys cannot be abused to sign anything else
+const DOMAIN: &[u8] = b"toy-rpc response signature\0";
+
+const HMAC_SHA256: &str = "hmac-sha256";
+const ED25519: &str = "ed25519";
+
+/// The kind of message that is signed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Signed {
+    Response,
+    StreamItem,
+}
+
+/// The bytes that are signed for a message
+fn signed_bytes(kind: Signed, id: MessageId, is_ok: bool, body: &[u8]) -> Vec<u8> {
+    let mut buf =
+        Vec::with_capacity(DOMAIN.len() + 4 + std::mem::size_of::<MessageId>() + body.len());
+    buf.extend_from_slice(DOMAIN);
+    buf.push(kind as u8);
+    buf.extend_from_slice(&id.to_be_bytes());
+    buf.push(is_ok as u8);
+    buf.extend_from_slice(body);
+    buf
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(s: &str) -> Option<Vec<u8>> {
+    s.as_bytes()
+        .chunks(2)
+        .map(|pair| match pair.len() {
+            2 => u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok(),
+            _ => None,
+        })
+        .collect()
+}
+
+enum SigningKey {
+    Hmac(hmac::Key),
+    Ed25519(signature::Ed25519KeyPair),
+}
+
+/// Key that a server signs its responses with
+pub struct ResponseSigner {
+    key_id: String,
+    key: SigningKey,
+}
+
+impl fmt::Debug for ResponseSigner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ResponseSigner")
+            .field("key_id", &self.key_id)
+            .field("algorithm", &self.algorithm())
+            .finish()
+    }
+}
+
+impl ResponseSigner {
+    /// Signs with HMAC-SHA256 and a secret that is shared with the clients
+    pub fn hmac_sha256(key_id: impl Into<String>, secret: &[u8]) -> Self {
+        Self {
+            key_id: key_id.into(),
+            key: SigningKey::Hmac(hmac::Key::new(hmac::HMAC_SHA256, secret)),
+        }
+    }
+
+    /// Signs with an Ed25519 key pair in PKCS#8 v1 or v2 format
+    pub fn ed25519(key_id: impl Into<String>, pkcs8: &[u8]) -> Result<Self, Error> {
+        let key_pair = signature::Ed25519KeyPair::from_pkcs8_maybe_unchecked(pkcs8)
+            .map_err(|err| Error::Internal(format!("Invalid Ed25519 key: {}", err).into()))?;
+        Ok(Self {
+            key_id: key_id.into(),
+            key: SigningKey::Ed25519(key_pair),
+        })
+    }
+
+    /// Signs with an Ed25519 key pair derived from a 32-byte seed
+    pub fn ed25519_from_seed(key_id: impl Into<String>, seed: &[u8]) -> Result<Self, Error> {
+        let key_pair = signature::Ed25519KeyPair::from_seed_unchecked(seed)
+            .map_err(|err| Error::Internal(format!("Invalid Ed25519 seed: {}", err).into()))?;
+        Ok(Self {
+            key_id: key_id.into(),
+            key: SigningKey::Ed25519(key_pair),
+        })
+    }
+
+    /// The key ID that the clients look the key up with
+    pub fn key_id(&self) -> &str {
+        &self.key_id
+    }
+
+    /// The public key to hand to the clients, or `None` with HMAC-SHA256
+    pub fn public_key(&self) -> Option<&[u8]> {
+        use signature::KeyPair;
+
+        match &self.key {
+            SigningKey::Hmac(_) => None,
+            SigningKey::Ed25519(key_pair) => Some(key_pair.public_key().as_ref()),
+        }
+    }
+
+    fn algorithm(&self) -> &'static str {
+        match self.key {
+            SigningKey::Hmac(_) => HMAC_SHA256,
+            SigningKey::Ed25519(_) => ED25519,
+        }
+    }
+
+    /// The content of the `Header::Ext` message that carries the signature, which
+    /// is `"<algorithm>:<hex signature>:<key ID>"`
+    #[cfg_attr(not(feature = "server"), allow(dead_code))]
+    pub(crate) fn sign(&self, kind: Signed, id: MessageId, is_ok: bool, body: &[u8]) -> String {
+        let msg = signed_bytes(kind, id, is_ok, body);
+        let signature = match &self.key {
+            SigningKey::Hmac(key) => to_hex(hmac::sign(key, &msg).as_ref()),
+            SigningKey::Ed25519(key_pair) => to_hex(key_pair.sign(&msg).as_ref()),
+        };
+        format!("{}:{}:{}", self.algorithm(), signature, self.key_id)
+    }
+}
+
+enum VerifyingKey {
+    Hmac(hmac::Key),
+    Ed25519(Vec<u8>),
+}
+
+/// Keys that a client verifies the signatures of the responses with, by key ID
+#[derive(Default)]
+pub struct ResponseVerifier {
+    keys: HashMap<String, VerifyingKey>,
+}
+
+impl fmt::Debug for ResponseVerifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ResponseVerifier")
+            .field("key_ids", &self.keys.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl ResponseVerifier {
+    /// Creates a verifier without any key, which rejects every response
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Trusts the HMAC-SHA256 secret of a server
+    pub fn hmac_sha256(self, key_id: impl Into<String>, secret: &[u8]) -> Self {
+        let mut verifier = self;
+        let key = hmac::Key::new(hmac::HMAC_SHA256, secret);
+        verifier.keys.insert(key_id.into(), VerifyingKey::Hmac(key));
+        verifier
+    }
+
+    /// Trusts the Ed25519 public key of a server
+    pub fn ed25519(self, key_id: impl Into<String>, public_key: &[u8]) -> Self {
+        let mut verifier = self;
+        let key = VerifyingKey::Ed25519(public_key.to_vec());
+        verifier.keys.insert(key_id.into(), key);
+        verifier
+    }
+
+    /// Verifies the content of the `Header::Ext` message that preceded a message, if
+    /// there was one, against the body of the message
+    #[cfg_attr(not(feature = "client"), allow(dead_code))]
+    pub(crate) fn verify(
+        &self,
+        content: Option<&str>,
+        kind: Signed,
+        id: MessageId,
+        is_ok: bool,
+        body: &[u8],
+    ) -> Result<(), Error> {
+        let content = content
+            .ok_or_else(|| Error::InvalidSignature(format!("message {} is not signed", id)))?;
+        let mut parts = content.splitn(3, ':');
+        let (algorithm, signature, key_id) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(algorithm), Some(signature), Some(key_id)) => (algorithm, signature, key_id),
+            _ => {
+                return Err(Error::InvalidSignature(format!(
+                    "message {} has a malformed signature",
+                    id
+                )))
+            }
+        };
+        let signature = from_hex(signature).ok_or_else(|| {
+            Error::InvalidSignature(format!("message {} has a malformed signature", id))
+        })?;
+        let key = self.keys.get(key_id).ok_or_else(|| {
+            Error::InvalidSignature(format!(
+                "message {} is signed with unknown key {}",
+                id, key_id
+            ))
+        })?;
+
+        let msg = signed_bytes(kind, id, is_ok, body);
+        let verified = match (key, algorithm) {
+            (VerifyingKey::Hmac(key), HMAC_SHA256) => hmac::verify(key, &msg, &signature),
+            (VerifyingKey::Ed25519(public_key), ED25519) => {
+                signature::UnparsedPublicKey::new(&signature::ED25519, public_key)
+                    .verify(&msg, &signature)
+            }
+            _ => {
+                return Err(Error::InvalidSignature(format!(
+                    "message {} is signed with {} instead of the algorithm of key {}",
+                    id, algorithm, key_id
+                )))
+            }
+        };
+        verified.map_err(|_| {
+            Error::InvalidSignature(format!("message {} does not match its signature", id))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SEED: [u8; 32] = [7; 32];
+
+    #[test]
+    fn hmac_signatures_are_verified() {
+        let signer = ResponseSigner::hmac_sha256("server-1", b"secret");
+        let verifier = ResponseVerifier::new().hmac_sha256("server-1", b"secret");
+        let content = signer.sign(Signed::Response, 3, true, b"body");
+        assert!(content.starts_with("hmac-sha256:"));
+        assert!(content.ends_with(":server-1"));
+        verifier
+            .verify(Some(&content), Signed::Response, 3, true, b"body")
+            .unwrap();
+
+        let other = ResponseVerifier::new().hmac_sha256("server-1", b"other secret");
+        assert!(other
+            .verify(Some(&content), Signed::Response, 3, true, b"body")
+            .is_err());
+    }
+
+    #[test]
+    fn ed25519_signatures_are_verified() {
+        let signer = ResponseSigner::ed25519_from_seed("server-1", &SEED).unwrap();
+        let public_key = signer.public_key().unwrap().to_vec();
+        let verifier = ResponseVerifier::new().ed25519("server-1", &public_key);
+        let content = signer.sign(Signed::StreamItem, 9, false, b"error");
+        verifier
+            .verify(Some(&content), Signed::StreamItem, 9, false, b"error")
+            .unwrap();
+    }
+
+    #[test]
+    fn tampering_is_detected() {
+        let signer = ResponseSigner::ed25519_from_seed("server-1", &SEED).unwrap();
+        let public_key = signer.public_key().unwrap().to_vec();
+        let verifier = ResponseVerifier::new().ed25519("server-1", &public_key);
+        let content = signer.sign(Signed::Response, 1, true, b"body");
+
+        let tampered = [
+            (Signed::Response, 1, true, &b"bodY"[..]),
+            (Signed::Response, 2, true, &b"body"[..]),
+            (Signed::Response, 1, false, &b"body"[..]),
+            (Signed::StreamItem, 1, true, &b"body"[..]),
+        ];
+        for (kind, id, is_ok, body) in tampered.iter() {
+            let res = verifier.verify(Some(&content), *kind, *id, *is_ok, body);
+            assert!(matches!(res, Err(Error::InvalidSignature(_))));
+        }
+    }
+
+    #[test]
+    fn missing_and_unknown_signatures_are_rejected() {
+        let verifier = ResponseVerifier::new().hmac_sha256("server-1", b"secret");
+        let res = verifier.verify(None, Signed::Response, 1, true, b"body");
+        assert!(matches!(res, Err(Error::InvalidSignature(_))));
+
+        let content = ResponseSigner::hmac_sha256("server-2", b"secret").sign(
+            Signed::Response,
+            1,
+            true,
+            b"body",
+        );
+        let res = verifier.verify(Some(&content), Signed::Response, 1, true, b"body");
+        assert!(matches!(res, Err(Error::InvalidSignature(_))));
+
+        for content in [
+            "hmac-sha256",
+            "hmac-sha256:zz:server-1",
+            "ed25519:00:server-1",
+        ]
+        .iter()
+        {
+            let res = verifier.verify(Some(content), Signed::Response, 1, true, b"body");
+            assert!(matches!(res, Err(Error::InvalidSignature(_))));
+        }
+    }
+}
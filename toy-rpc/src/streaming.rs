@@ -0,0 +1,355 @@
+//! Streaming responses
+//!
+//! An exported method that returns a `RpcStream` sends its items to the client one
+//! by one as they are produced, instead of a single response. The timeout of the
+//! call applies to the method returning the stream. The stream is then consumed
+//! until it ends or the call is canceled by the client.
+//!
+//! ```no_run
+//! # #[cfg(all(feature = "server", feature = "client", feature = "tokio_runtime", not(feature = "async_std_runtime")))]
+//! # {
+//! # use futures::{Stream, StreamExt};
+//! # use toy_rpc::Error;
+//! # use toy_rpc::macros::export_impl;
+//! # use toy_rpc::streaming::RpcStream;
+//! # struct Sensor;
+//! # impl Sensor {
+//! #     fn subscribe(&self) -> impl Stream<Item = f64> + Send + 'static {
+//! #         futures::stream::iter(vec![20.5, 21.0])
+//! #     }
+//! # }
+//! #[export_impl]
+//! impl Sensor {
+//!     #[export_method]
+//!     async fn readings(&self, count: usize) -> Result<RpcStream<f64>, Error> {
+//!         Ok(RpcStream::new(self.subscribe().take(count).map(Ok)))
+//!     }
+//! }
+//! # }
+//! ```
+//!
+//! On the client side, `Client::call_stream` returns a `CallStream` of the items.
+//! The generated client stub of a method returning a `RpcStream` returns a
+//! `CallStream` as well. Dropping the `CallStream` cancels the call.
+//!
+//! ```no_run
+//! # #[cfg(all(feature = "server", feature = "client", feature = "tokio_runtime", not(feature = "async_std_runtime")))]
+//! # {
+//! # use futures::{Stream, StreamExt};
+//! # use toy_rpc::Error;
+//! # use toy_rpc::macros::export_impl;
+//! # use toy_rpc::streaming::RpcStream;
+//! # struct Sensor;
+//! # impl Sensor {
+//! #     fn subscribe(&self) -> impl Stream<Item = f64> + Send + 'static {
+//! #         futures::stream::iter(vec![20.5, 21.0])
+//! #     }
+//! # }
+//! # #[export_impl]
+//! # impl Sensor {
+//! #     #[export_method]
+//! #     async fn readings(&self, count: usize) -> Result<RpcStream<f64>, Error> {
+//! #         Ok(RpcStream::new(self.subscribe().take(count).map(Ok)))
+//! #     }
+//! # }
+//! # async fn run(client: toy_rpc::Client) -> Result<(), toy_rpc::Error> {
+//! let mut readings = client.sensor().readings(10);
+//! while let Some(reading) = readings.next().await {
+//!     println!("{}", reading?);
+//! }
+//! # Ok(())
+//! # }
+//! # }
+//! ```
+//!
+//! The items are buffered on the client until they are consumed, so a fast stream
+//! consumed slowly grows the buffer.
+//!
+//! # Streaming arguments
+//!
+//! An exported method that takes a `RequestStream` receives the items of its argument
+//! one by one as the client sends them. It may return a single response, or a
+//! `RpcStream` to stream in both directions. The timeout of the call applies to the
+//! whole execution of the method, including the time spent waiting for the items.
+//!
+//! ```no_run
+//! # #[cfg(all(feature = "server", feature = "client", feature = "tokio_runtime", not(feature = "async_std_runtime")))]
+//! # {
+//! # use futures::StreamExt;
+//! # use toy_rpc::Error;
+//! # use toy_rpc::macros::export_impl;
+//! # use toy_rpc::streaming::RequestStream;
+//! # struct Stats;
+//! #[export_impl]
+//! impl Stats {
+//!     #[export_method]
+//!     async fn sum(&self, mut numbers: RequestStream<i64>) -> Result<i64, Error> {
+//!         let mut sum = 0;
+//!         while let Some(number) = numbers.next().await {
+//!             sum += number?;
+//!         }
+//!         Ok(sum)
+//!     }
+//! }
+//! # }
+//! ```
+//!
+//! On the client side, `Client::call_with_sink` returns a `ClientSink` to send the
+//! items and the `Call` of the response, and `Client::call_stream_with_sink` returns a
+//! `ClientSink` and the `CallStream` of the response items. The argument ends when the
+//! `ClientSink` is finished or dropped. The generated client stub of a method taking a
+//! `RequestStream` sends the items of the `RequestStream` it is given.
+//!
+//! ```no_run
+//! # #[cfg(all(feature = "server", feature = "client", feature = "tokio_runtime", not(feature = "async_std_runtime")))]
+//! # {
+//! # use futures::StreamExt;
+//! # use toy_rpc::Error;
+//! # use toy_rpc::macros::export_impl;
+//! # use toy_rpc::streaming::RequestStream;
+//! # struct Stats;
+//! # #[export_impl]
+//! # impl Stats {
+//! #     #[export_method]
+//! #     async fn sum(&self, mut numbers: RequestStream<i64>) -> Result<i64, Error> {
+//! #         let mut sum = 0;
+//! #         while let Some(number) = numbers.next().await {
+//! #             sum += number?;
+//! #         }
+//! #         Ok(sum)
+//! #     }
+//! # }
+//! # async fn run(client: toy_rpc::Client) -> Result<(), toy_rpc::Error> {
+//! let (numbers, sum) = client.call_with_sink::<i64, i64>("Stats.sum");
+//! for number in 1..=10 {
+//!     numbers.send(number)?;
+//! }
+//! numbers.finish()?;
+//! assert_eq!(sum.await?, 55);
+//!
+//! let sum = client.stats().sum(RequestStream::iter(1..=10)).await?;
+//! # Ok(())
+//! # }
+//! # }
+//! ```
+//!
+//! The items are buffered on the server until the method consumes them.
+
+use futures::{stream, Stream};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use crate::Error;
+
+#[cfg(feature = "server")]
+use crate::protocol::InboundBody;
+#[cfg(feature = "server")]
+use futures::StreamExt;
+#[cfg(feature = "server")]
+use lazy_static::lazy_static;
+#[cfg(feature = "server")]
+use std::{
+    collections::{hash_map::RandomState, HashMap},
+    hash::{BuildHasher, Hasher},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+/// Stream of the items returned by a streaming method
+pub struct RpcStream<T> {
+    inner: Pin<Box<dyn Stream<Item = Result<T, Error>> + Send>>,
+}
+
+impl<T> RpcStream<T> {
+    /// Creates a `RpcStream` of the items of `stream`
+    pub fn new<S>(stream: S) -> Self
+    where
+        S: Stream<Item = Result<T, Error>> + Send + 'static,
+    {
+        Self {
+            inner: Box::pin(stream),
+        }
+    }
+
+    /// Creates a `RpcStream` of the items of an iterator
+    pub fn iter<I>(items: I) -> Self
+    where
+        I: IntoIterator<Item = T>,
+        I::IntoIter: Send + 'static,
+        T: 'static,
+    {
+        Self::new(stream::iter(items.into_iter().map(Ok)))
+    }
+}
+
+impl<T> Stream for RpcStream<T> {
+    type Item = Result<T, Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.inner.as_mut().poll_next(cx)
+    }
+}
+
+/// Stream of the items of the argument of a method, sent by a `ClientSink`
+pub struct RequestStream<T> {
+    inner: Pin<Box<dyn Stream<Item = Result<T, Error>> + Send>>,
+}
+
+impl<T> RequestStream<T> {
+    /// Creates a `RequestStream` of the items of `stream`, to be passed to a client stub
+    pub fn new<S>(stream: S) -> Self
+    where
+        S: Stream<Item = Result<T, Error>> + Send + 'static,
+    {
+        Self {
+            inner: Box::pin(stream),
+        }
+    }
+
+    /// Creates a `RequestStream` of the items of an iterator
+    pub fn iter<I>(items: I) -> Self
+    where
+        I: IntoIterator<Item = T>,
+        I::IntoIter: Send + 'static,
+        T: 'static,
+    {
+        Self::new(stream::iter(items.into_iter().map(Ok)))
+    }
+}
+
+impl<T> Stream for RequestStream<T> {
+    type Item = Result<T, Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.inner.as_mut().poll_next(cx)
+    }
+}
+
+// The handler of a method receives a token instead of the argument of a `SinkRequest`,
+// and the `RequestStream` takes the items registered under the token when it is
+// deserialized. The tokens are random so that the argument of a call cannot be taken
+// by a request that sends a token in its body.
+#[cfg(feature = "server")]
+lazy_static! {
+    static ref SINKS: Mutex<HashMap<u64, flume::Receiver<Box<InboundBody>>>> =
+        Mutex::new(HashMap::new());
+}
+
+#[cfg(feature = "server")]
+fn sinks() -> std::sync::MutexGuard<'static, HashMap<u64, flume::Receiver<Box<InboundBody>>>> {
+    match SINKS.lock() {
+        Ok(sinks) => sinks,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
+
+#[cfg(feature = "server")]
+fn new_token() -> u64 {
+    static COUNT: AtomicU64 = AtomicU64::new(0);
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(COUNT.fetch_add(1, Ordering::Relaxed));
+    hasher.finish()
+}
+
+#[cfg(feature = "server")]
+impl<'de, T> serde::Deserialize<'de> for RequestStream<T>
+where
+    T: serde::de::DeserializeOwned + Send + 'static,
+{
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let token = u64::deserialize(deserializer)?;
+        let items = match sinks().remove(&token) {
+            Some(items) => items,
+            None => {
+                return Err(serde::de::Error::custom(
+                    "The argument is not sent by a ClientSink",
+                ))
+            }
+        };
+        let items = items.into_stream().map(|mut item| {
+            erased_serde::deserialize(&mut item).map_err(|err| Error::ParseError(Box::new(err)))
+        });
+        Ok(Self::new(items))
+    }
+}
+
+/// Argument of a `SinkRequest` whose items are being received
+///
+/// Dropping it ends the `RequestStream` of the handler.
+#[cfg(feature = "server")]
+#[cfg_attr(
+    all(not(feature = "tokio_runtime"), not(feature = "async_std_runtime")),
+    allow(dead_code)
+)]
+pub(crate) struct SinkArgument {
+    items: flume::Sender<Box<InboundBody>>,
+}
+
+#[cfg(feature = "server")]
+#[cfg_attr(
+    all(not(feature = "tokio_runtime"), not(feature = "async_std_runtime")),
+    allow(dead_code)
+)]
+impl SinkArgument {
+    /// Registers a new argument. Returns it with the deserializer to pass to the handler.
+    pub fn open() -> (Self, Box<InboundBody>) {
+        let (items, rx) = flume::unbounded();
+        let mut sinks = sinks();
+        let mut token = new_token();
+        while sinks.contains_key(&token) {
+            token = new_token();
+        }
+        sinks.insert(token, rx);
+        let deserializer = <dyn erased_serde::Deserializer>::erase(SinkToken(Some(token)));
+        (Self { items }, Box::new(deserializer))
+    }
+
+    /// Passes an item to the handler. The item is dropped if the handler has returned.
+    pub fn push(&self, item: Box<InboundBody>) {
+        if self.items.send(item).is_err() {
+            trace!("Dropping an item of an argument that is no longer consumed");
+        }
+    }
+}
+
+/// Deserializer of the token of a `SinkArgument`
+///
+/// The items are unregistered when it is dropped without being deserialized, which
+/// happens if the call fails or is canceled before the handler takes the argument.
+#[cfg(feature = "server")]
+struct SinkToken(Option<u64>);
+
+#[cfg(feature = "server")]
+impl<'de> serde::Deserializer<'de> for SinkToken {
+    type Error = serde::de::value::Error;
+
+    fn deserialize_any<V>(mut self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: serde::de::Visitor<'de>,
+    {
+        match self.0.take() {
+            Some(token) => visitor.visit_u64(token),
+            None => Err(serde::de::Error::custom("The token is already taken")),
+        }
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map struct enum identifier ignored_any
+    }
+}
+
+#[cfg(feature = "server")]
+impl Drop for SinkToken {
+    fn drop(&mut self) {
+        if let Some(token) = self.0 {
+            sinks().remove(&token);
+        }
+    }
+}
@@ -0,0 +1,210 @@
+//! Utilities for property-based testing of the codecs and for testing the timeouts
+//!
+//! This module is enabled with the `test-util` feature flag and provides `proptest`
+//! strategies generating arbitrary `Header`s and nested message bodies, together
+//! with functions checking that a value survives the round trip through a codec.
+//! The round trip is checked both through `Unmarshal`, which is used for the
+//! headers, and through the type erased deserializer from `EraseDeserializer`,
+//! which is used for the bodies.
+//!
+//! The `clock` submodule pauses and advances the clock of the timers by hand, so
+//! that the tests of the timeouts run without waiting.
+//!
+//! # Example
+//!
+//! In an integration test
+//!
+//! ```rust,ignore
+//! use proptest::prelude::*;
+//! use toy_rpc::codec::{DefaultCodec, Reserved};
+//! use toy_rpc::test_util::{arb_header, arb_value, assert_round_trip, assert_erased_round_trip};
+//!
+//! type Codec = DefaultCodec<Reserved, Reserved, Reserved>;
+//!
+//! proptest! {
+//!     #[test]
+//!     fn header_round_trip(header in arb_header()) {
+//!         assert_round_trip::<Codec, _>(&header);
+//!     }
+//!
+//!     #[test]
+//!     fn body_round_trip(body in arb_value()) {
+//!         assert_erased_round_trip::<Codec, _>(&body);
+//!     }
+//! }
+//! ```
+
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::time::Duration;
+
+use proptest::prelude::*;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::codec::{EraseDeserializer, Marshal, Unmarshal};
+use crate::error::Error;
+use crate::protocol::Header;
+
+pub mod clock;
+
+/// A self describing value used as an arbitrary message body
+///
+/// Floating point numbers are not included because not all codecs round trip
+/// them exactly.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Value {
+    /// `()`
+    Unit,
+    /// `bool`
+    Bool(bool),
+    /// `u8`
+    U8(u8),
+    /// `u16`
+    U16(u16),
+    /// `u32`
+    U32(u32),
+    /// `u64`
+    U64(u64),
+    /// `i8`
+    I8(i8),
+    /// `i16`
+    I16(i16),
+    /// `i32`
+    I32(i32),
+    /// `i64`
+    I64(i64),
+    /// `char`
+    Char(char),
+    /// `String`
+    String(String),
+    /// `Vec<u8>`
+    Bytes(Vec<u8>),
+    /// `Option<Value>`
+    Option(Option<Box<Value>>),
+    /// A struct with named fields
+    Struct {
+        /// Name of the field
+        name: String,
+        /// Value of the field
+        value: Box<Value>,
+    },
+    /// `Vec<Value>`
+    Seq(Vec<Value>),
+    /// `BTreeMap<String, Value>`
+    Map(BTreeMap<String, Value>),
+}
+
+/// Strategy generating arbitrary `Duration`s
+pub fn arb_duration() -> impl Strategy<Value = Duration> {
+    (any::<u64>(), 0..1_000_000_000u32).prop_map(|(secs, nanos)| Duration::new(secs, nanos))
+}
+
+/// Strategy generating every variant of `Header` with arbitrary fields
+pub fn arb_header() -> impl Strategy<Value = Header> {
+    prop_oneof![
+        (any::<u16>(), any::<String>(), arb_duration()).prop_map(
+            |(id, service_method, timeout)| Header::Request {
+                id,
+                service_method,
+                timeout
+            }
+        ),
+        (any::<u16>(), any::<bool>()).prop_map(|(id, is_ok)| Header::Response { id, is_ok }),
+        any::<u16>().prop_map(Header::Cancel),
+        (any::<u16>(), any::<String>()).prop_map(|(id, topic)| Header::Publish { id, topic }),
+        (any::<u16>(), any::<String>()).prop_map(|(id, topic)| Header::Subscribe { id, topic }),
+        (any::<u16>(), any::<String>()).prop_map(|(id, topic)| Header::Unsubscribe { id, topic }),
+        any::<u16>().prop_map(Header::Ack),
+        (any::<u16>(), any::<String>(), any::<u32>())
+            .prop_map(|(id, topic, tickets)| { Header::Produce { id, topic, tickets } }),
+        (any::<u16>(), any::<String>()).prop_map(|(id, topic)| Header::Consume { id, topic }),
+        (any::<u16>(), any::<String>()).prop_map(|(id, topic)| Header::Reject { id, topic }),
+        (any::<u16>(), any::<String>(), any::<u64>())
+            .prop_map(|(id, topic, tag)| Header::TaggedPublish { id, topic, tag }),
+        (any::<u16>(), any::<String>(), any::<Option<u64>>())
+            .prop_map(|(id, topic, tag)| Header::AckedPublish { id, topic, tag }),
+        (any::<u16>(), any::<String>(), any::<u32>()).prop_map(|(id, content, marker)| {
+            Header::Ext {
+                id,
+                content,
+                marker,
+            }
+        }),
+    ]
+}
+
+/// Strategy generating arbitrary `Value`s nested up to 4 levels deep
+pub fn arb_value() -> impl Strategy<Value = Value> {
+    let leaf = prop_oneof![
+        Just(Value::Unit),
+        any::<bool>().prop_map(Value::Bool),
+        any::<u8>().prop_map(Value::U8),
+        any::<u16>().prop_map(Value::U16),
+        any::<u32>().prop_map(Value::U32),
+        any::<u64>().prop_map(Value::U64),
+        any::<i8>().prop_map(Value::I8),
+        any::<i16>().prop_map(Value::I16),
+        any::<i32>().prop_map(Value::I32),
+        any::<i64>().prop_map(Value::I64),
+        any::<char>().prop_map(Value::Char),
+        any::<String>().prop_map(Value::String),
+        any::<Vec<u8>>().prop_map(Value::Bytes),
+    ];
+    leaf.prop_recursive(4, 64, 8, |inner| {
+        prop_oneof![
+            proptest::option::of(inner.clone()).prop_map(|v| Value::Option(v.map(Box::new))),
+            (any::<String>(), inner.clone()).prop_map(|(name, value)| Value::Struct {
+                name,
+                value: Box::new(value)
+            }),
+            proptest::collection::vec(inner.clone(), 0..8).prop_map(Value::Seq),
+            proptest::collection::btree_map(any::<String>(), inner, 0..8).prop_map(Value::Map),
+        ]
+    })
+}
+
+/// Marshals `val` with the codec `C` and unmarshals it back
+pub fn round_trip<C, T>(val: &T) -> Result<T, Error>
+where
+    C: Marshal + Unmarshal,
+    T: Serialize + DeserializeOwned,
+{
+    let buf = C::marshal(val)?;
+    C::unmarshal(&buf)
+}
+
+/// Marshals `val` with the codec `C` and deserializes it back with the type erased
+/// deserializer of `C`, which is how message bodies are read
+pub fn erased_round_trip<C, T>(val: &T) -> Result<T, Error>
+where
+    C: Marshal + EraseDeserializer,
+    T: Serialize + DeserializeOwned,
+{
+    let buf = C::marshal(val)?;
+    let mut de = C::from_bytes(buf);
+    erased_serde::deserialize(&mut de).map_err(|err| Error::ParseError(Box::new(err)))
+}
+
+/// Asserts that `val` is unchanged after `round_trip`
+pub fn assert_round_trip<C, T>(val: &T)
+where
+    C: Marshal + Unmarshal,
+    T: Serialize + DeserializeOwned + PartialEq + Debug,
+{
+    match round_trip::<C, T>(val) {
+        Ok(out) => assert_eq!(&out, val),
+        Err(err) => panic!("Round trip of {:?} failed: {}", val, err),
+    }
+}
+
+/// Asserts that `val` is unchanged after `erased_round_trip`
+pub fn assert_erased_round_trip<C, T>(val: &T)
+where
+    C: Marshal + EraseDeserializer,
+    T: Serialize + DeserializeOwned + PartialEq + Debug,
+{
+    match erased_round_trip::<C, T>(val) {
+        Ok(out) => assert_eq!(&out, val),
+        Err(err) => panic!("Erased round trip of {:?} failed: {}", val, err),
+    }
+}
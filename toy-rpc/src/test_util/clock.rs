@@ -0,0 +1,171 @@
+//! Clock of the timers that can be paused and advanced by hand
+//!
+//! Once the clock is paused with `pause`, the time only moves forward with
+//! `advance`. The timeouts of the calls and of their executions, the grace period
+//! of the shutdowns, the timers of the pubsub broker (delayed publications,
+//! redeliveries and the collection of idle topics) and the clock jump watchdog of
+//! the client then fire as soon as the clock is advanced past their deadlines, so
+//! that a test of a ten seconds timeout does not wait ten seconds and does not
+//! depend on the scheduling of the machine.
+//!
+//! Only the timers started while the clock is paused follow it, and `resume` fires
+//! all the timers still waiting on the paused clock. The clock is shared by the
+//! whole process, so a test pausing it should run in its own test binary or at
+//! least not in parallel with other tests. The timers of the runtime used directly
+//! by the handlers, eg. `tokio::time::sleep`, are not affected.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use toy_rpc::test_util::clock;
+//!
+//! clock::pause();
+//! let call = spawn_task(async move {
+//!     client.timeout(Duration::from_secs(10)).call::<_, ()>("Slow.method", ()).await
+//! });
+//! // waits for the timeout of the call to be started
+//! while clock::pending_timers() == 0 {
+//!     tokio::task::yield_now().await;
+//! }
+//! clock::advance(Duration::from_secs(10));
+//! assert!(matches!(call.await?, Err(Error::Timeout(_))));
+//! clock::resume();
+//! ```
+
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant, SystemTime};
+
+static CLOCK: Mutex<Option<PausedClock>> = Mutex::new(None);
+
+/// IDs of the timers waiting on the paused clock
+static TIMER_ID: AtomicU64 = AtomicU64::new(0);
+
+struct PausedClock {
+    paused_at: Instant,
+    system_paused_at: SystemTime,
+    advanced: Duration,
+    timers: BTreeMap<(Instant, u64), Waker>,
+}
+
+impl PausedClock {
+    fn now(&self) -> Instant {
+        self.paused_at + self.advanced
+    }
+}
+
+fn lock() -> MutexGuard<'static, Option<PausedClock>> {
+    match CLOCK.lock() {
+        Ok(clock) => clock,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
+
+/// Pauses the clock. Does nothing if the clock is already paused.
+pub fn pause() {
+    let mut clock = lock();
+    if clock.is_none() {
+        *clock = Some(PausedClock {
+            paused_at: Instant::now(),
+            system_paused_at: SystemTime::now(),
+            advanced: Duration::ZERO,
+            timers: BTreeMap::new(),
+        });
+    }
+}
+
+/// Lets the clock run again and fires all the timers waiting on the paused clock
+pub fn resume() {
+    let paused = lock().take();
+    if let Some(paused) = paused {
+        paused.timers.into_values().for_each(Waker::wake);
+    }
+}
+
+/// Returns whether the clock is paused
+pub fn is_paused() -> bool {
+    lock().is_some()
+}
+
+/// Moves the paused clock forward by `duration` and fires the timers that are due.
+/// The tasks of the timers run once the caller yields to the runtime.
+///
+/// # Panics
+///
+/// Panics if the clock is not paused.
+pub fn advance(duration: Duration) {
+    let due = {
+        let mut clock = lock();
+        let paused = clock
+            .as_mut()
+            .expect("The clock must be paused before it is advanced");
+        paused.advanced += duration;
+        let not_due = paused.timers.split_off(&(paused.now(), u64::MAX));
+        std::mem::replace(&mut paused.timers, not_due)
+    };
+    due.into_values().for_each(Waker::wake);
+}
+
+/// Returns the number of timers waiting on the paused clock, which can be used to
+/// wait for a timeout to be started before advancing the clock
+pub fn pending_timers() -> usize {
+    lock().as_ref().map_or(0, |paused| paused.timers.len())
+}
+
+pub(crate) fn paused_now() -> Option<Instant> {
+    lock().as_ref().map(PausedClock::now)
+}
+
+pub(crate) fn paused_system_now() -> Option<SystemTime> {
+    lock()
+        .as_ref()
+        .map(|paused| paused.system_paused_at + paused.advanced)
+}
+
+/// Returns a timer on the paused clock, or `None` if the clock is not paused
+#[cfg_attr(
+    all(not(feature = "tokio_runtime"), not(feature = "async_std_runtime")),
+    allow(dead_code)
+)]
+pub(crate) fn paused_sleep(duration: Duration) -> Option<PausedSleep> {
+    let deadline = paused_now()? + duration;
+    Some(PausedSleep {
+        deadline,
+        id: TIMER_ID.fetch_add(1, Ordering::Relaxed),
+    })
+}
+
+/// Timer completing once the paused clock is advanced past its deadline, or once the
+/// clock is resumed
+pub(crate) struct PausedSleep {
+    deadline: Instant,
+    id: u64,
+}
+
+impl Future for PausedSleep {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let key = (self.deadline, self.id);
+        let mut clock = lock();
+        match clock.as_mut() {
+            Some(paused) if paused.now() < self.deadline => {
+                paused.timers.insert(key, cx.waker().clone());
+                Poll::Pending
+            }
+            _ => Poll::Ready(()),
+        }
+    }
+}
+
+impl Drop for PausedSleep {
+    fn drop(&mut self) {
+        if let Some(paused) = lock().as_mut() {
+            paused.timers.remove(&(self.deadline, self.id));
+        }
+    }
+}
@@ -0,0 +1,333 @@
+//! Time that the calls spend queued and executing on the server
+//!
+//! Once the server has read a request, the call waits in a queue until its handler
+//! starts executing: until the executor of the runtime polls the task of the call,
+//! and for the methods exported with `#[export_method(blocking)]`, until a thread of
+//! the blocking pool is available. The server keeps this queue wait apart from the
+//! execution of the handler, so that the operators can tell a server that is
+//! saturated from a handler that is slow.
+//!
+//! - `ServerBuilder::queue_timeout` limits the time that a call may wait in the
+//!   queue, and `ServerBuilder::execution_timeout` the time that its handler may
+//!   execute. Both are bounded by the timeout of the request, which still covers
+//!   the whole call.
+//! - A call over a limit fails with `Error::ServerTimeout`, which tells in which
+//!   phase the call was stopped and how long it spent in each.
+//! - With `ServerBuilder::report_timing`, the server attaches the `Timing` of each
+//!   call returning a single response to the metadata of the response, which the
+//!   client reads back with `Timing::from_metadata`.
+//!
+//! # Example
+//!
+//! ```no_run
+//! # #[cfg(all(feature = "server", feature = "client", feature = "tokio_runtime", not(feature = "async_std_runtime")))]
+//! # {
+//! # use std::sync::Arc;
+//! # use std::time::Duration;
+//! # use toy_rpc::{Client, Error, Server};
+//! # use toy_rpc::macros::export_impl;
+//! # use toy_rpc::metadata::Metadata;
+//! # use toy_rpc::timing::{TimeoutPhase, Timing};
+//! # struct Example;
+//! # #[export_impl]
+//! # impl Example {
+//! #     #[export_method]
+//! #     async fn echo(&self, args: String) -> Result<String, String> {
+//! #         Ok(args)
+//! #     }
+//! # }
+//! # async fn run(client: Client) {
+//! # let example_service = Arc::new(Example);
+//! let server = Server::builder()
+//!     .register(example_service)
+//!     .queue_timeout(Duration::from_millis(200))
+//!     .execution_timeout(Duration::from_secs(5))
+//!     .report_timing(true)
+//!     .build();
+//!
+//! match client
+//!     .call_with_metadata::<_, String>("Echo.echo", "hello".to_string(), Metadata::new())
+//!     .await
+//! {
+//!     Ok((_, metadata)) => println!("{:?}", Timing::from_metadata(&metadata)),
+//!     Err(Error::ServerTimeout(timeout)) if timeout.phase == TimeoutPhase::Queue => {
+//!         println!("server is saturated, queued for {:?}", timeout.timing.queued)
+//!     }
+//!     Err(err) => println!("{}", err),
+//! }
+//! # }
+//! # }
+//! ```
+
+use std::fmt;
+use std::time::Duration;
+
+use crate::metadata::Metadata;
+
+#[cfg(feature = "server")]
+use std::{
+    sync::{Mutex, MutexGuard},
+    time::Instant,
+};
+
+#[cfg(feature = "server")]
+use crate::clock;
+
+/// Key of the response metadata holding the microseconds that the call was queued
+pub const QUEUED: &str = "queued-us";
+
+/// Key of the response metadata holding the microseconds that the handler executed
+pub const EXECUTED: &str = "executed-us";
+
+/// Time that a call spent on the server
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Timing {
+    /// Time from the request being read until the handler started executing
+    pub queued: Duration,
+    /// Time that the handler executed for
+    pub executed: Duration,
+}
+
+impl Timing {
+    /// Reads the timing from the metadata of a response. This is `None` if the
+    /// server does not report the timing of the calls.
+    pub fn from_metadata(metadata: &Metadata) -> Option<Self> {
+        let micros = |key| metadata.get(key)?.parse().ok().map(Duration::from_micros);
+        Some(Self {
+            queued: micros(QUEUED)?,
+            executed: micros(EXECUTED)?,
+        })
+    }
+}
+
+/// Phase of a call when it timed out on the server
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeoutPhase {
+    /// The handler had not started executing
+    Queue,
+    /// The handler was executing
+    Execution,
+}
+
+impl fmt::Display for TimeoutPhase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Queue => f.write_str("queue"),
+            Self::Execution => f.write_str("execution"),
+        }
+    }
+}
+
+/// A call that timed out on the server
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ServerTimeout {
+    /// Phase of the call when it timed out
+    pub phase: TimeoutPhase,
+    /// Time that the call spent in each phase
+    pub timing: Timing,
+}
+
+impl fmt::Display for ServerTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} timeout after {:?} queued and {:?} executing",
+            self.phase, self.timing.queued, self.timing.executed
+        )
+    }
+}
+
+impl ServerTimeout {
+    /// Encodes the timeout as the message of an `ErrorMessage::Timeout`, eg.
+    /// `"phase=queue queued-us=1500 executed-us=0"`
+    #[cfg(feature = "server")]
+    pub(crate) fn encode(&self) -> String {
+        format!(
+            "phase={} {}={} {}={}",
+            self.phase,
+            QUEUED,
+            self.timing.queued.as_micros(),
+            EXECUTED,
+            self.timing.executed.as_micros()
+        )
+    }
+
+    /// Decodes the message of an `ErrorMessage::Timeout`
+    pub(crate) fn decode(msg: &str) -> Option<Self> {
+        let fields: Metadata = msg
+            .split_whitespace()
+            .filter_map(|field| field.split_once('='))
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        let phase = match fields.get("phase").map(String::as_str) {
+            Some("queue") => TimeoutPhase::Queue,
+            Some("execution") => TimeoutPhase::Execution,
+            _ => return None,
+        };
+        Some(Self {
+            phase,
+            timing: Timing::from_metadata(&fields)?,
+        })
+    }
+}
+
+/// Limits of the phases of the calls, and whether their timing is reported
+#[cfg(feature = "server")]
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct TimingPolicy {
+    pub queue_timeout: Option<Duration>,
+    pub execution_timeout: Option<Duration>,
+    pub report: bool,
+}
+
+#[cfg(feature = "server")]
+#[cfg_attr(
+    all(not(feature = "tokio_runtime"), not(feature = "async_std_runtime")),
+    allow(dead_code)
+)]
+impl TimingPolicy {
+    /// Limit of the time queued of a call whose request times out after `timeout`
+    pub fn queue_limit(&self, timeout: Duration) -> Duration {
+        self.queue_timeout
+            .map_or(timeout, |limit| limit.min(timeout))
+    }
+
+    /// Limit of the execution of a call that was queued for `queued`
+    pub fn execution_limit(&self, timeout: Duration, queued: Duration) -> Duration {
+        let left = timeout.saturating_sub(queued);
+        self.execution_timeout.map_or(left, |limit| limit.min(left))
+    }
+}
+
+#[cfg(feature = "server")]
+#[derive(Debug, Default)]
+struct Marks {
+    // the execution is started by the blocking pool
+    deferred: bool,
+    started_at: Option<Instant>,
+    finished_at: Option<Instant>,
+}
+
+/// Measures the phases of a call, from the creation of its context
+#[cfg(feature = "server")]
+#[derive(Debug)]
+pub(crate) struct Stopwatch {
+    queued_at: Instant,
+    marks: Mutex<Marks>,
+    started: (flume::Sender<()>, flume::Receiver<()>),
+}
+
+#[cfg(feature = "server")]
+impl Default for Stopwatch {
+    fn default() -> Self {
+        Self {
+            queued_at: clock::now(),
+            marks: Mutex::new(Marks::default()),
+            started: flume::bounded(1),
+        }
+    }
+}
+
+#[cfg(feature = "server")]
+#[cfg_attr(
+    all(not(feature = "tokio_runtime"), not(feature = "async_std_runtime")),
+    allow(dead_code)
+)]
+impl Stopwatch {
+    fn lock(&self) -> MutexGuard<'_, Marks> {
+        match self.marks.lock() {
+            Ok(marks) => marks,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    /// Leaves the start of the execution to the blocking pool
+    pub fn defer_start(&self) {
+        self.lock().deferred = true;
+    }
+
+    /// Marks the start of the execution when the task of the call is polled, unless
+    /// it is left to the blocking pool
+    pub fn start_task(&self) {
+        if !self.lock().deferred {
+            self.start();
+        }
+    }
+
+    /// Marks the start of the execution
+    pub fn start(&self) {
+        let mut marks = self.lock();
+        if marks.started_at.is_none() {
+            marks.started_at = Some(clock::now());
+            let _ = self.started.0.try_send(());
+        }
+    }
+
+    /// Waits for the start of the execution
+    pub async fn started(&self) {
+        let _ = self.started.1.recv_async().await;
+    }
+
+    /// Marks the end of the execution and returns the timing of the call
+    pub fn finish(&self) -> Timing {
+        let mut marks = self.lock();
+        let finished_at = *marks.finished_at.get_or_insert_with(clock::now);
+        match marks.started_at {
+            Some(started_at) => Timing {
+                queued: started_at.saturating_duration_since(self.queued_at),
+                executed: finished_at.saturating_duration_since(started_at),
+            },
+            None => Timing {
+                queued: finished_at.saturating_duration_since(self.queued_at),
+                executed: Duration::ZERO,
+            },
+        }
+    }
+
+    /// Time that the call has been queued for, so far
+    pub fn queued(&self) -> Duration {
+        let end = self.lock().started_at.unwrap_or_else(clock::now);
+        end.saturating_duration_since(self.queued_at)
+    }
+}
+
+#[cfg(all(test, feature = "server"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timeouts_round_trip() {
+        let timeout = ServerTimeout {
+            phase: TimeoutPhase::Queue,
+            timing: Timing {
+                queued: Duration::from_micros(1500),
+                executed: Duration::ZERO,
+            },
+        };
+        assert_eq!(timeout.encode(), "phase=queue queued-us=1500 executed-us=0");
+        assert_eq!(ServerTimeout::decode(&timeout.encode()), Some(timeout));
+        assert_eq!(
+            ServerTimeout::decode("phase=sleep queued-us=1 executed-us=0"),
+            None
+        );
+        assert_eq!(ServerTimeout::decode("request reached timeout"), None);
+    }
+
+    #[test]
+    fn limits_are_bounded_by_the_request() {
+        let policy = TimingPolicy {
+            queue_timeout: Some(Duration::from_secs(1)),
+            execution_timeout: Some(Duration::from_secs(5)),
+            report: false,
+        };
+        let timeout = Duration::from_secs(3);
+        assert_eq!(policy.queue_limit(timeout), Duration::from_secs(1));
+        assert_eq!(
+            policy.execution_limit(timeout, Duration::from_millis(500)),
+            Duration::from_millis(2500)
+        );
+        let policy = TimingPolicy::default();
+        assert_eq!(policy.queue_limit(timeout), timeout);
+        assert_eq!(policy.execution_limit(timeout, timeout), Duration::ZERO);
+    }
+}
@@ -0,0 +1,163 @@
+//! Transactional call groups
+//!
+//! A client opens a `Transaction` with `Client::transaction` and makes several calls
+//! in it. Each call carries the id of the transaction along with its arguments in an
+//! `InTransaction`, so that the services can keep the changes of the transaction
+//! apart. Committing or rolling back the transaction calls the `Transactional` hook
+//! of every service that took part in it.
+//!
+//! This is a lightweight building block, eg. for sagas, rather than a distributed
+//! transaction protocol. The services are committed one after another, so a failed
+//! commit does not undo the commits of the other services, and a transaction that
+//! is dropped without being committed or rolled back stays open on the services.
+//!
+//! # Example
+//!
+//! ```no_run
+//! # #[cfg(all(feature = "server", feature = "client", feature = "tokio_runtime", not(feature = "async_std_runtime")))]
+//! # {
+//! # use std::collections::HashMap;
+//! # use async_trait::async_trait;
+//! # use futures::lock::Mutex;
+//! # use toy_rpc::{Client, Error, Server};
+//! # use toy_rpc::macros::export_impl;
+//! # use toy_rpc::transaction::{InTransaction, TransactionId, Transactional};
+//! # #[derive(Default)]
+//! # struct Bank {
+//! #     pending: Mutex<HashMap<TransactionId, Vec<u32>>>,
+//! # }
+//! # impl Bank {
+//! #     fn new() -> Self {
+//! #         Self::default()
+//! #     }
+//! # }
+//! #[export_impl]
+//! impl Bank {
+//!     #[export_method]
+//!     async fn deposit(&self, args: InTransaction<u32>) -> Result<(), Error> {
+//!         self.pending.lock().await.entry(args.transaction).or_default().push(args.args);
+//!         Ok(())
+//!     }
+//! }
+//!
+//! #[async_trait]
+//! impl Transactional for Bank {
+//!     async fn commit(&self, transaction: TransactionId) -> Result<(), Error> {
+//!         // apply the deposits of the transaction
+//! #         Ok(())
+//!     }
+//!
+//!     async fn rollback(&self, transaction: TransactionId) -> Result<(), Error> {
+//!         // drop the deposits of the transaction
+//! #         Ok(())
+//!     }
+//! }
+//!
+//! # async fn run(client: Client) -> Result<(), Error> {
+//! let server = Server::builder()
+//!     .register_transactional(Bank::new())
+//!     .build();
+//!
+//! let transaction = client.transaction();
+//! transaction.call::<_, ()>("Bank.deposit", 10u32).await?;
+//! transaction.call::<_, ()>("Bank.deposit", 20u32).await?;
+//! transaction.commit().await?;
+//! # Ok(())
+//! # }
+//! # }
+//! ```
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::Error;
+
+/// Name of the method that commits a transaction on a transactional service
+pub const COMMIT_METHOD: &str = "commit_transaction";
+
+/// Name of the method that rolls back a transaction on a transactional service
+pub const ROLLBACK_METHOD: &str = "rollback_transaction";
+
+/// ID of a transaction
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct TransactionId(pub u64);
+
+impl fmt::Display for TransactionId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+/// Arguments of a call made in a transaction
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InTransaction<A> {
+    /// ID of the transaction
+    pub transaction: TransactionId,
+    /// Arguments of the call
+    pub args: A,
+}
+
+/// Hook of a service that takes part in transactions
+///
+/// A service implementing `Transactional` is registered with
+/// `ServerBuilder::register_transactional`.
+#[async_trait]
+pub trait Transactional: Send + Sync + 'static {
+    /// Makes the changes of the transaction permanent
+    async fn commit(&self, transaction: TransactionId) -> Result<(), Error>;
+
+    /// Discards the changes of the transaction
+    async fn rollback(&self, transaction: TransactionId) -> Result<(), Error>;
+}
+
+#[cfg(feature = "server")]
+mod handlers {
+    use erased_serde as erased;
+    use std::sync::Arc;
+
+    use super::{TransactionId, Transactional};
+    use crate::{
+        service::{HandlerResultFut, Success},
+        Error,
+    };
+
+    fn deserialize_id(
+        mut deserializer: Box<dyn erased::Deserializer<'static> + Send>,
+    ) -> Result<TransactionId, Error> {
+        erased::deserialize(&mut deserializer).map_err(|err| Error::ParseError(Box::new(err)))
+    }
+
+    /// Handler of `COMMIT_METHOD`
+    pub(crate) fn commit_handler<S>(
+        service: Arc<S>,
+        deserializer: Box<dyn erased::Deserializer<'static> + Send>,
+    ) -> HandlerResultFut
+    where
+        S: Transactional + ?Sized,
+    {
+        let transaction = deserialize_id(deserializer);
+        Box::pin(async move {
+            service.commit(transaction?).await?;
+            Ok(Success::Reply(Box::new(())))
+        })
+    }
+
+    /// Handler of `ROLLBACK_METHOD`
+    pub(crate) fn rollback_handler<S>(
+        service: Arc<S>,
+        deserializer: Box<dyn erased::Deserializer<'static> + Send>,
+    ) -> HandlerResultFut
+    where
+        S: Transactional + ?Sized,
+    {
+        let transaction = deserialize_id(deserializer);
+        Box::pin(async move {
+            service.rollback(transaction?).await?;
+            Ok(Success::Reply(Box::new(())))
+        })
+    }
+}
+
+#[cfg(feature = "server")]
+pub(crate) use handlers::{commit_handler, rollback_handler};
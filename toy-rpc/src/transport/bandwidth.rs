@@ -0,0 +1,210 @@
+//! Traffic accounting and bandwidth limits of a connection
+//!
+//! A `TrafficMeter` counts the frames and the bytes that the transport of a
+//! connection reads and writes, and optionally caps the rate of either direction
+//! with a token bucket on bytes. A bucket holds up to `burst` bytes and is refilled
+//! at `bytes_per_second`. A frame is written, or the next frame is read, right away
+//! while the bucket holds enough bytes, and once the bucket is empty the transport
+//! waits for as long as it takes to refill the bytes of the frame. A frame larger
+//! than the burst is not split, it just makes the transport wait longer afterwards.
+//!
+//! The bytes of the frame transport are counted as they are on the wire, that is
+//! with the header of each frame and after the compression of the payload. The
+//! WebSocket transport counts the payloads of its messages, and the transport of
+//! the `serde_json` codec counts its lines.
+//!
+//! The server meters every connection it serves, see
+//! `ServerBuilder::outbound_bandwidth`, `ServerBuilder::inbound_bandwidth` and
+//! `Server::connection_stats`.
+
+#![cfg_attr(
+    not(any(feature = "async_std_runtime", feature = "tokio_runtime")),
+    allow(dead_code)
+)]
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use toy_rpc_core::frame::FRAME_HEADER_LEN;
+
+use crate::clock;
+
+/// Rate and burst of a bandwidth limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BandwidthLimit {
+    /// Number of bytes per second that the transport is limited to on average
+    pub bytes_per_second: u64,
+    /// Number of bytes that can be transferred at once after the connection was
+    /// idle
+    pub burst: u64,
+}
+
+impl BandwidthLimit {
+    /// Creates a bandwidth limit. The rate and the burst are at least one byte.
+    pub fn new(bytes_per_second: u64, burst: u64) -> Self {
+        Self {
+            bytes_per_second: bytes_per_second.max(1),
+            burst: burst.max(1),
+        }
+    }
+}
+
+/// Numbers of frames and bytes of a connection
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TrafficStats {
+    /// Number of frames read
+    pub frames_read: u64,
+    /// Number of bytes read, including the headers of the frames
+    pub bytes_read: u64,
+    /// Number of frames written
+    pub frames_written: u64,
+    /// Number of bytes written, including the headers of the frames
+    pub bytes_written: u64,
+    /// Total time that the transport waited because of the bandwidth limits
+    pub throttled: Duration,
+}
+
+/// Length on the wire of a frame with a payload of `payload_len` bytes, including
+/// the magic byte and the header
+#[cfg_attr(feature = "serde_json", allow(dead_code))]
+pub(crate) fn frame_len(payload_len: usize) -> usize {
+    1 + FRAME_HEADER_LEN + payload_len
+}
+
+/// Token bucket on bytes
+#[derive(Debug)]
+struct TokenBucket {
+    limit: BandwidthLimit,
+    /// Bytes in the bucket, which is negative while the transport is in debt
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl TokenBucket {
+    fn new(limit: BandwidthLimit, now: Instant) -> Self {
+        Self {
+            limit,
+            tokens: limit.burst as f64,
+            refilled: now,
+        }
+    }
+
+    /// Takes `bytes` out of the bucket, and returns how long the transport has to
+    /// wait until the bucket is refilled back to zero
+    fn take(&mut self, bytes: u64, now: Instant) -> Duration {
+        let rate = self.limit.bytes_per_second.max(1) as f64;
+        let elapsed = now.saturating_duration_since(self.refilled).as_secs_f64();
+        self.refilled = now;
+        self.tokens = (self.tokens + elapsed * rate).min(self.limit.burst as f64);
+        self.tokens -= bytes as f64;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / rate)
+        }
+    }
+}
+
+/// Counts the traffic of the transport of a connection and enforces its bandwidth
+/// limits
+///
+/// The meter is attached to the halves of a split codec with
+/// `CodecRead::set_meter` and `CodecWrite::set_meter`.
+#[derive(Debug, Default)]
+pub struct TrafficMeter {
+    outbound: Option<Mutex<TokenBucket>>,
+    inbound: Option<Mutex<TokenBucket>>,
+    frames_read: AtomicU64,
+    bytes_read: AtomicU64,
+    frames_written: AtomicU64,
+    bytes_written: AtomicU64,
+    throttled_micros: AtomicU64,
+}
+
+impl TrafficMeter {
+    /// Creates a meter that limits the bytes written to `outbound` and the bytes
+    /// read to `inbound`, if any
+    pub fn new(outbound: Option<BandwidthLimit>, inbound: Option<BandwidthLimit>) -> Self {
+        let now = clock::now();
+        Self {
+            outbound: outbound.map(|limit| Mutex::new(TokenBucket::new(limit, now))),
+            inbound: inbound.map(|limit| Mutex::new(TokenBucket::new(limit, now))),
+            ..Default::default()
+        }
+    }
+
+    /// Returns the numbers of frames and bytes counted so far
+    pub fn stats(&self) -> TrafficStats {
+        TrafficStats {
+            frames_read: self.frames_read.load(Ordering::Relaxed),
+            bytes_read: self.bytes_read.load(Ordering::Relaxed),
+            frames_written: self.frames_written.load(Ordering::Relaxed),
+            bytes_written: self.bytes_written.load(Ordering::Relaxed),
+            throttled: Duration::from_micros(self.throttled_micros.load(Ordering::Relaxed)),
+        }
+    }
+
+    /// Counts `bytes` about to be written, and waits until the outbound limit
+    /// allows them
+    pub(crate) async fn write(&self, bytes: usize) {
+        self.frames_written.fetch_add(1, Ordering::Relaxed);
+        self.bytes_written
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+        self.throttle(self.outbound.as_ref(), bytes).await
+    }
+
+    /// Counts `bytes` that were read, and waits until the inbound limit allows
+    /// reading more
+    pub(crate) async fn read(&self, bytes: usize) {
+        self.frames_read.fetch_add(1, Ordering::Relaxed);
+        self.bytes_read.fetch_add(bytes as u64, Ordering::Relaxed);
+        self.throttle(self.inbound.as_ref(), bytes).await
+    }
+
+    async fn throttle(&self, bucket: Option<&Mutex<TokenBucket>>, bytes: usize) {
+        let wait = match bucket {
+            Some(bucket) => match bucket.lock() {
+                Ok(mut bucket) => bucket.take(bytes as u64, clock::now()),
+                Err(poisoned) => poisoned.into_inner().take(bytes as u64, clock::now()),
+            },
+            None => return,
+        };
+        if wait.is_zero() {
+            return;
+        }
+        self.throttled_micros
+            .fetch_add(wait.as_micros() as u64, Ordering::Relaxed);
+        #[cfg(any(
+            all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+            all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+        ))]
+        clock::sleep(wait).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn token_bucket() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(BandwidthLimit::new(1000, 500), start);
+
+        // the burst is available right away
+        assert_eq!(bucket.take(300, start), Duration::ZERO);
+        assert_eq!(bucket.take(200, start), Duration::ZERO);
+        // then the bytes have to be refilled
+        assert_eq!(bucket.take(100, start), Duration::from_millis(100));
+
+        // a frame larger than the burst puts the bucket in debt
+        let later = start + Duration::from_millis(100);
+        assert_eq!(bucket.take(1000, later), Duration::from_secs(1));
+
+        // an idle connection does not save more than the burst
+        let idle = later + Duration::from_secs(60);
+        assert_eq!(bucket.take(500, idle), Duration::ZERO);
+        assert_eq!(bucket.take(250, idle), Duration::from_millis(250));
+    }
+}
@@ -1,17 +1,19 @@
 //! A custom framed binary transport
+//!
+//! The frames are defined in the `no_std` crate `toy-rpc-core`, see
+//! `toy_rpc_core::frame` for the encoding.
 
 use async_trait::async_trait;
-use bincode::{DefaultOptions, Options};
 use cfg_if::cfg_if;
-use lazy_static::lazy_static;
-use serde::{Deserialize, Serialize};
 use std::io::ErrorKind;
 
 use crate::message::MessageId;
-use crate::{error::Error, util::GracefulShutdown};
+use crate::{compression, error::Error, util::GracefulShutdown};
+
+pub use toy_rpc_core::frame::{FrameHeader, FrameId, PayloadLen, PayloadType};
+use toy_rpc_core::frame::{FRAME_HEADER_LEN, MAGIC};
 
 const INVALID_PROTOCOL: &str = "Magic byte mismatch.\rClient may be using a different protocol or version.\rClient of version <0.5.0 is not compatible with Server of version >0.5.0";
-const END_FRAME_ID: FrameId = 131;
 
 cfg_if! {
     if #[cfg(any(
@@ -28,17 +30,6 @@ cfg_if! {
     }
 }
 
-type FrameId = u8;
-type PayloadLen = u32;
-const MAGIC: u8 = 13;
-
-// const HEADER_LEN: usize = 8; // header length in bytes
-lazy_static! {
-    static ref HEADER_LEN: usize =
-        // default uses fixint size
-        bincode::serialized_size(&FrameHeader::default()).unwrap() as usize;
-}
-
 /// Trait for custom binary transport protocol
 ///
 /// `AsyncBufRead` or `AsyncRead` is required because `async_std::net::TcpStream`
@@ -62,86 +53,6 @@ pub trait FrameWrite {
         -> Result<(), Error>;
 }
 
-/// Header of a frame
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
-pub struct FrameHeader {
-    message_id: MessageId,
-    frame_id: FrameId,
-    payload_type: u8, // this is not used for now
-    payload_len: PayloadLen,
-}
-
-impl FrameHeader {
-    /// Constructs a new frame header
-    pub fn new(
-        message_id: MessageId,
-        frame_id: FrameId,
-        payload_type: PayloadType,
-        payload_len: PayloadLen,
-    ) -> Self {
-        Self {
-            message_id,
-            frame_id,
-            payload_type: payload_type.into(),
-            payload_len,
-        }
-    }
-
-    /// Constructs a new frame header from bytes
-    pub fn from_slice(buf: &[u8]) -> Result<Self, Error> {
-        DefaultOptions::new()
-            .with_fixint_encoding()
-            .deserialize(&buf)
-            .map_err(|err| Error::ParseError(err))
-    }
-
-    /// Convert a frame header to bytes
-    pub fn to_vec(&self) -> Result<Vec<u8>, Error> {
-        DefaultOptions::new()
-            .with_fixint_encoding()
-            .serialize(self)
-            .map_err(|err| Error::ParseError(err))
-    }
-}
-
-/// Type of payload carried by a frame
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub enum PayloadType {
-    /// Message header
-    Header,
-    /// Message body
-    Data,
-    /// Message trailer
-    Trailer,
-}
-
-impl Default for PayloadType {
-    fn default() -> Self {
-        PayloadType::Header
-    }
-}
-
-impl From<u8> for PayloadType {
-    fn from(t: u8) -> Self {
-        match t {
-            0 => Self::Header,
-            1 => Self::Data,
-            2 => Self::Trailer,
-            _ => Self::Trailer,
-        }
-    }
-}
-
-impl From<PayloadType> for u8 {
-    fn from(t: PayloadType) -> Self {
-        match t {
-            PayloadType::Header => 0,
-            PayloadType::Data => 1,
-            PayloadType::Trailer => 2,
-        }
-    }
-}
-
 /// Frame
 #[derive(Debug)]
 pub struct Frame {
@@ -153,6 +64,9 @@ pub struct Frame {
     pub payload_type: PayloadType,
     /// Payload
     pub payload: Vec<u8>,
+    /// Length of the payload on the wire, which is shorter than `payload` if the
+    /// payload was compressed
+    pub payload_len: PayloadLen,
 }
 
 impl Frame {
@@ -167,6 +81,7 @@ impl Frame {
             message_id,
             frame_id,
             payload_type,
+            payload_len: payload.len() as PayloadLen,
             payload,
         }
     }
@@ -186,31 +101,39 @@ impl<R: AsyncRead + Unpin + Send> FrameRead for R {
         }
 
         // read header
-        let mut buf = vec![0; *HEADER_LEN];
+        let mut buf = vec![0; FRAME_HEADER_LEN];
         let _ = self.read_exact(&mut buf).await.ok()?;
-        let header = match FrameHeader::from_slice(&buf) {
+        let header = match FrameHeader::decode(&buf) {
             Ok(h) => h,
-            Err(e) => return Some(Err(e)),
+            Err(e) => return Some(Err(e.into())),
         };
 
         // determine if end frame is received
-        if let PayloadType::Trailer = header.payload_type.into() {
-            if header.frame_id == END_FRAME_ID && header.message_id == 0 && header.payload_len == 0
-            {
-                return None;
-            }
+        if header.is_end() {
+            return None;
         }
 
         // read frame payload
         let mut payload = vec![0; header.payload_len as usize];
         let _ = self.read_exact(&mut payload).await.ok()?;
 
-        Some(Ok(Frame::new(
+        // decompress the payload if the header says it is compressed
+        let payload = match header.compression() {
+            0 => payload,
+            id => match compression::decompress(id, &payload, PayloadLen::MAX as usize) {
+                Ok(payload) => payload,
+                Err(err) => return Some(Err(err)),
+            },
+        };
+
+        let mut frame = Frame::new(
             header.message_id,
             header.frame_id,
             header.payload_type.into(),
             payload,
-        )))
+        );
+        frame.payload_len = header.payload_len;
+        Some(Ok(frame))
     }
 }
 
@@ -244,14 +167,20 @@ impl<W: AsyncWrite + Unpin + Send> FrameWrite for W {
         // let header = FrameHeader::new(message_id, frame_id, payload_type, payload.len() as u32);
 
         // write magic first
-        self.write_all(&[MAGIC]).await?;
+        self.write_all(&[MAGIC])
+            .await
+            .map_err(Error::from_transport)?;
 
         // write header
-        self.write_all(&frame_header.to_vec()?).await?;
+        self.write_all(&frame_header.encode())
+            .await
+            .map_err(Error::from_transport)?;
 
         // write payload
-        let _ = self.write_all(&payload).await?;
-        self.flush().await?;
+        self.write_all(payload)
+            .await
+            .map_err(Error::from_transport)?;
+        self.flush().await.map_err(Error::from_transport)?;
 
         Ok(())
     }
@@ -260,6 +189,7 @@ impl<W: AsyncWrite + Unpin + Send> FrameWrite for W {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use serde::{Deserialize, Serialize};
 
     #[test]
     fn frame_header_length() {
@@ -296,10 +226,10 @@ where
     async fn close(&mut self) {
         // send a trailer frame with message id 0 and END_FRAME_ID and empty payload
         // let end_frame = Frame::new(0, END_FRAME_ID, PayloadType::Trailer, Vec::with_capacity(0));
-        let end_frame_header = FrameHeader::new(0, END_FRAME_ID, PayloadType::Trailer, 0);
+        let end_frame_header = FrameHeader::end();
         let payload = Vec::with_capacity(0);
         self.write_frame(end_frame_header, &payload)
             .await
-            .unwrap_or_else(|e| log::error!("{}", e));
+            .unwrap_or_else(|e| error!("{}", e));
     }
 }
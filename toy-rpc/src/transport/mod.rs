@@ -4,19 +4,31 @@ use async_trait::async_trait;
 
 use crate::error::Error;
 
+pub mod bandwidth;
+
 #[cfg(all(
+    not(feature = "serde_json"),
+    any(feature = "async_std_runtime", feature = "tokio_runtime",)
+))]
+pub(crate) mod frame;
+
+#[cfg(all(
+    feature = "transport_quic",
     any(
         feature = "serde_bincode",
         feature = "serde_cbor",
-        feature = "serde_rmp"
+        feature = "serde_rmp",
+        feature = "serde_postcard"
     ),
-    any(feature = "async_std_runtime", feature = "tokio_runtime",)
 ))]
-pub(crate) mod frame;
+pub(crate) mod quic;
 
 // #[cfg(any(feature = "async_std_runtime", feature = "tokio_runtime",))]
 pub(crate) mod ws;
 
+#[cfg(any(feature = "async_std_runtime", feature = "tokio_runtime"))]
+pub mod post;
+
 /// Reads bytes from transport protocols that carry payload (ie. WebSocket)
 #[async_trait]
 pub trait PayloadRead {
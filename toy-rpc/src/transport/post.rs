@@ -0,0 +1,164 @@
+//! HTTP POST transport, for networks whose proxies block the WebSocket upgrades
+//!
+//! A client using `Client::dial_http_post` opens a session with a POST request to
+//! the RPC path, whose response carries the id of the session in the
+//! `x-toy-rpc-session` header. On the server, a session is served like any other
+//! connection, and its payloads are carried by the requests instead of a socket.
+//!
+//! Each request of the session carries the payloads written by the client since the
+//! last request, and its response carries the payloads that the server has written
+//! so far. The server holds a request until it has something to write, for up to
+//! `POLL_TIMEOUT`, so that the response of a call usually comes back in the
+//! response of the request that carried it. The client keeps one request, which is
+//! an empty long poll when there is nothing to send, pending at all times so that
+//! the server can write at any time. A new request releases the request held
+//! before it.
+//!
+//! The bodies of the requests and the responses are sequences of payloads, each one
+//! preceded by its length as a big endian `u32`. The requests carrying payloads are
+//! numbered with the `x-toy-rpc-input` header and the responses with the
+//! `x-toy-rpc-seq` header, so that both ends process the payloads in order even
+//! when the requests overtake each other. A session is closed by a request with
+//! the `x-toy-rpc-close` header, which is numbered like the requests carrying
+//! payloads so that the server reads the payloads sent before it first, or by the
+//! server once it has been idle for `SESSION_IDLE_TIMEOUT`. The response that tells the client the session is
+//! closed also has the `x-toy-rpc-close` header.
+
+#![cfg_attr(
+    not(any(feature = "client", feature = "http_hyper", feature = "http_warp")),
+    allow(dead_code)
+)]
+
+use async_trait::async_trait;
+use flume::{Receiver, Sender};
+use std::convert::TryInto;
+use std::time::Duration;
+
+use super::{PayloadRead, PayloadWrite};
+use crate::error::Error;
+use crate::util::GracefulShutdown;
+
+/// Header with the id of the session
+pub const SESSION_HEADER: &str = "x-toy-rpc-session";
+/// Header with the number of the payloads of a request, starting from 1
+pub const INPUT_HEADER: &str = "x-toy-rpc-input";
+/// Header with the number of a response, starting from 0 for the response that
+/// opens the session
+pub const SEQ_HEADER: &str = "x-toy-rpc-seq";
+/// Header of a request that closes its session, or of a response from a session
+/// that is closed
+pub const CLOSE_HEADER: &str = "x-toy-rpc-close";
+
+/// Longest time that the server holds a request with nothing to write
+pub const POLL_TIMEOUT: Duration = Duration::from_secs(20);
+/// Time after which the server closes a session without any request
+pub const SESSION_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+/// Longest body of a request that the server reads
+pub const MAX_BODY_LEN: usize = 16 * 1024 * 1024;
+/// Most requests carrying payloads that the client has sent at once, which is as
+/// far ahead of the requests read so far as the server accepts a request
+pub const MAX_PENDING_INPUTS: u64 = 64;
+
+/// Encodes the payloads into the body of a request or a response
+pub(crate) fn encode_batch(payloads: &[Vec<u8>]) -> Vec<u8> {
+    let len = payloads.iter().map(|payload| 4 + payload.len()).sum();
+    let mut body = Vec::with_capacity(len);
+    for payload in payloads {
+        body.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        body.extend_from_slice(payload);
+    }
+    body
+}
+
+/// Decodes the payloads of the body of a request or a response
+pub(crate) fn decode_batch(mut body: &[u8]) -> Result<Vec<Vec<u8>>, Error> {
+    let mut payloads = Vec::new();
+    while !body.is_empty() {
+        let len = match body.get(..4) {
+            Some(len) => u32::from_be_bytes(len.try_into().expect("slice of 4 bytes")) as usize,
+            None => return Err(Error::Internal("Truncated payload length".into())),
+        };
+        let payload = body
+            .get(4..4 + len)
+            .ok_or_else(|| Error::Internal("Truncated payload".into()))?;
+        payloads.push(payload.to_vec());
+        body = &body[4 + len..];
+    }
+    Ok(payloads)
+}
+
+/// Reading half of a connection whose payloads are carried by the requests of a
+/// session
+pub struct ChannelReader {
+    rx: Receiver<Vec<u8>>,
+}
+
+/// Writing half of a connection whose payloads are carried by the requests of a
+/// session
+pub struct ChannelWriter {
+    tx: Option<Sender<Vec<u8>>>,
+}
+
+/// Creates the halves of a connection, along with the sender of the payloads
+/// that it reads and the receiver of the payloads that it writes
+pub(crate) fn channel() -> (
+    ChannelReader,
+    ChannelWriter,
+    Sender<Vec<u8>>,
+    Receiver<Vec<u8>>,
+) {
+    let (inbound_tx, inbound_rx) = flume::unbounded();
+    let (outbound_tx, outbound_rx) = flume::unbounded();
+    (
+        ChannelReader { rx: inbound_rx },
+        ChannelWriter {
+            tx: Some(outbound_tx),
+        },
+        inbound_tx,
+        outbound_rx,
+    )
+}
+
+#[async_trait]
+impl PayloadRead for ChannelReader {
+    async fn read_payload(&mut self) -> Option<Result<Vec<u8>, Error>> {
+        self.rx.recv_async().await.ok().map(Ok)
+    }
+}
+
+#[async_trait]
+impl PayloadWrite for ChannelWriter {
+    async fn write_payload(&mut self, payload: &[u8]) -> Result<(), Error> {
+        match &self.tx {
+            Some(tx) => tx
+                .send_async(payload.to_vec())
+                .await
+                .map_err(|_| Error::IoError(std::io::ErrorKind::BrokenPipe.into())),
+            None => Err(Error::IoError(std::io::ErrorKind::NotConnected.into())),
+        }
+    }
+}
+
+#[async_trait]
+impl GracefulShutdown for ChannelWriter {
+    async fn close(&mut self) {
+        // the session is closed once the payloads written before are sent
+        self.tx.take();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn batch_round_trips() {
+        let payloads = vec![b"header".to_vec(), Vec::new(), vec![0u8; 300]];
+        let body = encode_batch(&payloads);
+        assert_eq!(body.len(), 3 * 4 + 6 + 300);
+        assert_eq!(decode_batch(&body).unwrap(), payloads);
+        assert!(decode_batch(&[]).unwrap().is_empty());
+        assert!(decode_batch(&body[..body.len() - 1]).is_err());
+        assert!(decode_batch(&[0, 0]).is_err());
+    }
+}
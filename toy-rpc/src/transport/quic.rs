@@ -0,0 +1,266 @@
+//! QUIC transport with `quinn`
+//!
+//! The messages are sent in the frames of the custom binary transport, but each
+//! message id has its own bidirectional QUIC stream instead of sharing a single
+//! connection, so that a large or slow call does not hold back the other calls.
+//! The stream of a message id is opened by the side that sends the first message
+//! with this id (ie. the client for a request), and the replies with the same id are
+//! sent back on the same stream. The messages of all the streams are merged into
+//! the reader of the codec.
+//!
+//! A side keeps at most `MAX_SEND_STREAMS` streams open for writing and finishes the
+//! oldest one when it needs another. A side finishes its half of a stream once the
+//! peer has finished the other half, and a later message with the same id is sent
+//! on a new stream.
+
+use async_trait::async_trait;
+use flume::{Receiver, Sender};
+use futures::future::{self, Either};
+use quinn::{Connection, RecvStream, SendStream, StreamId};
+use std::collections::{HashMap, VecDeque};
+use std::io::ErrorKind;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use super::frame::{Frame, FrameHeader, FrameRead, FrameWrite};
+use crate::error::Error;
+use crate::message::MessageId;
+
+/// Maximum number of streams a side keeps open for writing
+const MAX_SEND_STREAMS: usize = 32;
+
+type FramePair = Result<(Frame, Frame), Error>;
+
+/// Sending halves of the streams of a connection
+#[derive(Default)]
+struct SendStreams {
+    streams: HashMap<MessageId, SendStream>,
+    /// Message ids in `streams` from the oldest to the newest
+    order: VecDeque<MessageId>,
+    /// Stream taken by the writer and whether it is finished by the peer meanwhile
+    writing: Option<(StreamId, bool)>,
+}
+
+impl SendStreams {
+    /// Inserts the stream of `id`. A stream is finished when it is dropped, which
+    /// happens to the previous stream of `id` and to the oldest streams beyond
+    /// `MAX_SEND_STREAMS`.
+    fn insert(&mut self, id: MessageId, stream: SendStream) {
+        self.remove(id);
+        while self.order.len() >= MAX_SEND_STREAMS {
+            if let Some(oldest) = self.order.pop_front() {
+                self.streams.remove(&oldest);
+            }
+        }
+        self.streams.insert(id, stream);
+        self.order.push_back(id);
+    }
+
+    fn remove(&mut self, id: MessageId) -> Option<SendStream> {
+        self.order.retain(|other| *other != id);
+        self.streams.remove(&id)
+    }
+
+    /// Finishes the stream `stream_id` of `id` after the peer has finished it
+    fn peer_finished(&mut self, id: MessageId, stream_id: StreamId) {
+        match self.streams.get(&id) {
+            Some(stream) if stream.id() == stream_id => {
+                self.remove(id);
+            }
+            _ => {
+                if let Some((writing, finished)) = self.writing.as_mut() {
+                    if *writing == stream_id {
+                        *finished = true;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+struct SharedStreams(Arc<Mutex<SendStreams>>);
+
+impl SharedStreams {
+    fn lock(&self) -> MutexGuard<'_, SendStreams> {
+        match self.0.lock() {
+            Ok(streams) => streams,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
+
+/// Creates the reading and writing halves of a QUIC connection
+pub(crate) fn split(conn: Connection) -> (QuicReader, QuicWriter) {
+    let (frames_tx, frames_rx) = flume::unbounded();
+    let streams = SharedStreams::default();
+    tokio::task::spawn(accept_streams(
+        conn.clone(),
+        streams.clone(),
+        frames_tx.clone(),
+    ));
+
+    let reader = QuicReader {
+        conn: conn.clone(),
+        frames: frames_rx,
+        pending: None,
+    };
+    let writer = QuicWriter {
+        conn,
+        streams,
+        frames: frames_tx,
+    };
+    (reader, writer)
+}
+
+/// Accepts the streams opened by the peer until the connection is closed
+async fn accept_streams(conn: Connection, streams: SharedStreams, frames: Sender<FramePair>) {
+    while let Ok((send, recv)) = conn.accept_bi().await {
+        tokio::task::spawn(read_stream(
+            recv,
+            Some(send),
+            streams.clone(),
+            frames.clone(),
+        ));
+    }
+}
+
+/// Forwards the messages read on a stream to the reader. The sending half of a
+/// stream opened by the peer is registered with the id of its first message.
+async fn read_stream(
+    mut recv: RecvStream,
+    mut send: Option<SendStream>,
+    streams: SharedStreams,
+    frames: Sender<FramePair>,
+) {
+    let stream_id = recv.id();
+    let mut last_id = None;
+    loop {
+        let pair = match recv.read_frame().await {
+            Some(Ok(header)) => match recv.read_frame().await {
+                Some(Ok(body)) => Ok((header, body)),
+                Some(Err(err)) => Err(err),
+                None => Err(Error::IoError(std::io::Error::new(
+                    ErrorKind::UnexpectedEof,
+                    "QUIC stream finished before the body of a message",
+                ))),
+            },
+            Some(Err(err)) => Err(err),
+            None => break,
+        };
+
+        if let Ok((header, _)) = &pair {
+            let id = header.message_id;
+            if let Some(send) = send.take() {
+                streams.lock().insert(id, send);
+            }
+            last_id = Some(id);
+        }
+        let is_err = pair.is_err();
+        if frames.send_async(pair).await.is_err() || is_err {
+            return;
+        }
+    }
+
+    if let Some(id) = last_id {
+        streams.lock().peer_finished(id, stream_id);
+    }
+}
+
+/// Reading half of a QUIC connection
+pub struct QuicReader {
+    conn: Connection,
+    frames: Receiver<FramePair>,
+    pending: Option<Frame>,
+}
+
+#[async_trait]
+impl FrameRead for QuicReader {
+    async fn read_frame(&mut self) -> Option<Result<Frame, Error>> {
+        if let Some(body) = self.pending.take() {
+            return Some(Ok(body));
+        }
+
+        let pair = {
+            let recv = self.frames.recv_async();
+            let closed = self.conn.closed();
+            futures::pin_mut!(recv, closed);
+            match future::select(recv, closed).await {
+                Either::Left((pair, _)) => pair.ok()?,
+                Either::Right(_) => self.frames.try_recv().ok()?,
+            }
+        };
+        match pair {
+            Ok((header, body)) => {
+                self.pending = Some(body);
+                Some(Ok(header))
+            }
+            Err(err) => Some(Err(err)),
+        }
+    }
+}
+
+/// Writing half of a QUIC connection
+pub struct QuicWriter {
+    conn: Connection,
+    streams: SharedStreams,
+    frames: Sender<FramePair>,
+}
+
+impl QuicWriter {
+    /// Takes the stream of `id` out of the shared streams, opening a new one if
+    /// there is none
+    async fn take_stream(&mut self, id: MessageId) -> Result<SendStream, Error> {
+        let stream = {
+            let mut streams = self.streams.lock();
+            let stream = streams.remove(id);
+            if let Some(stream) = &stream {
+                streams.writing = Some((stream.id(), false));
+            }
+            stream
+        };
+        if let Some(stream) = stream {
+            return Ok(stream);
+        }
+
+        let (send, recv) = self.conn.open_bi().await.map_err(Error::from_quic)?;
+        self.streams.lock().writing = Some((send.id(), false));
+        tokio::task::spawn(read_stream(
+            recv,
+            None,
+            self.streams.clone(),
+            self.frames.clone(),
+        ));
+        Ok(send)
+    }
+
+    /// Puts the stream of `id` back unless the peer has finished it
+    fn put_stream(&mut self, id: MessageId, stream: SendStream) {
+        let mut streams = self.streams.lock();
+        match streams.writing.take() {
+            Some((_, true)) => drop(stream),
+            _ => streams.insert(id, stream),
+        }
+    }
+}
+
+#[async_trait]
+impl FrameWrite for QuicWriter {
+    async fn write_frame(
+        &mut self,
+        frame_header: FrameHeader,
+        payload: &[u8],
+    ) -> Result<(), Error> {
+        // the end frame closes the whole connection
+        if frame_header.is_end() {
+            *self.streams.lock() = SendStreams::default();
+            self.conn.close(0u32.into(), b"");
+            return Ok(());
+        }
+
+        let id = frame_header.message_id;
+        let mut stream = self.take_stream(id).await?;
+        let res = stream.write_frame(frame_header, payload).await;
+        self.put_stream(id, stream);
+        res
+    }
+}
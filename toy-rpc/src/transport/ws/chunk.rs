@@ -0,0 +1,221 @@
+//! Splitting of payloads into WebSocket messages
+//!
+//! The WebSocket implementations limit the size of the messages they receive, and
+//! close the connection with the status code 1009 (message too big) when a message
+//! exceeds the limit. The smallest limit is the one of `actix-web`, which is 64 KiB.
+//! A connection whose handshake agrees on the `PROTOCOL` subprotocol therefore
+//! writes each payload as a sequence of binary messages of at most
+//! `MAX_MESSAGE_LEN` bytes, and reassembles the messages when they are read. The
+//! client offers the subprotocol and the server accepts it, so a peer that does not
+//! know about it keeps writing each payload as a single message.
+//!
+//! Every message starts with a flag byte, `MORE` if the following message continues
+//! the payload or `LAST` if the message ends it, followed by a chunk of the payload.
+//! A payload that fits in one message, including an empty payload, is written as a
+//! single message flagged `LAST`. A payload longer than `MAX_PAYLOAD_LEN` is
+//! refused when it is read.
+
+use std::io::ErrorKind;
+
+use crate::error::Error;
+
+/// Subprotocol of the connections whose payloads are split into messages
+#[cfg(any(
+    feature = "async_std_runtime",
+    feature = "tokio_runtime",
+    feature = "http_actix_web",
+    feature = "wasm"
+))]
+pub(crate) const PROTOCOL: &str = "toy-rpc.chunked";
+
+/// Header of the handshake with the subprotocols offered by the client, or the one
+/// accepted by the server
+#[cfg(any(
+    feature = "async_std_runtime",
+    feature = "tokio_runtime",
+    feature = "http_actix_web"
+))]
+pub(crate) const PROTOCOL_HEADER: &str = "sec-websocket-protocol";
+
+/// Maximum length of a message, including the flag byte
+pub(crate) const MAX_MESSAGE_LEN: usize = 64 * 1024;
+
+/// Maximum length of a reassembled payload, which is the default limit of the
+/// messages of `tungstenite`
+pub(crate) const MAX_PAYLOAD_LEN: usize = 64 * 1024 * 1024;
+
+const MAX_CHUNK_LEN: usize = MAX_MESSAGE_LEN - 1;
+
+/// Flag of the last message of a payload
+const LAST: u8 = 0;
+
+/// Flag of a message that is continued by the following message
+const MORE: u8 = 1;
+
+/// Whether the values of the `PROTOCOL_HEADER` of a handshake contain `PROTOCOL`
+#[cfg(any(
+    feature = "async_std_runtime",
+    feature = "tokio_runtime",
+    feature = "http_actix_web"
+))]
+pub(crate) fn offered<'a>(values: impl IntoIterator<Item = &'a str>) -> bool {
+    values
+        .into_iter()
+        .flat_map(|value| value.split(','))
+        .any(|protocol| protocol.trim() == PROTOCOL)
+}
+
+/// Splits a payload into the messages that carry it, which is the payload alone
+/// unless the connection is `chunked`
+pub(crate) fn split(payload: &[u8], chunked: bool) -> impl Iterator<Item = Vec<u8>> + Send + '_ {
+    let count = if chunked {
+        std::cmp::max(1, payload.len().div_ceil(MAX_CHUNK_LEN))
+    } else {
+        1
+    };
+    (0..count).map(move |index| {
+        if !chunked {
+            return payload.to_vec();
+        }
+        let start = index * MAX_CHUNK_LEN;
+        let end = std::cmp::min(start + MAX_CHUNK_LEN, payload.len());
+        let mut message = Vec::with_capacity(end - start + 1);
+        message.push(if index + 1 == count { LAST } else { MORE });
+        message.extend_from_slice(&payload[start..end]);
+        message
+    })
+}
+
+/// Reassembles the payloads from the messages read
+#[derive(Debug, Default)]
+pub(crate) struct Reassembly {
+    chunked: bool,
+    payload: Vec<u8>,
+}
+
+impl Reassembly {
+    /// Creates a reassembly of the messages of a connection, each of which is a
+    /// payload unless the connection is `chunked`
+    pub fn new(chunked: bool) -> Self {
+        Self {
+            chunked,
+            payload: Vec::new(),
+        }
+    }
+
+    /// Whether the payloads of the connection are split into messages
+    #[cfg(any(feature = "http_actix_web", feature = "wasm"))]
+    pub fn chunked(&self) -> bool {
+        self.chunked
+    }
+
+    /// Appends a message, and returns the payload once its last message is read
+    pub fn push(&mut self, mut message: Vec<u8>) -> Result<Option<Vec<u8>>, Error> {
+        if !self.chunked {
+            return Ok(Some(message));
+        }
+        if self.payload.len() + message.len().saturating_sub(1) > MAX_PAYLOAD_LEN {
+            self.payload = Vec::new();
+            return Err(invalid_data("WebSocket payload exceeds the maximum length"));
+        }
+        match message.first() {
+            Some(&LAST) if self.payload.is_empty() => {
+                message.remove(0);
+                Ok(Some(message))
+            }
+            Some(&LAST) => {
+                self.payload.extend_from_slice(&message[1..]);
+                Ok(Some(std::mem::take(&mut self.payload)))
+            }
+            Some(&MORE) => {
+                self.payload.extend_from_slice(&message[1..]);
+                Ok(None)
+            }
+            _ => {
+                self.payload.clear();
+                Err(invalid_data("Invalid WebSocket message flag"))
+            }
+        }
+    }
+}
+
+fn invalid_data(msg: &str) -> Error {
+    Error::IoError(std::io::Error::new(ErrorKind::InvalidData, msg))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(payload: &[u8]) -> usize {
+        let mut reassembly = Reassembly::new(true);
+        let mut messages = 0;
+        for message in split(payload, true) {
+            assert!(message.len() <= MAX_MESSAGE_LEN);
+            messages += 1;
+            if let Some(reassembled) = reassembly.push(message).unwrap() {
+                assert_eq!(reassembled, payload);
+                return messages;
+            }
+        }
+        panic!("The last message is not flagged");
+    }
+
+    #[test]
+    fn payloads_are_reassembled() {
+        assert_eq!(round_trip(&[]), 1);
+        assert_eq!(round_trip(&[7; 10]), 1);
+        assert_eq!(round_trip(&[7; MAX_CHUNK_LEN]), 1);
+        assert_eq!(round_trip(&[7; MAX_CHUNK_LEN + 1]), 2);
+        let payload: Vec<u8> = (0..5 * MAX_MESSAGE_LEN).map(|i| i as u8).collect();
+        assert_eq!(round_trip(&payload), 6);
+
+        let mut reassembly = Reassembly::new(true);
+        assert!(reassembly.push(vec![]).is_err());
+        assert!(reassembly.push(vec![9, 1, 2]).is_err());
+    }
+
+    #[test]
+    fn payloads_are_whole_messages_unless_chunked() {
+        let payload = vec![7; 2 * MAX_MESSAGE_LEN];
+        let messages: Vec<_> = split(&payload, false).collect();
+        assert_eq!(messages, vec![payload.clone()]);
+        let mut reassembly = Reassembly::default();
+        assert_eq!(reassembly.push(payload.clone()).unwrap(), Some(payload));
+        assert_eq!(reassembly.push(Vec::new()).unwrap(), Some(Vec::new()));
+    }
+
+    #[test]
+    fn reassembled_payloads_are_bounded() {
+        let mut reassembly = Reassembly::new(true);
+        let mut chunk = vec![MORE];
+        chunk.resize(MAX_MESSAGE_LEN, 7);
+        let mut read = 0;
+        let err = loop {
+            match reassembly.push(chunk.clone()) {
+                Ok(None) => read += MAX_CHUNK_LEN,
+                Ok(Some(_)) => panic!("The payload is not flagged last"),
+                Err(err) => break err,
+            }
+        };
+        assert!(read <= MAX_PAYLOAD_LEN);
+        assert!(read + MAX_CHUNK_LEN > MAX_PAYLOAD_LEN);
+        assert!(err.to_string().contains("maximum length"));
+        // the connection starts over with the next payload
+        assert_eq!(reassembly.push(vec![LAST, 1]).unwrap(), Some(vec![1]));
+    }
+
+    #[test]
+    #[cfg(any(
+        feature = "async_std_runtime",
+        feature = "tokio_runtime",
+        feature = "http_actix_web"
+    ))]
+    fn protocol_is_found_among_those_offered() {
+        assert!(offered(["toy-rpc.chunked"]));
+        assert!(offered(["chat, toy-rpc.chunked"]));
+        assert!(offered(["chat", " toy-rpc.chunked "]));
+        assert!(!offered(["chat"]));
+        assert!(!offered(Vec::<&str>::new()));
+    }
+}
@@ -1,4 +1,7 @@
 //! WebSocket transport support
+//!
+//! Payloads larger than the message size limits are written as sequences of
+//! messages when both ends agree on it in the handshake, see the `chunk` module.
 
 use async_trait::async_trait;
 use async_tungstenite::WebSocketStream;
@@ -7,6 +10,7 @@ use futures::io::{AsyncRead, AsyncWrite};
 use futures::stream::{SplitSink, SplitStream};
 use futures::{Sink, SinkExt, Stream, StreamExt};
 use pin_project::pin_project;
+use tungstenite::protocol::{frame::coding::CloseCode, CloseFrame};
 use tungstenite::Message as WsMessage;
 
 use std::{io::ErrorKind, marker::PhantomData};
@@ -14,6 +18,9 @@ use std::{io::ErrorKind, marker::PhantomData};
 use super::{PayloadRead, PayloadWrite};
 use crate::{error::Error, util::GracefulShutdown};
 
+pub(crate) mod chunk;
+use chunk::Reassembly;
+
 type WsSinkHalf<S> = SinkHalf<SplitSink<S, WsMessage>, CanSink>;
 type WsStreamHalf<S> = StreamHalf<SplitStream<S>, CanSink>;
 
@@ -27,9 +34,78 @@ cfg_if! {
 }
 pub(crate) struct CanSink {}
 
+/// Whether the `chunk::PROTOCOL_HEADER` of a handshake contains `chunk::PROTOCOL`
+#[cfg(any(feature = "async_std_runtime", feature = "tokio_runtime"))]
+pub(crate) fn offered_in(headers: &tungstenite::http::HeaderMap) -> bool {
+    chunk::offered(
+        headers
+            .get_all(chunk::PROTOCOL_HEADER)
+            .iter()
+            .filter_map(|value| value.to_str().ok()),
+    )
+}
+
+/// Offers `chunk::PROTOCOL` in the handshake of a client
+#[cfg(all(
+    feature = "client",
+    any(feature = "async_std_runtime", feature = "tokio_runtime")
+))]
+pub(crate) fn client_request(
+    url: url::Url,
+) -> Result<tungstenite::handshake::client::Request, Error> {
+    use tungstenite::client::IntoClientRequest;
+
+    let mut request = url.into_client_request()?;
+    request.headers_mut().insert(
+        chunk::PROTOCOL_HEADER,
+        tungstenite::http::HeaderValue::from_static(chunk::PROTOCOL),
+    );
+    Ok(request)
+}
+
+/// Accepts `chunk::PROTOCOL` in the handshake of a server if the client offers it,
+/// and records in `chunked` whether it did
+#[cfg(all(
+    feature = "server",
+    any(feature = "async_std_runtime", feature = "tokio_runtime")
+))]
+// the error response is the one of the handshake callbacks of `tungstenite`
+#[allow(clippy::result_large_err)]
+pub(crate) fn accept_protocol(
+    chunked: &mut bool,
+) -> impl FnOnce(
+    &tungstenite::handshake::server::Request,
+    tungstenite::handshake::server::Response,
+) -> Result<
+    tungstenite::handshake::server::Response,
+    tungstenite::handshake::server::ErrorResponse,
+> + Unpin
+       + '_ {
+    move |request, mut response| {
+        if offered_in(request.headers()) {
+            response.headers_mut().insert(
+                chunk::PROTOCOL_HEADER,
+                tungstenite::http::HeaderValue::from_static(chunk::PROTOCOL),
+            );
+            *chunked = true;
+        }
+        Ok(response)
+    }
+}
+
 pub struct WebSocketConn<S, N> {
     pub inner: S,
     can_sink: PhantomData<N>,
+    chunked: bool,
+}
+
+impl<S, N> WebSocketConn<S, N> {
+    /// Splits the payloads into messages, once the handshake has agreed on
+    /// `chunk::PROTOCOL`
+    #[cfg(any(feature = "async_std_runtime", feature = "tokio_runtime"))]
+    pub(crate) fn chunked(self, chunked: bool) -> Self {
+        Self { chunked, ..self }
+    }
 }
 
 /// A wrapper around a type that impls Stream
@@ -38,6 +114,7 @@ pub struct StreamHalf<S, Mode> {
     #[pin]
     pub inner: S,
     pub can_sink: PhantomData<Mode>,
+    pub(crate) chunked: bool,
 }
 
 impl<S: Stream> Stream for StreamHalf<S, CanSink> {
@@ -58,6 +135,7 @@ pub struct SinkHalf<S, Mode> {
     #[pin]
     pub inner: S,
     pub can_sink: PhantomData<Mode>,
+    pub(crate) chunked: bool,
 }
 
 impl<S: Sink<Item>, Item> Sink<Item> for SinkHalf<S, CanSink> {
@@ -102,6 +180,7 @@ where
         Self {
             inner,
             can_sink: PhantomData,
+            chunked: false,
         }
     }
 
@@ -111,10 +190,12 @@ where
         let readhalf = StreamHalf {
             inner: reader,
             can_sink: PhantomData,
+            chunked: self.chunked,
         };
         let writehalf = SinkHalf {
             inner: writer,
             can_sink: PhantomData,
+            chunked: self.chunked,
         };
         (writehalf, readhalf)
     }
@@ -126,24 +207,26 @@ where
     T: AsyncRead + AsyncWrite + Send + Unpin,
 {
     async fn read_payload(&mut self) -> Option<Result<Vec<u8>, Error>> {
-        match self.next().await? {
-            Err(e) => {
-                return Some(Err(Error::IoError(std::io::Error::new(
-                    ErrorKind::InvalidData,
-                    e.to_string(),
-                ))))
-            }
-            Ok(msg) => {
-                if let WsMessage::Binary(bytes) = msg {
-                    return Some(Ok(bytes));
-                } else if let WsMessage::Close(_) = msg {
-                    return None;
-                }
+        let mut reassembly = Reassembly::new(self.chunked);
+        loop {
+            match self.next().await? {
+                Err(e) => return Some(Err(e.into())),
+                Ok(msg) => {
+                    if let WsMessage::Binary(bytes) = msg {
+                        match reassembly.push(bytes) {
+                            Ok(Some(payload)) => return Some(Ok(payload)),
+                            Ok(None) => continue,
+                            Err(err) => return Some(Err(err)),
+                        }
+                    } else if let WsMessage::Close(_) = msg {
+                        return None;
+                    }
 
-                Some(Err(Error::IoError(std::io::Error::new(
-                    ErrorKind::InvalidData,
-                    "Expecting WebSocket::Message::Binary",
-                ))))
+                    return Some(Err(Error::IoError(std::io::Error::new(
+                        ErrorKind::InvalidData,
+                        "Expecting WebSocket::Message::Binary",
+                    ))));
+                }
             }
         }
     }
@@ -155,30 +238,42 @@ where
     T: AsyncRead + AsyncWrite + Send + Unpin,
 {
     async fn write_payload(&mut self, payload: &[u8]) -> Result<(), Error> {
-        let msg = WsMessage::Binary(payload.to_owned());
-
-        self.send(msg)
-            .await
-            .map_err(|e| Error::IoError(std::io::Error::new(ErrorKind::InvalidData, e.to_string())))
+        for chunk in chunk::split(payload, self.chunked) {
+            let msg = WsMessage::Binary(chunk);
+            self.send(msg).await?;
+        }
+        Ok(())
     }
 }
 
-// GracefulShutdown is only required on the client side.
 #[async_trait]
 impl<T> GracefulShutdown for SinkHalf<SplitSink<WebSocketStream<T>, WsMessage>, CanSink>
 where
     T: AsyncRead + AsyncWrite + Send + Unpin,
 {
     async fn close(&mut self) {
-        let msg = WsMessage::Close(None);
+        self.send_close(None).await
+    }
+
+    async fn close_with(&mut self, code: u16, reason: &str) {
+        let frame = CloseFrame {
+            code: CloseCode::from(code),
+            reason: reason.to_string().into(),
+        };
+        self.send_close(Some(frame)).await
+    }
+}
+
+impl<T> SinkHalf<SplitSink<WebSocketStream<T>, WsMessage>, CanSink>
+where
+    T: AsyncRead + AsyncWrite + Send + Unpin,
+{
+    async fn send_close(&mut self, frame: Option<CloseFrame<'static>>) {
+        let msg = WsMessage::Close(frame);
 
-        match self
-            .send(msg)
-            .await
-            .map_err(|e| Error::IoError(std::io::Error::new(ErrorKind::InvalidData, e.to_string())))
-        {
+        match self.send(msg).await.map_err(Error::from) {
             Ok(()) => {}
-            Err(e) => log::error!("Error closing WebSocket {}", e.to_string()),
+            Err(e) => error!("Error closing WebSocket {}", e),
         };
     }
 }
@@ -1,6 +1,7 @@
 //! WebSocket support for `tide-websockets`
 
 use tide_websockets as tide_ws;
+use tide_websockets::tungstenite::protocol::{frame::coding::CloseCode, CloseFrame};
 
 use super::*;
 
@@ -9,6 +10,7 @@ impl WebSocketConn<tide_websockets::WebSocketConnection, CannotSink> {
         Self {
             inner,
             can_sink: PhantomData,
+            chunked: false,
         }
     }
 
@@ -21,10 +23,12 @@ impl WebSocketConn<tide_websockets::WebSocketConnection, CannotSink> {
         let writer = SinkHalf {
             inner: self.inner.clone(),
             can_sink: PhantomData,
+            chunked: self.chunked,
         };
         let reader = StreamHalf {
             inner: self.inner,
             can_sink: PhantomData,
+            chunked: self.chunked,
         };
         (writer, reader)
     }
@@ -33,24 +37,32 @@ impl WebSocketConn<tide_websockets::WebSocketConnection, CannotSink> {
 #[async_trait]
 impl PayloadRead for StreamHalf<tide_websockets::WebSocketConnection, CannotSink> {
     async fn read_payload(&mut self) -> Option<Result<Vec<u8>, Error>> {
-        match self.inner.next().await? {
-            Err(e) => {
-                return Some(Err(Error::IoError(std::io::Error::new(
-                    ErrorKind::InvalidData,
-                    e.to_string(),
-                ))))
-            }
-            Ok(msg) => {
-                if let tide_websockets::Message::Binary(bytes) = msg {
-                    return Some(Ok(bytes));
-                } else if let tide_websockets::Message::Close(_) = msg {
-                    return None;
+        let mut reassembly = Reassembly::new(self.chunked);
+        loop {
+            match self.inner.next().await? {
+                Err(e) => {
+                    return Some(Err(Error::IoError(std::io::Error::new(
+                        ErrorKind::InvalidData,
+                        e.to_string(),
+                    ))))
                 }
+                Ok(msg) => {
+                    if let tide_websockets::Message::Binary(bytes) = msg {
+                        match reassembly.push(bytes) {
+                            Ok(Some(payload)) => return Some(Ok(payload)),
+                            Ok(None) => continue,
+                            Err(err) => return Some(Err(err)),
+                        }
+                    } else if let tide_websockets::Message::Close(_) = msg {
+                        return None;
+                    }
 
-                Some(Err(Error::IoError(std::io::Error::new(
-                    ErrorKind::InvalidData,
-                    "Expecting WebSocket::Message::Binary, but found something else".to_string(),
-                ))))
+                    return Some(Err(Error::IoError(std::io::Error::new(
+                        ErrorKind::InvalidData,
+                        "Expecting WebSocket::Message::Binary, but found something else"
+                            .to_string(),
+                    ))));
+                }
             }
         }
     }
@@ -59,10 +71,13 @@ impl PayloadRead for StreamHalf<tide_websockets::WebSocketConnection, CannotSink
 #[async_trait]
 impl PayloadWrite for SinkHalf<tide_websockets::WebSocketConnection, CannotSink> {
     async fn write_payload(&mut self, payload: &[u8]) -> Result<(), Error> {
-        self.inner
-            .send_bytes(payload.to_owned())
-            .await
-            .map_err(|e| Error::Internal(Box::new(e)))
+        for chunk in chunk::split(payload, self.chunked) {
+            self.inner
+                .send_bytes(chunk)
+                .await
+                .map_err(|e| Error::Internal(Box::new(e)))?;
+        }
+        Ok(())
     }
 }
 
@@ -74,6 +89,19 @@ impl GracefulShutdown for SinkHalf<tide_websockets::WebSocketConnection, CannotS
             .send(close_msg)
             .await
             .map_err(|err| Error::Internal(Box::new(err)))
-            .unwrap_or_else(|err| log::error!("{}", err));
+            .unwrap_or_else(|err| error!("{}", err));
+    }
+
+    async fn close_with(&mut self, code: u16, reason: &str) {
+        let frame = CloseFrame {
+            code: CloseCode::from(code),
+            reason: reason.to_string().into(),
+        };
+        let close_msg = tide_websockets::Message::Close(Some(frame));
+        self.inner
+            .send(close_msg)
+            .await
+            .map_err(|err| Error::Internal(Box::new(err)))
+            .unwrap_or_else(|err| error!("{}", err));
     }
 }
@@ -6,24 +6,32 @@ use warp::ws::{Message as WsMessage, WebSocket};
 #[async_trait]
 impl PayloadRead for StreamHalf<SplitStream<WebSocket>, CanSink> {
     async fn read_payload(&mut self) -> Option<Result<Vec<u8>, Error>> {
-        let msg = self.next().await?;
-        match msg {
-            Err(e) => {
-                return Some(Err(Error::IoError(std::io::Error::new(
-                    ErrorKind::InvalidData,
-                    e.to_string(),
-                ))))
-            }
-            Ok(m) => {
-                if m.is_close() {
-                    return None;
-                } else if m.is_binary() {
-                    return Some(Ok(m.into_bytes()));
+        let mut reassembly = Reassembly::new(self.chunked);
+        loop {
+            let msg = self.next().await?;
+            match msg {
+                Err(e) => {
+                    return Some(Err(Error::IoError(std::io::Error::new(
+                        ErrorKind::InvalidData,
+                        e.to_string(),
+                    ))))
+                }
+                Ok(m) => {
+                    if m.is_close() {
+                        return None;
+                    } else if m.is_binary() {
+                        match reassembly.push(m.into_bytes()) {
+                            Ok(Some(payload)) => return Some(Ok(payload)),
+                            Ok(None) => continue,
+                            Err(err) => return Some(Err(err)),
+                        }
+                    }
+                    return Some(Err(Error::IoError(std::io::Error::new(
+                        ErrorKind::InvalidData,
+                        "Expecting WebSocket::Message::Binary, but found something else"
+                            .to_string(),
+                    ))));
                 }
-                Some(Err(Error::IoError(std::io::Error::new(
-                    ErrorKind::InvalidData,
-                    "Expecting WebSocket::Message::Binary, but found something else".to_string(),
-                ))))
             }
         }
     }
@@ -32,11 +40,13 @@ impl PayloadRead for StreamHalf<SplitStream<WebSocket>, CanSink> {
 #[async_trait]
 impl PayloadWrite for SinkHalf<SplitSink<WebSocket, WsMessage>, CanSink> {
     async fn write_payload(&mut self, payload: &[u8]) -> Result<(), Error> {
-        let msg = warp::ws::Message::binary(payload);
-
-        self.send(msg)
-            .await
-            .map_err(|e| Error::Internal(Box::new(e)))
+        for chunk in chunk::split(payload, self.chunked) {
+            let msg = warp::ws::Message::binary(chunk);
+            self.send(msg)
+                .await
+                .map_err(|e| Error::Internal(Box::new(e)))?;
+        }
+        Ok(())
     }
 }
 
@@ -48,6 +58,15 @@ impl GracefulShutdown for SinkHalf<SplitSink<WebSocket, WsMessage>, CanSink> {
         self.send(msg)
             .await
             .map_err(|err| Error::Internal(Box::new(err)))
-            .unwrap_or_else(|err| log::error!("{}", err))
+            .unwrap_or_else(|err| error!("{}", err))
+    }
+
+    async fn close_with(&mut self, code: u16, reason: &str) {
+        let msg = warp::ws::Message::close_with(code, reason.to_string());
+
+        self.send(msg)
+            .await
+            .map_err(|err| Error::Internal(Box::new(err)))
+            .unwrap_or_else(|err| error!("{}", err))
     }
 }
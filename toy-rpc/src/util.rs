@@ -2,6 +2,7 @@
 
 use async_trait::async_trait;
 use std::collections::HashMap;
+use std::sync::Arc;
 
 use crate::service::AsyncHandler;
 
@@ -17,6 +18,48 @@ pub trait RegisterService {
     ///
     /// For a struct defined as `pub struct Foo { }`, the default name will be `"Foo"`.
     fn default_name() -> &'static str;
+
+    /// Helper function that returns the names of the methods exported with
+    /// `#[export_method(blocking)]`, which are executed on the blocking thread pool
+    fn blocking_methods() -> Vec<&'static str> {
+        Vec::new()
+    }
+}
+
+/// Conversion into the shared state of a `Service`
+///
+/// This is implemented for both `T` and `Arc<T>` where `T: RegisterService`, so
+/// that a service can be registered without wrapping it in an `Arc` first. `T` can
+/// also be a trait object like `dyn Arith + Send + Sync` if the trait is exported
+/// with `#[export_trait]`.
+pub trait IntoService {
+    /// Type of the service
+    type Service: RegisterService + ?Sized + Send + Sync + 'static;
+
+    /// Converts `self` into `Arc<Self::Service>`
+    fn into_service(self) -> Arc<Self::Service>;
+}
+
+impl<S> IntoService for S
+where
+    S: RegisterService + Send + Sync + 'static,
+{
+    type Service = S;
+
+    fn into_service(self) -> Arc<S> {
+        Arc::new(self)
+    }
+}
+
+impl<S> IntoService for Arc<S>
+where
+    S: RegisterService + ?Sized + Send + Sync + 'static,
+{
+    type Service = S;
+
+    fn into_service(self) -> Arc<S> {
+        self
+    }
 }
 
 /// Client should be able to gracefully shutdown the connection by
@@ -25,9 +68,16 @@ pub trait RegisterService {
 pub trait GracefulShutdown {
     /// Closes the connection to allow graceful shutdown.
     async fn close(&mut self);
+
+    /// Closes the connection with a close code and a reason. Only WebSocket
+    /// connections can carry them, other connections are simply closed.
+    async fn close_with(&mut self, _code: u16, _reason: &str) {
+        self.close().await
+    }
 }
 
 /// .await until the end of the task in a blocking manner
+#[allow(dead_code)]
 pub(crate) trait Conclude {
     fn conclude(&mut self);
 }
@@ -35,7 +85,7 @@ pub(crate) trait Conclude {
 #[cfg(feature = "async_std_runtime")]
 impl Conclude for async_std::task::JoinHandle<Result<(), Error>> {
     fn conclude(&mut self) {
-        async_std::task::block_on(self).unwrap_or_else(|err| log::error!("{}", err));
+        async_std::task::block_on(self).unwrap_or_else(|err| error!("{}", err));
     }
 }
 
@@ -44,12 +94,13 @@ impl Conclude for tokio::task::JoinHandle<Result<(), Error>> {
     fn conclude(&mut self) {
         match tokio::task::block_in_place(|| tokio::runtime::Handle::current().block_on(self)) {
             Ok(res) => res.unwrap_or_else(|_| {}),
-            Err(err) => log::error!("{}", err),
+            Err(err) => error!("{}", err),
         }
     }
 }
 
 /// This trait simply cancel/abort the task during execution
+#[allow(dead_code)]
 #[async_trait]
 pub(crate) trait Terminate {
     async fn terminate(self);
@@ -70,3 +121,102 @@ impl<T: Send> Terminate for tokio::task::JoinHandle<T> {
         self.abort();
     }
 }
+
+cfg_if::cfg_if! {
+    if #[cfg(any(
+        all(feature = "async_std_runtime", not(feature = "tokio_runtime")),
+        all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+    ))] {
+        use futures::channel::oneshot;
+        use futures::future::{AbortHandle, Abortable, Aborted};
+        use std::future::Future;
+        use std::pin::Pin;
+        use std::task::{Context, Poll};
+
+        /// Handle of a task spawned with `spawn_task`
+        ///
+        /// `.await`ing on the handle returns the output of the task wrapped in a `Result`
+        /// regardless of the runtime:
+        /// - `Ok(output)` if the task runs to completion
+        /// - `Err(Error::Canceled(None))` if the task is aborted
+        /// - `Err(Error::Internal(_))` if the task panicked
+        ///
+        /// Dropping the handle detaches the task, which will keep running in the background.
+        pub struct TaskHandle<T> {
+            result: oneshot::Receiver<Result<T, Aborted>>,
+            abort_handle: AbortHandle,
+        }
+
+        impl<T> TaskHandle<T> {
+            /// Aborts the task. The task is stopped at its next `.await` point.
+            pub fn abort(&self) {
+                self.abort_handle.abort()
+            }
+        }
+
+        impl<T> Future for TaskHandle<T> {
+            type Output = Result<T, Error>;
+
+            fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+                Pin::new(&mut self.result).poll(cx).map(|result| match result {
+                    Ok(Ok(output)) => Ok(output),
+                    Ok(Err(Aborted)) => Err(Error::Canceled(None)),
+                    Err(oneshot::Canceled) => Err(Error::Internal("Task panicked".into())),
+                })
+            }
+        }
+
+        /// Spawns a future on the runtime selected by the feature flags and returns a
+        /// `TaskHandle`, which behaves the same under `tokio` and `async_std`
+        ///
+        /// # Example
+        ///
+        /// ```no_run
+        /// # #[cfg(all(feature = "server", not(feature = "http_actix_web")))]
+        /// # {
+        /// # #[cfg(feature = "async_std_runtime")]
+        /// # use async_std::net::TcpListener;
+        /// # #[cfg(feature = "tokio_runtime")]
+        /// # use tokio::net::TcpListener;
+        /// # use toy_rpc::Server;
+        /// # async fn run(server: Server, listener: TcpListener) -> Result<(), toy_rpc::Error> {
+        /// let handle = toy_rpc::util::spawn_task(async move {
+        ///     server.accept(listener).await
+        /// });
+        /// // no extra `Result` from the runtime's `JoinHandle`
+        /// handle.await??;
+        /// # Ok(())
+        /// # }
+        /// # }
+        /// ```
+        pub fn spawn_task<F>(fut: F) -> TaskHandle<F::Output>
+        where
+            F: Future + Send + 'static,
+            F::Output: Send + 'static,
+        {
+            let (tx, result) = oneshot::channel();
+            let (abort_handle, abort_registration) = AbortHandle::new_pair();
+            let task = async move {
+                let output = Abortable::new(fut, abort_registration).await;
+                // the handle may have been dropped to detach the task
+                let _ = tx.send(output);
+            };
+
+            #[cfg(all(feature = "async_std_runtime", not(feature = "tokio_runtime")))]
+            ::async_std::task::spawn(task);
+            #[cfg(all(
+                feature = "tokio_runtime",
+                not(feature = "async_std_runtime"),
+                not(feature = "http_actix_web")
+            ))]
+            ::tokio::task::spawn(task);
+            #[cfg(all(feature = "http_actix_web", not(feature = "async_std_runtime")))]
+            actix::spawn(task);
+
+            TaskHandle {
+                result,
+                abort_handle,
+            }
+        }
+    }
+}
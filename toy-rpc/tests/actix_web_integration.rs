@@ -1,8 +1,16 @@
 use actix_web::{web, App, HttpServer};
 use anyhow::Result;
 use flume::{Receiver, Sender};
-use std::sync::Arc;
-use toy_rpc::{Client, Server};
+use std::{
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use toy_rpc::metadata::Metadata;
+use toy_rpc::server::lifecycle::ConnectionInfo;
+use toy_rpc::server::shutdown::{ShutdownHandle, SHUTDOWN_REASON};
+use toy_rpc::timing::{TimeoutPhase, Timing};
+use toy_rpc::{Client, Error, Server};
 
 mod rpc;
 
@@ -26,6 +34,9 @@ async fn test_client(base: &str) -> Result<()> {
     rpc::test_service_not_found(&client).await;
     rpc::test_method_not_found(&client).await;
     rpc::test_execution_error(&client).await;
+    rpc::test_large_payload(&client).await;
+    rpc::test_extension(&client).await;
+    rpc::test_metadata(&client).await;
 
     println!("Client received all correct RPC result");
     Ok(())
@@ -34,7 +45,10 @@ async fn test_client(base: &str) -> Result<()> {
 async fn start_server(base: &'static str) -> Result<()> {
     let common_test_service = Arc::new(rpc::CommonTest::new());
 
-    let server = Server::builder().register(common_test_service).build();
+    let server = Server::builder()
+        .register(common_test_service)
+        .register_extension(rpc::EXT_MARKER, rpc::ext_ping)
+        .build();
     let app_data = web::Data::new(server);
 
     HttpServer::new(move || {
@@ -89,3 +103,166 @@ async fn http_actix_web_integration() {
     run(rpc::ADDR, server_is_ready, rx).await.unwrap();
     handle.await.unwrap();
 }
+
+async fn test_shutdown(addr: SocketAddr, shutdown: ShutdownHandle) {
+    let addr = format!("ws://{}/rpc/", addr);
+    let client = Client::dial_http(&addr)
+        .await
+        .expect("Error dialing http server");
+    rpc::test_get_magic_u8(&client).await;
+    assert_eq!(shutdown.active_sessions(), 1);
+
+    // a call in flight when the server shuts down
+    let slow_call = client.call::<_, ()>("CommonTest.sleep_millis", 300u64);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    let start = Instant::now();
+    let shutdown_handle = {
+        let shutdown = shutdown.clone();
+        tokio::spawn(async move { shutdown.shutdown(Duration::from_secs(5)).await })
+    };
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(shutdown.is_shutting_down());
+
+    // new calls are rejected while the calls in flight are drained
+    let reply: Result<u8, Error> = client.call("CommonTest.get_magic_u8", ()).await;
+    match reply {
+        Err(Error::ExecutionError(msg)) => assert_eq!(msg, SHUTDOWN_REASON),
+        other => panic!("Expecting the call to be rejected, got {:?}", other),
+    }
+
+    slow_call.await.expect("Call in flight should be drained");
+    shutdown_handle.await.expect("Error awaiting shutdown");
+    assert!(start.elapsed() < Duration::from_secs(5));
+    assert_eq!(shutdown.active_sessions(), 0);
+}
+
+#[actix_rt::test]
+async fn http_actix_web_shutdown() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+
+    let server = Server::builder()
+        .register(Arc::new(rpc::CommonTest::new()))
+        .build();
+    let shutdown = server.shutdown_handle();
+    let app_data = web::Data::new(server);
+    let http_server = HttpServer::new(move || {
+        App::new().service(
+            web::scope("/rpc/")
+                .app_data(app_data.clone())
+                .configure(Server::scope_config),
+        )
+    })
+    .bind("127.0.0.1:0")
+    .expect("Error binding test server");
+    let addr = http_server.addrs()[0];
+    let http_server = http_server.run();
+
+    let handle = rt.spawn(test_shutdown(addr, shutdown));
+    handle.await.unwrap();
+    http_server.stop(true).await;
+}
+
+async fn test_lifecycle(addr: SocketAddr, events: Receiver<(&'static str, ConnectionInfo)>) {
+    let url = format!("ws://{}/rpc/", addr);
+    let client = Client::dial_http(&url)
+        .await
+        .expect("Error dialing http server");
+    let (event, connected) = events.recv_async().await.unwrap();
+    assert_eq!(event, "connect");
+    assert_eq!(connected.calls, 0);
+    assert_eq!(connected.peer_addr.map(|peer| peer.ip()), Some(addr.ip()));
+
+    rpc::test_get_magic_u8(&client).await;
+    rpc::test_get_magic_str(&client).await;
+    client.close().await;
+
+    let disconnected = tokio::time::timeout(Duration::from_secs(5), events.recv_async());
+    let (event, disconnected) = disconnected.await.unwrap().unwrap();
+    assert_eq!(event, "disconnect");
+    assert_eq!(disconnected.client_id, connected.client_id);
+    assert_eq!(disconnected.calls, 2);
+}
+
+#[actix_rt::test]
+async fn http_actix_web_lifecycle() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+
+    let (tx, events) = flume::unbounded();
+    let on_disconnect = tx.clone();
+    let server = Server::builder()
+        .register(Arc::new(rpc::CommonTest::new()))
+        .on_connect(move |info: &ConnectionInfo| tx.send(("connect", *info)).unwrap())
+        .on_disconnect(move |info: &ConnectionInfo| {
+            on_disconnect.send(("disconnect", *info)).unwrap()
+        })
+        .build();
+    let app_data = web::Data::new(server);
+    let http_server = HttpServer::new(move || {
+        App::new().service(
+            web::scope("/rpc/")
+                .app_data(app_data.clone())
+                .configure(Server::scope_config),
+        )
+    })
+    .bind("127.0.0.1:0")
+    .expect("Error binding test server");
+    let addr = http_server.addrs()[0];
+    let http_server = http_server.run();
+
+    let handle = rt.spawn(test_lifecycle(addr, events));
+    handle.await.unwrap();
+    http_server.stop(true).await;
+}
+
+async fn test_timing(addr: SocketAddr) {
+    let url = format!("ws://{}/rpc/", addr);
+    let client = Client::dial_http(&url)
+        .await
+        .expect("Error dialing http server");
+
+    let (_, metadata): ((), Metadata) = client
+        .call_with_metadata("CommonTest.sleep_millis", 50u64, Metadata::new())
+        .await
+        .unwrap();
+    let timing = Timing::from_metadata(&metadata).unwrap();
+    assert!(timing.queued < Duration::from_millis(50));
+    assert!(timing.executed >= Duration::from_millis(50));
+
+    let reply: Result<(), Error> = client.call("CommonTest.sleep_millis", 1000u64).await;
+    match reply {
+        Err(Error::ServerTimeout(timeout)) => {
+            assert_eq!(timeout.phase, TimeoutPhase::Execution);
+            assert!(timeout.timing.executed >= Duration::from_millis(200));
+        }
+        reply => panic!("Expecting an execution timeout, got {:?}", reply),
+    }
+    client.close().await;
+}
+
+#[actix_rt::test]
+async fn http_actix_web_timing() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+
+    let server = Server::builder()
+        .register(Arc::new(rpc::CommonTest::new()))
+        .execution_timeout(Duration::from_millis(200))
+        .report_timing(true)
+        .build();
+    let app_data = web::Data::new(server);
+    let http_server = HttpServer::new(move || {
+        App::new().service(
+            web::scope("/rpc/")
+                .app_data(app_data.clone())
+                .configure(Server::scope_config),
+        )
+    })
+    .bind("127.0.0.1:0")
+    .expect("Error binding test server");
+    let addr = http_server.addrs()[0];
+    let http_server = http_server.run();
+
+    let handle = rt.spawn(test_timing(addr));
+    handle.await.unwrap();
+    http_server.stop(true).await;
+}
@@ -31,6 +31,8 @@ async fn test_client(base: &'static str, mut ready: Receiver<()>) -> Result<()>
     rpc::test_service_not_found(&client).await;
     rpc::test_method_not_found(&client).await;
     rpc::test_execution_error(&client).await;
+    rpc::test_large_payload(&client).await;
+    rpc::test_extension(&client).await;
 
     println!("Client received all correct RPC result");
     Ok(())
@@ -42,7 +44,10 @@ async fn run(base: &'static str) {
     let common_test_service = Arc::new(rpc::CommonTest::new());
 
     // start testing server
-    let server = Server::builder().register(common_test_service).build();
+    let server = Server::builder()
+        .register(common_test_service)
+        .register_extension(rpc::EXT_MARKER, rpc::ext_ping)
+        .build();
 
     let listener = TcpListener::bind(addr)
         .await
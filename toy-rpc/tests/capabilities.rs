@@ -0,0 +1,70 @@
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio::task;
+use toy_rpc::capability::Capabilities;
+use toy_rpc::macros::export_impl;
+use toy_rpc::{Client, Server};
+
+pub struct Feed;
+
+#[export_impl]
+impl Feed {
+    #[export_method]
+    async fn latest(&self, _args: ()) -> Result<u32, String> {
+        Ok(7)
+    }
+}
+
+pub struct Plain;
+
+#[export_impl]
+impl Plain {
+    #[export_method]
+    async fn echo(&self, args: String) -> Result<String, String> {
+        Ok(args)
+    }
+}
+
+async fn test_capabilities() {
+    let server = Server::builder()
+        .register(Arc::new(Feed))
+        .register(Arc::new(Plain))
+        .capabilities(
+            "Feed",
+            Capabilities::new()
+                .enable("supports_streaming")
+                .set("supports_compression", false),
+        )
+        .build();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    task::spawn(async move { server.accept(listener).await });
+
+    let client = Client::dial(addr).await.unwrap();
+
+    let capabilities = client.feed().capabilities().await.unwrap();
+    assert!(capabilities.supports("supports_streaming"));
+    assert!(!capabilities.supports("supports_compression"));
+    assert_eq!(capabilities.get("supports_compression"), Some(false));
+
+    // served from the cache
+    let cached = client.capabilities("Feed").await.unwrap();
+    assert_eq!(cached, capabilities);
+
+    // a service without declared capabilities describes itself as empty
+    let capabilities = client.plain().capabilities().await.unwrap();
+    assert!(capabilities.is_empty());
+
+    // the other methods are not affected
+    let reply = client.feed().latest(()).await.unwrap();
+    assert_eq!(reply, 7);
+    let reply = client.plain().echo("hello".to_string()).await.unwrap();
+    assert_eq!(reply, "hello");
+    client.close().await;
+}
+
+#[test]
+fn capabilities() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(test_capabilities());
+}
@@ -0,0 +1,100 @@
+//! Tests of the timers on the paused clock of `test_util::clock`. The clock is shared
+//! by the whole process, so these tests are kept in their own binary and run one
+//! after the other.
+
+#![cfg(all(
+    feature = "test-util",
+    feature = "server",
+    feature = "client",
+    not(feature = "http_actix_web"),
+    any(
+        all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+        all(feature = "async_std_runtime", not(feature = "tokio_runtime"))
+    )
+))]
+
+use futures::{FutureExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+use toy_rpc::{pubsub::Topic, test_util::clock, util::spawn_task, Error, Server};
+
+mod harness;
+mod rpc;
+
+use harness::{Pair, Transport};
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+struct Tick(u32);
+
+impl Topic for Tick {
+    type Item = Tick;
+
+    fn topic() -> String {
+        "Tick".into()
+    }
+}
+
+/// Waits until `count` timers are started on the paused clock
+async fn wait_for_timers(count: usize) {
+    let start = Instant::now();
+    while clock::pending_timers() < count {
+        assert!(start.elapsed() < Duration::from_secs(5));
+        harness::sleep(Duration::from_millis(1)).await;
+    }
+}
+
+/// A call times out once the clock is advanced past its timeout
+async fn run_call_timeout(pair: &Pair) {
+    let client = pair.dial().await;
+    let mut call = spawn_task(async move {
+        let reply: Result<(), Error> = client
+            .timeout(Duration::from_secs(60))
+            .call("CommonTest.sleep_millis", 3_600_000u64)
+            .await;
+        reply
+    });
+    wait_for_timers(1).await;
+
+    clock::advance(Duration::from_secs(59));
+    harness::sleep(Duration::from_millis(50)).await;
+    assert!((&mut call).now_or_never().is_none());
+
+    clock::advance(Duration::from_secs(1));
+    let reply = call.await.unwrap();
+    assert!(matches!(reply, Err(Error::Timeout(_))));
+}
+
+/// A delayed publication is delivered once the clock is advanced past its delay
+async fn run_delayed_publication(pair: &Pair) {
+    let mut client = pair.dial().await;
+    let mut subscriber = client.subscriber::<Tick>(4).unwrap();
+    harness::wait_for_subscription::<Tick>(pair).await;
+
+    let timers = clock::pending_timers();
+    let mut publisher = pair.server.publisher::<Tick>();
+    publisher
+        .send_delayed(Tick(1), Duration::from_secs(3600))
+        .await
+        .unwrap();
+    wait_for_timers(timers + 1).await;
+
+    clock::advance(Duration::from_secs(3600));
+    let item = subscriber.next().await.unwrap().unwrap();
+    assert_eq!(item, Tick(1));
+}
+
+#[test]
+fn test_paused_clock() {
+    harness::block_on(async {
+        let server = Server::builder().register(rpc::CommonTest::new()).build();
+        let pair = Pair::start(server, Transport::Tcp).await;
+        let start = Instant::now();
+
+        clock::pause();
+        run_call_timeout(&pair).await;
+        run_delayed_publication(&pair).await;
+        clock::resume();
+
+        assert!(start.elapsed() < Duration::from_secs(30));
+    });
+}
@@ -0,0 +1,151 @@
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio::task;
+use toy_rpc::codec::negotiate::{ContentType, ACCEPT, CONTENT_TYPE};
+use toy_rpc::macros::export_impl;
+use toy_rpc::{Client, Error, Server};
+
+pub struct Echo;
+
+#[export_impl]
+impl Echo {
+    #[export_method]
+    async fn shout(&self, args: String) -> Result<String, String> {
+        Ok(args.to_uppercase())
+    }
+
+    #[export_method]
+    async fn sum(&self, args: Vec<(u8, i64)>) -> Result<i64, String> {
+        Ok(args
+            .iter()
+            .map(|(count, value)| *count as i64 * value)
+            .sum())
+    }
+
+    #[export_method]
+    async fn describe(&self, _args: ()) -> Result<Vec<(String, u32)>, String> {
+        Ok(vec![("shout".into(), 1), ("sum".into(), 2)])
+    }
+
+    #[export_method]
+    async fn fail(&self, _args: ()) -> Result<(), String> {
+        Err("Failed".into())
+    }
+}
+
+async fn test_codec_negotiation() {
+    let server = Server::builder()
+        .register(Arc::new(Echo))
+        .content_types(vec![
+            ContentType::Bincode,
+            ContentType::Json,
+            ContentType::MessagePack,
+        ])
+        .build();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    task::spawn(async move { server.accept(listener).await });
+
+    // CBOR is not supported by the server, so the client falls back to JSON
+    for content_types in [
+        vec![ContentType::Bincode],
+        vec![ContentType::Cbor, ContentType::Json],
+        vec![ContentType::MessagePack],
+    ] {
+        let client = Client::builder()
+            .content_types(content_types)
+            .dial(addr)
+            .await
+            .unwrap();
+
+        let reply: String = client
+            .call("Echo.shout", "hello".to_string())
+            .await
+            .unwrap();
+        assert_eq!(reply, "HELLO");
+        let reply: i64 = client
+            .call("Echo.sum", vec![(2u8, -3i64), (1, 10)])
+            .await
+            .unwrap();
+        assert_eq!(reply, 4);
+        let reply: Result<(), Error> = client.call("Echo.fail", ()).await;
+        assert!(matches!(reply, Err(Error::ExecutionError(msg)) if msg == "Failed"));
+        client.close().await;
+    }
+
+    let result = Client::builder()
+        .content_types(vec![ContentType::Cbor])
+        .dial(addr)
+        .await;
+    assert!(matches!(result, Err(Error::NoCommonContentType)));
+}
+
+#[test]
+fn codec_negotiation() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(test_codec_negotiation());
+}
+
+async fn test_per_call_content_type() {
+    let server = Server::builder()
+        .register(Arc::new(Echo))
+        .content_types(vec![ContentType::Bincode, ContentType::Json])
+        .build();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    task::spawn(async move { server.accept(listener).await });
+
+    let client = Client::builder()
+        .content_types(vec![ContentType::Bincode])
+        .dial(addr)
+        .await
+        .unwrap();
+
+    // a JSON response on a bincode connection can be read without knowing its type
+    let describe = client.prepare("Echo.describe").accept(ContentType::Json);
+    let (value, metadata): (serde_json::Value, _) = describe.call_with_metadata(()).await.unwrap();
+    assert_eq!(value, serde_json::json!([["shout", 1], ["sum", 2]]));
+    assert_eq!(
+        metadata.get(CONTENT_TYPE).map(String::as_str),
+        Some(ContentType::Json.as_str())
+    );
+
+    let reply: i64 = client
+        .prepare("Echo.sum")
+        .accept(ContentType::MessagePack)
+        .call(vec![(3u8, 5i64)])
+        .await
+        .unwrap();
+    assert_eq!(reply, 15);
+
+    let reply: Result<(), Error> = client
+        .prepare("Echo.fail")
+        .accept(ContentType::Cbor)
+        .call(())
+        .await;
+    assert!(matches!(reply, Err(Error::ExecutionError(msg)) if msg == "Failed"));
+
+    // an unknown content type falls back to the content type of the connection
+    let (reply, metadata): (String, _) = client
+        .prepare("Echo.shout")
+        .meta(ACCEPT, "text/plain")
+        .call_with_metadata("hello".to_string())
+        .await
+        .unwrap();
+    assert_eq!(reply, "HELLO");
+    assert!(metadata.get(CONTENT_TYPE).is_none());
+
+    // the other calls keep the content type of the connection
+    let reply: String = client
+        .call("Echo.shout", "again".to_string())
+        .await
+        .unwrap();
+    assert_eq!(reply, "AGAIN");
+    client.close().await;
+}
+
+#[test]
+fn per_call_content_type() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(test_per_call_content_type());
+}
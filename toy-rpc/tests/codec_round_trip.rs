@@ -0,0 +1,86 @@
+use proptest::prelude::*;
+use serde::Serialize;
+#[cfg(all(
+    feature = "canonical",
+    any(feature = "serde_json", feature = "serde_cbor")
+))]
+use std::collections::{BTreeMap, HashMap};
+use toy_rpc::codec::{DefaultCodec, EraseDeserializer, Marshal as _, Reserved};
+use toy_rpc::message::RemoteError;
+use toy_rpc::test_util::{
+    arb_header, arb_value, assert_erased_round_trip, assert_round_trip, Value,
+};
+use toy_rpc_core::message::ErrorMessage;
+
+type Codec = DefaultCodec<Reserved, Reserved, Reserved>;
+
+proptest! {
+    #[test]
+    fn header_round_trip(header in arb_header()) {
+        assert_round_trip::<Codec, _>(&header);
+    }
+
+    #[test]
+    fn body_round_trip(body in arb_value()) {
+        assert_round_trip::<Codec, _>(&body);
+        assert_erased_round_trip::<Codec, _>(&body);
+    }
+
+    #[test]
+    fn integer_body_round_trip(a in any::<i16>(), b in any::<u16>(), c in any::<i64>()) {
+        assert_erased_round_trip::<Codec, _>(&a);
+        assert_erased_round_trip::<Codec, _>(&(a, b, c));
+        assert_erased_round_trip::<Codec, _>(&Value::I16(a));
+    }
+
+    #[cfg(all(feature = "canonical", any(feature = "serde_json", feature = "serde_cbor")))]
+    #[test]
+    fn canonical_map_encoding(entries in proptest::collection::hash_map(any::<String>(), any::<u32>(), 0..32)) {
+        // the iteration order of a `HashMap` differs from the order of the keys
+        let sorted: BTreeMap<String, u32> = entries.iter().map(|(k, v)| (k.clone(), *v)).collect();
+        let reversed: HashMap<String, u32> = sorted.iter().rev().map(|(k, v)| (k.clone(), *v)).collect();
+        prop_assert_eq!(Codec::marshal(&entries).unwrap(), Codec::marshal(&sorted).unwrap());
+        prop_assert_eq!(Codec::marshal(&reversed).unwrap(), Codec::marshal(&sorted).unwrap());
+        assert_round_trip::<Codec, _>(&reversed);
+    }
+}
+
+/// `ErrorMessage` of a later version with a new error kind
+#[derive(Serialize)]
+#[serde(rename = "ErrorMessage")]
+#[allow(dead_code)]
+enum NewerErrorMessage {
+    InvalidArgument,
+    ServiceNotFound,
+    MethodNotFound,
+    ExecutionError(String),
+    TopicRejected(String),
+    PayloadTooLarge(String),
+    Timeout(String),
+    Overloaded(String),
+}
+
+#[test]
+fn unknown_error_kind_is_remote() {
+    let buf = Codec::marshal(&NewerErrorMessage::Overloaded("queue is full".into())).unwrap();
+    let mut de = Codec::from_bytes(buf);
+    let msg: ErrorMessage = erased_serde::deserialize(&mut de).unwrap();
+    match msg {
+        ErrorMessage::Remote(RemoteError { kind, payload }) => {
+            // depending on the format, the variant is encoded by name or by index
+            assert!(
+                kind == "Overloaded" || kind == "7",
+                "unexpected kind {}",
+                kind
+            );
+            assert_eq!(payload, "queue is full");
+        }
+        msg => panic!("Expecting a remote error, got {:?}", msg),
+    }
+
+    let buf = Codec::marshal(&NewerErrorMessage::PayloadTooLarge("1 MB".into())).unwrap();
+    let mut de = Codec::from_bytes(buf);
+    let msg: ErrorMessage = erased_serde::deserialize(&mut de).unwrap();
+    assert_eq!(msg, ErrorMessage::PayloadTooLarge("1 MB".into()));
+    assert_erased_round_trip::<Codec, _>(&ErrorMessage::MethodNotFound);
+}
@@ -0,0 +1,105 @@
+// custom codecs use the frame transport, which is not built with `serde_json`
+#![cfg(not(feature = "serde_json"))]
+
+use serde::{Deserialize, Serialize};
+use std::io::Cursor;
+use std::sync::Arc;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task;
+use toy_rpc::codec::custom::{erase_owned, CustomCodec};
+use toy_rpc::codec::{EraseDeserializer, Marshal, Unmarshal};
+use toy_rpc::macros::export_impl;
+use toy_rpc::{Client, Error, Server};
+
+/// `bincode` with the fixed-size encoding of the integers, which none of the codecs
+/// of `toy-rpc` uses
+pub struct Fixint;
+
+fn parse_error(err: bincode::Error) -> Error {
+    Error::ParseError(Box::new(err))
+}
+
+impl Marshal for Fixint {
+    fn marshal<S: Serialize>(val: &S) -> Result<Vec<u8>, Error> {
+        bincode::serialize(val).map_err(parse_error)
+    }
+}
+
+impl Unmarshal for Fixint {
+    fn unmarshal<'de, D: Deserialize<'de>>(buf: &'de [u8]) -> Result<D, Error> {
+        bincode::deserialize(buf).map_err(parse_error)
+    }
+}
+
+impl EraseDeserializer for Fixint {
+    fn from_bytes(buf: Vec<u8>) -> Box<dyn erased_serde::Deserializer<'static> + Send> {
+        use bincode::Options;
+        let options = bincode::options().with_fixint_encoding();
+        erase_owned(bincode::Deserializer::with_reader(
+            Cursor::new(buf),
+            options,
+        ))
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Point {
+    x: i64,
+    y: i64,
+}
+
+pub struct Geometry;
+
+#[export_impl]
+impl Geometry {
+    #[export_method]
+    async fn manhattan(&self, args: (Point, Point)) -> Result<u64, String> {
+        let (a, b) = args;
+        Ok(a.x.abs_diff(b.x) + a.y.abs_diff(b.y))
+    }
+
+    #[export_method]
+    async fn name(&self, args: Option<String>) -> Result<String, String> {
+        args.ok_or_else(|| "No name".to_string())
+    }
+}
+
+async fn test_custom_codec() {
+    let server = Server::builder().register(Arc::new(Geometry)).build();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server_handle = task::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        server
+            .serve_codec(CustomCodec::<_, _, Fixint>::new(stream))
+            .await
+    });
+
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let client = Client::with_custom_codec::<_, Fixint>(stream);
+
+    let reply: u64 = client
+        .call(
+            "Geometry.manhattan",
+            (Point { x: -3, y: 4 }, Point { x: 2, y: -1 }),
+        )
+        .await
+        .unwrap();
+    assert_eq!(reply, 10);
+    let reply: String = client
+        .call("Geometry.name", Some("origin".to_string()))
+        .await
+        .unwrap();
+    assert_eq!(reply, "origin");
+    let reply: Result<String, Error> = client.call("Geometry.name", None::<String>).await;
+    assert!(matches!(reply, Err(Error::ExecutionError(msg)) if msg == "No name"));
+
+    client.close().await;
+    server_handle.await.unwrap().unwrap();
+}
+
+#[test]
+fn custom_codec() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(test_custom_codec());
+}
@@ -0,0 +1,231 @@
+use std::ffi::CString;
+use std::io::{ErrorKind, Read, Write};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::task;
+use toy_rpc::codec::postcard::PostcardCodec;
+use toy_rpc::macros::export_impl;
+use toy_rpc::Server;
+use toy_rpc_core::client::{CallError, Client, Transport};
+use toy_rpc_core::ffi::*;
+use toy_rpc_core::message::ErrorMessage;
+
+pub struct Arith;
+
+#[export_impl]
+impl Arith {
+    #[export_method]
+    async fn add(&self, args: (i32, i32)) -> Result<i32, String> {
+        Ok(args.0 + args.1)
+    }
+
+    #[export_method]
+    async fn div(&self, args: (i32, i32)) -> Result<i32, String> {
+        match args.1 {
+            0 => Err("Divide by zero".into()),
+            _ => Ok(args.0 / args.1),
+        }
+    }
+
+    #[export_method]
+    async fn shout(&self, args: String) -> Result<String, String> {
+        Ok(args.to_uppercase())
+    }
+}
+
+/// Transport over a non-blocking `TcpStream`, like the socket of a network
+/// co-processor
+struct TcpTransport(std::net::TcpStream);
+
+impl Transport for TcpTransport {
+    type Error = std::io::Error;
+
+    fn write_all(&mut self, buf: &[u8]) -> Result<(), Self::Error> {
+        let mut written = 0;
+        while written < buf.len() {
+            match self.0.write(&buf[written..]) {
+                Ok(n) => written += n,
+                Err(err) if err.kind() == ErrorKind::WouldBlock => std::thread::yield_now(),
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        match self.0.read(buf) {
+            Ok(0) => Err(ErrorKind::UnexpectedEof.into()),
+            Ok(n) => Ok(n),
+            Err(err) if err.kind() == ErrorKind::WouldBlock => Ok(0),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+fn run_client(addr: SocketAddr) {
+    let stream = std::net::TcpStream::connect(addr).unwrap();
+    stream.set_nonblocking(true).unwrap();
+    let mut client: Client<_, 4, 256> = Client::new(TcpTransport(stream));
+    let timeout = Duration::from_secs(5);
+
+    let add = client.call("Arith.add", &(1i32, 6i32), timeout).unwrap();
+    let div = client.call("Arith.div", &(1i32, 0i32), timeout).unwrap();
+    let shout = client.call("Arith.shout", "hello", timeout).unwrap();
+    let missing = client.call("Arith.mul", &(1i32, 6i32), timeout).unwrap();
+    assert_eq!(client.pending(), 4);
+
+    // the server may reply in any order
+    let mut replied = 0;
+    while replied < 4 {
+        let reply = match client.poll().unwrap() {
+            Some(reply) => reply,
+            None => {
+                std::thread::sleep(Duration::from_millis(1));
+                continue;
+            }
+        };
+        if reply.id == add {
+            assert_eq!(reply.result::<i32>(), Ok(7));
+        } else if reply.id == div {
+            let expected = ErrorMessage::ExecutionError("Divide by zero".into());
+            assert_eq!(reply.result::<i32>(), Err(CallError::Remote(expected)));
+        } else if reply.id == shout {
+            assert_eq!(reply.result::<&str>(), Ok("HELLO"));
+        } else if reply.id == missing {
+            let expected = ErrorMessage::MethodNotFound;
+            assert_eq!(reply.result::<i32>(), Err(CallError::Remote(expected)));
+        } else {
+            panic!("Unexpected reply {}", reply.id);
+        }
+        replied += 1;
+    }
+    assert_eq!(client.pending(), 0);
+}
+
+/// Polls `client` until it returns a message
+unsafe fn poll_ffi(client: *mut ToyRpcClient) -> ToyRpcMessage {
+    let mut message = std::mem::MaybeUninit::<ToyRpcMessage>::uninit();
+    loop {
+        assert_eq!(toy_rpc_poll(client, message.as_mut_ptr()), TOY_RPC_OK);
+        let message = message.assume_init_read();
+        if message.kind != TOY_RPC_NONE {
+            return message;
+        }
+        std::thread::sleep(Duration::from_millis(1));
+    }
+}
+
+/// Uses the C interface like the bindings of another language would
+unsafe fn run_ffi_client(addr: SocketAddr) {
+    let addr = CString::new(addr.to_string()).unwrap();
+    let client = toy_rpc_connect(addr.as_ptr());
+    assert!(!client.is_null());
+    let publisher = toy_rpc_connect(addr.as_ptr());
+    assert!(!publisher.is_null());
+
+    let service_method = CString::new("Arith.add").unwrap();
+    let args = postcard::to_allocvec(&(1i32, 6i32)).unwrap();
+    let mut id = 0;
+    let status = toy_rpc_call(
+        client,
+        service_method.as_ptr(),
+        args.as_ptr(),
+        args.len(),
+        5000,
+        &mut id,
+    );
+    assert_eq!(status, TOY_RPC_OK);
+    let reply = poll_ffi(client);
+    assert_eq!(reply.kind, TOY_RPC_REPLY_OK);
+    assert_eq!(reply.id, id);
+    let body = std::slice::from_raw_parts(reply.body, reply.body_len);
+    assert_eq!(postcard::from_bytes::<i32>(body).unwrap(), 7);
+
+    let service_method = CString::new("Arith.div").unwrap();
+    let args = postcard::to_allocvec(&(1i32, 0i32)).unwrap();
+    let status = toy_rpc_call(
+        client,
+        service_method.as_ptr(),
+        args.as_ptr(),
+        args.len(),
+        5000,
+        &mut id,
+    );
+    assert_eq!(status, TOY_RPC_OK);
+    let reply = poll_ffi(client);
+    assert_eq!(reply.kind, TOY_RPC_REPLY_ERR);
+    let body = std::slice::from_raw_parts(reply.body, reply.body_len);
+    assert_eq!(
+        postcard::from_bytes::<ErrorMessage>(body).unwrap(),
+        ErrorMessage::ExecutionError("Divide by zero".into())
+    );
+
+    // publications from another client, until the subscription has reached the server
+    let topic = CString::new("Count").unwrap();
+    assert_eq!(toy_rpc_subscribe(client, topic.as_ptr()), TOY_RPC_OK);
+    let item = postcard::to_allocvec(&42u32).unwrap();
+    let mut message = std::mem::MaybeUninit::<ToyRpcMessage>::uninit();
+    let publication = loop {
+        let status = toy_rpc_publish(publisher, topic.as_ptr(), item.as_ptr(), item.len());
+        assert_eq!(status, TOY_RPC_OK);
+        std::thread::sleep(Duration::from_millis(10));
+        assert_eq!(toy_rpc_poll(client, message.as_mut_ptr()), TOY_RPC_OK);
+        let message = message.assume_init_read();
+        if message.kind != TOY_RPC_NONE {
+            break message;
+        }
+    };
+    assert_eq!(publication.kind, TOY_RPC_PUBLICATION);
+    let name = std::slice::from_raw_parts(publication.topic, publication.topic_len);
+    assert_eq!(name, b"Count");
+    let body = std::slice::from_raw_parts(publication.body, publication.body_len);
+    assert_eq!(postcard::from_bytes::<u32>(body).unwrap(), 42);
+    assert_eq!(toy_rpc_unsubscribe(client, topic.as_ptr()), TOY_RPC_OK);
+
+    // null pointers are rejected
+    let status = toy_rpc_call(
+        client,
+        std::ptr::null(),
+        args.as_ptr(),
+        args.len(),
+        5000,
+        &mut id,
+    );
+    assert_eq!(status, TOY_RPC_ERR_ARGUMENT);
+    assert!(toy_rpc_connect(std::ptr::null()).is_null());
+
+    toy_rpc_close(publisher);
+    toy_rpc_close(client);
+}
+
+async fn run() {
+    // every connection is served by the same server
+    let server = Arc::new(Server::builder().register(Arc::new(Arith)).build());
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server_handle = task::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            let server = server.clone();
+            task::spawn(async move {
+                let _ = server.serve_codec(PostcardCodec::new(stream)).await;
+            });
+        }
+    });
+
+    task::spawn_blocking(move || run_client(addr))
+        .await
+        .unwrap();
+    task::spawn_blocking(move || unsafe { run_ffi_client(addr) })
+        .await
+        .unwrap();
+    server_handle.abort();
+}
+
+#[test]
+fn test_main() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(run());
+}
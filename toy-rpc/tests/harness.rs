@@ -0,0 +1,218 @@
+//! In-process test harness
+//!
+//! Spins up a server and a client connected to it over any of the `TRANSPORTS`
+//! with whatever runtime and codec the tests are compiled with, and provides
+//! assertions for replies, errors, timeouts and pubsub delivery. A test written
+//! against the harness covers the whole runtime/codec matrix when run with
+//! `cargo make test_matrix`.
+//!
+//! ```rust
+//! mod harness;
+//! use harness::{Pair, TRANSPORTS};
+//!
+//! harness::block_on(async {
+//!     for transport in TRANSPORTS {
+//!         let pair = Pair::start(Server::builder().register(service).build(), transport).await;
+//!         harness::assert_reply(&pair.client, "Service.echo", 1i32, 1i32).await;
+//!     }
+//! });
+//! ```
+
+#![allow(dead_code)]
+
+cfg_if::cfg_if! {
+    if #[cfg(all(
+        feature = "server",
+        feature = "client",
+        not(feature = "http_actix_web"),
+        any(
+            all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+            all(feature = "async_std_runtime", not(feature = "tokio_runtime"))
+        )
+    ))] {
+        use futures::{Future, SinkExt, StreamExt};
+        use serde::{de::DeserializeOwned, Serialize};
+        use std::{fmt::Debug, net::SocketAddr, time::Duration};
+        use toy_rpc::{
+            pubsub::Topic,
+            util::{spawn_task, TaskHandle},
+            Client, Error, Server,
+        };
+
+        #[cfg(all(feature = "tokio_runtime", not(feature = "async_std_runtime")))]
+        use ::tokio::net::TcpListener;
+        #[cfg(all(feature = "async_std_runtime", not(feature = "tokio_runtime")))]
+        use ::async_std::net::TcpListener;
+
+        /// How the client connects to the server
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub enum Transport {
+            Tcp,
+            WebSocket,
+        }
+
+        /// All transports supported by the harness
+        pub const TRANSPORTS: [Transport; 2] = [Transport::Tcp, Transport::WebSocket];
+
+        /// Runs a future to completion on the runtime the tests are compiled with
+        pub fn block_on<F: Future>(fut: F) -> F::Output {
+            #[cfg(all(feature = "tokio_runtime", not(feature = "async_std_runtime")))]
+            let output = ::tokio::runtime::Runtime::new()
+                .expect("Error creating runtime")
+                .block_on(fut);
+            #[cfg(all(feature = "async_std_runtime", not(feature = "tokio_runtime")))]
+            let output = ::async_std::task::block_on(fut);
+            output
+        }
+
+        /// Sleeps on the runtime the tests are compiled with
+        pub async fn sleep(duration: Duration) {
+            #[cfg(all(feature = "tokio_runtime", not(feature = "async_std_runtime")))]
+            ::tokio::time::sleep(duration).await;
+            #[cfg(all(feature = "async_std_runtime", not(feature = "tokio_runtime")))]
+            ::async_std::task::sleep(duration).await;
+        }
+
+        /// A running server and a client connected to it. The server is stopped when
+        /// the `Pair` is dropped.
+        pub struct Pair {
+            pub server: Server,
+            pub client: Client,
+            pub addr: SocketAddr,
+            pub transport: Transport,
+            handle: TaskHandle<Result<(), Error>>,
+        }
+
+        impl Pair {
+            /// Starts `server` on an ephemeral port and connects a client to it
+            pub async fn start(server: Server, transport: Transport) -> Self {
+                let listener = TcpListener::bind("127.0.0.1:0")
+                    .await
+                    .expect("Cannot bind to address");
+                let addr = listener.local_addr().expect("Cannot get local address");
+
+                let accepting = server.clone();
+                let handle = spawn_task(async move {
+                    match transport {
+                        Transport::Tcp => accepting.accept(listener).await,
+                        Transport::WebSocket => accepting.accept_websocket(listener).await,
+                    }
+                });
+
+                let client = dial(addr, transport).await;
+
+                Self {
+                    server,
+                    client,
+                    addr,
+                    transport,
+                    handle,
+                }
+            }
+        }
+
+        impl Pair {
+            /// Connects another client to the server
+            pub async fn dial(&self) -> Client {
+                dial(self.addr, self.transport).await
+            }
+        }
+
+        async fn dial(addr: SocketAddr, transport: Transport) -> Client {
+            match transport {
+                Transport::Tcp => Client::dial(addr).await,
+                Transport::WebSocket => Client::dial_websocket(&format!("ws://{}", addr)).await,
+            }
+            .expect("Error dialing server")
+        }
+
+        impl Drop for Pair {
+            fn drop(&mut self) {
+                self.handle.abort();
+            }
+        }
+
+        /// Asserts that the call succeeds with the `expected` reply
+        pub async fn assert_reply<Req, Res>(client: &Client, service_method: &str, args: Req, expected: Res)
+        where
+            Req: Serialize + Send + Sync + 'static,
+            Res: DeserializeOwned + PartialEq + Debug + Send + 'static,
+        {
+            let reply: Res = client
+                .call(service_method, args)
+                .await
+                .unwrap_or_else(|err| panic!("{} failed: {}", service_method, err));
+            assert_eq!(reply, expected, "Unexpected reply from {}", service_method);
+        }
+
+        /// Asserts that the call fails with an error that has the same message as `expected`
+        pub async fn assert_error<Req>(client: &Client, service_method: &str, args: Req, expected: Error)
+        where
+            Req: Serialize + Send + Sync + 'static,
+        {
+            let reply: Result<(), Error> = client.call(service_method, args).await;
+            match reply {
+                Ok(_) => panic!("Expecting {} to fail with {}", service_method, expected),
+                Err(err) => assert_eq!(
+                    err.to_string(),
+                    expected.to_string(),
+                    "Unexpected error from {}",
+                    service_method
+                ),
+            }
+        }
+
+        /// Asserts that the call times out with the given `timeout`
+        pub async fn assert_timeout<Req>(client: &Client, service_method: &str, args: Req, timeout: Duration)
+        where
+            Req: Serialize + Send + Sync + 'static,
+        {
+            let reply: Result<(), Error> = client
+                .set_next_timeout(timeout)
+                .call(service_method, args)
+                .await;
+            match reply {
+                Err(Error::Timeout(_)) => {}
+                other => panic!("Expecting {} to time out, got {:?}", service_method, other),
+            }
+        }
+
+        /// Waits until the subscription of the client to `T` reaches the server
+        pub async fn wait_for_subscription<T: Topic>(pair: &Pair) {
+            let admin = pair.server.topic_admin();
+            let mut retries = 0;
+            while admin.subscriber_count(T::topic()).await.unwrap_or(0) == 0 {
+                retries += 1;
+                assert!(retries < 100, "Subscription to {} never reached the server", T::topic());
+                sleep(Duration::from_millis(10)).await;
+            }
+        }
+
+        /// Asserts that `items` published on the server are delivered, in order, to a
+        /// subscriber on the client
+        pub async fn assert_pubsub<T>(pair: &mut Pair, items: Vec<T::Item>)
+        where
+            T: Topic + 'static,
+            T::Item: Clone + PartialEq + Debug,
+        {
+            let mut subscriber = pair
+                .client
+                .subscriber::<T>(items.len().max(1))
+                .expect("Error creating subscriber");
+            wait_for_subscription::<T>(pair).await;
+
+            let mut publisher = pair.server.publisher::<T>();
+            for item in &items {
+                publisher.send(item.clone()).await.expect("Error publishing");
+            }
+            for expected in items {
+                let item = subscriber
+                    .next()
+                    .await
+                    .expect("Subscriber stream ended")
+                    .expect("Error receiving publication");
+                assert_eq!(item, expected, "Unexpected item on {}", T::topic());
+            }
+        }
+    }
+}
@@ -0,0 +1,257 @@
+use futures::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::task;
+
+use toy_rpc::pubsub::Topic;
+use toy_rpc::{Client, Server};
+
+mod rpc;
+
+/// Serves `server` with hyper on an ephemeral port
+fn serve(server: Server) -> (SocketAddr, task::JoinHandle<()>) {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let handle = task::spawn(async move {
+        server
+            .serve_hyper_listener(listener)
+            .await
+            .expect("Error serving with hyper")
+    });
+    (addr, handle)
+}
+
+/// Sends a plain HTTP request and returns the status line of the response
+async fn status_line(addr: SocketAddr, request: &str) -> String {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut buf = vec![0; 1024];
+    let n = stream.read(&mut buf).await.unwrap();
+    let response = String::from_utf8_lossy(&buf[..n]).to_string();
+    response.lines().next().unwrap_or_default().to_string()
+}
+
+async fn run() {
+    let server = Server::builder()
+        .register(Arc::new(rpc::CommonTest::new()))
+        .register_extension(rpc::EXT_MARKER, rpc::ext_ping)
+        .build();
+    let (addr, server_handle) = serve(server);
+
+    let client = Client::dial_http(&format!("ws://{}/rpc/", addr))
+        .await
+        .expect("Error dialing http server");
+    rpc::test_get_magic_u8(&client).await;
+    rpc::test_get_magic_u16(&client).await;
+    rpc::test_get_magic_u32(&client).await;
+    rpc::test_get_magic_u64(&client).await;
+    rpc::test_get_magic_i8(&client).await;
+    rpc::test_get_magic_i16(&client).await;
+    rpc::test_get_magic_i32(&client).await;
+    rpc::test_get_magic_i64(&client).await;
+    rpc::test_get_magic_bool(&client).await;
+    rpc::test_get_magic_str(&client).await;
+    rpc::test_imcomplete_service_method(&client).await;
+    rpc::test_service_not_found(&client).await;
+    rpc::test_method_not_found(&client).await;
+    rpc::test_execution_error(&client).await;
+    rpc::test_large_payload(&client).await;
+    rpc::test_extension(&client).await;
+
+    // the RPC path is found under any prefix
+    let client = Client::dial_http(&format!("ws://{}/", addr))
+        .await
+        .expect("Error dialing http server");
+    rpc::test_get_magic_u8(&client).await;
+
+    // the other requests are not upgraded
+    let status = status_line(addr, "GET /rpc/ HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+    assert_eq!(status, "HTTP/1.1 404 Not Found");
+    let status = status_line(addr, "GET /rpc/_rpc_ HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+    assert_eq!(status, "HTTP/1.1 400 Bad Request");
+
+    server_handle.abort();
+}
+
+#[test]
+fn http_hyper_integration() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(run());
+}
+
+async fn run_connection_filter() {
+    let server = Server::builder()
+        .register(Arc::new(rpc::CommonTest::new()))
+        .connection_filter(|peer_addr| !peer_addr.ip().is_loopback())
+        .build();
+    let (addr, server_handle) = serve(server);
+
+    let result = tokio::time::timeout(
+        Duration::from_secs(5),
+        Client::dial_http(&format!("ws://{}/rpc/", addr)),
+    )
+    .await
+    .expect("The handshake should be rejected right away");
+    assert!(result.is_err());
+    server_handle.abort();
+}
+
+#[test]
+fn http_hyper_connection_filter() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(run_connection_filter());
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+struct Count(u32);
+
+impl Topic for Count {
+    type Item = Count;
+
+    fn topic() -> String {
+        "Count".into()
+    }
+}
+
+async fn run_http_post() {
+    let server = Server::builder()
+        .register(Arc::new(rpc::CommonTest::new()))
+        .register_extension(rpc::EXT_MARKER, rpc::ext_ping)
+        .build();
+    let admin = server.topic_admin();
+    let shutdown = server.shutdown_handle();
+    let mut publisher = server.publisher::<Count>();
+    let (addr, server_handle) = serve(server);
+
+    let mut client = Client::dial_http_post(&format!("http://{}/rpc/", addr))
+        .await
+        .expect("Error dialing http server");
+    rpc::test_get_magic_u8(&client).await;
+    rpc::test_get_magic_u16(&client).await;
+    rpc::test_get_magic_u32(&client).await;
+    rpc::test_get_magic_u64(&client).await;
+    rpc::test_get_magic_i8(&client).await;
+    rpc::test_get_magic_i16(&client).await;
+    rpc::test_get_magic_i32(&client).await;
+    rpc::test_get_magic_i64(&client).await;
+    rpc::test_get_magic_bool(&client).await;
+    rpc::test_get_magic_str(&client).await;
+    rpc::test_imcomplete_service_method(&client).await;
+    rpc::test_service_not_found(&client).await;
+    rpc::test_method_not_found(&client).await;
+    rpc::test_execution_error(&client).await;
+    rpc::test_large_payload(&client).await;
+    rpc::test_extension(&client).await;
+
+    // concurrent calls are carried by overlapping requests
+    let calls = (0..20).map(|_| client.call::<_, u8>("CommonTest.get_magic_u8", ()));
+    for reply in futures::future::join_all(calls).await {
+        assert_eq!(reply.unwrap(), rpc::COMMON_TEST_MAGIC_U8);
+    }
+
+    // the publications are pushed in the responses of the pending requests
+    let mut subscriber = client.subscriber::<Count>(10).unwrap();
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while admin.subscriber_count(Count::topic()).await.unwrap() == 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("The subscription should reach the server");
+    for i in 0..3 {
+        publisher.send(Count(i)).await.unwrap();
+    }
+    for i in 0..3 {
+        let count = tokio::time::timeout(Duration::from_secs(5), subscriber.next())
+            .await
+            .expect("The publication should be pushed")
+            .unwrap()
+            .unwrap();
+        assert_eq!(count, Count(i));
+    }
+
+    // a closed session ends its connection on the server
+    client.close().await;
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while shutdown.active_sessions() != 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("The connection of the session should end");
+
+    let request = "POST /rpc/_rpc_ HTTP/1.1\r\nHost: localhost\r\nx-toy-rpc-session: unknown\r\nContent-Length: 0\r\n\r\n";
+    assert_eq!(status_line(addr, request).await, "HTTP/1.1 404 Not Found");
+    // the body is refused before it is read
+    let request = "POST /rpc/_rpc_ HTTP/1.1\r\nHost: localhost\r\nx-toy-rpc-session: unknown\r\nContent-Length: 16777217\r\n\r\n";
+    assert_eq!(
+        status_line(addr, request).await,
+        "HTTP/1.1 413 Payload Too Large"
+    );
+
+    server_handle.abort();
+}
+
+#[test]
+fn http_hyper_post() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(run_http_post());
+}
+
+#[cfg(feature = "metrics")]
+async fn run_metrics() {
+    let server = Server::builder()
+        .register(Arc::new(rpc::CommonTest::new()))
+        .export_metrics(true)
+        .build();
+    let metrics = server.clone();
+    let (addr, server_handle) = serve(server);
+
+    let client = Client::dial_http(&format!("ws://{}/rpc/", addr))
+        .await
+        .expect("Error dialing http server");
+    rpc::test_get_magic_u8(&client).await;
+    rpc::test_get_magic_u8(&client).await;
+    rpc::test_execution_error(&client).await;
+    rpc::test_method_not_found(&client).await;
+
+    let snapshot = metrics.metrics();
+    let get_magic_u8 = snapshot.methods["CommonTest.get_magic_u8"];
+    assert_eq!(get_magic_u8.calls, 2);
+    assert_eq!(get_magic_u8.errors, 0);
+    assert_eq!(get_magic_u8.in_flight, 0);
+    assert_eq!(get_magic_u8.latency.count, 2);
+    assert_eq!(snapshot.methods["CommonTest.echo_error"].errors, 1);
+    assert!(snapshot
+        .methods
+        .keys()
+        .all(|name| name.starts_with("CommonTest.")));
+
+    let request = "GET /rpc/metrics HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n";
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK"));
+    assert!(response.contains("content-type: text/plain; version=0.0.4"));
+    assert!(
+        response.contains("toy_rpc_calls_total{service=\"CommonTest\",method=\"get_magic_u8\"} 2")
+    );
+    assert!(
+        response.contains("toy_rpc_errors_total{service=\"CommonTest\",method=\"echo_error\"} 1")
+    );
+
+    client.close().await;
+    server_handle.abort();
+}
+
+#[cfg(feature = "metrics")]
+#[test]
+fn http_hyper_metrics() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(run_metrics());
+}
@@ -0,0 +1,515 @@
+//! Runs the common RPC and pubsub tests over every transport with the runtime and codec
+//! this test is compiled with. Use `cargo make test_matrix` to cover all combinations.
+
+#![cfg(all(
+    feature = "server",
+    feature = "client",
+    not(feature = "http_actix_web"),
+    any(
+        all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+        all(feature = "async_std_runtime", not(feature = "tokio_runtime"))
+    )
+))]
+
+use futures::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+use std::time::{Duration, Instant};
+use toy_rpc::{
+    client::pubsub::{DropPolicy, ErrorPolicy},
+    macros::{export_impl, export_topics},
+    pubsub::Topic,
+    Client, Error, Server,
+};
+
+mod harness;
+mod rpc;
+
+use harness::{Pair, TRANSPORTS};
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+struct Count(u32);
+
+impl Topic for Count {
+    type Item = Count;
+
+    fn topic() -> String {
+        "Count".into()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+struct Tick(u32);
+
+impl Topic for Tick {
+    type Item = Tick;
+
+    fn topic() -> String {
+        "Tick".into()
+    }
+}
+
+/// A topic whose items are published with acknowledgements
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+struct Receipt(u32);
+
+impl Topic for Receipt {
+    type Item = Receipt;
+
+    fn topic() -> String {
+        "Receipt".into()
+    }
+}
+
+/// Topics matched by the `sensor/*` pattern
+struct Kitchen;
+
+impl Topic for Kitchen {
+    type Item = u32;
+
+    fn topic() -> String {
+        "sensor/kitchen".into()
+    }
+}
+
+struct Garage;
+
+impl Topic for Garage {
+    type Item = u32;
+
+    fn topic() -> String {
+        "sensor/garage".into()
+    }
+}
+
+/// A topic the server rejects
+struct Nameless;
+
+impl Topic for Nameless {
+    type Item = u32;
+
+    fn topic() -> String {
+        String::new()
+    }
+}
+
+struct Text;
+
+impl Topic for Text {
+    type Item = String;
+
+    fn topic() -> String {
+        "Text".into()
+    }
+}
+
+/// Topics whose typed publishers and subscribers are generated on the client
+#[export_topics]
+mod alerts {
+    use serde::{Deserialize, Serialize};
+    use toy_rpc::pubsub::Topic;
+
+    #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+    pub struct Alert(pub String);
+
+    impl Topic for Alert {
+        type Item = Alert;
+
+        fn topic() -> String {
+            "Alert".into()
+        }
+    }
+
+    pub struct SensorReading;
+
+    impl Topic for SensorReading {
+        type Item = f64;
+
+        fn topic() -> String {
+            "SensorReading".into()
+        }
+    }
+}
+
+use alerts::TopicsClientStub;
+
+/// Publishes numbers on the topic of `Text`
+struct Numbers;
+
+impl Topic for Numbers {
+    type Item = u32;
+
+    fn topic() -> String {
+        Text::topic()
+    }
+}
+
+fn server_with(sleeper: Arc<Sleeper>) -> Server {
+    Server::builder()
+        .register(rpc::CommonTest::new())
+        .register(sleeper)
+        .register_transactional(rpc::Ledger::default())
+        .register_extension(rpc::EXT_MARKER, rpc::ext_ping)
+        .redelivery_timeout(Duration::from_millis(100))
+        .build()
+}
+
+/// Counts the sleeps that start and the sleeps that run to completion
+#[derive(Default)]
+struct Sleeper {
+    started: AtomicUsize,
+    finished: AtomicUsize,
+}
+
+#[export_impl]
+impl Sleeper {
+    #[export_method]
+    async fn sleep_millis(&self, millis: u64) -> Result<(), Error> {
+        self.started.fetch_add(1, Ordering::SeqCst);
+        harness::sleep(Duration::from_millis(millis)).await;
+        self.finished.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+async fn run_matrix() {
+    for transport in TRANSPORTS.iter().copied() {
+        println!("Testing {:?}", transport);
+        let sleeper = Arc::new(Sleeper::default());
+        let mut pair = Pair::start(server_with(sleeper.clone()), transport).await;
+        let client = &pair.client;
+
+        harness::assert_reply(
+            client,
+            "CommonTest.get_magic_i16",
+            (),
+            rpc::COMMON_TEST_MAGIC_I16,
+        )
+        .await;
+        harness::assert_reply(
+            client,
+            "CommonTest.get_magic_str",
+            (),
+            rpc::COMMON_TEST_MAGIC_STR.to_string(),
+        )
+        .await;
+        harness::assert_error(
+            client,
+            "CommonTest.echo_error",
+            "an error message".to_string(),
+            Error::ExecutionError("an error message".into()),
+        )
+        .await;
+        harness::assert_error(client, "Undefined.method", (), Error::ServiceNotFound).await;
+        harness::assert_timeout(
+            client,
+            "CommonTest.sleep_millis",
+            1000u64,
+            Duration::from_millis(100),
+        )
+        .await;
+        assert_call_timeouts(client).await;
+        rpc::test_extension(client).await;
+        rpc::test_metadata(client).await;
+        rpc::test_prepared_calls(client).await;
+        rpc::test_pagination(client).await;
+        rpc::test_streaming(client).await;
+        rpc::test_client_streaming(client).await;
+        rpc::test_transactions(client).await;
+        assert_pending_requests(&pair).await;
+        assert_cancellation(&pair, &sleeper).await;
+
+        harness::assert_pubsub::<Count>(&mut pair, vec![Count(1), Count(2), Count(3)]).await;
+        assert_multiplexed_subscribers(&mut pair).await;
+        assert_subscriber_errors(&mut pair).await;
+        assert_type_tags(&mut pair).await;
+        assert_acked_publications(&mut pair).await;
+        assert_pattern_subscribers(&mut pair).await;
+        assert_named_topics(&mut pair).await;
+        assert_topic_stubs(&mut pair).await;
+    }
+}
+
+/// A timeout set with `Client::timeout` applies to the calls made through it only
+async fn assert_call_timeouts(client: &Client) {
+    let short = client.timeout(Duration::from_millis(100));
+    let result = short
+        .call::<_, ()>("CommonTest.sleep_millis", 1000u64)
+        .await;
+    assert!(matches!(result, Err(Error::Timeout(_))));
+    let mut items = short.call_stream::<_, u32>("CommonTest.count_up", 3u32);
+    assert_eq!(items.next().await.unwrap().unwrap(), 0);
+
+    let result = client
+        .call::<_, ()>("CommonTest.sleep_millis", 200u64)
+        .await;
+    assert!(result.is_ok());
+}
+
+/// Calls waiting for their responses are listed by the client
+async fn assert_pending_requests(pair: &Pair) {
+    let client = &pair.client;
+    let slow = client.call::<_, ()>("CommonTest.sleep_millis", 200u64);
+    let pending = client.pending_requests().await.unwrap();
+    assert_eq!(pending.len(), 1);
+    assert_eq!(pending[0].service_method, "CommonTest.sleep_millis");
+    assert!(pending[0].age < Duration::from_millis(200));
+
+    slow.await.unwrap();
+    assert!(client.pending_requests().await.unwrap().is_empty());
+}
+
+/// A canceled call resolves right away, is no longer pending and is aborted on the
+/// server
+async fn assert_cancellation(pair: &Pair, sleeper: &Sleeper) {
+    let client = &pair.client;
+    let start = Instant::now();
+    let mut slow = client.call::<_, ()>("Sleeper.sleep_millis", 500u64);
+    let id = slow.get_id();
+    // cancel only once the server is running the handler
+    while sleeper.started.load(Ordering::SeqCst) == 0 {
+        harness::sleep(Duration::from_millis(10)).await;
+    }
+    slow.cancel();
+    slow.cancel();
+    assert!(matches!(slow.await, Err(Error::Canceled(Some(canceled))) if canceled == id));
+    assert!(client.pending_requests().await.unwrap().is_empty());
+
+    let reply: i16 = client.call("CommonTest.get_magic_i16", ()).await.unwrap();
+    assert_eq!(reply, rpc::COMMON_TEST_MAGIC_I16);
+    assert!(start.elapsed() < Duration::from_millis(500));
+
+    // the handler would have finished by now if it were not aborted
+    harness::sleep(Duration::from_millis(700)).await;
+    assert_eq!(sleeper.started.load(Ordering::SeqCst), 1);
+    assert_eq!(sleeper.finished.load(Ordering::SeqCst), 0);
+}
+
+/// The publishers and subscribers generated by `#[export_topics]` are typed
+async fn assert_topic_stubs(pair: &mut Pair) {
+    let mut alert_subscriber = pair.client.alert_subscriber(10).unwrap();
+    let mut reading_subscriber = pair.client.sensor_reading_subscriber(10).unwrap();
+    harness::wait_for_subscription::<alerts::Alert>(pair).await;
+    harness::wait_for_subscription::<alerts::SensorReading>(pair).await;
+
+    let mut publisher = pair.client.alert_publisher();
+    publisher.send(alerts::Alert("fire".into())).await.unwrap();
+    let mut publisher = pair.client.sensor_reading_publisher();
+    publisher.send(21.5).await.unwrap();
+    assert_eq!(
+        alert_subscriber.next().await.unwrap().unwrap(),
+        alerts::Alert("fire".into())
+    );
+    assert_eq!(reading_subscriber.next().await.unwrap().unwrap(), 21.5);
+}
+
+/// Local subscribers on the same topic share one subscription on the server
+async fn assert_multiplexed_subscribers(pair: &mut Pair) {
+    // items are delivered in the order the subscribers are created
+    let mut latest = pair
+        .client
+        .subscriber_with_policy::<Tick>(1, DropPolicy::DropOldest)
+        .unwrap();
+    let mut first = pair.client.subscriber::<Tick>(10).unwrap();
+    let mut second = pair.client.subscriber::<Tick>(10).unwrap();
+    harness::wait_for_subscription::<Tick>(pair).await;
+    let admin = pair.server.topic_admin();
+    assert_eq!(admin.subscriber_count(Tick::topic()).await.unwrap(), 1);
+
+    let mut publisher = pair.server.publisher::<Tick>();
+    for i in 1..=3 {
+        publisher.send(Tick(i)).await.unwrap();
+    }
+    for i in 1..=3 {
+        assert_eq!(first.next().await.unwrap().unwrap(), Tick(i));
+        assert_eq!(second.next().await.unwrap().unwrap(), Tick(i));
+    }
+    // only the newest item is kept by a full subscriber that drops the oldest
+    assert_eq!(latest.next().await.unwrap().unwrap(), Tick(3));
+
+    // dropping one local subscriber does not affect the others
+    drop(second);
+    publisher.send(Tick(4)).await.unwrap();
+    assert_eq!(first.next().await.unwrap().unwrap(), Tick(4));
+    assert_eq!(latest.next().await.unwrap().unwrap(), Tick(4));
+}
+
+/// Errors are delivered on the subscriber streams
+async fn assert_subscriber_errors(pair: &mut Pair) {
+    let mut rejected = pair.client.subscriber::<Nameless>(1).unwrap();
+    match rejected.next().await {
+        Some(Err(Error::TopicRejected(_))) => {}
+        other => panic!("Expecting the subscription to be rejected, got {:?}", other),
+    }
+    assert!(rejected.next().await.is_none());
+
+    let mut canceled = pair
+        .client
+        .subscriber::<Text>(10)
+        .unwrap()
+        .with_error_policy(ErrorPolicy::Cancel);
+    harness::wait_for_subscription::<Text>(pair).await;
+    let mut publisher = pair.server.publisher::<Numbers>();
+    publisher.send(1).await.unwrap();
+    publisher.send(2).await.unwrap();
+    assert!(canceled.next().await.unwrap().is_err());
+    assert!(canceled.next().await.is_none());
+}
+
+/// Items published with a type tag are checked by the subscribers
+async fn assert_type_tags(pair: &mut Pair) {
+    let mut subscriber = pair.client.subscriber::<Text>(10).unwrap();
+    harness::wait_for_subscription::<Text>(pair).await;
+    let mut numbers = pair.server.publisher::<Numbers>().with_type_tag();
+    numbers.send(1).await.unwrap();
+    match subscriber.next().await {
+        Some(Err(Error::TopicTypeMismatch(_))) => {}
+        other => panic!("Expecting a type mismatch, got {:?}", other),
+    }
+    let mut text = pair.server.publisher::<Text>().with_type_tag();
+    text.send("tagged".into()).await.unwrap();
+    assert_eq!(subscriber.next().await.unwrap().unwrap(), "tagged");
+
+    // from the client to a subscriber on the server
+    let mut server_subscriber = pair.server.subscriber::<Text>(10).unwrap();
+    let mut numbers = pair.client.publisher::<Numbers>().with_type_tag();
+    numbers.send(2).await.unwrap();
+    match server_subscriber.next().await {
+        Some(Err(Error::TopicTypeMismatch(_))) => {}
+        other => panic!("Expecting a type mismatch, got {:?}", other),
+    }
+}
+
+/// Items published with acknowledgements are delivered again until every
+/// subscriber has received them
+async fn assert_acked_publications(pair: &mut Pair) {
+    let admin = pair.server.topic_admin();
+    let mut server_subscriber = pair.server.subscriber::<Receipt>(10).unwrap();
+    let mut subscriber = pair
+        .client
+        .subscriber_with_policy::<Receipt>(1, DropPolicy::DropNewest)
+        .unwrap();
+    while admin.subscriber_count(Receipt::topic()).await.unwrap() < 2 {
+        harness::sleep(Duration::from_millis(10)).await;
+    }
+
+    // the second item is dropped by the full local subscriber and not acknowledged
+    let mut publisher = pair.client.publisher::<Receipt>().with_stats();
+    publisher.publish_with_ack(Receipt(1)).await.unwrap();
+    publisher.publish_with_ack(Receipt(2)).await.unwrap();
+
+    // the server reports the deliveries to both subscribers, since the item dropped
+    // by the client is delivered again
+    let stats = publisher.stats().unwrap().snapshot();
+    assert_eq!(stats.acked, 2);
+    assert_eq!(stats.failed, 0);
+    assert_eq!(stats.dropped, 0);
+    assert_eq!(stats.subscribers, Some(2));
+    assert!(stats.last_latency.is_some());
+    assert!(stats.max_latency >= stats.mean_latency);
+    assert_eq!(subscriber.next().await.unwrap().unwrap(), Receipt(1));
+    assert_eq!(subscriber.next().await.unwrap().unwrap(), Receipt(2));
+
+    // the subscriber on the server acknowledges the items as they are received
+    assert_eq!(server_subscriber.next().await.unwrap().unwrap(), Receipt(1));
+    assert_eq!(server_subscriber.next().await.unwrap().unwrap(), Receipt(2));
+
+    let start = Instant::now();
+    loop {
+        let stats = admin.stats().await.unwrap();
+        if stats.unacked == 0 {
+            assert!(stats.redelivered >= 1);
+            break;
+        }
+        assert!(start.elapsed() < Duration::from_secs(5));
+        harness::sleep(Duration::from_millis(10)).await;
+    }
+    pair.client.unsubscribe::<Receipt>().await.unwrap();
+}
+
+/// A pattern subscriber receives the items of every topic that matches the pattern
+async fn assert_pattern_subscribers(pair: &mut Pair) {
+    let admin = pair.server.topic_admin();
+    let mut sensors = pair
+        .client
+        .subscriber_pattern::<u32>("sensor/*", 10)
+        .unwrap();
+    let mut kitchen = pair.client.subscriber::<Kitchen>(10).unwrap();
+    while admin.stats().await.unwrap().pattern_subscribers < 1
+        || admin.subscriber_count(Kitchen::topic()).await.unwrap() < 1
+    {
+        harness::sleep(Duration::from_millis(10)).await;
+    }
+
+    pair.server.publisher::<Kitchen>().send(1).await.unwrap();
+    pair.server
+        .publisher::<Text>()
+        .send("ignored".into())
+        .await
+        .unwrap();
+    pair.server.publisher::<Garage>().send(2).await.unwrap();
+    // the client subscribing to both the topic and the pattern receives the item once
+    assert_eq!(
+        sensors.next().await.unwrap().unwrap(),
+        (Kitchen::topic(), 1)
+    );
+    assert_eq!(sensors.next().await.unwrap().unwrap(), (Garage::topic(), 2));
+    assert_eq!(kitchen.next().await.unwrap().unwrap(), 1);
+
+    pair.client.unsubscribe_pattern("sensor/*").await.unwrap();
+    assert!(sensors.next().await.is_none());
+    while admin.stats().await.unwrap().pattern_subscribers > 0 {
+        harness::sleep(Duration::from_millis(10)).await;
+    }
+    pair.server.publisher::<Kitchen>().send(3).await.unwrap();
+    assert_eq!(kitchen.next().await.unwrap().unwrap(), 3);
+    pair.client.unsubscribe::<Kitchen>().await.unwrap();
+}
+
+/// Topics named at runtime are published and subscribed like the typed topics
+async fn assert_named_topics(pair: &mut Pair) {
+    let admin = pair.server.topic_admin();
+    let mut tenant = pair
+        .client
+        .subscriber_str::<u32>("tenant-a/count", 10)
+        .unwrap();
+    assert_eq!(tenant.topic(), "tenant-a/count");
+    while admin.subscriber_count("tenant-a/count").await.unwrap() < 1 {
+        harness::sleep(Duration::from_millis(10)).await;
+    }
+
+    let mut publisher = pair
+        .client
+        .publisher_str::<u32>("tenant-a/count")
+        .with_type_tag();
+    publisher.send(1).await.unwrap();
+    publisher.publish_with_ack(2).await.unwrap();
+    assert_eq!(tenant.next().await.unwrap().unwrap(), 1);
+    assert_eq!(tenant.next().await.unwrap().unwrap(), 2);
+
+    let mut text = pair
+        .client
+        .publisher_str::<String>("tenant-a/count")
+        .with_type_tag();
+    text.send("not a count".into()).await.unwrap();
+    match tenant.next().await {
+        Some(Err(Error::TopicTypeMismatch(_))) => {}
+        other => panic!("Expecting a type mismatch, got {:?}", other),
+    }
+
+    assert!(pair.client.subscriber_str::<u32>("tenant-*", 1).is_err());
+    assert!(pair
+        .client
+        .subscriber_str::<String>("tenant-a/count", 1)
+        .is_err());
+    pair.client.unsubscribe_str("tenant-a/count").await.unwrap();
+    assert!(tenant.next().await.is_none());
+}
+
+#[test]
+fn test_matrix() {
+    harness::block_on(run_matrix());
+}
@@ -0,0 +1,252 @@
+//! Runs the tests of the mirroring, the layers, the timeout storms and the pools of the
+//! client over every transport with the runtime and codec this test is compiled with. Use
+//! `cargo make test_matrix` to cover all combinations.
+
+#![cfg(all(
+    feature = "server",
+    feature = "client",
+    not(feature = "http_actix_web"),
+    any(
+        all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+        all(feature = "async_std_runtime", not(feature = "tokio_runtime"))
+    )
+))]
+
+use futures::StreamExt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use toy_rpc::{
+    client::{
+        layer::{Layer, Request, Response, ResponseAction},
+        pool::{ClientPool, PoolStrategy},
+        storm::StormAction,
+    },
+    Client, Error, Server,
+};
+
+mod harness;
+mod rpc;
+
+use harness::{Pair, TRANSPORTS};
+
+fn server() -> Server {
+    Server::builder()
+        .register(rpc::CommonTest::new())
+        .register_transactional(rpc::Ledger::default())
+        .build()
+}
+
+/// Selected calls are mirrored to the shadow server without changing their replies
+async fn run_mirroring() {
+    for transport in TRANSPORTS.iter().copied() {
+        let pair = Pair::start(server(), transport).await;
+        let shadow = Pair::start(server(), transport).await;
+        let client = &pair.client;
+        client
+            .mirror_to(shadow.dial().await, |service_method| {
+                service_method.starts_with("CommonTest.")
+            })
+            .unwrap();
+
+        harness::assert_reply(
+            client,
+            "CommonTest.get_magic_i16",
+            (),
+            rpc::COMMON_TEST_MAGIC_I16,
+        )
+        .await;
+        harness::assert_error(
+            client,
+            "CommonTest.echo_error",
+            "an error message".to_string(),
+            Error::ExecutionError("an error message".into()),
+        )
+        .await;
+        let balance: u32 = client.call("Ledger.balance", ()).await.unwrap();
+        assert_eq!(balance, 0);
+
+        let start = Instant::now();
+        while client.mirror_stats().succeeded + client.mirror_stats().failed < 2 {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            harness::sleep(Duration::from_millis(10)).await;
+        }
+        let stats = client.mirror_stats();
+        assert_eq!(stats.mirrored, 2);
+        assert_eq!(stats.succeeded, 1);
+        assert_eq!(stats.failed, 1);
+    }
+}
+
+const AUTH_MARKER: u32 = 8;
+
+/// Attaches a token to every call, renames the calls to `Alias` and rejects the calls
+/// to `Forbidden`
+struct Auth;
+
+impl Layer for Auth {
+    fn on_request(&self, request: &mut Request) -> Result<(), Error> {
+        if request.service_method.starts_with("Forbidden.") {
+            return Err(Error::Internal("Forbidden".into()));
+        }
+        if let Some(method) = request.service_method.strip_prefix("Alias.") {
+            request.service_method = format!("CommonTest.{}", method);
+        }
+        request.insert_extension(AUTH_MARKER, "secret");
+        Ok(())
+    }
+}
+
+/// Retries failed calls up to 3 attempts and records every response
+#[derive(Clone, Default)]
+struct Recorder(Arc<Mutex<Vec<(String, u32, bool)>>>);
+
+impl Layer for Recorder {
+    fn on_response(&self, response: &Response) -> ResponseAction {
+        self.0.lock().unwrap().push((
+            response.service_method.clone(),
+            response.attempt,
+            response.is_ok,
+        ));
+        match response.is_ok || response.attempt >= 3 {
+            true => ResponseAction::Return,
+            false => ResponseAction::Retry,
+        }
+    }
+}
+
+/// The layers see the requests before they are written and the responses after
+/// they are read
+async fn run_layers() {
+    for transport in TRANSPORTS.iter().copied() {
+        let tokens = Arc::new(Mutex::new(Vec::new()));
+        let received = tokens.clone();
+        let server = Server::builder()
+            .register(rpc::CommonTest::new())
+            .register_extension(AUTH_MARKER, move |token| {
+                received.lock().unwrap().push(token);
+                None
+            })
+            .build();
+        let pair = Pair::start(server, transport).await;
+        let recorder = Recorder::default();
+        let client = Client::builder()
+            .layer(Auth)
+            .layer(recorder.clone())
+            .build(pair.dial().await)
+            .unwrap();
+
+        let reply: i16 = client.call("Alias.get_magic_i16", ()).await.unwrap();
+        assert_eq!(reply, rpc::COMMON_TEST_MAGIC_I16);
+        harness::assert_error(
+            &client,
+            "CommonTest.echo_error",
+            "an error message".to_string(),
+            Error::ExecutionError("an error message".into()),
+        )
+        .await;
+        let reply: Result<(), Error> = client.call("Forbidden.method", ()).await;
+        assert!(matches!(reply, Err(Error::Internal(_))));
+
+        let responses = recorder.0.lock().unwrap().clone();
+        let echo = "CommonTest.echo_error".to_string();
+        assert_eq!(
+            responses,
+            vec![
+                ("CommonTest.get_magic_i16".to_string(), 1, true),
+                (echo.clone(), 1, false),
+                (echo.clone(), 2, false),
+                (echo, 3, false),
+            ]
+        );
+        // the token is sent again with each attempt
+        assert_eq!(*tokens.lock().unwrap(), vec!["secret"; 4]);
+    }
+}
+
+/// Calls in flight fail fast once a storm of timeouts is detected
+async fn run_timeout_storms() {
+    for transport in TRANSPORTS.iter().copied() {
+        for action in [StormAction::FailPending, StormAction::Disconnect]
+            .iter()
+            .copied()
+        {
+            let pair = Pair::start(server(), transport).await;
+            let client = &pair.client;
+            client
+                .on_timeout_storm(1, Duration::from_secs(10), action)
+                .unwrap();
+
+            let start = Instant::now();
+            let short = |millis| {
+                client
+                    .set_next_timeout(Duration::from_millis(millis))
+                    .call::<_, ()>("CommonTest.sleep_millis", 5000u64)
+            };
+            let long = client
+                .set_next_timeout(Duration::from_secs(10))
+                .call::<_, ()>("CommonTest.sleep_millis", 5000u64);
+            let (first, second, long) = futures::join!(short(100), short(150), long);
+            assert!(matches!(first, Err(Error::Timeout(_))));
+            assert!(matches!(second, Err(Error::Timeout(_))));
+            assert!(matches!(long, Err(Error::Timeout(_))));
+            assert!(start.elapsed() < Duration::from_secs(4));
+
+            let reply: Result<i16, Error> = client.call("CommonTest.get_magic_i16", ()).await;
+            match action {
+                StormAction::FailPending => assert_eq!(reply.unwrap(), rpc::COMMON_TEST_MAGIC_I16),
+                StormAction::Disconnect => assert!(matches!(reply, Err(Error::IoError(_)))),
+            }
+        }
+    }
+}
+
+/// Calls made through a pool are spread over its connections
+async fn run_client_pool() {
+    for transport in TRANSPORTS.iter().copied() {
+        let pair = Pair::start(server(), transport).await;
+        let pool = ClientPool::new(vec![pair.dial().await, pair.dial().await]).unwrap();
+        assert_eq!(pool.size(), 2);
+        assert!(!std::ptr::eq(pool.get(), pool.get()));
+        let numbers: Vec<u32> = pool
+            .call_stream::<_, u32>("CommonTest.count_up", 3u32)
+            .map(|number| number.unwrap())
+            .collect()
+            .await;
+        assert_eq!(numbers, vec![0, 1, 2]);
+
+        let pool = pool.strategy(PoolStrategy::LeastPending);
+        let slow = pool.call::<_, ()>("CommonTest.sleep_millis", 300u64);
+        let other = pool.call::<_, ()>("CommonTest.sleep_millis", 300u64);
+        assert_eq!(pool.pending(), vec![1, 1]);
+        let mut canceled = pool.call::<_, ()>("CommonTest.sleep_millis", 300u64);
+        canceled.cancel();
+        drop(canceled);
+        assert_eq!(pool.pending(), vec![1, 1]);
+        let reply: i16 = pool.call("CommonTest.get_magic_i16", ()).await.unwrap();
+        assert_eq!(reply, rpc::COMMON_TEST_MAGIC_I16);
+        slow.await.unwrap();
+        other.await.unwrap();
+        assert_eq!(pool.pending(), vec![0, 0]);
+        pool.close().await;
+    }
+}
+
+#[test]
+fn test_mirroring() {
+    harness::block_on(run_mirroring());
+}
+
+#[test]
+fn test_layers() {
+    harness::block_on(run_layers());
+}
+
+#[test]
+fn test_timeout_storms() {
+    harness::block_on(run_timeout_storms());
+}
+
+#[test]
+fn test_client_pool() {
+    harness::block_on(run_client_pool());
+}
@@ -0,0 +1,102 @@
+//! Runs the tests of the compression and the fixed headers with the runtime and codec this
+//! test is compiled with. Use `cargo make test_matrix` to cover all combinations.
+
+#![cfg(all(
+    feature = "server",
+    feature = "client",
+    not(feature = "http_actix_web"),
+    any(
+        all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+        all(feature = "async_std_runtime", not(feature = "tokio_runtime"))
+    )
+))]
+
+use toy_rpc::{Client, Server};
+
+mod harness;
+mod rpc;
+
+use harness::{Pair, Transport};
+
+/// Large payloads are compressed by both peers and the services are not aware of it
+#[cfg(any(
+    feature = "compression_gzip",
+    feature = "compression_zstd",
+    feature = "compression_lz4"
+))]
+async fn run_compression() {
+    use toy_rpc::compression::{Algorithm, Compression};
+
+    let algorithms = [
+        #[cfg(feature = "compression_gzip")]
+        Algorithm::Gzip,
+        #[cfg(feature = "compression_zstd")]
+        Algorithm::Zstd,
+        #[cfg(feature = "compression_lz4")]
+        Algorithm::Lz4,
+    ];
+    for algorithm in algorithms {
+        let server = Server::builder()
+            .register(rpc::CommonTest::new())
+            .compression(Compression::new(algorithm))
+            .build();
+        let pair = Pair::start(server, Transport::Tcp).await;
+        let client = Client::builder()
+            .compression(Compression::new(algorithm).min_size(0))
+            .dial(pair.addr)
+            .await
+            .unwrap();
+        rpc::test_large_payload(&client).await;
+        harness::assert_reply(
+            &client,
+            "CommonTest.get_magic_i16",
+            (),
+            rpc::COMMON_TEST_MAGIC_I16,
+        )
+        .await;
+
+        // a client without compression still reads the compressed replies
+        let client = Client::dial(pair.addr).await.unwrap();
+        rpc::test_large_payload(&client).await;
+    }
+}
+
+/// The headers with the fixed encoding are read by peers with or without the option
+async fn run_fixed_headers() {
+    for (server_fixed, client_fixed) in [(true, true), (true, false), (false, true)] {
+        let server = Server::builder()
+            .register(rpc::CommonTest::new())
+            .fixed_headers(server_fixed)
+            .build();
+        let pair = Pair::start(server, Transport::Tcp).await;
+        let client = Client::builder()
+            .fixed_headers(client_fixed)
+            .dial(pair.addr)
+            .await
+            .unwrap();
+        harness::assert_reply(
+            &client,
+            "CommonTest.get_magic_i16",
+            (),
+            rpc::COMMON_TEST_MAGIC_I16,
+        )
+        .await;
+        rpc::test_execution_error(&client).await;
+        rpc::test_large_payload(&client).await;
+    }
+}
+
+#[cfg(any(
+    feature = "compression_gzip",
+    feature = "compression_zstd",
+    feature = "compression_lz4"
+))]
+#[test]
+fn test_compression() {
+    harness::block_on(run_compression());
+}
+
+#[test]
+fn test_fixed_headers() {
+    harness::block_on(run_fixed_headers());
+}
@@ -0,0 +1,217 @@
+//! Runs the tests of the incoming connections, the connection stats and the dialing errors
+//! and timeouts over every transport with the runtime and codec this test is compiled with.
+//! Use `cargo make test_matrix` to cover all combinations.
+
+#![cfg(all(
+    feature = "server",
+    feature = "client",
+    not(feature = "http_actix_web"),
+    any(
+        all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+        all(feature = "async_std_runtime", not(feature = "tokio_runtime"))
+    )
+))]
+
+use futures::StreamExt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use toy_rpc::{server::incoming::AcceptOptions, util::spawn_task, Client, Error, Server};
+
+#[cfg(all(feature = "async_std_runtime", not(feature = "tokio_runtime")))]
+use ::async_std::{
+    io::ReadExt,
+    net::{TcpListener, TcpStream},
+};
+#[cfg(all(feature = "tokio_runtime", not(feature = "async_std_runtime")))]
+use ::tokio::{
+    io::AsyncReadExt,
+    net::{TcpListener, TcpStream},
+};
+
+mod harness;
+mod rpc;
+
+use harness::{Pair, Transport, TRANSPORTS};
+
+/// The connections yielded by `Server::incoming` are served once accepted
+async fn run_incoming() {
+    let server = Arc::new(Server::builder().register(rpc::CommonTest::new()).build());
+    // the connections to the first listener are rejected, and the connections to the
+    // second one are accepted with the WebSocket transport
+    let rejecting = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let rejecting_addr = rejecting.local_addr().unwrap();
+    let accepting = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let accepting_addr = accepting.local_addr().unwrap();
+    let handles = [
+        spawn_task({
+            let server = server.clone();
+            async move {
+                let mut incoming = server.incoming(rejecting);
+                while let Some(conn) = incoming.next().await {
+                    assert!(conn.peer_addr().ip().is_loopback());
+                    conn.reject();
+                }
+            }
+        }),
+        spawn_task({
+            let server = server.clone();
+            async move {
+                let mut incoming = server.incoming(accepting);
+                while let Some(conn) = incoming.next().await {
+                    let options = AcceptOptions::default().websocket().nodelay(true);
+                    conn.accept_with_options(options);
+                }
+            }
+        }),
+    ];
+
+    // the rejected connection is closed right away
+    let mut stream = TcpStream::connect(rejecting_addr).await.unwrap();
+    let read = stream.read(&mut [0u8; 16]).await;
+    assert!(matches!(read, Ok(0) | Err(_)));
+
+    let client = Client::dial_websocket(&format!("ws://{}", accepting_addr))
+        .await
+        .unwrap();
+    let reply: i16 = client.call("CommonTest.get_magic_i16", ()).await.unwrap();
+    assert_eq!(reply, rpc::COMMON_TEST_MAGIC_I16);
+    for handle in &handles {
+        handle.abort();
+    }
+}
+
+/// The messages, bodies and codec errors of each connection are counted
+async fn run_connection_stats() {
+    for transport in TRANSPORTS {
+        let server = Server::builder().register(rpc::CommonTest::new()).build();
+        let pair = Pair::start(server, transport).await;
+        let client = &pair.client;
+
+        let reply: i16 = client.call("CommonTest.get_magic_i16", ()).await.unwrap();
+        assert_eq!(reply, rpc::COMMON_TEST_MAGIC_I16);
+        let reply: Result<(), Error> = client.call("CommonTest.echo_error", 7u32).await;
+        assert!(matches!(reply, Err(Error::InvalidArgument)));
+
+        let connections = pair.server.connection_stats();
+        assert_eq!(connections.len(), 1);
+        let stats = connections.values().next().unwrap();
+        assert_eq!(stats.messages_read, 2);
+        assert_eq!(stats.messages_written, 2);
+        assert_eq!(stats.bodies_read, 2);
+        assert_eq!(stats.bodies_written, 1);
+        assert!(stats.average_body_written() > 0);
+        assert_eq!(stats.unmarshal_errors, 1);
+        assert_eq!(stats.marshal_errors, 0);
+
+        // a connection is removed once it is closed
+        let other = pair.dial().await;
+        let _: i16 = other.call("CommonTest.get_magic_i16", ()).await.unwrap();
+        assert_eq!(pair.server.connection_stats().len(), 2);
+        other.close().await;
+        let start = Instant::now();
+        while pair.server.connection_stats().len() > 1 {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            harness::sleep(Duration::from_millis(10)).await;
+        }
+    }
+}
+
+/// Failures to connect are reported by their class
+async fn run_transport_errors() {
+    use std::io::{Read, Write};
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    drop(listener);
+    assert!(matches!(
+        Client::dial(addr).await,
+        Err(Error::ConnectionRefused)
+    ));
+    assert!(matches!(
+        Client::dial_websocket(&format!("ws://{}", addr)).await,
+        Err(Error::ConnectionRefused)
+    ));
+
+    // an HTTP server that does not upgrade the connection
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let http = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut buf = [0; 1024];
+        let _ = stream.read(&mut buf).unwrap();
+        stream
+            .write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n")
+            .unwrap();
+    });
+    assert!(matches!(
+        Client::dial_websocket(&format!("ws://{}", addr)).await,
+        Err(Error::WsUpgradeFailed(_))
+    ));
+    http.join().unwrap();
+}
+
+/// The steps of a connection attempt fail once their timeout elapses
+async fn run_dial_timeouts() {
+    use std::io::Read;
+
+    // a server that accepts the connection but never answers the upgrade
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let silent = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut buf = [0; 1024];
+        while let Ok(n) = stream.read(&mut buf) {
+            if n == 0 {
+                break;
+            }
+        }
+    });
+    let start = Instant::now();
+    let result = Client::builder()
+        .handshake_timeout(Duration::from_millis(200))
+        .dial_websocket(&format!("ws://{}", addr))
+        .await;
+    assert!(matches!(result, Err(Error::HandshakeTimeout)));
+    assert!(start.elapsed() < Duration::from_secs(5));
+    silent.join().unwrap();
+
+    for transport in TRANSPORTS {
+        let server = Server::builder().register(rpc::CommonTest::new()).build();
+        let pair = Pair::start(server, transport).await;
+        let builder = Client::builder()
+            .connect_timeout(Duration::from_secs(5))
+            .handshake_timeout(Duration::from_secs(5));
+        let client = match transport {
+            Transport::Tcp => builder.dial(pair.addr).await,
+            Transport::WebSocket => builder.dial_websocket(&format!("ws://{}", pair.addr)).await,
+        }
+        .unwrap();
+        harness::assert_reply(
+            &client,
+            "CommonTest.get_magic_i16",
+            (),
+            rpc::COMMON_TEST_MAGIC_I16,
+        )
+        .await;
+    }
+}
+
+#[test]
+fn test_incoming() {
+    harness::block_on(run_incoming());
+}
+
+#[test]
+fn test_connection_stats() {
+    harness::block_on(run_connection_stats());
+}
+
+#[test]
+fn test_transport_errors() {
+    harness::block_on(run_transport_errors());
+}
+
+#[test]
+fn test_dial_timeouts() {
+    harness::block_on(run_dial_timeouts());
+}
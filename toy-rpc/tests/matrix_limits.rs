@@ -0,0 +1,218 @@
+//! Runs the tests of the size, deserialization, connection and bandwidth limits over every
+//! transport with the runtime and codec this test is compiled with. Use `cargo make
+//! test_matrix` to cover all combinations.
+
+#![cfg(all(
+    feature = "server",
+    feature = "client",
+    not(feature = "http_actix_web"),
+    any(
+        all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+        all(feature = "async_std_runtime", not(feature = "tokio_runtime"))
+    )
+))]
+
+use std::time::{Duration, Instant};
+use toy_rpc::{
+    payload::SizeLimit, server::guard::DeserializeLimits, transport::bandwidth::BandwidthLimit,
+    Client, Error, Server,
+};
+
+#[cfg(all(feature = "async_std_runtime", not(feature = "tokio_runtime")))]
+use ::async_std::{io::ReadExt, net::TcpStream};
+#[cfg(all(feature = "tokio_runtime", not(feature = "async_std_runtime")))]
+use ::tokio::{io::AsyncReadExt, net::TcpStream};
+
+mod harness;
+mod rpc;
+
+use harness::{Pair, Transport, TRANSPORTS};
+
+/// Payloads are counted per method and the calls that exceed a size limit fail
+async fn run_size_limits() {
+    for transport in TRANSPORTS.iter().copied() {
+        let server = Server::builder()
+            .register(rpc::CommonTest::new())
+            .size_limit("CommonTest", SizeLimit::default().max_request(64))
+            .size_limit(
+                "CommonTest.get_magic_str",
+                SizeLimit::default().max_response(1),
+            )
+            .build();
+        let pair = Pair::start(server, transport).await;
+        let client = &pair.client;
+
+        // limits of the server
+        harness::assert_error(
+            client,
+            "CommonTest.echo_error",
+            "an error message".to_string(),
+            Error::ExecutionError("an error message".into()),
+        )
+        .await;
+        let reply: Result<(), Error> = client.call("CommonTest.echo_error", "a".repeat(1024)).await;
+        assert!(matches!(reply, Err(Error::PayloadTooLarge(_))));
+        let reply: Result<String, Error> = client.call("CommonTest.get_magic_str", ()).await;
+        assert!(matches!(reply, Err(Error::PayloadTooLarge(_))));
+
+        // limits of the client
+        client
+            .set_size_limit(
+                "CommonTest.sleep_millis",
+                SizeLimit::default().max_request(0),
+            )
+            .unwrap();
+        client
+            .set_size_limit(
+                "CommonTest.get_magic_i16",
+                SizeLimit::default().max_response(0),
+            )
+            .unwrap();
+        let reply: Result<(), Error> = client.call("CommonTest.sleep_millis", 1u64).await;
+        assert!(matches!(reply, Err(Error::PayloadTooLarge(_))));
+        let reply: Result<i16, Error> = client.call("CommonTest.get_magic_i16", ()).await;
+        assert!(matches!(reply, Err(Error::PayloadTooLarge(_))));
+        let reply: u8 = client.call("CommonTest.get_magic_u8", ()).await.unwrap();
+        assert_eq!(reply, rpc::COMMON_TEST_MAGIC_U8);
+
+        let stats = pair.server.payload_stats();
+        let echo = stats["CommonTest.echo_error"];
+        assert_eq!(echo.calls, 2);
+        assert!(echo.largest_request >= 1024);
+        assert!(!stats.contains_key("CommonTest.sleep_millis"));
+        assert!(stats["CommonTest.get_magic_str"].response_bytes > 1);
+
+        let stats = client.payload_stats();
+        assert_eq!(stats["CommonTest.echo_error"].calls, 2);
+        assert_eq!(stats["CommonTest.sleep_millis"].calls, 1);
+        assert!(stats["CommonTest.get_magic_i16"].response_bytes > 0);
+        assert!(stats["CommonTest.get_magic_u8"].response_bytes > 0);
+    }
+}
+
+/// Arguments over the deserialization limits are rejected
+async fn run_deserialize_limits() {
+    let server = Server::builder()
+        .register(rpc::CommonTest::new())
+        .deserialize_limits(DeserializeLimits::default().max_str_len(16))
+        .build();
+    let pair = Pair::start(server, TRANSPORTS[0]).await;
+    let client = &pair.client;
+
+    let reply: Result<(), Error> = client.call("CommonTest.echo_error", "a".repeat(17)).await;
+    assert!(matches!(reply, Err(Error::InvalidArgument)));
+
+    let reply: Result<(), Error> = client.call("CommonTest.echo_error", "a".repeat(16)).await;
+    assert!(matches!(reply, Err(Error::ExecutionError(_))));
+}
+
+/// The traffic of each connection is counted in frames and bytes, and the bandwidth
+/// limits slow down the large transfers in either direction
+async fn run_bandwidth_limits() {
+    // 256 KiB each way, with bursts of 64 KiB at 512 KiB/s
+    let limit = BandwidthLimit::new(512 * 1024, 64 * 1024);
+    for transport in TRANSPORTS {
+        for (outbound, inbound) in [(None, None), (Some(limit), None), (None, Some(limit))] {
+            let mut builder = Server::builder().register(rpc::CommonTest::new());
+            if let Some(limit) = outbound {
+                builder = builder.outbound_bandwidth(limit);
+            }
+            if let Some(limit) = inbound {
+                builder = builder.inbound_bandwidth(limit);
+            }
+            let pair = Pair::start(builder.build(), transport).await;
+
+            let start = Instant::now();
+            rpc::test_large_payload(&pair.client).await;
+            let elapsed = start.elapsed();
+
+            let connections = pair.server.connection_stats();
+            let traffic = connections.values().next().unwrap().traffic;
+            assert_eq!(traffic.frames_read, 2);
+            assert_eq!(traffic.frames_written, 2);
+            assert!(traffic.bytes_read > 256 * 1024);
+            assert!(traffic.bytes_written > 256 * 1024);
+            if outbound.is_none() && inbound.is_none() {
+                assert_eq!(traffic.throttled, Duration::ZERO);
+            } else {
+                // the transfer over the burst waits for about 375 ms
+                assert!(traffic.throttled > Duration::from_millis(300));
+                assert!(elapsed > Duration::from_millis(300));
+            }
+        }
+    }
+}
+
+/// The connections over the limits are closed right away, and count again once closed
+async fn run_connection_limits() {
+    for transport in TRANSPORTS {
+        let server = Server::builder()
+            .register(rpc::CommonTest::new())
+            .max_connections(3)
+            .max_connections_per_ip(2)
+            .build();
+        let pair = Pair::start(server, transport).await;
+        let other = pair.dial().await;
+        let _: i16 = other.call("CommonTest.get_magic_i16", ()).await.unwrap();
+
+        // the pair already has two connections from the loopback address
+        let mut stream = TcpStream::connect(pair.addr).await.unwrap();
+        let read = stream.read(&mut [0u8; 16]).await;
+        assert!(matches!(read, Ok(0) | Err(_)));
+
+        other.close().await;
+        let start = Instant::now();
+        while pair.server.connection_stats().len() > 1 {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            harness::sleep(Duration::from_millis(10)).await;
+        }
+        // the permit is released after the stats entry, so a new connection may
+        // still be refused for a moment
+        let other = dial_until_accepted(&pair).await;
+        let reply: i16 = other.call("CommonTest.get_magic_i16", ()).await.unwrap();
+        assert_eq!(reply, rpc::COMMON_TEST_MAGIC_I16);
+    }
+}
+
+/// Dials the server of the pair until a connection is accepted and answers a call
+async fn dial_until_accepted(pair: &Pair) -> Client {
+    let start = Instant::now();
+    loop {
+        let client = match pair.transport {
+            Transport::Tcp => Client::dial(pair.addr).await,
+            Transport::WebSocket => Client::dial_websocket(&format!("ws://{}", pair.addr)).await,
+        };
+        if let Ok(client) = client {
+            let reply: Result<i16, Error> = client
+                .timeout(Duration::from_secs(1))
+                .call("CommonTest.get_magic_i16", ())
+                .await;
+            if reply.is_ok() {
+                return client;
+            }
+            client.close().await;
+        }
+        assert!(start.elapsed() < Duration::from_secs(5));
+        harness::sleep(Duration::from_millis(10)).await;
+    }
+}
+
+#[test]
+fn test_size_limits() {
+    harness::block_on(run_size_limits());
+}
+
+#[test]
+fn test_deserialize_limits() {
+    harness::block_on(run_deserialize_limits());
+}
+
+#[test]
+fn test_connection_limits() {
+    harness::block_on(run_connection_limits());
+}
+
+#[test]
+fn test_bandwidth_limits() {
+    harness::block_on(run_bandwidth_limits());
+}
@@ -0,0 +1,204 @@
+//! Runs the tests of the strict topics and of the delivery attempts over every transport
+//! with the runtime and codec this test is compiled with. Use `cargo make test_matrix` to
+//! cover all combinations.
+
+#![cfg(all(
+    feature = "server",
+    feature = "client",
+    not(feature = "http_actix_web"),
+    any(
+        all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+        all(feature = "async_std_runtime", not(feature = "tokio_runtime"))
+    )
+))]
+
+use futures::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use toy_rpc::{
+    pubsub::{DeadLetter, DeliveryFailure, Topic},
+    Error, Server,
+};
+
+mod harness;
+mod rpc;
+
+use harness::{Pair, TRANSPORTS};
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+struct Count(u32);
+
+impl Topic for Count {
+    type Item = Count;
+
+    fn topic() -> String {
+        "Count".into()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+struct Tick(u32);
+
+impl Topic for Tick {
+    type Item = Tick;
+
+    fn topic() -> String {
+        "Tick".into()
+    }
+}
+
+/// A topic whose items are published with acknowledgements
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+struct Receipt(u32);
+
+impl Topic for Receipt {
+    type Item = Receipt;
+
+    fn topic() -> String {
+        "Receipt".into()
+    }
+}
+
+/// Dead letter topic of `Receipt`
+struct DeadReceipt;
+
+impl Topic for DeadReceipt {
+    type Item = DeadLetter;
+
+    fn topic() -> String {
+        "DeadReceipt".into()
+    }
+}
+
+struct Text;
+
+impl Topic for Text {
+    type Item = String;
+
+    fn topic() -> String {
+        "Text".into()
+    }
+}
+
+/// Publishes numbers on the topic of `Text`
+struct Numbers;
+
+impl Topic for Numbers {
+    type Item = u32;
+
+    fn topic() -> String {
+        Text::topic()
+    }
+}
+
+/// Only the declared topics are accepted by a server in strict topic mode
+async fn run_strict_topics() {
+    for transport in TRANSPORTS.iter().copied() {
+        let server = Server::builder()
+            .declare_topic::<Count>()
+            .declare_topic::<Text>()
+            .build();
+        let mut pair = Pair::start(server, transport).await;
+        harness::assert_pubsub::<Count>(&mut pair, vec![Count(1)]).await;
+
+        let mut unknown = pair.client.subscriber::<Tick>(1).unwrap();
+        match unknown.next().await {
+            Some(Err(Error::TopicRejected(_))) => {}
+            other => panic!("Expecting an unknown topic to be rejected, got {:?}", other),
+        }
+        assert!(unknown.next().await.is_none());
+
+        // a pattern must match a declared topic
+        let mut unknown = pair.client.subscriber_pattern::<u32>("Tick/*", 1).unwrap();
+        match unknown.next().await {
+            Some(Err(Error::TopicRejected(_))) => {}
+            other => panic!(
+                "Expecting an unknown pattern to be rejected, got {:?}",
+                other
+            ),
+        }
+        assert!(unknown.next().await.is_none());
+        let mut counts = pair.client.subscriber_pattern::<Count>("Co*", 1).unwrap();
+        while pair
+            .server
+            .topic_admin()
+            .stats()
+            .await
+            .unwrap()
+            .pattern_subscribers
+            < 1
+        {
+            harness::sleep(Duration::from_millis(10)).await;
+        }
+        pair.server
+            .publisher::<Count>()
+            .send(Count(2))
+            .await
+            .unwrap();
+        assert_eq!(
+            counts.next().await.unwrap().unwrap(),
+            (Count::topic(), Count(2))
+        );
+
+        // a publisher waiting for the acknowledgement gets the rejection
+        let mut publisher = pair.client.publisher::<Tick>();
+        match publisher.publish_with_ack(Tick(1)).await {
+            Err(Error::TopicRejected(_)) => {}
+            other => panic!("Expecting the publication to be rejected, got {:?}", other),
+        }
+
+        // the item type is checked on the server side
+        let mut publisher = pair.server.publisher::<Numbers>();
+        match publisher.send(1).await {
+            Err(Error::TopicRejected(_)) => {}
+            other => panic!(
+                "Expecting a mismatched item type to be rejected, got {:?}",
+                other
+            ),
+        }
+        assert!(pair.server.subscriber::<Numbers>(1).is_err());
+        assert!(pair.server.subscriber::<Tick>(1).is_err());
+    }
+}
+
+/// A publication that is still not acknowledged after the last delivery attempt is
+/// published to the dead letter topic
+async fn run_delivery_attempts() {
+    for transport in TRANSPORTS.iter().copied() {
+        let server = Server::builder()
+            .redelivery_timeout(Duration::from_millis(50))
+            .max_delivery_attempts(2)
+            .build();
+        let pair = Pair::start(server, transport).await;
+        let admin = pair.server.topic_admin();
+        // the subscriber is never read, so it never acknowledges the publication
+        let _stuck = pair.server.subscriber::<Receipt>(10).unwrap();
+        let mut dead = pair.server.subscriber::<DeadReceipt>(10).unwrap();
+        assert!(admin
+            .set_dead_letter(Receipt::topic(), Some(DeadReceipt::topic()))
+            .await
+            .unwrap());
+
+        let mut publisher = pair.client.publisher::<Receipt>();
+        publisher.publish_with_ack(Receipt(1)).await.unwrap();
+        let letter = dead.next().await.unwrap().unwrap();
+        assert_eq!(letter.topic, Receipt::topic());
+        assert_eq!(letter.reason, DeliveryFailure::Unacknowledged);
+
+        let stats = admin.stats().await.unwrap();
+        assert_eq!(stats.unacked, 0);
+        assert_eq!(stats.redelivered, 1);
+        let info = admin.info(Receipt::topic()).await.unwrap().unwrap();
+        assert_eq!(info.dead_lettered, 1);
+    }
+}
+
+#[test]
+fn test_strict_topics() {
+    harness::block_on(run_strict_topics());
+}
+
+#[test]
+fn test_delivery_attempts() {
+    harness::block_on(run_delivery_attempts());
+}
@@ -0,0 +1,178 @@
+//! Runs the tests of the name normalizer, the duplex calls and the server data over every
+//! transport with the runtime and codec this test is compiled with. Use `cargo make
+//! test_matrix` to cover all combinations.
+
+#![cfg(all(
+    feature = "server",
+    feature = "client",
+    not(feature = "http_actix_web"),
+    any(
+        all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+        all(feature = "async_std_runtime", not(feature = "tokio_runtime"))
+    )
+))]
+
+use std::time::Duration;
+use toy_rpc::{
+    macros::export_impl, metadata::Context, payload::SizeLimit, server::naming::NameNormalizer,
+    Client, Error, Server,
+};
+
+mod harness;
+mod rpc;
+
+use harness::{Pair, TRANSPORTS};
+
+/// Names sent in another case, in camelCase or with a version reach the methods
+async fn run_name_normalizer() {
+    let server = Server::builder()
+        .register(rpc::CommonTest::new())
+        .normalize_names(
+            NameNormalizer::default()
+                .case_insensitive(true)
+                .snake_case(true)
+                .strip_version(true),
+        )
+        .size_limit(
+            "CommonTest.get_magic_i16",
+            SizeLimit::default().max_request(16),
+        )
+        .build();
+    let pair = Pair::start(server, TRANSPORTS[0]).await;
+    let client = &pair.client;
+
+    for name in &[
+        "CommonTest.get_magic_i16",
+        "commonTest.getMagicI16",
+        "COMMON_TEST.GET_MAGIC_I16",
+        "CommonTest.getMagicI16V2",
+    ] {
+        let reply: i16 = client.call(*name, ()).await.unwrap();
+        assert_eq!(reply, rpc::COMMON_TEST_MAGIC_I16);
+    }
+
+    // the size limit of the registered name applies to the normalized names
+    let reply: Result<i16, Error> = client.call("CommonTest.getMagicI16", vec![0u8; 64]).await;
+    assert!(matches!(reply, Err(Error::PayloadTooLarge(_))));
+
+    let reply: Result<i16, Error> = client.call("CommonTest.getMagicI17", ()).await;
+    assert!(matches!(reply, Err(Error::MethodNotFound)));
+    let reply: Result<i16, Error> = client.call("Common.getMagicI16", ()).await;
+    assert!(matches!(reply, Err(Error::ServiceNotFound)));
+}
+
+/// Calls the services of the clients through the `Peer` of their connection
+struct Hub;
+
+#[export_impl]
+impl Hub {
+    #[export_method]
+    async fn add_on_agent(&self, context: Context, args: (String, i32, i32)) -> Result<i32, Error> {
+        let peer = context
+            .peer()
+            .ok_or_else(|| Error::ExecutionError("No peer".into()))?;
+        let (service_method, a, b) = args;
+        peer.call(service_method, (a, b)).await
+    }
+
+    #[export_method]
+    async fn sleep_on_agent(&self, context: Context, millis: u64) -> Result<bool, Error> {
+        let peer = context
+            .peer()
+            .ok_or_else(|| Error::ExecutionError("No peer".into()))?;
+        let timeout = Duration::from_millis(100);
+        let result: Result<(), Error> = peer
+            .call_with_timeout("CommonTest.sleep_millis", millis, timeout)
+            .await;
+        Ok(matches!(result, Err(Error::Timeout(_))))
+    }
+}
+
+/// The server calls the services of a client over the connection the client dialed
+async fn run_duplex() {
+    for transport in TRANSPORTS.iter().copied() {
+        let server = Server::builder().register(Hub).build();
+        let pair = Pair::start(server, transport).await;
+        let agent = Client::builder()
+            .serve(rpc::arith_object())
+            .serve(rpc::CommonTest::new())
+            .build(pair.dial().await)
+            .unwrap();
+
+        let args = ("Arith.add".to_string(), 2, 3);
+        let reply: i32 = agent.call("Hub.add_on_agent", args).await.unwrap();
+        assert_eq!(reply, 5);
+        // the errors of the agent are passed to the server
+        let args = ("Undefined.add".to_string(), 2, 3);
+        let reply: Result<i32, _> = agent.call("Hub.add_on_agent", args).await;
+        assert!(matches!(reply, Err(Error::ServiceNotFound)));
+        let timed_out: bool = agent.call("Hub.sleep_on_agent", 1000u64).await.unwrap();
+        assert!(timed_out);
+        // the calls of the agent and of the server are multiplexed on the connection
+        let args = ("Arith.add".to_string(), 4, 5);
+        let reply: i32 = agent.call("Hub.add_on_agent", args).await.unwrap();
+        assert_eq!(reply, 9);
+
+        // a client without services does not serve any call
+        let args = ("Arith.add".to_string(), 2, 3);
+        let reply: Result<i32, _> = pair.client.call("Hub.add_on_agent", args).await;
+        assert!(matches!(reply, Err(Error::ServiceNotFound)));
+    }
+}
+
+/// Shared resource of the handlers of `Greeter`
+struct Greeting(&'static str);
+
+/// Greets with the `Greeting` set on the server
+struct Greeter;
+
+#[export_impl]
+impl Greeter {
+    #[export_method]
+    async fn greet(&self, context: Context, name: String) -> Result<String, Error> {
+        let greeting = context
+            .data::<Greeting>()
+            .ok_or_else(|| Error::ExecutionError("No greeting".into()))?;
+        Ok(format!("{}, {}", greeting.0, name))
+    }
+}
+
+/// The handlers get the resources set on the server from the context of their calls
+async fn run_data() {
+    for transport in TRANSPORTS {
+        let server = Server::builder()
+            .register(Greeter)
+            .data(Greeting("Hi"))
+            .data(Greeting("Hello"))
+            .build();
+        let pair = Pair::start(server, transport).await;
+        let args = "world".to_string();
+        harness::assert_reply(
+            &pair.client,
+            "Greeter.greet",
+            args,
+            "Hello, world".to_string(),
+        )
+        .await;
+
+        let server = Server::builder().register(Greeter).build();
+        let pair = Pair::start(server, transport).await;
+        let error = Error::ExecutionError("No greeting".into());
+        harness::assert_error(&pair.client, "Greeter.greet", "world".to_string(), error).await;
+    }
+}
+
+#[test]
+fn test_name_normalizer() {
+    harness::block_on(run_name_normalizer());
+}
+
+#[test]
+fn test_duplex() {
+    harness::block_on(run_duplex());
+}
+
+#[test]
+fn test_data() {
+    harness::block_on(run_data());
+}
@@ -0,0 +1,151 @@
+//! Runs the tests of the blocking methods, the call timing and the profiler with the
+//! runtime and codec this test is compiled with. Use `cargo make test_matrix` to cover all
+//! combinations.
+
+#![cfg(all(
+    feature = "server",
+    feature = "client",
+    not(feature = "http_actix_web"),
+    any(
+        all(feature = "tokio_runtime", not(feature = "async_std_runtime")),
+        all(feature = "async_std_runtime", not(feature = "tokio_runtime"))
+    )
+))]
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use toy_rpc::{
+    message::MessageId,
+    metadata::Metadata,
+    timing::{TimeoutPhase, Timing},
+    Error, Server,
+};
+
+mod harness;
+mod rpc;
+
+use harness::{Pair, TRANSPORTS};
+
+/// Blocking methods run on the blocking pool without stalling the other calls
+async fn run_blocking_methods() {
+    let server = Server::builder()
+        .register(rpc::CommonTest::new())
+        .blocking_pool(1)
+        .build();
+    let pair = Pair::start(server, TRANSPORTS[0]).await;
+    let client = &pair.client;
+
+    let start = Instant::now();
+    let block = || client.call::<_, ()>("CommonTest.block_millis", 300u64);
+    let magic = async {
+        let reply: i16 = client.call("CommonTest.get_magic_i16", ()).await.unwrap();
+        assert_eq!(reply, rpc::COMMON_TEST_MAGIC_I16);
+        start.elapsed()
+    };
+    let (first, second, elapsed) = futures::join!(block(), block(), magic);
+    first.unwrap();
+    second.unwrap();
+    assert!(elapsed < Duration::from_millis(300));
+    // the pool of one thread executes the blocking methods one after the other
+    assert!(start.elapsed() >= Duration::from_millis(600));
+}
+
+/// The time queued and the time executing are limited and reported apart
+async fn run_call_timing() {
+    let server = Server::builder()
+        .register(rpc::CommonTest::new())
+        .blocking_pool(1)
+        .queue_timeout(Duration::from_millis(100))
+        .execution_timeout(Duration::from_millis(200))
+        .report_timing(true)
+        .build();
+    let pair = Pair::start(server, TRANSPORTS[0]).await;
+    let client = &pair.client;
+
+    let (_, metadata): ((), Metadata) = client
+        .call_with_metadata("CommonTest.sleep_millis", 50u64, Metadata::new())
+        .await
+        .unwrap();
+    let timing = Timing::from_metadata(&metadata).unwrap();
+    assert!(timing.queued < Duration::from_millis(50));
+    assert!(timing.executed >= Duration::from_millis(50));
+
+    let reply: Result<(), Error> = client.call("CommonTest.sleep_millis", 1000u64).await;
+    match reply {
+        Err(Error::ServerTimeout(timeout)) => {
+            assert_eq!(timeout.phase, TimeoutPhase::Execution);
+            assert!(timeout.timing.executed >= Duration::from_millis(200));
+        }
+        reply => panic!("Expecting an execution timeout, got {:?}", reply),
+    }
+
+    // the pool of one thread is held by one call while the other one waits for it
+    let block = || client.call::<_, ()>("CommonTest.block_millis", 150u64);
+    let (first, second) = futures::join!(block(), block());
+    let waited = match (first, second) {
+        (Ok(()), waited) | (waited, Ok(())) => waited,
+        replies => panic!("Expecting a call to complete, got {:?}", replies),
+    };
+    match waited {
+        Err(Error::ServerTimeout(timeout)) => {
+            assert_eq!(timeout.phase, TimeoutPhase::Queue);
+            assert!(timeout.timing.queued >= Duration::from_millis(100));
+            assert_eq!(timeout.timing.executed, Duration::ZERO);
+        }
+        reply => panic!("Expecting a queue timeout, got {:?}", reply),
+    }
+}
+
+/// The profiler is called around each run of the handlers
+async fn run_profiler() {
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let (start, stop) = (events.clone(), events.clone());
+    let server = Server::builder()
+        .register(rpc::CommonTest::new())
+        .profiler((
+            move |service_method: &str, id: MessageId| {
+                start
+                    .lock()
+                    .unwrap()
+                    .push((true, service_method.to_string(), id))
+            },
+            move |service_method: &str, id: MessageId| {
+                stop.lock()
+                    .unwrap()
+                    .push((false, service_method.to_string(), id))
+            },
+        ))
+        .build();
+    let pair = Pair::start(server, TRANSPORTS[0]).await;
+    let client = &pair.client;
+
+    let call = client.call("CommonTest.get_magic_i16", ());
+    let id = call.get_id();
+    let reply: i16 = call.await.unwrap();
+    assert_eq!(reply, rpc::COMMON_TEST_MAGIC_I16);
+
+    // the handler is created and polled at least once, and stops after each run
+    let events = events.lock().unwrap();
+    assert!(events.len() >= 4);
+    assert_eq!(events.len() % 2, 0);
+    for (i, (started, service_method, event_id)) in events.iter().enumerate() {
+        assert_eq!(*started, i % 2 == 0);
+        assert_eq!(service_method, "CommonTest.get_magic_i16");
+        assert_eq!(*event_id, id);
+    }
+}
+
+#[test]
+fn test_blocking_methods() {
+    harness::block_on(run_blocking_methods());
+}
+
+#[test]
+fn test_call_timing() {
+    harness::block_on(run_call_timing());
+}
+
+#[test]
+fn test_profiler() {
+    harness::block_on(run_profiler());
+}
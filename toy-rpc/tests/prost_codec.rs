@@ -0,0 +1,150 @@
+use async_trait::async_trait;
+use std::sync::Arc;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task;
+use toy_rpc::codec::custom::CustomCodec;
+use toy_rpc::codec::prost::Prost;
+use toy_rpc::macros::{export_impl, export_trait, export_trait_impl};
+use toy_rpc::{Client, Error, Server};
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Reading {
+    #[prost(string, tag = "1")]
+    pub sensor: String,
+    #[prost(sint64, repeated, tag = "2")]
+    pub values: Vec<i64>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Summary {
+    #[prost(string, tag = "1")]
+    pub sensor: String,
+    #[prost(sint64, tag = "2")]
+    pub total: i64,
+}
+
+pub struct Sensors;
+
+#[export_impl]
+impl Sensors {
+    #[export_method(prost)]
+    async fn summarize(&self, args: Reading) -> Result<Summary, String> {
+        if args.values.is_empty() {
+            return Err(format!("No values from {}", args.sensor));
+        }
+        Ok(Summary {
+            sensor: args.sensor,
+            total: args.values.iter().sum(),
+        })
+    }
+
+    #[export_method]
+    async fn count(&self, args: Vec<i64>) -> Result<usize, String> {
+        Ok(args.len())
+    }
+}
+
+#[async_trait]
+#[export_trait(impl_for_client)]
+pub trait Calibrate {
+    #[export_method(prost)]
+    async fn adjust(&self, args: Reading) -> Result<Reading, Error>;
+}
+
+pub struct Offset(i64);
+
+#[async_trait]
+#[export_trait_impl]
+impl Calibrate for Offset {
+    async fn adjust(&self, args: Reading) -> Result<Reading, Error> {
+        Ok(Reading {
+            sensor: args.sensor,
+            values: args.values.iter().map(|value| value + self.0).collect(),
+        })
+    }
+}
+
+fn reading(values: Vec<i64>) -> Reading {
+    Reading {
+        sensor: "thermometer".into(),
+        values,
+    }
+}
+
+async fn assert_calls(client: &Client) {
+    let reply = client
+        .sensors()
+        .summarize(reading(vec![-3, 0, 21]))
+        .await
+        .unwrap();
+    assert_eq!(
+        reply,
+        Summary {
+            sensor: "thermometer".into(),
+            total: 18
+        }
+    );
+    let reply = client.sensors().summarize(reading(vec![])).await;
+    assert!(
+        matches!(reply, Err(Error::ExecutionError(msg)) if msg == "No values from thermometer")
+    );
+    let reply = client.sensors().count(vec![1, 2, 3]).await.unwrap();
+    assert_eq!(reply, 3);
+
+    let reply = client
+        .calibrate()
+        .adjust(reading(vec![-3, 0]))
+        .await
+        .unwrap();
+    assert_eq!(reply, reading(vec![-1, 2]));
+    let reply = Calibrate::adjust(client, reading(vec![5])).await.unwrap();
+    assert_eq!(reply, reading(vec![7]));
+}
+
+async fn test_prost_codec() {
+    let server = Server::builder()
+        .register(Arc::new(Sensors))
+        .register(Arc::new(Offset(2)))
+        .build();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server_handle = task::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        server
+            .serve_codec(CustomCodec::<_, _, Prost>::new(stream))
+            .await
+    });
+
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let client = Client::with_custom_codec::<_, Prost>(stream);
+    assert_calls(&client).await;
+
+    client.close().await;
+    server_handle.await.unwrap().unwrap();
+}
+
+async fn test_prost_messages_with_default_codec() {
+    let server = Server::builder()
+        .register(Arc::new(Sensors))
+        .register(Arc::new(Offset(2)))
+        .build();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    task::spawn(async move { server.accept(listener).await });
+
+    let client = Client::dial(addr).await.unwrap();
+    assert_calls(&client).await;
+    client.close().await;
+}
+
+#[test]
+fn prost_codec() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(test_prost_codec());
+}
+
+#[test]
+fn prost_messages_with_default_codec() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(test_prost_messages_with_default_codec());
+}
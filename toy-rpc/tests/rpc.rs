@@ -11,8 +11,16 @@ cfg_if::cfg_if! {
 
         use serde::{Deserialize, Serialize};
 
-        use toy_rpc::macros::export_impl;
+        use async_trait::async_trait;
+        use toy_rpc::macros::{export_impl, export_trait, export_trait_impl};
         use toy_rpc::Error;
+        use toy_rpc::metadata::Metadata;
+        #[cfg(feature = "server")]
+        use toy_rpc::metadata::Context;
+        use toy_rpc::pagination::{paginate, Page, PageRequest};
+        use futures::{StreamExt, TryStreamExt};
+        use toy_rpc::streaming::{RequestStream, RpcStream};
+        use toy_rpc::transaction::{InTransaction, TransactionId, Transactional};
 
         pub const COMMON_TEST_MAGIC_U8: u8 = 167;
         pub const COMMON_TEST_MAGIC_U16: u16 = 512;
@@ -26,9 +34,15 @@ cfg_if::cfg_if! {
         pub const COMMON_TEST_MAGIC_STR: &str = "a magic";
 
         pub const COMMON_TEST_SERVICE_NAME: &str = "CommonTest";
+        pub const OWNED_TEST_SERVICE_NAME: &str = "OwnedCommonTest";
+        pub const ARITH_OBJECT_SERVICE_NAME: &str = "ArithObject";
 
         pub const ADDR: &str = "127.0.0.1:8080";
 
+        pub const EXT_MARKER: u32 = 7;
+
+        pub const PAGINATED_NUMBERS: u32 = 10;
+
         #[derive(Debug, Clone, Default, Serialize, Deserialize, PartialOrd, Ord, PartialEq, Eq)]
         pub struct CustomStruct {
             field_u8: u8,
@@ -138,6 +152,118 @@ cfg_if::cfg_if! {
             async fn echo_error(&self, args: String) -> Result<(), String> {
                 Err(args)
             }
+
+            #[cfg(feature = "server")]
+            #[export_method]
+            async fn echo_metadata(&self, context: Context, key: String) -> Result<Option<String>, String> {
+                context.set_response_metadata("service_method", context.service_method());
+                Ok(context.get(&key).map(String::from))
+            }
+
+            #[export_method]
+            async fn sleep_millis(&self, args: u64) -> Result<(), String> {
+                #[cfg(all(feature = "tokio_runtime", not(feature = "async_std_runtime"), not(feature = "http_actix_web")))]
+                ::tokio::time::sleep(std::time::Duration::from_millis(args)).await;
+                // the handlers run on the runtime of actix
+                #[cfg(feature = "http_actix_web")]
+                ::actix_rt::time::delay_for(std::time::Duration::from_millis(args)).await;
+                #[cfg(all(feature = "async_std_runtime", not(feature = "tokio_runtime")))]
+                ::async_std::task::sleep(std::time::Duration::from_millis(args)).await;
+                Ok(())
+            }
+
+            #[export_method(blocking)]
+            async fn block_millis(&self, args: u64) -> Result<(), String> {
+                std::thread::sleep(std::time::Duration::from_millis(args));
+                Ok(())
+            }
+
+            #[export_method]
+            async fn paginated_numbers(&self, request: PageRequest) -> Result<Page<u32>, Error> {
+                paginate(0..PAGINATED_NUMBERS, &request)
+            }
+
+            #[export_method]
+            async fn count_up(&self, count: u32) -> Result<RpcStream<u32>, Error> {
+                match count {
+                    0 => Err(Error::InvalidArgument),
+                    _ => Ok(RpcStream::iter(0..count)),
+                }
+            }
+
+            #[export_method]
+            async fn sum(&self, numbers: RequestStream<u32>) -> Result<u32, Error> {
+                numbers.try_fold(0, |sum, number| async move { Ok(sum + number) }).await
+            }
+
+            #[export_method]
+            async fn running_sum(&self, numbers: RequestStream<u32>) -> Result<RpcStream<u32>, Error> {
+                let sums = numbers.scan(0, |sum, number| {
+                    let item = number.map(|number| {
+                        *sum += number;
+                        *sum
+                    });
+                    futures::future::ready(Some(item))
+                });
+                Ok(RpcStream::new(sums))
+            }
+        }
+
+        #[async_trait]
+        #[export_trait]
+        pub trait Arith {
+            #[export_method]
+            async fn add(&self, args: (i32, i32)) -> Result<i32, Error>;
+        }
+
+        pub struct Abacus;
+
+        #[async_trait]
+        #[export_trait_impl]
+        impl Arith for Abacus {
+            async fn add(&self, args: (i32, i32)) -> Result<i32, Error> {
+                Ok(args.0 + args.1)
+            }
+        }
+
+        /// Applies the deposits of a transaction when it is committed
+        #[derive(Default)]
+        pub struct Ledger {
+            balance: std::sync::Mutex<u32>,
+            deposits: std::sync::Mutex<std::collections::HashMap<TransactionId, u32>>,
+        }
+
+        #[export_impl]
+        impl Ledger {
+            #[export_method]
+            async fn deposit(&self, args: InTransaction<u32>) -> Result<(), String> {
+                *self.deposits.lock().unwrap().entry(args.transaction).or_default() += args.args;
+                Ok(())
+            }
+
+            #[export_method]
+            async fn balance(&self, _: ()) -> Result<u32, String> {
+                Ok(*self.balance.lock().unwrap())
+            }
+        }
+
+        #[async_trait]
+        impl Transactional for Ledger {
+            async fn commit(&self, transaction: TransactionId) -> Result<(), Error> {
+                let amount = self.deposits.lock().unwrap().remove(&transaction).unwrap_or(0);
+                *self.balance.lock().unwrap() += amount;
+                Ok(())
+            }
+
+            async fn rollback(&self, transaction: TransactionId) -> Result<(), Error> {
+                self.deposits.lock().unwrap().remove(&transaction);
+                Ok(())
+            }
+        }
+
+        /// Returns an implementation of `Arith` chosen at runtime
+        pub fn arith_object() -> std::sync::Arc<dyn Arith + Send + Sync> {
+            std::sync::Arc::new(Abacus)
         }
 
         use toy_rpc::client::{Client};
@@ -255,6 +381,46 @@ cfg_if::cfg_if! {
             println!("test_service_not_found() Passed")
         }
 
+        pub async fn test_owned_service(client: &Client) {
+            let service_method = format!("{}.get_magic_u8", OWNED_TEST_SERVICE_NAME);
+            let reply: u8 = client
+                .call(service_method, ())
+                .await
+                .expect("Unexpected error executing RPC");
+            assert_eq!(COMMON_TEST_MAGIC_U8, reply);
+            println!("test_owned_service() Passed")
+        }
+
+        pub async fn test_trait_object_service(client: &Client) {
+            let service_method = format!("{}.add", ARITH_OBJECT_SERVICE_NAME);
+            let reply: i32 = client
+                .call(service_method, (3, 4))
+                .await
+                .expect("Unexpected error executing RPC");
+            assert_eq!(7, reply);
+            println!("test_trait_object_service() Passed")
+        }
+
+        pub async fn test_typed_calls(client: &Client) {
+            use futures::TryStreamExt;
+            use toy_rpc::rpc_call;
+
+            let reply = rpc_call!(client, CommonTest::get_magic_i16, ())
+                .await
+                .expect("Unexpected error executing RPC");
+            assert_eq!(COMMON_TEST_MAGIC_I16, reply);
+            let numbers: Vec<u32> = rpc_call!(client, CommonTest::count_up, 3)
+                .try_collect()
+                .await
+                .expect("Unexpected error streaming");
+            assert_eq!(numbers, vec![0, 1, 2]);
+            let reply = rpc_call!(client, dyn Arith::add, (3, 4))
+                .await
+                .expect("Unexpected error executing RPC");
+            assert_eq!(7, reply);
+            println!("test_typed_calls() Passed")
+        }
+
         pub async fn test_method_not_found(client: &Client) {
             let service_method = format!("{}.undefined_method", COMMON_TEST_SERVICE_NAME);
             let reply: Result<(), toy_rpc::Error> = client.call(service_method, ()).await;
@@ -294,6 +460,229 @@ cfg_if::cfg_if! {
             println!("test_execution_error() Passed")
         }
 
+        /// Sends and receives a payload larger than the WebSocket message size limits
+        pub async fn test_large_payload(client: &Client) {
+            let val = "0123456789abcdef".repeat(16 * 1024);
+            let reply = client.common_test().echo_error(val.clone()).await;
+            match reply {
+                Ok(_) => panic!("Expecting an error"),
+                Err(err) => {
+                    assert_eq!(err.to_string(), toy_rpc::Error::ExecutionError(val).to_string())
+                }
+            };
+            println!("test_large_payload() Passed")
+        }
+
+        pub async fn test_metrics(client: &Client) {
+            use toy_rpc::client::metrics::{CallMetrics, CallOutcome};
+
+            let (tx, rx) = flume::unbounded::<CallMetrics>();
+            client.set_metrics_sink(tx).unwrap();
+
+            let reply: u8 = client.common_test().get_magic_u8(()).await.unwrap();
+            assert_eq!(COMMON_TEST_MAGIC_U8, reply);
+            let metrics = rx.recv_async().await.unwrap();
+            assert_eq!(metrics.service_method, format!("{}.get_magic_u8", COMMON_TEST_SERVICE_NAME));
+            assert_eq!(metrics.outcome, CallOutcome::Ok);
+            assert!(metrics.response_bytes > 0);
+
+            let _ = client.common_test().echo_error("an error message".to_string()).await;
+            let metrics = rx.recv_async().await.unwrap();
+            assert_eq!(metrics.outcome, CallOutcome::Error);
+            assert!(metrics.request_bytes > 0);
+            println!("test_metrics() Passed")
+        }
+
+        /// Server side handler of the test protocol extension
+        pub fn ext_ping(content: String) -> Option<String> {
+            Some(format!("pong: {}", content))
+        }
+
+        pub async fn test_extension(client: &Client) {
+            let (tx, rx) = flume::bounded(1);
+            client
+                .register_extension(EXT_MARKER, move |content| {
+                    tx.send(content).unwrap();
+                    None
+                })
+                .unwrap();
+            client.send_extension(EXT_MARKER, "ping").unwrap();
+            let reply = rx.recv_async().await.unwrap();
+            assert_eq!(reply, "pong: ping");
+            println!("test_extension() Passed")
+        }
+
+        pub async fn test_metadata(client: &Client) {
+            let mut metadata = Metadata::new();
+            metadata.insert("token".into(), "a=b\nc".into());
+            let (reply, metadata): (Option<String>, Metadata) = client
+                .call_with_metadata("CommonTest.echo_metadata", "token".to_string(), metadata)
+                .await
+                .unwrap();
+            assert_eq!(reply.as_deref(), Some("a=b\nc"));
+            assert_eq!(metadata["service_method"], "CommonTest.echo_metadata");
+
+            // the calls without metadata get an empty context
+            let reply: Option<String> = client
+                .call("CommonTest.echo_metadata", "token".to_string())
+                .await
+                .unwrap();
+            assert_eq!(reply, None);
+            let (_, metadata): (Option<String>, Metadata) = client
+                .call_with_metadata("CommonTest.get_magic_str", (), Metadata::new())
+                .await
+                .map(|(reply, metadata): (String, Metadata)| (Some(reply), metadata))
+                .unwrap();
+            assert!(metadata.is_empty());
+            println!("test_metadata() Passed")
+        }
+
+        pub async fn test_prepared_calls(client: &Client) {
+            let prepared = client
+                .prepare("CommonTest.echo_metadata")
+                .meta("user", "alice")
+                .bind_meta(Metadata::from([("token".to_string(), "a=b".to_string())]))
+                .timeout(std::time::Duration::from_secs(5));
+            assert_eq!(prepared.service_method(), "CommonTest.echo_metadata");
+            assert_eq!(prepared.metadata().len(), 2);
+            for key in ["token", "user", "missing"] {
+                let reply: Option<String> = prepared.call(key.to_string()).await.unwrap();
+                let expected = prepared.metadata().get(key).cloned();
+                assert_eq!(reply, expected);
+            }
+            let (reply, metadata): (Option<String>, Metadata) = prepared
+                .call_with_metadata("user".to_string())
+                .await
+                .unwrap();
+            assert_eq!(reply.as_deref(), Some("alice"));
+            assert_eq!(metadata["service_method"], "CommonTest.echo_metadata");
+
+            let sleep = client
+                .prepare("CommonTest.sleep_millis")
+                .timeout(std::time::Duration::from_millis(50));
+            let reply: Result<(), Error> = sleep.call(1000u64).await;
+            assert!(matches!(reply, Err(Error::Timeout(_))));
+            // the call that must succeed gets a timeout of its own, which a loaded
+            // machine does not run into
+            let sleep = client
+                .prepare("CommonTest.sleep_millis")
+                .timeout(std::time::Duration::from_secs(5));
+            let reply: Result<(), Error> = sleep.call(0u64).await;
+            assert!(reply.is_ok());
+            println!("test_prepared_calls() Passed")
+        }
+
+        pub async fn test_pagination(client: &Client) {
+            use futures::TryStreamExt;
+
+            let numbers: Vec<u32> = client
+                .common_test()
+                .paginated_numbers_stream(3)
+                .try_collect()
+                .await
+                .expect("Unexpected error paginating");
+            assert_eq!(numbers, (0..PAGINATED_NUMBERS).collect::<Vec<u32>>());
+
+            let result: Result<Vec<u32>, Error> = client
+                .paginate::<u32>("CommonTest.paginated_numbers", 0)
+                .try_collect()
+                .await;
+            assert!(result.is_err());
+            println!("test_pagination() Passed")
+        }
+
+        pub async fn test_streaming(client: &Client) {
+            use futures::{StreamExt, TryStreamExt};
+
+            let numbers: Vec<u32> = client
+                .common_test()
+                .count_up(5u32)
+                .try_collect()
+                .await
+                .expect("Unexpected error streaming");
+            assert_eq!(numbers, (0..5).collect::<Vec<u32>>());
+
+            // the error of a method that fails before returning the stream is the only item
+            let mut failed = client.call_stream::<_, u32>("CommonTest.count_up", 0u32);
+            assert!(matches!(
+                failed.next().await.unwrap(),
+                Err(Error::InvalidArgument)
+            ));
+            assert!(failed.next().await.is_none());
+
+            // dropping the stream before its end cancels the call
+            let mut numbers = client.common_test().count_up(u32::MAX);
+            assert_eq!(numbers.next().await.unwrap().unwrap(), 0);
+            assert_eq!(numbers.next().await.unwrap().unwrap(), 1);
+            drop(numbers);
+            let reply: i16 = client.call("CommonTest.get_magic_i16", ()).await.unwrap();
+            assert_eq!(reply, COMMON_TEST_MAGIC_I16);
+            println!("test_streaming() Passed")
+        }
+
+        pub async fn test_client_streaming(client: &Client) {
+            use futures::{SinkExt, StreamExt};
+            use toy_rpc::streaming::RequestStream;
+
+            let (numbers, sum) = client.call_with_sink::<u32, u32>("CommonTest.sum");
+            for number in 1..=10 {
+                numbers.send(number).unwrap();
+            }
+            numbers.finish().unwrap();
+            assert_eq!(sum.await.unwrap(), 55);
+
+            let sum = client
+                .common_test()
+                .sum(RequestStream::iter(1..=3u32))
+                .await
+                .unwrap();
+            assert_eq!(sum, 6);
+
+            // the argument also ends when the sink is closed
+            let (mut numbers, sum) = client.call_with_sink::<u32, u32>("CommonTest.sum");
+            numbers.send_all(&mut futures::stream::iter(vec![Ok(4), Ok(5)])).await.unwrap();
+            numbers.close().await.unwrap();
+            assert_eq!(sum.await.unwrap(), 9);
+
+            // the responses are streamed while the argument is still being sent
+            let (numbers, mut sums) =
+                client.call_stream_with_sink::<u32, u32>("CommonTest.running_sum");
+            numbers.send(1).unwrap();
+            assert_eq!(sums.next().await.unwrap().unwrap(), 1);
+            numbers.send(2).unwrap();
+            assert_eq!(sums.next().await.unwrap().unwrap(), 3);
+            drop(numbers);
+            assert!(sums.next().await.is_none());
+
+            // a streaming argument can only be sent with a ClientSink
+            let result: Result<u32, Error> = client.call("CommonTest.sum", 0u64).await;
+            assert!(result.is_err());
+            println!("test_client_streaming() Passed")
+        }
+
+        pub async fn test_transactions(client: &Client) {
+            let balance = || client.call::<_, u32>("Ledger.balance", ());
+
+            let transaction = client.transaction();
+            transaction.call::<_, ()>("Ledger.deposit", 10u32).await.unwrap();
+            transaction.call::<_, ()>("Ledger.deposit", 20u32).await.unwrap();
+            assert_eq!(transaction.participants(), vec!["Ledger".to_string()]);
+            assert_eq!(balance().await.unwrap(), 0);
+            transaction.commit().await.unwrap();
+            assert_eq!(balance().await.unwrap(), 30);
+
+            let transaction = client.transaction();
+            transaction.call::<_, ()>("Ledger.deposit", 5u32).await.unwrap();
+            transaction.rollback().await.unwrap();
+            assert_eq!(balance().await.unwrap(), 30);
+
+            // a service without the hook cannot be rolled back
+            let transaction = client.transaction();
+            let _ = transaction.call::<_, u8>("CommonTest.get_magic_u8", ()).await;
+            assert!(transaction.rollback().await.is_err());
+            println!("test_transactions() Passed")
+        }
+
         pub fn simply_panic() {
             panic!("just panics");
         }
@@ -0,0 +1,173 @@
+use futures::TryStreamExt;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task;
+use toy_rpc::macros::export_impl;
+use toy_rpc::signing::{ResponseSigner, ResponseVerifier};
+use toy_rpc::streaming::RpcStream;
+use toy_rpc::{Client, Error, Server};
+
+const SEED: [u8; 32] = [42; 32];
+
+pub struct Vault;
+
+#[export_impl]
+impl Vault {
+    #[export_method]
+    async fn reveal(&self, args: String) -> Result<String, String> {
+        Ok(format!("secret {}", args))
+    }
+
+    #[export_method]
+    async fn deny(&self, _args: ()) -> Result<(), String> {
+        Err("denied".to_string())
+    }
+
+    #[export_method]
+    async fn count_up(&self, count: u32) -> Result<RpcStream<u32>, Error> {
+        Ok(RpcStream::iter(0..count))
+    }
+}
+
+async fn serve(server: Server) -> std::net::SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    task::spawn(async move { server.accept(listener).await });
+    addr
+}
+
+/// A relay that rewrites the responses of the server, replacing `from` with `to`
+async fn tampering_relay(
+    server: std::net::SocketAddr,
+    from: &'static [u8],
+    to: &'static [u8],
+) -> std::net::SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    task::spawn(async move {
+        let (client, _) = listener.accept().await.unwrap();
+        let upstream = TcpStream::connect(server).await.unwrap();
+        let (mut client_read, mut client_write) = client.into_split();
+        let (mut upstream_read, mut upstream_write) = upstream.into_split();
+        task::spawn(async move { tokio::io::copy(&mut client_read, &mut upstream_write).await });
+        let mut buf = vec![0u8; 64 * 1024];
+        loop {
+            let n = match upstream_read.read(&mut buf).await {
+                Ok(0) | Err(_) => break,
+                Ok(n) => n,
+            };
+            let mut chunk = buf[..n].to_vec();
+            if let Some(pos) = chunk.windows(from.len()).position(|w| w == from) {
+                chunk[pos..pos + to.len()].copy_from_slice(to);
+            }
+            if client_write.write_all(&chunk).await.is_err() {
+                break;
+            }
+        }
+    });
+    addr
+}
+
+async fn test_signed_responses() {
+    let signer = ResponseSigner::ed25519_from_seed("vault-1", &SEED).unwrap();
+    let public_key = signer.public_key().unwrap().to_vec();
+    let server = Server::builder()
+        .register(Arc::new(Vault))
+        .sign_responses(signer)
+        .build();
+    let addr = serve(server).await;
+
+    // the responses, the errors and the items of the streams are verified
+    let client = Client::builder()
+        .verify_responses(ResponseVerifier::new().ed25519("vault-1", &public_key))
+        .dial(addr)
+        .await
+        .unwrap();
+    let reply = client.vault().reveal("plan".to_string()).await.unwrap();
+    assert_eq!(reply, "secret plan");
+    let err = client.vault().deny(()).await.unwrap_err();
+    assert!(matches!(err, Error::ExecutionError(msg) if msg == "denied"));
+    let numbers: Vec<u32> = client.vault().count_up(3).try_collect().await.unwrap();
+    assert_eq!(numbers, vec![0, 1, 2]);
+    client.close().await;
+
+    // a client without a verifier ignores the signatures
+    let client = Client::dial(addr).await.unwrap();
+    let reply = client.vault().reveal("plan".to_string()).await.unwrap();
+    assert_eq!(reply, "secret plan");
+    client.close().await;
+
+    // the key of another server is rejected
+    let other = ResponseSigner::ed25519_from_seed("vault-1", &[1; 32]).unwrap();
+    let client = Client::builder()
+        .verify_responses(ResponseVerifier::new().ed25519("vault-1", other.public_key().unwrap()))
+        .dial(addr)
+        .await
+        .unwrap();
+    let err = client.vault().reveal("plan".to_string()).await.unwrap_err();
+    assert!(matches!(err, Error::InvalidSignature(_)));
+    let err = client
+        .vault()
+        .count_up(3)
+        .try_collect::<Vec<u32>>()
+        .await
+        .unwrap_err();
+    assert!(matches!(err, Error::InvalidSignature(_)));
+    // so is an error
+    let err = client.vault().deny(()).await.unwrap_err();
+    assert!(matches!(err, Error::InvalidSignature(_)));
+    client.close().await;
+
+    // a response tampered with by a relay is rejected
+    let relay = tampering_relay(addr, b"secret", b"public").await;
+    let client = Client::builder()
+        .verify_responses(ResponseVerifier::new().ed25519("vault-1", &public_key))
+        .dial(relay)
+        .await
+        .unwrap();
+    let err = client.vault().reveal("plan".to_string()).await.unwrap_err();
+    assert!(matches!(err, Error::InvalidSignature(_)));
+    client.close().await;
+}
+
+async fn test_unsigned_responses() {
+    let server = Server::builder().register(Arc::new(Vault)).build();
+    let addr = serve(server).await;
+
+    let client = Client::builder()
+        .verify_responses(ResponseVerifier::new().hmac_sha256("vault-1", b"shared secret"))
+        .dial(addr)
+        .await
+        .unwrap();
+    let err = client.vault().reveal("plan".to_string()).await.unwrap_err();
+    assert!(matches!(err, Error::InvalidSignature(_)));
+    client.close().await;
+
+    // the same secret on both ends
+    let server = Server::builder()
+        .register(Arc::new(Vault))
+        .sign_responses(ResponseSigner::hmac_sha256("vault-1", b"shared secret"))
+        .build();
+    let addr = serve(server).await;
+    let client = Client::builder()
+        .verify_responses(ResponseVerifier::new().hmac_sha256("vault-1", b"shared secret"))
+        .dial(addr)
+        .await
+        .unwrap();
+    let reply = client.vault().reveal("plan".to_string()).await.unwrap();
+    assert_eq!(reply, "secret plan");
+    client.close().await;
+}
+
+#[test]
+fn signed_responses() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(test_signed_responses());
+}
+
+#[test]
+fn unsigned_responses() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(test_unsigned_responses());
+}
@@ -2,8 +2,10 @@ use anyhow::Result;
 use async_std::sync::Arc;
 use async_std::task;
 use futures::channel::oneshot::{channel, Receiver};
+use std::time::{Duration, Instant};
+use tide::listener::Listener;
 
-use toy_rpc::{Client, Server};
+use toy_rpc::{server::shutdown::SHUTDOWN_REASON, Client, Error, Server};
 
 mod rpc;
 
@@ -30,6 +32,8 @@ async fn test_client(base: &str, mut ready: Receiver<()>) -> Result<()> {
     rpc::test_service_not_found(&client).await;
     rpc::test_method_not_found(&client).await;
     rpc::test_execution_error(&client).await;
+    rpc::test_large_payload(&client).await;
+    rpc::test_extension(&client).await;
 
     println!("Client received all correct RPC result");
     Ok(())
@@ -40,7 +44,10 @@ async fn run(base: &'static str) {
     let common_test_service = Arc::new(rpc::CommonTest::new());
 
     // start testing server
-    let server = Server::builder().register(common_test_service).build();
+    let server = Server::builder()
+        .register(common_test_service)
+        .register_extension(rpc::EXT_MARKER, rpc::ext_ping)
+        .build();
 
     let mut app = tide::new();
     app.at("/rpc/").nest(server.into_endpoint());
@@ -53,7 +60,61 @@ async fn run(base: &'static str) {
     server_handle.cancel().await;
 }
 
+async fn run_shutdown() {
+    let server = Server::builder()
+        .register(Arc::new(rpc::CommonTest::new()))
+        .build();
+    let shutdown = server.shutdown_handle();
+
+    let mut app = tide::new();
+    app.at("/rpc/").nest(server.into_endpoint());
+    let mut listener = app.bind("127.0.0.1:0").await.expect("Error binding");
+    let base = listener.info()[0]
+        .connection()
+        .trim_start_matches("http://")
+        .to_string();
+    let server_handle = task::spawn(async move { listener.accept().await });
+
+    let addr = format!("ws://{}/rpc/", base);
+    let client = Client::dial_http(&addr)
+        .await
+        .expect("Error dialing http server");
+    rpc::test_get_magic_u8(&client).await;
+    assert_eq!(shutdown.active_sessions(), 1);
+
+    // a call in flight when the server shuts down
+    let slow_call = client.call::<_, ()>("CommonTest.sleep_millis", 300u64);
+    task::sleep(Duration::from_millis(50)).await;
+
+    let start = Instant::now();
+    let shutdown_handle = {
+        let shutdown = shutdown.clone();
+        task::spawn(async move { shutdown.shutdown(Duration::from_secs(5)).await })
+    };
+    task::sleep(Duration::from_millis(50)).await;
+    assert!(shutdown.is_shutting_down());
+
+    // new calls are rejected while the calls in flight are drained
+    let reply: Result<u8, Error> = client.call("CommonTest.get_magic_u8", ()).await;
+    match reply {
+        Err(Error::ExecutionError(msg)) => assert_eq!(msg, SHUTDOWN_REASON),
+        other => panic!("Expecting the call to be rejected, got {:?}", other),
+    }
+
+    slow_call.await.expect("Call in flight should be drained");
+    shutdown_handle.await;
+    assert!(start.elapsed() < Duration::from_secs(5));
+    assert_eq!(shutdown.active_sessions(), 0);
+
+    server_handle.cancel().await;
+}
+
 #[test]
 fn http_tide_integration() {
     task::block_on(run(rpc::ADDR));
 }
+
+#[test]
+fn http_tide_shutdown() {
+    task::block_on(run_shutdown());
+}
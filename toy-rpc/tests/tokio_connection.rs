@@ -0,0 +1,434 @@
+use async_trait::async_trait;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task;
+use toy_rpc::client::disconnect::{DisconnectInfo, DisconnectReason};
+use toy_rpc::client::unexpected::UnexpectedResponse;
+use toy_rpc::codec::split::SplittableCodec;
+use toy_rpc::codec::{CodecRead, DefaultCodec, EraseDeserializer, Unmarshal};
+use toy_rpc::connection::Connection;
+use toy_rpc::erased_serde;
+use toy_rpc::message::Metadata;
+use toy_rpc::protocol::Header;
+use toy_rpc::server::audit::AuditRecord;
+use toy_rpc::server::filter::{deny_list, Cidr};
+use toy_rpc::server::lifecycle::ConnectionInfo;
+use toy_rpc::{Client, Error, Server};
+
+mod rpc;
+
+const EXT_MARKER: u32 = 3;
+
+async fn test_raw_extension() {
+    let server = Server::builder()
+        .register_extension(EXT_MARKER, |content| Some(content.to_uppercase()))
+        .build();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server_handle = task::spawn(async move {
+        server.accept(listener).await.unwrap();
+    });
+
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let mut conn = Connection::new(DefaultCodec::new(stream));
+    let header = Header::Ext {
+        id: 11,
+        content: "presence".into(),
+        marker: EXT_MARKER,
+    };
+    conn.send(header, &()).await.unwrap();
+
+    let (header, _) = conn.recv_bytes().await.unwrap().unwrap();
+    match header {
+        Header::Ext {
+            id,
+            content,
+            marker,
+        } => {
+            assert_eq!(id, 11);
+            assert_eq!(content, "PRESENCE");
+            assert_eq!(marker, EXT_MARKER);
+        }
+        h => panic!("Unexpected header {:?}", h),
+    }
+
+    conn.close().await;
+    server_handle.abort();
+    println!("test_raw_extension() Passed");
+}
+
+async fn ext_ping<C: SplittableCodec>(conn: &mut Connection<C>) -> Option<String> {
+    let header = Header::Ext {
+        id: 1,
+        content: "ping".into(),
+        marker: EXT_MARKER,
+    };
+    conn.send(header, &()).await.ok()?;
+    match conn.recv_bytes().await?.ok()?.0 {
+        Header::Ext { content, .. } => Some(content),
+        _ => None,
+    }
+}
+
+async fn test_proxy_protocol() {
+    let server = Server::builder()
+        .register_extension(EXT_MARKER, |content| Some(content.to_uppercase()))
+        .proxy_protocol(true)
+        .build();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server_handle = task::spawn(async move {
+        server.accept(listener).await.unwrap();
+    });
+
+    // version 1
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream
+        .write_all(b"PROXY TCP4 203.0.113.7 192.0.2.1 56324 23333\r\n")
+        .await
+        .unwrap();
+    let mut conn = Connection::new(DefaultCodec::new(stream));
+    assert_eq!(ext_ping(&mut conn).await.as_deref(), Some("PING"));
+    conn.close().await;
+
+    // version 2
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let mut header = b"\r\n\r\n\0\r\nQUIT\n\x21\x11\x00\x0c".to_vec();
+    header.extend_from_slice(&[203, 0, 113, 7, 192, 0, 2, 1, 0xdc, 0x04, 0x5b, 0x25]);
+    stream.write_all(&header).await.unwrap();
+    let mut conn = Connection::new(DefaultCodec::new(stream));
+    assert_eq!(ext_ping(&mut conn).await.as_deref(), Some("PING"));
+    conn.close().await;
+
+    // connections without the header are dropped
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let mut conn = Connection::new(DefaultCodec::new(stream));
+    assert_eq!(ext_ping(&mut conn).await, None);
+
+    server_handle.abort();
+    println!("test_proxy_protocol() Passed");
+}
+
+async fn test_connection_filter() {
+    // reject the real client behind the proxy
+    let server = Server::builder()
+        .register_extension(EXT_MARKER, |content| Some(content.to_uppercase()))
+        .proxy_protocol(true)
+        .connection_filter(deny_list(vec!["203.0.113.0/24".parse::<Cidr>().unwrap()]))
+        .build();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server_handle = task::spawn(async move {
+        server.accept(listener).await.unwrap();
+    });
+
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream
+        .write_all(b"PROXY TCP4 198.51.100.3 192.0.2.1 56324 23333\r\n")
+        .await
+        .unwrap();
+    let mut conn = Connection::new(DefaultCodec::new(stream));
+    assert_eq!(ext_ping(&mut conn).await.as_deref(), Some("PING"));
+    conn.close().await;
+
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream
+        .write_all(b"PROXY TCP4 203.0.113.7 192.0.2.1 56324 23333\r\n")
+        .await
+        .unwrap();
+    let mut conn = Connection::new(DefaultCodec::new(stream));
+    assert_eq!(ext_ping(&mut conn).await, None);
+
+    server_handle.abort();
+    println!("test_connection_filter() Passed");
+}
+
+/// A codec defined outside of the crate that counts the frames read by the server
+struct CountingCodec<C> {
+    inner: C,
+    frames: Arc<AtomicUsize>,
+}
+
+struct CountingReader<R> {
+    inner: R,
+    frames: Arc<AtomicUsize>,
+}
+
+impl<C> SplittableCodec for CountingCodec<C>
+where
+    C: SplittableCodec,
+{
+    type Writer = C::Writer;
+    type Reader = CountingReader<C::Reader>;
+
+    fn split(self) -> (Self::Writer, Self::Reader) {
+        let (writer, reader) = self.inner.split();
+        let reader = CountingReader {
+            inner: reader,
+            frames: self.frames,
+        };
+        (writer, reader)
+    }
+}
+
+impl<R: Unmarshal> Unmarshal for CountingReader<R> {
+    fn unmarshal<'de, D: serde::Deserialize<'de>>(buf: &'de [u8]) -> Result<D, Error> {
+        R::unmarshal(buf)
+    }
+}
+
+impl<R: EraseDeserializer> EraseDeserializer for CountingReader<R> {
+    fn from_bytes(buf: Vec<u8>) -> Box<dyn erased_serde::Deserializer<'static> + Send> {
+        R::from_bytes(buf)
+    }
+}
+
+#[async_trait]
+impl<R: CodecRead> CodecRead for CountingReader<R> {
+    async fn read_bytes(&mut self) -> Option<Result<Vec<u8>, Error>> {
+        let bytes = self.inner.read_bytes().await;
+        self.frames.fetch_add(1, Ordering::Relaxed);
+        bytes
+    }
+}
+
+async fn test_serve_codec() {
+    let server = Server::builder().register(rpc::CommonTest::new()).build();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let frames = Arc::new(AtomicUsize::new(0));
+
+    // accept the connection outside of the server
+    let server_frames = frames.clone();
+    let server_handle = task::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let codec = CountingCodec {
+            inner: DefaultCodec::new(stream),
+            frames: server_frames,
+        };
+        server.serve_codec(codec).await.unwrap();
+    });
+
+    let client = Client::dial(addr).await.unwrap();
+    rpc::test_get_magic_u8(&client).await;
+    // header and body of the request
+    assert!(frames.load(Ordering::Relaxed) >= 2);
+
+    client.close().await;
+    server_handle.await.unwrap();
+    println!("test_serve_codec() Passed");
+}
+
+async fn test_audit_log() {
+    let (tx, rx) = flume::unbounded::<AuditRecord>();
+    let server = Server::builder()
+        .register(rpc::CommonTest::new())
+        .audit_sink(tx)
+        .build();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server_handle = task::spawn(async move {
+        server.accept(listener).await.unwrap();
+    });
+
+    let client = Client::dial(addr).await.unwrap();
+    rpc::test_get_magic_u8(&client).await;
+    rpc::test_service_not_found(&client).await;
+
+    let record = rx.recv_async().await.unwrap();
+    assert_eq!(record.service, rpc::COMMON_TEST_SERVICE_NAME);
+    assert_eq!(record.method, "get_magic_u8");
+    assert!(record.result.is_ok());
+
+    let record = rx.recv_async().await.unwrap();
+    assert_eq!(record.service, "UndefinedService");
+    assert_eq!(record.method, "method");
+    assert!(record.result.is_err());
+
+    client.close().await;
+    server_handle.abort();
+    println!("test_audit_log() Passed");
+}
+
+async fn test_unexpected_responses() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    // a misbehaving server
+    let server_handle = task::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut conn = Connection::new(DefaultCodec::new(stream));
+
+        // responds after the client has timed out
+        let (header, _) = conn.recv_bytes().await.unwrap().unwrap();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let response = Header::Response {
+            id: header.get_id(),
+            is_ok: true,
+        };
+        conn.send(response, &()).await.unwrap();
+
+        // responds twice, skipping the cancellation of the timed out call
+        let header = loop {
+            match conn.recv_bytes().await.unwrap().unwrap() {
+                (Header::Cancel(_), _) => continue,
+                (header, _) => break header,
+            }
+        };
+        let response = Header::Response {
+            id: header.get_id(),
+            is_ok: true,
+        };
+        conn.send(response.clone(), &()).await.unwrap();
+        conn.send(response, &()).await.unwrap();
+
+        // responds to a call that was never made
+        let response = Header::Response {
+            id: 999,
+            is_ok: true,
+        };
+        conn.send(response, &()).await.unwrap();
+
+        while let Some(Ok(_)) = conn.recv_bytes().await {}
+    });
+
+    let client = Client::dial(addr).await.unwrap();
+    let (tx, rx) = flume::unbounded();
+    client
+        .on_unexpected_response(move |id, kind| tx.send((id, kind)).unwrap())
+        .unwrap();
+
+    let call = client
+        .set_next_timeout(Duration::from_millis(50))
+        .call::<_, ()>("Service.late", ());
+    let late_id = call.get_id();
+    assert!(matches!(call.await, Err(Error::Timeout(Some(_)))));
+    assert_eq!(
+        rx.recv_async().await.unwrap(),
+        (late_id, UnexpectedResponse::Late)
+    );
+
+    let call = client.call::<_, ()>("Service.twice", ());
+    let id = call.get_id();
+    assert_ne!(id, late_id);
+    call.await.unwrap();
+    assert_eq!(
+        rx.recv_async().await.unwrap(),
+        (id, UnexpectedResponse::Duplicate)
+    );
+    assert_eq!(
+        rx.recv_async().await.unwrap(),
+        (999, UnexpectedResponse::Unknown)
+    );
+
+    let stats = client.unexpected_responses();
+    assert_eq!(stats.late, 1);
+    assert_eq!(stats.duplicate, 1);
+    assert_eq!(stats.unknown, 1);
+
+    client.close().await;
+    server_handle.await.unwrap();
+    println!("test_unexpected_responses() Passed");
+}
+
+async fn test_connection_lifecycle() {
+    let (connected_tx, connected_rx) = flume::unbounded::<ConnectionInfo>();
+    let (disconnected_tx, disconnected_rx) = flume::unbounded::<ConnectionInfo>();
+    let server = Server::builder()
+        .register(rpc::CommonTest::new())
+        .on_connect(move |info: &ConnectionInfo| connected_tx.send(*info).unwrap())
+        .on_disconnect(move |info: &ConnectionInfo| disconnected_tx.send(*info).unwrap())
+        .build();
+    let shutdown = server.shutdown_handle();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server_handle = task::spawn(async move {
+        server.accept(listener).await.unwrap();
+    });
+
+    // a client closed on purpose is not lost
+    let client = Client::dial(addr).await.unwrap();
+    let (lost_tx, lost_rx) = flume::unbounded::<DisconnectInfo>();
+    client
+        .on_disconnect(move |info: &DisconnectInfo| lost_tx.send(*info).unwrap())
+        .unwrap();
+    rpc::test_get_magic_u8(&client).await;
+    client.close().await;
+    let connected = connected_rx.recv_async().await.unwrap();
+    assert!(connected.peer_addr.unwrap().ip().is_loopback());
+    assert_eq!(connected.calls, 0);
+    let disconnected = disconnected_rx.recv_async().await.unwrap();
+    assert_eq!(disconnected.client_id, connected.client_id);
+    assert_eq!(disconnected.peer_addr, connected.peer_addr);
+    assert_eq!(disconnected.calls, 1);
+    assert!(lost_rx.recv_async().await.is_err());
+
+    let client = Client::dial(addr).await.unwrap();
+    let (lost_tx, lost_rx) = flume::unbounded::<DisconnectInfo>();
+    client
+        .on_disconnect(move |info: &DisconnectInfo| lost_tx.send(*info).unwrap())
+        .unwrap();
+    rpc::test_get_magic_u8(&client).await;
+    rpc::test_service_not_found(&client).await;
+    let connected = connected_rx.recv_async().await.unwrap();
+    assert!(disconnected_rx.is_empty());
+
+    // the server closes the connection
+    shutdown.shutdown(Duration::from_secs(1)).await;
+    let disconnected = disconnected_rx.recv_async().await.unwrap();
+    assert_eq!(disconnected.client_id, connected.client_id);
+    assert_eq!(disconnected.calls, 2);
+
+    let lost = lost_rx.recv_async().await.unwrap();
+    assert_eq!(lost.reason, DisconnectReason::Closed);
+    assert_eq!(lost.calls, 2);
+    assert_eq!(lost.in_flight, 0);
+
+    server_handle.abort();
+    println!("test_connection_lifecycle() Passed");
+}
+
+#[test]
+fn test_main() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(test_raw_extension());
+}
+
+#[test]
+fn test_proxy() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(test_proxy_protocol());
+}
+
+#[test]
+fn test_filter() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(test_connection_filter());
+}
+
+#[test]
+fn test_audit() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(test_audit_log());
+}
+
+#[test]
+fn test_custom_codec() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(test_serve_codec());
+}
+
+#[test]
+fn test_unexpected() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(test_unexpected_responses());
+}
+
+#[test]
+fn test_lifecycle() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(test_connection_lifecycle());
+}
@@ -0,0 +1,473 @@
+use async_trait::async_trait;
+use futures::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tokio::net::TcpListener;
+use toy_rpc::client::low_power::LowPower;
+use toy_rpc::codec::{DefaultCodec, Marshal, Reserved, Unmarshal};
+use toy_rpc::error::Error;
+use toy_rpc::pubsub::{DeadLetter, DeliveryFailure, Topic};
+use toy_rpc::server::pubsub::{Backpressure, Retention, TopicAdmin};
+use toy_rpc::server::source::TopicSource;
+use toy_rpc::server::store::FileStore;
+use toy_rpc::{Client, Server};
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+struct Count(u32);
+
+impl Topic for Count {
+    type Item = Count;
+
+    fn topic() -> String {
+        "Count".into()
+    }
+}
+
+/// Dead letter topic of `Count`
+struct DeadCount;
+
+impl Topic for DeadCount {
+    type Item = DeadLetter;
+
+    fn topic() -> String {
+        "DeadCount".into()
+    }
+}
+
+async fn test_topic_admin() {
+    let server = Server::builder().build();
+    let admin = server.topic_admin();
+
+    assert!(admin.create(Count::topic()).await.unwrap());
+    assert!(!admin.create(Count::topic()).await.unwrap());
+    assert!(admin
+        .set_retention(Count::topic(), Retention::LastN(2))
+        .await
+        .unwrap());
+    assert!(!admin
+        .set_retention("Unknown", Retention::LastN(2))
+        .await
+        .unwrap());
+
+    let mut publisher = server.publisher::<Count>();
+    for i in 0..3 {
+        publisher.send(Count(i)).await.unwrap();
+    }
+
+    let info = admin.info(Count::topic()).await.unwrap().unwrap();
+    assert_eq!(info.retention, Retention::LastN(2));
+    assert_eq!(info.retained, 2);
+    assert_eq!(info.subscribers, 0);
+
+    // a late subscriber receives the retained messages
+    let mut subscriber = server.subscriber::<Count>(10).unwrap();
+    assert_eq!(subscriber.next().await.unwrap().unwrap(), Count(1));
+    assert_eq!(subscriber.next().await.unwrap().unwrap(), Count(2));
+    assert_eq!(admin.subscriber_count(Count::topic()).await.unwrap(), 1);
+
+    assert_eq!(admin.list().await.unwrap().len(), 1);
+    assert!(admin.delete(Count::topic()).await.unwrap());
+    assert!(admin.list().await.unwrap().is_empty());
+    println!("test_topic_admin() Passed");
+}
+
+async fn test_server_subscribers() {
+    let server = Server::builder().build();
+    let admin = server.topic_admin();
+
+    let mut sub_a = server.subscriber::<Count>(10).unwrap();
+    let mut sub_b = server.subscriber::<Count>(10).unwrap();
+    assert_eq!(admin.subscriber_count(Count::topic()).await.unwrap(), 2);
+
+    let mut publisher = server.publisher::<Count>();
+    publisher.send(Count(7)).await.unwrap();
+    assert_eq!(sub_a.next().await.unwrap().unwrap(), Count(7));
+    assert_eq!(sub_b.next().await.unwrap().unwrap(), Count(7));
+
+    // dropping a subscriber unsubscribes it from the topic
+    drop(sub_a);
+    assert_eq!(admin.subscriber_count(Count::topic()).await.unwrap(), 1);
+    publisher.send(Count(8)).await.unwrap();
+    assert_eq!(sub_b.next().await.unwrap().unwrap(), Count(8));
+    println!("test_server_subscribers() Passed");
+}
+
+async fn test_file_store() {
+    let path = std::env::temp_dir().join(format!("toy_rpc_broker_{}.log", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+
+    {
+        let server = Server::builder()
+            .broker_store(FileStore::open(&path).unwrap())
+            .build();
+        let admin = server.topic_admin();
+        admin.create(Count::topic()).await.unwrap();
+        admin
+            .set_retention(Count::topic(), Retention::LastN(2))
+            .await
+            .unwrap();
+        admin
+            .set_dead_letter(Count::topic(), Some(DeadCount::topic()))
+            .await
+            .unwrap();
+        admin
+            .set_ttl(Count::topic(), Some(Duration::from_secs(60)))
+            .await
+            .unwrap();
+        let mut publisher = server.publisher::<Count>();
+        for i in 0..3 {
+            publisher.send(Count(i)).await.unwrap();
+        }
+        publisher
+            .send_with_ttl(Count(3), Duration::from_millis(100))
+            .await
+            .unwrap();
+        // wait for the broker to process the publications
+        assert_eq!(
+            admin.info(Count::topic()).await.unwrap().unwrap().retained,
+            2
+        );
+    }
+
+    // the retained messages survive a restart
+    let server = Server::builder()
+        .broker_store(FileStore::open(&path).unwrap())
+        .build();
+    let info = server
+        .topic_admin()
+        .info(Count::topic())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(info.retention, Retention::LastN(2));
+    assert_eq!(info.dead_letter, Some(DeadCount::topic()));
+    assert_eq!(info.ttl, Some(Duration::from_secs(60)));
+
+    // the retained message with a short TTL expires while the server is down
+    tokio::time::sleep(Duration::from_millis(150)).await;
+    let mut subscriber = server.subscriber::<Count>(10).unwrap();
+    assert_eq!(subscriber.next().await.unwrap().unwrap(), Count(2));
+    server.publisher::<Count>().send(Count(4)).await.unwrap();
+    assert_eq!(subscriber.next().await.unwrap().unwrap(), Count(4));
+
+    let _ = std::fs::remove_file(&path);
+    println!("test_file_store() Passed");
+}
+
+async fn test_delayed_publication() {
+    let server = Server::builder().build();
+    let mut subscriber = server.subscriber::<Count>(10).unwrap();
+    let mut publisher = server.publisher::<Count>();
+
+    let start = std::time::Instant::now();
+    publisher
+        .send_delayed(Count(1), Duration::from_millis(200))
+        .await
+        .unwrap();
+    publisher
+        .send_delayed(Count(0), Duration::from_millis(100))
+        .await
+        .unwrap();
+    publisher.send(Count(2)).await.unwrap();
+
+    // delayed messages are delivered in the order of their delivery time
+    assert_eq!(subscriber.next().await.unwrap().unwrap(), Count(2));
+    assert_eq!(subscriber.next().await.unwrap().unwrap(), Count(0));
+    assert!(start.elapsed() >= Duration::from_millis(100));
+    assert_eq!(subscriber.next().await.unwrap().unwrap(), Count(1));
+    assert!(start.elapsed() >= Duration::from_millis(200));
+    println!("test_delayed_publication() Passed");
+}
+
+async fn test_dead_letter() {
+    let server = Server::builder().build();
+    let admin = server.topic_admin();
+
+    // a subscriber that can only hold a single message
+    let mut slow = server.subscriber::<Count>(1).unwrap();
+    let mut dead = server.subscriber::<DeadCount>(10).unwrap();
+    assert!(admin
+        .set_dead_letter(Count::topic(), Some(DeadCount::topic()))
+        .await
+        .unwrap());
+
+    let mut publisher = server.publisher::<Count>();
+    publisher.send(Count(0)).await.unwrap();
+    publisher.send(Count(1)).await.unwrap();
+
+    let letter = dead.next().await.unwrap().unwrap();
+    assert_eq!(letter.topic, Count::topic());
+    assert_eq!(letter.reason, DeliveryFailure::Full);
+    assert!(letter.failed_at <= std::time::SystemTime::now());
+    let item: Count =
+        DefaultCodec::<Reserved, Reserved, Reserved>::unmarshal(&letter.content).unwrap();
+    assert_eq!(item, Count(1));
+    assert_eq!(slow.next().await.unwrap().unwrap(), Count(0));
+    let info = admin.info(Count::topic()).await.unwrap().unwrap();
+    assert_eq!(info.dead_letter, Some(DeadCount::topic()));
+    assert_eq!(info.dead_lettered, 1);
+    println!("test_dead_letter() Passed");
+}
+
+async fn test_message_ttl() {
+    let server = Server::builder().build();
+    let admin = server.topic_admin();
+    admin.create(Count::topic()).await.unwrap();
+    admin
+        .set_retention(Count::topic(), Retention::LastN(5))
+        .await
+        .unwrap();
+    assert!(admin
+        .set_ttl(Count::topic(), Some(Duration::from_millis(150)))
+        .await
+        .unwrap());
+
+    let mut publisher = server.publisher::<Count>();
+    publisher.send(Count(0)).await.unwrap();
+    publisher
+        .send_with_ttl(Count(1), Duration::from_millis(20))
+        .await
+        .unwrap();
+    publisher.send(Count(2)).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    // the message with the shorter TTL has expired
+    let mut subscriber = server.subscriber::<Count>(10).unwrap();
+    assert_eq!(subscriber.next().await.unwrap().unwrap(), Count(0));
+    assert_eq!(subscriber.next().await.unwrap().unwrap(), Count(2));
+
+    // all retained messages have expired after the topic TTL
+    tokio::time::sleep(Duration::from_millis(150)).await;
+    let _late = server.subscriber::<Count>(10).unwrap();
+    let info = admin.info(Count::topic()).await.unwrap().unwrap();
+    assert_eq!(info.retained, 0);
+    assert_eq!(info.ttl, Some(Duration::from_millis(150)));
+    println!("test_message_ttl() Passed");
+}
+
+async fn test_idle_topics() {
+    let server = Server::builder()
+        .topic_idle_timeout(Duration::from_millis(100))
+        .build();
+    let admin = server.topic_admin();
+    assert!(admin.create(DeadCount::topic()).await.unwrap());
+
+    let subscriber = server.subscriber::<Count>(10).unwrap();
+    let stats = admin.stats().await.unwrap();
+    assert_eq!(stats.topics, 2);
+    assert_eq!(stats.subscribers, 1);
+
+    // the topic is removed once it has had no subscribers for the idle timeout
+    drop(subscriber);
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    let stats = admin.stats().await.unwrap();
+    assert_eq!(stats.topics, 1);
+    assert_eq!(stats.subscribers, 0);
+    assert_eq!(stats.collected_topics, 1);
+    // topics created by the admin are kept
+    assert!(admin.info(DeadCount::topic()).await.unwrap().is_some());
+    println!("test_idle_topics() Passed");
+}
+
+async fn test_topic_retention() {
+    let server = Server::builder()
+        .topic_retention::<Count>(Retention::LastN(2))
+        .topic_idle_timeout(Duration::from_millis(50))
+        .build();
+    let admin = server.topic_admin();
+
+    // the topic exists before anything is published to it
+    let info = admin.info(Count::topic()).await.unwrap().unwrap();
+    assert_eq!(info.retention, Retention::LastN(2));
+    assert_eq!(info.retained, 0);
+
+    // and is not removed while it is idle
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert!(admin.info(Count::topic()).await.unwrap().is_some());
+
+    let mut publisher = server.publisher::<Count>();
+    for i in 0..3 {
+        publisher.send(Count(i)).await.unwrap();
+    }
+    assert_eq!(
+        admin.info(Count::topic()).await.unwrap().unwrap().retained,
+        2
+    );
+
+    // a late subscriber receives the retained messages
+    let mut subscriber = server.subscriber::<Count>(10).unwrap();
+    assert_eq!(subscriber.next().await.unwrap().unwrap(), Count(1));
+    assert_eq!(subscriber.next().await.unwrap().unwrap(), Count(2));
+    println!("test_topic_retention() Passed");
+}
+
+async fn test_backpressure() {
+    for policy in [
+        Backpressure::Block,
+        Backpressure::DropOldest,
+        Backpressure::DropNewest,
+        Backpressure::Disconnect,
+    ]
+    .iter()
+    .copied()
+    {
+        let server = Server::builder()
+            .topic_backpressure::<Count>(policy)
+            .build();
+        let admin = server.topic_admin();
+        let mut subscriber = server.subscriber::<Count>(1).unwrap();
+        let mut publisher = server.publisher::<Count>();
+        for i in 0..3 {
+            publisher.send(Count(i)).await.unwrap();
+        }
+        if policy == Backpressure::Block {
+            // the broker waits for the subscriber instead of dropping anything
+            for i in 0..3 {
+                assert_eq!(subscriber.next().await.unwrap().unwrap(), Count(i));
+            }
+            continue;
+        }
+        // the publications have been handled once the stats are returned
+        admin.stats().await.unwrap();
+        let expected = match policy {
+            Backpressure::DropOldest => Count(2),
+            _ => Count(0),
+        };
+        assert_eq!(subscriber.next().await.unwrap().unwrap(), expected);
+        match policy {
+            Backpressure::Disconnect => {
+                assert!(subscriber.next().await.is_none());
+                assert_eq!(admin.subscriber_count(Count::topic()).await.unwrap(), 0);
+            }
+            _ => {
+                let next = tokio::time::timeout(Duration::from_millis(100), subscriber.next());
+                assert!(next.await.is_err());
+                assert_eq!(admin.subscriber_count(Count::topic()).await.unwrap(), 1);
+            }
+        }
+    }
+    println!("test_backpressure() Passed");
+}
+
+async fn wait_for_subscribers(admin: &TopicAdmin, count: usize) {
+    while admin.subscriber_count(Count::topic()).await.unwrap() != count {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+}
+
+async fn test_pause_resume() {
+    let server = Server::builder().build();
+    let admin = server.topic_admin();
+    let mut publisher = server.publisher::<Count>();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server_handle = tokio::task::spawn(async move {
+        server.accept(listener).await.unwrap();
+    });
+
+    let mut client = Client::builder()
+        .low_power(LowPower::new(Duration::from_millis(20)))
+        .dial(addr)
+        .await
+        .unwrap();
+    let mut subscriber = client.subscriber::<Count>(10).unwrap();
+    wait_for_subscribers(&admin, 1).await;
+
+    // the subscription is canceled on the server and the publication is queued
+    client.pause().await.unwrap();
+    wait_for_subscribers(&admin, 0).await;
+    publisher.send(Count(1)).await.unwrap();
+    let mut client_publisher = client.publisher::<Count>();
+    client_publisher.send(Count(2)).await.unwrap();
+    // nothing is delivered while paused, and the heartbeats are not sent either
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let next = tokio::time::timeout(Duration::from_millis(50), subscriber.next());
+    assert!(next.await.is_err());
+
+    client.resume().await.unwrap();
+    assert_eq!(subscriber.next().await.unwrap().unwrap(), Count(2));
+    publisher.send(Count(3)).await.unwrap();
+    assert_eq!(subscriber.next().await.unwrap().unwrap(), Count(3));
+    assert_eq!(admin.subscriber_count(Count::topic()).await.unwrap(), 1);
+
+    client.close().await;
+    server_handle.abort();
+    println!("test_pause_resume() Passed");
+}
+
+/// History of `Count` in an external store
+struct CountHistory(Vec<Vec<u8>>);
+
+#[async_trait]
+impl TopicSource for CountHistory {
+    async fn read_last(&self, topic: &str, count: usize) -> Result<Vec<Vec<u8>>, Error> {
+        assert_eq!(topic, Count::topic());
+        let start = self.0.len().saturating_sub(count);
+        Ok(self.0[start..].to_vec())
+    }
+}
+
+async fn test_topic_source() {
+    let history = (0..5)
+        .map(|i| DefaultCodec::<Reserved, Reserved, Reserved>::marshal(&Count(i)).unwrap())
+        .collect();
+    let server = Server::builder()
+        .topic_retention::<Count>(Retention::LastN(2))
+        .topic_source::<Count>(CountHistory(history))
+        .build();
+    let admin = server.topic_admin();
+    let mut publisher = server.publisher::<Count>();
+    for i in 3..5 {
+        publisher.send(Count(i)).await.unwrap();
+    }
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server_handle = tokio::task::spawn(async move {
+        server.accept(listener).await.unwrap();
+    });
+
+    // replaying more than the retained messages reads the history of the source
+    let mut client = Client::dial(addr).await.unwrap();
+    let mut subscriber = client.subscriber_with_replay::<Count>(10, 5).unwrap();
+    for i in 0..5 {
+        assert_eq!(subscriber.next().await.unwrap().unwrap(), Count(i));
+    }
+    // replaying fewer messages than retained reads the retained messages only
+    let mut other = Client::dial(addr).await.unwrap();
+    let mut replayed = other.subscriber_with_replay::<Count>(10, 1).unwrap();
+    assert_eq!(replayed.next().await.unwrap().unwrap(), Count(4));
+    // and a plain subscriber receives all of the retained messages
+    let mut plain_client = Client::dial(addr).await.unwrap();
+    let mut plain = plain_client.subscriber::<Count>(10).unwrap();
+    assert_eq!(plain.next().await.unwrap().unwrap(), Count(3));
+    assert_eq!(plain.next().await.unwrap().unwrap(), Count(4));
+
+    // then the subscribers receive the new publications
+    wait_for_subscribers(&admin, 3).await;
+    publisher.send(Count(5)).await.unwrap();
+    assert_eq!(subscriber.next().await.unwrap().unwrap(), Count(5));
+    assert_eq!(replayed.next().await.unwrap().unwrap(), Count(5));
+    assert_eq!(plain.next().await.unwrap().unwrap(), Count(5));
+
+    client.close().await;
+    other.close().await;
+    plain_client.close().await;
+    server_handle.abort();
+    println!("test_topic_source() Passed");
+}
+
+#[test]
+fn test_main() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(test_topic_admin());
+    rt.block_on(test_server_subscribers());
+    rt.block_on(test_file_store());
+    rt.block_on(test_delayed_publication());
+    rt.block_on(test_dead_letter());
+    rt.block_on(test_message_ttl());
+    rt.block_on(test_idle_topics());
+    rt.block_on(test_topic_retention());
+    rt.block_on(test_backpressure());
+    rt.block_on(test_pause_resume());
+    rt.block_on(test_topic_source());
+}
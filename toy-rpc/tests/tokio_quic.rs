@@ -0,0 +1,96 @@
+use futures::future::join_all;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::task;
+use toy_rpc::{Client, Error, Server};
+
+mod rpc;
+
+/// Creates a server endpoint with a self-signed certificate for "localhost" and a
+/// client endpoint that trusts it
+fn endpoints() -> (quinn::Endpoint, quinn::Endpoint) {
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap();
+    let cert_der = quinn_rustls::Certificate(cert.serialize_der().unwrap());
+    let key = quinn_rustls::PrivateKey(cert.serialize_private_key_der());
+
+    let server_config = quinn::ServerConfig::with_single_cert(vec![cert_der.clone()], key).unwrap();
+    let server = quinn::Endpoint::server(server_config, "127.0.0.1:0".parse().unwrap()).unwrap();
+
+    let mut roots = quinn_rustls::RootCertStore::empty();
+    roots.add(&cert_der).unwrap();
+    let mut client = quinn::Endpoint::client("127.0.0.1:0".parse().unwrap()).unwrap();
+    client.set_default_client_config(quinn::ClientConfig::with_root_certificates(roots));
+    (server, client)
+}
+
+fn server() -> Server {
+    Server::builder()
+        .register(Arc::new(rpc::CommonTest::new()))
+        .register_with_name(rpc::OWNED_TEST_SERVICE_NAME, rpc::CommonTest::new())
+        .register_with_name(rpc::ARITH_OBJECT_SERVICE_NAME, rpc::arith_object())
+        .register_extension(rpc::EXT_MARKER, rpc::ext_ping)
+        .build()
+}
+
+async fn test_rpc(client: &Client) {
+    rpc::test_get_magic_u8(client).await;
+    rpc::test_get_magic_u16(client).await;
+    rpc::test_get_magic_u32(client).await;
+    rpc::test_get_magic_u64(client).await;
+    rpc::test_get_magic_i8(client).await;
+    rpc::test_get_magic_i16(client).await;
+    rpc::test_get_magic_i32(client).await;
+    rpc::test_get_magic_i64(client).await;
+    rpc::test_get_magic_bool(client).await;
+    rpc::test_get_magic_str(client).await;
+    rpc::test_imcomplete_service_method(client).await;
+    rpc::test_service_not_found(client).await;
+    rpc::test_owned_service(client).await;
+    rpc::test_trait_object_service(client).await;
+    rpc::test_method_not_found(client).await;
+    rpc::test_execution_error(client).await;
+    rpc::test_extension(client).await;
+}
+
+/// Runs more concurrent calls than the streams a side keeps open
+async fn test_concurrent_calls(client: &Client) {
+    let calls = (0..200).map(|_| client.call("CommonTest.get_magic_u32", ()));
+    for reply in join_all(calls).await {
+        let reply: u32 = reply.unwrap();
+        assert_eq!(reply, rpc::COMMON_TEST_MAGIC_U32);
+    }
+
+    // more calls after the streams of the previous ones are finished
+    for _ in 0..100 {
+        rpc::test_get_magic_u8(client).await;
+    }
+    println!("test_concurrent_calls() Passed");
+}
+
+async fn run() {
+    let (server_endpoint, client_endpoint) = endpoints();
+    let addr: SocketAddr = server_endpoint.local_addr().unwrap();
+    let server = server();
+    let server_handle = task::spawn(async move {
+        server.accept_quic(server_endpoint).await.unwrap();
+    });
+
+    let client = Client::dial_quic(&client_endpoint, addr, "localhost")
+        .await
+        .expect("Error dialing server");
+    test_rpc(&client).await;
+    test_concurrent_calls(&client).await;
+    client.close().await;
+
+    // the certificate is not valid for another server name
+    let res = Client::dial_quic(&client_endpoint, addr, "example.com").await;
+    assert!(matches!(res, Err(Error::TlsHandshake(_))));
+
+    server_handle.abort();
+}
+
+#[test]
+fn test_main() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(run());
+}
@@ -26,8 +26,13 @@ async fn test_client(addr: &'static str, mut ready: Receiver<()>) -> Result<()>
     rpc::test_get_magic_str(&client).await;
     rpc::test_imcomplete_service_method(&client).await;
     rpc::test_service_not_found(&client).await;
+    rpc::test_owned_service(&client).await;
+    rpc::test_trait_object_service(&client).await;
+    rpc::test_typed_calls(&client).await;
     rpc::test_method_not_found(&client).await;
     rpc::test_execution_error(&client).await;
+    rpc::test_extension(&client).await;
+    rpc::test_metrics(&client).await;
 
     println!("Client received all correct RPC result");
     Ok(())
@@ -38,7 +43,13 @@ async fn run(addr: &'static str) {
     let common_test_service = Arc::new(rpc::CommonTest::new());
 
     // start testing server
-    let server = Server::builder().register(common_test_service).build();
+    let server = Server::builder()
+        .register(common_test_service)
+        .register_with_name(rpc::OWNED_TEST_SERVICE_NAME, rpc::CommonTest::new())
+        .register_with_name(rpc::ARITH_OBJECT_SERVICE_NAME, rpc::arith_object())
+        .register(rpc::arith_object())
+        .register_extension(rpc::EXT_MARKER, rpc::ext_ping)
+        .build();
 
     let listener = TcpListener::bind(addr)
         .await
@@ -67,3 +78,18 @@ fn test_main() {
     let rt = tokio::runtime::Runtime::new().unwrap();
     rt.block_on(run(rpc::ADDR));
 }
+
+async fn spawn_task() {
+    let handle = toy_rpc::util::spawn_task(async { 7u8 });
+    assert_eq!(handle.await.unwrap(), 7);
+
+    let handle = toy_rpc::util::spawn_task(futures::future::pending::<()>());
+    handle.abort();
+    assert!(matches!(handle.await, Err(toy_rpc::Error::Canceled(None))));
+}
+
+#[test]
+fn test_spawn_task() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(spawn_task());
+}
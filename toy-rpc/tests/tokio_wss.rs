@@ -0,0 +1,85 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio::task;
+use tokio_rustls::TlsAcceptor;
+use toy_rpc::{Client, Error, Server};
+
+mod rpc;
+
+/// Creates a server config with a self-signed certificate for "localhost" and a
+/// client config that trusts it
+fn tls_configs() -> (rustls::ServerConfig, rustls::ClientConfig) {
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap();
+    let cert_der = rustls::Certificate(cert.serialize_der().unwrap());
+    let key = rustls::PrivateKey(cert.serialize_private_key_der());
+
+    let mut server_config = rustls::ServerConfig::new(rustls::NoClientAuth::new());
+    server_config
+        .set_single_cert(vec![cert_der.clone()], key)
+        .unwrap();
+
+    let mut client_config = rustls::ClientConfig::new();
+    client_config.root_store.add(&cert_der).unwrap();
+    (server_config, client_config)
+}
+
+/// Terminates TLS in front of the WebSocket server at `backend`, like a reverse
+/// proxy does
+async fn terminate_tls(listener: TcpListener, acceptor: TlsAcceptor, backend: SocketAddr) {
+    while let Ok((stream, _)) = listener.accept().await {
+        let acceptor = acceptor.clone();
+        task::spawn(async move {
+            let mut tls_stream = match acceptor.accept(stream).await {
+                Ok(tls_stream) => tls_stream,
+                Err(_) => return,
+            };
+            let mut plain = tokio::net::TcpStream::connect(backend).await.unwrap();
+            let _ = tokio::io::copy_bidirectional(&mut tls_stream, &mut plain).await;
+        });
+    }
+}
+
+async fn run() {
+    let (server_config, client_config) = tls_configs();
+    let server = Server::builder()
+        .register(Arc::new(rpc::CommonTest::new()))
+        .register_extension(rpc::EXT_MARKER, rpc::ext_ping)
+        .build();
+
+    let backend = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let backend_addr = backend.local_addr().unwrap();
+    let server_handle = task::spawn(async move {
+        server.accept_websocket(backend).await.unwrap();
+    });
+    let frontend = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = frontend.local_addr().unwrap().port();
+    let acceptor = TlsAcceptor::from(Arc::new(server_config));
+    let tls_handle = task::spawn(terminate_tls(frontend, acceptor, backend_addr));
+
+    let addr = format!("wss://localhost:{}", port);
+    let client = Client::dial_websocket_with_tls_config(&addr, "localhost", client_config)
+        .await
+        .expect("Error dialing server");
+    rpc::test_get_magic_u8(&client).await;
+    rpc::test_get_magic_str(&client).await;
+    rpc::test_service_not_found(&client).await;
+    rpc::test_execution_error(&client).await;
+    rpc::test_large_payload(&client).await;
+    rpc::test_extension(&client).await;
+    client.close().await;
+
+    // a "wss://" url is dialed with TLS, and the self-signed certificate is not
+    // trusted by the default root store
+    let res = Client::dial_websocket(&addr).await;
+    assert!(matches!(res, Err(Error::TlsHandshake(_))));
+
+    tls_handle.abort();
+    server_handle.abort();
+}
+
+#[test]
+fn secure_websocket_with_tokio() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(run());
+}
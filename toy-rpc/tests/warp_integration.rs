@@ -1,10 +1,17 @@
 use anyhow::Result;
 use futures::channel::oneshot::{channel, Receiver};
-use std::{net::SocketAddr, sync::Arc};
+use std::{
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
 use tokio::task;
 use warp::Filter;
 
-use toy_rpc::{Client, Server};
+use toy_rpc::server::flow::{FlowControl, SlowClientPolicy};
+use toy_rpc::{server::shutdown::SHUTDOWN_REASON, Client, Error, Server};
 
 mod rpc;
 
@@ -31,6 +38,8 @@ async fn test_client(base: &str, mut ready: Receiver<()>) -> Result<()> {
     rpc::test_service_not_found(&client).await;
     rpc::test_method_not_found(&client).await;
     rpc::test_execution_error(&client).await;
+    rpc::test_large_payload(&client).await;
+    rpc::test_extension(&client).await;
 
     println!("Client received all correct RPC result");
     Ok(())
@@ -41,7 +50,10 @@ async fn run(base: &'static str) {
     let common_test_service = Arc::new(rpc::CommonTest::new());
 
     // start testing server
-    let server = Server::builder().register(common_test_service).build();
+    let server = Server::builder()
+        .register(common_test_service)
+        .register_extension(rpc::EXT_MARKER, rpc::ext_ping)
+        .build();
 
     let routes = warp::path("rpc").and(server.into_boxed_filter());
 
@@ -60,8 +72,243 @@ async fn run(base: &'static str) {
     server_handle.abort();
 }
 
+async fn run_shutdown() {
+    let server = Server::builder()
+        .register(Arc::new(rpc::CommonTest::new()))
+        .build();
+    let shutdown = server.shutdown_handle();
+
+    let routes = warp::path("rpc").and(server.into_boxed_filter());
+    let (addr, serving) = warp::serve(routes).bind_ephemeral(([127, 0, 0, 1], 0));
+    let server_handle = task::spawn(serving);
+
+    let addr = format!("ws://{}/rpc/", addr);
+    let client = Client::dial_http(&addr)
+        .await
+        .expect("Error dialing http server");
+    rpc::test_get_magic_u8(&client).await;
+    assert_eq!(shutdown.active_sessions(), 1);
+
+    // a call in flight when the server shuts down
+    let slow_call = client.call::<_, ()>("CommonTest.sleep_millis", 300u64);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    let start = Instant::now();
+    let shutdown_handle = {
+        let shutdown = shutdown.clone();
+        task::spawn(async move { shutdown.shutdown(Duration::from_secs(5)).await })
+    };
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(shutdown.is_shutting_down());
+
+    // new calls are rejected while the calls in flight are drained
+    let reply: Result<u8, Error> = client.call("CommonTest.get_magic_u8", ()).await;
+    match reply {
+        Err(Error::ExecutionError(msg)) => assert_eq!(msg, SHUTDOWN_REASON),
+        other => panic!("Expecting the call to be rejected, got {:?}", other),
+    }
+
+    slow_call.await.expect("Call in flight should be drained");
+    shutdown_handle.await.expect("Error awaiting shutdown");
+    assert!(start.elapsed() < Duration::from_secs(5));
+    assert_eq!(shutdown.active_sessions(), 0);
+
+    server_handle.abort();
+}
+
+#[test]
+fn http_warp_shutdown() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(run_shutdown());
+}
+
 #[test]
 fn http_warp_integration() {
     let rt = tokio::runtime::Runtime::new().unwrap();
     rt.block_on(run(rpc::ADDR));
 }
+
+async fn run_flow_control() {
+    // the client is paused whenever a message is waiting to be written
+    let server = Server::builder()
+        .register(Arc::new(rpc::CommonTest::new()))
+        .flow_control(FlowControl::new(1, 0, SlowClientPolicy::Pause))
+        .build();
+    let routes = warp::path("rpc").and(server.into_boxed_filter());
+    let (addr, serving) = warp::serve(routes).bind_ephemeral(([127, 0, 0, 1], 0));
+    let server_handle = task::spawn(serving);
+
+    let client = Client::dial_http(&format!("ws://{}/rpc/", addr))
+        .await
+        .expect("Error dialing http server");
+    let calls = (0..20).map(|_| client.call::<_, u8>("CommonTest.get_magic_u8", ()));
+    for reply in futures::future::join_all(calls).await {
+        assert_eq!(
+            reply.expect("Paused calls should be resumed"),
+            rpc::COMMON_TEST_MAGIC_U8
+        );
+    }
+    server_handle.abort();
+
+    // the client is disconnected as soon as a message is waiting to be written
+    let server = Server::builder()
+        .register(Arc::new(rpc::CommonTest::new()))
+        .flow_control(FlowControl::new(1, 0, SlowClientPolicy::Disconnect))
+        .build();
+    let routes = warp::path("rpc").and(server.into_boxed_filter());
+    let (addr, serving) = warp::serve(routes).bind_ephemeral(([127, 0, 0, 1], 0));
+    let server_handle = task::spawn(serving);
+
+    let client = Client::dial_http(&format!("ws://{}/rpc/", addr))
+        .await
+        .expect("Error dialing http server");
+    let reply = tokio::time::timeout(
+        Duration::from_secs(5),
+        client.call::<_, u8>("CommonTest.get_magic_u8", ()),
+    )
+    .await
+    .expect("The call should fail once the client is disconnected");
+    assert!(reply.is_err());
+    server_handle.abort();
+}
+
+#[test]
+fn http_warp_flow_control() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(run_flow_control());
+}
+
+async fn run_http_post() {
+    let server = Server::builder()
+        .register(Arc::new(rpc::CommonTest::new()))
+        .build();
+    let shutdown = server.shutdown_handle();
+    let routes = warp::path("rpc").and(server.into_boxed_filter());
+    let (addr, serving) = warp::serve(routes).bind_ephemeral(([127, 0, 0, 1], 0));
+    let server_handle = task::spawn(serving);
+
+    let client = Client::dial_http_post(&format!("http://{}/rpc/", addr))
+        .await
+        .expect("Error dialing http server");
+    rpc::test_get_magic_u8(&client).await;
+    rpc::test_get_magic_str(&client).await;
+    rpc::test_execution_error(&client).await;
+    rpc::test_large_payload(&client).await;
+    assert_eq!(shutdown.active_sessions(), 1);
+
+    client.close().await;
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while shutdown.active_sessions() != 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("The connection of the session should end");
+    server_handle.abort();
+}
+
+#[test]
+fn http_warp_post() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(run_http_post());
+}
+
+/// Sends a plain HTTP request and returns the status line of the response
+async fn status_line(addr: SocketAddr, request: &str) -> String {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut buf = vec![0; 1024];
+    let n = stream.read(&mut buf).await.unwrap();
+    let response = String::from_utf8_lossy(&buf[..n]).to_string();
+    response.lines().next().unwrap_or_default().to_string()
+}
+
+async fn run_http_post_limits() {
+    let server = Server::builder()
+        .register(Arc::new(rpc::CommonTest::new()))
+        .max_connections(1)
+        .build();
+    let routes = warp::path("rpc").and(server.into_boxed_filter());
+    let (addr, serving) = warp::serve(routes).bind_ephemeral(([127, 0, 0, 1], 0));
+    let server_handle = task::spawn(serving);
+
+    let client = Client::dial_http_post(&format!("http://{}/rpc/", addr))
+        .await
+        .expect("Error dialing http server");
+    rpc::test_get_magic_u8(&client).await;
+    // the session holds the only connection allowed
+    let open = "POST /rpc/_rpc_ HTTP/1.1\r\nHost: localhost\r\nContent-Length: 0\r\n\r\n";
+    assert_eq!(
+        status_line(addr, open).await,
+        "HTTP/1.1 503 Service Unavailable"
+    );
+    let request = "POST /rpc/_rpc_ HTTP/1.1\r\nHost: localhost\r\nx-toy-rpc-session: unknown\r\nContent-Length: 16777217\r\n\r\n";
+    assert_eq!(
+        status_line(addr, request).await,
+        "HTTP/1.1 413 Payload Too Large"
+    );
+    server_handle.abort();
+
+    let server = Server::builder()
+        .register(Arc::new(rpc::CommonTest::new()))
+        .connection_filter(|peer_addr| !peer_addr.ip().is_loopback())
+        .build();
+    let routes = warp::path("rpc").and(server.into_boxed_filter());
+    let (addr, serving) = warp::serve(routes).bind_ephemeral(([127, 0, 0, 1], 0));
+    let server_handle = task::spawn(serving);
+    assert_eq!(status_line(addr, open).await, "HTTP/1.1 403 Forbidden");
+    server_handle.abort();
+}
+
+#[test]
+fn http_warp_post_limits() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(run_http_post_limits());
+}
+
+async fn run_health() {
+    let server = Server::builder()
+        .register(Arc::new(rpc::CommonTest::new()))
+        .overload_threshold(1)
+        .build();
+    let shutdown = server.shutdown_handle();
+    let health = warp::path("healthz").and(server.health_filter());
+    let routes = warp::path("rpc").and(server.into_boxed_filter()).or(health);
+    let (addr, serving) = warp::serve(routes.clone()).bind_ephemeral(([127, 0, 0, 1], 0));
+    let server_handle = task::spawn(serving);
+
+    let check = |status: u16, body: &'static str| {
+        let routes = routes.clone();
+        async move {
+            let response = warp::test::request().path("/healthz").reply(&routes).await;
+            assert_eq!(response.status(), status);
+            assert_eq!(response.body(), body);
+        }
+    };
+    check(200, "accepting").await;
+
+    let client = Client::dial_http(&format!("ws://{}/rpc/", addr))
+        .await
+        .expect("Error dialing http server");
+    rpc::test_get_magic_u8(&client).await;
+    check(503, "overloaded").await;
+    client.close().await;
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while shutdown.active_sessions() != 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("The session should end");
+    check(200, "accepting").await;
+
+    shutdown.shutdown(Duration::from_secs(1)).await;
+    check(503, "draining").await;
+    server_handle.abort();
+}
+
+#[test]
+fn http_warp_health() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(run_health());
+}